@@ -0,0 +1,1295 @@
+//! Filing of processed documents into the archive (the configured `outdir`)
+
+use std::{
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use chrono::NaiveDate;
+use tracing::{debug, warn};
+
+use crate::{
+    audit, clipboard,
+    config::{ArchiveConfig, Config, ConflictPolicy, Timezone},
+    date_detect, due, fs_utils, history, labels,
+    manifest::{self, DocumentState, Manifest},
+    naming::{self, DocumentInfo},
+    notes, ocr, pdf, prompt, queue, search, tags, tools, ui, verify,
+};
+
+/// Maximal number of other detected dates shown when asking for the date
+const MAX_OTHER_DATES: usize = 3;
+
+/// Name of the hidden subdirectory that older versions of documents are kept
+/// in, next to the current version
+const VERSIONS_DIR: &str = ".versions";
+
+/// Reserve a file named `filename` in `outdir`, return its path
+///
+/// If the name is already taken, a zero-padded numeric suffix is appended to
+/// the stem (e.g. `2025-06-01_invoice-02.pdf`). Existing files are never
+/// overwritten.
+fn reserve_file(outdir: &Path, filename: &str) -> Result<PathBuf> {
+    let path = Path::new(filename);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let mut candidate = outdir.join(filename);
+    let mut suffix = 2;
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                candidate = outdir.join(format!("{stem}-{suffix:02}.{extension}"));
+                suffix += 1;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {:?}", candidate));
+            }
+        }
+    }
+}
+
+/// Keep an archived PDF (and its copy for emailing, if any) as an older
+/// version in the [`VERSIONS_DIR`] next to it, return the path of the version
+///
+/// Versions are numbered from 1 (e.g. `.versions/invoice_v1.pdf`), so the
+/// highest number is the most recent older version.
+fn keep_version(pdf: &Path) -> Result<PathBuf> {
+    let versions_dir = pdf.with_file_name(VERSIONS_DIR);
+    fs::create_dir_all(&versions_dir)
+        .with_context(|| format!("Failed to create directory {versions_dir:?}"))?;
+    let stem = pdf.file_stem().unwrap_or_default().to_string_lossy();
+    let mut number = 1;
+    let version = loop {
+        let candidate = versions_dir.join(format!("{stem}_v{number}.pdf"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => break candidate,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {:?}", candidate));
+            }
+        }
+    };
+    move_to_archive(pdf, &version)?;
+
+    let email_pdf = pdf.with_file_name(email_filename(pdf));
+    if email_pdf.exists() {
+        let email_version = reserve_file(&versions_dir, &email_filename(&version))?;
+        move_to_archive(&email_pdf, &email_version)?;
+    }
+    Ok(version)
+}
+
+/// Move a file of a document to a reserved file in the archive
+fn move_to_archive(source: &Path, target: &Path) -> Result<()> {
+    if let Err(e) = fs_utils::move_path(source, target) {
+        let _ = fs::remove_file(target);
+        return Err(e).with_context(|| format!("Failed to move {:?} to {:?}", source, target));
+    }
+    Ok(())
+}
+
+/// Name of the copy for emailing next to the archived PDF `target`, e.g.
+/// `2025-06-01_invoice_email.pdf`
+fn email_filename(target: &Path) -> String {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{stem}_email.pdf")
+}
+
+/// Reserve the file of a document in `outdir` (or the subdirectory of the
+/// layout, which is created if needed), named after the filename template,
+/// return its path
+///
+/// If the name is taken, the conflict policy of the config decides whether
+/// the name gets a suffix, whether the existing document is kept as an older
+/// version, or whether it is replaced (see [`Reserved::finish`]). The indexes
+/// are keyed by path, so they then describe the new document.
+fn reserve_target(
+    outdir: &Path,
+    archive_config: &ArchiveConfig,
+    timezone: Timezone,
+    info: &DocumentInfo,
+) -> Result<Reserved> {
+    let target_dir = outdir.join(naming::directory(&archive_config.outdir_layout, info)?);
+    fs::create_dir_all(&target_dir)
+        .with_context(|| format!("Failed to create directory {target_dir:?}"))?;
+    let filename = naming::filename(&archive_config.filename, info)?;
+    let existing = target_dir.join(&filename);
+    if existing.exists() {
+        match conflict_policy(archive_config.on_conflict, &existing)? {
+            ConflictPolicy::Version => {
+                let version = keep_version(&existing)?;
+                println!("Kept the previous version as {}", version.display());
+                let event = audit::Event::new(audit::Action::Version, outdir, &version, timezone)
+                    .with_previous(outdir, &existing);
+                audit::record(outdir, &event);
+            }
+            ConflictPolicy::Overwrite => {
+                // The existing document is only replaced once the new one is
+                // in the archive, next to it under a hidden name
+                return Ok(Reserved {
+                    path: reserve_file(&target_dir, &format!(".{filename}"))?,
+                    replaces: Some(existing),
+                });
+            }
+            ConflictPolicy::Suffix | ConflictPolicy::Prompt => {}
+        }
+    }
+    Ok(Reserved {
+        path: reserve_file(&target_dir, &filename)?,
+        replaces: None,
+    })
+}
+
+/// File reserved in the archive for a document by [`reserve_target`]
+struct Reserved {
+    /// Path that the document is moved or copied to
+    path: PathBuf,
+    /// Archived PDF that the document replaces once it is in place
+    replaces: Option<PathBuf>,
+}
+
+impl Reserved {
+    /// Put the document that was moved or copied to the reserved file in
+    /// place, return the path of the archived PDF
+    ///
+    /// A replaced document is only removed (with its copy for emailing, which
+    /// no longer matches) after the new document took its name.
+    fn finish(self, outdir: &Path, timezone: Timezone) -> Result<PathBuf> {
+        let Some(existing) = self.replaces else {
+            return Ok(self.path);
+        };
+        fs::rename(&self.path, &existing)
+            .with_context(|| format!("Failed to move {:?} to {existing:?}", self.path))?;
+        debug!("Replaced {existing:?}");
+        let event = audit::Event::new(audit::Action::Replace, outdir, &existing, timezone);
+        audit::record(outdir, &event);
+        let email_pdf = existing.with_file_name(email_filename(&existing));
+        if email_pdf.exists() {
+            fs::remove_file(&email_pdf)
+                .with_context(|| format!("Failed to remove {email_pdf:?}"))?;
+            debug!("Removed {email_pdf:?}, which is replaced");
+            let event = audit::Event::new(audit::Action::Replace, outdir, &email_pdf, timezone);
+            audit::record(outdir, &event);
+        }
+        Ok(existing)
+    }
+}
+
+/// The conflict policy for archiving a document under the name of the
+/// `existing` document, asking for it with [`ConflictPolicy::Prompt`]
+fn conflict_policy(policy: ConflictPolicy, existing: &Path) -> Result<ConflictPolicy> {
+    if policy != ConflictPolicy::Prompt {
+        return Ok(policy);
+    }
+    let option_suffix = "Keep both, with a suffix for the new document";
+    let option_version = "Keep the existing document as older version";
+    let option_overwrite = "Replace the existing document";
+    let message = format!(
+        "{} exists already. What to do?",
+        existing.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let choice = prompt::Select::new(
+        &message,
+        vec![option_suffix, option_version, option_overwrite],
+    )
+    .prompt()?;
+    Ok(if choice == option_version {
+        ConflictPolicy::Version
+    } else if choice == option_overwrite {
+        ConflictPolicy::Overwrite
+    } else {
+        ConflictPolicy::Suffix
+    })
+}
+
+/// Move the final PDF of a document (and its copy for emailing, if any) into
+/// the archive (see [`reserve_target`]) and remove the document directory
+/// from the scans cache, unless the scans are kept. The document is recorded
+/// in the history file at `history_path` first. Returns the path of the
+/// archived PDF.
+fn archive_to(
+    document_dir: &Path,
+    manifest: &mut Manifest,
+    outdir: &Path,
+    archive_config: &ArchiveConfig,
+    timezone: Timezone,
+    info: &DocumentInfo,
+    history_path: &Path,
+) -> Result<PathBuf> {
+    let final_pdf = document_dir.join(queue::FINAL_PDF);
+    ensure!(
+        final_pdf.exists(),
+        "Document {:?} has no final PDF",
+        document_dir
+    );
+    let reserved = reserve_target(outdir, archive_config, timezone, info)?;
+    move_to_archive(&final_pdf, &reserved.path)?;
+    let target = reserved.finish(outdir, timezone)?;
+    let target_dir = target.parent().unwrap_or(outdir);
+    let mut outputs = vec![target.clone()];
+
+    // Archive the copy for emailing under the same name as the final PDF
+    let email_pdf = document_dir.join(queue::EMAIL_PDF);
+    if email_pdf.exists() {
+        let email_target = reserve_file(target_dir, &email_filename(&target))?;
+        move_to_archive(&email_pdf, &email_target)?;
+        println!("Email copy: {}", email_target.display());
+        outputs.push(email_target);
+    }
+
+    // Record the new state, in case the directory cannot be removed
+    manifest.outputs = outputs;
+    manifest.title = Some(info.title.clone());
+    manifest.correspondent = info.correspondent.clone();
+    manifest.tags = info.tags.clone();
+    manifest.state = DocumentState::Archived;
+    manifest.save(document_dir)?;
+    let text = fs::read_to_string(document_dir.join(ocr::OCR_TEXT)).unwrap_or_default();
+    if let Err(e) = history::record(history_path, manifest, &text) {
+        warn!("Failed to record the document in the history: {e:#}");
+    }
+    if !archive_config.keep_scans
+        && let Err(e) = fs::remove_dir_all(document_dir)
+    {
+        warn!(
+            "Failed to remove {:?} from the scans cache: {}",
+            document_dir, e
+        );
+    }
+    Ok(target)
+}
+
+/// Replace an archived PDF by the final PDF of its document directory (e.g.
+/// after processing its scans again), keeping the archived PDF as an older
+/// version, return the path of the version
+///
+/// The metadata is taken over from `info`, and the document stays in the
+/// scans cache.
+pub fn replace_archived(
+    document_dir: &Path,
+    pdf: &Path,
+    info: &DocumentInfo,
+    config: &Config,
+) -> Result<PathBuf> {
+    let mut manifest = Manifest::load(document_dir)?;
+    let final_pdf = document_dir.join(queue::FINAL_PDF);
+    ensure!(
+        final_pdf.exists(),
+        "Document {:?} has no final PDF",
+        document_dir
+    );
+    let id = manifest.id.clone().unwrap_or_default();
+    if let Err(e) = store_metadata(&final_pdf, &info.tags.join(", "), &id, config) {
+        warn!("Failed to store the tags and the ID in the PDF metadata: {e:#}");
+    }
+
+    let email_pdf = document_dir.join(queue::EMAIL_PDF);
+    let email_pdf = email_pdf.exists().then_some(email_pdf);
+    let version = replace_pdf(pdf, &final_pdf, email_pdf.as_deref(), config)?;
+    let mut outputs = vec![pdf.to_path_buf()];
+    if email_pdf.is_some() {
+        outputs.push(pdf.with_file_name(email_filename(pdf)));
+    }
+
+    manifest.outputs = outputs;
+    manifest.state = DocumentState::Archived;
+    manifest.save(document_dir)?;
+    let text = fs::read_to_string(document_dir.join(ocr::OCR_TEXT)).unwrap_or_default();
+    record_archived(
+        pdf,
+        &text,
+        info,
+        manifest.id.as_deref(),
+        audit::Action::Reprocess,
+        config,
+    )?;
+    Ok(version)
+}
+
+/// Replace an archived PDF by `new_pdf` (and its copy for emailing by
+/// `new_email_pdf`), keeping the archived PDF as older version, return the
+/// path of the older version
+///
+/// The indexes are not updated.
+pub fn replace_pdf(
+    pdf: &Path,
+    new_pdf: &Path,
+    new_email_pdf: Option<&Path>,
+    config: &Config,
+) -> Result<PathBuf> {
+    let version = keep_version(pdf)?;
+    let event = audit::Event::new(
+        audit::Action::Version,
+        &config.outdir,
+        &version,
+        config.scan.timezone,
+    )
+    .with_previous(&config.outdir, pdf);
+    audit::record(&config.outdir, &event);
+    move_to_archive(new_pdf, pdf)?;
+    if let Some(new_email_pdf) = new_email_pdf {
+        move_to_archive(new_email_pdf, &pdf.with_file_name(email_filename(pdf)))?;
+    }
+    Ok(version)
+}
+
+/// Ask for the correspondent, title, date and tags of a document,
+/// preselecting the known metadata and `default_date`
+///
+/// Unknown correspondents and tags are suggested from the earlier documents
+/// in `history` that are similar (by the characteristic `words` of the
+/// text). Other dates that were detected in the document are shown as help.
+/// Returns `None` if the user skips the document.
+fn prompt_document_info(
+    manifest: &Manifest,
+    default_date: NaiveDate,
+    other_dates: &[NaiveDate],
+    vocabulary: &[String],
+    history: &[history::Entry],
+    words: &[String],
+) -> Result<Option<DocumentInfo>> {
+    let mut correspondent_prompt = prompt::Text::new("Correspondent?").with_help_message(
+        "Sender or issuer of the document (may be empty), press Esc to skip this document",
+    );
+    let known_correspondent = manifest
+        .correspondent
+        .as_deref()
+        .or_else(|| history::similar_correspondent(history, words));
+    if let Some(correspondent) = known_correspondent {
+        correspondent_prompt = correspondent_prompt.with_initial_value(correspondent);
+    }
+    let Some(correspondent) = correspondent_prompt.prompt_skippable()? else {
+        return Ok(None);
+    };
+    let correspondent = correspondent.trim();
+
+    let mut title_prompt = prompt::Text::new("Title?")
+        .with_help_message("Leave empty or press Esc to skip this document");
+    if let Some(title) = &manifest.title {
+        title_prompt = title_prompt.with_initial_value(title);
+    }
+    let Some(title) = title_prompt.prompt_skippable()? else {
+        return Ok(None);
+    };
+    let title = title.trim();
+    if title.is_empty() {
+        return Ok(None);
+    }
+
+    let mut help = String::from("Press Esc to skip this document");
+    if !other_dates.is_empty() {
+        let others: Vec<String> = other_dates
+            .iter()
+            .take(MAX_OTHER_DATES)
+            .map(NaiveDate::to_string)
+            .collect();
+        help = format!("Also found: {}. {help}", others.join(", "));
+    }
+    let Some(date) = prompt::CustomType::<NaiveDate>::new("Date of the document?")
+        .with_default(default_date)
+        .with_error_message("Please enter a date as YYYY-MM-DD")
+        .with_help_message(&help)
+        .prompt_skippable()?
+    else {
+        return Ok(None);
+    };
+
+    let correspondent = (!correspondent.is_empty()).then(|| correspondent.to_string());
+    let known_tags = if manifest.tags.is_empty() {
+        history::suggested_tags(history, correspondent.as_deref(), words)
+    } else {
+        manifest.tags.clone()
+    };
+    let Some(tags) = prompt_tags(&known_tags, vocabulary)? else {
+        return Ok(None);
+    };
+    Ok(Some(DocumentInfo {
+        date,
+        title: title.to_string(),
+        correspondent,
+        tags,
+        due: None,
+        location: None,
+    }))
+}
+
+/// Ask where the paper original of a document is kept, `Some(None)` if it is
+/// not kept
+///
+/// Returns `None` if the document should be skipped.
+fn prompt_location() -> Result<Option<Option<String>>> {
+    let Some(location) = prompt::Text::new("Location of the paper original?")
+        .with_help_message(
+            "E.g. the label of a binder or box, leave empty if it is not kept, press Esc to skip \
+             this document",
+        )
+        .prompt_skippable()?
+    else {
+        return Ok(None);
+    };
+    let location = location.trim();
+    Ok(Some((!location.is_empty()).then(|| location.to_string())))
+}
+
+/// Ask for the tags of a document: Tags of the vocabulary are selected (the
+/// known tags of the document are preselected), new tags are entered
+///
+/// Returns `None` if the user skips the document.
+fn prompt_tags(known_tags: &[String], vocabulary: &[String]) -> Result<Option<Vec<String>>> {
+    let mut options = vocabulary.to_vec();
+    tags::merge(&mut options, known_tags.iter().map(String::as_str));
+    let mut tags = Vec::new();
+    if !options.is_empty() {
+        let defaults: Vec<usize> = options
+            .iter()
+            .enumerate()
+            .filter(|(_, tag)| {
+                known_tags
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(tag))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let Some(selected) = prompt::MultiSelect::new("Tags?", options)
+            .with_default(&defaults)
+            .with_help_message("Type to filter, space to select, press Esc to skip this document")
+            .prompt_skippable()?
+        else {
+            return Ok(None);
+        };
+        tags = selected;
+    }
+    let Some(new_tags) = prompt::Text::new("New tags?")
+        .with_help_message("Comma-separated, may be empty, press Esc to skip this document")
+        .prompt_skippable()?
+    else {
+        return Ok(None);
+    };
+    tags::merge(&mut tags, tags::parse(&new_tags).iter().map(String::as_str));
+    Ok(Some(tags))
+}
+
+/// Key of the document ID in the document info of archived PDFs
+const DOCUMENT_ID_KEY: &str = "ArkivistoID";
+
+/// Store `keywords` and the document ID in a PDF (e.g. the final PDF of a
+/// document), empty values are not stored
+///
+/// The document info is updated natively, the ID is stored in the custom
+/// [`DOCUMENT_ID_KEY`] entry. PDFs with XMP metadata (PDF/A of OCRmyPDF) are
+/// updated with pikepdf as well, which stores the ID as `dc:identifier` and
+/// keeps the keywords of both in sync.
+pub fn store_metadata(pdf: &Path, keywords: &str, id: &str, config: &Config) -> Result<()> {
+    let mut entries = vec![("Keywords", keywords), (DOCUMENT_ID_KEY, id)];
+    if pdf::has_xmp_metadata(pdf)? {
+        match ocr::find_ocrmypdf(&config.ocr) {
+            Some(ocrmypdf) => {
+                let directory = pdf.parent().unwrap_or(Path::new("."));
+                let name = pdf.file_name().unwrap_or_default().to_string_lossy();
+                ocr::set_metadata(
+                    &ocrmypdf,
+                    directory,
+                    &name,
+                    keywords,
+                    id,
+                    &config.processing.limits,
+                )?;
+                entries.retain(|(key, _)| *key != "Keywords");
+            }
+            None => tools::warn_missing(
+                ocr::ocrmypdf_program(&config.ocr),
+                "storing the tags and the ID only in the document info of the PDF (not in its \
+                 XMP metadata)",
+            ),
+        }
+    }
+    entries.retain(|(_, value)| !value.is_empty());
+    if entries.is_empty() {
+        return Ok(());
+    }
+    pdf::set_info(pdf, &entries)
+}
+
+/// Ask for the metadata of a document with the recognized `text`, see
+/// [`prompt_document_info`]
+///
+/// The first of the `detected` dates is preselected, or else
+/// `fallback_date`. Tags are offered from the vocabulary.
+pub fn ask_document_info(
+    manifest: &Manifest,
+    text: &str,
+    detected: &[NaiveDate],
+    fallback_date: NaiveDate,
+    history: &[history::Entry],
+) -> Result<Option<DocumentInfo>> {
+    let (default_date, other_dates) = match detected.split_first() {
+        Some((date, others)) => (*date, others),
+        None => (fallback_date, &[][..]),
+    };
+    let vocabulary = tags::load_vocabulary(&tags::vocabulary_path()?).unwrap_or_else(|e| {
+        warn!("{e:#}");
+        Vec::new()
+    });
+    prompt_document_info(
+        manifest,
+        default_date,
+        other_dates,
+        &vocabulary,
+        history,
+        &history::fingerprint(text),
+    )
+}
+
+/// Ask for the metadata of a processed document and archive it, return the
+/// path of the archived PDF
+///
+/// Returns `None` if the user skips the document.
+pub fn archive_document(document_dir: &Path, config: &Config) -> Result<Option<PathBuf>> {
+    let mut manifest = Manifest::load(document_dir)?;
+    let final_pdf = document_dir.join(queue::FINAL_PDF);
+    // Show the document, unless it was shown already or nobody is watching
+    if prompt::is_interactive()
+        && !manifest.shown
+        && let Err(e) = verify::open_file(&final_pdf)
+    {
+        warn!("Failed to open {:?}: {:#}", final_pdf, e);
+    }
+
+    // Preselect the date detected in the text, or the scan date
+    let today = config.scan.timezone.now().date_naive();
+    let detected = date_detect::detect(
+        &document_dir.join(ocr::OCR_TEXT),
+        config.date_order(),
+        today,
+    );
+    let scan_date = manifest
+        .scan_time()
+        .map(|time| time.date_naive())
+        .unwrap_or(today);
+    let text = fs::read_to_string(document_dir.join(ocr::OCR_TEXT)).unwrap_or_default();
+    let history = document_dir.parent().map(history::load).unwrap_or_default();
+    let Some(mut info) = ask_document_info(&manifest, &text, &detected, scan_date, &history)?
+    else {
+        return Ok(None);
+    };
+    if config.archive.ask_due_date {
+        let Some(due) = due::ask(info.date)? else {
+            return Ok(None);
+        };
+        info.due = due;
+    }
+    if config.archive.ask_location {
+        let Some(location) = prompt_location()? else {
+            return Ok(None);
+        };
+        info.location = location;
+    }
+
+    // Check the output directory before the PDF is changed
+    fs_utils::ensure_dir_prompt(&config.outdir)?;
+
+    // Store the tags in the keywords of the PDF, and the ID of the document
+    let id = manifest.id.clone().unwrap_or_default();
+    if (!info.tags.is_empty() || !id.is_empty())
+        && let Err(e) = store_metadata(&final_pdf, &info.tags.join(", "), &id, config)
+    {
+        warn!("Failed to store the tags and the ID in the PDF metadata: {e:#}");
+    }
+
+    let target = archive_to(
+        document_dir,
+        &mut manifest,
+        &config.outdir,
+        &config.archive,
+        config.scan.timezone,
+        &info,
+        &history::history_path()?,
+    )?;
+    record_archived(
+        &target,
+        &text,
+        &info,
+        manifest.id.as_deref(),
+        audit::Action::Archive,
+        config,
+    )?;
+    if let Some(labels_dir) = &config.archive.labels_dir {
+        match labels::create(labels_dir, &config.outdir, &target, manifest.id.as_deref()) {
+            Ok(Some(label)) => println!("Created QR label {}", label.display()),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to create the QR label of {target:?}: {e:#}"),
+        }
+    }
+    Ok(Some(target))
+}
+
+/// Summary of the metadata of a document, e.g. `2025-05-30, Muster AG,
+/// Invoice, tags: bills, car`
+fn summary(info: &DocumentInfo) -> String {
+    let mut fields = vec![info.date.to_string()];
+    fields.extend(info.correspondent.clone());
+    fields.push(info.title.clone());
+    if !info.tags.is_empty() {
+        fields.push(format!("tags: {}", info.tags.join(", ")));
+    }
+    fields.join(", ")
+}
+
+/// Record an archived PDF in the tag and search indexes and the audit log,
+/// its tags in the vocabulary, and write its note (if configured)
+///
+/// Failures are only logged, as the document itself was archived.
+fn record_archived(
+    target: &Path,
+    text: &str,
+    info: &DocumentInfo,
+    id: Option<&str>,
+    action: audit::Action,
+    config: &Config,
+) -> Result<()> {
+    let event = audit::Event::new(action, &config.outdir, target, config.scan.timezone)
+        .with_id(id)
+        .with_details(summary(info));
+    audit::record(&config.outdir, &event);
+    if let Err(e) = tags::record(&config.outdir, target, &info.tags, id) {
+        warn!("Failed to record the tags of {target:?}: {e:#}");
+    }
+    if let Err(e) = search::add(&config.outdir, target, text, id, Some(info)) {
+        warn!("Failed to add {target:?} to the search index: {e:#}");
+    }
+    if let Err(e) = tags::add_to_vocabulary(&tags::vocabulary_path()?, &info.tags) {
+        warn!("Failed to update the tag vocabulary: {e:#}");
+    }
+    if let Some(notes_dir) = &config.archive.notes_dir
+        && let Err(e) = notes::write(notes_dir, &config.outdir, target, text, info, id)
+    {
+        warn!("Failed to write the note of {target:?}: {e:#}");
+    }
+    Ok(())
+}
+
+/// File a copy of an existing PDF (e.g. from another document management
+/// system) with the given metadata and recognized text into the archive,
+/// return the path of the archived PDF
+///
+/// The tags and the ID are stored in the document info of the copy, and the
+/// copy is recorded in the indexes like a scanned document, and in the audit
+/// log with the given action.
+pub fn file_pdf(
+    source: &Path,
+    text: &str,
+    info: &DocumentInfo,
+    id: &str,
+    action: audit::Action,
+    config: &Config,
+) -> Result<PathBuf> {
+    let reserved = reserve_target(&config.outdir, &config.archive, config.scan.timezone, info)?;
+    if let Err(e) = fs::copy(source, &reserved.path) {
+        let _ = fs::remove_file(&reserved.path);
+        return Err(e).with_context(|| format!("Failed to copy {source:?} to {:?}", reserved.path));
+    }
+    let target = reserved.finish(&config.outdir, config.scan.timezone)?;
+    let keywords = info.tags.join(", ");
+    let mut entries = vec![("Keywords", keywords.as_str()), (DOCUMENT_ID_KEY, id)];
+    entries.retain(|(_, value)| !value.is_empty());
+    if let Err(e) = pdf::set_info(&target, &entries) {
+        warn!("Failed to store the tags and the ID in the PDF metadata: {e:#}");
+    }
+    record_archived(&target, text, info, Some(id), action, config)?;
+    Ok(target)
+}
+
+/// Move an archived PDF (with its copy for emailing) to the name and directory
+/// for its changed metadata, return its new path
+///
+/// A suffix is appended if the name is taken by another document. The PDF is
+/// not moved if its name stays the same.
+pub fn rename_archived(pdf: &Path, info: &DocumentInfo, config: &Config) -> Result<PathBuf> {
+    let target_dir = config
+        .outdir
+        .join(naming::directory(&config.archive.outdir_layout, info)?);
+    let filename = naming::filename(&config.archive.filename, info)?;
+    if pdf.parent() == Some(target_dir.as_path()) && has_name(pdf, &filename) {
+        return Ok(pdf.to_path_buf());
+    }
+    fs::create_dir_all(&target_dir)
+        .with_context(|| format!("Failed to create directory {target_dir:?}"))?;
+    let target = reserve_file(&target_dir, &filename)?;
+    move_to_archive(pdf, &target)?;
+    let email_pdf = pdf.with_file_name(email_filename(pdf));
+    if email_pdf.exists() {
+        let email_target = reserve_file(&target_dir, &email_filename(&target))?;
+        move_to_archive(&email_pdf, &email_target)?;
+    }
+    debug!("Renamed {pdf:?} to {target:?}");
+    Ok(target)
+}
+
+/// Whether a PDF is named `filename`, possibly with a suffix that was
+/// appended because the name was taken (e.g. `invoice-02.pdf`)
+fn has_name(pdf: &Path, filename: &str) -> bool {
+    let name = pdf.file_name().unwrap_or_default().to_string_lossy();
+    if name == filename {
+        return true;
+    }
+    let filename = Path::new(filename);
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = filename.extension().unwrap_or_default().to_string_lossy();
+    name.strip_prefix(&format!("{stem}-"))
+        .and_then(|rest| rest.strip_suffix(&format!(".{extension}")))
+        .is_some_and(|suffix| suffix.len() >= 2 && suffix.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Archive all processed documents in the scans cache, one after the other
+///
+/// Documents that need review are left in the cache. Returns the paths of the
+/// archived PDFs.
+pub fn archive(scans_dir: &Path, config: &Config) -> Result<Vec<PathBuf>> {
+    let documents = manifest::find_documents(scans_dir, |manifest| {
+        manifest.state == DocumentState::Processed
+    })?;
+    let needs_review = manifest::find_documents(scans_dir, |manifest| {
+        manifest.state == DocumentState::NeedsReview
+    })?
+    .len();
+    if needs_review > 0 {
+        println!("{needs_review} document(s) need review first (see `arkivisto review`)");
+    }
+
+    let total = documents.len();
+    let mut archived = Vec::new();
+    for (i, (document_dir, _)) in documents.into_iter().enumerate() {
+        println!("Document {}/{}: {}", i + 1, total, document_dir.display());
+        match archive_document(&document_dir, config)? {
+            Some(path) => {
+                println!("{}", ui::success(format!("Archived to {}", path.display())));
+                if let Some(content) = config.archive.clipboard {
+                    clipboard::offer(&path, content)?;
+                }
+                archived.push(path);
+            }
+            None => debug!("Skipped {:?}", document_dir),
+        }
+    }
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::{manifest::ScanStats, prompt::ScriptedPrompter};
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Ensure that the known metadata and the scan date are preselected,
+    /// that tags are selected from the vocabulary or entered, and that
+    /// documents can be skipped.
+    #[test]
+    fn document_info() {
+        let manifest = Manifest {
+            title: Some("Invoice".into()),
+            tags: vec!["bills".into()],
+            ..Default::default()
+        };
+        let vocabulary = vec!["car".to_string(), "taxes".to_string()];
+        let answers = [
+            // Known title and tags
+            "",
+            "",
+            "",
+            "",
+            "",
+            // New metadata
+            " Muster AG ",
+            " Tax return ",
+            "2025-05-30",
+            "taxes",
+            "2024, , Taxes,pension",
+            // Skipped at the correspondent and at the title (empty or Esc)
+            "<esc>",
+            "",
+            " ",
+            "",
+            "<esc>",
+            // Skipped at the date, the tags and the new tags
+            "",
+            "",
+            "<esc>",
+            "",
+            "",
+            "",
+            "<esc>",
+            "",
+            "",
+            "",
+            "",
+            "<esc>",
+        ];
+        prompt::with_prompter(ScriptedPrompter::new(answers), || {
+            assert_eq!(
+                prompt_document_info(&manifest, date(2025, 6, 1), &[], &vocabulary, &[], &[])
+                    .unwrap(),
+                Some(DocumentInfo {
+                    date: date(2025, 6, 1),
+                    title: "Invoice".into(),
+                    correspondent: None,
+                    tags: vec!["bills".into()],
+                    due: None,
+                    location: None,
+                })
+            );
+            assert_eq!(
+                prompt_document_info(&manifest, date(2025, 6, 1), &[], &vocabulary, &[], &[])
+                    .unwrap(),
+                Some(DocumentInfo {
+                    date: date(2025, 5, 30),
+                    title: "Tax return".into(),
+                    correspondent: Some("Muster AG".into()),
+                    tags: vec!["taxes".into(), "2024".into(), "pension".into()],
+                    due: None,
+                    location: None,
+                })
+            );
+            for _ in 0..6 {
+                assert_eq!(
+                    prompt_document_info(&manifest, date(2025, 6, 1), &[], &vocabulary, &[], &[])
+                        .unwrap(),
+                    None
+                );
+            }
+        });
+    }
+
+    /// Ensure that the correspondent and tags of a similar earlier document
+    /// are suggested.
+    #[test]
+    fn suggestions_from_history() {
+        let text = "Muster AG Rechnung Nummer 42 Betrag Zahlbar innert 30 Tagen";
+        let history = vec![history::Entry {
+            correspondent: Some("Muster AG".into()),
+            tags: vec!["bills".into()],
+            words: history::fingerprint(text),
+            ..Default::default()
+        }];
+        let vocabulary = vec!["bills".to_string(), "car".to_string()];
+        let answers = ["", "Invoice", "", "", ""];
+        prompt::with_prompter(ScriptedPrompter::new(answers), || {
+            assert_eq!(
+                prompt_document_info(
+                    &Manifest::default(),
+                    date(2025, 6, 1),
+                    &[],
+                    &vocabulary,
+                    &history,
+                    &history::fingerprint(&text.replace("42", "43")),
+                )
+                .unwrap(),
+                Some(DocumentInfo {
+                    date: date(2025, 6, 1),
+                    title: "Invoice".into(),
+                    correspondent: Some("Muster AG".into()),
+                    tags: vec!["bills".into()],
+                    due: None,
+                    location: None,
+                })
+            );
+        });
+    }
+
+    /// Ensure that the tag selection is skipped without known tags.
+    #[test]
+    fn tags_without_vocabulary() {
+        prompt::with_prompter(ScriptedPrompter::new(["bills, car"]), || {
+            assert_eq!(
+                prompt_tags(&[], &[]).unwrap().unwrap(),
+                vec!["bills", "car"]
+            );
+        });
+    }
+
+    /// Ensure that the location of the paper original may be left empty, and
+    /// that Esc skips the document.
+    #[test]
+    fn location() {
+        prompt::with_prompter(ScriptedPrompter::new([" Binder 3 ", "", "<esc>"]), || {
+            assert_eq!(
+                prompt_location().unwrap(),
+                Some(Some("Binder 3".to_string()))
+            );
+            assert_eq!(prompt_location().unwrap(), Some(None));
+            assert_eq!(prompt_location().unwrap(), None);
+        });
+    }
+
+    /// Ensure that existing files are not overwritten.
+    #[test]
+    fn unique_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = reserve_file(temp_dir.path(), "a.pdf").unwrap();
+        let second = reserve_file(temp_dir.path(), "a.pdf").unwrap();
+        let third = reserve_file(temp_dir.path(), "a.pdf").unwrap();
+        assert_eq!(first, temp_dir.path().join("a.pdf"));
+        assert_eq!(second, temp_dir.path().join("a-02.pdf"));
+        assert_eq!(third, temp_dir.path().join("a-03.pdf"));
+    }
+
+    /// Ensure that the scans of archived documents are kept in the cache if
+    /// configured, marked as archived.
+    #[test]
+    fn keep_scans() {
+        let scans_dir = TempDir::new().unwrap();
+        let outdir = TempDir::new().unwrap();
+        let document_dir = scans_dir.path().join("20250601-143210-01JX");
+        fs::create_dir(&document_dir).unwrap();
+        fs::write(document_dir.join(queue::FINAL_PDF), "%PDF").unwrap();
+        fs::write(document_dir.join("0001.tif"), "").unwrap();
+        let mut manifest = Manifest {
+            state: DocumentState::Processed,
+            ..Default::default()
+        };
+        let info = DocumentInfo {
+            date: date(2025, 5, 30),
+            title: "Invoice".into(),
+            correspondent: None,
+            tags: Vec::new(),
+            due: None,
+            location: None,
+        };
+        let archive_config = ArchiveConfig {
+            keep_scans: true,
+            ..Default::default()
+        };
+        let archived = archive_to(
+            &document_dir,
+            &mut manifest,
+            outdir.path(),
+            &archive_config,
+            Timezone::Utc,
+            &info,
+            &scans_dir.path().join("history.jsonl"),
+        )
+        .unwrap();
+        assert!(archived.exists());
+        assert!(document_dir.join("0001.tif").exists());
+        assert!(!document_dir.join(queue::FINAL_PDF).exists());
+        assert_eq!(
+            Manifest::load(&document_dir).unwrap().state,
+            DocumentState::Archived
+        );
+    }
+
+    /// Ensure that the final PDF is moved to the archive, that the scan is
+    /// recorded in the history, and that the document directory is removed
+    /// from the scans cache.
+    #[test]
+    fn archive_final_pdf() {
+        let scans_dir = TempDir::new().unwrap();
+        let outdir = TempDir::new().unwrap();
+        let document_dir = scans_dir.path().join("20250601-143210-01JX");
+        fs::create_dir(&document_dir).unwrap();
+        fs::write(document_dir.join(queue::FINAL_PDF), "%PDF").unwrap();
+        fs::write(document_dir.join("0001.tif"), "").unwrap();
+
+        let mut manifest = Manifest {
+            state: DocumentState::Processed,
+            scanner: Some("office".into()),
+            scan_stats: Some(ScanStats {
+                mode: "adf".into(),
+                dpi: 300,
+                pages: 1,
+                scan_secs: 2.0,
+                process_secs: Some(5.0),
+            }),
+            ..Default::default()
+        };
+        let history_path = scans_dir.path().join("history.jsonl");
+        let info = DocumentInfo {
+            date: date(2025, 5, 30),
+            title: "Invoice".into(),
+            correspondent: Some("Muster AG".into()),
+            tags: vec!["bills".into()],
+            due: None,
+            location: None,
+        };
+        let archive_config = ArchiveConfig::default();
+        let archived = archive_to(
+            &document_dir,
+            &mut manifest,
+            outdir.path(),
+            &archive_config,
+            Timezone::Utc,
+            &info,
+            &history_path,
+        )
+        .unwrap();
+        assert_eq!(
+            archived,
+            outdir.path().join("2025-05-30_muster-ag_invoice.pdf")
+        );
+        assert_eq!(fs::read_to_string(&archived).unwrap(), "%PDF");
+        assert!(!document_dir.exists());
+        assert_eq!(manifest.state, DocumentState::Archived);
+        assert_eq!(manifest.outputs, vec![archived.clone()]);
+        assert_eq!(manifest.correspondent.as_deref(), Some("Muster AG"));
+        assert_eq!(manifest.tags, vec!["bills"]);
+        assert_eq!(
+            fs::read_to_string(&history_path).unwrap().lines().count(),
+            1
+        );
+
+        // The copy for emailing is archived under the same name
+        fs::create_dir(&document_dir).unwrap();
+        fs::write(document_dir.join(queue::FINAL_PDF), "%PDF").unwrap();
+        fs::write(document_dir.join(queue::EMAIL_PDF), "%PDF small").unwrap();
+        let archived = archive_to(
+            &document_dir,
+            &mut manifest,
+            outdir.path(),
+            &archive_config,
+            Timezone::Utc,
+            &info,
+            &history_path,
+        )
+        .unwrap();
+        assert_eq!(
+            archived,
+            outdir.path().join("2025-05-30_muster-ag_invoice-02.pdf")
+        );
+        let email = outdir
+            .path()
+            .join("2025-05-30_muster-ag_invoice-02_email.pdf");
+        assert_eq!(fs::read_to_string(&email).unwrap(), "%PDF small");
+        assert_eq!(manifest.outputs, vec![archived, email]);
+
+        // Subdirectories of the layout are created on demand
+        fs::create_dir(&document_dir).unwrap();
+        fs::write(document_dir.join(queue::FINAL_PDF), "%PDF").unwrap();
+        let layout_config = ArchiveConfig {
+            outdir_layout: "{year}/{month}".into(),
+            ..Default::default()
+        };
+        let archived = archive_to(
+            &document_dir,
+            &mut manifest,
+            outdir.path(),
+            &layout_config,
+            Timezone::Utc,
+            &info,
+            &history_path,
+        )
+        .unwrap();
+        assert_eq!(
+            archived,
+            outdir
+                .path()
+                .join("2025/05/2025-05-30_muster-ag_invoice.pdf")
+        );
+
+        // Existing documents are kept as older versions
+        let version_config = ArchiveConfig {
+            on_conflict: ConflictPolicy::Version,
+            ..Default::default()
+        };
+        for content in ["%PDF rescan", "%PDF second rescan"] {
+            fs::create_dir(&document_dir).unwrap();
+            fs::write(document_dir.join(queue::FINAL_PDF), content).unwrap();
+            let archived = archive_to(
+                &document_dir,
+                &mut manifest,
+                outdir.path(),
+                &version_config,
+                Timezone::Utc,
+                &info,
+                &history_path,
+            )
+            .unwrap();
+            assert_eq!(
+                archived,
+                outdir.path().join("2025-05-30_muster-ag_invoice.pdf")
+            );
+            assert_eq!(fs::read_to_string(&archived).unwrap(), content);
+        }
+        let versions = outdir.path().join(VERSIONS_DIR);
+        let version = |name: &str| fs::read_to_string(versions.join(name)).unwrap();
+        assert_eq!(version("2025-05-30_muster-ag_invoice_v1.pdf"), "%PDF");
+        assert_eq!(
+            version("2025-05-30_muster-ag_invoice_v2.pdf"),
+            "%PDF rescan"
+        );
+
+        // The copy for emailing is kept with its version
+        for content in ["%PDF third rescan", "%PDF fourth rescan"] {
+            fs::create_dir(&document_dir).unwrap();
+            fs::write(document_dir.join(queue::FINAL_PDF), content).unwrap();
+            fs::write(document_dir.join(queue::EMAIL_PDF), "%PDF small").unwrap();
+            archive_to(
+                &document_dir,
+                &mut manifest,
+                outdir.path(),
+                &version_config,
+                Timezone::Utc,
+                &info,
+                &history_path,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            version("2025-05-30_muster-ag_invoice_v4.pdf"),
+            "%PDF third rescan"
+        );
+        assert_eq!(
+            version("2025-05-30_muster-ag_invoice_v4_email.pdf"),
+            "%PDF small"
+        );
+        assert!(
+            outdir
+                .path()
+                .join("2025-05-30_muster-ag_invoice_email.pdf")
+                .exists()
+        );
+
+        // Existing documents (and their copies for emailing) are replaced
+        let overwrite_config = ArchiveConfig {
+            on_conflict: ConflictPolicy::Overwrite,
+            ..Default::default()
+        };
+        fs::create_dir(&document_dir).unwrap();
+        fs::write(document_dir.join(queue::FINAL_PDF), "%PDF replacement").unwrap();
+        let archived = archive_to(
+            &document_dir,
+            &mut manifest,
+            outdir.path(),
+            &overwrite_config,
+            Timezone::Utc,
+            &info,
+            &history_path,
+        )
+        .unwrap();
+        assert_eq!(
+            archived,
+            outdir.path().join("2025-05-30_muster-ag_invoice.pdf")
+        );
+        assert_eq!(fs::read_to_string(&archived).unwrap(), "%PDF replacement");
+        assert!(
+            !outdir
+                .path()
+                .join("2025-05-30_muster-ag_invoice_email.pdf")
+                .exists()
+        );
+        let events = audit::load(outdir.path(), &["invoice".into()]).unwrap();
+        let replaced: Vec<_> = events
+            .iter()
+            .filter(|event| event.action == audit::Action::Replace)
+            .map(|event| event.path.as_str())
+            .collect();
+        assert_eq!(
+            replaced,
+            vec![
+                "2025-05-30_muster-ag_invoice.pdf",
+                "2025-05-30_muster-ag_invoice_email.pdf"
+            ]
+        );
+
+        // Replaced documents are kept if the new document can't be moved in
+        let email = outdir.path().join("2025-05-30_muster-ag_invoice_email.pdf");
+        fs::write(&email, "%PDF small").unwrap();
+        fs::create_dir_all(document_dir.join(queue::FINAL_PDF).join("unmovable")).unwrap();
+        assert!(
+            archive_to(
+                &document_dir,
+                &mut manifest,
+                outdir.path(),
+                &overwrite_config,
+                Timezone::Utc,
+                &info,
+                &history_path,
+            )
+            .is_err()
+        );
+        assert_eq!(fs::read_to_string(&archived).unwrap(), "%PDF replacement");
+        assert_eq!(fs::read_to_string(&email).unwrap(), "%PDF small");
+        assert!(
+            !outdir
+                .path()
+                .join(".2025-05-30_muster-ag_invoice.pdf")
+                .exists()
+        );
+        fs::remove_dir_all(&document_dir).unwrap();
+        let first_version = events
+            .iter()
+            .find(|event| event.action == audit::Action::Version)
+            .unwrap();
+        assert_eq!(
+            first_version.path,
+            ".versions/2025-05-30_muster-ag_invoice_v1.pdf"
+        );
+        assert_eq!(
+            first_version.previous.as_deref(),
+            Some("2025-05-30_muster-ag_invoice.pdf")
+        );
+
+        // The policy may be chosen for every conflict
+        let prompt_config = ArchiveConfig {
+            on_conflict: ConflictPolicy::Prompt,
+            ..Default::default()
+        };
+        for (answer, name) in [
+            (
+                "Keep both, with a suffix for the new document",
+                "invoice-03.pdf",
+            ),
+            ("Replace the existing document", "invoice.pdf"),
+        ] {
+            fs::create_dir(&document_dir).unwrap();
+            fs::write(document_dir.join(queue::FINAL_PDF), "%PDF").unwrap();
+            let archived = prompt::with_prompter(ScriptedPrompter::new([answer]), || {
+                archive_to(
+                    &document_dir,
+                    &mut manifest,
+                    outdir.path(),
+                    &prompt_config,
+                    Timezone::Utc,
+                    &info,
+                    &history_path,
+                )
+            })
+            .unwrap();
+            assert_eq!(
+                archived,
+                outdir.path().join(format!("2025-05-30_muster-ag_{name}"))
+            );
+        }
+
+        // Documents without a final PDF are not archived
+        fs::create_dir(&document_dir).unwrap();
+        assert!(
+            archive_to(
+                &document_dir,
+                &mut manifest,
+                outdir.path(),
+                &archive_config,
+                Timezone::Utc,
+                &info,
+                &history_path,
+            )
+            .is_err()
+        );
+        assert!(document_dir.exists());
+    }
+}
@@ -1,6 +1,11 @@
-use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
 use tracing_subscriber::filter::LevelFilter;
 
+use crate::{convert_archive::Grouping, extract::Redaction};
+
 #[derive(Debug, Clone, ValueEnum, Default)]
 pub enum LogLevel {
     Trace,
@@ -36,28 +41,378 @@ impl std::fmt::Display for LogLevel {
     }
 }
 
-#[derive(Debug, Clone, ValueEnum, Default)]
+/// Format of progress reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum ProgressFormat {
+    /// Progress bars and messages for humans
+    #[default]
+    Terminal,
+    /// Additionally, newline-delimited JSON events on stdout (for GUI
+    /// frontends)
+    Json,
+}
+
+#[derive(Debug, Clone, Subcommand, Default)]
 pub enum Mode {
-    Scan,
-    Process,
+    /// Scan a document without processing it
+    Scan {
+        /// Scan with multiple scanners at the same time
+        #[arg(long)]
+        parallel: bool,
+    },
+    /// Create the config file, with the scanners detected by SANE
+    InitConfig,
+    /// Add newly detected scanners to the config file, or remove configured
+    /// ones
+    ManageScanners,
+    /// Process scanned documents that have not been processed yet, selected
+    /// interactively
+    Process {
+        /// Process all unprocessed documents without asking
+        #[arg(long)]
+        all: bool,
+        /// Create a document from image files of other scanner software (in
+        /// page order) and process it. With `-`, the files are read from
+        /// stdin, one path per line.
+        #[arg(long, num_args = 1.., value_name = "FILE", conflicts_with = "all")]
+        from_files: Option<Vec<PathBuf>>,
+    },
+    /// Archive processed documents
     Archive,
+    /// Search the text, names and tags of the archived documents
+    Search {
+        /// Words that the documents must contain (e.g. `insurance 2023`)
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Maximal number of documents to list
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Copy the path of the best match to the clipboard (as `file://` URL
+        /// if the configured `clipboard` is `url`)
+        #[arg(long)]
+        copy: bool,
+    },
+    /// Open the archived document that best matches a search in the PDF
+    /// viewer, asking which one if several documents match
+    Open {
+        /// Words that the document must contain (e.g. `insurance 2023`)
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Combine archived documents that are found by a search into a single
+    /// PDF, with a bookmark per document (e.g. a dossier for an insurance)
+    Combine {
+        /// Words that the documents must contain (e.g. `car accident 2025`)
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Split an archived PDF that contains several documents into separate
+    /// documents, asking for the metadata of each
+    Split {
+        /// The archived PDF
+        document: PathBuf,
+        /// Pages of each document (e.g. `1-2 3-5`), covering every page once
+        #[arg(required = true, num_args = 2..)]
+        ranges: Vec<String>,
+    },
+    /// Process an archived document again from its scans (kept with
+    /// `keep_scans`) with the current settings, e.g. after enabling
+    /// deskewing, and replace it, keeping the archived PDF as older version
+    Reprocess {
+        /// The archived PDF
+        document: PathBuf,
+    },
+    /// List the archived documents whose expiry or follow-up dates are
+    /// approaching or past
+    Due {
+        /// Number of days ahead
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+    },
+    /// Run OCR again over archived PDFs (e.g. after an upgrade of
+    /// Tesseract), replacing their text layers and keeping the previous PDFs
+    /// as older versions. The old and new text is recorded in
+    /// `.arkivisto-re-ocr` in the archive for comparison.
+    ReOcr {
+        /// The archived PDFs
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        documents: Vec<PathBuf>,
+        /// Run OCR over all archived PDFs that were not recognized by an
+        /// earlier run yet
+        #[arg(long)]
+        all: bool,
+        /// Start a new run over all archived PDFs (e.g. after another upgrade
+        /// of Tesseract), dropping the old and new text of the earlier runs
+        #[arg(long, requires = "all")]
+        restart: bool,
+        /// Seconds to pause between documents, to limit the load
+        #[arg(long, default_value_t = 0)]
+        pause: u64,
+    },
+    /// Link two archived documents (e.g. an invoice and its payment
+    /// confirmation), which are then listed with each other in search results
+    Link {
+        /// The archived PDF
+        document: PathBuf,
+        /// The archived PDF to link it to
+        other: PathBuf,
+        /// Kind of relation (e.g. `payment` or `warranty`)
+        #[arg(long)]
+        relation: Option<String>,
+        /// Remove the link instead
+        #[arg(long, conflicts_with = "relation")]
+        remove: bool,
+    },
+    /// Move an archived document into the trash of the archive, dropping it
+    /// from the indexes
+    Delete {
+        /// The archived PDF
+        document: PathBuf,
+    },
+    /// Manage the deleted documents in the trash of the archive
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+    /// Manage the tags and correspondents of the archived documents
+    Tags {
+        #[command(subcommand)]
+        action: TagsAction,
+    },
+    /// Update the search index with the PDFs in the archive directory (e.g.
+    /// after filing or deleting documents by hand)
+    Index,
+    /// Check the search and tag indexes against the PDFs in the archive
+    /// directory, reporting PDFs without entries, entries without PDFs and
+    /// PDFs that changed since they were indexed
+    Fsck {
+        /// Update the indexes to repair the inconsistencies
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Export the archived documents with their metadata, e.g. to migrate to
+    /// another document management system
+    Export {
+        /// Empty or new directory (paperless) or file (RIS) to export to
+        target: PathBuf,
+        /// Format of the export
+        #[arg(long, value_enum, default_value = "paperless")]
+        format: ExportFormat,
+        /// Only export the documents with this tag (e.g. `paper`)
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Push scanned papers with their PDFs into Zotero (which must be
+    /// running), with the DOIs found in the text
+    Zotero {
+        /// Only push the documents with this tag
+        #[arg(long, default_value = "paper")]
+        tag: String,
+    },
+    /// Import the documents of a paperless-ngx export (created with
+    /// `document_exporter`) into the archive
+    ImportPaperless {
+        /// Directory of the export, with the `manifest.json`
+        source: PathBuf,
+    },
+    /// Replace the views of the archive (links to the documents by tag,
+    /// correspondent and year) in the configured `views_dir`
+    RebuildViews,
+    /// List the changes to the archive (archived, replaced, split documents),
+    /// oldest first
+    History {
+        /// Words that the path, ID or metadata of the documents must contain
+        query: Vec<String>,
+    },
+    /// Sum the totals of the archived receipts per month, as expense report
+    Report {
+        /// Tag of the receipts
+        #[arg(long, default_value = "receipt")]
+        tag: String,
+        /// First date of the period (e.g. `2025-01-01`)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last date of the period (e.g. `2025-12-31`)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Scan, process and archive a single document
     #[default]
     Single,
+    /// Scan and process a large backlog of documents from the ADF, separated by
+    /// blank sheets. Documents are named later with `name-pending`.
+    Bulk,
+    /// Name the documents that were scanned in bulk mode
+    NamePending,
+    /// Review documents that are awaiting metadata or were flagged with
+    /// warnings
+    Review,
+    /// Calibrate a scanner by scanning a reference sheet
+    Calibrate {
+        /// ID of the scanner to calibrate
+        scanner_id: String,
+        /// Determine the geometry corrections from a sheet with a frame,
+        /// instead of the level and gamma correction
+        #[arg(long)]
+        geometry: bool,
+    },
+    /// Extract the transactions from the text of a statement (e.g. `_final.txt`)
+    /// and print them as CSV, to debug the statement parser
+    ParseStatement {
+        /// Text file with the recognized text
+        file: PathBuf,
+        /// Locale that determines the date order (defaults to the configured
+        /// locale)
+        #[arg(long)]
+        locale: Option<String>,
+    },
+    /// Import photos of documents (JPEG) from the configured hot folder or
+    /// the given directory. Photos taken within a minute of each other form
+    /// one document.
+    Import {
+        /// Directory to import from (defaults to the hot folder)
+        directory: Option<PathBuf>,
+    },
+    /// Convert an old archive of loose TIFF/JPEG scans into searchable PDFs,
+    /// keeping the original timestamps
+    ConvertArchive {
+        /// Root directory of the old archive (it is not modified)
+        source: PathBuf,
+        /// How the scans are grouped into documents
+        #[arg(long, value_enum, default_value = "folder")]
+        group_by: Grouping,
+    },
+    /// Create a copy of a document (a PDF, or a processed document directory)
+    /// with a watermark across every page, e.g. "Copy for Insurance AG,
+    /// 2025-06-01". The document itself is not modified.
+    Share {
+        /// The document
+        document: PathBuf,
+        /// Recipient of the copy
+        #[arg(long)]
+        recipient: String,
+        /// Output file (default: named after the document and the recipient,
+        /// in the working directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Extract selected pages of a document (a PDF, or a processed document
+    /// directory) into a new PDF, e.g. to share only the relevant pages
+    Extract {
+        /// The document
+        document: PathBuf,
+        /// Pages to extract (e.g. `2-3,5`)
+        #[arg(long)]
+        pages: String,
+        /// Output file (default: named after the document and the pages, in
+        /// the working directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Black out a rectangle, given as `PAGE:X,Y,WIDTH,HEIGHT` in mm from
+        /// the top left corner (can be repeated). Redacted pages lose their
+        /// text layer.
+        #[arg(long)]
+        redact: Vec<Redaction>,
+    },
+    /// Manage the processing queue
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+}
+
+/// Format of an export of the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum ExportFormat {
+    /// Directory for the document importer of paperless-ngx
+    /// (`document_importer`), with the PDFs and a `manifest.json`
+    #[default]
+    Paperless,
+    /// RIS file for reference managers like Zotero, with the DOIs found in
+    /// the text and links to the PDFs (e.g. for scanned papers)
+    Ris,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum TrashAction {
+    /// List the deleted documents, oldest first
+    List,
+    /// Restore a deleted document to its previous path, with its metadata
+    Restore {
+        /// Name of the document in the trash (see `trash list`)
+        name: String,
+    },
+    /// Remove the documents that were deleted longer ago than the configured
+    /// `trash_days`
+    Purge {
+        /// Remove all documents in the trash
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum TagsAction {
+    /// Find tags and correspondents that only differ in case or by a typo
+    /// (e.g. "Insurance" and "Insurnace"), and merge them in the archive
+    Normalize,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum QueueAction {
+    /// Pause processing (running processes wait before the next heavy step)
+    Pause,
+    /// Resume processing
+    Resume,
+    /// Show the state of the processing queue
+    Status,
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(next_line_help = true)]
 pub struct Args {
-    /// Processing mode
-    #[arg(value_enum, default_value_t = Mode::default())]
-    pub mode: Mode,
+    /// Processing mode (default: single)
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
 
     /// Log level
     #[arg(short, long, value_enum, default_value_t = LogLevel::default())]
     pub log_level: LogLevel,
 
-    /// Dev mode: Don't actually scan, but use simulated scan TIFFs
+    /// Profile from the config file to use
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
+
+    /// Skip OCR and produce an image-only PDF (faster)
+    #[arg(long, global = true)]
+    pub skip_ocr: bool,
+
+    /// Format of progress reports
+    #[arg(long, value_enum, global = true, default_value = "terminal")]
+    pub progress: ProgressFormat,
+
+    /// Answer all questions from a file instead of asking (one answer per
+    /// line, an empty line accepts the default, `-` clears a pre-filled text,
+    /// `<esc>` skips a question)
+    #[arg(long, global = true)]
+    pub answers: Option<PathBuf>,
+
+    /// Ask questions with numbered options and line-based input instead of
+    /// interactive widgets (for screen readers, dumb terminals and `expect`;
+    /// an empty line accepts the default, `-` clears a pre-filled text,
+    /// `<esc>` skips a question)
+    #[arg(long, global = true, conflicts_with = "answers")]
+    pub plain_prompts: bool,
+
+    /// Print a breakdown of the time spent in the individual steps
+    #[arg(long)]
+    pub profile_timings: bool,
+
+    /// Dev mode: Don't actually scan, but generate virtual pages
     #[cfg_attr(not(debug_assertions), arg(skip))]
     #[cfg_attr(debug_assertions, arg(long))]
     pub fake_scan: bool,
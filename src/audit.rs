@@ -0,0 +1,256 @@
+//! Audit log of the changes to the archive
+//!
+//! Every change that arkivisto makes to the archive (e.g. archiving, replacing
+//! or splitting a document) is appended to a log in the archive directory,
+//! with the time, the affected paths and the new metadata. The log is never
+//! rewritten, so that it can be traced what happened to a document that is
+//! missing (`arkivisto history`).
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{config::Timezone, fs_utils};
+
+/// Name of the audit log in the archive directory (one JSON event per line)
+const LOG_FILE: &str = ".arkivisto-audit.jsonl";
+
+/// Kind of change to the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// A scanned document was archived
+    Archive,
+    /// A document of another document management system was archived
+    Import,
+    /// A document was kept as older version, as a new one replaced it
+    Version,
+    /// A document was replaced and deleted
+    Replace,
+    /// A document was split into several documents, and removed
+    Split,
+    /// A document was moved into the trash
+    Delete,
+    /// A document was restored from the trash
+    Restore,
+    /// A document was removed from the trash
+    Purge,
+    /// The metadata of a document was changed (e.g. by merging tags), which
+    /// may have renamed it
+    Edit,
+    /// A document was processed again from its scans and replaced
+    Reprocess,
+    /// The text layer of a document was recognized again and replaced
+    #[serde(rename = "re-ocr")]
+    ReOcr,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Action::Archive => "archive",
+            Action::Import => "import",
+            Action::Version => "version",
+            Action::Replace => "replace",
+            Action::Split => "split",
+            Action::Delete => "delete",
+            Action::Restore => "restore",
+            Action::Purge => "purge",
+            Action::Edit => "edit",
+            Action::Reprocess => "reprocess",
+            Action::ReOcr => "re-ocr",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A change to the archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Time of the change (RFC 3339, in the configured timezone)
+    pub at: String,
+    pub action: Action,
+    /// Path of the document, relative to the archive directory
+    pub path: String,
+    /// Previous path of the document, if it was moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    /// Stable ID of the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// New metadata or other details (e.g. the parts of a split document)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl Event {
+    /// A change of the document at `path` in the archive `outdir`, now
+    pub fn new(action: Action, outdir: &Path, path: &Path, timezone: Timezone) -> Self {
+        Self {
+            at: timezone.now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            action,
+            path: fs_utils::relative_key(outdir, path),
+            previous: None,
+            id: None,
+            details: None,
+        }
+    }
+
+    pub fn with_previous(mut self, outdir: &Path, previous: &Path) -> Self {
+        self.previous = Some(fs_utils::relative_key(outdir, previous));
+        self
+    }
+
+    pub fn with_id(mut self, id: Option<&str>) -> Self {
+        self.id = id.map(str::to_string);
+        self
+    }
+
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    /// Whether the event concerns a document whose path, ID or details
+    /// contain all `words` (ignoring case)
+    fn matches(&self, words: &[String]) -> bool {
+        let text = [
+            Some(&self.path),
+            self.previous.as_ref(),
+            self.id.as_ref(),
+            self.details.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|value| value.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+        words.iter().all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<7} {}", self.at, self.action, self.path)?;
+        if let Some(previous) = &self.previous {
+            write!(f, " (from {previous})")?;
+        }
+        if let Some(details) = &self.details {
+            write!(f, ": {details}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Append an event to the audit log of `outdir`
+///
+/// Failures are only logged, as the change itself was made.
+pub fn record(outdir: &Path, event: &Event) {
+    let path = outdir.join(LOG_FILE);
+    let result = serde_json::to_string(event)
+        .context("Failed to serialize audit event")
+        .and_then(|mut line| {
+            line.push('\n');
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| {
+                    file.write_all(line.as_bytes())?;
+                    file.sync_all()
+                })
+                .with_context(|| format!("Failed to write audit log {path:?}"))
+        });
+    if let Err(e) = result {
+        warn!("{e:#}");
+    }
+}
+
+/// The events in the audit log of `outdir` that concern documents matching
+/// all `words` (see [`Event::matches`]), oldest first
+///
+/// Invalid lines are skipped with a warning.
+pub fn load(outdir: &Path, words: &[String]) -> Result<Vec<Event>> {
+    let path = outdir.join(LOG_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs_utils::retry_stale(|| fs::read_to_string(&path))
+        .with_context(|| format!("Failed to read audit log {path:?}"))?;
+    let mut events = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Event>(line) {
+            Ok(event) if event.matches(words) => events.push(event),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping line {} of the audit log: {e}", number + 1),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that events are appended to the log, and that they are found
+    /// by their paths, ID and details.
+    #[test]
+    fn log() {
+        let outdir = TempDir::new().unwrap();
+        let invoice = outdir.path().join("2025/invoice.pdf");
+        let version = outdir.path().join("2025/.versions/invoice_v1.pdf");
+        record(
+            outdir.path(),
+            &Event::new(Action::Archive, outdir.path(), &invoice, Timezone::Utc)
+                .with_id(Some("01JAB"))
+                .with_details("Invoice from Muster AG"),
+        );
+        record(
+            outdir.path(),
+            &Event::new(Action::Version, outdir.path(), &version, Timezone::Utc)
+                .with_previous(outdir.path(), &invoice),
+        );
+        fs::write(
+            outdir.path().join(LOG_FILE),
+            fs::read_to_string(outdir.path().join(LOG_FILE)).unwrap() + "garbage\n",
+        )
+        .unwrap();
+
+        let events = load(outdir.path(), &[]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].path, "2025/invoice.pdf");
+        assert!(events[0].at.ends_with("+00:00"));
+        assert_eq!(
+            events[1].to_string(),
+            format!(
+                "{} version 2025/.versions/invoice_v1.pdf (from 2025/invoice.pdf)",
+                events[1].at
+            )
+        );
+
+        let find = |query: &str| -> Vec<Action> {
+            let words: Vec<String> = query.split_whitespace().map(str::to_string).collect();
+            load(outdir.path(), &words)
+                .unwrap()
+                .into_iter()
+                .map(|event| event.action)
+                .collect()
+        };
+        assert_eq!(find("INVOICE.pdf"), vec![Action::Archive, Action::Version]);
+        assert_eq!(find("muster 01jab"), vec![Action::Archive]);
+        assert!(find("letter").is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tracing::{debug, trace, warn};
+
+use crate::{
+    fs_utils,
+    manifest::{self, DocumentState, Manifest, ScanSource},
+    process, prompt, quality, queue,
+    scan::{self, ScanContext},
+    timings::Timings,
+    verify,
+};
+
+/// Pages with a contrast (0-100) below this value are considered blank, i.e.
+/// separator sheets
+const SEPARATOR_MAX_CONTRAST: f32 = 15.0;
+
+/// Scan a large backlog of documents from the ADF, return the document
+/// directories
+///
+/// Documents are separated by blank sheets. All documents are parked as
+/// "needs naming", so that they can be named later in a batch with
+/// [`name_pending`].
+pub fn scan_bulk(context: &ScanContext, timings: &mut Timings) -> Result<Vec<PathBuf>> {
+    println!("Insert a blank sheet between documents. Documents are named later.");
+    let staging_dir = scan::scan_continuous(context, timings)?;
+
+    // Split pages at separator sheets
+    let pages = process::collect_page_tifs(&staging_dir)?;
+    let separators: Vec<bool> = pages
+        .iter()
+        .map(|page| is_separator(&staging_dir.join(page)))
+        .collect();
+    let documents = split_documents(&separators);
+    debug!(
+        "Split {} page(s) into {} document(s)",
+        pages.len(),
+        documents.len()
+    );
+
+    // Move every document into its own directory
+    let scans_dir = scan::scans_dir()?;
+    let mut document_dirs = Vec::new();
+    for document in documents {
+        let (document_dir, manifest) = scan::create_document_dir(&scans_dir, context, "bulk")?;
+        for (i, page) in document.into_iter().enumerate() {
+            fs_utils::move_path(
+                &staging_dir.join(&pages[page]),
+                &document_dir.join(format!("{:04}.tif", i + 1)),
+            )
+            .context("Failed to move scanned page")?;
+        }
+        let manifest = Manifest {
+            source: Some(ScanSource::Adf),
+            needs_naming: true,
+            ..manifest
+        };
+        manifest.save(&document_dir)?;
+        document_dirs.push(document_dir);
+    }
+
+    // Only the separator sheets are left
+    fs::remove_dir_all(&staging_dir).context("Failed to remove staging directory")?;
+
+    Ok(document_dirs)
+}
+
+/// Whether a scanned page is a (blank) separator sheet
+fn is_separator(page: &Path) -> bool {
+    match quality::assess(page) {
+        Ok(page_quality) => {
+            trace!("Quality of {:?}: {:?}", page, page_quality);
+            page_quality.contrast < SEPARATOR_MAX_CONTRAST
+        }
+        Err(e) => {
+            debug!("Failed to assess {:?}, assuming content: {:#}", page, e);
+            false
+        }
+    }
+}
+
+/// Split a sequence of pages into documents at the separator pages, return
+/// the page indices of every document
+///
+/// Separator pages are dropped, and consecutive separators don't result in
+/// empty documents.
+fn split_documents(separators: &[bool]) -> Vec<Vec<usize>> {
+    let mut documents = Vec::new();
+    let mut current = Vec::new();
+    for (i, is_separator) in separators.iter().enumerate() {
+        if *is_separator {
+            if !current.is_empty() {
+                documents.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(i);
+        }
+    }
+    if !current.is_empty() {
+        documents.push(current);
+    }
+    documents
+}
+
+/// Name all documents that were parked as "needs naming", one after another
+pub fn name_pending(scans_dir: &Path) -> Result<()> {
+    let pending = manifest::find_documents(scans_dir, |manifest| manifest.needs_naming)?;
+    if pending.is_empty() {
+        println!("No documents need naming");
+        return Ok(());
+    }
+
+    let total = pending.len();
+    for (i, (document_dir, mut manifest)) in pending.into_iter().enumerate() {
+        // Show the document
+        let final_pdf = document_dir.join(queue::FINAL_PDF);
+        let preview = if final_pdf.exists() {
+            final_pdf
+        } else {
+            document_dir.join("0001.tif")
+        };
+        if let Err(e) = verify::open_file(&preview) {
+            warn!("Failed to open {:?}: {:#}", preview, e);
+        }
+
+        let title = prompt::Text::new(&format!("Title of document {}/{}?", i + 1, total))
+            .with_help_message(&format!(
+                "{}. Leave empty to skip, press Esc to stop",
+                document_dir.display()
+            ))
+            .prompt_skippable()?;
+        let Some(title) = title else {
+            break;
+        };
+        let title = title.trim();
+        if title.is_empty() {
+            continue;
+        }
+        manifest.title = Some(title.to_string());
+        manifest.needs_naming = false;
+        if manifest.state == DocumentState::NeedsReview {
+            manifest.mark_processed();
+        }
+        manifest.save(&document_dir)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that documents are split at separator sheets, which are
+    /// dropped.
+    #[test]
+    fn split_at_separators() {
+        let separators = [false, false, true, false, true, true, false, false, false];
+        assert_eq!(
+            split_documents(&separators),
+            vec![vec![0, 1], vec![3], vec![6, 7, 8]]
+        );
+    }
+
+    /// Ensure that leading and trailing separators don't result in empty
+    /// documents.
+    #[test]
+    fn split_leading_trailing_separators() {
+        assert_eq!(split_documents(&[true, false, true]), vec![vec![1]]);
+        assert!(split_documents(&[true, true]).is_empty());
+        assert!(split_documents(&[]).is_empty());
+    }
+}
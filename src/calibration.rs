@@ -0,0 +1,329 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{config::Geometry, fs_utils, scan::ScanContext, tiff_utils};
+
+/// Name of the file storing the calibrations of all scanners
+const CALIBRATION_FILE: &str = "calibration.toml";
+
+/// Fraction of the darkest and brightest pixels that are ignored when
+/// determining the black and white points (e.g. dust or specular highlights)
+const CLIP_FRACTION: f64 = 0.01;
+
+/// Distance (in mm) of the outer edges of the frame on the geometry reference
+/// sheet from the paper edges
+const FRAME_MARGIN_MM: f32 = 10.0;
+
+/// Pixel rows or columns whose mean luminance is below this fraction of the
+/// median are considered part of a frame line
+const FRAME_LINE_THRESHOLD: f32 = 0.8;
+
+/// Level and gamma correction of a scanner, applied in post-processing
+///
+/// The values correspond to the arguments of ImageMagick's `-level` operator.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Black point in percent
+    pub black: f32,
+    /// White point in percent
+    pub white: f32,
+    /// Gamma correction
+    pub gamma: f32,
+}
+
+impl Calibration {
+    /// Compute the calibration from the luminance histogram of a reference
+    /// sheet with equally large black, mid-gray and white areas
+    ///
+    /// The black and white points are mapped to black and white, and the
+    /// gamma is chosen so that the median (the mid-gray area) ends up at 50%.
+    fn from_histogram(histogram: &[u64; 256]) -> Result<Self> {
+        let total: u64 = histogram.iter().sum();
+        ensure!(total > 0, "Reference scan is empty");
+
+        let percentile = |fraction| tiff_utils::histogram_percentile(histogram, fraction);
+        let black = percentile(CLIP_FRACTION);
+        let white = percentile(1.0 - CLIP_FRACTION);
+        let median = percentile(0.5);
+        ensure!(
+            white > black + 16,
+            "Reference scan has too little contrast (black point {black}, white point {white}). \
+             Please use a sheet with black, mid-gray and white areas."
+        );
+
+        let position = ((median - black) as f64 / (white - black) as f64).clamp(0.05, 0.95);
+        let gamma = (position.ln() / 0.5f64.ln()).clamp(0.2, 5.0);
+        Ok(Self {
+            black: black as f32 / 255.0 * 100.0,
+            white: white as f32 / 255.0 * 100.0,
+            gamma: gamma as f32,
+        })
+    }
+}
+
+/// Calibrations of all scanners, by scanner ID
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CalibrationStore {
+    #[serde(flatten)]
+    scanners: HashMap<String, Calibration>,
+}
+
+impl CalibrationStore {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+            .with_context(|| format!("Failed to read calibration file {path:?}"))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse calibration file {path:?}"))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize calibration")?;
+        fs_utils::write_synced(path, content)
+            .with_context(|| format!("Failed to write calibration file {path:?}"))
+    }
+}
+
+/// Return the path of the calibration file in the XDG app data directory
+fn calibration_path() -> Result<PathBuf> {
+    let data_dir = app_dirs::app_root(app_dirs::AppDataType::UserData, &crate::APP_INFO)
+        .context("Could not determine XDG app data directory")?;
+    Ok(data_dir.join(CALIBRATION_FILE))
+}
+
+/// Load the calibration of a scanner, if it has been calibrated
+pub fn load(scanner_id: &str) -> Result<Option<Calibration>> {
+    let store = CalibrationStore::load(&calibration_path()?)?;
+    Ok(store.scanners.get(scanner_id).copied())
+}
+
+/// Scan a reference sheet with the scanner of the context and store the
+/// resulting calibration
+pub fn calibrate(context: &ScanContext) -> Result<Calibration> {
+    println!(
+        "Please insert a reference sheet with equally large black, mid-gray and white areas \
+         into {}.",
+        context.scanner.id
+    );
+    let (staging_dir, page) = crate::scan::scan_reference_page(context)?;
+    let result = tiff_utils::luminance_histogram(&page)
+        .and_then(|histogram| Calibration::from_histogram(&histogram));
+    fs::remove_dir_all(&staging_dir).context("Failed to remove reference scan")?;
+    let calibration = result?;
+    debug!("Calibration of {}: {:?}", context.scanner.id, calibration);
+
+    let path = calibration_path()?;
+    let mut store = CalibrationStore::load(&path)?;
+    store
+        .scanners
+        .insert(context.scanner.id.clone(), calibration);
+    store.save(&path)?;
+
+    Ok(calibration)
+}
+
+/// Find the outer edges of the frame lines in a luminance profile (the mean
+/// luminance of every pixel column or row), return the index of the first and
+/// the last pixel belonging to the frame
+///
+/// Dark margins at the edges (e.g. the black background of an ADF) are
+/// skipped.
+fn frame_lines(profile: &[f32]) -> Option<(usize, usize)> {
+    let mut sorted = profile.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = *sorted.get(sorted.len() / 2)?;
+    let is_dark = |i: &usize| profile[*i] < median * FRAME_LINE_THRESHOLD;
+
+    let paper_start = (0..profile.len()).find(|i| !is_dark(i))?;
+    let paper_end = (0..profile.len()).rfind(|i| !is_dark(i))?;
+    let first = (paper_start..=paper_end).find(is_dark)?;
+    let last = (paper_start..=paper_end).rfind(is_dark)?;
+    (first < last).then_some((first, last))
+}
+
+/// Correct the offset and extra size of one axis, given the size of the paper
+/// and the positions of the outer frame edges in the scan (all in mm)
+///
+/// Returns the new offset and extra size, and how much of the paper is
+/// clipped at the start of the axis even without an offset.
+fn correct_axis(offset: f32, size: f32, (first, last): (f32, f32)) -> (f32, f32, f32) {
+    let round = |mm: f32| (mm * 10.0).round() / 10.0;
+
+    // Paper edges in the coordinates of the scanner
+    let paper_start = offset + first - FRAME_MARGIN_MM;
+    let paper_end = offset + last + FRAME_MARGIN_MM;
+
+    let offset = paper_start.max(0.0);
+    let extra = (paper_end - offset - size).max(0.0);
+    let clipped = (-paper_start).max(0.0);
+    (round(offset), round(extra), round(clipped))
+}
+
+/// Scan a reference sheet with a frame and determine the geometry corrections
+/// of the scanner of the context
+///
+/// The outer edges of the frame lines must be [`FRAME_MARGIN_MM`] from the
+/// paper edges. The corrections are relative to the geometry currently
+/// configured for the scanner.
+pub fn calibrate_geometry(context: &ScanContext) -> Result<Geometry> {
+    println!(
+        "Please insert a reference sheet ({:?}) with a thin frame whose outer edges are \
+         {FRAME_MARGIN_MM} mm from the paper edges into {}.",
+        context.paper, context.scanner.id
+    );
+    let (staging_dir, page) = crate::scan::scan_reference_page(context)?;
+    let result = tiff_utils::read_luminance(&page);
+    fs::remove_dir_all(&staging_dir).context("Failed to remove reference scan")?;
+    let image = result?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    ensure!(width > 0 && height > 0, "Reference scan is empty");
+
+    // Mean luminance of all columns and rows
+    let mut columns = vec![0f32; width];
+    let mut rows = vec![0f32; height];
+    for (y, row) in image.pixels.chunks_exact(width).enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            columns[x] += f32::from(*pixel) / height as f32;
+            rows[y] += f32::from(*pixel) / width as f32;
+        }
+    }
+    let not_found = || {
+        anyhow!("Frame not found on the reference scan. Is the whole frame visible on the scan?")
+    };
+    let horizontal = frame_lines(&columns).ok_or_else(not_found)?;
+    let vertical = frame_lines(&rows).ok_or_else(not_found)?;
+
+    // Convert pixels to millimetres, based on the size of the scan area
+    let current = context.scanner.geometry;
+    let (paper_width, paper_height) = context.paper.dimensions_mm();
+    let px_per_mm = width as f32 / (paper_width + current.extra_width);
+    let mm =
+        |(first, last): (usize, usize)| (first as f32 / px_per_mm, (last + 1) as f32 / px_per_mm);
+    debug!(
+        "Frame of reference scan: {:?} x {:?} mm",
+        mm(horizontal),
+        mm(vertical)
+    );
+
+    let (left, extra_width, clipped_left) = correct_axis(current.left, paper_width, mm(horizontal));
+    let (top, extra_height, clipped_top) = correct_axis(current.top, paper_height, mm(vertical));
+    for (clipped, edge) in [(clipped_left, "left"), (clipped_top, "top")] {
+        if clipped > 0.0 {
+            warn!("The scanner clips {clipped} mm at the {edge} edge, which cannot be corrected");
+        }
+    }
+    Ok(Geometry {
+        left,
+        top,
+        extra_width,
+        extra_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Build a histogram with equally large areas of the given luminances
+    fn histogram(values: &[u8]) -> [u64; 256] {
+        let mut histogram = [0; 256];
+        for value in values {
+            histogram[*value as usize] += 1000;
+        }
+        histogram
+    }
+
+    /// Ensure that a perfect reference scan results in a neutral calibration.
+    #[test]
+    fn neutral() {
+        let calibration = Calibration::from_histogram(&histogram(&[0, 128, 255])).unwrap();
+        assert_eq!(calibration.black, 0.0);
+        assert_eq!(calibration.white, 100.0);
+        assert!((calibration.gamma - 1.0).abs() < 0.02, "{calibration:?}");
+    }
+
+    /// Ensure that a washed out, dark scan is stretched and brightened.
+    #[test]
+    fn washed_out() {
+        let calibration = Calibration::from_histogram(&histogram(&[51, 102, 204])).unwrap();
+        assert_eq!(calibration.black, 20.0);
+        assert_eq!(calibration.white, 80.0);
+        assert!(calibration.gamma > 1.0, "{calibration:?}");
+    }
+
+    /// Ensure that a blank sheet is rejected.
+    #[test]
+    fn no_contrast() {
+        assert!(Calibration::from_histogram(&histogram(&[250])).is_err());
+        assert!(Calibration::from_histogram(&[0; 256]).is_err());
+    }
+
+    /// Ensure that calibrations of multiple scanners are stored.
+    #[test]
+    fn store_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(CALIBRATION_FILE);
+        assert!(CalibrationStore::load(&path).unwrap().scanners.is_empty());
+
+        let calibration = Calibration {
+            black: 5.0,
+            white: 95.0,
+            gamma: 1.2,
+        };
+        let mut store = CalibrationStore::default();
+        store.scanners.insert("flatbed".into(), calibration);
+        store.scanners.insert("adf".into(), calibration);
+        store.save(&path).unwrap();
+
+        let store = CalibrationStore::load(&path).unwrap();
+        assert_eq!(store.scanners.len(), 2);
+        assert_eq!(store.scanners["adf"], calibration);
+    }
+
+    /// Ensure that the outer edges of the frame lines are found, also behind
+    /// dark margins.
+    #[test]
+    fn find_frame_lines() {
+        let mut profile = vec![250.0; 100];
+        for i in [10, 11, 88, 89] {
+            profile[i] = 40.0;
+        }
+        assert_eq!(frame_lines(&profile), Some((10, 89)));
+
+        // Black ADF background at the edges
+        profile[0] = 5.0;
+        profile[1] = 5.0;
+        profile[99] = 5.0;
+        assert_eq!(frame_lines(&profile), Some((10, 89)));
+
+        // No frame
+        assert_eq!(frame_lines(&[250.0; 100]), None);
+    }
+
+    /// Ensure that the scan area is shifted towards the paper and enlarged if
+    /// the paper extends beyond it, while a clipped start is reported.
+    #[test]
+    fn correct_axis_offsets() {
+        // Perfectly aligned
+        assert_eq!(correct_axis(0.0, 210.0, (10.0, 200.0)), (0.0, 0.0, 0.0));
+        // Paper starts 2 mm into the scan area
+        assert_eq!(correct_axis(0.0, 210.0, (12.0, 202.0)), (2.0, 0.0, 0.0));
+        // Paper is stretched by 3 mm (e.g. by the ADF feed)
+        assert_eq!(correct_axis(0.0, 297.0, (10.0, 290.0)), (0.0, 3.0, 0.0));
+        // The first 3 mm of the paper are not scanned
+        assert_eq!(correct_axis(0.0, 210.0, (7.0, 197.0)), (0.0, 0.0, 3.0));
+        // Corrections are relative to the current offset
+        assert_eq!(correct_axis(2.0, 210.0, (10.5, 200.5)), (2.5, 0.0, 0.0));
+    }
+}
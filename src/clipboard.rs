@@ -0,0 +1,109 @@
+//! Copying the paths of archived documents to the clipboard
+//!
+//! The clipboard is written with the tool of the desktop environment, e.g. to
+//! attach an archived document to an email right away.
+
+use std::{
+    env,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use tracing::{debug, warn};
+
+use crate::{config::ClipboardContent, fs_utils, prompt, tools};
+
+/// Clipboard tools with their arguments, in the order they are tried
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        return vec![("pbcopy", &[])];
+    }
+    let mut commands: Vec<(&str, &[&str])> = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(("wl-copy", &[]));
+    }
+    commands.push(("xclip", &["-selection", "clipboard"]));
+    commands.push(("xsel", &["--clipboard", "--input"]));
+    commands
+}
+
+/// Copy a text to the clipboard
+pub fn copy(text: &str) -> Result<()> {
+    let commands = clipboard_commands();
+    let Some((program, args)) = commands
+        .iter()
+        .find(|(program, _)| tools::is_installed(program))
+    else {
+        let programs: Vec<&str> = commands.iter().map(|(program, _)| *program).collect();
+        bail!(
+            "No clipboard tool is installed (install {})",
+            programs.join(" or ")
+        );
+    };
+    debug!("Copying {text:?} to the clipboard with {program}");
+    let mut child = Command::new(program)
+        .args(*args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run `{program}`"))?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open stdin")?
+        .write_all(text.as_bytes())
+        .with_context(|| format!("Failed to write to `{program}`"))?;
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for `{program}`"))?;
+    ensure!(status.success(), "`{program}` failed with {status}");
+    Ok(())
+}
+
+/// The text copied to the clipboard for a document
+fn clipboard_text(path: &Path, content: ClipboardContent) -> String {
+    match content {
+        ClipboardContent::Path => path.display().to_string(),
+        ClipboardContent::Url => fs_utils::file_url(path),
+    }
+}
+
+/// Copy the path (or URL) of a document to the clipboard, warning if that
+/// fails
+pub fn copy_path(path: &Path, content: ClipboardContent) {
+    match copy(&clipboard_text(path, content)) {
+        Ok(()) => println!("Copied {} to the clipboard", path.display()),
+        Err(e) => warn!("{e:#}"),
+    }
+}
+
+/// Offer to copy the path (or URL) of an archived document to the clipboard
+pub fn offer(path: &Path, content: ClipboardContent) -> Result<()> {
+    let copy = prompt::Confirm::new("Copy the path of the document to the clipboard?")
+        .with_default(true)
+        .prompt()?;
+    if copy {
+        copy_path(path, content);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that documents are copied as path or as file URL.
+    #[test]
+    fn text() {
+        let path = Path::new("/archive/2025/Invoice #1.pdf");
+        assert_eq!(
+            clipboard_text(path, ClipboardContent::Path),
+            "/archive/2025/Invoice #1.pdf"
+        );
+        assert_eq!(
+            clipboard_text(path, ClipboardContent::Url),
+            "file:///archive/2025/Invoice%20%231.pdf"
+        );
+    }
+}
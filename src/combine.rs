@@ -0,0 +1,133 @@
+//! Combination of several archived documents into a single PDF (e.g. a
+//! complete dossier for an insurance), with a bookmark per document
+
+use std::{path::Path, process::Command};
+
+use anyhow::{Result, ensure};
+
+use crate::{extract, pdf, prompt, search::Document};
+
+/// Title of the bookmark of a document, e.g. `2025-05-30 Invoice (Muster AG)`
+fn bookmark_title(document: &Document) -> String {
+    let title = document.title.clone().unwrap_or_else(|| {
+        document
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+    let mut bookmark = match document.date {
+        Some(date) => format!("{date} {title}"),
+        None => title,
+    };
+    if let Some(correspondent) = &document.correspondent {
+        bookmark.push_str(&format!(" ({correspondent})"));
+    }
+    bookmark
+}
+
+/// Ask which of the `documents` (e.g. found by a search) to combine, all
+/// preselected, return them in chronological order
+///
+/// Documents without a known date are sorted by their path, after the dated
+/// ones.
+pub fn select_documents(mut documents: Vec<Document>) -> Result<Vec<Document>> {
+    documents.sort_by(|a, b| {
+        (a.date.is_none(), a.date, &a.path).cmp(&(b.date.is_none(), b.date, &b.path))
+    });
+    let labels: Vec<String> = documents.iter().map(bookmark_title).collect();
+    let defaults: Vec<usize> = (0..documents.len()).collect();
+    let selected = prompt::MultiSelect::new("Which documents do you want to combine?", labels)
+        .with_default(&defaults)
+        .with_help_message("Press space to select, enter to combine")
+        .with_validator(|selected| {
+            if selected == 0 {
+                Err("Please select at least one document".into())
+            } else {
+                Ok(())
+            }
+        })
+        .prompt_indices()?;
+    Ok(documents
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| selected.contains(index))
+        .map(|(_, document)| document)
+        .collect())
+}
+
+/// Combine the PDFs of the `documents` into `output`, in the given order, with
+/// a bookmark at the first page of every document
+///
+/// Requires `qpdf`.
+pub fn combine(documents: &[Document], output: &Path) -> Result<()> {
+    ensure!(!documents.is_empty(), "No documents to combine");
+    let mut command = Command::new("qpdf");
+    command.arg("--empty").arg("--pages");
+    let mut bookmarks = Vec::new();
+    let mut page = 0;
+    for document in documents {
+        bookmarks.push((bookmark_title(document), page));
+        page += extract::page_count(&document.path)? as usize;
+        command.arg(&document.path);
+    }
+    extract::run(command.arg("--").arg(output))?;
+    let bookmarks: Vec<(&str, usize)> = bookmarks
+        .iter()
+        .map(|(title, page)| (title.as_str(), *page))
+        .collect();
+    pdf::set_outline(output, &bookmarks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+
+    use crate::prompt::ScriptedPrompter;
+
+    /// Ensure that bookmarks are titled with the date, title and
+    /// correspondent, as far as they are known.
+    #[test]
+    fn bookmark_titles() {
+        let mut document = Document {
+            path: PathBuf::from("/archive/2025/invoice.pdf"),
+            ..Default::default()
+        };
+        assert_eq!(bookmark_title(&document), "invoice");
+        document.date = NaiveDate::from_ymd_opt(2025, 5, 30);
+        document.title = Some("Invoice".into());
+        document.correspondent = Some("Muster AG".into());
+        assert_eq!(bookmark_title(&document), "2025-05-30 Invoice (Muster AG)");
+    }
+
+    /// Ensure that the selected documents are combined in chronological
+    /// order.
+    #[test]
+    fn select_chronologically() {
+        let document = |name: &str, date: Option<(i32, u32, u32)>| Document {
+            path: PathBuf::from(format!("/archive/{name}.pdf")),
+            date: date.and_then(|(year, month, day)| NaiveDate::from_ymd_opt(year, month, day)),
+            ..Default::default()
+        };
+        let documents = vec![
+            document("filed", None),
+            document("claim", Some((2025, 3, 1))),
+            document("policy", Some((2020, 1, 1))),
+            document("letter", Some((2025, 4, 1))),
+        ];
+        let selected = prompt::with_prompter(ScriptedPrompter::new(["1,2,4"]), || {
+            select_documents(documents)
+        })
+        .unwrap();
+        let names: Vec<_> = selected
+            .iter()
+            .map(|document| document.path.file_stem().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["policy", "claim", "filed"]);
+    }
+}
@@ -0,0 +1,136 @@
+//! Transparent compression of the raw scanned pages
+//!
+//! Users who keep the raw TIFFs after processing can have them compressed
+//! with zstd. A page `0001.tif` is stored as `0001.tif.zst` and restored
+//! before the document is processed again.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{
+    fs_utils, process,
+    scheduler::{self, JobKind},
+};
+
+/// Filename suffix of compressed pages
+pub const COMPRESSED_SUFFIX: &str = ".zst";
+
+/// zstd compression level (scanned pages compress well already at low levels)
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Compress all raw page TIFFs in a document directory
+///
+/// Each page is replaced by its compressed version only once the compressed
+/// file was completely written.
+pub fn compress_pages(directory: &Path) -> Result<()> {
+    let pages = process::collect_page_tifs(directory)?;
+    let results = scheduler::global().map(JobKind::Cpu, &pages, |_, page| {
+        let path = directory.join(page);
+        let compressed = directory.join(format!("{page}{COMPRESSED_SUFFIX}"));
+        transcode(&path, &compressed, |input, output| {
+            zstd::stream::copy_encode(input, output, COMPRESSION_LEVEL)
+        })
+        .with_context(|| format!("Failed to compress {path:?}"))?;
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))
+    });
+    results.into_iter().collect::<Result<Vec<()>>>()?;
+    debug!("Compressed {} page(s) in {directory:?}", pages.len());
+    Ok(())
+}
+
+/// Decompress all compressed pages in a document directory, return the number
+/// of restored pages
+pub fn decompress_pages(directory: &Path) -> Result<usize> {
+    let pages = compressed_pages(directory)?;
+    let results = scheduler::global().map(JobKind::Cpu, &pages, |_, page| {
+        let path = directory.join(page);
+        let decompressed = directory.join(page.trim_end_matches(COMPRESSED_SUFFIX));
+        transcode(&path, &decompressed, |input, output| {
+            zstd::stream::copy_decode(input, output)
+        })
+        .with_context(|| format!("Failed to decompress {path:?}"))?;
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))
+    });
+    results.into_iter().collect::<Result<Vec<()>>>()?;
+    if !pages.is_empty() {
+        debug!("Decompressed {} page(s) in {directory:?}", pages.len());
+    }
+    Ok(pages.len())
+}
+
+/// Find the compressed pages in a directory
+fn compressed_pages(directory: &Path) -> Result<Vec<String>> {
+    let mut pages = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(directory))
+        .with_context(|| format!("Failed to read directory {directory:?}"))?
+    {
+        let Ok(filename) = entry?.file_name().into_string() else {
+            continue;
+        };
+        if filename
+            .strip_suffix(COMPRESSED_SUFFIX)
+            .and_then(process::page_number)
+            .is_some()
+        {
+            pages.push(filename);
+        }
+    }
+    Ok(pages)
+}
+
+/// Stream `from` through `codec` into `to`
+///
+/// The output is written to a temporary file and flushed to disk before it is
+/// renamed, so that an interruption never leaves a truncated page behind.
+fn transcode(
+    from: &Path,
+    to: &Path,
+    codec: impl FnOnce(File, &mut File) -> io::Result<()>,
+) -> Result<()> {
+    let tmp_path = to.with_extension("tmp");
+    let input = File::open(from)?;
+    let mut output = File::create(&tmp_path)?;
+    codec(input, &mut output)?;
+    output.sync_all()?;
+    fs::rename(&tmp_path, to)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that only raw pages are compressed, and that decompressing
+    /// restores them unchanged.
+    #[test]
+    fn roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let page = vec![0xAB; 64 * 1024];
+        fs::write(dir.join("0001.tif"), &page).unwrap();
+        fs::write(dir.join("0002.tif"), &page).unwrap();
+        fs::write(dir.join("0001_processed.tif"), "processed").unwrap();
+        fs::write(dir.join("_final.pdf"), "pdf").unwrap();
+
+        compress_pages(dir).unwrap();
+        assert!(!dir.join("0001.tif").exists());
+        assert!(dir.join("0001.tif.zst").exists());
+        assert!(fs::metadata(dir.join("0002.tif.zst")).unwrap().len() < page.len() as u64);
+        assert!(dir.join("0001_processed.tif").exists());
+        assert!(dir.join("_final.pdf").exists());
+
+        assert_eq!(decompress_pages(dir).unwrap(), 2);
+        assert_eq!(fs::read(dir.join("0001.tif")).unwrap(), page);
+        assert_eq!(fs::read(dir.join("0002.tif")).unwrap(), page);
+        assert!(!dir.join("0001.tif.zst").exists());
+        assert_eq!(decompress_pages(dir).unwrap(), 0);
+    }
+}
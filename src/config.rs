@@ -1,32 +1,128 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{Context, Result, anyhow, ensure};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, format::StrftimeItems};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, trace};
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::{
+    fs_utils,
+    locale::Locale,
+    naming,
+    scheduler::{self, JobKind},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// Default output directory for scanned files
-    #[allow(dead_code)] // TODO
+    /// Archive directory that processed documents are filed into (`~` and
+    /// environment variables like `$HOME` are expanded, relative paths are
+    /// relative to the directory of the config file)
     pub outdir: PathBuf,
+    /// Locale of the documents (e.g. "de_CH" or "en_US"), which determines
+    /// the default paper size, date order and OCR languages
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Paper size of scanned documents (overrides the default of the locale)
+    #[serde(default)]
+    pub default_paper: Option<PaperSize>,
     /// Scanner configuration
     pub scanners: Vec<Scanner>,
+    /// Scan configuration
+    #[serde(default)]
+    pub scan: ScanConfig,
+    /// Post-processing configuration
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    /// OCR configuration
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    /// Import of photos of documents
+    #[serde(default)]
+    pub import: ImportConfig,
+    /// Filing of documents into the archive
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    /// Sharing of stamped copies of documents
+    #[serde(default)]
+    pub share: ShareConfig,
+    /// Appearance of the terminal output
+    #[serde(default)]
+    pub ui: UiConfig,
+    /// Named profiles, selected with `--profile`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named set of options for a run (e.g. a "quick" profile for forms, or a
+/// "handwritten" profile for letters)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// Skip OCR and produce an image-only PDF
+    #[serde(default)]
+    pub skip_ocr: bool,
+    /// Keep the original contrast (e.g. for pencil strokes, which contrast
+    /// stretching destroys)
+    #[serde(default)]
+    pub skip_contrast: bool,
+    /// Don't straighten the pages, even if `deskew` is enabled
+    #[serde(default)]
+    pub skip_deskew: bool,
+    /// Also create a small copy for emailing, even if `email_copy` is not
+    /// enabled globally
+    #[serde(default)]
+    pub email_copy: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Scanner {
     /// Identifier
     pub id: String,
 
     /// Name of the scanner as indicated by SANE (e.g. "airscan:e1:HP ScanJet Flow N7000 snw1")
+    #[serde(default)]
     pub device_name: String,
 
+    /// Backend used to scan
+    #[serde(default)]
+    pub backend: ScannerBackend,
+
+    /// Generated pages of the virtual backend
+    #[serde(default, rename = "virtual", skip_serializing_if = "is_default")]
+    pub virtual_pages: VirtualPages,
+
+    /// Largest paper size that the scanner can scan (e.g. `a3`)
+    #[serde(default)]
+    pub max_paper: Option<PaperSize>,
+
+    /// Corrections of the scan area, for scanners that clip or shift an edge
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub geometry: Geometry,
+
     /// Additional arguments passed to scanimage
     #[serde(default)]
     pub additional_args: Vec<String>,
 
     /// Configure scan sources
     pub sources: ScannerSources,
+
+    /// Lockfile (e.g. on a network share) that prevents multiple machines from
+    /// using the scanner at the same time (`~` and environment variables are
+    /// expanded)
+    pub lock_file: Option<PathBuf>,
+}
+
+/// Whether a setting has its default value, so that it is not written to the
+/// config file
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
 impl Display for Scanner {
@@ -35,11 +131,100 @@ impl Display for Scanner {
     }
 }
 
+/// Backend of a scanner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScannerBackend {
+    /// Scan with SANE (`scanimage`)
+    #[default]
+    Sane,
+    /// Generate synthetic pages, for demos and tests
+    Virtual,
+}
+
+/// Corrections of the scan area of a scanner, in millimetres
+///
+/// The values can be determined with `arkivisto calibrate <scanner-id>
+/// --geometry`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Geometry {
+    /// Offset of the scan area from the left edge (`-l`)
+    #[serde(default)]
+    pub left: f32,
+
+    /// Offset of the scan area from the top edge (`-t`)
+    #[serde(default)]
+    pub top: f32,
+
+    /// Added to the width of the scan area, so that the right edge is not
+    /// clipped
+    #[serde(default)]
+    pub extra_width: f32,
+
+    /// Added to the height of the scan area, so that the bottom edge is not
+    /// clipped
+    #[serde(default)]
+    pub extra_height: f32,
+}
+
+/// Configure the synthetic pages of the virtual scanner backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirtualPages {
+    /// Number of sheets in the document feeder
+    #[serde(default = "default_virtual_sheets")]
+    pub sheets: usize,
+
+    /// Text printed on every page
+    #[serde(default = "default_virtual_text")]
+    pub text: String,
+
+    /// Amount of noise (0-1)
+    #[serde(default = "default_virtual_noise")]
+    pub noise: f32,
+
+    /// Rotation of the pages in degrees
+    #[serde(default)]
+    pub skew: f32,
+
+    /// Whether the back sides of duplex scans are blank
+    #[serde(default)]
+    pub blank_backs: bool,
+
+    /// Seed of the noise, so that scans are reproducible
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_virtual_sheets() -> usize {
+    2
+}
+
+fn default_virtual_text() -> String {
+    "The quick brown fox jumps over the lazy dog.".into()
+}
+
+fn default_virtual_noise() -> f32 {
+    0.1
+}
+
+impl Default for VirtualPages {
+    fn default() -> Self {
+        Self {
+            sheets: default_virtual_sheets(),
+            text: default_virtual_text(),
+            noise: default_virtual_noise(),
+            skew: 0.0,
+            blank_backs: false,
+            seed: 0,
+        }
+    }
+}
+
 /// Configure the possible sources of a scanner
 ///
 /// For example, one scanner might call the ADF scan source "ADF", while another
 /// might call it "Automatic Document Feeder(centrally aligned)".
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScannerSources {
     /// ADF single-sided source
     pub adf_single: Option<String>,
@@ -51,18 +236,802 @@ pub struct ScannerSources {
     pub flatbed: Option<String>,
 }
 
+/// Configure the scanning of documents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanConfig {
+    /// Offer to rescan pages whose quality score (0-100, based on sharpness
+    /// and contrast) is below this value
+    #[serde(default)]
+    pub min_page_quality: Option<f32>,
+
+    /// Format of the timestamp that document directories are named after
+    /// (`strftime` syntax). It must contain the date and time down to the
+    /// second, starting with the year, so that the directories sort
+    /// chronologically. The document ID is appended to the timestamp.
+    #[serde(default = "default_dir_format")]
+    pub dir_format: String,
+
+    /// Add the scan source and the scanner to the directory names, after the
+    /// timestamp (e.g. `20250601-143210_adf-duplex_hp-n7000-<ID>`), so that
+    /// the scans cache can be browsed by humans and other tools
+    #[serde(default)]
+    pub dir_details: bool,
+
+    /// Timezone of the timestamps
+    #[serde(default)]
+    pub timezone: Timezone,
+
+    /// Scan the area of `max_paper` (or the whole scan area if it is not
+    /// set), detect the paper size of every page from the scanned image and
+    /// crop the page to its paper. This requires a scanner that detects the
+    /// length of the pages, but allows feeding mixed paper sizes.
+    #[serde(default)]
+    pub detect_paper_size: bool,
+}
+
+fn default_dir_format() -> String {
+    "%Y%m%d-%H%M%S".into()
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            min_page_quality: None,
+            dir_format: default_dir_format(),
+            dir_details: false,
+            timezone: Timezone::default(),
+            detect_paper_size: false,
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Ensure that the directory format is valid, and that it produces
+    /// distinct names that sort chronologically
+    fn validate(&self) -> Result<()> {
+        let invalid = StrftimeItems::new(&self.dir_format)
+            .any(|item| matches!(item, chrono::format::Item::Error));
+        ensure!(
+            !invalid,
+            "Invalid scan directory format {:?}",
+            self.dir_format
+        );
+
+        // Timestamps in chronological order, with rollovers of every unit and
+        // changes in the number of digits
+        let samples = [
+            (2001, 2, 3, 4, 5, 6),
+            (2001, 2, 3, 4, 5, 7),
+            (2001, 2, 3, 4, 5, 59),
+            (2001, 2, 3, 4, 6, 0),
+            (2001, 2, 3, 4, 59, 0),
+            (2001, 2, 3, 5, 0, 0),
+            (2001, 2, 3, 23, 0, 0),
+            (2001, 2, 4, 0, 0, 0),
+            (2001, 2, 28, 0, 0, 0),
+            (2001, 3, 1, 0, 0, 0),
+            (2001, 12, 1, 0, 0, 0),
+            (2002, 1, 1, 0, 0, 0),
+            (2009, 9, 9, 9, 9, 9),
+            (2009, 10, 10, 10, 10, 10),
+        ];
+        let names: Vec<String> = samples
+            .iter()
+            .map(|&(year, month, day, hour, minute, second)| {
+                let time = NaiveDate::from_ymd_opt(year, month, day)
+                    .and_then(|date| date.and_hms_opt(hour, minute, second))
+                    .expect("Invalid sample timestamp")
+                    .and_utc()
+                    .fixed_offset();
+                dir_timestamp(&time, &self.dir_format)
+            })
+            .collect();
+        ensure!(
+            names
+                .iter()
+                .all(|name| !name.is_empty() && !name.contains('/')),
+            "Scan directory format {:?} must produce non-empty names without slashes",
+            self.dir_format
+        );
+        ensure!(
+            names.windows(2).all(|pair| pair[0] < pair[1]),
+            "Scan directory format {:?} must produce unique names (per second) that sort \
+             chronologically, e.g. \"%Y-%m-%d_%H%M%S\"",
+            self.dir_format
+        );
+        Ok(())
+    }
+}
+
+/// Path of the config file in the XDG app config directory, which is created
+/// if it doesn't exist
+pub fn config_path() -> Result<PathBuf> {
+    let config_dir = app_dirs::app_root(app_dirs::AppDataType::UserConfig, &crate::APP_INFO)
+        .context("Could not determine XDG app config directory")?;
+    trace!("Config directory: {:?}", config_dir);
+    Ok(config_dir.join("config.toml"))
+}
+
+/// Format a timestamp for a directory name
+pub fn dir_timestamp(time: &DateTime<FixedOffset>, format: &str) -> String {
+    time.format(format).to_string()
+}
+
+/// Convert a detail of a scan (e.g. a scanner ID) to a part of a directory
+/// name, consisting of lowercase letters, digits and dashes
+pub fn dir_slug(detail: &str) -> String {
+    let mut slug = String::with_capacity(detail.len());
+    for c in detail.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Timezone of timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timezone {
+    /// Local time of the machine
+    #[default]
+    Local,
+    /// Coordinated Universal Time
+    Utc,
+}
+
+impl Timezone {
+    /// The current time in this timezone
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => chrono::Local::now().fixed_offset(),
+            Timezone::Utc => chrono::Utc::now().fixed_offset(),
+        }
+    }
+
+    /// Interpret a time without timezone (e.g. from EXIF data) in this
+    /// timezone
+    ///
+    /// Returns `None` for local times that don't exist (e.g. during a
+    /// daylight saving time transition).
+    pub fn localize(&self, time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Timezone::Local => chrono::TimeZone::from_local_datetime(&chrono::Local, &time)
+                .earliest()
+                .map(|time| time.fixed_offset()),
+            Timezone::Utc => Some(time.and_utc().fixed_offset()),
+        }
+    }
+
+    /// Convert a system time (e.g. a file modification time) to this timezone
+    pub fn convert_system_time(&self, time: SystemTime) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => DateTime::<chrono::Local>::from(time).fixed_offset(),
+            Timezone::Utc => DateTime::<chrono::Utc>::from(time).fixed_offset(),
+        }
+    }
+}
+
+/// Configure the post-processing of scanned documents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingConfig {
+    /// Compression used when combining the scanned pages into a multi-page TIFF
+    #[serde(default)]
+    pub tiff_compression: TiffCompression,
+
+    /// Approximate amount of memory (in MiB) that processing may use. It is
+    /// divided among the concurrently running CPU jobs (see `[jobs]
+    /// max_cpu_jobs`). Large pages are streamed or cached on disk instead.
+    #[serde(default = "default_memory_budget_mb")]
+    pub memory_budget_mb: u64,
+
+    /// Defer processing while running on battery power (scanning always
+    /// proceeds immediately)
+    #[serde(default)]
+    pub defer_on_battery: bool,
+
+    /// Defer processing while the 1-minute load average exceeds this value
+    #[serde(default)]
+    pub max_load_average: Option<f32>,
+
+    /// Resource limits for external processing tools
+    #[serde(default)]
+    pub limits: ResourceLimits,
+
+    /// Digitally remove vertical streaks (e.g. caused by dust on the ADF
+    /// glass) that are detected on all pages of an ADF scan
+    #[serde(default)]
+    pub remove_streaks: bool,
+
+    /// After processing, open the final PDF and ask for confirmation that it
+    /// is complete and legible (for users who destroy the paper originals)
+    #[serde(default)]
+    pub confirm_final: bool,
+
+    /// After processing, compress the raw page TIFFs kept in the document
+    /// directory with zstd (they are decompressed again when needed)
+    #[serde(default)]
+    pub compress_raw_pages: bool,
+
+    /// JPEG quality (1-100) of the page images in the PDF (default: 92).
+    /// Lower values result in smaller files.
+    #[serde(default)]
+    pub jpeg_quality: Option<u8>,
+
+    /// Flag documents whose final PDF is larger than this size (in MiB) for
+    /// review
+    #[serde(default)]
+    pub max_pdf_size_mb: Option<f32>,
+
+    /// Straighten pages that were fed at a slight angle (can be disabled per
+    /// profile). Unlike the other steps, this holds a whole page in memory.
+    #[serde(default)]
+    pub deskew: bool,
+
+    /// In addition to the final PDF/A for the archive, create a small,
+    /// aggressively optimized PDF for emailing (can also be enabled per
+    /// profile). It is archived next to the final PDF with an `_email` suffix.
+    /// Requires Docker, unless OCR is skipped.
+    #[serde(default)]
+    pub email_copy: bool,
+}
+
+fn default_memory_budget_mb() -> u64 {
+    512
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            tiff_compression: TiffCompression::default(),
+            memory_budget_mb: default_memory_budget_mb(),
+            defer_on_battery: false,
+            max_load_average: None,
+            limits: ResourceLimits::default(),
+            remove_streaks: false,
+            confirm_final: false,
+            compress_raw_pages: false,
+            jpeg_quality: None,
+            max_pdf_size_mb: None,
+            deskew: false,
+            email_copy: false,
+        }
+    }
+}
+
+impl ProcessingConfig {
+    /// The memory budget of a single CPU job in MiB
+    pub fn job_memory_budget_mb(&self) -> u64 {
+        let jobs = scheduler::global().limit(JobKind::Cpu) as u64;
+        (self.memory_budget_mb / jobs).max(1)
+    }
+
+    /// The memory budget of a single CPU job in bytes
+    pub fn memory_budget(&self) -> usize {
+        usize::try_from(self.job_memory_budget_mb().saturating_mul(1024 * 1024))
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// Resource limits for external processing tools (ImageMagick and OCR), so
+/// that processing large documents doesn't make the system unusable.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// CPU niceness of local tools (-20 to 19, higher is nicer)
+    pub niceness: Option<i32>,
+
+    /// I/O scheduling class of local tools (Linux only)
+    pub io_priority: Option<IoPriority>,
+
+    /// Number of CPUs that a tool (or the OCR container) may use
+    pub cpus: Option<f32>,
+
+    /// Memory limit of the OCR container (in MiB)
+    pub memory_mb: Option<u64>,
+}
+
+/// I/O scheduling class, as set by `ionice`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoPriority {
+    /// Normal I/O scheduling
+    BestEffort,
+    /// Only get disk time when no other program needs it
+    Idle,
+}
+
+/// Compression algorithm for TIFF files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TiffCompression {
+    /// Lossless LZW compression
+    #[default]
+    Lzw,
+    /// Lossless Deflate (zlib) compression
+    Deflate,
+    /// No compression
+    None,
+}
+
+/// Paper size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaperSize {
+    /// ISO A4 (210 × 297 mm)
+    #[default]
+    A4,
+    /// US Letter (8.5 × 11 in)
+    Letter,
+    /// ISO A3 (297 × 420 mm)
+    A3,
+}
+
+/// Maximum difference (in mm) between the size of a scanned page and a paper
+/// size, for the page to be considered of that size
+const PAPER_SIZE_TOLERANCE_MM: f32 = 6.0;
+
+impl PaperSize {
+    /// Width and height in millimeters
+    pub fn dimensions_mm(&self) -> (f32, f32) {
+        match self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::A3 => (297.0, 420.0),
+        }
+    }
+
+    /// Width and height in pixels at 300 DPI
+    pub fn pixels_300_dpi(&self) -> (u32, u32) {
+        match self {
+            PaperSize::A4 => (2480, 3508),
+            PaperSize::Letter => (2550, 3300),
+            PaperSize::A3 => (3508, 4961),
+        }
+    }
+
+    /// Whether a page of this size fits into the scan area of `other`
+    pub fn fits_into(&self, other: PaperSize) -> bool {
+        let (width, height) = self.dimensions_mm();
+        let (max_width, max_height) = other.dimensions_mm();
+        width <= max_width && height <= max_height
+    }
+
+    /// Detect the paper size of a scanned page from its dimensions (in
+    /// portrait or landscape orientation), if it is a known size
+    pub fn detect(width_mm: f32, height_mm: f32) -> Option<Self> {
+        let (width, height) = (width_mm.min(height_mm), width_mm.max(height_mm));
+        [PaperSize::A4, PaperSize::Letter, PaperSize::A3]
+            .into_iter()
+            .find(|paper| {
+                let (paper_width, paper_height) = paper.dimensions_mm();
+                (width - paper_width).abs() <= PAPER_SIZE_TOLERANCE_MM
+                    && (height - paper_height).abs() <= PAPER_SIZE_TOLERANCE_MM
+            })
+    }
+
+    /// Detect the paper size of a scanned page that is only cropped to the
+    /// paper in its length (e.g. by the automatic length detection of an
+    /// ADF), and may be wider than the paper
+    ///
+    /// Returns the paper size and the width of the paper in the orientation
+    /// of the page (portrait is preferred).
+    pub fn detect_by_length(width_mm: f32, length_mm: f32) -> Option<(Self, f32)> {
+        let papers = [PaperSize::A4, PaperSize::Letter, PaperSize::A3];
+        let portrait = papers.map(|paper| (paper, paper.dimensions_mm()));
+        let landscape = portrait.map(|(paper, (width, height))| (paper, (height, width)));
+        portrait
+            .into_iter()
+            .chain(landscape)
+            .find(|(_, (paper_width, paper_length))| {
+                (length_mm - paper_length).abs() <= PAPER_SIZE_TOLERANCE_MM
+                    && *paper_width <= width_mm + PAPER_SIZE_TOLERANCE_MM
+            })
+            .map(|(paper, (paper_width, _))| (paper, paper_width))
+    }
+}
+
+/// Order of day, month and year in dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateOrder {
+    /// Day, month, year (e.g. 31.12.2024)
+    #[default]
+    Dmy,
+    /// Month, day, year (e.g. 12/31/2024)
+    Mdy,
+    /// Year, month, day (e.g. 2024/12/31)
+    Ymd,
+}
+
+/// Configure the limits of parallel work
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobsConfig {
+    /// Maximal number of concurrent CPU-bound jobs (e.g. processing of
+    /// pages). Defaults to the number of CPUs. Note that every job may use
+    /// the configured memory budget.
+    #[serde(default)]
+    pub max_cpu_jobs: Option<usize>,
+
+    /// Maximal number of concurrent I/O-bound jobs (e.g. scans)
+    #[serde(default = "default_max_io_jobs")]
+    pub max_io_jobs: usize,
+}
+
+fn default_max_io_jobs() -> usize {
+    4
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            max_cpu_jobs: None,
+            max_io_jobs: default_max_io_jobs(),
+        }
+    }
+}
+
+impl JobsConfig {
+    /// Maximal number of concurrent CPU-bound jobs
+    pub fn cpu_limit(&self) -> usize {
+        self.max_cpu_jobs
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|cpus| cpus.get())
+                    .unwrap_or(1)
+            })
+            .max(1)
+    }
+}
+
+/// Configure the import of photos of documents
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportConfig {
+    /// Folder that photos of documents (e.g. synced from a phone) are
+    /// imported from with `arkivisto import` (`~` and environment variables
+    /// are expanded)
+    #[serde(default)]
+    pub hot_folder: Option<PathBuf>,
+}
+
+/// Configure the filing of documents into the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    /// Name of archived documents. The placeholders `{date}`, `{year}`,
+    /// `{month}`, `{day}`, `{correspondent}`, `{title}` and `{tags}` are
+    /// replaced with the (slugified) metadata entered when archiving.
+    #[serde(default = "default_filename")]
+    pub filename: String,
+    /// Subdirectories of `outdir` that documents are filed into, e.g.
+    /// `{year}/{month}` or `{correspondent}`, with the same placeholders as
+    /// `filename`. Empty to file all documents directly into `outdir`.
+    #[serde(default)]
+    pub outdir_layout: String,
+    /// What to do if a document is archived under the name of an existing
+    /// document
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    /// Directory with secondary views of the archive, i.e. links to the
+    /// archived documents by tag, by correspondent and by year, updated with
+    /// `arkivisto rebuild-views` (`~` and environment variables are
+    /// expanded). It must not be inside `outdir`.
+    #[serde(default)]
+    pub views_dir: Option<PathBuf>,
+    /// Kind of links in the views
+    #[serde(default)]
+    pub view_links: LinkKind,
+    /// Number of days that deleted documents are kept in the trash of the
+    /// archive before `arkivisto trash purge` removes them
+    #[serde(default = "default_trash_days")]
+    pub trash_days: u32,
+    /// Offer to copy the path of a document to the clipboard after archiving
+    /// it, as path or as `file://` URL (e.g. to attach it to an email)
+    #[serde(default)]
+    pub clipboard: Option<ClipboardContent>,
+    /// Directory that a QR label (PNG) of every archived document is written
+    /// to, at the path of the document in the archive, linking a kept paper
+    /// original to the archived document (`~` and environment variables are
+    /// expanded). Requires `qrencode`.
+    #[serde(default)]
+    pub labels_dir: Option<PathBuf>,
+    /// Keep the scans of archived documents in the scans cache, so that they
+    /// can be processed again with other settings (`arkivisto reprocess`)
+    #[serde(default)]
+    pub keep_scans: bool,
+    /// Ask for an expiry or follow-up date when archiving a document (e.g.
+    /// end of a contract or due date of a payment), listed by `arkivisto due`
+    #[serde(default)]
+    pub ask_due_date: bool,
+    /// Ask where the paper original of a document is kept when archiving it
+    /// (e.g. the label of a binder or box), for originals that must be
+    /// retained. The location is searchable.
+    #[serde(default)]
+    pub ask_location: bool,
+    /// Directory that a Markdown note of every archived document is written
+    /// to (e.g. a folder of an Obsidian vault), at the path of the document in
+    /// the archive, with its metadata as front matter, a link to the PDF and
+    /// an excerpt of its text (`~` and environment variables are expanded)
+    #[serde(default)]
+    pub notes_dir: Option<PathBuf>,
+}
+
+/// What is copied to the clipboard for a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardContent {
+    /// The path of the PDF
+    #[default]
+    Path,
+    /// A `file://` URL of the PDF
+    Url,
+}
+
+/// Kind of links to archived documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// Symbolic links, which break if the archive is moved
+    #[default]
+    Symlink,
+    /// Hard links, which need to be on the same file system as the archive
+    Hardlink,
+}
+
+/// Handling of archived documents whose name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Append a numeric suffix to the name of the new document (e.g.
+    /// `invoice-02.pdf`)
+    #[default]
+    Suffix,
+    /// Replace the existing document (e.g. by a better rescan) and keep it as
+    /// an older version in the hidden `.versions` subdirectory (e.g.
+    /// `.versions/invoice_v1.pdf`)
+    Version,
+    /// Replace the existing document, which is deleted
+    Overwrite,
+    /// Ask which of the other policies to apply
+    Prompt,
+}
+
+fn default_filename() -> String {
+    "{date}_{correspondent}_{title}.pdf".into()
+}
+
+fn default_trash_days() -> u32 {
+    30
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            filename: default_filename(),
+            outdir_layout: String::new(),
+            on_conflict: ConflictPolicy::default(),
+            views_dir: None,
+            view_links: LinkKind::default(),
+            trash_days: default_trash_days(),
+            clipboard: None,
+            labels_dir: None,
+            keep_scans: false,
+            ask_due_date: false,
+            ask_location: false,
+            notes_dir: None,
+        }
+    }
+}
+
+/// Configure the copies of documents created with `arkivisto share`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareConfig {
+    /// Text stamped across every page. `{recipient}` is replaced with the
+    /// recipient and `{date}` with the current date.
+    #[serde(default = "default_watermark")]
+    pub watermark: String,
+}
+
+fn default_watermark() -> String {
+    "Copy for {recipient}, {date}".into()
+}
+
+impl Default for ShareConfig {
+    fn default() -> Self {
+        Self {
+            watermark: default_watermark(),
+        }
+    }
+}
+
+/// Configure text recognition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcrConfig {
+    /// OCR engine used to create the final, searchable PDF
+    #[serde(default)]
+    pub engine: OcrEngine,
+
+    /// How OCRmyPDF is run: "auto" (through Docker, or a local installation
+    /// if Docker is not installed or in sandboxes like Flatpak), "docker" or
+    /// "local"
+    #[serde(default)]
+    pub ocrmypdf_runner: OcrmypdfRunner,
+
+    /// Python virtual environment with a local OCRmyPDF installation (e.g.
+    /// "~/.local/share/ocrmypdf-venv"). Without it, `ocrmypdf` is searched in
+    /// `PATH`.
+    #[serde(default)]
+    pub ocrmypdf_venv: Option<PathBuf>,
+
+    /// Languages of the documents (Tesseract language codes, e.g. "deu" or
+    /// "eng"). If multiple languages are configured, the languages of every
+    /// document are detected before OCR. Defaults to the language of the
+    /// locale.
+    #[serde(default)]
+    pub languages: Vec<String>,
+
+    /// Export the transactions of bank and credit card statements as CSV
+    #[serde(default = "default_true")]
+    pub extract_transactions: bool,
+
+    /// Warn if the average word confidence (0-100) of a document is below this
+    /// value (read from the hOCR output of Tesseract, with both OCR engines)
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_min_confidence() -> f32 {
+    70.0
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            engine: OcrEngine::default(),
+            ocrmypdf_runner: OcrmypdfRunner::default(),
+            ocrmypdf_venv: None,
+            languages: Vec::new(),
+            extract_transactions: true,
+            min_confidence: default_min_confidence(),
+        }
+    }
+}
+
+/// OCR engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrEngine {
+    /// OCRmyPDF, run through Docker (creates PDF/A files)
+    #[default]
+    Ocrmypdf,
+    /// Tesseract, run directly on every processed page (its hOCR output is merged
+    /// into the PDF as text layer)
+    Tesseract,
+}
+
+/// How OCRmyPDF is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrmypdfRunner {
+    /// Docker if it is installed and usable, a local installation otherwise
+    #[default]
+    Auto,
+    /// Through Docker
+    Docker,
+    /// A local installation (in a Python virtual environment or in `PATH`)
+    Local,
+}
+
+/// Configure the appearance of the terminal output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct UiConfig {
+    /// Colors of messages, spinners and progress bars. Colors are always
+    /// disabled if the `NO_COLOR` environment variable is set.
+    #[serde(default)]
+    pub theme: Theme,
+
+    /// Symbols in front of success, warning and error messages
+    #[serde(default)]
+    pub symbols: Symbols,
+
+    /// Characters of spinners and progress bars
+    #[serde(default)]
+    pub progress_style: ProgressStyle,
+}
+
+/// Color theme of the terminal output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Bright colors for dark terminal backgrounds
+    #[default]
+    Dark,
+    /// Darker colors for light terminal backgrounds
+    Light,
+    /// No colors
+    None,
+}
+
+/// Symbols in front of messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Symbols {
+    /// Unicode symbols (e.g. ✔ and ⚠)
+    #[default]
+    Unicode,
+    /// Emoji (e.g. ✅ and ⚠️)
+    Emoji,
+    /// Plain ASCII (e.g. `[ok]` and `[!]`), for limited fonts and screen
+    /// readers
+    Ascii,
+}
+
+/// Style of spinners and progress bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStyle {
+    /// Braille spinner and smooth bar
+    #[default]
+    Smooth,
+    /// Block characters
+    Blocks,
+    /// Plain ASCII characters
+    Ascii,
+}
+
+/// Comments written before the top-level settings and sections of the config
+/// file
+const SECTION_COMMENTS: &[(&str, &str)] = &[
+    (
+        "outdir",
+        "Archive directory that processed documents are filed into",
+    ),
+    (
+        "locale",
+        "Locale of the documents (paper size, date order, OCR languages)",
+    ),
+    ("default_paper", "Paper size of scanned documents"),
+    ("scanners", "Scanners and the names of their sources"),
+    ("scan", "Scanning"),
+    ("processing", "Post-processing of scanned pages"),
+    ("ocr", "Text recognition"),
+    ("jobs", "Limits of parallel work"),
+    ("import", "Import of photos of documents"),
+    ("archive", "Filing of documents into the archive"),
+    ("share", "Stamped copies for sharing"),
+    ("ui", "Appearance of the terminal output"),
+    ("profiles", "Named profiles, selected with `--profile`"),
+];
+
 impl Config {
-    pub fn load() -> Result<Self> {
-        // Determine the XDG app config directory, creating it if it doesn't exist
-        let config_dir = app_dirs::app_root(app_dirs::AppDataType::UserConfig, &super::APP_INFO)
-            .context("Could not determine XDG app config directory")?;
-        trace!("Config directory: {:?}", config_dir);
+    /// A config with default settings
+    pub fn new(outdir: PathBuf, scanners: Vec<Scanner>) -> Self {
+        Self {
+            outdir,
+            locale: None,
+            default_paper: None,
+            scanners,
+            scan: ScanConfig::default(),
+            processing: ProcessingConfig::default(),
+            ocr: OcrConfig::default(),
+            jobs: JobsConfig::default(),
+            import: ImportConfig::default(),
+            archive: ArchiveConfig::default(),
+            share: ShareConfig::default(),
+            ui: UiConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
 
+    pub fn load() -> Result<Self> {
         // Check if file exists
-        let config_path = config_dir.join("config.toml");
+        let config_path = config_path()?;
         if !config_path.exists() {
             anyhow::bail!(
-                "Config file does not exist. Please create a config file at: {}",
+                "Config file does not exist. Please create a config file at {} (e.g. with `arkivisto init-config`)",
                 config_path.display()
             );
         }
@@ -71,8 +1040,516 @@ impl Config {
         debug!("Loading config from {:?}", config_path);
         let config_string = std::fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
-        let config: Self = toml::from_str(&config_string).context("Failed to parse config file")?;
+        let mut config: Self =
+            toml::from_str(&config_string).context("Failed to parse config file")?;
+        config.scan.validate()?;
+        naming::validate_filename(&config.archive.filename)?;
+        naming::validate_layout(&config.archive.outdir_layout)?;
+        let config_dir = config_path.parent().unwrap_or(Path::new("/"));
+        config.expand_paths(config_dir, |name| env::var(name).ok())?;
 
         Ok(config)
     }
+
+    /// Write the config to the config file, replacing it atomically
+    ///
+    /// The file is written in a canonical form, with all settings and a short
+    /// comment per section. Comments and formatting of the existing file are
+    /// not preserved, and paths are written as loaded (i.e. expanded).
+    pub fn save(&self) -> Result<PathBuf> {
+        let config_path = config_path()?;
+        self.save_to(&config_path)?;
+        Ok(config_path)
+    }
+
+    /// Write the config to `path`, see [`Config::save`]
+    fn save_to(&self, path: &Path) -> Result<()> {
+        self.scan.validate()?;
+        naming::validate_filename(&self.archive.filename)?;
+        naming::validate_layout(&self.archive.outdir_layout)?;
+        debug!("Saving config to {:?}", path);
+        fs_utils::write_synced(path, self.to_toml()?)
+            .with_context(|| format!("Failed to write config file: {}", path.display()))
+    }
+
+    /// Serialize the config to TOML, with a comment before every section
+    fn to_toml(&self) -> Result<String> {
+        let toml = toml::to_string(self).context("Failed to serialize config")?;
+        let mut document: toml_edit::DocumentMut =
+            toml.parse().context("Failed to parse serialized config")?;
+        for (key, comment) in SECTION_COMMENTS {
+            let prefix = format!("\n# {comment}\n");
+            match document.get_mut(key) {
+                Some(toml_edit::Item::Table(table)) => {
+                    // Tables with only subtables (e.g. `[profiles.quick]`)
+                    // need a header for the comment
+                    table.set_implicit(false);
+                    table.decor_mut().set_prefix(prefix);
+                }
+                Some(toml_edit::Item::ArrayOfTables(tables)) => {
+                    if let Some(table) = tables.get_mut(0) {
+                        table.decor_mut().set_prefix(prefix);
+                    }
+                }
+                Some(toml_edit::Item::Value(_)) => {
+                    if let Some(mut key) = document.key_mut(key) {
+                        key.leaf_decor_mut().set_prefix(prefix);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(format!(
+            "# arkivisto configuration\n{}",
+            document.to_string().trim_start()
+        ))
+    }
+
+    /// Expand `~` and environment variables in all configured paths, using
+    /// `lookup` to resolve variables, and resolve relative paths against
+    /// `config_dir` (the directory of the config file)
+    fn expand_paths(
+        &mut self,
+        config_dir: &Path,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<()> {
+        let expand = |path: &Path| expand_path(path, config_dir, &lookup);
+        self.outdir = expand(&self.outdir).context("Invalid `outdir`")?;
+        if let Some(hot_folder) = &self.import.hot_folder {
+            self.import.hot_folder = Some(expand(hot_folder).context("Invalid `hot_folder`")?);
+        }
+        if let Some(views_dir) = &self.archive.views_dir {
+            self.archive.views_dir = Some(expand(views_dir).context("Invalid `views_dir`")?);
+        }
+        if let Some(labels_dir) = &self.archive.labels_dir {
+            self.archive.labels_dir = Some(expand(labels_dir).context("Invalid `labels_dir`")?);
+        }
+        if let Some(notes_dir) = &self.archive.notes_dir {
+            self.archive.notes_dir = Some(expand(notes_dir).context("Invalid `notes_dir`")?);
+        }
+        if let Some(venv) = &self.ocr.ocrmypdf_venv {
+            self.ocr.ocrmypdf_venv = Some(expand(venv).context("Invalid `ocrmypdf_venv`")?);
+        }
+        for scanner in &mut self.scanners {
+            if let Some(lock_file) = &scanner.lock_file {
+                scanner.lock_file =
+                    Some(expand(lock_file).with_context(|| {
+                        format!("Invalid `lock_file` of scanner {}", scanner.id)
+                    })?);
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured locale
+    fn locale(&self) -> Option<Locale> {
+        self.locale.as_deref().and_then(Locale::parse)
+    }
+
+    /// Paper size of scanned documents
+    pub fn paper_size(&self) -> PaperSize {
+        self.default_paper
+            .or_else(|| self.locale().map(|locale| locale.paper_size()))
+            .unwrap_or_default()
+    }
+
+    /// Order of day, month and year in dates on documents
+    pub fn date_order(&self) -> DateOrder {
+        self.locale()
+            .map(|locale| locale.date_order())
+            .unwrap_or_default()
+    }
+
+    /// Candidate OCR languages (Tesseract language codes)
+    pub fn ocr_languages(&self) -> Vec<String> {
+        if !self.ocr.languages.is_empty() {
+            return self.ocr.languages.clone();
+        }
+        self.locale()
+            .and_then(|locale| locale.ocr_language())
+            .map(|language| vec![language.to_string()])
+            .unwrap_or_default()
+    }
+
+    /// Look up a profile by name
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            anyhow!(
+                "Profile \"{name}\" is not defined in the config (available: {})",
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )
+        })
+    }
+}
+
+/// Expand a leading `~` (the home directory) and environment variables
+/// (`$NAME` or `${NAME}`) in a configured path
+///
+/// `$$` and a `$` that doesn't start a variable reference (e.g. `$1`) stand
+/// for a literal `$`. Relative paths are resolved against `base_dir` rather
+/// than the working directory, which changes from run to run.
+fn expand_path(
+    path: &Path,
+    base_dir: &Path,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<PathBuf> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Path {path:?} is not valid UTF-8"))?;
+    let lookup = |name: &str| {
+        lookup(name).ok_or_else(|| anyhow!("Environment variable `{name}` in {path:?} is not set"))
+    };
+
+    // Home directory
+    let mut expanded = String::new();
+    let rest = if path == "~" || path.starts_with("~/") {
+        expanded.push_str(&lookup("HOME")?);
+        &path[1..]
+    } else {
+        ensure!(
+            !path.starts_with('~'),
+            "Home directories of other users are not supported in {path:?}"
+        );
+        path
+    };
+
+    // Environment variables
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'$').is_some()
+            || chars
+                .peek()
+                .is_none_or(|c| *c != '{' && *c != '_' && !c.is_ascii_alphabetic())
+        {
+            expanded.push('$');
+            continue;
+        }
+        let name: String = if chars.next_if_eq(&'{').is_some() {
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break name,
+                    Some(c) => name.push(c),
+                    None => return Err(anyhow!("Unterminated variable reference in {path:?}")),
+                }
+            }
+        } else {
+            std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_'))
+                .collect()
+        };
+        ensure!(!name.is_empty(), "Invalid variable reference in {path:?}");
+        expanded.push_str(&lookup(&name)?);
+    }
+
+    Ok(base_dir.join(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    /// Ensure that a saved config is loaded with the same settings, and that
+    /// the sections are commented.
+    #[test]
+    fn saved_config() {
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/archive"
+            locale = "en_US"
+
+            [[scanners]]
+            id = "office"
+            device_name = "airscan:e1:office"
+            lock_file = "/shared/office.lock"
+            [scanners.sources]
+            adf_duplex = "ADF Duplex"
+
+            [processing]
+            deskew = true
+            jpeg_quality = 80
+
+            [profiles.quick]
+            skip_ocr = true
+            "#,
+        )
+        .unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        config.save_to(&path).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# arkivisto configuration\n"));
+        assert!(content.contains("\n# Post-processing of scanned pages\n[processing]\n"));
+        let saved: Config = toml::from_str(&content).unwrap();
+        assert_eq!(saved.outdir, Path::new("/archive"));
+        assert_eq!(saved.locale.as_deref(), Some("en_US"));
+        assert_eq!(saved.scanners.len(), 1);
+        assert_eq!(saved.scanners[0].id, "office");
+        assert_eq!(
+            saved.scanners[0].sources.adf_duplex.as_deref(),
+            Some("ADF Duplex")
+        );
+        assert_eq!(saved.scanners[0].sources.flatbed, None);
+        assert_eq!(
+            saved.scanners[0].lock_file.as_deref(),
+            Some(Path::new("/shared/office.lock"))
+        );
+        assert!(saved.processing.deskew);
+        assert_eq!(saved.processing.jpeg_quality, Some(80));
+        assert_eq!(saved.scan.dir_format, config.scan.dir_format);
+        assert!(saved.profile("quick").unwrap().skip_ocr);
+    }
+
+    /// Ensure that profiles are parsed and unknown profiles are rejected.
+    #[test]
+    fn profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/tmp"
+            scanners = []
+
+            [profiles.quick]
+            skip_ocr = true
+
+            [profiles.handwritten]
+            skip_ocr = true
+            skip_contrast = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.profile("quick").unwrap().skip_ocr);
+        assert!(!config.profile("quick").unwrap().skip_contrast);
+        assert!(config.profile("handwritten").unwrap().skip_contrast);
+        assert!(!config.profile("handwritten").unwrap().skip_deskew);
+        let error = config.profile("slow").unwrap_err().to_string();
+        assert!(error.contains("available: handwritten, quick"), "{error}");
+    }
+
+    /// Ensure that the appearance of the output is configurable, and that the
+    /// defaults are used without a `[ui]` section.
+    #[test]
+    fn ui_config() {
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/tmp"
+            scanners = []
+
+            [ui]
+            theme = "light"
+            symbols = "ascii"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.ui.theme, Theme::Light);
+        assert_eq!(config.ui.symbols, Symbols::Ascii);
+        assert_eq!(config.ui.progress_style, ProgressStyle::Smooth);
+
+        let config: Config = toml::from_str("outdir = \"/tmp\"\nscanners = []").unwrap();
+        assert_eq!(config.ui, UiConfig::default());
+    }
+
+    /// Ensure that scan details are converted to readable slugs.
+    #[test]
+    fn dir_slugs() {
+        assert_eq!(dir_slug("HP N7000"), "hp-n7000");
+        assert_eq!(dir_slug("airscan:e1:HP ScanJet"), "airscan-e1-hp-scanjet");
+        assert_eq!(dir_slug("  --Flatbed--  "), "flatbed");
+        assert_eq!(dir_slug("Scanner ä"), "scanner");
+        assert_eq!(dir_slug(""), "");
+    }
+
+    /// Ensure that only directory formats that produce unique, chronologically
+    /// sorted names are accepted.
+    #[test]
+    fn dir_format() {
+        let valid = ["%Y%m%d-%H%M%S", "%Y-%m-%d_%H-%M-%S", "%Y-%m-%dT%H%M%S%z"];
+        for dir_format in valid {
+            let config = ScanConfig {
+                dir_format: dir_format.into(),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{dir_format}");
+        }
+        let invalid = [
+            "%Y%m%d",
+            "%d.%m.%Y %H:%M:%S",
+            "%Y/%m/%d-%H%M%S",
+            "%Y-%-m-%d-%H%M%S",
+            "%Q",
+        ];
+        for dir_format in invalid {
+            let config = ScanConfig {
+                dir_format: dir_format.into(),
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{dir_format}");
+        }
+    }
+
+    /// Ensure that virtual scanners don't need a device name, and that the
+    /// generated pages can be configured.
+    #[test]
+    fn virtual_scanner() {
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/tmp"
+
+            [[scanners]]
+            id = "virtual"
+            backend = "virtual"
+            sources = { adf_single = "ADF", adf_duplex = "ADF Duplex", flatbed = "Flatbed" }
+
+            [scanners.virtual]
+            sheets = 3
+            blank_backs = true
+            "#,
+        )
+        .unwrap();
+        let scanner = &config.scanners[0];
+        assert_eq!(scanner.backend, ScannerBackend::Virtual);
+        assert_eq!(scanner.virtual_pages.sheets, 3);
+        assert!(scanner.virtual_pages.blank_backs);
+        assert_eq!(scanner.virtual_pages.noise, 0.1);
+    }
+
+    /// Ensure that the locale determines the defaults, and that explicit
+    /// settings take precedence.
+    #[test]
+    fn locale_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/tmp"
+            scanners = []
+            locale = "en_US.UTF-8"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.paper_size(), PaperSize::Letter);
+        assert_eq!(config.date_order(), DateOrder::Mdy);
+        assert_eq!(config.ocr_languages(), vec!["eng"]);
+
+        let config: Config = toml::from_str(
+            r#"
+            outdir = "/tmp"
+            scanners = []
+            locale = "en_US"
+            default_paper = "a4"
+
+            [ocr]
+            languages = ["eng", "spa"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.paper_size(), PaperSize::A4);
+        assert_eq!(config.ocr_languages(), vec!["eng", "spa"]);
+
+        let config: Config = toml::from_str("outdir = \"/tmp\"\nscanners = []").unwrap();
+        assert_eq!(config.paper_size(), PaperSize::A4);
+        assert_eq!(config.date_order(), DateOrder::Dmy);
+        assert!(config.ocr_languages().is_empty());
+    }
+
+    /// Ensure that `~` and environment variables are expanded, and that
+    /// unknown variables and relative paths are rejected.
+    #[test]
+    fn expand_paths() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/user".to_string()),
+            "ARCHIVE" => Some("/mnt/archive".to_string()),
+            _ => None,
+        };
+        let config_dir = Path::new("/home/user/.config/arkivisto");
+        let expand = |path: &str| expand_path(Path::new(path), config_dir, lookup);
+
+        assert_eq!(expand("~").unwrap(), PathBuf::from("/home/user"));
+        assert_eq!(
+            expand("~/archive").unwrap(),
+            PathBuf::from("/home/user/archive")
+        );
+        assert_eq!(
+            expand("$ARCHIVE/scans").unwrap(),
+            PathBuf::from("/mnt/archive/scans")
+        );
+        assert_eq!(
+            expand("${ARCHIVE}_old/$HOME").unwrap(),
+            PathBuf::from("/mnt/archive_old//home/user")
+        );
+        assert_eq!(expand("/tmp/~x").unwrap(), PathBuf::from("/tmp/~x"));
+        assert!(expand("$MISSING/archive").is_err());
+        assert!(expand("~other/archive").is_err());
+        assert_eq!(
+            expand("archive").unwrap(),
+            PathBuf::from("/home/user/.config/arkivisto/archive")
+        );
+        assert_eq!(
+            expand("../archive").unwrap(),
+            PathBuf::from("/home/user/.config/arkivisto/../archive")
+        );
+        assert_eq!(expand("/tmp/$").unwrap(), PathBuf::from("/tmp/$"));
+        assert_eq!(
+            expand("/tmp/$$ARCHIVE/$1 $-").unwrap(),
+            PathBuf::from("/tmp/$ARCHIVE/$1 $-")
+        );
+        assert!(expand("/tmp/${ARCHIVE").is_err());
+        assert!(expand("/tmp/${}").is_err());
+
+        let mut config: Config = toml::from_str(
+            r#"
+            outdir = "~/archive"
+
+            [[scanners]]
+            id = "s1"
+            device_name = "s1"
+            lock_file = "$ARCHIVE/scanner.lock"
+            sources = {}
+            "#,
+        )
+        .unwrap();
+        config.expand_paths(config_dir, lookup).unwrap();
+        assert_eq!(config.outdir, PathBuf::from("/home/user/archive"));
+        assert_eq!(
+            config.scanners[0].lock_file,
+            Some(PathBuf::from("/mnt/archive/scanner.lock"))
+        );
+    }
+
+    /// Ensure that paper sizes are detected in both orientations, within a
+    /// tolerance for scanner inaccuracies.
+    #[test]
+    fn detect_paper_size() {
+        assert_eq!(PaperSize::detect(210.0, 297.0), Some(PaperSize::A4));
+        assert_eq!(PaperSize::detect(298.5, 208.0), Some(PaperSize::A4));
+        assert_eq!(PaperSize::detect(216.0, 280.0), Some(PaperSize::Letter));
+        assert_eq!(PaperSize::detect(297.0, 420.0), Some(PaperSize::A3));
+        assert_eq!(PaperSize::detect(148.0, 210.0), None);
+        assert_eq!(
+            PaperSize::detect_by_length(297.0, 297.0),
+            Some((PaperSize::A4, 210.0))
+        );
+        assert_eq!(
+            PaperSize::detect_by_length(297.0, 210.0),
+            Some((PaperSize::A4, 297.0))
+        );
+        assert_eq!(
+            PaperSize::detect_by_length(216.0, 280.0),
+            Some((PaperSize::Letter, 215.9))
+        );
+        assert_eq!(PaperSize::detect_by_length(200.0, 297.0), None);
+        assert_eq!(PaperSize::detect_by_length(297.0, 600.0), None);
+        assert!(PaperSize::A4.fits_into(PaperSize::A3));
+        assert!(!PaperSize::Letter.fits_into(PaperSize::A4));
+    }
 }
@@ -0,0 +1,256 @@
+//! Conversion of an old archive of loose TIFF/JPEG scans into searchable PDFs
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use tracing::{debug, warn};
+
+use crate::{
+    config::Config,
+    fs_utils, import,
+    manifest::Manifest,
+    process::{self, ProcessOptions},
+    queue::{self, ProcessingQueue},
+    scan,
+    timings::Timings,
+};
+
+/// How the scans of an old archive are grouped into documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Grouping {
+    /// All scans in a folder form one document
+    #[default]
+    Folder,
+    /// Scans whose names only differ in a trailing page number (e.g.
+    /// `invoice_1.tif` and `invoice_2.tif`) form one document
+    Name,
+}
+
+/// Whether a file is a scan that can be converted
+fn is_scan(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ["tif", "tiff", "jpg", "jpeg"]
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Split a file stem into the document name and the trailing page number
+/// (e.g. `invoice_p02` into `invoice_p` and 2)
+fn split_page_number(stem: &str) -> (&str, Option<u64>) {
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let page = stem[name.len()..].parse().ok();
+    (name.trim_end_matches(['_', '-', '.', ' ']), page)
+}
+
+/// Find all scans below `root`, recursively
+fn collect_scans(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut scans = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(root))
+        .with_context(|| format!("Failed to read directory {root:?}"))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            scans.extend(collect_scans(&path)?);
+        } else if is_scan(&path) {
+            scans.push(path);
+        }
+    }
+    Ok(scans)
+}
+
+/// Group scans into documents, named by their path relative to `root`
+///
+/// The scans of a document are sorted by page number, then by name.
+fn group_scans(
+    root: &Path,
+    scans: Vec<PathBuf>,
+    grouping: Grouping,
+) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let mut documents: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for scan in scans {
+        let folder = scan
+            .parent()
+            .and_then(|parent| parent.strip_prefix(root).ok())
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        let key = match grouping {
+            Grouping::Folder => folder,
+            Grouping::Name => {
+                let stem = scan
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or("");
+                folder.join(split_page_number(stem).0)
+            }
+        };
+        documents.entry(key).or_default().push(scan);
+    }
+    for scans in documents.values_mut() {
+        scans.sort_by_cached_key(|scan| {
+            let stem = scan
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("");
+            (split_page_number(stem).1, scan.clone())
+        });
+    }
+    documents
+}
+
+/// Convert the scans below `root` into documents and process them, return the
+/// document directories
+///
+/// The original files are left untouched. The scan time of every document and
+/// the modification time of its final PDF are taken from the original files.
+/// Documents that fail to process stay queued for the next run.
+pub fn convert_archive(
+    root: &Path,
+    grouping: Grouping,
+    config: &Config,
+    options: &ProcessOptions,
+    timings: &mut Timings,
+) -> Result<Vec<PathBuf>> {
+    let documents = group_scans(root, collect_scans(root)?, grouping);
+    println!(
+        "Found {} document(s) in {}",
+        documents.len(),
+        root.display()
+    );
+
+    let scans_dir = scan::scans_dir()?;
+    let mut queue = ProcessingQueue::load(&scans_dir)?;
+    let mut document_dirs = Vec::new();
+    for (name, scans) in documents {
+        let modified = scans
+            .iter()
+            .map(|scan| fs::metadata(scan).and_then(|metadata| metadata.modified()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to read modification times of {name:?}"))?;
+        let (Some(first), Some(last)) = (modified.iter().min(), modified.iter().max()) else {
+            continue;
+        };
+
+        // Convert the scans to pages
+        let (document_dir, manifest) = scan::create_document_dir_at(
+            &scans_dir,
+            config.scan.timezone.convert_system_time(*first),
+            &config.scan.dir_format,
+            if config.scan.dir_details {
+                &["archive"]
+            } else {
+                &[]
+            },
+        )?;
+        let result = scans.iter().try_for_each(|scan| {
+            let next_page = process::collect_page_tifs(&document_dir)?.len() + 1;
+            import::convert_to_pages(scan, &document_dir, next_page, &config.processing)
+        });
+        if let Err(e) = result {
+            warn!("Skipping {name:?}: {e:#}");
+            let _ = fs::remove_dir_all(&document_dir);
+            continue;
+        }
+        let title = name
+            .to_str()
+            .filter(|name| !name.is_empty())
+            .map(String::from);
+        let manifest = Manifest { title, ..manifest };
+        manifest.save(&document_dir)?;
+
+        // Process the document
+        let directory = document_dir;
+        queue.start(directory.clone());
+        if let Err(e) = process::process_document(&directory, config, options, timings) {
+            warn!("Failed to post-process {:?}: {:#}", directory, e);
+            queue.fail(&directory, &e);
+            continue;
+        }
+        queue.finish(&directory);
+
+        // Preserve the original timestamp
+        let final_pdf = directory.join(queue::FINAL_PDF);
+        if let Err(e) = File::options()
+            .write(true)
+            .open(&final_pdf)
+            .and_then(|file| file.set_modified(*last))
+        {
+            debug!("Failed to set modification time of {final_pdf:?}: {e}");
+        }
+        println!(
+            "Converted {} ({} file(s)) to {}",
+            name.display(),
+            scans.len(),
+            final_pdf.display()
+        );
+        document_dirs.push(directory);
+    }
+    Ok(document_dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that trailing page numbers and their separators are split off.
+    #[test]
+    fn page_numbers() {
+        assert_eq!(split_page_number("invoice_2"), ("invoice", Some(2)));
+        assert_eq!(split_page_number("invoice-p03"), ("invoice-p", Some(3)));
+        assert_eq!(split_page_number("0001"), ("", Some(1)));
+        assert_eq!(split_page_number("contract"), ("contract", None));
+    }
+
+    /// Ensure that scans are grouped by folder or by name, and sorted by page
+    /// number.
+    #[test]
+    fn grouping() {
+        let root = Path::new("/archive");
+        let scans = vec![
+            PathBuf::from("/archive/2010/tax_10.tif"),
+            PathBuf::from("/archive/2010/tax_2.tif"),
+            PathBuf::from("/archive/2010/lease.jpg"),
+            PathBuf::from("/archive/cover.tif"),
+        ];
+
+        let documents = group_scans(root, scans.clone(), Grouping::Folder);
+        assert_eq!(
+            documents.keys().collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("2010")]
+        );
+        assert_eq!(
+            documents[Path::new("2010")],
+            vec![
+                PathBuf::from("/archive/2010/lease.jpg"),
+                PathBuf::from("/archive/2010/tax_2.tif"),
+                PathBuf::from("/archive/2010/tax_10.tif"),
+            ]
+        );
+
+        let documents = group_scans(root, scans, Grouping::Name);
+        assert_eq!(
+            documents[Path::new("2010/tax")],
+            vec![
+                PathBuf::from("/archive/2010/tax_2.tif"),
+                PathBuf::from("/archive/2010/tax_10.tif"),
+            ]
+        );
+        assert_eq!(documents.len(), 3);
+    }
+
+    /// Ensure that only TIFF and JPEG files are converted.
+    #[test]
+    fn scan_extensions() {
+        assert!(is_scan(Path::new("a.TIF")));
+        assert!(is_scan(Path::new("a.tiff")));
+        assert!(is_scan(Path::new("a.jpeg")));
+        assert!(!is_scan(Path::new("a.pdf")));
+    }
+}
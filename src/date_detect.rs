@@ -0,0 +1,368 @@
+//! Detection of the date of a document from its recognized text
+
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::{Datelike, NaiveDate};
+use tracing::debug;
+
+use crate::config::DateOrder;
+
+/// Month names and their abbreviations (English, German, French, Italian and
+/// Spanish), lowercase and without trailing dots
+const MONTHS: [&[&str]; 12] = [
+    &[
+        "january", "jan", "januar", "jänner", "janvier", "janv", "gennaio", "gen", "enero", "ene",
+    ],
+    &[
+        "february", "feb", "februar", "février", "fevrier", "févr", "fevr", "febbraio", "febrero",
+    ],
+    &["march", "mar", "märz", "maerz", "mrz", "mars", "marzo"],
+    &["april", "apr", "avril", "avr", "aprile", "abril", "abr"],
+    &["may", "mai", "maggio", "mag", "mayo"],
+    &["june", "jun", "juni", "juin", "giugno", "giu", "junio"],
+    &[
+        "july", "jul", "juli", "juillet", "juil", "luglio", "lug", "julio",
+    ],
+    &["august", "aug", "août", "aout", "agosto", "ago"],
+    &[
+        "september",
+        "sep",
+        "sept",
+        "septembre",
+        "settembre",
+        "set",
+        "septiembre",
+    ],
+    &[
+        "october", "oct", "oktober", "okt", "octobre", "ottobre", "ott", "octubre",
+    ],
+    &["november", "nov", "novembre", "noviembre"],
+    &[
+        "december",
+        "dec",
+        "dezember",
+        "dez",
+        "décembre",
+        "decembre",
+        "déc",
+        "dicembre",
+        "dic",
+        "diciembre",
+    ],
+];
+
+/// Filler words between the parts of written dates (e.g. "3 de marzo de
+/// 2024" or "3rd of March 2024")
+const FILLER_WORDS: &[&str] = &["de", "del", "of"];
+
+/// Beginnings of words that mark the date of a document (e.g. "Datum:" or
+/// "Date of issue")
+const DATE_WORDS: &[&str] = &["date", "datum", "fecha"];
+
+/// Beginnings of words that mark other dates (e.g. due dates or birth dates)
+const OTHER_DATE_WORDS: &[&str] = &[
+    "birth",
+    "born",
+    "geburt",
+    "geboren",
+    "due",
+    "payable",
+    "until",
+    "bis",
+    "expir",
+    "valid",
+    "gültig",
+    "fällig",
+    "zahlbar",
+    "échéance",
+    "echeance",
+    "scadenza",
+    "vencimiento",
+];
+
+/// Oldest year that is considered plausible for a document
+const MIN_YEAR: i32 = 1900;
+
+/// Candidate dates of a document in `text`, most likely first
+///
+/// Dates are found in numeric (e.g. `3.12.2024`, `2024-12-03` or `12/3/24`,
+/// read according to `date_order`) and written form (e.g. "3. März 2024" or
+/// "March 3, 2024"). Every occurrence is scored: Dates that are written out,
+/// labeled as date or near the top of the text score higher, dates labeled as
+/// due date or birth date score lower. Dates after `today` are ignored.
+pub fn candidates(text: &str, date_order: DateOrder, today: NaiveDate) -> Vec<NaiveDate> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut scores: HashMap<NaiveDate, (f32, usize)> = HashMap::new();
+    let mut occurrence = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let words: Vec<String> = line
+            .split_whitespace()
+            .map(|word| trim_punctuation(word).to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let mut line_score = 1.0 - i as f32 / lines.len() as f32;
+        if words.iter().any(|word| is_date_word(word)) {
+            line_score += 2.0;
+        }
+        if words
+            .iter()
+            .any(|word| OTHER_DATE_WORDS.iter().any(|w| word.starts_with(w)))
+        {
+            line_score -= 5.0;
+        }
+
+        let numeric = words
+            .iter()
+            .filter_map(|word| parse_numeric(word, date_order, today))
+            .map(|date| (date, 1.0));
+        let written = written_dates(&words).into_iter().map(|date| (date, 2.0));
+        for (date, score) in numeric.chain(written) {
+            if date.year() < MIN_YEAR || date > today {
+                continue;
+            }
+            let entry = scores.entry(date).or_insert((0.0, occurrence));
+            entry.0 += score + line_score;
+            occurrence += 1;
+        }
+    }
+
+    let mut ranked: Vec<(NaiveDate, (f32, usize))> = scores
+        .into_iter()
+        .filter(|(_, (score, _))| *score > 0.0)
+        .collect();
+    ranked
+        .sort_by(|(_, (a, first_a)), (_, (b, first_b))| b.total_cmp(a).then(first_a.cmp(first_b)));
+    ranked.into_iter().map(|(date, _)| date).collect()
+}
+
+/// Candidate dates of a processed document, read from its recognized text
+/// (see [`candidates`])
+///
+/// Returns no candidates if the document has no text (e.g. if OCR was
+/// skipped).
+pub fn detect(text_file: &Path, date_order: DateOrder, today: NaiveDate) -> Vec<NaiveDate> {
+    match fs::read_to_string(text_file) {
+        Ok(text) => {
+            let dates = candidates(&text, date_order, today);
+            debug!("Detected dates in {text_file:?}: {dates:?}");
+            dates
+        }
+        Err(e) => {
+            debug!("Failed to read recognized text {text_file:?}: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Remove punctuation around a word, except for dots (which are part of
+/// dates and abbreviations)
+fn trim_punctuation(word: &str) -> &str {
+    word.trim_matches(|c: char| matches!(c, ',' | ';' | ':' | '(' | ')' | '"' | '«' | '»'))
+}
+
+/// Whether a word labels a date (e.g. "Datum", "Rechnungsdatum" or "Date")
+fn is_date_word(word: &str) -> bool {
+    DATE_WORDS.iter().any(|w| word.starts_with(w)) || word.ends_with("datum")
+}
+
+/// Parse a numeric date, e.g. `3.12.2024`, `03/12/24` or `2024-12-03`
+///
+/// Dates with a four digit year in front are always read as year, month, day.
+/// Two digit years are in the past century if they would be after `today`.
+fn parse_numeric(word: &str, date_order: DateOrder, today: NaiveDate) -> Option<NaiveDate> {
+    let word = word.trim_end_matches('.');
+    let parts: Vec<&str> = word.split(['.', '-', '/']).collect();
+    if parts.len() != 3 || !parts.iter().all(|part| is_number(part)) {
+        return None;
+    }
+    let lengths: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+    let numbers: Vec<u32> = parts.iter().filter_map(|part| part.parse().ok()).collect();
+    match (lengths.as_slice(), numbers.as_slice()) {
+        ([4, 1..=2, 1..=2], &[y, m, d]) => NaiveDate::from_ymd_opt(y as i32, m, d),
+        ([1..=2, 1..=2, 2 | 4], &[a, b, y]) => {
+            let (d, m) = match date_order {
+                DateOrder::Mdy => (b, a),
+                DateOrder::Dmy | DateOrder::Ymd => (a, b),
+            };
+            NaiveDate::from_ymd_opt(full_year(y, today), m, d)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a string consists of ASCII digits only
+fn is_number(part: &str) -> bool {
+    !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Expand a two digit year to the most recent matching year until `today`
+fn full_year(year: u32, today: NaiveDate) -> i32 {
+    let year = year as i32;
+    if year >= 100 {
+        return year;
+    }
+    let century = today.year() / 100 * 100;
+    if century + year > today.year() {
+        century - 100 + year
+    } else {
+        century + year
+    }
+}
+
+/// Written dates in the (lowercase) words of a line, e.g. "3. märz 2024",
+/// "3 de marzo de 2024" or "march 3, 2024"
+fn written_dates(words: &[String]) -> Vec<NaiveDate> {
+    let words: Vec<&str> = words
+        .iter()
+        .map(String::as_str)
+        .filter(|word| !FILLER_WORDS.contains(word))
+        .collect();
+    let mut dates = Vec::new();
+    for window in words.windows(3) {
+        let year = match window[2].trim_end_matches('.') {
+            year if year.len() == 4 && is_number(year) => year.parse().ok(),
+            _ => None,
+        };
+        let Some(year) = year else {
+            continue;
+        };
+        let day_month = match (parse_day(window[0]), parse_month(window[1])) {
+            (Some(day), Some(month)) => Some((day, month)),
+            _ => parse_month(window[0])
+                .zip(parse_day(window[1]))
+                .map(|(m, d)| (d, m)),
+        };
+        if let Some((day, month)) = day_month
+            && let Some(date) = NaiveDate::from_ymd_opt(year, month, day)
+        {
+            dates.push(date);
+        }
+    }
+    dates
+}
+
+/// Parse the day of a written date, e.g. `3.`, `03`, `3rd` or `1er`
+fn parse_day(word: &str) -> Option<u32> {
+    let word = word.trim_end_matches('.');
+    let digits = ["st", "nd", "rd", "th", "er"]
+        .iter()
+        .find_map(|suffix| word.strip_suffix(suffix))
+        .unwrap_or(word);
+    if digits.len() > 2 || !is_number(digits) {
+        return None;
+    }
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+/// Parse the name or abbreviation of a month, return its number
+fn parse_month(word: &str) -> Option<u32> {
+    let word = word.trim_end_matches('.');
+    MONTHS
+        .iter()
+        .position(|names| names.contains(&word))
+        .map(|index| index as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Ensure that numeric dates are read according to the date order, and
+    /// that two digit years are not in the future.
+    #[test]
+    fn numeric_dates() {
+        let today = date(2025, 6, 1);
+        let parse = |word, order| parse_numeric(word, order, today);
+        assert_eq!(parse("3.12.2024", DateOrder::Dmy), Some(date(2024, 12, 3)));
+        assert_eq!(parse("03.12.24.", DateOrder::Dmy), Some(date(2024, 12, 3)));
+        assert_eq!(parse("12/3/2024", DateOrder::Mdy), Some(date(2024, 12, 3)));
+        assert_eq!(parse("2024-12-03", DateOrder::Mdy), Some(date(2024, 12, 3)));
+        assert_eq!(parse("1.2.99", DateOrder::Dmy), Some(date(1999, 2, 1)));
+        assert_eq!(parse("12/31/2024", DateOrder::Dmy), None);
+        assert_eq!(parse("044-123-4567", DateOrder::Dmy), None);
+        assert_eq!(parse("1.2.", DateOrder::Dmy), None);
+        assert_eq!(parse("3.50", DateOrder::Dmy), None);
+    }
+
+    /// Ensure that written dates are found in several languages and word
+    /// orders.
+    #[test]
+    fn written() {
+        let words = |line: &str| -> Vec<String> {
+            line.split_whitespace()
+                .map(|word| trim_punctuation(word).to_lowercase())
+                .collect()
+        };
+        assert_eq!(
+            written_dates(&words("Zürich, 3. März 2024")),
+            vec![date(2024, 3, 3)]
+        );
+        assert_eq!(
+            written_dates(&words("Madrid, 3 de marzo de 2024")),
+            vec![date(2024, 3, 3)]
+        );
+        assert_eq!(
+            written_dates(&words("Dated March 3rd, 2024")),
+            vec![date(2024, 3, 3)]
+        );
+        assert_eq!(
+            written_dates(&words("le 1er févr. 2024")),
+            vec![date(2024, 2, 1)]
+        );
+        assert!(written_dates(&words("March 2024")).is_empty());
+        assert!(written_dates(&words("31 February 2024")).is_empty());
+    }
+
+    /// Ensure that the labeled date of a letter ranks before due dates, birth
+    /// dates and dates in the future.
+    #[test]
+    fn ranking() {
+        let text = "\
+            Muster AG\n\
+            Geburtsdatum: 01.04.1980\n\
+            \n\
+            Rechnung\n\
+            Rechnungsdatum: 15.05.2025\n\
+            Leistung vom 02.05.2025\n\
+            Zahlbar bis 14.06.2025\n\
+            Nächster Termin: 20.07.2025\n";
+        let dates = candidates(text, DateOrder::Dmy, date(2025, 6, 1));
+        assert_eq!(dates, vec![date(2025, 5, 15), date(2025, 5, 2)]);
+
+        // Written dates in the letterhead beat numeric dates further down
+        let text = "Bern, 3. März 2024\n\nIhre Bestellung vom 28.02.2024\n";
+        let dates = candidates(text, DateOrder::Dmy, date(2025, 6, 1));
+        assert_eq!(dates, vec![date(2024, 3, 3), date(2024, 2, 28)]);
+
+        assert!(candidates("No dates here", DateOrder::Dmy, date(2025, 6, 1)).is_empty());
+    }
+
+    proptest! {
+        /// Ensure that detecting dates in arbitrary text never panics, and
+        /// that no date is in the future.
+        #[test]
+        fn candidates_arbitrary(text in "\\PC*") {
+            let today = date(2025, 6, 1);
+            for date_order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+                let dates = candidates(&text, date_order, today);
+                prop_assert!(dates.iter().all(|date| *date <= today));
+            }
+        }
+
+        /// Ensure that detecting dates in date-like text never panics.
+        #[test]
+        fn candidates_date_like(
+            text in "([0-9]{0,5}[./ -]?){0,6}( (März|March|Dez\\.?|[A-Za-zä]{0,9}))?( [0-9]{0,5})?",
+        ) {
+            for date_order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+                candidates(&text, date_order, date(2025, 6, 1));
+            }
+        }
+    }
+}
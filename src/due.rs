@@ -0,0 +1,141 @@
+//! Expiry and follow-up dates of archived documents
+//!
+//! A date can be attached to a document when archiving it (with
+//! `[archive] ask_due_date`), e.g. the end of a contract, the expiry of a
+//! warranty or the due date of a payment. It is kept in the search index, and
+//! `arkivisto due` lists the documents whose dates are approaching or past.
+
+use anyhow::Result;
+use chrono::{Days, Months, NaiveDate};
+
+use crate::{prompt, search::Document};
+
+/// Parse a due date, either as `YYYY-MM-DD` or relative to the date of the
+/// document as number of days, weeks, months or years (e.g. `+30d` or `+2y`)
+///
+/// Returns `None` for an empty input.
+pub fn parse(input: &str, date: NaiveDate) -> Result<Option<NaiveDate>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let Some(relative) = input.strip_prefix('+') else {
+        return input
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Expected a date as YYYY-MM-DD or e.g. +30d, not {input:?}"));
+    };
+    let unit_start = relative.char_indices().last().map_or(0, |(index, _)| index);
+    let (number, unit) = relative.split_at(unit_start);
+    let number: u32 = number
+        .parse()
+        .map_err(|_| format!("Expected a number of days, weeks, months or years, not {input:?}"))?;
+    let due = match unit {
+        "d" => date.checked_add_days(Days::new(number.into())),
+        "w" => date.checked_add_days(Days::new(u64::from(number) * 7)),
+        "m" => date.checked_add_months(Months::new(number)),
+        "y" => number
+            .checked_mul(12)
+            .and_then(|months| date.checked_add_months(Months::new(months))),
+        _ => return Err(format!("Unknown unit {unit:?}, expected d, w, m or y")),
+    };
+    due.map(Some)
+        .ok_or_else(|| format!("{input:?} is out of range"))
+}
+
+/// Ask for the expiry or follow-up date of a document dated `date`,
+/// `Some(None)` if it has none
+///
+/// Returns `None` if the document should be skipped.
+pub fn ask(date: NaiveDate) -> Result<Option<Option<NaiveDate>>> {
+    let Some(input) = prompt::Text::new("Due or expiry date?")
+        .with_help_message(
+            "E.g. end of a contract, expiry of a warranty or due date of a payment, as \
+             YYYY-MM-DD or relative to the date of the document (e.g. +30d, +2y), leave empty \
+             for none, press Esc to skip this document",
+        )
+        .with_validator(move |input| parse(input, date).map(|_| ()))
+        .prompt_skippable()?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(parse(&input, date).unwrap_or_default()))
+}
+
+/// Documents that are due within `days` after `today` (or are overdue),
+/// ordered by due date
+pub fn upcoming(documents: Vec<Document>, today: NaiveDate, days: u64) -> Vec<Document> {
+    let until = today.checked_add_days(Days::new(days)).unwrap_or(today);
+    let mut upcoming: Vec<Document> = documents
+        .into_iter()
+        .filter(|document| document.due.is_some_and(|due| due <= until))
+        .collect();
+    upcoming.sort_by(|a, b| (a.due, &a.path).cmp(&(b.due, &b.path)));
+    upcoming
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::prompt::ScriptedPrompter;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Ensure that due dates are parsed as dates or relative to the date of
+    /// the document.
+    #[test]
+    fn parse_due() {
+        let document = date(2025, 1, 31);
+        assert_eq!(parse(" ", document), Ok(None));
+        assert_eq!(parse("2026-03-01", document), Ok(Some(date(2026, 3, 1))));
+        assert_eq!(parse("+30d", document), Ok(Some(date(2025, 3, 2))));
+        assert_eq!(parse("+2w", document), Ok(Some(date(2025, 2, 14))));
+        assert_eq!(parse("+1m", document), Ok(Some(date(2025, 2, 28))));
+        assert_eq!(parse("+2y", document), Ok(Some(date(2027, 1, 31))));
+        for invalid in ["tomorrow", "+d", "+3x", "+", "2025-02-30"] {
+            assert!(parse(invalid, document).is_err(), "{invalid}");
+        }
+    }
+
+    /// Ensure that the due date may be left empty, and that Esc skips the
+    /// document.
+    #[test]
+    fn ask_due() {
+        let answers = ["+1m", "", "<esc>"];
+        prompt::with_prompter(ScriptedPrompter::new(answers), || {
+            assert_eq!(
+                ask(date(2025, 1, 31)).unwrap(),
+                Some(Some(date(2025, 2, 28)))
+            );
+            assert_eq!(ask(date(2025, 1, 31)).unwrap(), Some(None));
+            assert_eq!(ask(date(2025, 1, 31)).unwrap(), None);
+        });
+    }
+
+    /// Ensure that overdue documents and documents that are due soon are
+    /// listed by due date.
+    #[test]
+    fn upcoming_documents() {
+        let document = |name: &str, due| Document {
+            path: name.into(),
+            due,
+            ..Default::default()
+        };
+        let documents = vec![
+            document("later.pdf", Some(date(2025, 8, 1))),
+            document("soon.pdf", Some(date(2025, 6, 20))),
+            document("none.pdf", None),
+            document("overdue.pdf", Some(date(2025, 5, 1))),
+        ];
+        assert_eq!(
+            upcoming(documents, date(2025, 6, 1), 30),
+            vec![
+                document("overdue.pdf", Some(date(2025, 5, 1))),
+                document("soon.pdf", Some(date(2025, 6, 20))),
+            ]
+        );
+    }
+}
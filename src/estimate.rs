@@ -0,0 +1,125 @@
+//! Estimation of scan and processing durations from earlier scans
+
+use crate::manifest::ScanStats;
+
+/// Durations per page, derived from earlier scans
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// Seconds per page for scanning
+    pub scan_secs: f32,
+    /// Seconds per page for processing, if documents were processed
+    pub process_secs: Option<f32>,
+    /// Number of earlier scans that the estimate is based on
+    pub samples: usize,
+}
+
+impl Estimate {
+    /// Estimated total duration for the given number of pages, in seconds
+    pub fn total_secs(&self, pages: usize) -> f32 {
+        (self.scan_secs + self.process_secs.unwrap_or_default()) * pages as f32
+    }
+}
+
+/// Estimate the durations per page for a scan mode and resolution
+///
+/// Scans with the same mode and resolution are preferred. If there are none,
+/// all scans with the same resolution are used.
+pub fn estimate(history: &[ScanStats], mode: &str, dpi: u32) -> Option<Estimate> {
+    let same_dpi: Vec<&ScanStats> = history
+        .iter()
+        .filter(|stats| stats.dpi == dpi && stats.pages > 0)
+        .collect();
+    let same_mode: Vec<&ScanStats> = same_dpi
+        .iter()
+        .copied()
+        .filter(|stats| stats.mode == mode)
+        .collect();
+    let samples = if same_mode.is_empty() {
+        same_dpi
+    } else {
+        same_mode
+    };
+    if samples.is_empty() {
+        return None;
+    }
+
+    let pages: usize = samples.iter().map(|stats| stats.pages).sum();
+    let scan_secs: f32 = samples.iter().map(|stats| stats.scan_secs).sum();
+    let processed: Vec<&&ScanStats> = samples
+        .iter()
+        .filter(|stats| stats.process_secs.is_some())
+        .collect();
+    let process_secs = (!processed.is_empty()).then(|| {
+        let pages: usize = processed.iter().map(|stats| stats.pages).sum();
+        let secs: f32 = processed
+            .iter()
+            .filter_map(|stats| stats.process_secs)
+            .sum();
+        secs / pages as f32
+    });
+    Some(Estimate {
+        scan_secs: scan_secs / pages as f32,
+        process_secs,
+        samples: samples.len(),
+    })
+}
+
+/// Format a duration in a rounded, human-readable form (e.g. "≈3 min")
+pub fn format_duration(secs: f32) -> String {
+    if secs < 90.0 {
+        format!("≈{:.0} s", secs.max(1.0))
+    } else if secs < 90.0 * 60.0 {
+        format!("≈{:.0} min", secs / 60.0)
+    } else {
+        format!("≈{:.1} h", secs / 3600.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(mode: &str, dpi: u32, pages: usize, scan: f32, process: Option<f32>) -> ScanStats {
+        ScanStats {
+            mode: mode.into(),
+            dpi,
+            pages,
+            scan_secs: scan,
+            process_secs: process,
+        }
+    }
+
+    /// Ensure that estimates are averaged per page, preferring scans with
+    /// the same mode.
+    #[test]
+    fn per_page_estimates() {
+        let history = vec![
+            stats("adf-duplex", 300, 10, 20.0, Some(30.0)),
+            stats("adf-duplex", 300, 30, 40.0, None),
+            stats("flatbed", 300, 2, 60.0, Some(6.0)),
+            stats("adf-duplex", 600, 4, 16.0, Some(40.0)),
+        ];
+        let estimate = estimate(&history, "adf-duplex", 300).unwrap();
+        assert_eq!(estimate.scan_secs, 1.5);
+        assert_eq!(estimate.process_secs, Some(3.0));
+        assert_eq!(estimate.samples, 2);
+        assert_eq!(estimate.total_secs(20), 90.0);
+
+        // Fall back to other modes with the same resolution
+        let estimate = super::estimate(&history, "adf", 600).unwrap();
+        assert_eq!(estimate.scan_secs, 4.0);
+        assert_eq!(estimate.process_secs, Some(10.0));
+
+        assert!(super::estimate(&history, "adf", 1200).is_none());
+        assert!(super::estimate(&[], "adf", 300).is_none());
+    }
+
+    /// Ensure that durations are rounded to a sensible unit.
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(0.2), "≈1 s");
+        assert_eq!(format_duration(42.0), "≈42 s");
+        assert_eq!(format_duration(180.0), "≈3 min");
+        assert_eq!(format_duration(7200.0), "≈2.0 h");
+    }
+}
@@ -0,0 +1,139 @@
+//! Machine-readable progress events for GUI frontends
+//!
+//! With `--progress json`, every event is printed to stdout as a JSON object
+//! on a line of its own (newline-delimited JSON). The `event` field names the
+//! kind of event. Other output on stdout is not JSON, so frontends should
+//! ignore lines that don't parse.
+//!
+//! Frontends control the processing queue with `arkivisto --progress json
+//! queue pause|resume|status`, which report the new state as events. Running
+//! processes report when they wait for a paused queue and when they continue.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::OnceLock,
+};
+
+use serde::Serialize;
+use tracing::debug;
+
+use crate::args::ProgressFormat;
+
+static FORMAT: OnceLock<ProgressFormat> = OnceLock::new();
+
+/// Configure the format of progress reports
+///
+/// Has no effect if events were already emitted.
+pub fn init(format: ProgressFormat) {
+    if FORMAT.set(format).is_err() {
+        debug!("Progress format is already initialized");
+    }
+}
+
+/// A step of a scan or of the processing of a document
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A scanner started scanning
+    ScanStarted { scanner: &'a str },
+    /// A scan is complete, the pages are stored in `directory`
+    ScanFinished {
+        scanner: &'a str,
+        directory: &'a Path,
+        pages: usize,
+    },
+    /// Processing of a document started
+    ProcessingStarted { directory: &'a Path, pages: usize },
+    /// A processing step started, `position` of `length` steps are done
+    ProcessingStep {
+        directory: &'a Path,
+        step: &'a str,
+        position: u64,
+        length: u64,
+    },
+    /// A page was postprocessed
+    PageProcessed { directory: &'a Path, page: usize },
+    /// The final PDF of a document was created
+    ProcessingFinished { directory: &'a Path, pdf: &'a Path },
+    /// Processing of a document failed
+    ProcessingFailed { directory: &'a Path, error: String },
+    /// Processing of the queue was paused, or a process waits for it to be
+    /// resumed
+    QueuePaused,
+    /// Processing of the queue was resumed
+    QueueResumed,
+    /// State of the processing queue
+    QueueStatus {
+        paused: bool,
+        queued: usize,
+        in_flight: usize,
+        failed: usize,
+        needs_review: usize,
+    },
+}
+
+/// Report an event, if machine-readable progress reports are enabled
+pub fn emit(event: &Event) {
+    if FORMAT.get() != Some(&ProgressFormat::Json) {
+        return;
+    }
+    let line = match serde_json::to_string(event) {
+        Ok(line) => line,
+        Err(e) => {
+            debug!("Failed to serialize event {event:?}: {e}");
+            return;
+        }
+    };
+    // Lock stdout, so that events of parallel jobs don't interleave
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = writeln!(stdout, "{line}").and_then(|()| stdout.flush()) {
+        debug!("Failed to write event: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that events are serialized as single-line objects tagged with
+    /// the event name.
+    #[test]
+    fn event_format() {
+        let event = Event::ProcessingStep {
+            directory: Path::new("/scans/20250601-143210-01JX"),
+            step: "Converting to PDF",
+            position: 4,
+            length: 6,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"processing_step","directory":"/scans/20250601-143210-01JX","step":"Converting to PDF","position":4,"length":6}"#
+        );
+        let event = Event::ProcessingFailed {
+            directory: Path::new("/scans/a"),
+            error: "Failed to run \"magick\"".into(),
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(
+            line.starts_with(r#"{"event":"processing_failed""#),
+            "{line}"
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::QueuePaused).unwrap(),
+            r#"{"event":"queue_paused"}"#
+        );
+        let event = Event::QueueStatus {
+            paused: false,
+            queued: 2,
+            in_flight: 1,
+            failed: 0,
+            needs_review: 3,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"queue_status","paused":false,"queued":2,"in_flight":1,"failed":0,"needs_review":3}"#
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Extraction of selected pages of a document into a standalone PDF (e.g. to
+//! share only the relevant page of a long contract)
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use tracing::{debug, warn};
+
+use crate::{fs_utils, magick, queue};
+
+/// Resolution at which redacted pages are rasterized
+const REDACTION_DPI: f32 = 300.0;
+
+/// A rectangle to black out on a page, in millimetres from the top left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Redaction {
+    /// Page number in the original document (starting at 1)
+    pub page: u32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl FromStr for Redaction {
+    type Err = anyhow::Error;
+
+    /// Parse a redaction in the form `PAGE:X,Y,WIDTH,HEIGHT` (e.g.
+    /// `2:10,20,80,15`)
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid redaction {s:?}, expected PAGE:X,Y,WIDTH,HEIGHT (in mm)");
+        let (page, rectangle) = s.split_once(':').ok_or_else(invalid)?;
+        let values = rectangle
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        ensure!(
+            values
+                .iter()
+                .all(|value| value.is_finite() && *value >= 0.0),
+            "Redaction {s:?} must not be negative"
+        );
+        Ok(Self {
+            page: page.trim().parse().map_err(|_| invalid())?,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl Redaction {
+    /// ImageMagick `-draw` primitive at [`REDACTION_DPI`]
+    fn draw_primitive(&self) -> String {
+        let px = |mm: f32| (mm / 25.4 * REDACTION_DPI).round();
+        format!(
+            "rectangle {},{} {},{}",
+            px(self.x),
+            px(self.y),
+            px(self.x + self.width),
+            px(self.y + self.height)
+        )
+    }
+}
+
+/// Parse page ranges (e.g. `2-3,5`) into page numbers, in the given order,
+/// for a document with `page_count` pages
+pub fn parse_pages(spec: &str, page_count: u32) -> Result<Vec<u32>> {
+    let mut pages = Vec::new();
+    for range in spec.split(',') {
+        let invalid = || anyhow!("Invalid page range {range:?}");
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (first, last),
+            None => (range, range),
+        };
+        let first: u32 = first.trim().parse().map_err(|_| invalid())?;
+        let last: u32 = last.trim().parse().map_err(|_| invalid())?;
+        ensure!(first >= 1 && first <= last, "Invalid page range {range:?}");
+        ensure!(
+            last <= page_count,
+            "Page range {range:?} exceeds the {page_count} pages of the document"
+        );
+        pages.extend(first..=last);
+    }
+    Ok(pages)
+}
+
+/// Number of pages of a PDF, as reported by qpdf
+pub fn page_count(pdf: &Path) -> Result<u32> {
+    let output = Command::new("qpdf")
+        .arg("--show-npages")
+        .arg(pdf)
+        .output()
+        .context("Failed to run `qpdf`")?;
+    ensure!(
+        output.status.success(),
+        "Failed to count the pages of {pdf:?}: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid page count of {pdf:?}"))
+}
+
+/// Default path of the extracted PDF in the working directory (e.g.
+/// `contract-p2-3.pdf`)
+fn default_output(document: &Path, spec: &str) -> Result<PathBuf> {
+    let name = document_name(document)?;
+    let spec: String = spec
+        .chars()
+        .map(|c| if c == ',' { '_' } else { c })
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    Ok(env::current_dir()?.join(format!("{name}-p{spec}.pdf")))
+}
+
+/// Path of the PDF of a document, which is either a PDF or a document
+/// directory with a final PDF
+pub fn document_pdf(document: &Path) -> Result<PathBuf> {
+    let pdf = if document.is_dir() {
+        document.join(queue::FINAL_PDF)
+    } else {
+        document.to_path_buf()
+    };
+    ensure!(pdf.exists(), "Document {:?} does not exist", pdf);
+    Ok(pdf)
+}
+
+/// Name of a document for derived files (the final PDF of a document
+/// directory is named after the directory)
+pub fn document_name(pdf: &Path) -> Result<String> {
+    let name = if pdf.file_name() == Some(queue::FINAL_PDF.as_ref()) {
+        pdf.parent().and_then(|dir| dir.file_name())
+    } else {
+        pdf.file_stem()
+    }
+    .context("Invalid document path")?;
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Run a command, return an error (and log its stderr) if it fails
+pub fn run(command: &mut Command) -> Result<()> {
+    debug!("Running {:?}", command);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run `{program}`"))?;
+    if !output.status.success() {
+        if program == magick::program() {
+            return Err(magick::failure(&output));
+        }
+        warn!(
+            "{program} failed with status {}. Stderr: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        return Err(anyhow!("Failed to run `{program}` command"));
+    }
+    Ok(())
+}
+
+/// Extract `pages` of a document (a PDF, or a document directory with a final
+/// PDF) into a new PDF, return its path
+///
+/// Pages with redactions are rasterized, so that they don't contain a text
+/// layer that would still reveal the redacted content.
+pub fn extract(
+    document: &Path,
+    spec: &str,
+    output: Option<&Path>,
+    redactions: &[Redaction],
+) -> Result<PathBuf> {
+    let document = document_pdf(document)?;
+    let pages = parse_pages(spec, page_count(&document)?)?;
+    for redaction in redactions {
+        ensure!(
+            pages.contains(&redaction.page),
+            "Page {} is redacted, but not extracted",
+            redaction.page
+        );
+    }
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => default_output(&document, spec)?,
+    };
+
+    // Extract the pages
+    let page_list = pages
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    run(Command::new("qpdf")
+        .arg("--empty")
+        .arg("--pages")
+        .arg(&document)
+        .arg(&page_list)
+        .arg("--")
+        .arg(&output))?;
+    if redactions.is_empty() {
+        return Ok(output);
+    }
+
+    // Rasterize the redacted pages with black rectangles, and replace them
+    let work_dir = env::temp_dir().join(format!("arkivisto-extract-{}", std::process::id()));
+    fs_utils::ensure_empty_dir_exists(&work_dir)?;
+    let result = (|| {
+        let mut replacements = Vec::new();
+        for (index, page) in pages.iter().enumerate() {
+            let page_redactions: Vec<&Redaction> =
+                redactions.iter().filter(|r| r.page == *page).collect();
+            if page_redactions.is_empty() {
+                continue;
+            }
+            let redacted = work_dir.join(format!("{index}.pdf"));
+            let mut command = magick::command();
+            command
+                .args(["-density", &REDACTION_DPI.to_string()])
+                .arg(format!("{}[{index}]", output.display()))
+                .args(["-fill", "black"]);
+            for redaction in page_redactions {
+                command.args(["-draw", &redaction.draw_primitive()]);
+            }
+            run(command.arg(&redacted))?;
+            replacements.push((index + 1, redacted));
+        }
+
+        // Assemble the pages of the extracted and the redacted PDFs
+        let extracted = work_dir.join("extracted.pdf");
+        fs_utils::move_path(&output, &extracted)?;
+        let mut command = Command::new("qpdf");
+        command.arg("--empty").arg("--pages");
+        for number in 1..=pages.len() {
+            match replacements.iter().find(|(n, _)| *n == number) {
+                Some((_, redacted)) => command.arg(redacted).arg("1"),
+                None => command.arg(&extracted).arg(number.to_string()),
+            };
+        }
+        run(command.arg("--").arg(&output))
+    })();
+    if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+        debug!("Failed to remove {work_dir:?}: {e}");
+    }
+    result?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that page ranges are expanded in the given order, and that
+    /// invalid ranges are rejected.
+    #[test]
+    fn page_ranges() {
+        assert_eq!(parse_pages("2-3", 5).unwrap(), vec![2, 3]);
+        assert_eq!(parse_pages("5, 1-2", 5).unwrap(), vec![5, 1, 2]);
+        assert!(parse_pages("0", 5).is_err());
+        assert!(parse_pages("3-2", 5).is_err());
+        assert!(parse_pages("1-", 5).is_err());
+        assert!(parse_pages("", 5).is_err());
+        assert!(parse_pages("6", 5).is_err());
+        assert!(parse_pages("1-99999999", 5).is_err());
+    }
+
+    /// Ensure that redactions are parsed and converted to pixels.
+    #[test]
+    fn redactions() {
+        let redaction: Redaction = "2:25.4,0,50.8,10".parse().unwrap();
+        assert_eq!(redaction.page, 2);
+        assert_eq!(redaction.draw_primitive(), "rectangle 300,0 900,118");
+        assert!("2:1,2,3".parse::<Redaction>().is_err());
+        assert!("x:1,2,3,4".parse::<Redaction>().is_err());
+        assert!("1:1,-2,3,4".parse::<Redaction>().is_err());
+    }
+
+    /// Ensure that the extracted PDF is named after the document and the
+    /// pages.
+    #[test]
+    fn output_name() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            default_output(Path::new("/docs/contract.pdf"), "2-3, 5").unwrap(),
+            cwd.join("contract-p2-3_5.pdf")
+        );
+        assert_eq!(
+            default_output(Path::new("/scans/20250101-120000/_final.pdf"), "1").unwrap(),
+            cwd.join("20250101-120000-p1.pdf")
+        );
+    }
+}
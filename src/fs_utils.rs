@@ -1,6 +1,21 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
 use anyhow::{Context, Result, ensure};
+use tracing::{debug, warn};
+
+use crate::prompt;
+
+/// How often an operation failing with a stale file handle is attempted
+const STALE_HANDLE_ATTEMPTS: u32 = 4;
+
+/// Delay before retrying an operation, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(250);
 
 /// Ensure that a directory exists and is empty
 ///
@@ -20,23 +35,178 @@ pub fn ensure_empty_dir_exists(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Copy the contents of a directory non-recursively to another directory.
+/// Ensure that a configured directory exists, offering to create it if it
+/// doesn't
+pub fn ensure_dir_prompt(path: &Path) -> Result<()> {
+    if path.exists() {
+        ensure!(
+            path.is_dir(),
+            "Path {:?} exists and is not a directory",
+            path
+        );
+        return Ok(());
+    }
+    let create = prompt::Confirm::new(&format!(
+        "Directory {} does not exist. Create it?",
+        path.display()
+    ))
+    .with_default(true)
+    .with_help_message("If the directory is on a network share, make sure that it is mounted")
+    .prompt()?;
+    ensure!(create, "Directory {:?} does not exist", path);
+    fs::create_dir_all(path).with_context(|| format!("Failed to create directory {:?}", path))
+}
+
+/// Create a new directory named `name` inside `parent`, return its path.
 ///
-/// Only files are copied, not directories. The destination directory must exist
-/// and be a directory.
-pub fn copy_dir_file_contents(src: &Path, dst: &Path) -> Result<()> {
-    ensure!(
-        dst.exists() && dst.is_dir(),
-        "Destination path {:?} does not exist or is not a directory",
-        dst
-    );
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_file() {
-            fs::copy(entry.path(), dst.join(entry.file_name()))?;
+/// If the name is already taken, a zero-padded numeric suffix is appended (e.g.
+/// `name-02`), so that the names still sort in creation order.
+/// Creating the directory reserves the name, so concurrent callers never
+/// receive the same path.
+pub fn create_unique_dir(parent: &Path, name: &str) -> Result<PathBuf> {
+    let mut candidate = parent.join(name);
+    let mut suffix = 2;
+    loop {
+        match fs::create_dir(&candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                candidate = parent.join(format!("{name}-{suffix:02}"));
+                suffix += 1;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to create directory {:?}", candidate));
+            }
         }
     }
+}
+
+/// Run a file system operation, retrying it if it fails with a stale file
+/// handle
+///
+/// On NFS, a handle becomes stale when the file was replaced on the server.
+/// Reopening the path usually resolves this.
+pub fn retry_stale<T>(mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e)
+                if e.kind() == ErrorKind::StaleNetworkFileHandle
+                    && attempt < STALE_HANDLE_ATTEMPTS =>
+            {
+                warn!("Stale file handle (attempt {attempt}), retrying");
+                thread::sleep(RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Retry listing a directory until `done` returns true for the result
+///
+/// Network file systems may cache directory listings, so files created
+/// shortly before (possibly by another host) can show up with a delay.
+pub fn retry_listing<T>(
+    mut list: impl FnMut() -> Result<T>,
+    done: impl Fn(&T) -> bool,
+) -> Result<T> {
+    let mut result = list()?;
+    for attempt in 1..STALE_HANDLE_ATTEMPTS {
+        if done(&result) {
+            break;
+        }
+        debug!("Directory listing incomplete (attempt {attempt}), retrying");
+        thread::sleep(RETRY_DELAY * attempt);
+        result = list()?;
+    }
+    Ok(result)
+}
+
+/// Write a file durably
+///
+/// The content is written to a temporary file, flushed to disk and renamed
+/// over the target, so that the file is never left truncated (even on a
+/// network file system or after a crash).
+pub fn write_synced(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid file path {path:?}"))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    let mut file =
+        File::create(&tmp_path).with_context(|| format!("Failed to create {tmp_path:?}"))?;
+    file.write_all(contents.as_ref())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to rename {tmp_path:?} to {path:?}"))?;
+    // Persist the rename itself. Not all file systems support syncing
+    // directories, so errors are ignored.
+    if let Some(parent) = path.parent() {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Move a file or directory
+///
+/// Renaming fails if source and destination are on different file systems
+/// (e.g. when part of the scans directory is mounted from the network). In
+/// that case, the source is copied and removed afterwards.
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            debug!("Cannot rename {from:?} across file systems, copying");
+            copy_recursively(from, to)
+                .with_context(|| format!("Failed to copy {from:?} to {to:?}"))?;
+            if from.is_dir() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+            .with_context(|| format!("Failed to remove {from:?}"))
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to move {from:?} to {to:?}")),
+    }
+}
+
+/// Path of `path` relative to `base`, with `/` as separator (e.g. the key of
+/// an archived PDF in the indexes of the archive directory)
+pub fn relative_key(base: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    relative
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// URL of a local file, with reserved characters percent-encoded
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
+
+/// Copy a file or directory (including its contents), flushing files to disk
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+        File::open(to)?.sync_all()?;
+    }
     Ok(())
 }
 
@@ -130,4 +300,105 @@ mod tests {
             assert!(!target_path.is_dir());
         }
     }
+
+    mod create_unique_dir {
+        use super::*;
+
+        use tempfile::TempDir;
+
+        /// Ensure that a suffix is appended if the name is already taken.
+        #[test]
+        fn append_suffix() {
+            let temp_dir = TempDir::new().unwrap();
+            let parent = temp_dir.path();
+
+            let first = create_unique_dir(parent, "20250101-120000").unwrap();
+            let second = create_unique_dir(parent, "20250101-120000").unwrap();
+            let third = create_unique_dir(parent, "20250101-120000").unwrap();
+            assert_eq!(first, parent.join("20250101-120000"));
+            assert_eq!(second, parent.join("20250101-120000-02"));
+            assert_eq!(third, parent.join("20250101-120000-03"));
+            assert!(third.is_dir());
+        }
+
+        /// Ensure that an error is returned if the parent does not exist.
+        #[test]
+        fn parent_dir_does_not_exist() {
+            let temp_dir = TempDir::new().unwrap();
+            let result = create_unique_dir(&temp_dir.path().join("nonexistent"), "target");
+            assert!(result.is_err());
+        }
+    }
+
+    mod retry_stale {
+        use super::*;
+
+        /// Ensure that an operation failing with a stale file handle is
+        /// retried, while other errors are returned immediately.
+        #[test]
+        fn retry_only_stale() {
+            let mut calls = 0;
+            let result = retry_stale(|| {
+                calls += 1;
+                if calls < 2 {
+                    Err(io::Error::from(ErrorKind::StaleNetworkFileHandle))
+                } else {
+                    Ok(calls)
+                }
+            });
+            assert_eq!(result.unwrap(), 2);
+
+            let mut calls = 0;
+            let result: io::Result<()> = retry_stale(|| {
+                calls += 1;
+                Err(io::Error::from(ErrorKind::NotFound))
+            });
+            assert!(result.is_err());
+            assert_eq!(calls, 1);
+        }
+    }
+
+    mod write_synced {
+        use super::*;
+
+        use tempfile::TempDir;
+
+        /// Ensure that the file is replaced and no temporary file is left
+        /// behind.
+        #[test]
+        fn replace_file() {
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("manifest.toml");
+            fs::write(&path, "old").unwrap();
+
+            write_synced(&path, "new").unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+            assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        }
+    }
+
+    mod move_path {
+        use super::*;
+
+        use tempfile::TempDir;
+
+        /// Ensure that a directory is copied with its contents and removed
+        /// afterwards when renaming is not possible.
+        #[test]
+        fn copy_directory() {
+            let temp_dir = TempDir::new().unwrap();
+            let from = temp_dir.path().join("from");
+            let to = temp_dir.path().join("to");
+            fs::create_dir_all(from.join("sub")).unwrap();
+            fs::write(from.join("sub/0001.tif"), "page").unwrap();
+
+            copy_recursively(&from, &to).unwrap();
+            assert_eq!(fs::read_to_string(to.join("sub/0001.tif")).unwrap(), "page");
+
+            fs::remove_dir_all(&to).unwrap();
+            move_path(&from, &to).unwrap();
+            assert!(!from.exists());
+            assert!(to.join("sub/0001.tif").exists());
+        }
+    }
 }
@@ -0,0 +1,141 @@
+//! Consistency check of the archive
+//!
+//! Compares the search, tag and link indexes with the PDFs in the archive directory,
+//! e.g. after documents were filed, deleted or changed by hand, or to detect
+//! corrupted files. The check only reads the archive. Its findings are
+//! repaired on request by updating the indexes; the PDFs are never changed.
+
+use std::{collections::BTreeSet, path::Path};
+
+use anyhow::Result;
+
+use crate::{fs_utils, links, search, tags, ui};
+
+/// Inconsistencies between the indexes and the PDFs of an archive, by path
+/// relative to the archive directory
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// PDFs without an entry in the search index
+    pub orphans: Vec<String>,
+    /// Entries of the search, tag or link index whose PDF does not exist
+    pub ghosts: Vec<String>,
+    /// PDFs whose content does not match the checksum in the search index
+    pub mismatches: Vec<String>,
+}
+
+impl Report {
+    /// Number of inconsistencies
+    pub fn len(&self) -> usize {
+        self.orphans.len() + self.ghosts.len() + self.mismatches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Check the indexes of the archive in `outdir` against its PDFs
+///
+/// Entries that were indexed without checksum are not compared.
+pub fn check(outdir: &Path) -> Result<Report> {
+    let pdfs: BTreeSet<String> = search::collect_pdfs(outdir)?
+        .iter()
+        .map(|pdf| fs_utils::relative_key(outdir, pdf))
+        .collect();
+    let checksums = search::checksums(outdir)?;
+    let mut report = Report {
+        orphans: pdfs
+            .iter()
+            .filter(|key| !checksums.contains_key(*key))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    let indexed: BTreeSet<String> = checksums
+        .keys()
+        .cloned()
+        .chain(tags::keys(outdir)?)
+        .chain(links::keys(outdir)?)
+        .collect();
+    report.ghosts = indexed.difference(&pdfs).cloned().collect();
+
+    let progress = ui::progress_bar(checksums.len() as u64).with_message("Checking documents");
+    for (key, expected) in &checksums {
+        if let Some(expected) = expected
+            && pdfs.contains(key)
+            && search::checksum(&outdir.join(key))? != *expected
+        {
+            report.mismatches.push(key.clone());
+        }
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    Ok(report)
+}
+
+/// Repair the inconsistencies of a [`check`] of the archive in `outdir`
+///
+/// Orphans are indexed, ghosts are dropped from the indexes, and PDFs that do
+/// not match their checksum are indexed anew, accepting their current
+/// content.
+pub fn repair(outdir: &Path, report: &Report) -> Result<()> {
+    for key in &report.ghosts {
+        let pdf = outdir.join(key);
+        search::remove(outdir, &pdf)?;
+        tags::remove(outdir, &pdf)?;
+        links::remove(outdir, &pdf)?;
+    }
+    for key in report.orphans.iter().chain(&report.mismatches) {
+        search::reindex(outdir, &outdir.join(key))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    /// Ensure that orphans, ghosts and changed PDFs are found, and that the
+    /// archive is consistent after repairing them.
+    #[test]
+    fn check_and_repair() {
+        let outdir = TempDir::new().unwrap();
+        let outdir = outdir.path();
+        let pdf = |name: &str| outdir.join(name);
+        for name in ["indexed.pdf", "changed.pdf", "orphan.pdf", "ghost.pdf"] {
+            fs::write(pdf(name), format!("%PDF {name}")).unwrap();
+        }
+        for name in ["indexed.pdf", "changed.pdf", "ghost.pdf"] {
+            search::add(outdir, &pdf(name), "Text", None, None).unwrap();
+        }
+        tags::record(outdir, &pdf("tagged-ghost.pdf"), &["bills".into()], None).unwrap();
+        links::link(outdir, &pdf("indexed.pdf"), &pdf("ghost.pdf"), None).unwrap();
+        fs::remove_file(pdf("ghost.pdf")).unwrap();
+        fs::write(pdf("changed.pdf"), "%PDF corrupted").unwrap();
+        // Copies for emailing and hidden files are not archived documents
+        fs::write(pdf("indexed_email.pdf"), "%PDF").unwrap();
+        fs::create_dir(pdf(".versions")).unwrap();
+        fs::write(pdf(".versions/indexed_v1.pdf"), "%PDF").unwrap();
+
+        let report = check(outdir).unwrap();
+        assert_eq!(
+            report,
+            Report {
+                orphans: vec!["orphan.pdf".into()],
+                ghosts: vec!["ghost.pdf".into(), "tagged-ghost.pdf".into()],
+                mismatches: vec!["changed.pdf".into()],
+            }
+        );
+
+        repair(outdir, &report).unwrap();
+        assert!(check(outdir).unwrap().is_empty());
+        let documents = search::documents(outdir).unwrap();
+        assert_eq!(documents.len(), 3);
+        assert!(tags::keys(outdir).unwrap().is_empty());
+        assert!(links::keys(outdir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+use std::{fs, ops::Range, path::Path};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{
+    config::{PaperSize, TiffCompression},
+    tiff_utils::{self, PixelFormat, Raster},
+};
+
+/// Largest skew (in degrees) that is detected. Pages that are fed at a steeper
+/// angle are most likely rotated on purpose.
+const MAX_SKEW: f64 = 5.0;
+
+/// Step (in degrees) of the coarse search for the skew, which is then refined
+/// in steps of a tenth
+const SKEW_STEP: f64 = 0.25;
+
+/// Smallest skew (in degrees) that is corrected, since rotating a page blurs
+/// it slightly
+const MIN_SKEW: f64 = 0.05;
+
+/// Size (in pixels) of the longer side of the reduced page that the skew is
+/// detected on
+const ANALYSIS_SIZE: u32 = 1000;
+
+/// Minimal difference (in luminance levels) between the dark pixels and the
+/// background for the skew to be detected
+const MIN_CONTRAST: usize = 48;
+
+/// Minimal number of dark pixels on the reduced page for the skew to be
+/// detected
+const MIN_DARK_PIXELS: usize = 200;
+
+/// Resolution (in DPI) of composed pages
+const COMPOSE_DPI: f32 = 300.0;
+
+/// Straighten the first page of a TIFF file, return the skew of the page (in
+/// degrees, clockwise)
+///
+/// The skew is detected from the lines of text (or other horizontal
+/// structures) of the page. Pages without a skew are copied unchanged. Unlike
+/// the corrections of [`tiff_utils::correct_page`], the whole page is held in
+/// memory. The corners that are rotated into the page are white.
+pub fn deskew(
+    input: &Path,
+    output: &Path,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<f64> {
+    let page = tiff_utils::read_raster(input, memory_budget)
+        .with_context(|| format!("Failed to read {input:?}"))?;
+    let skew = detect_skew(&page);
+    debug!("Skew of {input:?}: {skew:.2}°");
+    if skew.abs() < MIN_SKEW {
+        if input != output {
+            fs::copy(input, output)
+                .with_context(|| format!("Failed to copy {input:?} to {output:?}"))?;
+        }
+        return Ok(skew);
+    }
+    tiff_utils::write_raster(output, &rotate(&page, skew), compression)
+        .with_context(|| format!("Failed to write {output:?}"))?;
+    Ok(skew)
+}
+
+/// Compose the first pages of TIFF files onto a single page of `paper` size
+/// at 300 DPI, one below the other (e.g. the front and back of an ID card)
+///
+/// Portrait pages are rotated to landscape, and pages are shrunk if they don't
+/// fit into their share of the page. Pages without a resolution are taken as
+/// 300 DPI. The composed page is in color if any of the pages is.
+pub fn compose<P: AsRef<Path>>(
+    pages: &[P],
+    paper: PaperSize,
+    output: &Path,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<()> {
+    let pages = pages
+        .iter()
+        .map(|page| {
+            let page = page.as_ref();
+            tiff_utils::read_raster(page, memory_budget)
+                .with_context(|| format!("Failed to read {page:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let formats = || pages.iter().map(|page| page.format);
+    let format = if formats().any(|format| format == PixelFormat::Rgb) {
+        PixelFormat::Rgb
+    } else if formats().any(|format| format == PixelFormat::Gray) {
+        PixelFormat::Gray
+    } else {
+        PixelFormat::Bilevel
+    };
+
+    let (width, height) = paper.pixels_300_dpi();
+    let slot_height = height / pages.len().max(1) as u32;
+    let mut composed = Raster::white(width, height, Some((COMPOSE_DPI, COMPOSE_DPI)), format);
+    for (i, page) in pages.iter().enumerate() {
+        let rotated;
+        let page = if page.width < page.height {
+            rotated = rotate_quarter(page);
+            &rotated
+        } else {
+            page
+        };
+        let (dpi_x, dpi_y) = page.dpi.unwrap_or((COMPOSE_DPI, COMPOSE_DPI));
+        let page_width = f64::from(page.width) * f64::from(COMPOSE_DPI / dpi_x);
+        let page_height = f64::from(page.height) * f64::from(COMPOSE_DPI / dpi_y);
+        let fit = (f64::from(width) / page_width)
+            .min(f64::from(slot_height) / page_height)
+            .min(1.0);
+        let scaled = resize(
+            page,
+            ((page_width * fit).round() as u32).clamp(1, width),
+            ((page_height * fit).round() as u32).clamp(1, slot_height),
+        );
+        let left = (width - scaled.width) / 2;
+        let top = slot_height * i as u32 + (slot_height - scaled.height) / 2;
+        paste(&mut composed, &scaled, left, top);
+    }
+    tiff_utils::write_raster(output, &composed, compression)
+        .with_context(|| format!("Failed to write {output:?}"))
+}
+
+/// Detect the skew of a page, in degrees clockwise
+///
+/// The page is reduced and binarized, then the angle is searched at which the
+/// projection of the dark pixels onto the vertical axis is the most uneven,
+/// i.e. at which the lines of text fall into the fewest rows.
+fn detect_skew(page: &Raster) -> f64 {
+    let pixels = dark_pixels(page);
+    if pixels.len() < MIN_DARK_PIXELS {
+        return 0.0;
+    }
+    let coarse = best_angle(&pixels, -MAX_SKEW, MAX_SKEW, SKEW_STEP);
+    best_angle(
+        &pixels,
+        coarse - SKEW_STEP,
+        coarse + SKEW_STEP,
+        SKEW_STEP / 10.0,
+    )
+}
+
+/// The angle from `from` to `to` (in steps of `step`) with the highest
+/// [`projection_score`], the one closest to zero on a tie
+fn best_angle(pixels: &[(f32, f32)], from: f64, to: f64, step: f64) -> f64 {
+    let steps = ((to - from) / step).round() as i32;
+    let mut best = (0.0f64, 0.0);
+    for angle in (0..=steps).map(|i| from + f64::from(i) * step) {
+        let score = projection_score(pixels, angle);
+        if score > best.1 || (score == best.1 && angle.abs() < best.0.abs()) {
+            best = (angle, score);
+        }
+    }
+    best.0
+}
+
+/// Sum of the squared numbers of dark pixels in the rows of the page, if the
+/// page is rotated by `angle` degrees counterclockwise
+fn projection_score(pixels: &[(f32, f32)], angle: f64) -> f64 {
+    let (sin, cos) = (angle.to_radians() as f32).sin_cos();
+    let radius = pixels
+        .iter()
+        .map(|(x, y)| x.hypot(*y))
+        .fold(0.0f32, f32::max)
+        .ceil() as usize;
+    let mut rows = vec![0u32; 2 * radius + 2];
+    for (x, y) in pixels {
+        let row = (y * cos - x * sin).round() as isize + radius as isize;
+        rows[row as usize] += 1;
+    }
+    rows.iter().map(|&count| f64::from(count).powi(2)).sum()
+}
+
+/// Positions of the dark pixels of a page that is reduced to about
+/// [`ANALYSIS_SIZE`] pixels, relative to the center of the page
+///
+/// Pixels are dark if they are closer to the darkest pixels than to the
+/// background (the median). Pages with too little contrast have no dark
+/// pixels.
+fn dark_pixels(page: &Raster) -> Vec<(f32, f32)> {
+    let factor = page.width.max(page.height).div_ceil(ANALYSIS_SIZE).max(1);
+    let (width, height) = (page.width / factor, page.height / factor);
+    let mut reduced = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let sum: u32 = (0..factor * factor)
+                .map(|i| {
+                    let (dx, dy) = (i % factor, i / factor);
+                    u32::from(luminance(page, x * factor + dx, y * factor + dy))
+                })
+                .sum();
+            reduced.push((sum / (factor * factor)) as u8);
+        }
+    }
+
+    let mut histogram = [0u64; 256];
+    for &value in &reduced {
+        histogram[value as usize] += 1;
+    }
+    let dark = tiff_utils::histogram_percentile(&histogram, 0.01);
+    let background = tiff_utils::histogram_percentile(&histogram, 0.5);
+    if background < dark + MIN_CONTRAST {
+        return Vec::new();
+    }
+    let threshold = ((dark + background) / 2) as u8;
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+    (reduced.iter().enumerate())
+        .filter(|(_, value)| **value < threshold)
+        .map(|(i, _)| {
+            let (x, y) = (i % width as usize, i / width as usize);
+            (x as f32 - center_x, y as f32 - center_y)
+        })
+        .collect()
+}
+
+/// Luminance (0-255) of a pixel
+fn luminance(page: &Raster, x: u32, y: u32) -> u8 {
+    let channels = page.format.channels();
+    let i = (y as usize * page.width as usize + x as usize) * channels;
+    match page.samples[i..i + channels] {
+        // ITU-R BT.601 luma
+        [r, g, b] => (0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b))
+            .round()
+            .min(255.0) as u8,
+        [value, ..] => value,
+        [] => 255,
+    }
+}
+
+/// Rotate a page by `angle` degrees counterclockwise around its center,
+/// keeping its size
+///
+/// The pixels are interpolated bilinearly, the corners that are rotated into
+/// the page are white.
+fn rotate(page: &Raster, angle: f64) -> Raster {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let channels = page.format.channels();
+    let center_x = f64::from(page.width - 1) / 2.0;
+    let center_y = f64::from(page.height - 1) / 2.0;
+    let mut samples = Vec::with_capacity(page.samples.len());
+    for y in 0..page.height {
+        let dy = f64::from(y) - center_y;
+        for x in 0..page.width {
+            let dx = f64::from(x) - center_x;
+            let source_x = center_x + dx * cos - dy * sin;
+            let source_y = center_y + dx * sin + dy * cos;
+            for channel in 0..channels {
+                samples.push(interpolate(page, source_x, source_y, channel));
+            }
+        }
+    }
+    Raster {
+        width: page.width,
+        height: page.height,
+        dpi: page.dpi,
+        format: page.format,
+        samples,
+    }
+}
+
+/// Sample of a page at a position between pixels, white outside of the page
+fn interpolate(page: &Raster, x: f64, y: f64, channel: usize) -> u8 {
+    let channels = page.format.channels();
+    let sample = |x: f64, y: f64| {
+        if x < 0.0 || y < 0.0 || x >= f64::from(page.width) || y >= f64::from(page.height) {
+            return 255.0;
+        }
+        let i = (y as usize * page.width as usize + x as usize) * channels + channel;
+        f64::from(page.samples[i])
+    };
+    let (left, top) = (x.floor(), y.floor());
+    let (fx, fy) = (x - left, y - top);
+    let upper = sample(left, top) * (1.0 - fx) + sample(left + 1.0, top) * fx;
+    let lower = sample(left, top + 1.0) * (1.0 - fx) + sample(left + 1.0, top + 1.0) * fx;
+    (upper * (1.0 - fy) + lower * fy).round() as u8
+}
+
+/// Rotate a page by 90 degrees counterclockwise
+fn rotate_quarter(page: &Raster) -> Raster {
+    let channels = page.format.channels();
+    let mut samples = Vec::with_capacity(page.samples.len());
+    for y in 0..page.width {
+        for x in 0..page.height {
+            // The right column of the page becomes the top row
+            let i = (x as usize * page.width as usize + (page.width - 1 - y) as usize) * channels;
+            samples.extend_from_slice(&page.samples[i..i + channels]);
+        }
+    }
+    Raster {
+        width: page.height,
+        height: page.width,
+        dpi: page.dpi.map(|(x, y)| (y, x)),
+        format: page.format,
+        samples,
+    }
+}
+
+/// Scale a page to `width` x `height` pixels, averaging the pixels that fall
+/// onto a pixel of the scaled page (or taking the nearest one when enlarging)
+fn resize(page: &Raster, width: u32, height: u32) -> Raster {
+    let channels = page.format.channels();
+    let scale_x = f64::from(page.width) / f64::from(width);
+    let scale_y = f64::from(page.height) / f64::from(height);
+    let mut samples = Vec::with_capacity(width as usize * height as usize * channels);
+    for y in 0..height {
+        let rows = span(y, scale_y, page.height);
+        for x in 0..width {
+            let columns = span(x, scale_x, page.width);
+            let count = (rows.len() * columns.len()) as u32;
+            for channel in 0..channels {
+                let sum: u32 = (rows.clone())
+                    .flat_map(|row| columns.clone().map(move |column| (row, column)))
+                    .map(|(row, column)| {
+                        u32::from(
+                            page.samples[(row * page.width as usize + column) * channels + channel],
+                        )
+                    })
+                    .sum();
+                samples.push(((sum + count / 2) / count) as u8);
+            }
+        }
+    }
+    Raster {
+        width,
+        height,
+        dpi: (page.dpi).map(|(x, y)| (x / scale_x as f32, y / scale_y as f32)),
+        format: page.format,
+        samples,
+    }
+}
+
+/// Pixels of a page of `size` pixels that fall onto pixel `index` of the page
+/// scaled by 1 / `scale`
+fn span(index: u32, scale: f64, size: u32) -> Range<usize> {
+    let start = ((f64::from(index) * scale).floor() as u32).min(size - 1);
+    let end = ((f64::from(index + 1) * scale).ceil() as u32).clamp(start + 1, size);
+    start as usize..end as usize
+}
+
+/// Copy a page onto a larger page, with its top left corner at `left`, `top`
+fn paste(target: &mut Raster, page: &Raster, left: u32, top: u32) {
+    let target_channels = target.format.channels();
+    let channels = page.format.channels();
+    for y in 0..page.height {
+        for x in 0..page.width {
+            let source = (y as usize * page.width as usize + x as usize) * channels;
+            let pixel = &page.samples[source..source + channels];
+            let i = ((top + y) as usize * target.width as usize + (left + x) as usize)
+                * target_channels;
+            for channel in 0..target_channels {
+                target.samples[i + channel] = pixel[channel.min(channels - 1)];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// A gray page with lines of "text" that are skewed by `angle` degrees
+    /// clockwise
+    fn skewed_page(angle: f64) -> Raster {
+        let mut page = Raster::white(800, 600, Some((300.0, 300.0)), PixelFormat::Gray);
+        let slope = angle.to_radians().tan();
+        for y in 0..600u32 {
+            for x in 100..700u32 {
+                let line = (f64::from(y) - 300.0) - (f64::from(x) - 400.0) * slope;
+                if line.abs() < 250.0 && line.rem_euclid(30.0) < 6.0 {
+                    page.samples[(y * 800 + x) as usize] = 20;
+                }
+            }
+        }
+        page
+    }
+
+    /// Ensure that the skew of lines of text is detected, also after the page
+    /// was straightened, and that blank pages are not skewed.
+    #[test]
+    fn skew_detection() {
+        for angle in [2.0, -3.3, 0.0] {
+            let page = skewed_page(angle);
+            let skew = detect_skew(&page);
+            assert!((skew - angle).abs() < 0.1, "{skew} instead of {angle}");
+            let straightened = rotate(&page, skew);
+            assert!(detect_skew(&straightened).abs() < 0.1);
+        }
+        let blank = Raster::white(800, 600, None, PixelFormat::Rgb);
+        assert_eq!(detect_skew(&blank), 0.0);
+    }
+
+    /// Ensure that skewed pages are straightened and bilevel pages stay
+    /// bilevel, while straight pages are copied unchanged.
+    #[test]
+    fn deskew_pages() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let output = temp_dir.path().join("output.tif");
+        let memory_budget = usize::MAX;
+
+        let mut page = skewed_page(1.5);
+        page.format = PixelFormat::Bilevel;
+        tiff_utils::write_raster(&input, &page, TiffCompression::Lzw).unwrap();
+        let skew = deskew(&input, &output, TiffCompression::Lzw, memory_budget).unwrap();
+        assert!((skew - 1.5).abs() < 0.1);
+        let straightened = tiff_utils::read_raster(&output, memory_budget).unwrap();
+        assert_eq!(straightened.format, PixelFormat::Bilevel);
+        assert_eq!(straightened.dpi, Some((300.0, 300.0)));
+        assert!(detect_skew(&straightened).abs() < 0.1);
+
+        tiff_utils::write_raster(&input, &skewed_page(0.0), TiffCompression::Lzw).unwrap();
+        deskew(&input, &output, TiffCompression::Lzw, memory_budget).unwrap();
+        assert_eq!(fs::read(&input).unwrap(), fs::read(&output).unwrap());
+    }
+
+    /// Ensure that pages are placed in the upper and lower half of the page,
+    /// at 300 DPI, and that portrait pages are rotated to landscape.
+    #[test]
+    fn compose_two_pages() {
+        let temp_dir = TempDir::new().unwrap();
+        let front = temp_dir.path().join("front.tif");
+        let back = temp_dir.path().join("back.tif");
+        let output = temp_dir.path().join("n_up.tif");
+        let memory_budget = usize::MAX;
+
+        let mut page = Raster::white(600, 400, Some((300.0, 300.0)), PixelFormat::Gray);
+        page.samples.fill(0);
+        tiff_utils::write_raster(&front, &page, TiffCompression::Lzw).unwrap();
+        // Portrait page at 150 DPI, red at the top and blue at the bottom
+        let mut page = Raster::white(200, 300, Some((150.0, 150.0)), PixelFormat::Rgb);
+        for (i, pixel) in page.samples.chunks_exact_mut(3).enumerate() {
+            let color = if i < 200 * 150 {
+                [255, 0, 0]
+            } else {
+                [0, 0, 255]
+            };
+            pixel.copy_from_slice(&color);
+        }
+        tiff_utils::write_raster(&back, &page, TiffCompression::Lzw).unwrap();
+
+        compose(
+            &[&front, &back],
+            PaperSize::A4,
+            &output,
+            TiffCompression::Lzw,
+            memory_budget,
+        )
+        .unwrap();
+        let composed = tiff_utils::read_raster(&output, memory_budget).unwrap();
+        assert_eq!((composed.width, composed.height), (2480, 3508));
+        assert_eq!(composed.dpi, Some((300.0, 300.0)));
+        assert_eq!(composed.format, PixelFormat::Rgb);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 2480 + x) * 3;
+            &composed.samples[i..i + 3]
+        };
+        // The front is centered in the upper half (1754 pixels)
+        assert_eq!(pixel(1240, 877), [0, 0, 0]);
+        assert_eq!(pixel(939, 877), [255, 255, 255]);
+        assert_eq!(pixel(940, 677), [0, 0, 0]);
+        assert_eq!(pixel(940, 676), [255, 255, 255]);
+        // The back is scaled to 300 DPI and rotated counterclockwise
+        assert_eq!(pixel(1090, 1754 + 877), [255, 0, 0]);
+        assert_eq!(pixel(1390, 1754 + 877), [0, 0, 255]);
+        assert_eq!(pixel(1240, 1754 + 676), [255, 255, 255]);
+        assert_eq!(pixel(10, 10), [255, 255, 255]);
+    }
+}
@@ -0,0 +1,483 @@
+//! History of earlier documents, used to estimate durations and to preselect
+//! the choices that the user usually makes (the scan mode and resolution per
+//! scanner, and the correspondent and tags of similar documents)
+//!
+//! The scan statistics and metadata of archived documents are appended to a
+//! history file in the XDG app data directory, since their manifests are
+//! removed from the scans cache. Documents that are still in the cache are
+//! read from their manifests.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    fs_utils,
+    manifest::{self, Manifest, ScanStats},
+};
+
+/// Name of the history file in the XDG app data directory (one JSON object
+/// per line)
+const HISTORY_FILE: &str = "scan-history.jsonl";
+
+/// Number of characteristic words of a document that are kept to find
+/// similar documents
+const FINGERPRINT_WORDS: usize = 30;
+
+/// Minimal share of common characteristic words of similar documents
+const MIN_SIMILARITY: f32 = 0.3;
+
+/// An earlier document
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// ID of the scanner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ScanStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correspondent: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Characteristic words of the recognized text, see [`fingerprint`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<String>,
+}
+
+impl Entry {
+    fn from_manifest(manifest: &Manifest, text: &str) -> Self {
+        Self {
+            scanner: manifest.scanner.clone(),
+            stats: manifest.scan_stats.clone(),
+            correspondent: manifest.correspondent.clone(),
+            tags: manifest.tags.clone(),
+            words: fingerprint(text),
+        }
+    }
+}
+
+/// The most frequent words of a text with at least four letters, lowercase
+/// and sorted
+pub fn fingerprint(text: &str) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for word in text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| word.chars().count() >= 4)
+    {
+        *counts.entry(word.to_lowercase()).or_default() += 1;
+    }
+    let mut words: Vec<(String, usize)> = counts.into_iter().collect();
+    words.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    let mut words: Vec<String> = words
+        .into_iter()
+        .take(FINGERPRINT_WORDS)
+        .map(|(word, _)| word)
+        .collect();
+    words.sort();
+    words
+}
+
+/// Return the path of the history file in the XDG app data directory
+pub fn history_path() -> Result<PathBuf> {
+    let data_dir = app_dirs::app_root(app_dirs::AppDataType::UserData, &crate::APP_INFO)
+        .context("Could not determine XDG app data directory")?;
+    Ok(data_dir.join(HISTORY_FILE))
+}
+
+/// Append a document with its recognized text to the history file (e.g.
+/// before it is removed from the scans cache)
+pub fn record(path: &Path, manifest: &Manifest, text: &str) -> Result<()> {
+    let entry = Entry::from_manifest(manifest, text);
+    let mut line = serde_json::to_string(&entry).context("Failed to serialize scan history")?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write scan history {path:?}"))
+}
+
+/// Replace correspondents and tags of the documents in the history file (e.g.
+/// misspelled variants by the correct ones), given by their old names
+///
+/// Invalid lines are kept as they are.
+pub fn rename(
+    path: &Path,
+    correspondents: &BTreeMap<String, String>,
+    tags: &BTreeMap<String, String>,
+) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+        .with_context(|| format!("Failed to read scan history {path:?}"))?;
+    let mut renamed = String::new();
+    for line in content.lines() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(mut entry) => {
+                if let Some(correspondent) = &entry.correspondent
+                    && let Some(new) = correspondents.get(correspondent)
+                {
+                    entry.correspondent = Some(new.clone());
+                }
+                let mut tags_renamed = Vec::new();
+                crate::tags::merge(
+                    &mut tags_renamed,
+                    entry
+                        .tags
+                        .iter()
+                        .map(|tag| tags.get(tag).unwrap_or(tag).as_str()),
+                );
+                entry.tags = tags_renamed;
+                renamed.push_str(
+                    &serde_json::to_string(&entry).context("Failed to serialize scan history")?,
+                );
+            }
+            Err(_) => renamed.push_str(line),
+        }
+        renamed.push('\n');
+    }
+    if renamed == content {
+        return Ok(());
+    }
+    fs_utils::write_synced(path, renamed)
+        .with_context(|| format!("Failed to write scan history {path:?}"))
+}
+
+/// Load the earlier documents, from the history file and the scans cache
+/// (oldest first)
+pub fn load(scans_dir: &Path) -> Vec<Entry> {
+    match history_path() {
+        Ok(path) => load_from(&path, scans_dir),
+        Err(e) => {
+            debug!("Failed to load scan history: {e:#}");
+            load_from(Path::new(""), scans_dir)
+        }
+    }
+}
+
+/// Load the earlier documents from the history file at `path` and the scans
+/// cache
+fn load_from(path: &Path, scans_dir: &Path) -> Vec<Entry> {
+    let mut history = Vec::new();
+    if path.is_file() {
+        match fs_utils::retry_stale(|| fs::read_to_string(path)) {
+            Ok(content) => {
+                for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                    match serde_json::from_str(line) {
+                        Ok(entry) => history.push(entry),
+                        Err(e) => debug!("Skipping invalid scan history entry {line:?}: {e}"),
+                    }
+                }
+            }
+            Err(e) => debug!("Failed to read scan history {path:?}: {e}"),
+        }
+    }
+    // Archived documents kept in the cache are in the history file already
+    match manifest::find_documents(scans_dir, |manifest| {
+        manifest.scan_stats.is_some() && manifest.state != manifest::DocumentState::Archived
+    }) {
+        Ok(documents) => history.extend(
+            documents
+                .iter()
+                .map(|(_, manifest)| Entry::from_manifest(manifest, "")),
+        ),
+        Err(e) => debug!("Failed to load the scans in the cache: {e:#}"),
+    }
+    history
+}
+
+/// Scan statistics of earlier scans with a scanner
+fn scans_with<'a>(history: &'a [Entry], scanner: &'a str) -> impl Iterator<Item = &'a ScanStats> {
+    history
+        .iter()
+        .filter(move |entry| entry.scanner.as_deref() == Some(scanner))
+        .filter_map(|entry| entry.stats.as_ref())
+}
+
+/// The scan mode that is used most often with a scanner (the most recent one
+/// on a tie)
+pub fn preferred_mode<'a>(history: &'a [Entry], scanner: &'a str) -> Option<&'a str> {
+    let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (i, stats) in scans_with(history, scanner).enumerate() {
+        let entry = counts.entry(stats.mode.as_str()).or_default();
+        *entry = (entry.0 + 1, i);
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count_and_last)| *count_and_last)
+        .map(|(mode, _)| mode)
+}
+
+/// Whether most earlier scans with a scanner and mode used a resolution above
+/// `dpi`
+pub fn prefers_higher_dpi(history: &[Entry], scanner: &str, mode: &str, dpi: u32) -> bool {
+    let (higher, total) = scans_with(history, scanner)
+        .filter(|stats| stats.mode == mode)
+        .fold((0, 0), |(higher, total), stats| {
+            (higher + usize::from(stats.dpi > dpi), total + 1)
+        });
+    higher * 2 > total
+}
+
+/// Share of the characteristic words that two documents have in common
+fn similarity(a: &[String], b: &[String]) -> f32 {
+    let a: BTreeSet<&String> = a.iter().collect();
+    let b: BTreeSet<&String> = b.iter().collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f32 / union as f32
+}
+
+/// The earlier document that is most similar to a document with the given
+/// characteristic words (the most recent one on a tie)
+fn most_similar<'a>(
+    history: impl IntoIterator<Item = &'a Entry>,
+    words: &[String],
+) -> Option<&'a Entry> {
+    history
+        .into_iter()
+        .map(|entry| (similarity(&entry.words, words), entry))
+        .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+        .fold(
+            None,
+            |best: Option<(f32, &Entry)>, (similarity, entry)| match best {
+                Some((best_similarity, _)) if best_similarity > similarity => best,
+                _ => Some((similarity, entry)),
+            },
+        )
+        .map(|(_, entry)| entry)
+}
+
+/// The correspondent of the most similar earlier document, if any
+pub fn similar_correspondent<'a>(history: &'a [Entry], words: &[String]) -> Option<&'a str> {
+    let with_correspondent = history.iter().filter(|entry| entry.correspondent.is_some());
+    most_similar(with_correspondent, words).and_then(|entry| entry.correspondent.as_deref())
+}
+
+/// Tags for a document: the tags used for most earlier documents of the
+/// correspondent, or else the tags of the most similar earlier document
+pub fn suggested_tags(
+    history: &[Entry],
+    correspondent: Option<&str>,
+    words: &[String],
+) -> Vec<String> {
+    let same_correspondent: Vec<&Entry> = history
+        .iter()
+        .filter(|entry| {
+            correspondent.is_some_and(|correspondent| {
+                entry
+                    .correspondent
+                    .as_deref()
+                    .is_some_and(|known| known.eq_ignore_ascii_case(correspondent))
+            })
+        })
+        .collect();
+    if same_correspondent.is_empty() {
+        return most_similar(history, words)
+            .map(|entry| entry.tags.clone())
+            .unwrap_or_default();
+    }
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for tag in same_correspondent.iter().flat_map(|entry| &entry.tags) {
+        match counts
+            .iter_mut()
+            .find(|(known, _)| known.eq_ignore_ascii_case(tag))
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((tag, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| count * 2 > same_correspondent.len())
+        .map(|(tag, _)| tag.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn scan(scanner: &str, mode: &str, dpi: u32) -> Entry {
+        Entry {
+            scanner: Some(scanner.into()),
+            stats: Some(ScanStats {
+                mode: mode.into(),
+                dpi,
+                pages: 1,
+                scan_secs: 1.0,
+                process_secs: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Ensure that recorded documents are loaded before the documents in the
+    /// cache.
+    #[test]
+    fn record_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(HISTORY_FILE);
+        let scans_dir = temp_dir.path().join("scans");
+        fs::create_dir(&scans_dir).unwrap();
+        assert!(load_from(&path, &scans_dir).is_empty());
+
+        let manifest = |entry: &Entry| Manifest {
+            scanner: entry.scanner.clone(),
+            scan_stats: entry.stats.clone(),
+            correspondent: entry.correspondent.clone(),
+            tags: entry.tags.clone(),
+            ..Default::default()
+        };
+        let archived = Entry {
+            correspondent: Some("Muster AG".into()),
+            tags: vec!["bills".into()],
+            words: vec!["rechnung".into()],
+            ..scan("office", "adf", 300)
+        };
+        record(&path, &manifest(&archived), "Rechnung").unwrap();
+        let imported = Entry {
+            correspondent: Some("Other".into()),
+            ..Default::default()
+        };
+        record(&path, &manifest(&imported), "").unwrap();
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+
+        let cached = scan("home", "flatbed", 600);
+        let document_dir = scans_dir.join("20250101-120000");
+        fs::create_dir(&document_dir).unwrap();
+        manifest(&cached).save(&document_dir).unwrap();
+
+        assert_eq!(
+            load_from(&path, &scans_dir),
+            vec![archived, imported, cached]
+        );
+    }
+
+    /// Ensure that correspondents and tags are renamed in the history file,
+    /// merging duplicate tags and keeping invalid lines.
+    #[test]
+    fn rename_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(HISTORY_FILE);
+        let entry = Entry {
+            correspondent: Some("Insurnace AG".into()),
+            tags: vec!["insurance".into(), "Insurance".into(), "car".into()],
+            ..Default::default()
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        fs::write(&path, format!("{line}\ngarbage\n")).unwrap();
+
+        let correspondents =
+            BTreeMap::from([("Insurnace AG".to_string(), "Insurance AG".to_string())]);
+        let tags = BTreeMap::from([("insurance".to_string(), "Insurance".to_string())]);
+        rename(&path, &correspondents, &tags).unwrap();
+        let expected = Entry {
+            correspondent: Some("Insurance AG".into()),
+            tags: vec!["Insurance".into(), "car".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\ngarbage\n", serde_json::to_string(&expected).unwrap())
+        );
+    }
+
+    /// Ensure that the most frequent longer words are kept, lowercase and
+    /// sorted.
+    #[test]
+    fn fingerprints() {
+        assert_eq!(
+            fingerprint("Rechnung 2024: Die RECHNUNG ist bis zum 30. Juni zahlbar."),
+            vec!["juni", "rechnung", "zahlbar"]
+        );
+        let text: String = (0..100)
+            .map(|i| format!("word{}x ", "a".repeat(i)))
+            .collect();
+        assert_eq!(fingerprint(&text).len(), FINGERPRINT_WORDS);
+        assert!(fingerprint("").is_empty());
+    }
+
+    /// Ensure that the correspondent and tags of similar documents, and the
+    /// usual tags of a correspondent, are suggested.
+    #[test]
+    fn suggestions() {
+        let document = |correspondent: &str, tags: &[&str], text: &str| Entry {
+            correspondent: Some(correspondent.into()),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            words: fingerprint(text),
+            ..Default::default()
+        };
+        let history = vec![
+            document("Muster AG", &["bills"], "Rechnung Muster Betrag Zahlbar"),
+            document(
+                "Muster AG",
+                &["bills", "car"],
+                "Rechnung Muster Fahrzeug Service",
+            ),
+            document(
+                "Insurer",
+                &["insurance"],
+                "Police Versicherung Praemie Fahrzeug",
+            ),
+        ];
+
+        let words = fingerprint("Versicherung Police Praemie Jahr");
+        assert_eq!(similar_correspondent(&history, &words), Some("Insurer"));
+        assert_eq!(suggested_tags(&history, None, &words), vec!["insurance"]);
+        assert_eq!(
+            suggested_tags(&history, Some("muster ag"), &words),
+            vec!["bills"]
+        );
+
+        let words = fingerprint("Something completely different");
+        assert_eq!(similar_correspondent(&history, &words), None);
+        assert!(suggested_tags(&history, Some("Other"), &words).is_empty());
+    }
+
+    /// Ensure that the most frequent mode of a scanner is preferred, and the
+    /// most recent one on a tie.
+    #[test]
+    fn preferred_modes() {
+        let history = vec![
+            scan("office", "adf", 300),
+            scan("office", "adf-duplex", 300),
+            scan("office", "adf-duplex", 600),
+            scan("home", "flatbed", 300),
+            scan("office", "adf", 300),
+        ];
+        assert_eq!(preferred_mode(&history, "office"), Some("adf"));
+        assert_eq!(preferred_mode(&history[..3], "office"), Some("adf-duplex"));
+        assert_eq!(preferred_mode(&history, "home"), Some("flatbed"));
+        assert_eq!(preferred_mode(&history, "other"), None);
+    }
+
+    /// Ensure that a higher resolution is only preferred if most scans with
+    /// the mode used it.
+    #[test]
+    fn preferred_resolution() {
+        let history = vec![
+            scan("office", "flatbed", 600),
+            scan("office", "flatbed", 600),
+            scan("office", "flatbed", 300),
+            scan("office", "adf", 600),
+            scan("office", "adf", 300),
+        ];
+        assert!(prefers_higher_dpi(&history, "office", "flatbed", 300));
+        assert!(!prefers_higher_dpi(&history, "office", "adf", 300));
+        assert!(!prefers_higher_dpi(&history, "home", "flatbed", 300));
+    }
+}
@@ -0,0 +1,232 @@
+//! Recognized words and their positions in the hOCR output of Tesseract
+
+/// A recognized word
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    /// Left, top, right and bottom edge in pixels
+    pub bbox: [u32; 4],
+    /// Confidence (0-100) of the recognition, if known
+    pub confidence: Option<f32>,
+    pub text: String,
+}
+
+/// The recognized words of a page
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Page {
+    /// Width of the page in pixels
+    pub width: u32,
+    /// Height of the page in pixels
+    pub height: u32,
+    pub words: Vec<Word>,
+}
+
+/// Parse an hOCR document of a single page
+///
+/// Elements other than the page and its words (e.g. lines) are ignored.
+pub fn parse(hocr: &str) -> Page {
+    let mut page = Page::default();
+    if let Some([_, _, width, height]) = elements(hocr, "ocr_page")
+        .next()
+        .and_then(|(title, _)| property(title, "bbox"))
+        .and_then(parse_bbox)
+    {
+        page.width = width;
+        page.height = height;
+    }
+    page.words = elements(hocr, "ocrx_word")
+        .filter_map(|(title, content)| {
+            let text = decode_entities(&strip_tags(content));
+            let text = text.trim();
+            if text.is_empty() {
+                return None;
+            }
+            Some(Word {
+                bbox: property(title, "bbox").and_then(parse_bbox)?,
+                confidence: property(title, "x_wconf").and_then(|value| value.parse().ok()),
+                text: text.to_string(),
+            })
+        })
+        .collect();
+    page
+}
+
+/// Average confidence of all words of the pages
+///
+/// Returns `None` if the pages contain no words with a confidence.
+pub fn average_confidence(pages: &[Page]) -> Option<f32> {
+    let confidences: Vec<f32> = pages
+        .iter()
+        .flat_map(|page| &page.words)
+        .filter_map(|word| word.confidence)
+        .collect();
+    if confidences.is_empty() {
+        return None;
+    }
+    Some(confidences.iter().sum::<f32>() / confidences.len() as f32)
+}
+
+/// The title and the content (up to the next closing `</span>` or `</div>`)
+/// of all elements with the class
+fn elements<'a>(hocr: &'a str, class: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+    hocr.match_indices('<').filter_map(move |(position, _)| {
+        let (start, _) = hocr[position + 1..].split_once('>')?;
+        let attributes = attributes(start);
+        let classes = attributes.iter().find(|(name, _)| *name == "class")?.1;
+        if !classes.split_whitespace().any(|name| name == class) {
+            return None;
+        }
+        let title = attributes
+            .iter()
+            .find(|(name, _)| *name == "title")
+            .map_or("", |(_, value)| value);
+        let content = &hocr[position + start.len() + 2..];
+        let content_end = ["</span>", "</div>"]
+            .iter()
+            .filter_map(|closing| content.find(closing))
+            .min()
+            .unwrap_or(content.len());
+        Some((title, &content[..content_end]))
+    })
+}
+
+/// The quoted attributes of a start tag (e.g. `span class='ocrx_word'`)
+fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    while let Some((name, value)) = rest.split_once('=') {
+        let name = name.split_whitespace().last().unwrap_or("");
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '\'' || *c == '"') else {
+            break;
+        };
+        let Some((value, remainder)) = value[1..].split_once(quote) else {
+            break;
+        };
+        attributes.push((name, value));
+        rest = remainder;
+    }
+    attributes
+}
+
+/// The value of a property of an hOCR title (e.g. `bbox 36 92 96 122;
+/// x_wconf 96`)
+fn property<'a>(title: &'a str, name: &str) -> Option<&'a str> {
+    title.split(';').find_map(|property| {
+        let (key, value) = property.trim().split_once(' ')?;
+        (key == name).then_some(value.trim())
+    })
+}
+
+/// Parse the value of a `bbox` property
+fn parse_bbox(value: &str) -> Option<[u32; 4]> {
+    let values: Vec<u32> = value
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Remove the tags of formatted text (e.g. `<strong>`)
+fn strip_tags(content: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in content.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Decode the character references of HTML text
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end + 1)));
+        match entity {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode a character reference without `&` and `;` (e.g. `amp` or `#39`)
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let code = match name.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => name.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the page size and the words with their position,
+    /// confidence and decoded text are parsed.
+    #[test]
+    fn parse_page() {
+        let hocr = r#"
+            <div class='ocr_page' id='page_1' title='image "/scans/doc/0001_processed.tif"; bbox 0 0 2480 3508; ppageno 0'>
+             <span class='ocr_line' id='line_1_1' title="bbox 36 92 580 122; baseline 0 -6">
+              <span class='ocrx_word' id='word_1_1' title='bbox 36 92 96 122; x_wconf 96'>Invoice</span>
+              <span class='ocrx_word' id='word_1_2' title='bbox 104 92 150 122; x_wconf 60'><strong>M&amp;M&#39;s</strong></span>
+              <span class='ocrx_word' id='word_1_3' title='bbox 158 92 200 122; x_wconf 45'> </span>
+             </span>
+            </div>
+        "#;
+        let page = parse(hocr);
+        assert_eq!((page.width, page.height), (2480, 3508));
+        assert_eq!(
+            page.words,
+            vec![
+                Word {
+                    bbox: [36, 92, 96, 122],
+                    confidence: Some(96.0),
+                    text: "Invoice".into(),
+                },
+                Word {
+                    bbox: [104, 92, 150, 122],
+                    confidence: Some(60.0),
+                    text: "M&M's".into(),
+                },
+            ]
+        );
+        assert_eq!(average_confidence(&[page]), Some(78.0));
+        assert_eq!(average_confidence(&[Page::default()]), None);
+        assert_eq!(parse("<div class='ocr_page'></div>"), Page::default());
+    }
+
+    /// Ensure that unknown or invalid character references are kept.
+    #[test]
+    fn entities() {
+        assert_eq!(decode_entities("a &lt;b&gt; &#x263A;"), "a <b> ☺");
+        assert_eq!(decode_entities("R&D &foo; &"), "R&D &foo; &");
+    }
+}
@@ -0,0 +1,329 @@
+//! Import of photos of documents (e.g. taken with a phone camera)
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeDelta};
+use tracing::debug;
+
+use crate::{
+    config::{Config, ProcessingConfig, TiffCompression},
+    fs_utils, limits, magick,
+    manifest::{Manifest, ScanSource},
+    process, scan,
+    scheduler::{self, JobKind},
+    tiff_utils, tools,
+};
+
+/// Photos taken at most this long after the previous photo are pages of the
+/// same document
+const BURST_INTERVAL: TimeDelta = TimeDelta::seconds(60);
+
+/// Subdirectory of the imported folder that imported photos are moved to
+const IMPORTED_DIR: &str = "imported";
+
+/// A photo to be imported
+#[derive(Debug, Clone, PartialEq)]
+struct Photo {
+    path: PathBuf,
+    /// Time the photo was taken (or modified, without EXIF data)
+    taken_at: DateTime<FixedOffset>,
+}
+
+/// Whether a file is a photo that can be imported
+fn is_photo(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg")
+        })
+}
+
+/// Parse an EXIF timestamp (e.g. `2025:06:01 14:30:00`)
+fn parse_exif_time(value: &[u8]) -> Option<NaiveDateTime> {
+    let value = std::str::from_utf8(value).ok()?;
+    NaiveDateTime::parse_from_str(value.trim(), "%Y:%m:%d %H:%M:%S").ok()
+}
+
+/// Read the time a photo was taken from its EXIF data
+fn exif_time(path: &Path) -> Option<NaiveDateTime> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => parse_exif_time(values.first()?),
+        _ => None,
+    }
+}
+
+/// Group photos into documents, sorted by the time they were taken
+///
+/// A photo belongs to the same document as the previous one if it was taken
+/// within [`BURST_INTERVAL`].
+fn group_bursts(mut photos: Vec<Photo>) -> Vec<Vec<Photo>> {
+    photos.sort_by(|a, b| (a.taken_at, &a.path).cmp(&(b.taken_at, &b.path)));
+    let mut documents: Vec<Vec<Photo>> = Vec::new();
+    for photo in photos {
+        if let Some(document) = documents.last_mut()
+            && let Some(previous) = document.last()
+            && photo.taken_at - previous.taken_at <= BURST_INTERVAL
+        {
+            document.push(photo);
+        } else {
+            documents.push(vec![photo]);
+        }
+    }
+    documents
+}
+
+/// Whether a file is a TIFF image (by its extension)
+fn is_tiff(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("tif") || extension.eq_ignore_ascii_case("tiff")
+        })
+}
+
+/// Convert an image (e.g. a photo or a multi-page TIFF) to TIFF pages in a
+/// document directory, numbered from `first_page`
+///
+/// The pages are rotated according to their EXIF orientation. Without
+/// ImageMagick, only TIFF images can be converted (without rotation).
+pub fn convert_to_pages(
+    image: &Path,
+    document_dir: &Path,
+    first_page: usize,
+    config: &ProcessingConfig,
+) -> Result<()> {
+    if is_tiff(image) && !magick::is_installed() {
+        tools::warn_missing("magick", "copying TIFF pages without rotating them");
+        tiff_utils::split_pages(
+            image,
+            document_dir,
+            first_page,
+            TiffCompression::Lzw,
+            config.memory_budget(),
+        )
+        .with_context(|| format!("Failed to convert {image:?}"))?;
+        return Ok(());
+    }
+    let output = magick::limited_command(&config.limits)
+        .args(limits::magick_args(
+            &config.limits,
+            scheduler::global().limit(JobKind::Cpu),
+        ))
+        .arg(image)
+        .arg("-auto-orient")
+        .args(["-compress", "LZW"])
+        .args(["-scene", &first_page.to_string(), "+adjoin"])
+        .arg(document_dir.join("%04d.tif"))
+        .output()
+        .with_context(|| format!("Failed to run `{}` command", magick::program()))?;
+    if !output.status.success() {
+        return Err(magick::failure(&output).context(format!("Failed to convert {image:?}")));
+    }
+    Ok(())
+}
+
+/// Import all photos in `folder` as documents into the scans directory, return
+/// the document directories
+///
+/// The EXIF timestamps of the photos determine the scan time of the
+/// documents. Imported photos are moved to a subdirectory of `folder` named
+/// after the document, so that they are not imported again.
+pub fn import_photos(folder: &Path, config: &Config) -> Result<Vec<PathBuf>> {
+    let timezone = config.scan.timezone;
+    let mut photos = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(folder))
+        .with_context(|| format!("Failed to read directory {folder:?}"))?
+    {
+        let path = entry?.path();
+        if !path.is_file() || !is_photo(&path) {
+            continue;
+        }
+        let taken_at = match exif_time(&path).and_then(|time| timezone.localize(time)) {
+            Some(time) => time,
+            None => {
+                debug!("No EXIF timestamp in {path:?}, using the modification time");
+                timezone.convert_system_time(fs::metadata(&path)?.modified()?)
+            }
+        };
+        photos.push(Photo { path, taken_at });
+    }
+
+    let scans_dir = scan::scans_dir()?;
+    let mut document_dirs = Vec::new();
+    for photos in group_bursts(photos) {
+        let (document_dir, manifest) = scan::create_document_dir_at(
+            &scans_dir,
+            photos[0].taken_at,
+            &config.scan.dir_format,
+            if config.scan.dir_details {
+                &["camera"]
+            } else {
+                &[]
+            },
+        )?;
+        let results = scheduler::global().map(JobKind::Cpu, &photos, |i, photo| {
+            convert_to_pages(&photo.path, &document_dir, i + 1, &config.processing)
+        });
+        if let Err(e) = results.into_iter().collect::<Result<Vec<()>>>() {
+            let _ = fs::remove_dir_all(&document_dir);
+            return Err(e);
+        }
+        let manifest = Manifest {
+            source: Some(ScanSource::Camera),
+            ..manifest
+        };
+        manifest.save(&document_dir)?;
+
+        // Keep the originals, but out of the way of the next import
+        let imported_dir = folder.join(IMPORTED_DIR).join(
+            document_dir
+                .file_name()
+                .context("Invalid document directory")?,
+        );
+        fs::create_dir_all(&imported_dir)
+            .with_context(|| format!("Failed to create directory {imported_dir:?}"))?;
+        for photo in &photos {
+            let file_name = photo.path.file_name().context("Invalid photo path")?;
+            fs_utils::move_path(&photo.path, &imported_dir.join(file_name))?;
+        }
+        document_dirs.push(document_dir);
+    }
+    Ok(document_dirs)
+}
+
+/// Read a list of files, one path per line (empty lines are skipped)
+fn read_file_list(reader: impl BufRead) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read file list")?;
+        let path = line.trim();
+        if !path.is_empty() {
+            files.push(PathBuf::from(path));
+        }
+    }
+    Ok(files)
+}
+
+/// Import image files produced by other scanner software as the pages of a
+/// single document, in the given order, return the document directory
+///
+/// If the only file is `-`, the list of files is read from stdin. The files
+/// are copied, the originals are left in place.
+pub fn import_files(files: &[PathBuf], config: &Config) -> Result<PathBuf> {
+    let files = match files {
+        [file] if file.as_os_str() == "-" => read_file_list(io::stdin().lock())?,
+        files => files.to_vec(),
+    };
+    ensure!(!files.is_empty(), "No files to import");
+    for file in &files {
+        ensure!(file.is_file(), "File {file:?} does not exist");
+    }
+
+    let (document_dir, manifest) = scan::create_document_dir_at(
+        &scan::scans_dir()?,
+        config.scan.timezone.now(),
+        &config.scan.dir_format,
+        if config.scan.dir_details {
+            &["external"]
+        } else {
+            &[]
+        },
+    )?;
+    // Files may contain multiple pages, so they are converted one after the
+    // other to number the pages consecutively
+    let result = files.iter().try_for_each(|file| {
+        let first_page = process::collect_page_tifs(&document_dir)?.len() + 1;
+        convert_to_pages(file, &document_dir, first_page, &config.processing)
+    });
+    if let Err(e) = result.and_then(|()| manifest.save(&document_dir)) {
+        let _ = fs::remove_dir_all(&document_dir);
+        return Err(e);
+    }
+    Ok(document_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    /// Ensure that only JPEG files are imported.
+    #[test]
+    fn photo_extensions() {
+        assert!(is_photo(Path::new("IMG_0001.JPG")));
+        assert!(is_photo(Path::new("photo.jpeg")));
+        assert!(!is_photo(Path::new("notes.txt")));
+        assert!(!is_photo(Path::new("jpg")));
+    }
+
+    /// Ensure that EXIF timestamps are parsed, and invalid ones are ignored.
+    #[test]
+    fn exif_timestamps() {
+        assert_eq!(
+            parse_exif_time(b"2025:06:01 14:30:05"),
+            NaiveDate::from_ymd_opt(2025, 6, 1)
+                .unwrap()
+                .and_hms_opt(14, 30, 5)
+        );
+        assert_eq!(parse_exif_time(b"0000:00:00 00:00:00"), None);
+        assert_eq!(parse_exif_time(b"    :  :     :  :  "), None);
+    }
+
+    /// Ensure that photos taken within a minute of the previous one are
+    /// grouped into one document, in the order they were taken.
+    #[test]
+    fn burst_grouping() {
+        let photo = |name: &str, seconds: i64| Photo {
+            path: PathBuf::from(name),
+            taken_at: DateTime::parse_from_rfc3339("2025-06-01T14:00:00+02:00").unwrap()
+                + TimeDelta::seconds(seconds),
+        };
+        let documents = group_bursts(vec![
+            photo("c.jpg", 50),
+            photo("a.jpg", 0),
+            photo("d.jpg", 200),
+            photo("b.jpg", 20),
+            photo("e.jpg", 260),
+        ]);
+        let names: Vec<Vec<&str>> = documents
+            .iter()
+            .map(|document| {
+                document
+                    .iter()
+                    .map(|photo| photo.path.to_str().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![vec!["a.jpg", "b.jpg", "c.jpg"], vec!["d.jpg", "e.jpg"]]
+        );
+        assert!(group_bursts(Vec::new()).is_empty());
+    }
+
+    /// Ensure that file lists are read line by line, without empty lines and
+    /// surrounding whitespace.
+    #[test]
+    fn file_list() {
+        let list = "scan-1.tif\n\n  scan 2.tif \r\n/tmp/scan-3.jpg";
+        assert_eq!(
+            read_file_list(list.as_bytes()).unwrap(),
+            [
+                PathBuf::from("scan-1.tif"),
+                PathBuf::from("scan 2.tif"),
+                PathBuf::from("/tmp/scan-3.jpg"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! QR labels of archived documents
+//!
+//! Paper originals that are kept (e.g. in a binder) can be labelled with a QR
+//! code that links them to the archived document. The code holds the stable ID
+//! of the document (the scanned code finds it with `arkivisto search` even
+//! after renaming), or its path in the archive if it has no ID. The labels are
+//! PNG images, rendered with `qrencode`, and mirror the structure of the
+//! archive.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{extract, fs_utils, tools};
+
+/// Prefix of the document IDs in the QR codes
+pub const ID_PREFIX: &str = "arkivisto:";
+
+/// Size of a module of the QR code in pixels
+const MODULE_SIZE: u32 = 8;
+
+/// Content of the QR code of an archived PDF of `outdir`
+pub fn label_content(outdir: &Path, pdf: &Path, id: Option<&str>) -> String {
+    match id.filter(|id| !id.is_empty()) {
+        Some(id) => format!("{ID_PREFIX}{id}"),
+        None => fs_utils::relative_key(outdir, pdf),
+    }
+}
+
+/// Path of the label of an archived PDF of `outdir` in `labels_dir`, at the
+/// path of the PDF relative to `outdir`
+fn label_path(labels_dir: &Path, outdir: &Path, pdf: &Path) -> PathBuf {
+    let key = fs_utils::relative_key(outdir, pdf);
+    labels_dir.join(Path::new(&key).with_extension("png"))
+}
+
+/// Write the QR label of an archived PDF of `outdir` into `labels_dir`,
+/// return its path
+///
+/// Returns `None` if `qrencode` is not installed.
+pub fn create(
+    labels_dir: &Path,
+    outdir: &Path,
+    pdf: &Path,
+    id: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    if !tools::is_installed("qrencode") {
+        tools::warn_missing("qrencode", "no QR label is created");
+        return Ok(None);
+    }
+    let label = label_path(labels_dir, outdir, pdf);
+    if let Some(parent) = label.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {parent:?}"))?;
+    }
+    let content = label_content(outdir, pdf, id);
+    debug!("Writing QR label with {content:?} to {label:?}");
+    extract::run(
+        Command::new("qrencode")
+            .args(["--type", "PNG", "--level", "M"])
+            .arg(format!("--size={MODULE_SIZE}"))
+            .arg("--output")
+            .arg(&label)
+            .arg("--")
+            .arg(content),
+    )?;
+    Ok(Some(label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the label holds the ID of the document, or its path in
+    /// the archive, and mirrors the path of the PDF.
+    #[test]
+    fn content_and_path() {
+        let outdir = Path::new("/archive");
+        let pdf = outdir.join("2025/2025-05-30_muster-ag_invoice.pdf");
+        assert_eq!(
+            label_content(outdir, &pdf, Some("01JABCDEFGHJKMNPQRSTVWXYZ0")),
+            "arkivisto:01JABCDEFGHJKMNPQRSTVWXYZ0"
+        );
+        assert_eq!(
+            label_content(outdir, &pdf, Some("")),
+            "2025/2025-05-30_muster-ag_invoice.pdf"
+        );
+        assert_eq!(
+            label_path(Path::new("/labels"), outdir, &pdf),
+            Path::new("/labels/2025/2025-05-30_muster-ag_invoice.png")
+        );
+    }
+}
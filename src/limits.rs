@@ -0,0 +1,147 @@
+use std::{ffi::OsStr, process::Command};
+
+use crate::config::{IoPriority, ResourceLimits};
+
+/// Create a command for a local external tool, wrapped in `nice` and `ionice`
+/// according to the configured resource limits.
+pub fn limited_command(program: impl AsRef<OsStr>, limits: &ResourceLimits) -> Command {
+    let mut wrappers: Vec<Vec<String>> = Vec::new();
+    if let Some(niceness) = limits.niceness {
+        wrappers.push(vec!["nice".into(), "-n".into(), niceness.to_string()]);
+    }
+    if let Some(io_priority) = limits.io_priority
+        && cfg!(target_os = "linux")
+    {
+        let class = match io_priority {
+            IoPriority::BestEffort => "2",
+            IoPriority::Idle => "3",
+        };
+        wrappers.push(vec!["ionice".into(), "-c".into(), class.into()]);
+    }
+
+    let mut args = wrappers.into_iter().flatten();
+    match args.next() {
+        Some(wrapper) => {
+            let mut command = Command::new(wrapper);
+            command.args(args).arg(program);
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+/// Number of threads that a tool may use (e.g. through OpenMP) if it runs
+/// as one of `cpu_jobs` concurrent jobs
+///
+/// With more than one job, every job is limited to a single thread, since the
+/// jobs already occupy the CPUs.
+pub fn thread_limit(limits: &ResourceLimits, cpu_jobs: usize) -> Option<u32> {
+    if cpu_jobs > 1 {
+        return Some(1);
+    }
+    limits.cpus.map(|cpus| (cpus.ceil() as u32).max(1))
+}
+
+/// Arguments for ImageMagick that limit its resource usage if it runs as one
+/// of `cpu_jobs` concurrent jobs
+pub fn magick_args(limits: &ResourceLimits, cpu_jobs: usize) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(threads) = thread_limit(limits, cpu_jobs) {
+        args.extend(["-limit".into(), "thread".into(), threads.to_string()]);
+    }
+    args
+}
+
+/// Arguments for `docker run` that limit the resource usage of the container
+pub fn container_args(limits: &ResourceLimits) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(cpus) = limits.cpus {
+        args.push(format!("--cpus={cpus}"));
+    }
+    if let Some(memory_mb) = limits.memory_mb {
+        args.push(format!("--memory={memory_mb}m"));
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Ensure that commands are not wrapped if no limits are configured.
+    #[test]
+    fn no_limits() {
+        let limits = ResourceLimits::default();
+        let command = limited_command("magick", &limits);
+        assert_eq!(command.get_program(), "magick");
+        assert!(args(&command).is_empty());
+        assert!(magick_args(&limits, 1).is_empty());
+        assert!(container_args(&limits).is_empty());
+    }
+
+    /// Ensure that the niceness is applied through `nice`.
+    #[test]
+    fn niceness() {
+        let limits = ResourceLimits {
+            niceness: Some(10),
+            ..Default::default()
+        };
+        let mut command = limited_command("magick", &limits);
+        command.arg("in.tif");
+        assert_eq!(command.get_program(), "nice");
+        assert_eq!(args(&command), vec!["-n", "10", "magick", "in.tif"]);
+    }
+
+    /// Ensure that the I/O priority is applied through `ionice` on Linux.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn nice_and_ionice() {
+        let limits = ResourceLimits {
+            niceness: Some(5),
+            io_priority: Some(IoPriority::Idle),
+            ..Default::default()
+        };
+        let command = limited_command("magick", &limits);
+        assert_eq!(command.get_program(), "nice");
+        assert_eq!(
+            args(&command),
+            vec!["-n", "5", "ionice", "-c", "3", "magick"]
+        );
+    }
+
+    /// Ensure that CPU and memory limits are passed to ImageMagick and Docker.
+    #[test]
+    fn cpu_and_memory() {
+        let limits = ResourceLimits {
+            cpus: Some(1.5),
+            memory_mb: Some(2048),
+            ..Default::default()
+        };
+        assert_eq!(magick_args(&limits, 1), vec!["-limit", "thread", "2"]);
+        assert_eq!(
+            container_args(&limits),
+            vec!["--cpus=1.5", "--memory=2048m"]
+        );
+    }
+
+    /// Ensure that concurrent jobs are limited to a single thread each.
+    #[test]
+    fn concurrent_jobs() {
+        let limits = ResourceLimits {
+            cpus: Some(4.0),
+            ..Default::default()
+        };
+        assert_eq!(thread_limit(&limits, 1), Some(4));
+        assert_eq!(thread_limit(&limits, 8), Some(1));
+        assert_eq!(thread_limit(&ResourceLimits::default(), 1), None);
+        assert_eq!(thread_limit(&ResourceLimits::default(), 8), Some(1));
+        assert_eq!(magick_args(&limits, 8), vec!["-limit", "thread", "1"]);
+    }
+}
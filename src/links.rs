@@ -0,0 +1,235 @@
+//! Links between archived documents
+//!
+//! Related documents (e.g. an invoice, its payment confirmation and the
+//! warranty) can be linked to each other, optionally with the kind of
+//! relation. The links are kept in an index in the archive directory, by the
+//! paths of the PDFs. Links are undirected, and they are dropped together with
+//! their documents, so they never point to documents that don't exist.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::fs_utils;
+
+/// Name of the link index in the archive directory
+const INDEX_FILE: &str = ".arkivisto-links.toml";
+
+/// A link between two PDFs, by path relative to the archive directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Link {
+    a: String,
+    b: String,
+    /// Kind of relation (e.g. `payment`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relation: Option<String>,
+}
+
+impl Link {
+    fn connects(&self, key: &str, other: &str) -> bool {
+        (self.a == key && self.b == other) || (self.a == other && self.b == key)
+    }
+
+    /// The other end of the link, if `key` is one of its ends
+    fn other(&self, key: &str) -> Option<&str> {
+        if self.a == key {
+            Some(&self.b)
+        } else if self.b == key {
+            Some(&self.a)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    links: Vec<Link>,
+}
+
+impl Index {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+            .with_context(|| format!("Failed to read link index {path:?}"))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse link index {path:?}"))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize link index")?;
+        fs_utils::write_synced(path, content)
+            .with_context(|| format!("Failed to write link index {path:?}"))
+    }
+}
+
+/// Resolve the path of an archived PDF, which must be in `outdir` (as
+/// canonical path)
+pub fn archived_pdf(outdir: &Path, pdf: &Path) -> Result<PathBuf> {
+    let pdf = fs::canonicalize(pdf).with_context(|| format!("Document {pdf:?} does not exist"))?;
+    if !pdf.starts_with(outdir) {
+        bail!("{pdf:?} is not in the archive directory {outdir:?}");
+    }
+    Ok(pdf)
+}
+
+/// Link two PDFs of `outdir`, or change the relation of their link
+pub fn link(outdir: &Path, pdf: &Path, other: &Path, relation: Option<&str>) -> Result<()> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let other = fs_utils::relative_key(outdir, other);
+    if key == other {
+        bail!("A document can't be linked to itself");
+    }
+    let relation = relation.filter(|relation| !relation.is_empty());
+    match index
+        .links
+        .iter_mut()
+        .find(|link| link.connects(&key, &other))
+    {
+        Some(link) if link.relation.as_deref() == relation => return Ok(()),
+        Some(link) => link.relation = relation.map(str::to_string),
+        None => index.links.push(Link {
+            a: key,
+            b: other,
+            relation: relation.map(str::to_string),
+        }),
+    }
+    index.save(&path)
+}
+
+/// Remove the link between two PDFs of `outdir`, return whether they were
+/// linked
+pub fn unlink(outdir: &Path, pdf: &Path, other: &Path) -> Result<bool> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let other = fs_utils::relative_key(outdir, other);
+    let count = index.links.len();
+    index.links.retain(|link| !link.connects(&key, &other));
+    if index.links.len() == count {
+        return Ok(false);
+    }
+    index.save(&path)?;
+    Ok(true)
+}
+
+/// PDFs of `outdir` that are linked to a PDF, with the relation of the link,
+/// ordered by path
+pub fn linked(outdir: &Path, pdf: &Path) -> Result<Vec<(PathBuf, Option<String>)>> {
+    let index = Index::load(&outdir.join(INDEX_FILE))?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let mut linked: Vec<_> = index
+        .links
+        .into_iter()
+        .filter_map(|link| {
+            let other = link.other(&key)?;
+            Some((outdir.join(other), link.relation.clone()))
+        })
+        .collect();
+    linked.sort();
+    Ok(linked)
+}
+
+/// Remove all links of a PDF (e.g. when it is deleted) from the index of
+/// `outdir`
+pub fn remove(outdir: &Path, pdf: &Path) -> Result<()> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let count = index.links.len();
+    index.links.retain(|link| link.other(&key).is_none());
+    if index.links.len() == count {
+        return Ok(());
+    }
+    index.save(&path)
+}
+
+/// Move the links of a PDF of `outdir` that was renamed to its new path
+pub fn rename(outdir: &Path, pdf: &Path, new_pdf: &Path) -> Result<()> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let new_key = fs_utils::relative_key(outdir, new_pdf);
+    let mut changed = false;
+    for link in &mut index.links {
+        for end in [&mut link.a, &mut link.b] {
+            if *end == key {
+                end.clone_from(&new_key);
+                changed = true;
+            }
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+    index.save(&path)
+}
+
+/// Paths of the linked PDFs in the index of `outdir`, relative to `outdir`
+pub fn keys(outdir: &Path) -> Result<BTreeSet<String>> {
+    let index = Index::load(&outdir.join(INDEX_FILE))?;
+    Ok(index
+        .links
+        .into_iter()
+        .flat_map(|link| [link.a, link.b])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that links are undirected, that their relation can be changed,
+    /// and that they follow renamed PDFs and are dropped with removed ones.
+    #[test]
+    fn links() {
+        let outdir = TempDir::new().unwrap();
+        let outdir = outdir.path();
+        let invoice = outdir.join("2025/invoice.pdf");
+        let payment = outdir.join("2025/payment.pdf");
+        let warranty = outdir.join("warranty.pdf");
+        link(outdir, &invoice, &payment, None).unwrap();
+        link(outdir, &payment, &invoice, Some("payment")).unwrap();
+        link(outdir, &warranty, &invoice, Some("")).unwrap();
+        assert!(link(outdir, &invoice, &invoice, None).is_err());
+
+        assert_eq!(
+            linked(outdir, &invoice).unwrap(),
+            vec![
+                (payment.clone(), Some("payment".to_string())),
+                (warranty.clone(), None),
+            ]
+        );
+        assert_eq!(
+            linked(outdir, &payment).unwrap(),
+            vec![(invoice.clone(), Some("payment".to_string()))]
+        );
+
+        let renamed = outdir.join("2025/receipt.pdf");
+        rename(outdir, &payment, &renamed).unwrap();
+        assert_eq!(
+            keys(outdir).unwrap(),
+            BTreeSet::from([
+                "2025/invoice.pdf".to_string(),
+                "2025/receipt.pdf".to_string(),
+                "warranty.pdf".to_string(),
+            ])
+        );
+
+        assert!(unlink(outdir, &invoice, &warranty).unwrap());
+        assert!(!unlink(outdir, &invoice, &warranty).unwrap());
+        remove(outdir, &invoice).unwrap();
+        assert!(keys(outdir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::{DateOrder, PaperSize};
+
+/// Regions that use US Letter paper
+const LETTER_REGIONS: [&str; 10] = ["US", "CA", "MX", "PH", "CL", "CO", "VE", "CR", "GT", "PR"];
+
+/// Regions that write dates as month, day, year
+const MDY_REGIONS: [&str; 3] = ["US", "PH", "PR"];
+
+/// Regions that write dates as year, month, day
+const YMD_REGIONS: [&str; 6] = ["CN", "JP", "KR", "TW", "HU", "LT"];
+
+/// A locale, e.g. `de_CH`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    /// ISO 639-1 language code (lowercase)
+    language: String,
+    /// ISO 3166-1 region code (uppercase)
+    region: Option<String>,
+}
+
+impl Locale {
+    /// Parse a locale like `de_CH`, `en-US` or `en_US.UTF-8`
+    pub fn parse(locale: &str) -> Option<Self> {
+        let locale = locale.split(['.', '@']).next()?;
+        let mut parts = locale.split(['_', '-']);
+        let language = parts.next()?.to_lowercase();
+        if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let region = parts.next().map(str::to_uppercase);
+        Some(Self { language, region })
+    }
+
+    fn region_in(&self, regions: &[&str]) -> bool {
+        self.region
+            .as_deref()
+            .is_some_and(|region| regions.contains(&region))
+    }
+
+    /// Default paper size in the region
+    pub fn paper_size(&self) -> PaperSize {
+        if self.region_in(&LETTER_REGIONS) {
+            PaperSize::Letter
+        } else {
+            PaperSize::A4
+        }
+    }
+
+    /// Usual order of dates in the region
+    pub fn date_order(&self) -> DateOrder {
+        if self.region_in(&MDY_REGIONS) {
+            DateOrder::Mdy
+        } else if self.region_in(&YMD_REGIONS) {
+            DateOrder::Ymd
+        } else {
+            DateOrder::Dmy
+        }
+    }
+
+    /// Tesseract language code of the language
+    pub fn ocr_language(&self) -> Option<&'static str> {
+        Some(match self.language.as_str() {
+            "cs" => "ces",
+            "da" => "dan",
+            "de" => "deu",
+            "en" => "eng",
+            "es" => "spa",
+            "fi" => "fin",
+            "fr" => "fra",
+            "it" => "ita",
+            "ja" => "jpn",
+            "ko" => "kor",
+            "nb" | "nn" | "no" => "nor",
+            "nl" => "nld",
+            "pl" => "pol",
+            "pt" => "por",
+            "ru" => "rus",
+            "sv" => "swe",
+            "zh" => "chi_sim",
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// Ensure that common locale notations are parsed.
+    #[test]
+    fn parse() {
+        let expected = Locale {
+            language: "en".into(),
+            region: Some("US".into()),
+        };
+        assert_eq!(Locale::parse("en_US"), Some(expected.clone()));
+        assert_eq!(Locale::parse("en-us"), Some(expected.clone()));
+        assert_eq!(Locale::parse("en_US.UTF-8"), Some(expected));
+        assert_eq!(Locale::parse("de").unwrap().region, None);
+        assert_eq!(Locale::parse(""), None);
+        assert_eq!(Locale::parse("C.UTF-8").unwrap().ocr_language(), None);
+    }
+
+    /// Ensure that the defaults depend on the region and language.
+    #[test]
+    fn defaults() {
+        let us = Locale::parse("en_US").unwrap();
+        assert_eq!(us.paper_size(), PaperSize::Letter);
+        assert_eq!(us.date_order(), DateOrder::Mdy);
+        assert_eq!(us.ocr_language(), Some("eng"));
+
+        let swiss = Locale::parse("de_CH").unwrap();
+        assert_eq!(swiss.paper_size(), PaperSize::A4);
+        assert_eq!(swiss.date_order(), DateOrder::Dmy);
+        assert_eq!(swiss.ocr_language(), Some("deu"));
+
+        let canada = Locale::parse("fr_CA").unwrap();
+        assert_eq!(canada.paper_size(), PaperSize::Letter);
+        assert_eq!(canada.ocr_language(), Some("fra"));
+
+        assert_eq!(Locale::parse("ja_JP").unwrap().date_order(), DateOrder::Ymd);
+    }
+
+    proptest! {
+        /// Ensure that parsing arbitrary locales never panics.
+        #[test]
+        fn parse_arbitrary(locale in "\\PC*") {
+            Locale::parse(&locale);
+        }
+
+        /// Ensure that valid locales keep their language and region.
+        #[test]
+        fn parse_valid(
+            language in "[a-z]{2,3}",
+            region in "[A-Z]{2}",
+            encoding in "(\\.UTF-8)?",
+        ) {
+            let locale = Locale::parse(&format!("{language}_{region}{encoding}")).unwrap();
+            prop_assert_eq!(locale.language, language);
+            prop_assert_eq!(locale.region, Some(region));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{prompt, ui};
+
+/// Locks older than this are considered stale (e.g. left behind by a crashed
+/// process) and are removed.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Information about the process holding a scanner lock
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    /// Hostname of the machine holding the lock
+    pub host: String,
+    /// Process ID of the process holding the lock
+    pub pid: u32,
+    /// Local time at which the lock was acquired
+    pub since: String,
+}
+
+impl LockHolder {
+    /// The current process
+    pub fn current() -> Self {
+        Self {
+            host: hostname(),
+            pid: std::process::id(),
+            since: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+
+    /// Whether the process is still running
+    ///
+    /// Processes on other machines can't be checked and are assumed to be
+    /// running.
+    pub fn is_running(&self) -> bool {
+        if self.host != hostname() {
+            return true;
+        }
+        if Path::new("/proc/self").exists() {
+            return Path::new("/proc").join(self.pid.to_string()).exists();
+        }
+        Command::new("kill")
+            .args(["-0", &self.pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+/// A lock on a scanner that is shared between multiple machines, implemented
+/// as a lockfile (e.g. on a network share).
+///
+/// The lock is released when this value is dropped.
+#[derive(Debug)]
+pub struct ScannerLock {
+    path: PathBuf,
+}
+
+/// Result of an attempt to acquire a scanner lock
+#[derive(Debug)]
+pub enum LockAttempt {
+    Acquired(ScannerLock),
+    Held(Option<LockHolder>),
+}
+
+impl ScannerLock {
+    /// Try to acquire the lock once, without waiting
+    ///
+    /// Stale locks are removed.
+    pub fn try_acquire(path: &Path) -> Result<LockAttempt> {
+        Self::try_acquire_with_timeout(path, STALE_AFTER)
+    }
+
+    fn try_acquire_with_timeout(path: &Path, stale_after: Duration) -> Result<LockAttempt> {
+        if is_stale(path, stale_after) {
+            warn!("Removing stale scanner lock {:?}", path);
+            fs::remove_file(path).context("Failed to remove stale scanner lock")?;
+        }
+
+        // Creating the file fails atomically if it already exists
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let holder = toml::to_string(&LockHolder::current())?;
+                file.write_all(holder.as_bytes())
+                    .context("Failed to write scanner lock")?;
+                file.sync_all()?;
+                debug!("Acquired scanner lock {:?}", path);
+                Ok(LockAttempt::Acquired(ScannerLock {
+                    path: path.to_path_buf(),
+                }))
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(path)
+                    .ok()
+                    .and_then(|content| toml::from_str(&content).ok());
+                Ok(LockAttempt::Held(holder))
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to create scanner lock {:?}", path)),
+        }
+    }
+
+    /// Acquire the lock, asking the user whether to wait if it is held by
+    /// someone else.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut spinner: Option<indicatif::ProgressBar> = None;
+        loop {
+            match Self::try_acquire(path)? {
+                LockAttempt::Acquired(lock) => {
+                    if let Some(spinner) = spinner {
+                        spinner.finish_with_message(ui::success("Scanner is available"));
+                    }
+                    return Ok(lock);
+                }
+                LockAttempt::Held(holder) if spinner.is_none() => {
+                    let holder = holder
+                        .map(|h| format!("{} (pid {}) since {}", h.host, h.pid, h.since))
+                        .unwrap_or_else(|| "another process".into());
+                    let wait = prompt::Confirm::new(&format!(
+                        "Scanner is in use by {holder}. Wait until it is available?"
+                    ))
+                    .with_default(true)
+                    .prompt()?;
+                    if !wait {
+                        return Err(anyhow!("Scanner is in use by {holder}"));
+                    }
+                    let new_spinner = ui::spinner("Waiting for scanner to become available…");
+                    new_spinner.enable_steady_tick(Duration::from_millis(100));
+                    spinner = Some(new_spinner);
+                }
+                LockAttempt::Held(_) => std::thread::sleep(Duration::from_secs(2)),
+            }
+        }
+    }
+}
+
+impl Drop for ScannerLock {
+    fn drop(&mut self) {
+        debug!("Releasing scanner lock {:?}", self.path);
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to release scanner lock {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Whether the lockfile exists and is older than `stale_after`
+fn is_stale(path: &Path, stale_after: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > stale_after)
+}
+
+/// Determine the hostname of this machine
+fn hostname() -> String {
+    Command::new("hostname")
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown host".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that a lock can only be held once, and is released on drop.
+    #[test]
+    fn acquire_and_release() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scanner.lock");
+
+        let lock = match ScannerLock::try_acquire(&path).unwrap() {
+            LockAttempt::Acquired(lock) => lock,
+            LockAttempt::Held(_) => panic!("Lock should be free"),
+        };
+        assert!(path.exists());
+
+        match ScannerLock::try_acquire(&path).unwrap() {
+            LockAttempt::Acquired(_) => panic!("Lock should be held"),
+            LockAttempt::Held(holder) => {
+                let holder = holder.expect("Holder should be readable");
+                assert_eq!(holder.pid, std::process::id());
+            }
+        }
+
+        drop(lock);
+        assert!(!path.exists());
+        assert!(matches!(
+            ScannerLock::try_acquire(&path).unwrap(),
+            LockAttempt::Acquired(_)
+        ));
+    }
+
+    /// Ensure that stale locks are removed.
+    #[test]
+    fn stale_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scanner.lock");
+        fs::write(&path, "garbage").unwrap();
+
+        assert!(matches!(
+            ScannerLock::try_acquire(&path).unwrap(),
+            LockAttempt::Held(None)
+        ));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(
+            ScannerLock::try_acquire_with_timeout(&path, Duration::from_millis(10)).unwrap(),
+            LockAttempt::Acquired(_)
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Running ImageMagick, and handling its errors
+//!
+//! ImageMagick 7 is called as `magick`. Distributions that still ship
+//! ImageMagick 6 only provide `convert` (or only the versioned binaries that
+//! it links to), which is used instead. The options used by Arkivisto are the
+//! same in both versions.
+//!
+//! Many distributions ship an ImageMagick security policy that forbids
+//! reading and writing PDFs (and other Ghostscript formats). These failures
+//! are detected, so that the user gets instructions instead of a generic
+//! error.
+
+use std::{
+    env,
+    ffi::OsStr,
+    process::{Command, Output},
+    sync::OnceLock,
+};
+
+use anyhow::{Error, anyhow};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::ResourceLimits,
+    limits,
+    tools::{self, Version},
+};
+
+/// Binaries of ImageMagick, in the order of preference: ImageMagick 7, then
+/// ImageMagick 6 (Debian and Ubuntu install `convert-im6.q16` or
+/// `convert.im6`, and `convert` only as an alternative that links to them)
+const PROGRAMS: &[&str] = &["magick", "convert", "convert-im6.q16", "convert.im6"];
+
+/// The installed ImageMagick, detected on first use
+static IMAGEMAGICK: OnceLock<Option<ImageMagick>> = OnceLock::new();
+
+/// An installed ImageMagick
+#[derive(Debug)]
+pub struct ImageMagick {
+    /// Binary to run (`magick` or `convert`)
+    pub program: &'static str,
+    /// Version, if it could be determined
+    pub version: Option<Version>,
+}
+
+/// Whether `program -version` reports ImageMagick, and its version
+///
+/// Other tools named `convert` exist (e.g. on Windows), so the output is
+/// checked.
+fn probe(program: &'static str) -> Option<ImageMagick> {
+    probe_in(program, &env::var_os("PATH")?)
+}
+
+/// Like [`probe`], but search `program` in the directories of `path` (in the
+/// format of `PATH`)
+fn probe_in(program: &'static str, path: &OsStr) -> Option<ImageMagick> {
+    let binary = tools::find_in(program, path)?;
+    let output = Command::new(binary).arg("-version").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.contains("ImageMagick") {
+        debug!("`{program}` is not ImageMagick");
+        return None;
+    }
+    Some(ImageMagick {
+        program,
+        version: Version::parse(&stdout),
+    })
+}
+
+/// Find the ImageMagick binary (see [`PROGRAMS`])
+///
+/// Versions older than the oldest supported one are reported.
+fn detect() -> Option<ImageMagick> {
+    let Some(imagemagick) = PROGRAMS.iter().find_map(|program| probe(program)) else {
+        info!("ImageMagick not found (tried {})", PROGRAMS.join(", "));
+        return None;
+    };
+    match imagemagick.version {
+        Some(version) => {
+            info!("Using ImageMagick {version} (`{}`)", imagemagick.program);
+            tools::check_version("ImageMagick", version);
+        }
+        None => info!(
+            "Using ImageMagick of unknown version (`{}`)",
+            imagemagick.program
+        ),
+    }
+    Some(imagemagick)
+}
+
+/// The installed ImageMagick
+pub fn installed() -> Option<&'static ImageMagick> {
+    IMAGEMAGICK.get_or_init(detect).as_ref()
+}
+
+/// Whether ImageMagick is installed
+pub fn is_installed() -> bool {
+    installed().is_some()
+}
+
+/// Binary of the installed ImageMagick (`magick` if it is not installed, for
+/// the error messages)
+pub fn program() -> &'static str {
+    installed().map_or("magick", |imagemagick| imagemagick.program)
+}
+
+/// Create an ImageMagick command
+pub fn command() -> Command {
+    Command::new(program())
+}
+
+/// Create an ImageMagick command, wrapped according to the resource limits
+/// (see [`limits::limited_command`])
+pub fn limited_command(limits: &ResourceLimits) -> Command {
+    limits::limited_command(program(), limits)
+}
+
+/// Whether the stderr of ImageMagick reports an operation that its security
+/// policy forbids
+pub fn is_policy_error(stderr: &str) -> bool {
+    stderr.contains("not allowed by the security policy") || stderr.contains("not authorized")
+}
+
+/// The coder (e.g. "PDF") that the security policy forbids, if ImageMagick
+/// names it (ImageMagick 7 does, ImageMagick 6 only names the file)
+fn blocked_coder(stderr: &str) -> Option<&str> {
+    let (_, rest) = stderr.split_once("security policy `")?;
+    let (coder, _) = rest.split_once('\'')?;
+    (!coder.is_empty()).then_some(coder)
+}
+
+/// Instructions for allowing a coder that the security policy forbids
+pub fn policy_instructions(stderr: &str) -> String {
+    let coder = blocked_coder(stderr).unwrap_or("PDF");
+    format!(
+        "ImageMagick's security policy does not allow {coder} files. \
+         Run `{} -list policy` to find the policy file, and change the line \
+         `<policy domain=\"coder\" rights=\"none\" pattern=\"{coder}\" />` \
+         to `rights=\"read|write\"`",
+        program()
+    )
+}
+
+/// Log the stderr of a failed ImageMagick command, return an error for it
+///
+/// Errors caused by the security policy explain how to change it.
+pub fn failure(output: &Output) -> Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    warn!(
+        "{} failed with status {}. Stderr: {}",
+        program(),
+        output.status.code().unwrap_or(-1),
+        stderr,
+    );
+    if is_policy_error(&stderr) {
+        anyhow!(policy_instructions(&stderr))
+    } else {
+        anyhow!("Failed to run `{}` command", program())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    use tempfile::TempDir;
+
+    /// Create an executable script `name` in `dir` that prints `output`
+    fn fake_binary(dir: &Path, name: &str, output: &str) {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\necho '{output}'\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Ensure that ImageMagick is found in `PATH` with its version, and that
+    /// other tools with the same name are ignored.
+    #[test]
+    fn probe_binaries() {
+        let dir = TempDir::new().unwrap();
+        fake_binary(
+            dir.path(),
+            "magick",
+            "Version: ImageMagick 7.1.1-29 Q16-HDRI x86_64 22086",
+        );
+        fake_binary(dir.path(), "convert", "Converts FAT volumes to NTFS");
+        let path = dir.path().as_os_str();
+
+        let imagemagick = probe_in("magick", path).unwrap();
+        assert_eq!(imagemagick.program, "magick");
+        assert_eq!(imagemagick.version, Some(Version::new(7, 1, 1)));
+        assert!(probe_in("convert", path).is_none());
+        assert!(probe_in("convert.im6", path).is_none());
+    }
+
+    /// Ensure that policy errors of ImageMagick 6 and 7 are detected, and
+    /// that the instructions name the blocked coder.
+    #[test]
+    fn policy_errors() {
+        let im7 = "magick: attempt to perform an operation not allowed by the security \
+                   policy `PDF' @ error/constitute.c/IsCoderAuthorized/1454.";
+        let im6 = "convert-im6.q16: not authorized `_combined.pdf' @ \
+                   error/constitute.c/WriteImage/1037.";
+        assert!(is_policy_error(im7));
+        assert!(is_policy_error(im6));
+        assert!(!is_policy_error("magick: unable to open image `0001.tif'"));
+
+        assert_eq!(blocked_coder(im7), Some("PDF"));
+        assert_eq!(blocked_coder(im6), None);
+        let instructions =
+            policy_instructions("not allowed by the security policy `PS' @ error/constitute.c");
+        assert!(instructions.contains("pattern=\"PS\""), "{instructions}");
+        assert!(policy_instructions(im6).contains("pattern=\"PDF\""));
+    }
+}
@@ -1,14 +1,75 @@
-use anyhow::{Context, Result};
+use std::{fs, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result, bail, ensure};
 use app_dirs::AppInfo;
 use clap::Parser;
-use tracing::{debug, level_filters::LevelFilter};
+use tracing::{debug, level_filters::LevelFilter, warn};
 use tracing_subscriber::{filter::Targets, prelude::*};
 
+mod archive;
 mod args;
+mod audit;
+mod bulk;
+mod calibration;
+mod clipboard;
+mod combine;
+mod compression;
 mod config;
+mod convert_archive;
+mod date_detect;
+mod due;
+mod estimate;
+mod events;
+mod extract;
 mod fs_utils;
+mod fsck;
+mod geometry;
+mod history;
+mod hocr;
+mod import;
+mod labels;
+mod limits;
+mod links;
+mod locale;
+mod lock;
+mod magick;
+mod manifest;
+mod naming;
+mod normalize;
+mod notes;
+mod ocr;
+mod paperless;
+mod pdf;
+mod power;
 mod process;
+mod prompt;
+mod quality;
+mod queue;
+mod re_ocr;
+mod receipts;
+mod references;
+mod reprocess;
+mod review;
+mod sandbox;
+mod sane;
 mod scan;
+mod scheduler;
+mod search;
+mod setup;
+mod share;
+mod split;
+mod statements;
+mod streaks;
+mod tags;
+mod tiff_utils;
+mod timings;
+mod tools;
+mod trash;
+mod troubleshoot;
+mod ui;
+mod verify;
+mod views;
+mod virtual_scanner;
 
 pub const APP_INFO: AppInfo = AppInfo {
     name: "arkivisto",
@@ -27,31 +88,908 @@ fn initialize_tracing(level_filter: LevelFilter) -> Result<()> {
     Ok(())
 }
 
+fn trash_command(action: &args::TrashAction, config: &config::Config) -> Result<()> {
+    let timezone = config.scan.timezone;
+    match action {
+        args::TrashAction::List => {
+            let trashed = trash::list(&config.outdir)?;
+            if trashed.is_empty() {
+                println!("The trash is empty");
+            }
+            for trashed in trashed {
+                println!("{trashed}");
+            }
+        }
+        args::TrashAction::Restore { name } => {
+            let path = trash::restore(
+                &config.outdir,
+                name,
+                config.archive.notes_dir.as_deref(),
+                timezone,
+            )?;
+            println!("{}", ui::success(format!("Restored {}", path.display())));
+        }
+        args::TrashAction::Purge { all } => {
+            let days = (!all).then_some(config.archive.trash_days);
+            let purged = trash::purge(&config.outdir, days, timezone)?;
+            println!("Purged {} document(s)", purged.len());
+        }
+    }
+    Ok(())
+}
+
+fn queue_command(action: &args::QueueAction) -> Result<()> {
+    let scans_dir = scan::scans_dir()?;
+    match action {
+        args::QueueAction::Pause => {
+            queue::set_paused(&scans_dir, true)?;
+            events::emit(&events::Event::QueuePaused);
+            println!("Processing paused");
+        }
+        args::QueueAction::Resume => {
+            queue::set_paused(&scans_dir, false)?;
+            events::emit(&events::Event::QueueResumed);
+            println!("Processing resumed");
+        }
+        args::QueueAction::Status => {
+            let paused = queue::is_paused(&scans_dir);
+            let state = if paused { "paused" } else { "running" };
+            let status = queue::status(&scans_dir)?;
+            let needs_review = manifest::find_documents(&scans_dir, |manifest| {
+                manifest.state == manifest::DocumentState::NeedsReview
+            })?;
+            events::emit(&events::Event::QueueStatus {
+                paused,
+                queued: status.queued,
+                in_flight: status.in_flight,
+                failed: status.failed.len(),
+                needs_review: needs_review.len(),
+            });
+            println!(
+                "Processing is {state}, {} queued, {} unfinished and {} failed scan(s), {} document(s) need review",
+                status.queued,
+                status.in_flight,
+                status.failed.len(),
+                needs_review.len()
+            );
+            for (directory, error) in status.failed {
+                println!(
+                    "{}",
+                    ui::warning(format!("{}: {error}", directory.display()))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Maximal number of search results offered by `open`
+const OPEN_CHOICES: usize = 20;
+
+/// Open the archived document that matches the query, asking which one if
+/// several documents match
+fn open_command(query: &[String], config: &config::Config) -> Result<()> {
+    let mut hits = search::search(&config.outdir, &query.join(" "))?;
+    ensure!(
+        !hits.is_empty(),
+        "No documents found (run `arkivisto index` to update the search index)"
+    );
+    hits.truncate(OPEN_CHOICES);
+    let path = if hits.len() == 1 {
+        hits.remove(0).path
+    } else {
+        let labels: Vec<String> = hits
+            .iter()
+            .map(|hit| {
+                let path = fs_utils::relative_key(&config.outdir, &hit.path);
+                match &hit.snippet {
+                    Some(snippet) => format!("{path} ({snippet})"),
+                    None => path,
+                }
+            })
+            .collect();
+        let index =
+            prompt::Select::new("Which document do you want to open?", labels).prompt_index()?;
+        hits.swap_remove(index).path
+    };
+    println!("Opening {}", path.display());
+    verify::open_file(&path)
+}
+
+/// Process the selected (or all) unprocessed scan directories
+fn process_command(
+    all: bool,
+    from_files: Option<&[PathBuf]>,
+    config: &config::Config,
+    options: &process::ProcessOptions,
+    timings: &mut timings::Timings,
+) -> Result<()> {
+    let scans_dir = scan::scans_dir()?;
+    if let Some(files) = from_files {
+        let document_dir = import::import_files(files, config)?;
+        println!("Imported files to {:?}", document_dir);
+        return process_directories(vec![document_dir], config, options, timings);
+    }
+    let unprocessed = queue::find_unprocessed(&scans_dir)?;
+    if unprocessed.is_empty() {
+        println!("No unprocessed scans found");
+        return Ok(());
+    }
+    let selected = if all {
+        unprocessed
+    } else {
+        let names: Vec<String> = unprocessed
+            .iter()
+            .map(|directory| {
+                directory
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        prompt::MultiSelect::new("Which scans do you want to process?", names)
+            .with_help_message("Press space to select, enter to start processing")
+            .prompt_indices()?
+            .into_iter()
+            .map(|index| unprocessed[index].clone())
+            .collect()
+    };
+    process_directories(selected, config, options, timings)
+}
+
+/// Process the document directories one after the other, as tracked by the
+/// processing queue
+fn process_directories(
+    selected: Vec<PathBuf>,
+    config: &config::Config,
+    options: &process::ProcessOptions,
+    timings: &mut timings::Timings,
+) -> Result<()> {
+    let scans_dir = scan::scans_dir()?;
+    let mut queue = queue::ProcessingQueue::load(&scans_dir)?;
+    let mut processed = 0;
+    for directory in selected {
+        queue::wait_while_paused(&scans_dir);
+        queue.start(directory.clone());
+        if let Err(e) = process::process_document(&directory, config, options, timings) {
+            warn!("Failed to post-process {:?}: {:#}", directory, e);
+            queue.fail(&directory, &e);
+            continue;
+        }
+        queue.finish(&directory);
+        processed += 1;
+        troubleshoot::troubleshoot_ocr(&directory, config, options, None, timings)?;
+        if config.processing.confirm_final {
+            verify::confirm_final_pdf(&directory, config)?;
+        }
+    }
+    println!("Processed {processed} document(s)");
+    Ok(())
+}
+
 fn main() -> Result<()> {
     // Parse args
     let args = args::Args::try_parse().context("Failed to parse command line arguments")?;
 
     // Initialize tracing
     initialize_tracing(args.log_level.to_filter())?;
+    events::init(args.progress);
+    if let Some(answers) = &args.answers {
+        prompt::init(Box::new(prompt::ScriptedPrompter::from_file(answers)?));
+    } else if args.plain_prompts {
+        prompt::init(Box::new(prompt::PlainPrompter::stdio()));
+    }
+
+    // Handle commands that don't need a scanner
+    let mode = args.mode.clone().unwrap_or_default();
+    if let args::Mode::Queue { action } = mode {
+        return queue_command(&action);
+    }
+    if let args::Mode::NamePending = mode {
+        return bulk::name_pending(&scan::scans_dir()?);
+    }
+    if let args::Mode::Review = mode {
+        return review::review(&scan::scans_dir()?);
+    }
+    if let args::Mode::InitConfig = mode {
+        let path = setup::init_config()?;
+        println!(
+            "{}",
+            ui::success(format!("Wrote config to {}", path.display()))
+        );
+        return Ok(());
+    }
+    if let args::Mode::ManageScanners = mode {
+        return setup::manage_scanners();
+    }
+
+    // Extract pages of a document
+    if let args::Mode::Extract {
+        document,
+        pages,
+        output,
+        redact,
+    } = &mode
+    {
+        let output = extract::extract(document, pages, output.as_deref(), redact)?;
+        println!("Extracted pages {pages} to {}", output.display());
+        return Ok(());
+    }
+
+    // Debug the statement parser
+    if let args::Mode::ParseStatement { file, locale } = &mode {
+        let date_order = match locale {
+            Some(locale) => locale::Locale::parse(locale)
+                .with_context(|| format!("Invalid locale \"{locale}\""))?
+                .date_order(),
+            None => config::Config::load()
+                .context("Failed to load config")?
+                .date_order(),
+        };
+        let text = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        match statements::extract_statement(&text, date_order) {
+            Some(transactions) => print!("{}", statements::to_csv(&transactions)),
+            None => println!("No statement found in {}", file.display()),
+        }
+        return Ok(());
+    }
 
     // Load config
     let config = config::Config::load().context("Failed to load config")?;
+    scheduler::init(&config.jobs);
+    ui::init(&config.ui);
+    let profile = match &args.profile {
+        Some(name) => config.profile(name)?.clone(),
+        None => config::Profile::default(),
+    };
+    let process_options = process::ProcessOptions {
+        skip_ocr: args.skip_ocr || profile.skip_ocr,
+        skip_contrast: profile.skip_contrast,
+        skip_deskew: profile.skip_deskew,
+        email_copy: config.processing.email_copy || profile.email_copy,
+        ocr_languages: None,
+    };
+
+    // Import photos of documents
+    if let args::Mode::Import { directory } = &mode {
+        let folder = directory
+            .clone()
+            .or_else(|| config.import.hot_folder.clone())
+            .context("No directory given and no hot folder configured (`[import] hot_folder`)")?;
+        let document_dirs = import::import_photos(&folder, &config)?;
+        if document_dirs.is_empty() {
+            println!("No photos found in {}", folder.display());
+        }
+        let mut queue = queue::ProcessingQueue::load(&scan::scans_dir()?)?;
+        for document_dir in document_dirs {
+            println!("Imported document to {:?}", document_dir);
+            queue.push(document_dir, queue::Priority::Backlog);
+        }
+        return Ok(());
+    }
+
+    // Process earlier scans
+    if let args::Mode::Process { all, from_files } = &mode {
+        let mut timings = timings::Timings::default();
+        process_command(
+            *all,
+            from_files.as_deref(),
+            &config,
+            &process_options,
+            &mut timings,
+        )?;
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
+
+    // Archive processed documents
+    if let args::Mode::Archive = mode {
+        let archived = archive::archive(&scan::scans_dir()?, &config)?;
+        println!("Archived {} document(s)", archived.len());
+        return Ok(());
+    }
+
+    // Search the archived documents
+    if let args::Mode::Search { query, limit, copy } = &mode {
+        let hits = search::search(&config.outdir, &query.join(" "))?;
+        if hits.is_empty() {
+            println!("No documents found (run `arkivisto index` to update the search index)");
+        }
+        for hit in hits.iter().take(*limit) {
+            println!("{}", hit.path.display());
+            if let Some(snippet) = &hit.snippet {
+                println!("  {snippet}");
+            }
+            if let Some(location) = &hit.location {
+                println!("  Original: {location}");
+            }
+            for (linked, relation) in links::linked(&config.outdir, &hit.path)? {
+                match relation {
+                    Some(relation) => println!("  ↔ {} ({relation})", linked.display()),
+                    None => println!("  ↔ {}", linked.display()),
+                }
+            }
+        }
+        if hits.len() > *limit {
+            println!("… and {} more (see `--limit`)", hits.len() - limit);
+        }
+        if let Some(hit) = hits.first().filter(|_| *copy) {
+            clipboard::copy_path(&hit.path, config.archive.clipboard.unwrap_or_default());
+        }
+        return Ok(());
+    }
+
+    // Open an archived document
+    if let args::Mode::Open { query } = &mode {
+        return open_command(query, &config);
+    }
+
+    // Combine archived documents
+    if let args::Mode::Combine { query, output } = &mode {
+        let hits = search::search(&config.outdir, &query.join(" "))?;
+        ensure!(
+            !hits.is_empty(),
+            "No documents found (run `arkivisto index` to update the search index)"
+        );
+        let documents = search::documents(&config.outdir)?
+            .into_iter()
+            .filter(|document| hits.iter().any(|hit| hit.path == document.path))
+            .collect();
+        let documents = combine::select_documents(documents)?;
+        combine::combine(&documents, output)?;
+        println!(
+            "Combined {} document(s) into {}",
+            documents.len(),
+            output.display()
+        );
+        return Ok(());
+    }
+
+    // Split an archived document
+    if let args::Mode::Split { document, ranges } = &mode {
+        let archived = split::split(document, ranges, &config)?;
+        if archived.is_empty() {
+            println!("The document was not split");
+        }
+        for path in archived {
+            println!("{}", ui::success(format!("Archived to {}", path.display())));
+        }
+        return Ok(());
+    }
+
+    // Process an archived document again
+    if let args::Mode::Reprocess { document } = &mode {
+        let mut timings = timings::Timings::default();
+        let version = reprocess::reprocess(
+            document,
+            &scan::scans_dir()?,
+            &config,
+            &process_options,
+            &mut timings,
+        )?;
+        match version {
+            Some(version) => println!(
+                "{}",
+                ui::success(format!(
+                    "Replaced {}, the previous version is {}",
+                    document.display(),
+                    version.display()
+                ))
+            ),
+            None => println!("The archived document was kept"),
+        }
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
+
+    // List documents that are due
+    if let args::Mode::Due { days } = &mode {
+        search::update_index(&config.outdir)?;
+        let today = config.scan.timezone.now().date_naive();
+        let documents = due::upcoming(search::documents(&config.outdir)?, today, *days);
+        if documents.is_empty() {
+            println!("No documents are due within {days} days");
+        }
+        for document in documents {
+            let Some(date) = document.due else {
+                continue;
+            };
+            let line = format!("{date}  {}", document.path.display());
+            if date < today {
+                println!("{}", ui::warning(format!("{line} (overdue)")));
+            } else {
+                println!("{line}");
+            }
+        }
+        return Ok(());
+    }
+
+    // Run OCR again over archived documents
+    if let args::Mode::ReOcr {
+        documents,
+        all,
+        restart,
+        pause,
+    } = &mode
+    {
+        search::update_index(&config.outdir)?;
+        if *restart {
+            re_ocr::restart(&config.outdir)?;
+        }
+        let changed = re_ocr::re_ocr(
+            documents,
+            *all,
+            Duration::from_secs(*pause),
+            &scan::scans_dir()?,
+            &config,
+        )?;
+        println!("Replaced the text of {changed} document(s)");
+        return Ok(());
+    }
+
+    // Link archived documents
+    if let args::Mode::Link {
+        document,
+        other,
+        relation,
+        remove,
+    } = &mode
+    {
+        let outdir = fs::canonicalize(&config.outdir)
+            .with_context(|| format!("Archive directory {:?} does not exist", config.outdir))?;
+        let document = links::archived_pdf(&outdir, document)?;
+        let other = links::archived_pdf(&outdir, other)?;
+        if *remove {
+            if links::unlink(&outdir, &document, &other)? {
+                println!("Removed the link");
+            } else {
+                println!("The documents are not linked");
+            }
+        } else {
+            links::link(&outdir, &document, &other, relation.as_deref())?;
+            println!(
+                "{}",
+                ui::success(format!(
+                    "Linked {} and {}",
+                    document.display(),
+                    other.display()
+                ))
+            );
+        }
+        return Ok(());
+    }
+
+    // Move a document into the trash
+    if let args::Mode::Delete { document } = &mode {
+        let timezone = config.scan.timezone;
+        let name = trash::delete(
+            document,
+            &config.outdir,
+            config.archive.notes_dir.as_deref(),
+            timezone,
+        )?;
+        println!(
+            "{}",
+            ui::success(format!(
+                "Moved {} to the trash as {name}",
+                document.display()
+            ))
+        );
+        let purged = trash::purge(&config.outdir, Some(config.archive.trash_days), timezone)?;
+        if !purged.is_empty() {
+            println!(
+                "Purged {} document(s) deleted more than {} days ago",
+                purged.len(),
+                config.archive.trash_days
+            );
+        }
+        return Ok(());
+    }
+    if let args::Mode::Trash { action } = &mode {
+        return trash_command(action, &config);
+    }
+
+    // Merge similar tags and correspondents
+    if let args::Mode::Tags {
+        action: args::TagsAction::Normalize,
+    } = mode
+    {
+        let changed = normalize::normalize(&scan::scans_dir()?, &config)?;
+        println!("Updated {} document(s)", changed.len());
+        return Ok(());
+    }
+
+    // Update the search index
+    if let args::Mode::Index = mode {
+        let update = search::update_index(&config.outdir)?;
+        println!(
+            "Indexed {} document(s): {} added or updated, {} removed",
+            update.documents, update.updated, update.removed
+        );
+        return Ok(());
+    }
+
+    // Check the indexes against the archive
+    if let args::Mode::Fsck { repair } = mode {
+        let report = fsck::check(&config.outdir)?;
+        for (problems, description) in [
+            (&report.orphans, "not indexed"),
+            (&report.ghosts, "indexed, but missing"),
+            (&report.mismatches, "changed since it was indexed"),
+        ] {
+            for key in problems {
+                println!("{}", ui::warning(format!("{key}: {description}")));
+            }
+        }
+        if report.is_empty() {
+            println!("{}", ui::success("The indexes match the archive"));
+        } else if repair {
+            fsck::repair(&config.outdir, &report)?;
+            println!(
+                "{}",
+                ui::success(format!("Repaired {} problem(s)", report.len()))
+            );
+        } else {
+            bail!(
+                "Found {} problem(s), repair them with `arkivisto fsck --repair`",
+                report.len()
+            );
+        }
+        return Ok(());
+    }
+
+    // Export the archive
+    if let args::Mode::Export {
+        target,
+        format,
+        tag,
+    } = &mode
+    {
+        search::update_index(&config.outdir)?;
+        let mut documents = search::documents(&config.outdir)?;
+        if let Some(tag) = tag {
+            documents.retain(|document| {
+                document
+                    .tags
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(tag))
+            });
+        }
+        let (exported, hint) = match format {
+            args::ExportFormat::Paperless => (
+                paperless::export(&config.outdir, &documents, target)?,
+                "import them with `document_importer`",
+            ),
+            args::ExportFormat::Ris => (
+                references::export_ris(&documents, target)?,
+                "import the file in your reference manager",
+            ),
+        };
+        println!(
+            "Exported {exported} document(s) to {} ({hint})",
+            target.display()
+        );
+        return Ok(());
+    }
+
+    // Push papers into Zotero
+    if let args::Mode::Zotero { tag } = &mode {
+        search::update_index(&config.outdir)?;
+        let mut documents = search::documents(&config.outdir)?;
+        documents.retain(|document| {
+            document
+                .tags
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(tag))
+        });
+        ensure!(!documents.is_empty(), "No documents are tagged {tag:?}");
+        let pushed = references::push_to_zotero(&documents, references::ZOTERO_ADDRESS)?;
+        println!(
+            "{}",
+            ui::success(format!("Pushed {pushed} document(s) to Zotero"))
+        );
+        return Ok(());
+    }
+
+    // Import a paperless-ngx export
+    if let args::Mode::ImportPaperless { source } = &mode {
+        let archived = paperless::import(source, &config)?;
+        println!("Imported {} document(s)", archived.len());
+        return Ok(());
+    }
+
+    // Rebuild the views of the archive
+    if let args::Mode::RebuildViews = mode {
+        let views_dir = config.archive.views_dir.as_deref().context(
+            "No views directory is configured (set `views_dir` in the `[archive]` section)",
+        )?;
+        search::update_index(&config.outdir)?;
+        let documents = search::documents(&config.outdir)?;
+        let update = views::rebuild(
+            &config.outdir,
+            views_dir,
+            &documents,
+            config.archive.view_links,
+        )?;
+        println!(
+            "Linked {} of {} document(s) in {} ({} link(s))",
+            update.documents,
+            documents.len(),
+            views_dir.display(),
+            update.links
+        );
+        return Ok(());
+    }
+
+    // List the changes to the archive
+    if let args::Mode::History { query } = &mode {
+        let events = audit::load(&config.outdir, query)?;
+        if events.is_empty() {
+            println!("No changes found");
+        }
+        for event in events {
+            println!("{event}");
+        }
+        return Ok(());
+    }
+
+    // Sum the totals of the archived receipts
+    if let args::Mode::Report { tag, from, to } = &mode {
+        search::update_index(&config.outdir)?;
+        let documents = search::documents(&config.outdir)?;
+        let report = receipts::report(&documents, tag, *from, *to);
+        println!("{report}");
+        for path in &report.without_total {
+            println!(
+                "{}",
+                ui::warning(format!("{}: no total found", path.display()))
+            );
+        }
+        return Ok(());
+    }
+
+    // Share a stamped copy of a document
+    if let args::Mode::Share {
+        document,
+        recipient,
+        output,
+    } = &mode
+    {
+        let output = share::share(document, recipient, output.as_deref(), &config)?;
+        println!("Created copy for {recipient} at {}", output.display());
+        return Ok(());
+    }
+
+    // Convert an old archive
+    if let args::Mode::ConvertArchive { source, group_by } = &mode {
+        let mut timings = timings::Timings::default();
+        let document_dirs = convert_archive::convert_archive(
+            source,
+            *group_by,
+            &config,
+            &process_options,
+            &mut timings,
+        )?;
+        println!("Converted {} document(s)", document_dirs.len());
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
+
+    // Calibrate a scanner
+    if let args::Mode::Calibrate {
+        scanner_id,
+        geometry,
+    } = &mode
+    {
+        let scanner = config
+            .scanners
+            .iter()
+            .find(|scanner| &scanner.id == scanner_id)
+            .with_context(|| format!("Scanner \"{scanner_id}\" is not configured"))?;
+        let scan_context = scan::ScanContext {
+            scanner,
+            fake_scan: args.fake_scan,
+            progress: None,
+            min_page_quality: None,
+            paper: config.paper_size(),
+            dir_format: &config.scan.dir_format,
+            dir_details: config.scan.dir_details,
+            timezone: config.scan.timezone,
+            detect_paper: false,
+            memory_budget: config.processing.memory_budget(),
+        };
+        if *geometry {
+            let geometry = calibration::calibrate_geometry(&scan_context)?;
+            println!(
+                "Add the following to the configuration of scanner {}:\n\n\
+                 [scanners.geometry]\n\
+                 left = {:.1}\n\
+                 top = {:.1}\n\
+                 extra_width = {:.1}\n\
+                 extra_height = {:.1}",
+                scanner.id,
+                geometry.left,
+                geometry.top,
+                geometry.extra_width,
+                geometry.extra_height
+            );
+            return Ok(());
+        }
+        let calibration = calibration::calibrate(&scan_context)?;
+        println!(
+            "Calibrated {}: black point {:.1}%, white point {:.1}%, gamma {:.2}",
+            scanner.id, calibration.black, calibration.white, calibration.gamma
+        );
+        return Ok(());
+    }
+
+    // Scan with multiple scanners concurrently
+    let mut timings = timings::Timings::default();
+    if let args::Mode::Scan { parallel: true } = mode {
+        let document_dirs = scan::scan_parallel(
+            &config.scanners,
+            args.fake_scan,
+            config.paper_size(),
+            &config.scan,
+            config.processing.memory_budget(),
+            &mut timings,
+        )?;
+        let mut queue = queue::ProcessingQueue::load(&scan::scans_dir()?)?;
+        for document_dir in &document_dirs {
+            queue.push(document_dir.clone(), queue::Priority::Backlog);
+        }
+        println!("Scanned {} document(s)", document_dirs.len());
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
 
     // Select scan device
-    let scanner = scan::select_scanner(&config.scanners)?;
+    let scanner = scan::select_scanner(&config.scanners, args.fake_scan)?;
     debug!("Selected scanner: {} ({})", scanner.id, scanner.device_name);
 
     // Create scan context
     let scan_context = scan::ScanContext {
         scanner: &scanner,
         fake_scan: args.fake_scan,
+        progress: None,
+        min_page_quality: config.scan.min_page_quality,
+        paper: config.paper_size(),
+        dir_format: &config.scan.dir_format,
+        dir_details: config.scan.dir_details,
+        timezone: config.scan.timezone,
+        detect_paper: config.scan.detect_paper_size,
+        memory_budget: config.processing.memory_budget(),
     };
 
-    // TODO: Handle mode
+    // Scan a document, or many documents in bulk mode
+    let document_dirs = if let args::Mode::Bulk = mode {
+        bulk::scan_bulk(&scan_context, &mut timings)?
+    } else {
+        vec![scan::scan_document(&scan_context, &mut timings)?]
+    };
+    if let args::Mode::Scan { .. } = mode {
+        let mut queue = queue::ProcessingQueue::load(&scan::scans_dir()?)?;
+        for document_dir in &document_dirs {
+            queue.push(document_dir.clone(), queue::Priority::Backlog);
+            println!("Scanned document to {:?}", document_dir);
+        }
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
+
+    // Queue the new documents with priority, followed by interrupted and
+    // earlier unprocessed scans
+    let scans_dir = scan::scans_dir()?;
+    let mut queue = queue::ProcessingQueue::load(&scans_dir)?;
+    let new_documents = document_dirs.len();
+    for document_dir in document_dirs {
+        queue.push(document_dir, queue::Priority::Recent);
+    }
+
+    // Defer processing if the system is on battery or busy. The documents stay
+    // queued in the scans directory and are picked up by the next run.
+    if let Some(reason) = power::defer_reason(&config.processing) {
+        println!(
+            "Deferring processing of {} document(s): {reason}",
+            queue.len()
+        );
+        if args.profile_timings {
+            println!("{timings}");
+        }
+        return Ok(());
+    }
+
+    // Process the new documents
+    for _ in 0..new_documents {
+        let Some(directory) = queue.pop() else {
+            break;
+        };
+        queue::wait_while_paused(&scans_dir);
+        if let Err(e) =
+            process::process_document(&directory, &config, &process_options, &mut timings)
+        {
+            queue.fail(&directory, &e);
+            return Err(e.context("Failed to post-process document"));
+        }
+        queue.finish(&directory);
+        troubleshoot::troubleshoot_ocr(
+            &directory,
+            &config,
+            &process_options,
+            Some(&scan_context),
+            &mut timings,
+        )?;
+        if config.processing.confirm_final && !verify::confirm_final_pdf(&directory, &config)? {
+            continue;
+        }
+
+        // Archive the document right away, after reviewing it if needed
+        if let args::Mode::Single = mode {
+            let manifest = manifest::Manifest::load(&directory)?;
+            if manifest.state == manifest::DocumentState::NeedsReview {
+                review::review_document(&directory, manifest, "New document")?;
+            }
+            let manifest = manifest::Manifest::load(&directory)?;
+            if manifest.state != manifest::DocumentState::Processed {
+                println!("The document was not archived, because it still needs review");
+            } else if let Some(path) = archive::archive_document(&directory, &config)? {
+                println!("{}", ui::success(format!("Archived to {}", path.display())));
+                if let Some(content) = config.archive.clipboard {
+                    clipboard::offer(&path, content)?;
+                }
+            }
+        }
+    }
+
+    // Optionally continue with the backlog
+    if !queue.is_empty() {
+        let process_backlog = prompt::Confirm::new(&format!(
+            "{} earlier scan(s) have not been processed yet. Process them now?",
+            queue.len()
+        ))
+        .with_default(false)
+        .prompt()?;
+        if process_backlog {
+            while let Some(directory) = queue.pop() {
+                queue::wait_while_paused(&scans_dir);
+                if let Err(e) =
+                    process::process_document(&directory, &config, &process_options, &mut timings)
+                {
+                    warn!("Failed to post-process {:?}: {:#}", directory, e);
+                    queue.fail(&directory, &e);
+                    continue;
+                }
+                queue.finish(&directory);
+                troubleshoot::troubleshoot_ocr(
+                    &directory,
+                    &config,
+                    &process_options,
+                    None,
+                    &mut timings,
+                )?;
+                if config.processing.confirm_final {
+                    verify::confirm_final_pdf(&directory, &config)?;
+                }
+            }
+        }
+    }
 
-    // Scan a document
-    let document_dir = scan::scan_document(&scan_context)?;
-    process::process_document(&document_dir).context("Failed to post-process document")?;
+    // Print timing breakdown
+    if args.profile_timings {
+        println!("{timings}");
+    }
 
     Ok(())
 }
@@ -0,0 +1,388 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{config::PaperSize, fs_utils, queue};
+
+/// Name of the manifest file in a document directory
+pub const MANIFEST: &str = "manifest.toml";
+
+/// Metadata about a scanned document, stored next to its pages
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Processing state of the document
+    #[serde(default)]
+    pub state: DocumentState,
+
+    /// Stable ID of the document (a ULID), which doesn't change when the
+    /// document is renamed or moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// ID of the scanner that scanned the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanner: Option<String>,
+
+    /// Time of the scan (RFC 3339, with timezone offset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanned_at: Option<String>,
+
+    /// Scan source that was used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ScanSource>,
+
+    /// Paper size of the pages, if it was detected and is the same for all
+    /// pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper: Option<PaperSize>,
+
+    /// Whether all pages are composed onto a single page (e.g. front and
+    /// back of an ID card)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub n_up: bool,
+
+    /// Title of the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// Sender or issuer of the document (e.g. a company)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correspondent: Option<String>,
+
+    /// Tags assigned when archiving the document
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Whether the document was parked to be named later (see the
+    /// `name-pending` command)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub needs_naming: bool,
+
+    /// Problems detected while scanning or processing, which should be
+    /// reviewed by the user
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+
+    /// Processing steps that were completed, so that they are skipped if
+    /// processing is interrupted and resumed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub completed_steps: Vec<PipelineStep>,
+
+    /// Result of the manual verification of the final PDF
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+
+    /// Whether the final PDF was already opened for the user, so that it isn't
+    /// opened again when archiving
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shown: bool,
+
+    /// Files created by processing (the final PDF and the optional copy for
+    /// emailing), relative to the document directory, or absolute once
+    /// archived
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<PathBuf>,
+
+    /// Additional OCRmyPDF arguments chosen for this document only (e.g.
+    /// `--force-ocr` for a faxed copy)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ocr_args: Vec<String>,
+
+    /// Parameters and durations of the scan, for estimating later scans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_stats: Option<ScanStats>,
+}
+
+/// Processing state of a document
+///
+/// Documents move from `scanned` to `processed`, or to `needs-review` if they
+/// lack metadata or problems were detected. Reviewed documents are
+/// `processed`, until they are `archived`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DocumentState {
+    /// Scanned, but not processed yet
+    #[default]
+    Scanned,
+    /// Processed and ready for archiving
+    Processed,
+    /// Processed, but awaiting metadata or review of warnings
+    NeedsReview,
+    /// Filed into the archive
+    Archived,
+}
+
+/// Source from which a document was scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanSource {
+    /// Automatic document feeder
+    Adf,
+    /// Flatbed
+    Flatbed,
+    /// Photo taken with a camera (e.g. of a phone)
+    Camera,
+}
+
+/// A step of the processing pipeline with intermediate results on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipelineStep {
+    /// Postprocessing of the individual pages
+    ProcessPages,
+    /// Composition of all pages onto a single page
+    ComposePages,
+    /// Combination of the pages into a multi-page TIFF
+    CombinePages,
+    /// Conversion of the combined TIFF to PDF
+    ConvertToPdf,
+}
+
+/// Parameters and durations of a scan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanStats {
+    /// Scan mode (e.g. `adf-duplex`)
+    pub mode: String,
+    /// Resolution in DPI
+    pub dpi: u32,
+    /// Number of scanned pages
+    pub pages: usize,
+    /// Time spent in `scanimage` in seconds (without the time between pages)
+    pub scan_secs: f32,
+    /// Duration of the processing in seconds, once processed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_secs: Option<f32>,
+}
+
+/// Manual verification of the final PDF by the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Verification {
+    /// Whether the user confirmed that the digital copy is complete and legible
+    pub confirmed: bool,
+    /// Time of the verification, in the configured timezone
+    pub at: String,
+}
+
+impl Manifest {
+    /// Load the manifest of a document directory
+    ///
+    /// If the directory does not contain a manifest yet, an empty manifest is
+    /// returned.
+    pub fn load(directory: &Path) -> Result<Self> {
+        let path = directory.join(MANIFEST);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs_utils::retry_stale(|| fs::read_to_string(&path))
+            .with_context(|| format!("Failed to read manifest {path:?}"))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse manifest {path:?}"))
+    }
+
+    /// Record a warning, unless it was already recorded
+    pub fn add_warning(&mut self, warning: impl Into<String>) {
+        let warning = warning.into();
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Update the state after the document was processed
+    pub fn mark_processed(&mut self) {
+        self.state = if self.needs_naming || !self.warnings.is_empty() {
+            DocumentState::NeedsReview
+        } else {
+            DocumentState::Processed
+        };
+    }
+
+    /// Time of the scan, if recorded
+    pub fn scan_time(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(self.scanned_at.as_deref()?).ok()
+    }
+
+    /// Write the manifest to a document directory
+    pub fn save(&self, directory: &Path) -> Result<()> {
+        let path = directory.join(MANIFEST);
+        let content = toml::to_string(self).context("Failed to serialize manifest")?;
+        fs_utils::write_synced(&path, content)
+            .with_context(|| format!("Failed to write manifest {path:?}"))
+    }
+}
+
+/// Find all document directories in `scans_dir` whose manifest matches the
+/// predicate
+///
+/// Staging directories and directories with unreadable manifests are skipped.
+/// The returned directories are sorted by scan time (oldest first). Documents
+/// without a recorded scan time come first, sorted by name.
+pub fn find_documents(
+    scans_dir: &Path,
+    predicate: impl Fn(&Manifest) -> bool,
+) -> Result<Vec<(PathBuf, Manifest)>> {
+    let mut documents = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(scans_dir))
+        .with_context(|| format!("Failed to read scans directory {:?}", scans_dir))?
+    {
+        let entry = entry?;
+        let is_staging_dir = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(queue::CURRENT_DIR));
+        if !entry.file_type()?.is_dir() || is_staging_dir {
+            continue;
+        }
+        let path = entry.path();
+        match Manifest::load(&path) {
+            Ok(manifest) if predicate(&manifest) => documents.push((path, manifest)),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping {:?}: {:#}", path, e),
+        }
+    }
+    documents.sort_by_key(|(path, manifest)| (manifest.scan_time(), path.clone()));
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that a missing manifest is treated as empty, and that a saved
+    /// manifest can be loaded again.
+    #[test]
+    fn roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(
+            Manifest::load(temp_dir.path()).unwrap(),
+            Manifest::default()
+        );
+
+        let manifest = Manifest {
+            id: Some("01JGF3V8Z0C9QK2X5N7R4T6WYB".into()),
+            scanner: Some("flatbed".into()),
+            scanned_at: Some("2025-01-01T12:00:00.000+01:00".into()),
+            source: Some(ScanSource::Flatbed),
+            paper: Some(PaperSize::Letter),
+            title: Some("Tax return 2024".into()),
+            correspondent: Some("Steueramt".into()),
+            tags: vec!["taxes".into()],
+            needs_naming: false,
+            n_up: false,
+            state: DocumentState::NeedsReview,
+            warnings: vec!["Low OCR confidence (42%)".into()],
+            completed_steps: vec![PipelineStep::ProcessPages],
+            verification: Some(Verification {
+                confirmed: true,
+                at: "2025-01-01 12:00:00".into(),
+            }),
+            shown: true,
+            outputs: vec![queue::FINAL_PDF.into(), queue::EMAIL_PDF.into()],
+            ocr_args: vec!["--force-ocr".into()],
+            scan_stats: Some(ScanStats {
+                mode: "flatbed".into(),
+                dpi: 300,
+                pages: 2,
+                scan_secs: 31.5,
+                process_secs: Some(12.0),
+            }),
+        };
+        manifest.save(temp_dir.path()).unwrap();
+        assert_eq!(Manifest::load(temp_dir.path()).unwrap(), manifest);
+    }
+
+    /// Ensure that documents with warnings or without a name need review after
+    /// processing.
+    #[test]
+    fn state_after_processing() {
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.state, DocumentState::Scanned);
+        manifest.mark_processed();
+        assert_eq!(manifest.state, DocumentState::Processed);
+
+        manifest.add_warning("Streaks detected");
+        manifest.add_warning("Streaks detected");
+        assert_eq!(manifest.warnings.len(), 1);
+        manifest.mark_processed();
+        assert_eq!(manifest.state, DocumentState::NeedsReview);
+
+        let mut manifest = Manifest {
+            needs_naming: true,
+            ..Default::default()
+        };
+        manifest.mark_processed();
+        assert_eq!(manifest.state, DocumentState::NeedsReview);
+    }
+
+    /// Ensure that documents are filtered by their manifest, and that staging
+    /// directories are ignored.
+    #[test]
+    fn find_by_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        for (name, state) in [
+            ("20250102-120000", DocumentState::NeedsReview),
+            ("20250101-120000", DocumentState::NeedsReview),
+            ("20250103-120000", DocumentState::Processed),
+            ("current-scanner", DocumentState::NeedsReview),
+        ] {
+            let dir = scans_dir.join(name);
+            fs::create_dir(&dir).unwrap();
+            let manifest = Manifest {
+                state,
+                ..Default::default()
+            };
+            manifest.save(&dir).unwrap();
+        }
+        fs::create_dir(scans_dir.join("20250104-120000")).unwrap();
+
+        let documents: Vec<_> = find_documents(scans_dir, |manifest| {
+            manifest.state == DocumentState::NeedsReview
+        })
+        .unwrap()
+        .into_iter()
+        .map(|(dir, _)| dir)
+        .collect();
+        assert_eq!(
+            documents,
+            vec![
+                scans_dir.join("20250101-120000"),
+                scans_dir.join("20250102-120000"),
+            ]
+        );
+    }
+
+    /// Ensure that documents are sorted by their scan time, regardless of
+    /// their directory names and timezones.
+    #[test]
+    fn sort_by_scan_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        for (name, scanned_at) in [
+            ("a", Some("2025-01-01T12:00:00+01:00")),
+            ("b", Some("2025-01-01T11:30:00Z")),
+            ("c", None),
+        ] {
+            let dir = scans_dir.join(name);
+            fs::create_dir(&dir).unwrap();
+            let manifest = Manifest {
+                scanned_at: scanned_at.map(Into::into),
+                ..Default::default()
+            };
+            manifest.save(&dir).unwrap();
+        }
+
+        let names: Vec<_> = find_documents(scans_dir, |_| true)
+            .unwrap()
+            .into_iter()
+            .map(|(dir, _)| dir.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["c", "a", "b"]);
+    }
+}
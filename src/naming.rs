@@ -0,0 +1,361 @@
+//! Names of archived documents, rendered from the templates in the config
+//!
+//! Templates contain placeholders like `{date}` or `{title}`, which are
+//! replaced with the (slugified) metadata of a document, e.g.
+//! `{date}_{correspondent}_{title}` results in
+//! `2025-06-01_muster-ag_invoice.pdf`.
+//!
+//! The layout of the archive directory is a template as well, with one
+//! subdirectory per `/`-separated component, e.g. `{year}/{month}`.
+
+use std::path::PathBuf;
+
+use anyhow::{Result, bail, ensure};
+use chrono::NaiveDate;
+
+/// Placeholders that can be used in templates
+pub const PLACEHOLDERS: &[&str] = &[
+    "date",
+    "year",
+    "month",
+    "day",
+    "title",
+    "correspondent",
+    "tags",
+];
+
+/// Characters that separate the fields in templates, which are dropped after
+/// empty fields
+const SEPARATORS: &[char] = &['_', '-', ' ', '.'];
+
+/// Maximum length of a slug in bytes, so that file names with several fields
+/// stay below the limit of most file systems
+const MAX_SLUG_BYTES: usize = 80;
+
+/// Maximum length of a file name in bytes (e.g. ext4, Btrfs and APFS)
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Metadata of a document, entered when archiving it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentInfo {
+    pub date: NaiveDate,
+    pub title: String,
+    /// Sender or issuer of the document (e.g. a company)
+    pub correspondent: Option<String>,
+    pub tags: Vec<String>,
+    /// Expiry or follow-up date (e.g. end of a contract, expiry of a warranty
+    /// or due date of a payment)
+    pub due: Option<NaiveDate>,
+    /// Where the paper original is kept (e.g. the label of a binder or box)
+    pub location: Option<String>,
+}
+
+impl DocumentInfo {
+    /// Value of a placeholder, slugified
+    fn field(&self, name: &str) -> String {
+        match name {
+            "date" => self.date.format("%Y-%m-%d").to_string(),
+            "year" => self.date.format("%Y").to_string(),
+            "month" => self.date.format("%m").to_string(),
+            "day" => self.date.format("%d").to_string(),
+            "title" => slug(&self.title),
+            "correspondent" => self.correspondent.as_deref().map(slug).unwrap_or_default(),
+            "tags" => slug(&self.tags.join(" ")),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A part of a template
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template into literal text and placeholders
+fn parse(template: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Literal(&rest[..start]));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in template {template:?}");
+        };
+        let name = &rest[start + 1..start + end];
+        ensure!(
+            PLACEHOLDERS.contains(&name),
+            "Unknown placeholder {{{name}}} in template {template:?} (supported: {})",
+            PLACEHOLDERS
+                .iter()
+                .map(|name| format!("{{{name}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        parts.push(Part::Placeholder(name));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest));
+    }
+    Ok(parts)
+}
+
+/// Ensure that a filename template is valid: It may only contain known
+/// placeholders, and no slashes
+pub fn validate_filename(template: &str) -> Result<()> {
+    parse(template)?;
+    ensure!(
+        !template.contains('/'),
+        "Filename template {template:?} must not contain slashes"
+    );
+    Ok(())
+}
+
+/// Ensure that a directory layout template is valid: It may only contain
+/// known placeholders, and must be relative without `.` or `..` components
+pub fn validate_layout(template: &str) -> Result<()> {
+    parse(template)?;
+    ensure!(
+        !template.starts_with('/'),
+        "Directory layout {template:?} must be relative to the archive directory"
+    );
+    ensure!(
+        !template.split('/').any(|part| part == "." || part == ".."),
+        "Directory layout {template:?} must not contain `.` or `..`"
+    );
+    Ok(())
+}
+
+/// Render a template with the metadata of a document
+///
+/// Separators after empty fields are dropped, as well as separators at the
+/// start and end.
+fn render(template: &str, info: &DocumentInfo) -> Result<String> {
+    let parts = parse(template)?;
+    let mut rendered = String::new();
+    let mut after_empty = false;
+    for part in parts {
+        match part {
+            Part::Literal(text) if after_empty => {
+                rendered.push_str(text.trim_start_matches(SEPARATORS));
+            }
+            Part::Literal(text) => rendered.push_str(text),
+            Part::Placeholder(name) => {
+                let value = info.field(name);
+                after_empty = value.is_empty();
+                rendered.push_str(&value);
+            }
+        }
+    }
+    Ok(rendered.trim_matches(SEPARATORS).to_string())
+}
+
+/// Filename of an archived document, rendered from the template (with or
+/// without `.pdf` extension)
+///
+/// If all fields are empty, the document is named after its date.
+pub fn filename(template: &str, info: &DocumentInfo) -> Result<String> {
+    let stem = render(template.strip_suffix(".pdf").unwrap_or(template), info)?;
+    let stem = truncate(&stem, MAX_FILENAME_BYTES - ".pdf".len()).trim_end_matches(SEPARATORS);
+    if stem.is_empty() {
+        Ok(format!("{}.pdf", info.field("date")))
+    } else {
+        Ok(format!("{stem}.pdf"))
+    }
+}
+
+/// Subdirectory of the archive directory for a document, rendered from the
+/// layout template (e.g. `2025/06` for `{year}/{month}`)
+///
+/// Components that are empty (e.g. a missing correspondent) are left out.
+pub fn directory(layout: &str, info: &DocumentInfo) -> Result<PathBuf> {
+    let mut directory = PathBuf::new();
+    for component in layout.split('/') {
+        let component = render(component, info)?;
+        if !component.is_empty() {
+            directory.push(component);
+        }
+    }
+    Ok(directory)
+}
+
+/// The longest prefix of `text` with at most `max_bytes`, cut at a character
+/// boundary
+fn truncate(text: &str, max_bytes: usize) -> &str {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Convert text (e.g. a title) to a part of a filename: Lowercased, and
+/// everything except letters and digits replaced with dashes
+///
+/// Long slugs are cut after [`MAX_SLUG_BYTES`].
+pub fn slug(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    truncate(&slug, MAX_SLUG_BYTES)
+        .trim_end_matches('-')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Component, Path};
+
+    use proptest::prelude::*;
+
+    fn info(title: &str, correspondent: Option<&str>, tags: &[&str]) -> DocumentInfo {
+        DocumentInfo {
+            date: NaiveDate::from_ymd_opt(2025, 6, 1).unwrap(),
+            title: title.into(),
+            correspondent: correspondent.map(String::from),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            due: None,
+            location: None,
+        }
+    }
+
+    /// Ensure that titles are lowercased, and that everything except letters
+    /// and digits (including non-ASCII letters) is replaced with dashes.
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Tax return 2024"), "tax-return-2024");
+        assert_eq!(
+            slug("Steuererklärung: Kanton/Bund!"),
+            "steuererklärung-kanton-bund"
+        );
+        assert_eq!(slug(" / "), "");
+    }
+
+    /// Ensure that all placeholders are replaced, and that separators after
+    /// empty fields are dropped.
+    #[test]
+    fn filenames() {
+        let template = "{date}_{correspondent}_{title}.pdf";
+        assert_eq!(
+            filename(template, &info("Invoice 42", Some("Muster AG"), &[])).unwrap(),
+            "2025-06-01_muster-ag_invoice-42.pdf"
+        );
+        assert_eq!(
+            filename(template, &info("Tax return 2024", None, &[])).unwrap(),
+            "2025-06-01_tax-return-2024.pdf"
+        );
+        assert_eq!(
+            filename(template, &info(" / ", None, &[])).unwrap(),
+            "2025-06-01.pdf"
+        );
+        assert_eq!(
+            filename(
+                "{correspondent} - {title} ({year}-{month}-{day}) {tags}",
+                &info("Policy", None, &["Insurance", "car"])
+            )
+            .unwrap(),
+            "policy (2025-06-01) insurance-car.pdf"
+        );
+        assert_eq!(
+            filename(
+                "{correspondent} - {title} ({date}) {tags}",
+                &info("Policy", Some("Muster AG"), &[])
+            )
+            .unwrap(),
+            "muster-ag - policy (2025-06-01).pdf"
+        );
+        assert_eq!(
+            filename("{correspondent}", &info("", None, &[])).unwrap(),
+            "2025-06-01.pdf"
+        );
+    }
+
+    /// Ensure that the layout is rendered per directory, and that empty
+    /// directories are left out.
+    #[test]
+    fn directories() {
+        let invoice = info("Invoice", Some("Muster AG"), &[]);
+        assert_eq!(
+            directory("{year}/{month}", &invoice).unwrap(),
+            PathBuf::from("2025/06")
+        );
+        assert_eq!(
+            directory("{correspondent}/{year}", &invoice).unwrap(),
+            PathBuf::from("muster-ag/2025")
+        );
+        assert_eq!(
+            directory("Letters/{correspondent}/{year}", &info("Note", None, &[])).unwrap(),
+            PathBuf::from("Letters/2025")
+        );
+        assert_eq!(directory("", &invoice).unwrap(), PathBuf::new());
+        assert!(directory("{year}/{week}", &invoice).is_err());
+
+        assert!(validate_layout("{year}/{month}").is_ok());
+        assert!(validate_layout("").is_ok());
+        assert!(validate_layout("/{year}").is_err());
+        assert!(validate_layout("{year}/../{month}").is_err());
+        assert!(validate_layout("{year}/{week}").is_err());
+    }
+
+    /// Ensure that unknown placeholders, unclosed placeholders and slashes
+    /// are rejected.
+    #[test]
+    fn validation() {
+        assert!(validate_filename("{date}_{correspondent}_{title}.pdf").is_ok());
+        assert!(validate_filename("{date}_{sender}").is_err());
+        assert!(validate_filename("{date}_{title").is_err());
+        assert!(validate_filename("{year}/{title}").is_err());
+        assert!(filename("{date}_{sender}", &info("Invoice", None, &[])).is_err());
+    }
+
+    /// Ensure that long titles are cut at a character boundary.
+    #[test]
+    fn long_slugs() {
+        let title = "Ü".repeat(100);
+        assert_eq!(slug(&title), "ü".repeat(40));
+        assert_eq!(slug(&"a b".repeat(50)).len(), 79);
+    }
+
+    proptest! {
+        /// Ensure that file names are a single non-empty path component within
+        /// the length limit, whatever the metadata.
+        #[test]
+        fn filename_arbitrary(
+            title in "\\PC*",
+            correspondent in proptest::option::of("\\PC*"),
+            tags in proptest::collection::vec("\\PC*", 0..5),
+        ) {
+            let info = DocumentInfo {
+                date: NaiveDate::from_ymd_opt(2025, 6, 1).unwrap(),
+                title,
+                correspondent,
+                tags,
+                due: None,
+                location: None,
+            };
+            for template in [
+                "{date}_{correspondent}_{title}.pdf",
+                "{title} {title} {title} {tags} {correspondent}",
+            ] {
+                let name = filename(template, &info).unwrap();
+                prop_assert!(name.len() <= MAX_FILENAME_BYTES, "{name}");
+                let components: Vec<Component> = Path::new(&name).components().collect();
+                prop_assert!(
+                    matches!(components.as_slice(), [Component::Normal(_)]),
+                    "{name}"
+                );
+                prop_assert_ne!(name.as_str(), ".pdf");
+            }
+        }
+    }
+}
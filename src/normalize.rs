@@ -0,0 +1,420 @@
+//! Merging of near-duplicate tags and correspondents
+//!
+//! Tags and correspondents are entered freely when archiving, so the archive
+//! collects variants of the same name over time (e.g. "Insurance",
+//! "insurance" and "Insurnace"). `arkivisto tags normalize` finds names that
+//! only differ in case or by a typo, asks which spelling to keep, and applies
+//! it everywhere: in the indexes, the PDF keywords, the names of the archived
+//! PDFs, the tag vocabulary, the history and the manifests in the scans cache.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tracing::{debug, warn};
+
+use crate::{
+    archive, audit,
+    config::Config,
+    history, links, manifest,
+    naming::DocumentInfo,
+    notes, prompt,
+    search::{self, Document},
+    tags,
+};
+
+/// Maximal number of typos in names of at least 4 and at least 8 characters
+const MAX_TYPOS: [(usize, usize); 2] = [(8, 2), (4, 1)];
+
+/// Number of edits (insertions, deletions, substitutions and transpositions
+/// of neighbouring characters) that turn `a` into `b`
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Whether two names probably mean the same, i.e. only differ in case or by
+/// a few typos (none in short names)
+fn similar(a: &str, b: &str) -> bool {
+    let (a, b) = (a.to_lowercase(), b.to_lowercase());
+    let len = a.chars().count().min(b.chars().count());
+    let max_typos = MAX_TYPOS
+        .iter()
+        .find(|(min_len, _)| len >= *min_len)
+        .map_or(0, |(_, typos)| *typos);
+    distance(&a, &b) <= max_typos
+}
+
+/// Names that probably mean the same, with the number of documents using
+/// them, most used first
+pub type Group = Vec<(String, usize)>;
+
+/// Find the groups of similar names among the names used by documents, with
+/// the number of documents using each
+///
+/// Names are grouped if they are similar to any name of the group.
+pub fn find_groups(counts: &BTreeMap<String, usize>) -> Vec<Group> {
+    let names: Vec<&String> = counts.keys().collect();
+    let mut group_of: Vec<usize> = (0..names.len()).collect();
+    for i in 0..names.len() {
+        for j in i + 1..names.len() {
+            if group_of[i] != group_of[j] && similar(names[i], names[j]) {
+                let (old, new) = (group_of[j], group_of[i]);
+                for group in &mut group_of {
+                    if *group == old {
+                        *group = new;
+                    }
+                }
+            }
+        }
+    }
+    let mut groups: BTreeMap<usize, Group> = BTreeMap::new();
+    for (name, group) in names.iter().zip(group_of) {
+        groups
+            .entry(group)
+            .or_default()
+            .push(((*name).clone(), counts[*name]));
+    }
+    groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+            group
+        })
+        .collect()
+}
+
+/// Merged tags and correspondents, by their old names
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Renames {
+    pub tags: BTreeMap<String, String>,
+    pub correspondents: BTreeMap<String, String>,
+}
+
+impl Renames {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.correspondents.is_empty()
+    }
+
+    /// The tags with the new names, without duplicates
+    fn rename_tags(&self, tags: &[String]) -> Vec<String> {
+        let mut renamed = Vec::new();
+        tags::merge(
+            &mut renamed,
+            tags.iter()
+                .map(|tag| self.tags.get(tag).unwrap_or(tag).as_str()),
+        );
+        renamed
+    }
+
+    /// The correspondent with the new name
+    fn rename_correspondent(&self, correspondent: Option<&String>) -> Option<String> {
+        correspondent.map(|name| self.correspondents.get(name).unwrap_or(name).clone())
+    }
+}
+
+/// Ask which name of a group to keep, return it, or `None` to keep the names
+/// separate
+fn ask_name(kind: &str, group: &Group) -> Result<Option<String>> {
+    let names: Vec<String> = group
+        .iter()
+        .map(|(name, _)| format!("\"{name}\""))
+        .collect();
+    let mut options: Vec<String> = group
+        .iter()
+        .map(|(name, count)| format!("Merge into \"{name}\" ({count} document(s))"))
+        .collect();
+    options.push("Keep them separate".into());
+    let message = format!("The {kind} {} look alike.", names.join(", "));
+    let index = prompt::Select::new(&message, options).prompt_index()?;
+    Ok(group.get(index).map(|(name, _)| name.clone()))
+}
+
+/// Ask which of the similar names of a kind to merge, add the renames to
+/// `renames`
+fn ask_renames(
+    kind: &str,
+    names: impl IntoIterator<Item = String>,
+    renames: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    for group in find_groups(&counts) {
+        if let Some(kept) = ask_name(kind, &group)? {
+            for (name, _) in group.into_iter().filter(|(name, _)| *name != kept) {
+                renames.insert(name, kept.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply the renames to the archived `documents`: update their metadata in
+/// the indexes and the PDFs, and rename the PDFs named after their
+/// correspondent or tags, return the paths of the changed documents
+///
+/// Documents without a known date and title (e.g. filed by hand) keep their
+/// names.
+pub fn apply(documents: &[Document], renames: &Renames, config: &Config) -> Result<Vec<PathBuf>> {
+    let outdir = &config.outdir;
+    let mut changed = Vec::new();
+    for document in documents {
+        let tags = renames.rename_tags(&document.tags);
+        let correspondent = renames.rename_correspondent(document.correspondent.as_ref());
+        if tags == document.tags && correspondent == document.correspondent {
+            continue;
+        }
+
+        let path = match (document.date, &document.title) {
+            (Some(date), Some(title)) => {
+                let info = DocumentInfo {
+                    date,
+                    title: title.clone(),
+                    correspondent: correspondent.clone(),
+                    tags: tags.clone(),
+                    due: document.due,
+                    location: document.location.clone(),
+                };
+                archive::rename_archived(&document.path, &info, config)?
+            }
+            _ => document.path.clone(),
+        };
+        if tags != document.tags {
+            let id = document.id.as_deref().unwrap_or_default();
+            if let Err(e) = archive::store_metadata(&path, &tags.join(", "), id, config) {
+                warn!("Failed to store the tags of {path:?} in the PDF metadata: {e:#}");
+            }
+        }
+
+        // Re-key the indexes if the PDF was renamed
+        if path != document.path {
+            tags::remove(outdir, &document.path)?;
+            search::remove(outdir, &document.path)?;
+            links::rename(outdir, &document.path, &path)?;
+        }
+        let updated = Document {
+            path: path.clone(),
+            correspondent,
+            tags,
+            ..document.clone()
+        };
+        tags::record(outdir, &path, &updated.tags, updated.id.as_deref())?;
+        search::add_document(outdir, &updated)?;
+        if let Some(notes_dir) = &config.archive.notes_dir
+            && let Err(e) = notes::update(notes_dir, outdir, &document.path, &updated)
+        {
+            warn!("Failed to update the note of {path:?}: {e:#}");
+        }
+
+        let mut details = Vec::new();
+        details.extend(updated.correspondent.clone());
+        if !updated.tags.is_empty() {
+            details.push(format!("tags: {}", updated.tags.join(", ")));
+        }
+        let mut event = audit::Event::new(audit::Action::Edit, outdir, &path, config.scan.timezone)
+            .with_id(updated.id.as_deref())
+            .with_details(details.join(", "));
+        if path != document.path {
+            event = event.with_previous(outdir, &document.path);
+        }
+        audit::record(outdir, &event);
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+/// Rename the tags and correspondents in the manifests of the documents in
+/// the scans cache (e.g. awaiting review)
+fn rename_in_manifests(scans_dir: &Path, renames: &Renames) -> Result<()> {
+    for (directory, mut manifest) in manifest::find_documents(scans_dir, |_| true)? {
+        let tags = renames.rename_tags(&manifest.tags);
+        let correspondent = renames.rename_correspondent(manifest.correspondent.as_ref());
+        if tags != manifest.tags || correspondent != manifest.correspondent {
+            debug!("Renaming the metadata in the manifest of {directory:?}");
+            manifest.tags = tags;
+            manifest.correspondent = correspondent;
+            manifest.save(&directory)?;
+        }
+    }
+    Ok(())
+}
+
+/// Find similar tags and correspondents in the archive, ask which ones to
+/// merge and apply the merges, return the paths of the changed documents
+pub fn normalize(scans_dir: &Path, config: &Config) -> Result<Vec<PathBuf>> {
+    search::update_index(&config.outdir)?;
+    let documents = search::documents(&config.outdir)?;
+    let mut renames = Renames::default();
+    ask_renames(
+        "tags",
+        documents.iter().flat_map(|document| document.tags.clone()),
+        &mut renames.tags,
+    )?;
+    ask_renames(
+        "correspondents",
+        documents
+            .iter()
+            .filter_map(|document| document.correspondent.clone()),
+        &mut renames.correspondents,
+    )?;
+    if renames.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let changed = apply(&documents, &renames, config)?;
+    if let Err(e) = tags::rename_in_vocabulary(&tags::vocabulary_path()?, &renames.tags) {
+        warn!("Failed to update the tag vocabulary: {e:#}");
+    }
+    if let Err(e) = history::rename(
+        &history::history_path()?,
+        &renames.correspondents,
+        &renames.tags,
+    ) {
+        warn!("Failed to update the history: {e:#}");
+    }
+    rename_in_manifests(scans_dir, &renames)?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that typos, including swapped letters, are counted as one edit.
+    #[test]
+    fn distances() {
+        assert_eq!(distance("insurance", "insurance"), 0);
+        assert_eq!(distance("insurance", "insurnace"), 1);
+        assert_eq!(distance("insurance", "insurances"), 1);
+        assert_eq!(distance("car", "bar"), 1);
+        assert_eq!(distance("", "tax"), 3);
+    }
+
+    /// Ensure that names that only differ in case or by a typo are grouped,
+    /// most used first, but not short names that differ.
+    #[test]
+    fn groups() {
+        let counts = BTreeMap::from([
+            ("Insurance".to_string(), 3),
+            ("insurance".to_string(), 5),
+            ("Insurnace".to_string(), 1),
+            ("car".to_string(), 2),
+            ("bar".to_string(), 1),
+            ("Car".to_string(), 1),
+            ("taxes".to_string(), 4),
+        ]);
+        assert_eq!(
+            find_groups(&counts),
+            vec![
+                vec![("car".to_string(), 2), ("Car".to_string(), 1)],
+                vec![
+                    ("insurance".to_string(), 5),
+                    ("Insurance".to_string(), 3),
+                    ("Insurnace".to_string(), 1),
+                ],
+            ]
+        );
+    }
+
+    /// Ensure that renamed documents are moved to their new name and
+    /// re-keyed in the indexes, and that documents filed by hand keep their
+    /// name.
+    #[test]
+    fn apply_renames() {
+        let outdir = TempDir::new().unwrap();
+        let config: Config = toml::from_str(&format!(
+            "outdir = {:?}\nscanners = []\n[archive]\nfilename = \"{{correspondent}}_{{title}}.pdf\"",
+            outdir.path()
+        ))
+        .unwrap();
+        let archived = outdir.path().join("insurnace-ag_policy.pdf");
+        let filed = outdir.path().join("filed.pdf");
+        let other = outdir.path().join("other.pdf");
+        for pdf in [&archived, &filed, &other] {
+            fs::write(pdf, "%PDF").unwrap();
+        }
+        let documents = vec![
+            Document {
+                path: archived.clone(),
+                id: Some("01JABCDEFGHJKMNPQRSTVWXYZ0".into()),
+                date: NaiveDate::from_ymd_opt(2025, 1, 1),
+                correspondent: Some("Insurnace AG".into()),
+                title: Some("Policy".into()),
+                tags: vec!["Insurance".into()],
+                due: None,
+                location: None,
+                text: "Policy".into(),
+            },
+            Document {
+                path: filed.clone(),
+                tags: vec!["insurance".into(), "Insurance".into()],
+                ..Default::default()
+            },
+            Document {
+                path: other.clone(),
+                tags: vec!["car".into()],
+                ..Default::default()
+            },
+        ];
+        for document in &documents {
+            search::add_document(outdir.path(), document).unwrap();
+            tags::record(outdir.path(), &document.path, &document.tags, None).unwrap();
+        }
+
+        let renames = Renames {
+            tags: BTreeMap::from([("insurance".to_string(), "Insurance".to_string())]),
+            correspondents: BTreeMap::from([(
+                "Insurnace AG".to_string(),
+                "Insurance AG".to_string(),
+            )]),
+        };
+        let renamed = outdir.path().join("insurance-ag_policy.pdf");
+        assert_eq!(
+            apply(&documents, &renames, &config).unwrap(),
+            vec![renamed.clone(), filed.clone()]
+        );
+        assert!(!archived.exists());
+
+        let documents = search::documents(outdir.path()).unwrap();
+        assert_eq!(
+            documents.iter().map(|d| &d.path).collect::<Vec<_>>(),
+            vec![&filed, &renamed, &other]
+        );
+        assert_eq!(documents[0].tags, vec!["Insurance"]);
+        assert_eq!(documents[1].correspondent.as_deref(), Some("Insurance AG"));
+        assert_eq!(
+            documents[1].id.as_deref(),
+            Some("01JABCDEFGHJKMNPQRSTVWXYZ0")
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Markdown notes of archived documents
+//!
+//! With a configured notes directory (e.g. a folder of an Obsidian or Logseq
+//! vault), a note is written for every archived document. It holds the
+//! metadata of the document as front matter, a link to the PDF and an excerpt
+//! of the recognized text, so that the documents show up in the knowledge base
+//! and can be linked from other notes. The notes mirror the structure of the
+//! archive, and follow their documents when they are renamed or deleted.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{fs_utils, naming::DocumentInfo, search::Document};
+
+/// Maximal length (in characters) of the excerpt of the recognized text
+const EXCERPT_LENGTH: usize = 500;
+
+/// Quote a value for the front matter (JSON strings are valid YAML)
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Beginning of a text on a single line, cut after a word
+fn excerpt(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= EXCERPT_LENGTH {
+        return text;
+    }
+    let cut: String = text.chars().take(EXCERPT_LENGTH).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(words, _)| words);
+    format!("{cut} …")
+}
+
+/// Content of the note of an archived PDF
+fn note_content(pdf: &Path, text: &str, info: &DocumentInfo, id: Option<&str>) -> String {
+    let mut front_matter = vec![
+        format!("title: {}", quote(&info.title)),
+        format!("date: {}", info.date),
+    ];
+    if let Some(correspondent) = &info.correspondent {
+        front_matter.push(format!("correspondent: {}", quote(correspondent)));
+    }
+    if !info.tags.is_empty() {
+        let tags: Vec<String> = info.tags.iter().map(|tag| quote(tag)).collect();
+        front_matter.push(format!("tags: [{}]", tags.join(", ")));
+    }
+    if let Some(id) = id.filter(|id| !id.is_empty()) {
+        front_matter.push(format!("id: {}", quote(id)));
+    }
+    if let Some(due) = info.due {
+        front_matter.push(format!("due: {due}"));
+    }
+    if let Some(location) = &info.location {
+        front_matter.push(format!("location: {}", quote(location)));
+    }
+    front_matter.push(format!("pdf: {}", quote(&fs_utils::file_url(pdf))));
+
+    let name = pdf.file_name().unwrap_or_default().to_string_lossy();
+    let mut content = format!(
+        "---\n{}\n---\n\n# {}\n\n[{name}]({})\n",
+        front_matter.join("\n"),
+        info.title,
+        fs_utils::file_url(pdf)
+    );
+    let excerpt = excerpt(text);
+    if !excerpt.is_empty() {
+        content.push_str(&format!("\n> {excerpt}\n"));
+    }
+    content
+}
+
+/// Path of the note of an archived PDF of `outdir` in `notes_dir`, at the
+/// path of the PDF relative to `outdir`
+fn note_path(notes_dir: &Path, outdir: &Path, pdf: &Path) -> PathBuf {
+    let key = fs_utils::relative_key(outdir, pdf);
+    notes_dir.join(Path::new(&key).with_extension("md"))
+}
+
+/// Write the note of an archived PDF of `outdir` into `notes_dir`, return its
+/// path
+///
+/// An existing note of the PDF is replaced.
+pub fn write(
+    notes_dir: &Path,
+    outdir: &Path,
+    pdf: &Path,
+    text: &str,
+    info: &DocumentInfo,
+    id: Option<&str>,
+) -> Result<PathBuf> {
+    let note = note_path(notes_dir, outdir, pdf);
+    if let Some(parent) = note.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {parent:?}"))?;
+    }
+    fs_utils::write_synced(&note, note_content(pdf, text, info, id))
+        .with_context(|| format!("Failed to write note {note:?}"))?;
+    Ok(note)
+}
+
+/// Write the note of an archived document of `outdir` into `notes_dir` (e.g.
+/// after its metadata changed), return its path
+///
+/// Documents without date and title (which were not archived by arkivisto)
+/// get no note.
+pub fn write_document(
+    notes_dir: &Path,
+    outdir: &Path,
+    document: &Document,
+) -> Result<Option<PathBuf>> {
+    let (Some(date), Some(title)) = (document.date, &document.title) else {
+        return Ok(None);
+    };
+    let info = DocumentInfo {
+        date,
+        title: title.clone(),
+        correspondent: document.correspondent.clone(),
+        tags: document.tags.clone(),
+        due: document.due,
+        location: document.location.clone(),
+    };
+    write(
+        notes_dir,
+        outdir,
+        &document.path,
+        &document.text,
+        &info,
+        document.id.as_deref(),
+    )
+    .map(Some)
+}
+
+/// Remove the note of an archived PDF of `outdir` (e.g. when the PDF is
+/// deleted) from `notes_dir`, if it has one
+pub fn remove(notes_dir: &Path, outdir: &Path, pdf: &Path) -> Result<()> {
+    let note = note_path(notes_dir, outdir, pdf);
+    if !note.exists() {
+        return Ok(());
+    }
+    fs::remove_file(&note).with_context(|| format!("Failed to remove note {note:?}"))
+}
+
+/// Replace the note of a PDF of `outdir` that was renamed or changed by the
+/// note of the updated `document`, if the PDF has a note in `notes_dir`
+pub fn update(notes_dir: &Path, outdir: &Path, pdf: &Path, document: &Document) -> Result<()> {
+    if !note_path(notes_dir, outdir, pdf).exists() {
+        return Ok(());
+    }
+    remove(notes_dir, outdir, pdf)?;
+    write_document(notes_dir, outdir, document)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that the note holds the metadata as front matter, a link to
+    /// the PDF and an excerpt of the text.
+    #[test]
+    fn content() {
+        let info = DocumentInfo {
+            date: NaiveDate::from_ymd_opt(2025, 5, 30).unwrap(),
+            title: "Invoice \"May\"".into(),
+            correspondent: Some("Muster AG".into()),
+            tags: vec!["bills".into(), "car".into()],
+            due: NaiveDate::from_ymd_opt(2025, 6, 30),
+            location: None,
+        };
+        let pdf = Path::new("/archive/2025/2025-05-30_muster-ag_invoice.pdf");
+        assert_eq!(
+            note_content(pdf, "Total\n\n42.00", &info, Some("01JAB")),
+            "---\n\
+             title: \"Invoice \\\"May\\\"\"\n\
+             date: 2025-05-30\n\
+             correspondent: \"Muster AG\"\n\
+             tags: [\"bills\", \"car\"]\n\
+             id: \"01JAB\"\n\
+             due: 2025-06-30\n\
+             pdf: \"file:///archive/2025/2025-05-30_muster-ag_invoice.pdf\"\n\
+             ---\n\
+             \n\
+             # Invoice \"May\"\n\
+             \n\
+             [2025-05-30_muster-ag_invoice.pdf](file:///archive/2025/2025-05-30_muster-ag_invoice.pdf)\n\
+             \n\
+             > Total 42.00\n"
+        );
+    }
+
+    /// Ensure that long texts are cut after a word.
+    #[test]
+    fn long_excerpt() {
+        let text = "word ".repeat(200);
+        let excerpt = excerpt(&text);
+        assert!(excerpt.ends_with("word …"));
+        assert!(excerpt.chars().count() <= EXCERPT_LENGTH + 2);
+    }
+
+    /// Ensure that notes mirror the paths of their PDFs in the archive, are
+    /// replaced when their document is renamed, and are removed with it.
+    #[test]
+    fn follow_documents() {
+        let notes_dir = TempDir::new().unwrap();
+        let notes_dir = notes_dir.path();
+        let outdir = Path::new("/archive");
+        let document = Document {
+            path: outdir.join("2025/invoice.pdf"),
+            date: NaiveDate::from_ymd_opt(2025, 5, 30),
+            title: Some("Invoice".into()),
+            tags: vec!["bills".into()],
+            text: "Total 42".into(),
+            ..Default::default()
+        };
+        let note = write_document(notes_dir, outdir, &document)
+            .unwrap()
+            .unwrap();
+        assert_eq!(note, notes_dir.join("2025/invoice.md"));
+        // Documents with the same name in another directory have their own note
+        let other = Document {
+            path: outdir.join("2024/invoice.pdf"),
+            ..document.clone()
+        };
+        write_document(notes_dir, outdir, &other).unwrap();
+        assert!(notes_dir.join("2024/invoice.md").exists());
+        // Documents that were not archived by arkivisto get no note
+        let filed = Document {
+            path: outdir.join("filed.pdf"),
+            ..Default::default()
+        };
+        assert_eq!(write_document(notes_dir, outdir, &filed).unwrap(), None);
+
+        let renamed = Document {
+            path: outdir.join("2025/muster-ag_invoice.pdf"),
+            tags: vec!["Bills".into()],
+            ..document.clone()
+        };
+        update(notes_dir, outdir, &document.path, &renamed).unwrap();
+        assert!(!note.exists());
+        let content = fs::read_to_string(notes_dir.join("2025/muster-ag_invoice.md")).unwrap();
+        assert!(content.contains("file:///archive/2025/muster-ag_invoice.pdf"));
+        assert!(content.contains("tags: [\"Bills\"]"));
+        // Documents without a note get none
+        update(notes_dir, outdir, &filed.path, &filed).unwrap();
+        assert!(!notes_dir.join("filed.md").exists());
+
+        remove(notes_dir, outdir, &renamed.path).unwrap();
+        remove(notes_dir, outdir, &other.path).unwrap();
+        assert!(!notes_dir.join("2025/muster-ag_invoice.md").exists());
+        assert!(!notes_dir.join("2024/invoice.md").exists());
+    }
+}
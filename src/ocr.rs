@@ -0,0 +1,896 @@
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, anyhow};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{OcrConfig, OcrEngine, OcrmypdfRunner, ResourceLimits},
+    hocr, limits, pdf, queue,
+    sandbox::{self, Sandbox},
+    scheduler::{self, JobKind},
+    tools,
+};
+
+/// Docker image used to run OCRmyPDF
+const OCRMYPDF_IMAGE: &str = "docker.io/jbarlow83/ocrmypdf:v16.10.0";
+
+/// Name of the directory for the temporary files of OCRmyPDF, which are kept
+/// to read the word confidences from the hOCR output of Tesseract
+const OCRMYPDF_TEMP: &str = "_ocrmypdf";
+
+/// Name of the plain text file with the recognized text, written by both OCR
+/// engines
+pub const OCR_TEXT: &str = "_final.txt";
+
+/// Minimal length (in characters) of a paragraph to be used for language
+/// detection
+const MIN_PARAGRAPH_LENGTH: usize = 40;
+
+/// OCRmyPDF arguments that are always set by arkivisto and may not be
+/// overridden per document
+const RESERVED_OCRMYPDF_ARGS: &[&str] = &[
+    "--sidecar",
+    "-l",
+    "--language",
+    "--output-type",
+    "-j",
+    "--jobs",
+    "--pdf-renderer",
+];
+
+/// OCRmyPDF options that take a value as the next argument (e.g.
+/// `--tesseract-timeout 300`)
+const OCRMYPDF_VALUE_ARGS: &[&str] = &[
+    "--tesseract-timeout",
+    "--tesseract-non-ocr-timeout",
+    "--tesseract-oem",
+    "--tesseract-pagesegmode",
+    "--tesseract-thresholding",
+    "--tesseract-downsample-above",
+    "--tesseract-config",
+    "--rotate-pages-threshold",
+    "--oversample",
+    "-O",
+    "--optimize",
+    "--jpeg-quality",
+    "--png-quality",
+    "--jbig2-threshold",
+    "--pdfa-image-compression",
+    "--color-conversion-strategy",
+    "--skip-big",
+    "--max-image-mpixels",
+    "--unpaper-args",
+    "--user-words",
+    "--user-patterns",
+    "--pages",
+    "--title",
+    "--author",
+    "--subject",
+    "--keywords",
+];
+
+/// Parse additional OCRmyPDF arguments entered by the user (separated by
+/// whitespace)
+///
+/// The arguments are inserted before the input file, so every argument has
+/// to be an option (e.g. `--force-ocr`) or the value of the preceding option
+/// (e.g. `--tesseract-timeout 300`).
+pub fn parse_ocrmypdf_args(input: &str) -> Result<Vec<String>, String> {
+    let args: Vec<String> = input.split_whitespace().map(String::from).collect();
+    let mut expects_value = false;
+    for arg in &args {
+        if !arg.starts_with('-') {
+            if !expects_value {
+                return Err(format!(
+                    "Expected an option (e.g. --force-ocr), not {arg:?}"
+                ));
+            }
+            expects_value = false;
+            continue;
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if RESERVED_OCRMYPDF_ARGS.contains(&name) {
+            return Err(format!(
+                "{name} is set by arkivisto and can't be overridden"
+            ));
+        }
+        expects_value = value.is_none() && OCRMYPDF_VALUE_ARGS.contains(&name);
+    }
+    if expects_value && let Some(last) = args.last() {
+        return Err(format!("{last} needs a value"));
+    }
+    Ok(args)
+}
+
+/// Installation of OCRmyPDF that is used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ocrmypdf {
+    /// The Docker image, with the document directory mounted at `/document`
+    Docker,
+    /// A local installation, with the directory of its executables (the `bin`
+    /// directory of a Python virtual environment), or in `PATH`
+    Local(Option<PathBuf>),
+}
+
+impl Ocrmypdf {
+    /// Create a command that runs `program` of the installation (`ocrmypdf`,
+    /// or `python3` with pikepdf) on files in `directory`
+    fn command(&self, program: &str, directory: &Path, limits: &ResourceLimits) -> Result<Command> {
+        match self {
+            Ocrmypdf::Docker => {
+                let mut command = docker_command(directory, limits)?;
+                if program != "ocrmypdf" {
+                    command.args(["--entrypoint", program]);
+                }
+                command.arg(OCRMYPDF_IMAGE);
+                Ok(command)
+            }
+            Ocrmypdf::Local(bin_dir) => {
+                let mut command = match bin_dir {
+                    Some(bin_dir) => limits::limited_command(bin_dir.join(program), limits),
+                    None => limits::limited_command(program, limits),
+                };
+                if program == "ocrmypdf"
+                    && let Some(cpus) = limits.cpus
+                {
+                    let jobs = (cpus.ceil() as u32).max(1);
+                    command.args(["--jobs", &jobs.to_string()]);
+                }
+                Ok(command)
+            }
+        }
+    }
+
+    /// Create an `ocrmypdf` command that keeps its temporary files in
+    /// [`OCRMYPDF_TEMP`] in `directory`
+    fn command_keeping_temp(&self, directory: &Path, limits: &ResourceLimits) -> Result<Command> {
+        let temp_dir = self.path(directory, OCRMYPDF_TEMP);
+        let mut command = match self {
+            Ocrmypdf::Docker => {
+                let mut command = docker_command(directory, limits)?;
+                command
+                    .arg("-e")
+                    .arg(format!("TMPDIR={}", temp_dir.display()))
+                    .arg(OCRMYPDF_IMAGE);
+                command
+            }
+            Ocrmypdf::Local(_) => {
+                let mut command = self.command("ocrmypdf", directory, limits)?;
+                command.env("TMPDIR", temp_dir);
+                command
+            }
+        };
+        command.arg("--keep-temporary-files");
+        Ok(command)
+    }
+
+    /// Path of the file `name` in `directory`, as seen by the installation
+    fn path(&self, directory: &Path, name: impl AsRef<Path>) -> PathBuf {
+        match self {
+            Ocrmypdf::Docker => Path::new("/document/").join(name),
+            Ocrmypdf::Local(_) => directory.join(name),
+        }
+    }
+}
+
+impl Display for Ocrmypdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ocrmypdf::Docker => f.write_str("through Docker"),
+            Ocrmypdf::Local(_) => f.write_str("local installation"),
+        }
+    }
+}
+
+/// OCR engine that is used for a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engine {
+    Ocrmypdf(Ocrmypdf),
+    Tesseract,
+}
+
+/// Find the OCRmyPDF installation to use, according to the config
+///
+/// In sandboxes (e.g. a Flatpak), Docker is not used automatically.
+pub fn find_ocrmypdf(config: &OcrConfig) -> Option<Ocrmypdf> {
+    find_ocrmypdf_with(config, sandbox::detect(), tools::is_installed)
+}
+
+/// Find the OCRmyPDF installation to use in the given sandbox, with the
+/// programs in `PATH` given by `is_installed`
+fn find_ocrmypdf_with(
+    config: &OcrConfig,
+    sandbox: Option<Sandbox>,
+    is_installed: impl Fn(&str) -> bool,
+) -> Option<Ocrmypdf> {
+    let docker = || is_installed("docker").then_some(Ocrmypdf::Docker);
+    match config.ocrmypdf_runner {
+        OcrmypdfRunner::Docker => docker(),
+        OcrmypdfRunner::Local => local_ocrmypdf(config, &is_installed),
+        OcrmypdfRunner::Auto => match sandbox {
+            Some(sandbox) => {
+                debug!("Running in a {sandbox} sandbox, not using Docker for OCRmyPDF");
+                local_ocrmypdf(config, &is_installed)
+            }
+            None => docker().or_else(|| local_ocrmypdf(config, &is_installed)),
+        },
+    }
+}
+
+/// Find a local OCRmyPDF installation, in the configured virtual environment
+/// or in `PATH`
+fn local_ocrmypdf(config: &OcrConfig, is_installed: impl Fn(&str) -> bool) -> Option<Ocrmypdf> {
+    match &config.ocrmypdf_venv {
+        Some(venv) => {
+            let bin_dir = venv.join("bin");
+            let installed = tools::is_executable(&bin_dir.join("ocrmypdf"));
+            if !installed {
+                debug!("OCRmyPDF is not installed in the virtual environment {venv:?}");
+            }
+            installed.then_some(Ocrmypdf::Local(Some(bin_dir)))
+        }
+        None => is_installed("ocrmypdf").then_some(Ocrmypdf::Local(None)),
+    }
+}
+
+/// Program that is reported as missing if OCRmyPDF is not found
+pub fn ocrmypdf_program(config: &OcrConfig) -> &'static str {
+    ocrmypdf_program_with(config, sandbox::detect())
+}
+
+/// Program that is reported as missing if OCRmyPDF is not found in the given
+/// sandbox
+fn ocrmypdf_program_with(config: &OcrConfig, sandbox: Option<Sandbox>) -> &'static str {
+    match config.ocrmypdf_runner {
+        OcrmypdfRunner::Docker => "docker",
+        OcrmypdfRunner::Local => "ocrmypdf",
+        OcrmypdfRunner::Auto if sandbox.is_some() => "ocrmypdf",
+        OcrmypdfRunner::Auto => "docker",
+    }
+}
+
+/// Select the OCR engine, according to the config and the installed tools
+///
+/// If OCRmyPDF is not available, Tesseract is run directly (without PDF/A).
+/// Returns `None` if no OCR engine is available.
+pub fn select_engine(config: &OcrConfig) -> Option<Engine> {
+    select_engine_with(config, sandbox::detect(), tools::is_installed)
+}
+
+/// Select the OCR engine in the given sandbox, with the programs in `PATH`
+/// given by `is_installed`
+fn select_engine_with(
+    config: &OcrConfig,
+    sandbox: Option<Sandbox>,
+    is_installed: impl Fn(&str) -> bool,
+) -> Option<Engine> {
+    let tesseract = is_installed("tesseract");
+    match config.engine {
+        OcrEngine::Tesseract if tesseract => Some(Engine::Tesseract),
+        OcrEngine::Tesseract => {
+            tools::warn_missing("tesseract", "creating image-only PDFs without OCR");
+            None
+        }
+        OcrEngine::Ocrmypdf => {
+            if let Some(ocrmypdf) = find_ocrmypdf_with(config, sandbox, &is_installed) {
+                return Some(Engine::Ocrmypdf(ocrmypdf));
+            }
+            let program = ocrmypdf_program_with(config, sandbox);
+            if tesseract {
+                tools::warn_missing(program, "running Tesseract directly (no PDF/A)");
+                Some(Engine::Tesseract)
+            } else {
+                tools::warn_missing(program, "creating image-only PDFs without OCR");
+                None
+            }
+        }
+    }
+}
+
+/// Arguments that select the OCR languages (for both Tesseract and OCRmyPDF)
+fn language_args(languages: &[String]) -> Vec<String> {
+    if languages.is_empty() {
+        return Vec::new();
+    }
+    vec!["-l".into(), languages.join("+")]
+}
+
+/// Create a `docker run` command with `directory` mounted at `/document`
+fn docker_command(directory: &Path, limits: &ResourceLimits) -> Result<Command> {
+    // TODO: Download docker image at setup time
+    let mut command = Command::new("docker");
+    command
+        .arg("run")
+        .arg("--rm")
+        .args(limits::container_args(limits))
+        .arg("-v")
+        .arg(format!(
+            "{}:/document",
+            directory
+                .to_str()
+                .context("Failed to convert directory path to string")?
+        ));
+    // Create the files as the owner of the directory instead of root, so that
+    // they can be removed
+    #[cfg(unix)]
+    if let Ok(metadata) = fs::metadata(directory) {
+        use std::os::unix::fs::MetadataExt;
+
+        command
+            .arg("--user")
+            .arg(format!("{}:{}", metadata.uid(), metadata.gid()));
+    }
+    Ok(command)
+}
+
+/// Run OCRmyPDF on `pdf`, which must be located in `directory`, and write the
+/// final PDF/A as well as the recognized text to `directory`. Returns the
+/// recognized words of every page, read from the hOCR output of Tesseract.
+///
+/// `extra_args` are passed to OCRmyPDF in addition to the default arguments
+/// (see [`parse_ocrmypdf_args`]). Pages that are not recognized by Tesseract
+/// (e.g. because they already contain text) are missing in the result.
+pub fn run_ocrmypdf(
+    ocrmypdf: &Ocrmypdf,
+    directory: &Path,
+    pdf: &Path,
+    languages: &[String],
+    extra_args: &[String],
+    limits: &ResourceLimits,
+) -> Result<Vec<hocr::Page>> {
+    let temp_dir = directory.join(OCRMYPDF_TEMP);
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir).with_context(|| format!("Failed to remove {temp_dir:?}"))?;
+    }
+    fs::create_dir(&temp_dir).with_context(|| format!("Failed to create {temp_dir:?}"))?;
+
+    let mut command = ocrmypdf.command_keeping_temp(directory, limits)?;
+    command
+        .args(language_args(languages))
+        .args(extra_args)
+        .arg("--sidecar")
+        .arg(ocrmypdf.path(directory, OCR_TEXT))
+        .arg(
+            ocrmypdf.path(
+                directory,
+                pdf.file_name()
+                    .context("Failed to get output PDF file name")?,
+            ),
+        )
+        .arg(ocrmypdf.path(directory, queue::FINAL_PDF));
+    let result = run_ocrmypdf_command(command, ocrmypdf).map(|()| read_hocr_files(&temp_dir));
+    if let Err(e) = fs::remove_dir_all(&temp_dir) {
+        warn!("Failed to remove the temporary files of OCRmyPDF in {temp_dir:?}: {e}");
+    }
+    result
+}
+
+/// Read the hOCR files below `directory`, ordered by their path (OCRmyPDF
+/// names them after the page number, e.g. `000001_ocr_hocr.hocr`)
+///
+/// Unreadable files are skipped.
+fn read_hocr_files(directory: &Path) -> Vec<hocr::Page> {
+    fn collect(directory: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                collect(&path, files);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "hocr")
+            {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    collect(directory, &mut files);
+    files.sort();
+    files
+        .iter()
+        .filter_map(|path| match fs::read_to_string(path) {
+            Ok(hocr) => Some(hocr::parse(&hocr)),
+            Err(e) => {
+                debug!("Failed to read hOCR file {path:?}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Run OCRmyPDF on the final PDF in `directory` to write a small, aggressively
+/// optimized copy for emailing. The existing text layer is kept, OCR is not
+/// run again.
+pub fn optimize_for_email(
+    ocrmypdf: &Ocrmypdf,
+    directory: &Path,
+    limits: &ResourceLimits,
+) -> Result<()> {
+    let quality = pdf::EMAIL_JPEG_QUALITY.to_string();
+    let mut command = ocrmypdf.command("ocrmypdf", directory, limits)?;
+    command
+        .args(["--skip-text", "--optimize", "3", "--output-type", "pdf"])
+        .args(["--jpeg-quality", &quality, "--png-quality", &quality])
+        .arg(ocrmypdf.path(directory, queue::FINAL_PDF))
+        .arg(ocrmypdf.path(directory, queue::EMAIL_PDF));
+    run_ocrmypdf_command(command, ocrmypdf)
+}
+
+/// Script that sets the keywords and the identifier of a PDF with pikepdf
+/// (which is installed with OCRmyPDF) in the XMP metadata, and the keywords
+/// also in the document info, so that PDF/A files stay valid. Empty values are
+/// not set.
+const SET_METADATA_SCRIPT: &str = "\
+import sys, pikepdf
+with pikepdf.open(sys.argv[1], allow_overwriting_input=True) as pdf:
+    with pdf.open_metadata() as meta:
+        if sys.argv[2]:
+            meta['pdf:Keywords'] = sys.argv[2]
+        if sys.argv[3]:
+            meta['dc:identifier'] = sys.argv[3]
+    pdf.save(sys.argv[1])
+";
+
+/// Set the keywords and the identifier (`dc:identifier`) of the PDF
+/// `pdf_name` in `directory`, empty values are not set
+pub fn set_metadata(
+    ocrmypdf: &Ocrmypdf,
+    directory: &Path,
+    pdf_name: &str,
+    keywords: &str,
+    identifier: &str,
+    limits: &ResourceLimits,
+) -> Result<()> {
+    let mut command = ocrmypdf.command("python3", directory, limits)?;
+    command
+        .args(["-c", SET_METADATA_SCRIPT])
+        .arg(ocrmypdf.path(directory, pdf_name))
+        .arg(keywords)
+        .arg(identifier);
+    debug!("Running {:?}", command);
+    let output = command.output().context("Failed to run `python3`")?;
+    if !output.status.success() {
+        warn!(
+            "Setting the PDF metadata failed with status {}. Stderr: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        return Err(anyhow!("Failed to set the PDF metadata ({ocrmypdf})"));
+    }
+    Ok(())
+}
+
+/// Run an OCRmyPDF command created by [`Ocrmypdf::command`]
+fn run_ocrmypdf_command(mut command: Command, ocrmypdf: &Ocrmypdf) -> Result<()> {
+    debug!("Running {:?}", command);
+    let output = command.output().context("Failed to run `ocrmypdf`")?;
+    if !output.status.success() {
+        warn!(
+            "ocrmypdf failed with status {}. Stderr: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        return Err(anyhow!("Failed to run `ocrmypdf` command ({ocrmypdf})"));
+    }
+    Ok(())
+}
+
+/// Run Tesseract on every processed page and write the recognized text to
+/// `directory`, return the recognized words of every page
+///
+/// Tesseract writes an hOCR file and a text file next to every page (e.g.
+/// `0001_processed.hocr`). The words are merged into the PDF as text layer by
+/// [`pdf::write_text_pdf`]. The pages are recognized concurrently.
+pub fn run_tesseract(
+    directory: &Path,
+    pages: &[PathBuf],
+    languages: &[String],
+    limits: &ResourceLimits,
+) -> Result<Vec<hocr::Page>> {
+    let results = scheduler::global().map(JobKind::Cpu, pages, |_, page| {
+        let mut command = limits::limited_command("tesseract", limits);
+        command.args(tesseract_args(page, languages));
+        // Tesseract uses OpenMP for multithreading
+        if let Some(threads) = limits::thread_limit(limits, scheduler::global().limit(JobKind::Cpu))
+        {
+            command.env("OMP_THREAD_LIMIT", threads.to_string());
+        }
+        debug!("Running {:?}", command);
+        let output = command.output().context("Failed to run `tesseract`")?;
+        if !output.status.success() {
+            warn!(
+                "tesseract failed with status {}. Stderr: {}",
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr),
+            );
+            return Err(anyhow!("Failed to run `tesseract` command on {page:?}"));
+        }
+        let hocr_path = page.with_extension("hocr");
+        let hocr = fs::read_to_string(&hocr_path)
+            .with_context(|| format!("Failed to read hOCR file {hocr_path:?}"))?;
+        let text_path = page.with_extension("txt");
+        let text = fs::read_to_string(&text_path)
+            .with_context(|| format!("Failed to read text file {text_path:?}"))?;
+        Ok((hocr::parse(&hocr), text))
+    });
+
+    let mut words = Vec::new();
+    let mut text = String::new();
+    for result in results {
+        let (page_words, page_text) = result?;
+        words.push(page_words);
+        text.push_str(&page_text);
+    }
+    fs::write(directory.join(OCR_TEXT), text).context("Failed to write recognized text")?;
+    Ok(words)
+}
+
+/// Arguments for Tesseract to write an hOCR file and a text file next to a
+/// page
+///
+/// Tesseract expects the output path without extension and appends the
+/// extension of every configured output format.
+fn tesseract_args(page: &Path, languages: &[String]) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![page.into(), page.with_extension("").into()];
+    args.extend(language_args(languages).into_iter().map(OsString::from));
+    args.extend(["hocr".into(), "txt".into()]);
+    args
+}
+
+/// Quickly recognize the text of a single page with all candidate languages
+///
+/// The page must be located in `directory`. With OCRmyPDF through Docker,
+/// Tesseract is run from the OCRmyPDF container, so that no local installation
+/// is needed.
+pub fn rough_text(
+    directory: &Path,
+    page: &Path,
+    languages: &[String],
+    engine: &Engine,
+    limits: &ResourceLimits,
+) -> Result<String> {
+    let mut command = match engine {
+        Engine::Tesseract | Engine::Ocrmypdf(Ocrmypdf::Local(_)) => {
+            let mut command = limits::limited_command("tesseract", limits);
+            command.arg(page);
+            command
+        }
+        Engine::Ocrmypdf(ocrmypdf) => {
+            let mut command = ocrmypdf.command("tesseract", directory, limits)?;
+            command.arg(ocrmypdf.path(
+                directory,
+                page.file_name().context("Failed to get page file name")?,
+            ));
+            command
+        }
+    };
+    command.arg("stdout").args(language_args(languages));
+    debug!("Running {:?}", command);
+    let output = command.output().context("Failed to run `tesseract`")?;
+    if !output.status.success() {
+        warn!(
+            "tesseract failed with status {}. Stderr: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        return Err(anyhow!("Failed to run `tesseract` command"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Detect the languages of a text, out of the candidate languages (Tesseract
+/// language codes, e.g. `deu`)
+///
+/// Every paragraph is detected separately, so that multilingual documents
+/// result in multiple languages. The languages are ordered by their share of
+/// the text, languages with a small share (e.g. a secondary language in the
+/// footer) are kept. Candidates that are not supported by the detection are
+/// ignored.
+pub fn detect_languages(text: &str, candidates: &[String]) -> Vec<String> {
+    let allowlist: Vec<whatlang::Lang> = candidates
+        .iter()
+        .filter_map(whatlang::Lang::from_code)
+        .collect();
+    if allowlist.is_empty() {
+        return Vec::new();
+    }
+    let detector = whatlang::Detector::with_allowlist(allowlist);
+
+    // Count the characters per language
+    let mut shares: Vec<(whatlang::Lang, usize)> = Vec::new();
+    for paragraph in text.split("\n\n") {
+        let paragraph = paragraph.trim();
+        let length = paragraph.chars().count();
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        if let Some(lang) = detector.detect_lang(paragraph) {
+            match shares.iter_mut().find(|(l, _)| *l == lang) {
+                Some((_, count)) => *count += length,
+                None => shares.push((lang, length)),
+            }
+        }
+    }
+
+    shares.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    shares
+        .into_iter()
+        .map(|(lang, _)| lang.code().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that Docker is preferred outside of sandboxes, that a local
+    /// installation is used in sandboxes and if configured, and that a
+    /// configured virtual environment must contain OCRmyPDF.
+    #[test]
+    fn find_ocrmypdf_installations() {
+        let installed =
+            |programs: &'static [&'static str]| move |program: &str| programs.contains(&program);
+        let auto = OcrConfig::default();
+        let local = OcrConfig {
+            ocrmypdf_runner: OcrmypdfRunner::Local,
+            ..Default::default()
+        };
+        let docker = OcrConfig {
+            ocrmypdf_runner: OcrmypdfRunner::Docker,
+            ..Default::default()
+        };
+        let both = installed(&["docker", "ocrmypdf"]);
+        let flatpak = Some(Sandbox::Flatpak);
+
+        assert_eq!(
+            find_ocrmypdf_with(&auto, None, both),
+            Some(Ocrmypdf::Docker)
+        );
+        assert_eq!(
+            find_ocrmypdf_with(&auto, None, installed(&["ocrmypdf"])),
+            Some(Ocrmypdf::Local(None))
+        );
+        assert_eq!(
+            find_ocrmypdf_with(&auto, flatpak, both),
+            Some(Ocrmypdf::Local(None))
+        );
+        assert_eq!(
+            find_ocrmypdf_with(&auto, flatpak, installed(&["docker"])),
+            None
+        );
+        assert_eq!(
+            find_ocrmypdf_with(&local, None, both),
+            Some(Ocrmypdf::Local(None))
+        );
+        assert_eq!(
+            find_ocrmypdf_with(&docker, flatpak, both),
+            Some(Ocrmypdf::Docker)
+        );
+        assert_eq!(ocrmypdf_program_with(&auto, None), "docker");
+        assert_eq!(ocrmypdf_program_with(&auto, flatpak), "ocrmypdf");
+
+        let venv = TempDir::new().unwrap();
+        let venv_config = OcrConfig {
+            ocrmypdf_venv: Some(venv.path().to_path_buf()),
+            ..local
+        };
+        assert_eq!(find_ocrmypdf_with(&venv_config, None, both), None);
+        let bin_dir = venv.path().join("bin");
+        fs::create_dir(&bin_dir).unwrap();
+        fs::write(bin_dir.join("ocrmypdf"), "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(bin_dir.join("ocrmypdf"), fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        assert_eq!(
+            find_ocrmypdf_with(&venv_config, None, installed(&[])),
+            Some(Ocrmypdf::Local(Some(bin_dir)))
+        );
+    }
+
+    /// Ensure that Tesseract is used if configured or if OCRmyPDF is missing,
+    /// and that no engine is selected without the needed tools.
+    #[test]
+    fn select_engines() {
+        let installed =
+            |programs: &'static [&'static str]| move |program: &str| programs.contains(&program);
+        let ocrmypdf = OcrConfig::default();
+        let tesseract = OcrConfig {
+            engine: OcrEngine::Tesseract,
+            ..Default::default()
+        };
+        let all = installed(&["docker", "tesseract"]);
+
+        assert_eq!(
+            select_engine_with(&ocrmypdf, None, all),
+            Some(Engine::Ocrmypdf(Ocrmypdf::Docker))
+        );
+        assert_eq!(
+            select_engine_with(&ocrmypdf, Some(Sandbox::Snap), all),
+            Some(Engine::Tesseract)
+        );
+        assert_eq!(select_engine_with(&ocrmypdf, None, installed(&[])), None);
+        assert_eq!(
+            select_engine_with(&tesseract, None, all),
+            Some(Engine::Tesseract)
+        );
+        assert_eq!(
+            select_engine_with(&tesseract, None, installed(&["docker"])),
+            None
+        );
+    }
+
+    /// Ensure that local installations are run from their virtual
+    /// environment with the files in place, and Docker with the mounted
+    /// directory.
+    #[test]
+    fn ocrmypdf_commands() {
+        let directory = Path::new("/scans/doc");
+        let limits = ResourceLimits {
+            cpus: Some(1.5),
+            ..Default::default()
+        };
+        let args = |command: &Command| -> Vec<String> {
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let venv = Ocrmypdf::Local(Some(PathBuf::from("/opt/venv/bin")));
+        let command = venv.command("ocrmypdf", directory, &limits).unwrap();
+        assert_eq!(command.get_program(), "/opt/venv/bin/ocrmypdf");
+        assert_eq!(args(&command), vec!["--jobs", "2"]);
+        assert_eq!(
+            venv.path(directory, OCR_TEXT),
+            Path::new("/scans/doc/_final.txt")
+        );
+        let command = Ocrmypdf::Local(None)
+            .command("python3", directory, &limits)
+            .unwrap();
+        assert_eq!(command.get_program(), "python3");
+        assert!(args(&command).is_empty());
+
+        let command = Ocrmypdf::Docker
+            .command("tesseract", directory, &limits)
+            .unwrap();
+        assert_eq!(command.get_program(), "docker");
+        let docker_args = args(&command);
+        assert!(docker_args.contains(&"/scans/doc:/document".to_string()));
+        assert!(docker_args.ends_with(&[
+            "--entrypoint".to_string(),
+            "tesseract".to_string(),
+            OCRMYPDF_IMAGE.to_string()
+        ]));
+        assert_eq!(
+            Ocrmypdf::Docker.path(directory, OCR_TEXT),
+            Path::new("/document/_final.txt")
+        );
+    }
+
+    /// Ensure that the hOCR files of OCRmyPDF are read in the order of the
+    /// pages, also from subdirectories.
+    #[test]
+    fn ocrmypdf_hocr_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let work_dir = temp_dir.path().join("ocrmypdf.io.abc");
+        fs::create_dir(&work_dir).unwrap();
+        for (page, width) in [(2, 200), (1, 100)] {
+            fs::write(
+                work_dir.join(format!("00000{page}_ocr_hocr.hocr")),
+                format!("<div class='ocr_page' title='bbox 0 0 {width} 50'></div>"),
+            )
+            .unwrap();
+        }
+        fs::write(work_dir.join("000001_ocr.png"), "").unwrap();
+        let widths: Vec<u32> = read_hocr_files(temp_dir.path())
+            .iter()
+            .map(|page| page.width)
+            .collect();
+        assert_eq!(widths, vec![100, 200]);
+
+        // Docker creates the files as the owner of the directory
+        let command = Ocrmypdf::Docker
+            .command_keeping_temp(temp_dir.path(), &ResourceLimits::default())
+            .unwrap();
+        let args: Vec<_> = command.get_args().collect();
+        assert!(args.contains(&"--user".as_ref()));
+        assert!(args.ends_with(&[OCRMYPDF_IMAGE.as_ref(), "--keep-temporary-files".as_ref()]));
+        assert!(args.contains(&"TMPDIR=/document/_ocrmypdf".as_ref()));
+    }
+
+    /// Ensure that additional OCRmyPDF arguments are split at whitespace, and
+    /// that positional and reserved arguments are rejected.
+    #[test]
+    fn ocrmypdf_args() {
+        assert_eq!(parse_ocrmypdf_args("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(
+            parse_ocrmypdf_args("--force-ocr  --tesseract-timeout 300").unwrap(),
+            vec!["--force-ocr", "--tesseract-timeout", "300"]
+        );
+        assert!(parse_ocrmypdf_args("input.pdf --force-ocr").is_err());
+        assert!(parse_ocrmypdf_args("--sidecar=out.txt").is_err());
+        assert!(parse_ocrmypdf_args("--force-ocr -l deu").is_err());
+        assert!(parse_ocrmypdf_args("--force-ocr input.pdf").is_err());
+        assert!(parse_ocrmypdf_args("--tesseract-timeout 300 input.pdf").is_err());
+        assert!(parse_ocrmypdf_args("--tesseract-timeout=300 input.pdf").is_err());
+        assert!(parse_ocrmypdf_args("-O 2 --jobs 4").is_err());
+        assert!(parse_ocrmypdf_args("--output-type=pdf").is_err());
+        assert!(parse_ocrmypdf_args("--pdf-renderer sandwich").is_err());
+        // Without a value, OCRmyPDF would take the next argument as value
+        assert!(parse_ocrmypdf_args("--tesseract-timeout").is_err());
+        assert!(parse_ocrmypdf_args("--force-ocr -O").is_err());
+    }
+
+    /// Ensure that the languages of a multilingual text are detected, ordered
+    /// by their share of the text.
+    #[test]
+    fn detect_multilingual() {
+        let text = "Sehr geehrte Damen und Herren, wir bedanken uns für Ihre Bestellung \
+                    und senden Ihnen anbei die Rechnung für den vergangenen Monat.\n\n\
+                    Bitte überweisen Sie den Betrag innerhalb von dreissig Tagen auf das \
+                    unten angegebene Konto. Vielen Dank für Ihr Vertrauen.\n\n\
+                    Please find attached the invoice for the last month. Payment is due \
+                    within thirty days.\n\n\
+                    Seite 1";
+        let candidates = ["deu".to_string(), "eng".to_string(), "fra".to_string()];
+        assert_eq!(detect_languages(text, &candidates), vec!["deu", "eng"]);
+
+        // Languages with a small share are kept
+        let text = format!(
+            "{}\n\nPlease find attached the invoice for the last month.",
+            "Sehr geehrte Damen und Herren, wir bedanken uns für Ihre Bestellung \
+             und senden Ihnen anbei die Rechnung für den vergangenen Monat.\n\n"
+                .repeat(5)
+        );
+        assert_eq!(detect_languages(&text, &candidates), vec!["deu", "eng"]);
+    }
+
+    /// Ensure that only candidate languages are detected.
+    #[test]
+    fn detect_candidates_only() {
+        let text = "Nous vous remercions de votre commande et vous prions de trouver \
+                    ci-joint la facture du mois dernier.";
+        assert_eq!(
+            detect_languages(text, &["fra".to_string(), "ita".to_string()]),
+            vec!["fra"]
+        );
+        assert!(detect_languages(text, &["xyz".to_string()]).is_empty());
+        assert!(detect_languages("Seite 1", &["deu".to_string()]).is_empty());
+    }
+
+    /// Ensure that Tesseract writes the hOCR and text file next to the page.
+    #[test]
+    fn tesseract_output() {
+        let args = tesseract_args(
+            Path::new("/scans/doc/0001_processed.tif"),
+            &["deu".into(), "eng".into()],
+        );
+        assert_eq!(
+            args,
+            vec![
+                "/scans/doc/0001_processed.tif",
+                "/scans/doc/0001_processed",
+                "-l",
+                "deu+eng",
+                "hocr",
+                "txt"
+            ]
+        );
+    }
+}
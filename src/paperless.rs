@@ -0,0 +1,484 @@
+//! Exchange of documents with paperless-ngx
+//!
+//! The archive can be exported in the format of the paperless-ngx document
+//! exporter, which is read by its `document_importer` command: the PDFs (in
+//! the directory layout of the archive) and a `manifest.json` with the
+//! correspondents, tags and documents, as serialized Django model instances.
+//!
+//! Conversely, such an export (with a single manifest) can be imported into
+//! the archive.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use md5::{Digest, Md5};
+use serde_json::{Value, json};
+use tracing::{debug, warn};
+use ulid::Ulid;
+
+use crate::{archive, audit, config::Config, fs_utils, naming::DocumentInfo, search::Document, ui};
+
+/// Name of the manifest in an export
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Key of the exported file of a document in the manifest
+const EXPORTED_FILE_KEY: &str = "__exported_file_name__";
+
+/// Key of the exported archive version of a document (a PDF/A with the
+/// recognized text) in the manifest
+const EXPORTED_ARCHIVE_KEY: &str = "__exported_archive_name__";
+
+/// Matching algorithm "none" of paperless-ngx, so that the exported
+/// correspondents and tags are not assigned to new documents automatically
+const MATCH_NONE: u32 = 0;
+
+/// Hexadecimal MD5 checksum of a file, which paperless-ngx uses to detect
+/// duplicates
+fn md5_checksum(path: &Path) -> Result<String> {
+    let data = fs_utils::retry_stale(|| fs::read(path))
+        .with_context(|| format!("Failed to read {path:?}"))?;
+    Ok(Md5::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Modification time of a file, as RFC 3339 timestamp
+fn modified(path: &Path) -> Result<String> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read modification time of {path:?}"))?;
+    Ok(DateTime::<Utc>::from(modified).to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Primary keys of names, numbered from 1 in alphabetical order
+fn primary_keys<'a>(names: impl IntoIterator<Item = &'a str>) -> BTreeMap<&'a str, usize> {
+    let mut keys: BTreeMap<&str, usize> = names.into_iter().map(|name| (name, 0)).collect();
+    for (pk, key) in keys.values_mut().enumerate() {
+        *key = pk + 1;
+    }
+    keys
+}
+
+/// Export the `documents` of the archive in `outdir` to the empty or new
+/// directory `target`, return the number of exported documents
+///
+/// Documents are dated with their date, or the modification time of the PDF
+/// if it isn't known (e.g. PDFs filed by hand), and titled with their title
+/// or file name.
+pub fn export(outdir: &Path, documents: &[Document], target: &Path) -> Result<usize> {
+    if target.exists() {
+        ensure!(
+            fs::read_dir(target)
+                .with_context(|| format!("Failed to read directory {target:?}"))?
+                .next()
+                .is_none(),
+            "The export directory {target:?} is not empty"
+        );
+    }
+    fs::create_dir_all(target).with_context(|| format!("Failed to create directory {target:?}"))?;
+
+    let correspondents = primary_keys(
+        documents
+            .iter()
+            .filter_map(|document| document.correspondent.as_deref()),
+    );
+    let tags = primary_keys(
+        documents
+            .iter()
+            .flat_map(|document| document.tags.iter().map(String::as_str)),
+    );
+    let mut manifest: Vec<Value> = Vec::new();
+    for (name, pk) in &correspondents {
+        manifest.push(json!({
+            "model": "documents.correspondent",
+            "pk": pk,
+            "fields": {
+                "name": name,
+                "match": "",
+                "matching_algorithm": MATCH_NONE,
+                "is_insensitive": true,
+            },
+        }));
+    }
+    for (name, pk) in &tags {
+        manifest.push(json!({
+            "model": "documents.tag",
+            "pk": pk,
+            "fields": {
+                "name": name,
+                "match": "",
+                "matching_algorithm": MATCH_NONE,
+                "is_insensitive": true,
+                "is_inbox_tag": false,
+            },
+        }));
+    }
+
+    let progress = ui::progress_bar(documents.len() as u64).with_message("Exporting documents");
+    for (pk, document) in documents.iter().enumerate() {
+        let key = fs_utils::relative_key(outdir, &document.path);
+        let exported = target.join(&key);
+        if let Some(parent) = exported.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {parent:?}"))?;
+        }
+        fs::copy(&document.path, &exported)
+            .with_context(|| format!("Failed to copy {:?} to {exported:?}", document.path))?;
+
+        let modified = modified(&document.path)?;
+        let created = match document.date {
+            Some(date) => date.to_string(),
+            None => modified[..10].to_string(),
+        };
+        let file_name = document
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let title = document.title.clone().unwrap_or_else(|| {
+            document
+                .path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        });
+        let tag_keys: Vec<usize> = document.tags.iter().map(|tag| tags[tag.as_str()]).collect();
+        manifest.push(json!({
+            "model": "documents.document",
+            "pk": pk + 1,
+            "fields": {
+                "correspondent": document
+                    .correspondent
+                    .as_deref()
+                    .map(|correspondent| correspondents[correspondent]),
+                "title": title,
+                "content": document.text,
+                "mime_type": "application/pdf",
+                "checksum": md5_checksum(&document.path)?,
+                "created": created,
+                "modified": modified,
+                "added": DateTime::<Utc>::from(SystemTime::now())
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+                "storage_type": "unencrypted",
+                "filename": key,
+                "original_filename": file_name,
+                "archive_serial_number": null,
+                "tags": tag_keys,
+            },
+            EXPORTED_FILE_KEY: key,
+        }));
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+
+    let path = target.join(MANIFEST_FILE);
+    let content =
+        serde_json::to_string_pretty(&manifest).context("Failed to serialize the manifest")?;
+    fs_utils::write_synced(&path, content)
+        .with_context(|| format!("Failed to write manifest {path:?}"))?;
+    debug!("Exported {} document(s) to {target:?}", documents.len());
+    Ok(documents.len())
+}
+
+/// A document of an export, to be filed into the archive
+#[derive(Debug, PartialEq)]
+struct ExportedDocument {
+    pdf: PathBuf,
+    info: DocumentInfo,
+    text: String,
+}
+
+/// Names of the model instances in a manifest, by primary key
+fn names(manifest: &[Value], model: &str) -> BTreeMap<u64, String> {
+    manifest
+        .iter()
+        .filter(|record| record["model"] == model)
+        .filter_map(|record| {
+            Some((
+                record["pk"].as_u64()?,
+                record["fields"]["name"].as_str()?.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Read a document of a manifest, from the export in `source`
+///
+/// The archive version is preferred over the original file, which may be an
+/// image or an office document.
+fn read_document(
+    source: &Path,
+    record: &Value,
+    correspondents: &BTreeMap<u64, String>,
+    tags: &BTreeMap<u64, String>,
+) -> Result<ExportedDocument> {
+    let fields = &record["fields"];
+    let file = match record[EXPORTED_ARCHIVE_KEY].as_str() {
+        Some(file) => file,
+        None if fields["mime_type"] == "application/pdf" => record[EXPORTED_FILE_KEY]
+            .as_str()
+            .ok_or_else(|| anyhow!("The document has no exported file"))?,
+        None => {
+            return Err(anyhow!(
+                "The document is no PDF ({}) and has no archive version",
+                fields["mime_type"]
+            ));
+        }
+    };
+    // Dates were exported as timestamps before paperless-ngx 2.16
+    let created = fields["created"].as_str().unwrap_or_default();
+    let date = created
+        .get(..10)
+        .and_then(|date| date.parse::<NaiveDate>().ok())
+        .ok_or_else(|| anyhow!("Invalid creation date {created:?}"))?;
+    let title = fields["title"].as_str().unwrap_or_default().trim();
+    let title = if title.is_empty() {
+        Path::new(file)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        title.to_string()
+    };
+    let tags = fields["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|pk| tags.get(&pk.as_u64()?).cloned())
+        .collect();
+    Ok(ExportedDocument {
+        pdf: source.join(file),
+        info: DocumentInfo {
+            date,
+            title,
+            correspondent: fields["correspondent"]
+                .as_u64()
+                .and_then(|pk| correspondents.get(&pk).cloned()),
+            tags,
+            due: None,
+            location: None,
+        },
+        text: fields["content"].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+/// Read the documents of the export in `source`, skipping (with a warning)
+/// those that cannot be imported
+fn read_export(source: &Path) -> Result<Vec<ExportedDocument>> {
+    let path = source.join(MANIFEST_FILE);
+    let content = fs_utils::retry_stale(|| fs::read_to_string(&path))
+        .with_context(|| format!("Failed to read manifest {path:?}"))?;
+    let manifest: Vec<Value> =
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse {path:?}"))?;
+    let correspondents = names(&manifest, "documents.correspondent");
+    let tags = names(&manifest, "documents.tag");
+    let mut documents = Vec::new();
+    for record in manifest
+        .iter()
+        .filter(|record| record["model"] == "documents.document")
+    {
+        match read_document(source, record, &correspondents, &tags) {
+            Ok(document) => documents.push(document),
+            Err(e) => warn!("Skipping document {}: {e:#}", record["pk"]),
+        }
+    }
+    Ok(documents)
+}
+
+/// Import the documents of the paperless-ngx export in `source` into the
+/// archive, return the paths of the archived PDFs
+///
+/// The documents are named and filed like scanned documents, with their
+/// title, correspondent, creation date and tags, and indexed with their
+/// recognized text. The export is not modified.
+pub fn import(source: &Path, config: &Config) -> Result<Vec<PathBuf>> {
+    let documents = read_export(source)?;
+    fs_utils::ensure_dir_prompt(&config.outdir)?;
+    let progress = ui::progress_bar(documents.len() as u64).with_message("Importing documents");
+    let mut archived = Vec::new();
+    for document in documents {
+        let id = Ulid::from_datetime(SystemTime::now()).to_string();
+        let target = archive::file_pdf(
+            &document.pdf,
+            &document.text,
+            &document.info,
+            &id,
+            audit::Action::Import,
+            config,
+        )?;
+        debug!("Imported {:?} to {target:?}", document.pdf);
+        archived.push(target);
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that documents are exported with their correspondents and tags
+    /// in the manifest format of paperless-ngx, and that PDFs without
+    /// metadata are titled after their file name.
+    #[test]
+    fn export_documents() {
+        let outdir = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+        fs::create_dir(outdir.path().join("2025")).unwrap();
+        let invoice = outdir.path().join("2025/invoice.pdf");
+        let filed = outdir.path().join("filed.pdf");
+        fs::write(&invoice, "%PDF").unwrap();
+        fs::write(&filed, "%PDF filed").unwrap();
+        let documents = vec![
+            Document {
+                path: invoice,
+                date: NaiveDate::from_ymd_opt(2025, 5, 30),
+                correspondent: Some("Muster AG".into()),
+                title: Some("Invoice".into()),
+                tags: vec!["car".into(), "bills".into()],
+                text: "Invoice 42".into(),
+                ..Default::default()
+            },
+            Document {
+                path: filed,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(export(outdir.path(), &documents, target.path()).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(target.path().join("2025/invoice.pdf")).unwrap(),
+            "%PDF"
+        );
+
+        let manifest: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(target.path().join(MANIFEST_FILE)).unwrap())
+                .unwrap();
+        let models: Vec<&str> = manifest
+            .iter()
+            .map(|record| record["model"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                "documents.correspondent",
+                "documents.tag",
+                "documents.tag",
+                "documents.document",
+                "documents.document"
+            ]
+        );
+        assert_eq!(manifest[1]["fields"]["name"], "bills");
+        let invoice = &manifest[3];
+        assert_eq!(invoice[EXPORTED_FILE_KEY], "2025/invoice.pdf");
+        assert_eq!(invoice["fields"]["correspondent"], 1);
+        assert_eq!(invoice["fields"]["tags"], json!([2, 1]));
+        assert_eq!(invoice["fields"]["created"], "2025-05-30");
+        assert_eq!(invoice["fields"]["content"], "Invoice 42");
+        assert_eq!(
+            invoice["fields"]["checksum"],
+            md5_checksum(&documents[0].path).unwrap()
+        );
+        let filed = &manifest[4]["fields"];
+        assert_eq!(filed["title"], "filed");
+        assert_eq!(filed["correspondent"], Value::Null);
+
+        // The export directory must be empty
+        assert!(export(outdir.path(), &documents, target.path()).is_err());
+    }
+
+    /// Ensure that exported documents are read with their metadata, also from
+    /// exports of older paperless-ngx versions, and that documents that
+    /// aren't PDFs are skipped unless they have an archive version.
+    #[test]
+    fn read_exported_documents() {
+        let outdir = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+        let invoice = outdir.path().join("invoice.pdf");
+        fs::write(&invoice, "%PDF").unwrap();
+        let documents = vec![Document {
+            path: invoice,
+            date: NaiveDate::from_ymd_opt(2025, 5, 30),
+            correspondent: Some("Muster AG".into()),
+            title: Some("Invoice".into()),
+            tags: vec!["bills".into()],
+            text: "Invoice 42".into(),
+            ..Default::default()
+        }];
+        export(outdir.path(), &documents, target.path()).unwrap();
+        assert_eq!(
+            read_export(target.path()).unwrap(),
+            vec![ExportedDocument {
+                pdf: target.path().join("invoice.pdf"),
+                info: DocumentInfo {
+                    date: NaiveDate::from_ymd_opt(2025, 5, 30).unwrap(),
+                    title: "Invoice".into(),
+                    correspondent: Some("Muster AG".into()),
+                    tags: vec!["bills".into()],
+                    due: None,
+                    location: None,
+                },
+                text: "Invoice 42".into(),
+            }]
+        );
+
+        let manifest = json!([
+            {
+                "model": "documents.document",
+                "pk": 7,
+                "fields": {
+                    "title": "",
+                    "mime_type": "image/jpeg",
+                    "created": "2021-03-04T10:00:00+01:00",
+                    "correspondent": null,
+                    "tags": [],
+                },
+                "__exported_file_name__": "photo.jpg",
+                "__exported_archive_name__": "archive/photo.pdf",
+            },
+            {
+                "model": "documents.document",
+                "pk": 8,
+                "fields": {
+                    "title": "Letter",
+                    "mime_type": "application/msword",
+                    "created": "2021-03-04",
+                },
+                "__exported_file_name__": "letter.doc",
+            },
+        ]);
+        fs::write(target.path().join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        let documents = read_export(target.path()).unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].pdf, target.path().join("archive/photo.pdf"));
+        assert_eq!(documents[0].info.title, "photo");
+        assert_eq!(
+            documents[0].info.date,
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap()
+        );
+    }
+
+    /// Ensure that checksums are hexadecimal MD5 digests.
+    #[test]
+    fn checksums() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            md5_checksum(&path).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+    }
+}
@@ -0,0 +1,816 @@
+//! Image-only PDFs of scanned pages, and updates of the document info and
+//! the bookmarks of existing PDFs
+
+use std::{
+    cell::RefCell,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, write::ZlibEncoder};
+use jpeg_encoder::{ImageBuffer, JpegColorType, SamplingFactor, rgb_to_ycbcr};
+use lopdf::{Dictionary, Document, IncrementalDocument, Object, ObjectId, Stream, dictionary};
+use tracing::trace;
+
+use crate::{
+    fs_utils, hocr,
+    tiff_utils::{self, Page, PixelFormat},
+};
+
+/// JPEG quality of the page images, unless configured otherwise
+pub const DEFAULT_JPEG_QUALITY: u8 = 92;
+
+/// JPEG quality of the page images in the copy for emailing
+pub const EMAIL_JPEG_QUALITY: u8 = 40;
+
+/// Resolution of pages without resolution tags (the default scan resolution)
+const DEFAULT_DPI: f32 = 300.0;
+
+/// Width of every glyph of the text layer font in thousandths of the font size
+const GLYPH_WIDTH: f32 = 500.0;
+
+/// Write the pages of a (multi-page) TIFF file to a PDF, one image per page
+///
+/// Grayscale and color pages are embedded as JPEG with the quality (1-100),
+/// bilevel pages losslessly. The size of a page follows from its resolution,
+/// e.g. a page of 2480x3508 pixels at 300 DPI is A4.
+///
+/// The pages are decoded and encoded strip by strip, see
+/// [`tiff_utils::for_each_page`] for the `memory_budget` (in bytes). Only the
+/// compressed pages are kept until the PDF is written.
+pub fn write_image_pdf(
+    tif: &Path,
+    output: &Path,
+    jpeg_quality: u8,
+    memory_budget: usize,
+) -> Result<()> {
+    write_pdf(tif, output, jpeg_quality, memory_budget, None)
+}
+
+/// Write the pages of a (multi-page) TIFF file to a PDF like
+/// [`write_image_pdf`], with the recognized words of every page as invisible
+/// text layer, so that the text can be searched and copied
+pub fn write_text_pdf(
+    tif: &Path,
+    output: &Path,
+    jpeg_quality: u8,
+    memory_budget: usize,
+    text: &[hocr::Page],
+) -> Result<()> {
+    write_pdf(tif, output, jpeg_quality, memory_budget, Some(text))
+}
+
+fn write_pdf(
+    tif: &Path,
+    output: &Path,
+    jpeg_quality: u8,
+    memory_budget: usize,
+    text: Option<&[hocr::Page]>,
+) -> Result<()> {
+    let mut document = Document::with_version("1.4");
+    let pages_id = document.new_object_id();
+    let font_id = text.map(|_| add_font(&mut document));
+    let mut kids: Vec<Object> = Vec::new();
+    tiff_utils::for_each_page(tif, memory_budget, |page| {
+        trace!("Adding page {} to {:?}", kids.len() + 1, output);
+        let words = text.map(|text| text.get(kids.len()).unwrap_or(&EMPTY_PAGE));
+        let id = add_page(
+            &mut document,
+            pages_id,
+            page,
+            jpeg_quality,
+            words.zip(font_id),
+        )?;
+        kids.push(id.into());
+        Ok(())
+    })
+    .with_context(|| format!("Failed to convert {:?} to PDF", tif))?;
+
+    let count = kids.len() as i64;
+    document.objects.insert(
+        pages_id,
+        dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count }.into(),
+    );
+    let catalog_id = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    document.trailer.set("Root", catalog_id);
+
+    let file =
+        File::create(output).with_context(|| format!("Failed to create PDF {:?}", output))?;
+    let mut writer = BufWriter::new(file);
+    document
+        .save_to(&mut writer)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| Ok(writer.flush()?))
+        .with_context(|| format!("Failed to write PDF {:?}", output))
+}
+
+/// Text of a page without recognized words
+static EMPTY_PAGE: hocr::Page = hocr::Page {
+    width: 0,
+    height: 0,
+    words: Vec::new(),
+};
+
+/// Add a page with its content stream and image to the document, return the
+/// ID of the page
+///
+/// The `words` are placed as invisible text over the image, with the font of
+/// the text layer.
+fn add_page(
+    document: &mut Document,
+    pages_id: ObjectId,
+    mut page: Page,
+    jpeg_quality: u8,
+    words: Option<(&hocr::Page, ObjectId)>,
+) -> Result<ObjectId> {
+    let (dpi_x, dpi_y) = page.dpi.unwrap_or((DEFAULT_DPI, DEFAULT_DPI));
+    let width = page.width as f32 / dpi_x * 72.0;
+    let height = page.height as f32 / dpi_y * 72.0;
+
+    let (color_space, bits, filter, data) = match page.format {
+        PixelFormat::Bilevel => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            while let Some(rows) = page.read_rows()? {
+                encoder.write_all(&rows)?;
+            }
+            ("DeviceGray", 1, "FlateDecode", encoder.finish()?)
+        }
+        PixelFormat::Gray | PixelFormat::Rgb => {
+            let color_space = match page.format {
+                PixelFormat::Rgb => "DeviceRGB",
+                _ => "DeviceGray",
+            };
+            (
+                color_space,
+                8,
+                "DCTDecode",
+                encode_jpeg(&mut page, jpeg_quality)?,
+            )
+        }
+    };
+    let image_id = document.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => page.width as i64,
+            "Height" => page.height as i64,
+            "ColorSpace" => color_space,
+            "BitsPerComponent" => bits,
+            "Filter" => filter,
+        },
+        data,
+    ));
+
+    let mut content = format!("q {width:.2} 0 0 {height:.2} 0 0 cm /Im0 Do Q");
+    let mut resources = dictionary! { "XObject" => dictionary! { "Im0" => image_id } };
+    if let Some((words, font_id)) = words {
+        content.push_str(&text_layer(words, width, height));
+        resources.set("Font", dictionary! { "F0" => font_id });
+    }
+    let content_id = document.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+    Ok(document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Resources" => resources,
+        "Contents" => content_id,
+    }))
+}
+
+/// Encode a grayscale or RGB page as JPEG (1-100), reading its rows while they
+/// are encoded
+///
+/// Color pages are not subsampled, to keep colored text sharp.
+fn encode_jpeg(page: &mut Page, quality: u8) -> Result<Vec<u8>> {
+    let width = u16::try_from(page.width).context("Page is too wide for JPEG")?;
+    let height = u16::try_from(page.height).context("Page is too high for JPEG")?;
+    let color_type = match page.format {
+        PixelFormat::Rgb => JpegColorType::Ycbcr,
+        _ => JpegColorType::Luma,
+    };
+    let rows = PageRows {
+        width,
+        height,
+        color_type,
+        state: RefCell::new(PageRowsState {
+            page,
+            rows: Vec::new(),
+            first_row: 0,
+            error: None,
+        }),
+    };
+    let mut jpeg = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg, quality.clamp(1, 100));
+    encoder.set_sampling_factor(SamplingFactor::F_1_1);
+    encoder.encode_image(&rows)?;
+    if let Some(e) = rows.state.into_inner().error {
+        return Err(e);
+    }
+    Ok(jpeg)
+}
+
+/// The rows of a page for the JPEG encoder, which requests them in order
+/// (repeating the last row to pad the last blocks)
+struct PageRows<'p, 'a> {
+    width: u16,
+    height: u16,
+    color_type: JpegColorType,
+    state: RefCell<PageRowsState<'p, 'a>>,
+}
+
+struct PageRowsState<'p, 'a> {
+    page: &'p mut Page<'a>,
+    /// The rows that were read last, starting with row `first_row`
+    rows: Vec<u8>,
+    first_row: usize,
+    /// The first error while reading rows, the missing rows are black
+    error: Option<anyhow::Error>,
+}
+
+impl ImageBuffer for &PageRows<'_, '_> {
+    fn get_jpeg_color_type(&self) -> JpegColorType {
+        self.color_type
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+        let channels = match self.color_type {
+            JpegColorType::Ycbcr => 3,
+            _ => 1,
+        };
+        let row_bytes = usize::from(self.width) * channels;
+        let y = usize::from(y);
+        let mut state = self.state.borrow_mut();
+        while state.error.is_none() && y >= state.first_row + state.rows.len() / row_bytes {
+            state.first_row += state.rows.len() / row_bytes;
+            match state.page.read_rows() {
+                Ok(Some(rows)) => state.rows = rows,
+                Ok(None) => state.error = Some(anyhow!("Page ended before row {y}")),
+                Err(e) => state.error = Some(e),
+            }
+        }
+        let start = y.saturating_sub(state.first_row) * row_bytes;
+        let row = state.rows.get(start..start + row_bytes).unwrap_or_default();
+        if row.len() < row_bytes {
+            for buffer in buffers.iter_mut().take(channels) {
+                buffer.extend(std::iter::repeat_n(0, usize::from(self.width)));
+            }
+        } else if channels == 3 {
+            for pixel in row.chunks_exact(3) {
+                let (y, cb, cr) = rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]);
+                buffers[0].push(y);
+                buffers[1].push(cb);
+                buffers[2].push(cr);
+            }
+        } else {
+            buffers[0].extend_from_slice(row);
+        }
+    }
+}
+
+/// Content stream operators that place every word invisibly (text rendering
+/// mode 3) over its bounding box on a page of `width` x `height` points
+///
+/// The words are stretched horizontally to the width of their bounding box
+/// and followed by a space, so that text extraction separates them.
+fn text_layer(words: &hocr::Page, width: f32, height: f32) -> String {
+    if words.words.is_empty() || words.width == 0 || words.height == 0 {
+        return String::new();
+    }
+    let scale_x = width / words.width as f32;
+    let scale_y = height / words.height as f32;
+    let mut content = String::from("\nBT 3 Tr");
+    for word in &words.words {
+        let [left, top, right, bottom] = word.bbox;
+        let size = bottom.saturating_sub(top) as f32 * scale_y;
+        let chars = word.text.chars().count() as f32;
+        if size <= 0.0 || right <= left {
+            continue;
+        }
+        let natural_width = chars * GLYPH_WIDTH / 1000.0 * size;
+        let stretch = (right - left) as f32 * scale_x / natural_width * 100.0;
+        let glyphs: String = word
+            .text
+            .chars()
+            .chain([' '])
+            .map(|c| {
+                // Characters outside of the Basic Multilingual Plane cannot be
+                // mapped by the 2 byte codes of the font
+                let code = u16::try_from(u32::from(c)).unwrap_or(0xfffd);
+                format!("{code:04X}")
+            })
+            .collect();
+        content.push_str(&format!(
+            "\n/F0 {size:.2} Tf {stretch:.2} Tz 1 0 0 1 {:.2} {:.2} Tm <{glyphs}> Tj",
+            left as f32 * scale_x,
+            height - bottom as f32 * scale_y,
+        ));
+    }
+    content.push_str("\nET");
+    content
+}
+
+/// Add the font of the text layer: A font without glyphs whose codes are the
+/// UTF-16 code units of the text, return the ID of the font
+fn add_font(document: &mut Document) -> ObjectId {
+    let descriptor_id = document.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "GlyphLessFont",
+        "Flags" => 5,
+        "FontBBox" => vec![0.into(), 0.into(), GLYPH_WIDTH.into(), 1000.into()],
+        "ItalicAngle" => 0,
+        "Ascent" => 1000,
+        "Descent" => 0,
+        "CapHeight" => 1000,
+        "StemV" => 80,
+    });
+    let cid_font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => "GlyphLessFont",
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => descriptor_id,
+        "DW" => GLYPH_WIDTH,
+        "CIDToGIDMap" => "Identity",
+    });
+
+    // Map every code to the same Unicode value (ranges may only differ in the
+    // last byte, at most 100 ranges per block)
+    let ranges: Vec<String> = (0..=0xffu32)
+        .filter(|high| !(0xd8..=0xdf).contains(high))
+        .map(|high| format!("<{high:02X}00> <{high:02X}FF> <{high:02X}00>"))
+        .collect();
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    for block in ranges.chunks(100) {
+        cmap.push_str(&format!(
+            "{} beginbfrange\n{}\nendbfrange\n",
+            block.len(),
+            block.join("\n")
+        ));
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend");
+    let to_unicode_id = document.add_object(Stream::new(dictionary! {}, cmap.into_bytes()));
+
+    document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "GlyphLessFont",
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![cid_font_id.into()],
+        "ToUnicode" => to_unicode_id,
+    })
+}
+
+/// Set entries of the document info of a PDF (e.g. `Keywords`), keeping the
+/// other entries
+///
+/// The PDF is updated incrementally: the new document info and a
+/// cross-reference section (a table or a stream, like the last one of the
+/// file) are appended. The XMP metadata of the PDF is not updated.
+pub fn set_info(path: &Path, entries: &[(&str, &str)]) -> Result<()> {
+    let data = fs_utils::retry_stale(|| fs::read(path))
+        .with_context(|| format!("Failed to read PDF {path:?}"))?;
+    let updated = with_info(data, entries)
+        .with_context(|| format!("Failed to update the document info of {path:?}"))?;
+    fs_utils::write_synced(path, updated).with_context(|| format!("Failed to write PDF {path:?}"))
+}
+
+/// Replace the bookmarks (outline) of a PDF with a flat list of `bookmarks`,
+/// given as title and index of the page (starting at 0), and show them when
+/// the PDF is opened
+///
+/// Unlike [`set_info`], this rewrites the whole PDF.
+pub fn set_outline(path: &Path, bookmarks: &[(&str, usize)]) -> Result<()> {
+    let mut document =
+        Document::load(path).with_context(|| format!("Failed to read PDF {path:?}"))?;
+    let pages: Vec<ObjectId> = document.page_iter().collect();
+    let outline_id = document.new_object_id();
+    let item_ids: Vec<ObjectId> = bookmarks.iter().map(|_| document.new_object_id()).collect();
+    for (index, (title, page)) in bookmarks.iter().enumerate() {
+        let page_id = *pages
+            .get(*page)
+            .with_context(|| format!("{path:?} has no page {}", page + 1))?;
+        let mut item = dictionary! {
+            "Title" => lopdf::text_string(title),
+            "Parent" => outline_id,
+            "Dest" => vec![page_id.into(), "Fit".into()],
+        };
+        if index > 0 {
+            item.set("Prev", item_ids[index - 1]);
+        }
+        if let Some(next) = item_ids.get(index + 1) {
+            item.set("Next", *next);
+        }
+        document
+            .objects
+            .insert(item_ids[index], Object::Dictionary(item));
+    }
+    let mut outline = dictionary! {
+        "Type" => "Outlines",
+        "Count" => item_ids.len() as i64,
+    };
+    if let (Some(first), Some(last)) = (item_ids.first(), item_ids.last()) {
+        outline.set("First", *first);
+        outline.set("Last", *last);
+    }
+    document
+        .objects
+        .insert(outline_id, Object::Dictionary(outline));
+    let catalog = document.catalog_mut()?;
+    catalog.set("Outlines", outline_id);
+    catalog.set("PageMode", "UseOutlines");
+
+    let mut data = Vec::new();
+    document.save_to(&mut data)?;
+    fs_utils::write_synced(path, data).with_context(|| format!("Failed to write PDF {path:?}"))
+}
+
+/// Whether a PDF contains XMP metadata (e.g. of PDF/A), which has to match
+/// the document info
+pub fn has_xmp_metadata(path: &Path) -> Result<bool> {
+    let document = Document::load(path).with_context(|| format!("Failed to read PDF {path:?}"))?;
+    Ok(document.catalog()?.has(b"Metadata"))
+}
+
+/// The PDF `data` with an incremental update that sets entries of its
+/// document info
+///
+/// The document info may be stored anywhere, also in an object stream. If the
+/// trailer refers to a document info that doesn't exist, the PDF is not
+/// updated, so that its other entries aren't lost.
+fn with_info(data: Vec<u8>, entries: &[(&str, &str)]) -> Result<Vec<u8>> {
+    let previous = Document::load_mem(&data)?;
+    let mut document = IncrementalDocument::create_from(data, previous);
+    let info_id = match document.get_prev_documents().trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => {
+            let id = *id;
+            document
+                .opt_clone_object_to_new_document(id)
+                .with_context(|| format!("The document info {} {} R is missing", id.0, id.1))?;
+            id
+        }
+        Ok(Object::Dictionary(info)) => {
+            let info = info.clone();
+            document.new_document.add_object(info)
+        }
+        Ok(_) => bail!("The document info is not a dictionary"),
+        Err(_) => document.new_document.add_object(Dictionary::new()),
+    };
+    let info = document
+        .new_document
+        .get_object_mut(info_id)?
+        .as_dict_mut()
+        .context("The document info is not a dictionary")?;
+    for (key, text) in entries {
+        info.set(*key, lopdf::text_string(text));
+    }
+
+    // The trailer is copied from the last cross-reference section, which may
+    // be a stream with its own encoding or point to a hybrid one
+    let trailer = &mut document.new_document.trailer;
+    trailer.set("Info", info_id);
+    trailer.remove(b"DecodeParms");
+    trailer.remove(b"XRefStm");
+    let mut updated = Vec::new();
+    document.save_to(&mut updated)?;
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lopdf::SaveOptions;
+    use tempfile::TempDir;
+    use tiff::{
+        encoder::{Rational, TiffEncoder, colortype},
+        tags::ResolutionUnit,
+    };
+
+    /// The dictionaries of the pages of a PDF, and their images
+    fn pages(document: &Document) -> Vec<(&lopdf::Dictionary, &Stream)> {
+        document
+            .page_iter()
+            .map(|id| {
+                let page = document.get_dictionary(id).unwrap();
+                let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+                let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+                let image_id = xobjects.get(b"Im0").unwrap().as_reference().unwrap();
+                let image = document.get_object(image_id).unwrap().as_stream().unwrap();
+                (page, image)
+            })
+            .collect()
+    }
+
+    /// The size of a page in points
+    fn media_box(page: &lopdf::Dictionary) -> Vec<f32> {
+        page.get(b"MediaBox")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_float().unwrap())
+            .collect()
+    }
+
+    /// Ensure that every page is embedded with the size given by its
+    /// resolution, and that grayscale pages are embedded as JPEG.
+    #[test]
+    fn image_pdf() {
+        let temp_dir = TempDir::new().unwrap();
+        let tif = temp_dir.path().join("combined.tif");
+        let pdf = temp_dir.path().join("combined.pdf");
+        {
+            let file = File::create(&tif).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(30, 60).unwrap();
+            image.resolution(ResolutionUnit::Inch, Rational { n: 10, d: 1 });
+            image.write_data(&[200; 30 * 60]).unwrap();
+            encoder
+                .write_image::<colortype::RGB8>(20, 10, &[90; 20 * 10 * 3])
+                .unwrap();
+        }
+        write_image_pdf(&tif, &pdf, 80, usize::MAX).unwrap();
+
+        let document = Document::load(&pdf).unwrap();
+        let pages = pages(&document);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(media_box(pages[0].0), [0.0, 0.0, 216.0, 432.0]);
+        assert_eq!(media_box(pages[1].0), [0.0, 0.0, 4.8, 2.4]);
+        let color_spaces: Vec<&[u8]> = pages
+            .iter()
+            .map(|(_, image)| image.dict.get(b"ColorSpace").unwrap().as_name().unwrap())
+            .collect();
+        assert_eq!(color_spaces, [b"DeviceGray".as_slice(), b"DeviceRGB"]);
+
+        // The JPEG has the size and the gray value of the page
+        let (_, image) = pages[0];
+        assert_eq!(
+            image.dict.get(b"Filter").unwrap().as_name().unwrap(),
+            b"DCTDecode"
+        );
+        let mut decoder = zune_jpeg::JpegDecoder::new(image.content.as_slice());
+        let pixels = decoder.decode().unwrap();
+        assert_eq!(decoder.dimensions(), Some((30, 60)));
+        assert!(pixels.iter().all(|&value| value.abs_diff(200) <= 2));
+    }
+
+    /// Ensure that the rows of a color page are read strip by strip while it
+    /// is encoded, without subsampling the colors.
+    #[test]
+    fn color_strips() {
+        let temp_dir = TempDir::new().unwrap();
+        let tif = temp_dir.path().join("combined.tif");
+        let pdf = temp_dir.path().join("combined.pdf");
+        let rgb: Vec<u8> = (0..40 * 300)
+            .flat_map(|i| [(i / 40) as u8, 0, 255 - (i / 40) as u8])
+            .collect();
+        {
+            let file = File::create(&tif).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            encoder
+                .write_image::<colortype::RGB8>(40, 300, &rgb)
+                .unwrap();
+        }
+        // Far less memory than the page, so that it is read in several strips
+        write_image_pdf(&tif, &pdf, 95, 1000).unwrap();
+
+        let document = Document::load(&pdf).unwrap();
+        let (_, image) = pages(&document)[0];
+        let mut decoder = zune_jpeg::JpegDecoder::new(image.content.as_slice());
+        let pixels = decoder.decode().unwrap();
+        assert_eq!(decoder.dimensions(), Some((40, 300)));
+        let max_error = pixels
+            .iter()
+            .zip(&rgb)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        assert!(max_error <= 12, "max error {max_error}");
+    }
+
+    /// Ensure that the recognized words are placed invisibly over their
+    /// bounding box, with the font of the text layer.
+    #[test]
+    fn text_pdf() {
+        let temp_dir = TempDir::new().unwrap();
+        let tif = temp_dir.path().join("combined.tif");
+        let pdf = temp_dir.path().join("combined.pdf");
+        {
+            let file = File::create(&tif).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(200, 100).unwrap();
+            image.resolution(ResolutionUnit::Inch, Rational { n: 100, d: 1 });
+            image.write_data(&[255; 200 * 100]).unwrap();
+        }
+        let words = hocr::Page {
+            width: 200,
+            height: 100,
+            words: vec![hocr::Word {
+                bbox: [50, 20, 90, 45],
+                confidence: Some(90.0),
+                text: "Öl".into(),
+            }],
+        };
+        write_text_pdf(&tif, &pdf, 80, usize::MAX, &[words]).unwrap();
+
+        let document = Document::load(&pdf).unwrap();
+        let page_id = document.page_iter().next().unwrap();
+        let fonts = document.get_page_fonts(page_id).unwrap();
+        let font = fonts.get(b"F0".as_slice()).unwrap();
+        assert_eq!(font.get(b"Subtype").unwrap().as_name().unwrap(), b"Type0");
+        assert_eq!(
+            font.get(b"BaseFont").unwrap().as_name().unwrap(),
+            b"GlyphLessFont"
+        );
+        let to_unicode = font.get(b"ToUnicode").unwrap().as_reference().unwrap();
+        let cmap = document
+            .get_object(to_unicode)
+            .unwrap()
+            .as_stream()
+            .unwrap();
+        let cmap = String::from_utf8_lossy(&cmap.content);
+        assert!(cmap.contains("<0000> <00FF> <0000>"));
+        assert!(!cmap.contains("<D800>"));
+
+        // 25 px at 100 DPI are 18 pt, the word is stretched from 18 pt to 28.8 pt
+        let content = String::from_utf8(document.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.contains(
+            "BT 3 Tr\n/F0 18.00 Tf 160.00 Tz 1 0 0 1 36.00 39.60 Tm <00D6006C0020> Tj\nET"
+        ));
+    }
+
+    /// Ensure that the document info of a PDF with a cross-reference table is
+    /// added and then updated, keeping its other entries.
+    #[test]
+    fn document_info() {
+        let temp_dir = TempDir::new().unwrap();
+        let tif = temp_dir.path().join("combined.tif");
+        let pdf = temp_dir.path().join("combined.pdf");
+        {
+            let file = File::create(&tif).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            encoder
+                .write_image::<colortype::Gray8>(10, 10, &[0; 100])
+                .unwrap();
+        }
+        write_image_pdf(&tif, &pdf, 80, usize::MAX).unwrap();
+        assert!(!has_xmp_metadata(&pdf).unwrap());
+        let original = std::fs::read(&pdf).unwrap();
+
+        set_info(&pdf, &[("Keywords", "tax"), ("Title", "Invoice")]).unwrap();
+        set_info(&pdf, &[("Keywords", "tax, Gebühr")]).unwrap();
+
+        // The PDF is updated incrementally
+        assert!(std::fs::read(&pdf).unwrap().starts_with(&original));
+        let document = Document::load(&pdf).unwrap();
+        let info = document
+            .trailer
+            .get(b"Info")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let info = document.get_dictionary(info).unwrap();
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Keywords").unwrap()).unwrap(),
+            "tax, Gebühr"
+        );
+        assert_eq!(info.get(b"Title").unwrap().as_str().unwrap(), b"Invoice");
+        assert_eq!(document.page_iter().count(), 1);
+    }
+
+    /// Ensure that the bookmarks point to the given pages, in order.
+    #[test]
+    fn outline() {
+        let temp_dir = TempDir::new().unwrap();
+        let tif = temp_dir.path().join("combined.tif");
+        let pdf = temp_dir.path().join("combined.pdf");
+        {
+            let file = File::create(&tif).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            for _ in 0..3 {
+                encoder
+                    .write_image::<colortype::Gray8>(10, 10, &[0; 100])
+                    .unwrap();
+            }
+        }
+        write_image_pdf(&tif, &pdf, 80, usize::MAX).unwrap();
+        set_outline(&pdf, &[("Invoice", 0), ("Prämie", 2)]).unwrap();
+        assert!(set_outline(&pdf, &[("Missing", 3)]).is_err());
+
+        let document = Document::load(&pdf).unwrap();
+        let pages: Vec<ObjectId> = document.page_iter().collect();
+        let catalog = document.catalog().unwrap();
+        assert_eq!(
+            catalog.get(b"PageMode").unwrap().as_name().unwrap(),
+            b"UseOutlines"
+        );
+        let outline = catalog.get(b"Outlines").unwrap().as_reference().unwrap();
+        let outline = document.get_dictionary(outline).unwrap();
+        assert_eq!(outline.get(b"Count").unwrap().as_i64().unwrap(), 2);
+        let mut item_id = outline.get(b"First").unwrap().as_reference().unwrap();
+        let mut bookmarks = Vec::new();
+        loop {
+            let item = document.get_dictionary(item_id).unwrap();
+            let title = lopdf::decode_text_string(item.get(b"Title").unwrap()).unwrap();
+            let destination = item.get(b"Dest").unwrap().as_array().unwrap();
+            let page = destination[0].as_reference().unwrap();
+            bookmarks.push((title, pages.iter().position(|id| *id == page).unwrap()));
+            match item.get(b"Next") {
+                Ok(next) => item_id = next.as_reference().unwrap(),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(
+            bookmarks,
+            vec![("Invoice".to_string(), 0), ("Prämie".to_string(), 2)]
+        );
+    }
+
+    /// Ensure that the document info is updated when it is stored in an
+    /// object stream (as by qpdf, pikepdf and OCRmyPDF), keeping its other
+    /// entries, and that a missing document info is reported.
+    #[test]
+    fn document_info_in_object_stream() {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }.into(),
+        );
+        let catalog_id =
+            document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        let info_id = document.add_object(dictionary! {
+            "Producer" => Object::string_literal("OCRmyPDF"),
+            "Title" => Object::string_literal("Invoice"),
+        });
+        document.trailer.set("Root", catalog_id);
+        document.trailer.set("Info", info_id);
+        let mut data = Vec::new();
+        document
+            .save_with_options(
+                &mut data,
+                SaveOptions::builder()
+                    .use_object_streams(true)
+                    .use_xref_streams(true)
+                    .build(),
+            )
+            .unwrap();
+        let loaded = Document::load_mem(&data).unwrap();
+        assert!(matches!(
+            loaded.reference_table.get(info_id.0),
+            Some(lopdf::xref::XrefEntry::Compressed { .. })
+        ));
+
+        let updated = with_info(data.clone(), &[("Keywords", "tax, Gebühr")]).unwrap();
+        assert!(updated.starts_with(&data));
+        let document = Document::load_mem(&updated).unwrap();
+        let info = document
+            .trailer
+            .get(b"Info")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let info = document.get_dictionary(info).unwrap();
+        assert_eq!(
+            info.get(b"Producer").unwrap().as_str().unwrap(),
+            b"OCRmyPDF"
+        );
+        assert_eq!(info.get(b"Title").unwrap().as_str().unwrap(), b"Invoice");
+        assert_eq!(
+            lopdf::decode_text_string(info.get(b"Keywords").unwrap()).unwrap(),
+            "tax, Gebühr"
+        );
+        assert_eq!(document.page_iter().count(), 1);
+
+        // A reference to a document info that doesn't exist
+        let mut document = Document::load_mem(&data).unwrap();
+        document.trailer.set("Info", (99, 0));
+        let mut broken = Vec::new();
+        document.save_to(&mut broken).unwrap();
+        assert!(with_info(broken, &[("Keywords", "tax")]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{fs, path::Path, process::Command};
+
+use tracing::{debug, trace};
+
+use crate::config::ProcessingConfig;
+
+/// Determine whether heavy processing should be deferred, based on the power
+/// and load thresholds in the config.
+///
+/// Returns the reason for deferring, or `None` if processing may start.
+pub fn defer_reason(config: &ProcessingConfig) -> Option<String> {
+    if config.defer_on_battery && on_battery() {
+        return Some("running on battery power".into());
+    }
+    if let Some(max_load) = config.max_load_average
+        && let Some(load) = load_average()
+    {
+        trace!("Current load average: {load}");
+        if load > max_load {
+            return Some(format!(
+                "system load ({load:.2}) exceeds configured maximum ({max_load:.2})"
+            ));
+        }
+    }
+    None
+}
+
+/// Whether the system is running on battery power
+///
+/// If the power state cannot be determined, `false` is returned.
+pub fn on_battery() -> bool {
+    if cfg!(target_os = "macos") {
+        match Command::new("pmset").arg("-g").arg("batt").output() {
+            Ok(output) => parse_pmset(&String::from_utf8_lossy(&output.stdout)),
+            Err(e) => {
+                debug!("Failed to run `pmset`: {e}");
+                false
+            }
+        }
+    } else {
+        on_battery_sysfs(Path::new("/sys/class/power_supply"))
+    }
+}
+
+/// Determine the battery state from the Linux sysfs power supply class.
+///
+/// The system is considered to run on battery if there is at least one mains
+/// power supply, but none of them is online.
+fn on_battery_sysfs(power_supply_dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(power_supply_dir) else {
+        return false;
+    };
+    let mut has_mains = false;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let supply_type = fs::read_to_string(path.join("type")).unwrap_or_default();
+        if supply_type.trim() != "Mains" {
+            continue;
+        }
+        has_mains = true;
+        let online = fs::read_to_string(path.join("online")).unwrap_or_default();
+        if online.trim() == "1" {
+            return false;
+        }
+    }
+    has_mains
+}
+
+/// Parse the output of `pmset -g batt` (macOS)
+fn parse_pmset(output: &str) -> bool {
+    output.contains("'Battery Power'")
+}
+
+/// The 1-minute system load average, if available
+pub fn load_average() -> Option<f32> {
+    if cfg!(target_os = "macos") {
+        let output = Command::new("sysctl")
+            .arg("-n")
+            .arg("vm.loadavg")
+            .output()
+            .ok()?;
+        parse_load_average(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        parse_load_average(&fs::read_to_string("/proc/loadavg").ok()?)
+    }
+}
+
+/// Parse the first number from `/proc/loadavg` or `sysctl -n vm.loadavg`
+fn parse_load_average(loadavg: &str) -> Option<f32> {
+    loadavg
+        .split_whitespace()
+        .find(|part| *part != "{")?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn add_supply(dir: &Path, name: &str, supply_type: &str, online: Option<&str>) {
+        let supply_dir = dir.join(name);
+        fs::create_dir(&supply_dir).unwrap();
+        fs::write(supply_dir.join("type"), format!("{supply_type}\n")).unwrap();
+        if let Some(online) = online {
+            fs::write(supply_dir.join("online"), format!("{online}\n")).unwrap();
+        }
+    }
+
+    /// Ensure that a laptop with an unplugged AC adapter is on battery.
+    #[test]
+    fn sysfs_on_battery() {
+        let temp_dir = TempDir::new().unwrap();
+        add_supply(temp_dir.path(), "AC", "Mains", Some("0"));
+        add_supply(temp_dir.path(), "BAT0", "Battery", None);
+        assert!(on_battery_sysfs(temp_dir.path()));
+    }
+
+    /// Ensure that a laptop with a plugged in AC adapter is not on battery.
+    #[test]
+    fn sysfs_on_ac() {
+        let temp_dir = TempDir::new().unwrap();
+        add_supply(temp_dir.path(), "AC", "Mains", Some("1"));
+        add_supply(temp_dir.path(), "BAT0", "Battery", None);
+        assert!(!on_battery_sysfs(temp_dir.path()));
+    }
+
+    /// Ensure that systems without power supply information (e.g. desktops)
+    /// are not considered to be on battery.
+    #[test]
+    fn sysfs_no_supplies() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(!on_battery_sysfs(temp_dir.path()));
+        assert!(!on_battery_sysfs(&temp_dir.path().join("missing")));
+    }
+
+    /// Ensure that the power source is detected from the `pmset` output.
+    #[test]
+    fn parse_pmset_output() {
+        assert!(parse_pmset(
+            "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=1)\t85%; discharging"
+        ));
+        assert!(!parse_pmset(
+            "Now drawing from 'AC Power'\n -InternalBattery-0 (id=1)\t100%; charged"
+        ));
+    }
+
+    /// Ensure that the load average is parsed on Linux and macOS.
+    #[test]
+    fn parse_load_averages() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 12345\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average("{ 1.75 1.60 1.55 }\n"), Some(1.75));
+        assert_eq!(parse_load_average(""), None);
+    }
+}
@@ -1,28 +1,289 @@
-use std::{fs, path::Path, process::Command};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use anyhow::{Context, Result, anyhow};
-use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use indicatif::{ProgressBar, ProgressFinish};
 use tracing::{debug, warn};
 
-/// Process scanned files in a directory.
-pub fn process_document(directory: &Path) -> Result<()> {
-    debug!("Processing directory {directory:?}");
+use crate::{
+    calibration, compression,
+    config::{Config, DateOrder, TiffCompression},
+    events::{self, Event},
+    fs_utils, geometry, hocr,
+    manifest::{Manifest, PipelineStep, ScanSource},
+    ocr::{self, Engine},
+    pdf, queue,
+    scheduler::{self, JobKind},
+    statements, streaks, tiff_utils,
+    timings::Timings,
+    ui,
+};
 
-    // TODO: Check dependencies at setup time
+/// Return the page number of a scanned page filename (e.g. 12 for `0012.tif`)
+///
+/// Processed or combined files (e.g. `0012_processed.tif`) are not pages.
+pub fn page_number(filename: &str) -> Option<u64> {
+    let stem = filename.strip_suffix(".tif")?;
+    if stem.is_empty() || !stem.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    stem.parse().ok()
+}
 
-    // Collect all unprocessed TIFF files
-    let tifs_step0: Vec<String> = fs::read_dir(directory)
-        .expect("Failed to read directory")
-        .filter_map(|entry| {
-            let entry = entry.expect("Failed to read directory entry");
-            let filename = entry.file_name().into_string().unwrap();
-            if filename.ends_with(".tif") && !filename.contains('_') {
-                Some(filename)
-            } else {
+/// Filter the scanned page TIFFs from a list of filenames, sorted numerically
+/// by page number
+fn page_tifs(filenames: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut pages: Vec<(u64, String)> = filenames
+        .into_iter()
+        .filter_map(|filename| Some((page_number(&filename)?, filename)))
+        .collect();
+    pages.sort();
+    pages.into_iter().map(|(_, filename)| filename).collect()
+}
+
+/// Collect the scanned page TIFFs in a directory, sorted by page number
+///
+/// The order of `fs::read_dir` depends on the filesystem, so the pages must
+/// always be sorted explicitly. Entries that cannot be read, or whose names are
+/// not valid UTF-8, are skipped with a warning.
+pub fn collect_page_tifs(directory: &Path) -> Result<Vec<String>> {
+    let entries = fs_utils::retry_stale(|| fs::read_dir(directory))
+        .with_context(|| format!("Failed to read directory {directory:?}"))?;
+    let filenames = entries.filter_map(|entry| match entry {
+        Ok(entry) => match entry.file_name().into_string() {
+            Ok(filename) => Some(filename),
+            Err(filename) => {
+                warn!("Skipping file with non-UTF-8 name {filename:?} in {directory:?}");
                 None
             }
+        },
+        Err(e) => {
+            warn!("Skipping unreadable directory entry in {directory:?}: {e}");
+            None
+        }
+    });
+    Ok(page_tifs(filenames))
+}
+
+/// Options for processing a single run, resolved from the command line and the
+/// selected profile
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// Skip OCR and produce an image-only PDF
+    pub skip_ocr: bool,
+    /// Keep the original contrast of the pages
+    pub skip_contrast: bool,
+    /// Don't straighten the pages
+    pub skip_deskew: bool,
+    /// Also create a small copy of the final PDF for emailing
+    pub email_copy: bool,
+    /// OCR languages to use instead of the detected ones
+    pub ocr_languages: Option<Vec<String>>,
+}
+
+/// Warning recorded if OCR found no text at all
+pub const NO_TEXT_WARNING: &str = "No text recognized";
+
+/// Warning recorded if OCR found only a few characters
+pub const SPARSE_TEXT_WARNING: &str = "Almost no text recognized";
+
+/// Minimal number of letters and digits per page that OCR is expected to find
+const MIN_CHARS_PER_PAGE: usize = 20;
+
+/// Number of pages whose text is used to detect the languages of a document
+const LANGUAGE_SAMPLE_PAGES: usize = 3;
+
+/// Whether the pages are straightened (see `geometry::deskew`)
+fn deskew_enabled(config: &Config, options: &ProcessOptions) -> bool {
+    config.processing.deskew && !options.skip_deskew
+}
+
+/// Process scanned files in a directory.
+///
+/// The duration of every processing step is recorded in `timings`. Problems
+/// that were detected during processing are recorded in the manifest, and the
+/// document is marked for review.
+pub fn process_document(
+    directory: &Path,
+    config: &Config,
+    options: &ProcessOptions,
+    timings: &mut Timings,
+) -> Result<()> {
+    let mut warnings = Vec::new();
+    let start = Instant::now();
+    let pages = match run_pipeline(directory, config, options, &mut warnings, timings) {
+        Ok(pages) => pages,
+        Err(e) => {
+            events::emit(&Event::ProcessingFailed {
+                directory,
+                error: format!("{e:#}"),
+            });
+            return Err(e);
+        }
+    };
+    events::emit(&Event::ProcessingFinished {
+        directory,
+        pdf: &directory.join(queue::FINAL_PDF),
+    });
+
+    // Report the size of the final PDF
+    match fs::metadata(directory.join(queue::FINAL_PDF)) {
+        Ok(metadata) => {
+            let size = metadata.len();
+            println!("Final PDF: {}", size_summary(size, pages));
+            if let Some(max_mb) = config.processing.max_pdf_size_mb
+                && size as f64 > f64::from(max_mb) * MIB
+            {
+                warnings.push(format!(
+                    "The final PDF is larger than {max_mb} MiB ({}). Consider lowering \
+                     `jpeg_quality` or scanning at a lower resolution.",
+                    size_summary(size, pages)
+                ));
+            }
+        }
+        Err(e) => debug!("Failed to determine size of final PDF: {e}"),
+    }
+
+    // Create a smaller copy for emailing, replacing one of an earlier run
+    let email_pdf = directory.join(queue::EMAIL_PDF);
+    if let Err(e) = fs::remove_file(&email_pdf)
+        && e.kind() != ErrorKind::NotFound
+    {
+        warn!("Failed to remove {email_pdf:?}: {e}");
+    }
+    let mut outputs = vec![PathBuf::from(queue::FINAL_PDF)];
+    if options.email_copy {
+        match timings.measure("Create email copy", || {
+            create_email_copy(directory, config, options)
+        }) {
+            Ok(()) => {
+                if let Ok(metadata) = fs::metadata(&email_pdf) {
+                    println!("Email copy: {}", size_summary(metadata.len(), pages));
+                }
+                outputs.push(queue::EMAIL_PDF.into());
+            }
+            Err(e) => warnings.push(format!("Failed to create the copy for emailing: {e:#}")),
+        }
+    }
+
+    let mut manifest = Manifest::load(directory)?;
+    for warning in warnings {
+        manifest.add_warning(warning);
+    }
+    manifest.outputs = outputs;
+    manifest.completed_steps.clear();
+    manifest.mark_processed();
+    if let Some(stats) = &mut manifest.scan_stats {
+        stats.process_secs = Some(start.elapsed().as_secs_f32());
+    }
+    manifest.save(directory)?;
+
+    // The raw pages are only needed again for rescans or reprocessing
+    if config.processing.compress_raw_pages
+        && let Err(e) = timings.measure("Compress raw pages", || {
+            compression::compress_pages(directory)
         })
-        .collect();
+    {
+        warn!("Failed to compress raw pages in {directory:?}: {e:#}");
+    }
+    Ok(())
+}
+
+/// Write a small copy of the final PDF for emailing to [`queue::EMAIL_PDF`]
+///
+/// Image-only PDFs are written again from the combined TIFF at a lower JPEG
+/// quality (also if OCRmyPDF is not available). Otherwise, OCRmyPDF optimizes
+/// the final PDF, keeping its text layer.
+fn create_email_copy(directory: &Path, config: &Config, options: &ProcessOptions) -> Result<()> {
+    match ocr::find_ocrmypdf(&config.ocr) {
+        Some(ocrmypdf) if !options.skip_ocr => {
+            ocr::optimize_for_email(&ocrmypdf, directory, &config.processing.limits)
+        }
+        _ => pdf::write_image_pdf(
+            &directory.join("_combined.tif"),
+            &directory.join(queue::EMAIL_PDF),
+            pdf::EMAIL_JPEG_QUALITY,
+            config.processing.memory_budget(),
+        ),
+    }
+}
+
+/// Bytes per mebibyte
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Describe the size of a PDF, including the average size per page
+fn size_summary(size: u64, pages: usize) -> String {
+    let format = |bytes: f64| {
+        if bytes >= MIB {
+            format!("{:.1} MiB", bytes / MIB)
+        } else {
+            format!("{:.0} KiB", bytes / 1024.0)
+        }
+    };
+    let size = size as f64;
+    format!(
+        "{} ({} per page)",
+        format(size),
+        format(size / pages.max(1) as f64)
+    )
+}
+
+/// Whether a step was completed by an earlier, interrupted run, and its
+/// outputs still exist
+fn step_completed(manifest: &Manifest, step: PipelineStep, outputs: &[impl AsRef<Path>]) -> bool {
+    let completed =
+        manifest.completed_steps.contains(&step) && outputs.iter().all(|o| o.as_ref().exists());
+    if completed {
+        debug!("Step {step:?} was already completed, skipping");
+    }
+    completed
+}
+
+/// Record that a step is completed, together with the warnings collected so
+/// far, so that processing can be resumed after an interruption
+fn complete_step(directory: &Path, step: PipelineStep, warnings: &[String]) -> Result<()> {
+    let mut manifest = Manifest::load(directory)?;
+    for warning in warnings {
+        manifest.add_warning(warning.as_str());
+    }
+    if !manifest.completed_steps.contains(&step) {
+        manifest.completed_steps.push(step);
+    }
+    manifest.save(directory)
+}
+
+/// Show the current processing step, and report it to frontends
+fn report_step(progress: &ProgressBar, directory: &Path, step: &str) {
+    progress.set_message(step.to_string());
+    events::emit(&Event::ProcessingStep {
+        directory,
+        step,
+        position: progress.position(),
+        length: progress.length().unwrap_or_default(),
+    });
+}
+
+/// Run all processing steps, collect warnings about the result
+fn run_pipeline(
+    directory: &Path,
+    config: &Config,
+    options: &ProcessOptions,
+    warnings: &mut Vec<String>,
+    timings: &mut Timings,
+) -> Result<usize> {
+    debug!("Processing directory {directory:?}");
+
+    // Restore pages that were compressed after an earlier run
+    compression::decompress_pages(directory)?;
+
+    // Collect all unprocessed TIFF files, in page order. On network file
+    // systems, freshly scanned pages may show up with a delay.
+    let tifs_step0 =
+        fs_utils::retry_listing(|| collect_page_tifs(directory), |tifs| !tifs.is_empty())?;
 
     // If no TIFF files are found, delete directory and return error
     if tifs_step0.is_empty() {
@@ -39,121 +300,684 @@ pub fn process_document(directory: &Path) -> Result<()> {
     // - Postprocessing of pages: n steps
     // - Combining TIFs: 1 step
     // - Converting to PDF: 1 step
-    // - OCRmyPDF: 1 step
-    let progress = ProgressBar::new(tifs_step0.len() as u64 + 4)
+    // - OCR: 1 step (unless skipped, before combining with Tesseract)
+    let ocr_engine = if options.skip_ocr {
+        None
+    } else {
+        ocr::select_engine(&config.ocr)
+    };
+    let steps = match ocr_engine {
+        None => 3,
+        Some(_) => 4,
+    };
+    let progress = ui::progress_bar(tifs_step0.len() as u64 + steps)
         .with_message(format!("Processing directory {directory:?}"))
-        .with_style(ProgressStyle::with_template("{bar} {msg}").expect("Invalid style"))
         .with_finish(ProgressFinish::AndLeave);
+    events::emit(&Event::ProcessingStarted {
+        directory,
+        pages: tifs_step0.len(),
+    });
 
-    // Postprocess with ImageMagick:
-    //
-    // - Improve contrast
-    let mut tifs_step1 = Vec::new();
-    // TODO: Parallel processing
-    for (i, tif) in tifs_step0.iter().enumerate() {
-        progress.set_message(format!(
-            "Improving contrast ({}/{})",
-            i + 1,
-            tifs_step0.len()
-        ));
-        progress.inc(1);
+    // Apply the calibration of the scanner that scanned the document
+    let manifest = Manifest::load(directory)?;
+    let calibration = match &manifest.scanner {
+        Some(scanner_id) => calibration::load(scanner_id)?,
+        None => None,
+    };
+    debug!("Calibration: {calibration:?}");
 
-        let tif_in = directory.join(tif);
-        let tif_out = directory.join(tif.replace(".tif", "_processed.tif"));
-
-        // TODO: Tweak parameters
-        // TODO: Compress with LZW or something else?
-        let output = Command::new("magick")
-            .arg(tif_in.as_os_str())
-            .arg("-auto-level")
-            .arg("-level")
-            .arg("10%,90%")
-            .arg(tif_out.as_os_str())
-            .output()?;
-        if !output.status.success() {
-            warn!(
-                "magick failed with status {}. Stderr: {}",
-                output.status.code().unwrap_or(-1),
-                String::from_utf8_lossy(&output.stderr),
-            );
-            return Err(anyhow!("Failed to run `magick` command"));
+    // Postprocess the pages, unless an interrupted run already did
+    let processed_tifs: Vec<PathBuf> = tifs_step0
+        .iter()
+        .map(|tif| directory.join(tif.replace(".tif", "_processed.tif")))
+        .collect();
+    if step_completed(&manifest, PipelineStep::ProcessPages, &processed_tifs) {
+        progress.inc(tifs_step0.len() as u64);
+    } else {
+        // Detect streaks caused by dirt on the ADF glass
+        let streaks = if manifest.source == Some(ScanSource::Adf) {
+            let duplex =
+                (manifest.scan_stats.as_ref()).is_some_and(|stats| stats.mode.ends_with("duplex"));
+            detect_streaks(
+                directory,
+                &tifs_step0,
+                if duplex { 2 } else { 1 },
+                config.processing.memory_budget(),
+                warnings,
+            )
+        } else {
+            Vec::new()
+        };
+        let remove_streaks =
+            config.processing.remove_streaks && streaks.iter().any(|side| !side.is_empty());
+
+        // Postprocess the pages:
+        //
+        // - Remove streaks (if enabled)
+        // - Apply scanner calibration
+        // - Improve contrast (unless disabled)
+        // - Straighten pages (if enabled)
+        report_step(
+            &progress,
+            directory,
+            &format!("Processing pages ({} pages)", tifs_step0.len()),
+        );
+        let deskew = deskew_enabled(config, options);
+        let results = scheduler::global().map(JobKind::Cpu, &tifs_step0, |i, tif| {
+            let tif_out = &processed_tifs[i];
+            let tif_in = directory.join(tif);
+            let fill_columns = if remove_streaks {
+                streaks[i % streaks.len()]
+                    .iter()
+                    .map(|streak| (streak.x, streak.width))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let start = Instant::now();
+            let corrections = tiff_utils::Corrections {
+                fill_columns,
+                calibration,
+                stretch_contrast: !options.skip_contrast,
+            };
+            tiff_utils::correct_page(
+                &tif_in,
+                tif_out,
+                &corrections,
+                TiffCompression::Lzw,
+                config.processing.memory_budget(),
+            )?;
+            if deskew {
+                geometry::deskew(
+                    tif_out,
+                    tif_out,
+                    TiffCompression::Lzw,
+                    config.processing.memory_budget(),
+                )?;
+            }
+            progress.inc(1);
+            events::emit(&Event::PageProcessed {
+                directory,
+                page: i + 1,
+            });
+            anyhow::Ok(start.elapsed())
+        });
+        for (i, result) in results.into_iter().enumerate() {
+            let duration = result?;
+            timings.record(format!("Process page {}", i + 1), duration);
         }
-        tifs_step1.push(tif_out);
+        complete_step(directory, PipelineStep::ProcessPages, warnings)?;
     }
+    let mut tifs_step1 = processed_tifs;
     progress.inc(1);
 
+    // Compose all pages onto a single page (e.g. front and back of an ID
+    // card)
+    if manifest.n_up {
+        report_step(&progress, directory, "Composing pages");
+        let tif_n_up = directory.join("_n_up.tif");
+        if !step_completed(&manifest, PipelineStep::ComposePages, &[&tif_n_up]) {
+            timings.measure("Compose pages", || {
+                geometry::compose(
+                    &tifs_step1,
+                    manifest.paper.unwrap_or_else(|| config.paper_size()),
+                    &tif_n_up,
+                    TiffCompression::Lzw,
+                    config.processing.memory_budget(),
+                )
+            })?;
+            complete_step(directory, PipelineStep::ComposePages, warnings)?;
+        }
+        tifs_step1 = vec![tif_n_up];
+    }
+
+    // Determine the OCR languages
+    let languages = match (&ocr_engine, &options.ocr_languages) {
+        (Some(_), Some(languages)) => languages.clone(),
+        (Some(engine), None) => {
+            report_step(&progress, directory, "Detecting languages");
+            ocr_languages(directory, &tifs_step1, engine, config, timings)
+        }
+        (None, _) => Vec::new(),
+    };
+
+    // Tesseract recognizes the processed pages, its text is merged into the PDF
+    let text = if ocr_engine == Some(Engine::Tesseract) {
+        if !manifest.ocr_args.is_empty() {
+            warn!(
+                "Ignoring OCRmyPDF arguments {:?}, since the Tesseract engine is used",
+                manifest.ocr_args
+            );
+        }
+        if let Some(scans_dir) = directory.parent() {
+            queue::wait_while_paused(scans_dir);
+        }
+        report_step(&progress, directory, "Running OCR with Tesseract");
+        let text = timings.measure("Run OCR", || {
+            ocr::run_tesseract(
+                directory,
+                &tifs_step1,
+                &languages,
+                &config.processing.limits,
+            )
+        })?;
+        progress.inc(1);
+        Some(text)
+    } else {
+        None
+    };
+
     // Combine TIFs
-    progress.set_message("Combining TIFs");
+    report_step(&progress, directory, "Combining TIFs");
     let tif_combined = directory.join("_combined.tif");
-    let output = Command::new("tiffcp")
-        .arg("-c")
-        .arg("lzw")
-        .args(&tifs_step1)
-        .arg(tif_combined.as_os_str())
-        .output()?;
-    if !output.status.success() {
-        warn!(
-            "tiffcp failed with status {}. Stderr: {}",
-            output.status.code().unwrap_or(-1),
-            String::from_utf8_lossy(&output.stderr),
-        );
-        return Err(anyhow!("Failed to run `tiffcp` command"));
+    if !step_completed(&manifest, PipelineStep::CombinePages, &[&tif_combined]) {
+        timings
+            .measure("Combine TIFs", || {
+                tiff_utils::combine_tiffs(
+                    &tifs_step1,
+                    &tif_combined,
+                    config.processing.tiff_compression,
+                    config.processing.memory_budget(),
+                )
+            })
+            .context("Failed to combine TIFs")?;
+        complete_step(directory, PipelineStep::CombinePages, warnings)?;
     }
     progress.inc(1);
 
     // Convert TIF to PDF
-    progress.set_message("Converting to PDF");
+    report_step(&progress, directory, "Converting to PDF");
     let pdf_out = directory.join("_combined.pdf");
-    let output = Command::new("magick")
-        .arg(tif_combined.as_os_str())
-        .arg("-compress")
-        .arg("JPEG")
-        .arg(pdf_out.as_os_str())
-        .output()?;
-    if !output.status.success() {
-        warn!(
-            "magick failed with status {}. Stderr: {}",
-            output.status.code().unwrap_or(-1),
-            String::from_utf8_lossy(&output.stderr),
-        );
-        return Err(anyhow!("Failed to run `magick` command"));
+    if !step_completed(&manifest, PipelineStep::ConvertToPdf, &[&pdf_out]) {
+        let jpeg_quality = config
+            .processing
+            .jpeg_quality
+            .unwrap_or(pdf::DEFAULT_JPEG_QUALITY);
+        let memory_budget = config.processing.memory_budget();
+        timings.measure("Convert to PDF", || match &text {
+            Some(text) => {
+                pdf::write_text_pdf(&tif_combined, &pdf_out, jpeg_quality, memory_budget, text)
+            }
+            None => pdf::write_image_pdf(&tif_combined, &pdf_out, jpeg_quality, memory_budget),
+        })?;
+        complete_step(directory, PipelineStep::ConvertToPdf, warnings)?;
     }
     progress.inc(1);
 
-    // Run OCR and other postprocessing
-    // TODO: Download docker image at setup time
-    progress.set_message("Running OCR and generate PDF/A");
-    let output = Command::new("docker")
-        .arg("run")
-        .arg("--rm")
-        .arg("-v")
-        .arg(format!(
-            "{}:/document",
-            directory
-                .to_str()
-                .context("Failed to convert directory path to string")?
-        ))
-        .arg("docker.io/jbarlow83/ocrmypdf:v16.10.0") // TODO: Extract version
-        .arg(
-            Path::new("/document/").join(
-                pdf_out
-                    .file_name()
-                    .context("Failed to get output PDF file name")?,
-            ),
-        )
-        .arg(Path::new("/document/_final.pdf"))
-        .output()?;
-    if !output.status.success() {
-        warn!(
-            "ocrmypdf failed with status {}. Stderr: {}",
-            output.status.code().unwrap_or(-1),
-            String::from_utf8_lossy(&output.stderr),
+    // The PDF with the text layer of Tesseract is the final PDF
+    if let Some(text) = &text {
+        fs::rename(&pdf_out, directory.join(queue::FINAL_PDF))
+            .context("Failed to move PDF with text layer")?;
+        progress.finish();
+        warn_on_low_confidence(
+            directory,
+            hocr::average_confidence(text),
+            config.ocr.min_confidence,
+            warnings,
         );
-        return Err(anyhow!("Failed to run `ocrmypdf` command (through Docker)"));
+        warn_on_sparse_text(directory, tifs_step1.len(), warnings);
+        if config.ocr.extract_transactions {
+            export_transactions(directory, config.date_order());
+        }
+        return Ok(tifs_step1.len());
+    }
+
+    // Fast path: Use the image-only PDF as final PDF
+    let Some(Engine::Ocrmypdf(ocrmypdf)) = &ocr_engine else {
+        debug!("Skipping OCR");
+        fs::rename(&pdf_out, directory.join(queue::FINAL_PDF))
+            .context("Failed to move image-only PDF")?;
+        progress.finish_with_message(ui::success("Created image-only PDF (OCR skipped)"));
+        return Ok(tifs_step1.len());
+    };
+
+    // Run OCR and other postprocessing
+    if let Some(scans_dir) = directory.parent() {
+        queue::wait_while_paused(scans_dir);
     }
+    report_step(&progress, directory, "Running OCR and generate PDF/A");
+    let text = timings.measure("Run OCR", || {
+        ocr::run_ocrmypdf(
+            ocrmypdf,
+            directory,
+            &pdf_out,
+            &languages,
+            &manifest.ocr_args,
+            &config.processing.limits,
+        )
+    })?;
     progress.inc(1);
 
     progress.finish();
+    if text.is_empty() {
+        debug!("No hOCR output of OCRmyPDF found, not checking the OCR confidence");
+    } else {
+        warn_on_low_confidence(
+            directory,
+            hocr::average_confidence(&text),
+            config.ocr.min_confidence,
+            warnings,
+        );
+    }
+    warn_on_sparse_text(directory, tifs_step1.len(), warnings);
 
-    Ok(())
+    if config.ocr.extract_transactions {
+        export_transactions(directory, config.date_order());
+    }
+
+    Ok(tifs_step1.len())
+}
+
+/// If the document is a bank or credit card statement, export its
+/// transactions as CSV next to the final PDF
+fn export_transactions(directory: &Path, date_order: DateOrder) {
+    let text = match fs::read_to_string(directory.join(ocr::OCR_TEXT)) {
+        Ok(text) => text,
+        Err(e) => {
+            debug!("Failed to read recognized text: {e}");
+            return;
+        }
+    };
+    let Some(transactions) = statements::extract_statement(&text, date_order) else {
+        return;
+    };
+    let csv_path = directory.join(statements::TRANSACTIONS_CSV);
+    match statements::write_csv(&transactions, &csv_path) {
+        Ok(()) => println!(
+            "Exported {} transaction(s) of the statement to {}",
+            transactions.len(),
+            csv_path.display()
+        ),
+        Err(e) => warn!("Failed to export transactions: {e:#}"),
+    }
+}
+
+/// Pages that are sampled for language detection
+fn language_samples(pages: &[PathBuf]) -> Vec<&PathBuf> {
+    if pages.len() <= LANGUAGE_SAMPLE_PAGES {
+        return pages.iter().collect();
+    }
+    // Spread evenly, including the first and the last page
+    let step = (pages.len() - 1) as f64 / (LANGUAGE_SAMPLE_PAGES - 1) as f64;
+    (0..LANGUAGE_SAMPLE_PAGES)
+        .map(|i| &pages[(i as f64 * step).round() as usize])
+        .collect()
+}
+
+/// Determine the languages that are passed to the OCR engine
+///
+/// If multiple languages are configured, the languages of the document are
+/// detected from a quick OCR run of a few pages (see [`language_samples`]).
+/// If detection fails, all configured languages are used.
+fn ocr_languages(
+    directory: &Path,
+    pages: &[PathBuf],
+    engine: &Engine,
+    config: &Config,
+    timings: &mut Timings,
+) -> Vec<String> {
+    let candidates = config.ocr_languages();
+    if pages.is_empty() || candidates.len() <= 1 {
+        return candidates;
+    }
+
+    let samples = language_samples(pages);
+    let detected = timings.measure("Detect languages", || {
+        let texts = scheduler::global().map(JobKind::Cpu, &samples, |_, page| {
+            ocr::rough_text(
+                directory,
+                page,
+                &candidates,
+                engine,
+                &config.processing.limits,
+            )
+        });
+        let text = texts.into_iter().collect::<Result<Vec<_>>>()?.join("\n\n");
+        anyhow::Ok(ocr::detect_languages(&text, &candidates))
+    });
+    match detected {
+        Ok(languages) if !languages.is_empty() => {
+            debug!("Detected languages: {}", languages.join(", "));
+            languages
+        }
+        Ok(_) => {
+            debug!("No language detected, using all configured languages");
+            candidates
+        }
+        Err(e) => {
+            warn!("Failed to detect languages, using all configured languages: {e:#}");
+            candidates
+        }
+    }
+}
+
+/// Detect vertical streaks on all pages of a document and warn the user,
+/// return the streaks of every side (see [`streaks::detect`])
+///
+/// Detection is best-effort: If the pages cannot be analyzed, no streaks are
+/// returned.
+fn detect_streaks(
+    directory: &Path,
+    pages: &[String],
+    sides: usize,
+    memory_budget: usize,
+    warnings: &mut Vec<String>,
+) -> Vec<Vec<streaks::Streak>> {
+    let paths: Vec<_> = pages.iter().map(|page| directory.join(page)).collect();
+    match streaks::detect(&paths, sides, memory_budget) {
+        Ok(streaks) if streaks.iter().any(|side| !side.is_empty()) => {
+            let positions: Vec<String> = streaks
+                .iter()
+                .enumerate()
+                .flat_map(|(side, streaks)| {
+                    streaks.iter().map(move |streak| match (sides, side) {
+                        (1, _) => format!("x={}", streak.x),
+                        (_, 0) => format!("x={} on fronts", streak.x),
+                        _ => format!("x={} on backs", streak.x),
+                    })
+                })
+                .collect();
+            warn!(
+                "Detected {} vertical streak(s) on all pages ({}). Please clean the ADF glass.",
+                positions.len(),
+                positions.join(", ")
+            );
+            warnings.push(format!(
+                "Vertical streaks detected ({})",
+                positions.join(", ")
+            ));
+            streaks
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            debug!("Failed to detect streaks: {e:#}");
+            Vec::new()
+        }
+    }
+}
+
+/// Warn the user if the text of a document was recognized with low confidence,
+/// so that it can be rescanned before the original is discarded.
+///
+/// The `confidence` is the average confidence of the recognized words, if any.
+fn warn_on_low_confidence(
+    directory: &Path,
+    confidence: Option<f32>,
+    min_confidence: f32,
+    warnings: &mut Vec<String>,
+) {
+    match confidence {
+        Some(confidence) if confidence < min_confidence => {
+            warn!(
+                "Low OCR confidence for {directory:?} ({confidence:.0}%, expected at least \
+                 {min_confidence:.0}%). Consider rescanning at a higher resolution or in a \
+                 different mode before shredding the original."
+            );
+            warnings.push(format!("Low OCR confidence ({confidence:.0}%)"));
+        }
+        Some(confidence) => debug!("Average OCR confidence: {confidence:.0}%"),
+        None => {
+            warn!(
+                "No text was recognized in {directory:?}. If the document contains text, \
+                 consider rescanning it before shredding the original."
+            );
+            warnings.push(NO_TEXT_WARNING.into());
+        }
+    }
+}
+
+/// Whether the recognized text is too short for the number of pages (e.g.
+/// because the wrong language was used, or the scan is too blurry)
+fn is_sparse_text(text: &str, pages: usize) -> bool {
+    let chars = text.chars().filter(|c| c.is_alphanumeric()).count();
+    chars < MIN_CHARS_PER_PAGE * pages.max(1)
+}
+
+/// Record a warning if OCR recognized almost no text
+fn warn_on_sparse_text(directory: &Path, pages: usize, warnings: &mut Vec<String>) {
+    if warnings.iter().any(|warning| warning == NO_TEXT_WARNING) {
+        return;
+    }
+    match fs::read_to_string(directory.join(ocr::OCR_TEXT)) {
+        Ok(text) if is_sparse_text(&text, pages) => {
+            warn!("Almost no text was recognized in {directory:?}");
+            warnings.push(SPARSE_TEXT_WARNING.into());
+        }
+        Ok(_) => {}
+        Err(e) => debug!("Failed to read the recognized text: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, io::BufWriter};
+
+    use tempfile::TempDir;
+    use tiff::encoder::{TiffEncoder, colortype};
+
+    /// Ensure that only scanned pages are recognized.
+    #[test]
+    fn page_numbers() {
+        assert_eq!(page_number("0001.tif"), Some(1));
+        assert_eq!(page_number("0120.tif"), Some(120));
+        assert_eq!(page_number("12345.tif"), Some(12345));
+        assert_eq!(page_number("0001_processed.tif"), None);
+        assert_eq!(page_number("_combined.tif"), None);
+        assert_eq!(page_number(".tif"), None);
+        assert_eq!(page_number("+1.tif"), None);
+        assert_eq!(page_number("0001.pdf"), None);
+    }
+
+    /// Ensure that pages of large batches, whose numbers exceed four digits,
+    /// are sorted numerically and not lexically.
+    #[test]
+    fn sort_large_batch() {
+        let filenames = (1..=12000u32).rev().map(|i| format!("{i:04}.tif")).chain([
+            "_combined.tif".to_string(),
+            "0001_processed.tif".to_string(),
+        ]);
+        let pages = page_tifs(filenames);
+        assert_eq!(pages.len(), 12000);
+        assert_eq!(pages[0], "0001.tif");
+        assert_eq!(pages[9998], "9999.tif");
+        assert_eq!(pages[9999], "10000.tif");
+        assert_eq!(pages[11999], "12000.tif");
+    }
+
+    /// Regression test: Ensure that pages 9, 10 and 11 are combined in
+    /// numerical order, independent of the order in which they were created
+    /// and of the zero-padding of their filenames.
+    #[test]
+    fn collect_pages_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        for filename in [
+            "11.tif",
+            "0010.tif",
+            "9.tif",
+            "0010_processed.tif",
+            "_combined.tif",
+            "_combined.pdf",
+        ] {
+            File::create(temp_dir.path().join(filename)).unwrap();
+        }
+        assert_eq!(
+            collect_page_tifs(temp_dir.path()).unwrap(),
+            vec!["9.tif", "0010.tif", "11.tif"]
+        );
+    }
+
+    /// Ensure that files with non-UTF-8 names are skipped instead of causing
+    /// a panic.
+    #[test]
+    #[cfg(unix)]
+    fn collect_pages_non_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let temp_dir = TempDir::new().unwrap();
+        File::create(temp_dir.path().join("0001.tif")).unwrap();
+        let non_utf8 = OsStr::from_bytes(b"0002\xff.tif");
+        if File::create(temp_dir.path().join(non_utf8)).is_err() {
+            // Some filesystems (e.g. on macOS) reject non-UTF-8 names
+            return;
+        }
+        assert_eq!(
+            collect_page_tifs(temp_dir.path()).unwrap(),
+            vec!["0001.tif"]
+        );
+    }
+
+    /// Ensure that steps are only skipped if they were completed and their
+    /// outputs still exist.
+    #[test]
+    fn resume_completed_steps() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("_combined.tif");
+        File::create(&output).unwrap();
+        let manifest = Manifest {
+            completed_steps: vec![PipelineStep::CombinePages],
+            ..Default::default()
+        };
+        assert!(step_completed(
+            &manifest,
+            PipelineStep::CombinePages,
+            &[&output]
+        ));
+        assert!(!step_completed(
+            &manifest,
+            PipelineStep::ConvertToPdf,
+            &[&output]
+        ));
+        let missing = temp_dir.path().join("_combined.pdf");
+        assert!(!step_completed(
+            &manifest,
+            PipelineStep::CombinePages,
+            &[&output, &missing]
+        ));
+
+        complete_step(
+            temp_dir.path(),
+            PipelineStep::ConvertToPdf,
+            &["Streaks".into()],
+        )
+        .unwrap();
+        let manifest = Manifest::load(temp_dir.path()).unwrap();
+        assert_eq!(manifest.completed_steps, vec![PipelineStep::ConvertToPdf]);
+        assert_eq!(manifest.warnings, vec!["Streaks"]);
+    }
+
+    /// Ensure that deskewing follows the config and can be disabled per
+    /// profile.
+    #[test]
+    fn page_enhancements() {
+        let mut config: Config = toml::from_str("outdir = \"/tmp\"\nscanners = []").unwrap();
+        let defaults = ProcessOptions::default();
+        assert!(!deskew_enabled(&config, &defaults));
+        config.processing.deskew = true;
+        assert!(deskew_enabled(&config, &defaults));
+        let handwritten = ProcessOptions {
+            skip_ocr: true,
+            skip_contrast: true,
+            ..Default::default()
+        };
+        assert!(deskew_enabled(&config, &handwritten));
+        let options = ProcessOptions {
+            skip_deskew: true,
+            ..Default::default()
+        };
+        assert!(!deskew_enabled(&config, &options));
+    }
+
+    /// Ensure that image-only documents get a smaller copy for emailing,
+    /// which is recorded in the manifest next to the final PDF.
+    #[test]
+    fn email_copy_without_ocr() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path().join("20250601-143210");
+        fs::create_dir(&directory).unwrap();
+        {
+            let file = File::create(directory.join("0001.tif")).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let pixels: Vec<u8> = (0..200u32 * 300).map(|i| (i * 7 % 251) as u8).collect();
+            encoder
+                .write_image::<colortype::Gray8>(200, 300, &pixels)
+                .unwrap();
+        }
+        let config: Config = toml::from_str("outdir = \"/tmp\"\nscanners = []").unwrap();
+        let options = ProcessOptions {
+            skip_ocr: true,
+            email_copy: true,
+            ..Default::default()
+        };
+        process_document(&directory, &config, &options, &mut Timings::default()).unwrap();
+
+        let size = |name: &str| fs::metadata(directory.join(name)).unwrap().len();
+        assert!(size(queue::EMAIL_PDF) < size(queue::FINAL_PDF));
+        let manifest = Manifest::load(&directory).unwrap();
+        assert_eq!(
+            manifest.outputs,
+            vec![PathBuf::from(queue::FINAL_PDF), queue::EMAIL_PDF.into()]
+        );
+
+        // Without the option, a stale copy is removed
+        let options = ProcessOptions {
+            skip_ocr: true,
+            ..Default::default()
+        };
+        process_document(&directory, &config, &options, &mut Timings::default()).unwrap();
+        assert!(!directory.join(queue::EMAIL_PDF).exists());
+        let manifest = Manifest::load(&directory).unwrap();
+        assert_eq!(manifest.outputs, vec![PathBuf::from(queue::FINAL_PDF)]);
+    }
+
+    /// Ensure that documents with only a few recognized characters per page
+    /// are detected.
+    #[test]
+    fn sparse_text() {
+        assert!(is_sparse_text("", 1));
+        assert!(is_sparse_text(" ~~ | . ,\n\n ' ", 1));
+        assert!(!is_sparse_text("Invoice 2025-06-01, amount due", 1));
+        assert!(is_sparse_text("Invoice 2025-06-01, amount due", 2));
+    }
+
+    /// Ensure that the PDF size is shown in a readable unit, together with the
+    /// average size per page.
+    #[test]
+    fn pdf_size_summary() {
+        assert_eq!(size_summary(300 * 1024, 2), "300 KiB (150 KiB per page)");
+        assert_eq!(
+            size_summary(6 * 1024 * 1024, 4),
+            "6.0 MiB (1.5 MiB per page)"
+        );
+        assert_eq!(size_summary(2048, 0), "2 KiB (2 KiB per page)");
+    }
+
+    /// Ensure that a missing directory results in an error.
+    #[test]
+    fn collect_pages_missing_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let result = collect_page_tifs(&temp_dir.path().join("missing"));
+        assert!(result.is_err());
+    }
+
+    /// Ensure that the pages sampled for language detection are spread over
+    /// the document, including the first and the last page.
+    #[test]
+    fn language_sample_pages() {
+        let pages: Vec<PathBuf> = (1..=10)
+            .map(|i| PathBuf::from(format!("{i:04}.tif")))
+            .collect();
+        let names = |count: usize| -> Vec<String> {
+            language_samples(&pages[..count])
+                .iter()
+                .map(|page| page.display().to_string())
+                .collect()
+        };
+        assert_eq!(names(10), vec!["0001.tif", "0006.tif", "0010.tif"]);
+        assert_eq!(names(4), vec!["0001.tif", "0003.tif", "0004.tif"]);
+        assert_eq!(names(2), vec!["0001.tif", "0002.tif"]);
+        assert!(names(0).is_empty());
+    }
 }
@@ -0,0 +1,958 @@
+//! Interactive prompts
+//!
+//! All questions are asked through a [`Prompter`], so that interactive flows
+//! can be answered from a script (see [`ScriptedPrompter`]) in tests or with
+//! `--answers`. The builders mirror the prompts of `inquire`, which answers
+//! the questions by default.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use tracing::{debug, trace};
+
+/// The text of a question
+#[derive(Debug, Clone, Copy)]
+pub struct Question<'a> {
+    pub message: &'a str,
+    pub help: Option<&'a str>,
+}
+
+/// Answers questions
+///
+/// Every method returns `None` if the user skipped the question (e.g. with
+/// Esc).
+pub trait Prompter {
+    /// Answer a yes/no question
+    fn confirm(&self, question: &Question, default: Option<bool>) -> Result<Option<bool>>;
+
+    /// Enter a text, pre-filled with `initial` or falling back to `default`
+    fn text(
+        &self,
+        question: &Question,
+        initial: Option<&str>,
+        default: Option<&str>,
+    ) -> Result<Option<String>>;
+
+    /// Select one of `options`, return its index
+    fn select(
+        &self,
+        question: &Question,
+        options: &[String],
+        cursor: usize,
+    ) -> Result<Option<usize>>;
+
+    /// Select any number of `options`, return their indices
+    fn multi_select(
+        &self,
+        question: &Question,
+        options: &[String],
+        defaults: &[usize],
+    ) -> Result<Option<Vec<usize>>>;
+
+    /// Report an invalid answer, before the question is asked again
+    fn invalid(&self, message: &str) -> Result<()>;
+
+    /// Whether a user answers the questions, rather than a script
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+static PROMPTER: OnceLock<Box<dyn Prompter + Send + Sync>> = OnceLock::new();
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Rc<dyn Prompter>>> = const { RefCell::new(None) };
+}
+
+/// Set the prompter that answers all questions (by default, the user through
+/// `inquire`)
+///
+/// Has no effect if a question was already asked.
+pub fn init(prompter: Box<dyn Prompter + Send + Sync>) {
+    if PROMPTER.set(prompter).is_err() {
+        debug!("Prompter is already initialized");
+    }
+}
+
+/// Answer the questions asked by `f` on the current thread with `prompter`
+#[cfg(test)]
+pub fn with_prompter<R>(prompter: impl Prompter + 'static, f: impl FnOnce() -> R) -> R {
+    let previous = OVERRIDE.with(|current| current.replace(Some(Rc::new(prompter))));
+    let result = f();
+    OVERRIDE.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Ask a question with the current prompter
+fn ask<R>(f: impl FnOnce(&dyn Prompter) -> Result<R>) -> Result<R> {
+    let prompter = OVERRIDE.with(|current| current.borrow().clone());
+    match prompter {
+        Some(prompter) => f(prompter.as_ref()),
+        None => f(PROMPTER.get_or_init(|| Box::new(InquirePrompter)).as_ref()),
+    }
+}
+
+/// Whether a user answers the questions, rather than a script
+pub fn is_interactive() -> bool {
+    ask(|prompter| Ok(prompter.is_interactive())).unwrap_or(true)
+}
+
+/// Error for a question that the user skipped, but that must be answered
+fn canceled() -> anyhow::Error {
+    inquire::InquireError::OperationCanceled.into()
+}
+
+/// A yes/no question
+pub struct Confirm<'a> {
+    question: Question<'a>,
+    default: Option<bool>,
+}
+
+impl<'a> Confirm<'a> {
+    pub fn new(message: &'a str) -> Self {
+        Self {
+            question: Question {
+                message,
+                help: None,
+            },
+            default: None,
+        }
+    }
+
+    pub fn with_default(mut self, default: bool) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn with_help_message(mut self, help: &'a str) -> Self {
+        self.question.help = Some(help);
+        self
+    }
+
+    pub fn prompt(self) -> Result<bool> {
+        ask(|prompter| prompter.confirm(&self.question, self.default))?.ok_or_else(canceled)
+    }
+}
+
+type Validator<'a, T> = Box<dyn Fn(&T) -> Result<(), String> + 'a>;
+
+/// A question answered with a text
+pub struct Text<'a> {
+    question: Question<'a>,
+    initial: Option<&'a str>,
+    default: Option<&'a str>,
+    validator: Option<Validator<'a, str>>,
+}
+
+impl<'a> Text<'a> {
+    pub fn new(message: &'a str) -> Self {
+        Self {
+            question: Question {
+                message,
+                help: None,
+            },
+            initial: None,
+            default: None,
+            validator: None,
+        }
+    }
+
+    pub fn with_help_message(mut self, help: &'a str) -> Self {
+        self.question.help = Some(help);
+        self
+    }
+
+    pub fn with_initial_value(mut self, initial: &'a str) -> Self {
+        self.initial = Some(initial);
+        self
+    }
+
+    pub fn with_validator(mut self, validator: impl Fn(&str) -> Result<(), String> + 'a) -> Self {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    pub fn prompt(self) -> Result<String> {
+        self.prompt_skippable()?.ok_or_else(canceled)
+    }
+
+    /// Ask the question, return `None` if the user skipped it
+    pub fn prompt_skippable(self) -> Result<Option<String>> {
+        let mut initial = self.initial.map(String::from);
+        loop {
+            let Some(answer) =
+                ask(|prompter| prompter.text(&self.question, initial.as_deref(), self.default))?
+            else {
+                return Ok(None);
+            };
+            match self.validator.as_ref().map(|validate| validate(&answer)) {
+                Some(Err(message)) => {
+                    ask(|prompter| prompter.invalid(&message))?;
+                    initial = Some(answer);
+                }
+                _ => return Ok(Some(answer)),
+            }
+        }
+    }
+}
+
+/// A question answered with a value that is parsed from a text (e.g. a
+/// number or a date)
+pub struct CustomType<'a, T> {
+    question: Question<'a>,
+    default: Option<T>,
+    error_message: &'a str,
+    validator: Option<Validator<'a, T>>,
+}
+
+impl<'a, T: FromStr + Display> CustomType<'a, T> {
+    pub fn new(message: &'a str) -> Self {
+        Self {
+            question: Question {
+                message,
+                help: None,
+            },
+            default: None,
+            error_message: "Invalid input",
+            validator: None,
+        }
+    }
+
+    pub fn with_default(mut self, default: T) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn with_help_message(mut self, help: &'a str) -> Self {
+        self.question.help = Some(help);
+        self
+    }
+
+    /// Message shown if the answer cannot be parsed
+    pub fn with_error_message(mut self, error_message: &'a str) -> Self {
+        self.error_message = error_message;
+        self
+    }
+
+    pub fn with_validator(mut self, validator: impl Fn(&T) -> Result<(), String> + 'a) -> Self {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    pub fn prompt(self) -> Result<T> {
+        self.prompt_skippable()?.ok_or_else(canceled)
+    }
+
+    /// Ask the question, return `None` if the user skipped it
+    pub fn prompt_skippable(self) -> Result<Option<T>> {
+        let default = self.default.as_ref().map(T::to_string);
+        loop {
+            let Some(answer) =
+                ask(|prompter| prompter.text(&self.question, None, default.as_deref()))?
+            else {
+                return Ok(None);
+            };
+            let message = match answer.trim().parse::<T>() {
+                Ok(value) => match self.validator.as_ref().map(|validate| validate(&value)) {
+                    Some(Err(message)) => message,
+                    _ => return Ok(Some(value)),
+                },
+                Err(_) => self.error_message.to_string(),
+            };
+            ask(|prompter| prompter.invalid(&message))?;
+        }
+    }
+}
+
+/// A question answered by selecting one of multiple options
+pub struct Select<'a, T> {
+    question: Question<'a>,
+    options: Vec<T>,
+    cursor: usize,
+}
+
+impl<'a, T: Display> Select<'a, T> {
+    pub fn new(message: &'a str, options: Vec<T>) -> Self {
+        Self {
+            question: Question {
+                message,
+                help: None,
+            },
+            options,
+            cursor: 0,
+        }
+    }
+
+    pub fn with_starting_cursor(mut self, cursor: usize) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
+    pub fn prompt(mut self) -> Result<T> {
+        let index = self.prompt_index()?;
+        Ok(self.options.swap_remove(index))
+    }
+
+    /// Ask the question, return the index of the selected option
+    pub fn prompt_index(&self) -> Result<usize> {
+        let labels: Vec<String> = self.options.iter().map(T::to_string).collect();
+        let index = ask(|prompter| prompter.select(&self.question, &labels, self.cursor))?
+            .ok_or_else(canceled)?;
+        if index >= self.options.len() {
+            bail!("Invalid selection for \"{}\"", self.question.message);
+        }
+        Ok(index)
+    }
+}
+
+/// A question answered by selecting any number of options
+pub struct MultiSelect<'a, T> {
+    question: Question<'a>,
+    options: Vec<T>,
+    defaults: &'a [usize],
+    validator: Option<Validator<'a, usize>>,
+}
+
+impl<'a, T: Display> MultiSelect<'a, T> {
+    pub fn new(message: &'a str, options: Vec<T>) -> Self {
+        Self {
+            question: Question {
+                message,
+                help: None,
+            },
+            options,
+            defaults: &[],
+            validator: None,
+        }
+    }
+
+    pub fn with_help_message(mut self, help: &'a str) -> Self {
+        self.question.help = Some(help);
+        self
+    }
+
+    /// Preselect the options at the given indices
+    pub fn with_default(mut self, defaults: &'a [usize]) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Validate the number of selected options
+    pub fn with_validator(mut self, validator: impl Fn(usize) -> Result<(), String> + 'a) -> Self {
+        self.validator = Some(Box::new(move |selected: &usize| validator(*selected)));
+        self
+    }
+
+    /// Ask the question, return the indices of the selected options
+    pub fn prompt_indices(&self) -> Result<Vec<usize>> {
+        self.prompt_indices_skippable()?.ok_or_else(canceled)
+    }
+
+    /// Ask the question, return the indices of the selected options or `None`
+    /// if the user skipped it
+    fn prompt_indices_skippable(&self) -> Result<Option<Vec<usize>>> {
+        let labels: Vec<String> = self.options.iter().map(T::to_string).collect();
+        loop {
+            let Some(indices) =
+                ask(|prompter| prompter.multi_select(&self.question, &labels, self.defaults))?
+            else {
+                return Ok(None);
+            };
+            if indices.iter().any(|&index| index >= self.options.len()) {
+                bail!("Invalid selection for \"{}\"", self.question.message);
+            }
+            match self
+                .validator
+                .as_ref()
+                .map(|validate| validate(&indices.len()))
+            {
+                Some(Err(message)) => ask(|prompter| prompter.invalid(&message))?,
+                _ => return Ok(Some(indices)),
+            }
+        }
+    }
+
+    /// Ask the question, return the selected options
+    pub fn prompt(self) -> Result<Vec<T>> {
+        self.prompt_skippable()?.ok_or_else(canceled)
+    }
+
+    /// Ask the question, return the selected options or `None` if the user
+    /// skipped it
+    pub fn prompt_skippable(self) -> Result<Option<Vec<T>>> {
+        let Some(indices) = self.prompt_indices_skippable()? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.options
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| indices.contains(index))
+                .map(|(_, option)| option)
+                .collect(),
+        ))
+    }
+}
+
+/// Ask the user to select one of `options`, with the cursor on the option at
+/// `starting_cursor`
+///
+/// If there is only one option, it is returned without asking.
+pub fn select<T: Display>(message: &str, mut options: Vec<T>, starting_cursor: usize) -> Result<T> {
+    if options.len() == 1 {
+        let option = options.remove(0);
+        trace!("Only one option for \"{message}\", using {option}");
+        return Ok(option);
+    }
+    Select::new(message, options)
+        .with_starting_cursor(starting_cursor)
+        .prompt()
+}
+
+/// Asks the user in the terminal, with the widgets of `inquire`
+pub struct InquirePrompter;
+
+impl Prompter for InquirePrompter {
+    fn confirm(&self, question: &Question, default: Option<bool>) -> Result<Option<bool>> {
+        let mut prompt = inquire::Confirm::new(question.message);
+        if let Some(default) = default {
+            prompt = prompt.with_default(default);
+        }
+        if let Some(help) = question.help {
+            prompt = prompt.with_help_message(help);
+        }
+        Ok(prompt.prompt_skippable()?)
+    }
+
+    fn text(
+        &self,
+        question: &Question,
+        initial: Option<&str>,
+        default: Option<&str>,
+    ) -> Result<Option<String>> {
+        let mut prompt = inquire::Text::new(question.message);
+        if let Some(initial) = initial {
+            prompt = prompt.with_initial_value(initial);
+        }
+        if let Some(default) = default {
+            prompt = prompt.with_default(default);
+        }
+        if let Some(help) = question.help {
+            prompt = prompt.with_help_message(help);
+        }
+        Ok(prompt.prompt_skippable()?)
+    }
+
+    fn select(
+        &self,
+        question: &Question,
+        options: &[String],
+        cursor: usize,
+    ) -> Result<Option<usize>> {
+        let mut prompt =
+            inquire::Select::new(question.message, options.to_vec()).with_starting_cursor(cursor);
+        if let Some(help) = question.help {
+            prompt = prompt.with_help_message(help);
+        }
+        match prompt.raw_prompt() {
+            Ok(option) => Ok(Some(option.index)),
+            Err(inquire::InquireError::OperationCanceled) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn multi_select(
+        &self,
+        question: &Question,
+        options: &[String],
+        defaults: &[usize],
+    ) -> Result<Option<Vec<usize>>> {
+        let mut prompt =
+            inquire::MultiSelect::new(question.message, options.to_vec()).with_default(defaults);
+        if let Some(help) = question.help {
+            prompt = prompt.with_help_message(help);
+        }
+        Ok(prompt
+            .raw_prompt_skippable()?
+            .map(|selection| selection.into_iter().map(|option| option.index).collect()))
+    }
+
+    fn invalid(&self, message: &str) -> Result<()> {
+        println!("{message}");
+        Ok(())
+    }
+}
+
+/// Answers questions from a list of prepared answers, in order
+///
+/// An empty answer accepts the default (or the pre-filled text), and `<esc>`
+/// skips the question. Options are selected by their number (starting at 1)
+/// or their text, multiple options are separated by commas, and `-` selects
+/// none of them. Invalid answers are errors, since the question cannot be
+/// asked again.
+pub struct ScriptedPrompter {
+    answers: Mutex<VecDeque<String>>,
+}
+
+/// Answer that skips a question
+const SKIP: &str = "<esc>";
+
+/// Answer that clears a pre-filled text
+const CLEAR: &str = "-";
+
+impl ScriptedPrompter {
+    pub fn new<S: Into<String>>(answers: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            answers: Mutex::new(answers.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// Read the answers from a file, one per line (lines starting with `#`
+    /// are ignored)
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read answers from {:?}", path))?;
+        Ok(Self::new(
+            content
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .map(str::trim_end),
+        ))
+    }
+
+    /// The next answer, `None` if the question is skipped
+    fn next(&self, question: &Question) -> Result<Option<String>> {
+        let answer = self
+            .answers
+            .lock()
+            .map_err(|_| anyhow!("Answers are poisoned"))?
+            .pop_front()
+            .ok_or_else(|| anyhow!("No answer left for \"{}\"", question.message))?;
+        println!("{} {}", question.message, answer);
+        Ok((answer != SKIP).then_some(answer))
+    }
+}
+
+/// Find the option that an answer refers to, by number (starting at 1) or
+/// text
+fn option_index(answer: &str, options: &[String]) -> Result<usize, String> {
+    let answer = answer.trim();
+    if let Ok(number) = answer.parse::<usize>()
+        && (1..=options.len()).contains(&number)
+    {
+        return Ok(number - 1);
+    }
+    options
+        .iter()
+        .position(|option| option == answer)
+        .ok_or_else(|| format!("\"{answer}\" is not one of the options"))
+}
+
+/// Interpret the answer to a yes/no question, an empty answer accepts the
+/// default
+fn parse_confirm(answer: &str, default: Option<bool>) -> Result<bool, String> {
+    match answer.trim().to_lowercase().as_str() {
+        "" => default.ok_or_else(|| "Please answer y or n".to_string()),
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        other => Err(format!("\"{other}\" is not y or n")),
+    }
+}
+
+/// Interpret the answer to a text question, an empty answer accepts the
+/// pre-filled text or the default and `-` clears it
+fn parse_text(answer: String, initial: Option<&str>, default: Option<&str>) -> String {
+    match answer.as_str() {
+        "" => initial.or(default).unwrap_or_default().to_string(),
+        CLEAR => String::new(),
+        _ => answer,
+    }
+}
+
+/// Interpret the selection of an option, an empty answer selects the option
+/// at the cursor
+fn parse_select(answer: &str, options: &[String], cursor: usize) -> Result<usize, String> {
+    if answer.trim().is_empty() {
+        Ok(cursor)
+    } else {
+        option_index(answer, options)
+    }
+}
+
+/// Interpret the selection of any number of options (separated by commas),
+/// an empty answer selects the defaults and `-` none
+fn parse_multi_select(
+    answer: &str,
+    options: &[String],
+    defaults: &[usize],
+) -> Result<Vec<usize>, String> {
+    match answer.trim() {
+        "" => Ok(defaults.to_vec()),
+        "-" => Ok(Vec::new()),
+        answer => answer
+            .split(',')
+            .map(|answer| option_index(answer, options))
+            .collect(),
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn confirm(&self, question: &Question, default: Option<bool>) -> Result<Option<bool>> {
+        let Some(answer) = self.next(question)? else {
+            return Ok(None);
+        };
+        parse_confirm(&answer, default)
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid answer for \"{}\": {e}", question.message))
+    }
+
+    fn text(
+        &self,
+        question: &Question,
+        initial: Option<&str>,
+        default: Option<&str>,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .next(question)?
+            .map(|answer| parse_text(answer, initial, default)))
+    }
+
+    fn select(
+        &self,
+        question: &Question,
+        options: &[String],
+        cursor: usize,
+    ) -> Result<Option<usize>> {
+        let Some(answer) = self.next(question)? else {
+            return Ok(None);
+        };
+        parse_select(&answer, options, cursor)
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid answer for \"{}\": {e}", question.message))
+    }
+
+    fn multi_select(
+        &self,
+        question: &Question,
+        options: &[String],
+        defaults: &[usize],
+    ) -> Result<Option<Vec<usize>>> {
+        let Some(answer) = self.next(question)? else {
+            return Ok(None);
+        };
+        parse_multi_select(&answer, options, defaults)
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid answer for \"{}\": {e}", question.message))
+    }
+
+    fn invalid(&self, message: &str) -> Result<()> {
+        bail!("Invalid answer: {message}")
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// Asks the user with numbered lists and line-based input, without cursor
+/// movement, for screen readers, dumb terminals and `expect` scripts
+///
+/// An empty line accepts the default, `-` clears a pre-filled text and
+/// `<esc>` skips the question. End of input (Ctrl-D) is an error, so that
+/// the remaining questions aren't skipped silently.
+pub struct PlainPrompter {
+    input: Mutex<Box<dyn BufRead + Send>>,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl PlainPrompter {
+    /// Ask on stdout, read the answers from stdin
+    pub fn stdio() -> Self {
+        Self::new(io::BufReader::new(io::stdin()), io::stdout())
+    }
+
+    fn new(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> Self {
+        Self {
+            input: Mutex::new(Box::new(input)),
+            output: Mutex::new(Box::new(output)),
+        }
+    }
+
+    /// Print a question with its help and the `hint` on the answer, then read
+    /// the answer (`None` if the question is skipped)
+    fn ask(&self, question: &Question, lines: &[String], hint: &str) -> Result<Option<String>> {
+        {
+            let mut output = self
+                .output
+                .lock()
+                .map_err(|_| anyhow!("Output is poisoned"))?;
+            writeln!(output, "{}", question.message)?;
+            if let Some(help) = question.help {
+                writeln!(output, "({help})")?;
+            }
+            for line in lines {
+                writeln!(output, "{line}")?;
+            }
+            write!(output, "{hint}> ")?;
+            output.flush()?;
+        }
+        let mut answer = String::new();
+        let read = self
+            .input
+            .lock()
+            .map_err(|_| anyhow!("Input is poisoned"))?
+            .read_line(&mut answer)
+            .context("Failed to read answer")?;
+        ensure!(read > 0, "Input ended before \"{}\"", question.message);
+        let answer = answer.trim_end_matches(['\r', '\n']);
+        Ok((answer != SKIP).then(|| answer.to_string()))
+    }
+
+    /// Ask until the answer can be interpreted
+    fn ask_until<T>(
+        &self,
+        question: &Question,
+        lines: &[String],
+        hint: &str,
+        parse: impl Fn(String) -> Result<T, String>,
+    ) -> Result<Option<T>> {
+        loop {
+            let Some(answer) = self.ask(question, lines, hint)? else {
+                return Ok(None);
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(Some(value)),
+                Err(message) => self.invalid(&message)?,
+            }
+        }
+    }
+}
+
+/// Numbered lines of options, with the selected options marked
+fn numbered_options(options: &[String], selected: &[usize]) -> Vec<String> {
+    options
+        .iter()
+        .enumerate()
+        .map(|(index, option)| {
+            let mark = if selected.contains(&index) { "*" } else { " " };
+            format!("{mark}{:>3}. {option}", index + 1)
+        })
+        .collect()
+}
+
+impl Prompter for PlainPrompter {
+    fn confirm(&self, question: &Question, default: Option<bool>) -> Result<Option<bool>> {
+        let hint = match default {
+            Some(true) => "[Y/n]",
+            Some(false) => "[y/N]",
+            None => "[y/n]",
+        };
+        self.ask_until(question, &[], hint, |answer| {
+            parse_confirm(&answer, default)
+        })
+    }
+
+    fn text(
+        &self,
+        question: &Question,
+        initial: Option<&str>,
+        default: Option<&str>,
+    ) -> Result<Option<String>> {
+        let hint = match initial.or(default) {
+            Some(value) if !value.is_empty() => format!("[{value}, {CLEAR} to clear]"),
+            _ => String::new(),
+        };
+        self.ask_until(question, &[], &hint, |answer| {
+            Ok(parse_text(answer, initial, default))
+        })
+    }
+
+    fn select(
+        &self,
+        question: &Question,
+        options: &[String],
+        cursor: usize,
+    ) -> Result<Option<usize>> {
+        let lines = numbered_options(options, &[cursor]);
+        let hint = format!("[{}]", cursor + 1);
+        self.ask_until(question, &lines, &hint, |answer| {
+            parse_select(&answer, options, cursor)
+        })
+    }
+
+    fn multi_select(
+        &self,
+        question: &Question,
+        options: &[String],
+        defaults: &[usize],
+    ) -> Result<Option<Vec<usize>>> {
+        let mut lines = numbered_options(options, defaults);
+        lines.push("Enter the numbers separated by commas, or - for none".into());
+        let defaults_hint: Vec<String> = defaults
+            .iter()
+            .map(|index| (index + 1).to_string())
+            .collect();
+        let hint = if defaults.is_empty() {
+            "[-]".to_string()
+        } else {
+            format!("[{}]", defaults_hint.join(","))
+        };
+        self.ask_until(question, &lines, &hint, |answer| {
+            parse_multi_select(&answer, options, defaults)
+        })
+    }
+
+    fn invalid(&self, message: &str) -> Result<()> {
+        let mut output = self
+            .output
+            .lock()
+            .map_err(|_| anyhow!("Output is poisoned"))?;
+        writeln!(output, "{message}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the only option is selected without a prompt.
+    #[test]
+    fn single_option() {
+        assert_eq!(
+            select("Which scanner?", vec!["office"], 0).unwrap(),
+            "office"
+        );
+    }
+
+    /// Ensure that only scripted runs are reported as not interactive.
+    #[test]
+    fn interactive() {
+        assert!(is_interactive());
+        with_prompter(ScriptedPrompter::new([""; 0]), || {
+            assert!(!is_interactive());
+        });
+        assert!(is_interactive());
+    }
+
+    /// Ensure that scripted answers are parsed for every kind of question,
+    /// and that defaults and pre-filled texts are accepted with empty
+    /// answers.
+    #[test]
+    fn scripted_answers() {
+        let answers = [
+            "y", "", "Invoice", "", "<esc>", "2", "Flatbed", "", "1,3", "-", "12",
+        ];
+        with_prompter(ScriptedPrompter::new(answers), || {
+            assert!(Confirm::new("Scan?").prompt().unwrap());
+            assert!(!Confirm::new("Stop?").with_default(false).prompt().unwrap());
+            assert_eq!(Text::new("Title?").prompt().unwrap(), "Invoice");
+            assert_eq!(
+                Text::new("Title?")
+                    .with_initial_value("Letter")
+                    .prompt()
+                    .unwrap(),
+                "Letter"
+            );
+            assert_eq!(Text::new("Title?").prompt_skippable().unwrap(), None);
+
+            let options = || vec!["ADF", "Flatbed", "ID document"];
+            assert_eq!(Select::new("Mode?", options()).prompt().unwrap(), "Flatbed");
+            assert_eq!(Select::new("Mode?", options()).prompt().unwrap(), "Flatbed");
+            assert_eq!(
+                Select::new("Mode?", options())
+                    .with_starting_cursor(2)
+                    .prompt()
+                    .unwrap(),
+                "ID document"
+            );
+            assert_eq!(
+                MultiSelect::new("Options?", options()).prompt().unwrap(),
+                ["ADF", "ID document"]
+            );
+            assert!(
+                MultiSelect::new("Options?", options())
+                    .with_default(&[0])
+                    .prompt()
+                    .unwrap()
+                    .is_empty()
+            );
+            assert_eq!(CustomType::<usize>::new("Pages?").prompt().unwrap(), 12);
+
+            // No answers left
+            assert!(Confirm::new("Scan?").prompt().is_err());
+        });
+    }
+
+    /// Ensure that plain prompts list the options with numbers, and that
+    /// invalid answers are asked again.
+    #[test]
+    fn plain_prompts() {
+        let options: Vec<String> = ["ADF", "Flatbed"].map(String::from).to_vec();
+        assert_eq!(
+            numbered_options(&options, &[1]),
+            ["   1. ADF", "*  2. Flatbed"]
+        );
+
+        let input = io::Cursor::new("maybe\n\n3\nFlatbed\n1, 2\nInvoice\n-\n\n<esc>\n");
+        let prompter = PlainPrompter::new(input, io::sink());
+        let question = Question {
+            message: "Question?",
+            help: None,
+        };
+        assert_eq!(prompter.confirm(&question, Some(true)).unwrap(), Some(true));
+        assert_eq!(prompter.select(&question, &options, 0).unwrap(), Some(1));
+        assert_eq!(
+            prompter.multi_select(&question, &options, &[]).unwrap(),
+            Some(vec![0, 1])
+        );
+        assert_eq!(
+            prompter.text(&question, None, None).unwrap().as_deref(),
+            Some("Invoice")
+        );
+        // A pre-filled text is cleared with `-`, and accepted when empty
+        assert_eq!(
+            prompter.text(&question, Some("Letter"), None).unwrap(),
+            Some(String::new())
+        );
+        assert_eq!(
+            prompter.text(&question, Some("Letter"), None).unwrap(),
+            Some("Letter".into())
+        );
+        // `<esc>` skips the question, end of input is an error
+        assert_eq!(prompter.confirm(&question, None).unwrap(), None);
+        assert!(prompter.confirm(&question, None).is_err());
+    }
+
+    /// Ensure that invalid answers are rejected by validators.
+    #[test]
+    fn invalid_answers() {
+        with_prompter(ScriptedPrompter::new(["0", "x", "4"]), || {
+            let pages = || {
+                CustomType::<usize>::new("Pages?").with_validator(|pages| {
+                    if *pages > 0 {
+                        Ok(())
+                    } else {
+                        Err("Please enter a number ≥ 1".into())
+                    }
+                })
+            };
+            assert!(pages().prompt().is_err());
+            assert!(pages().prompt().is_err());
+            assert_eq!(pages().prompt().unwrap(), 4);
+        });
+        with_prompter(ScriptedPrompter::new(["Scanner"]), || {
+            assert!(Select::new("Mode?", vec!["ADF"]).prompt().is_err());
+        });
+    }
+}
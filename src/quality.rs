@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::tiff_utils::{self, LuminanceImage};
+
+/// Fraction of the darkest and brightest pixels that are ignored when
+/// determining the contrast
+const CLIP_FRACTION: f64 = 0.01;
+
+/// Fraction of the pixels with the strongest gradients that are used to
+/// determine the sharpness (i.e. the edges of text and lines)
+const EDGE_FRACTION: f64 = 0.01;
+
+/// Simple quality metrics of a scanned page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageQuality {
+    /// Difference between the dark and bright parts of the page (0-100)
+    pub contrast: f32,
+    /// Strength of the edges on the page (0-100)
+    pub sharpness: f32,
+}
+
+impl PageQuality {
+    /// Combined quality score (0-100)
+    ///
+    /// Blurry pages (e.g. from a misfeed) have soft edges, and washed out
+    /// pages have low contrast. Both result in a low score.
+    pub fn score(&self) -> f32 {
+        (self.contrast * self.sharpness).sqrt()
+    }
+}
+
+/// Assess the quality of the first page of a TIFF file
+pub fn assess(path: &Path) -> Result<PageQuality> {
+    Ok(assess_image(&tiff_utils::read_luminance(path)?))
+}
+
+fn assess_image(image: &LuminanceImage) -> PageQuality {
+    let width = image.width as usize;
+
+    // Contrast: Spread of the luminance, ignoring outliers
+    let mut histogram = [0u64; 256];
+    for pixel in &image.pixels {
+        histogram[*pixel as usize] += 1;
+    }
+    let dark = tiff_utils::histogram_percentile(&histogram, CLIP_FRACTION);
+    let bright = tiff_utils::histogram_percentile(&histogram, 1.0 - CLIP_FRACTION);
+    let contrast = bright.saturating_sub(dark) as f32 / 255.0 * 100.0;
+
+    // Sharpness: Gradient magnitude at the strongest edges
+    let mut gradients = [0u64; 256];
+    if width > 1 {
+        let rows: Vec<&[u8]> = image.pixels.chunks_exact(width).collect();
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..width - 1 {
+                let horizontal = row[x].abs_diff(row[x + 1]);
+                let vertical = rows.get(y + 1).map_or(0, |next| row[x].abs_diff(next[x]));
+                gradients[horizontal.max(vertical) as usize] += 1;
+            }
+        }
+    }
+    let edge = tiff_utils::histogram_percentile(&gradients, 1.0 - EDGE_FRACTION);
+    let sharpness = edge as f32 / 255.0 * 100.0;
+
+    PageQuality {
+        contrast,
+        sharpness,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a white test page with black vertical bars, with edges that are
+    /// blurred over `blur` pixels
+    fn bars(blur: usize) -> LuminanceImage {
+        let (width, height) = (120, 20);
+        let ramp = |step: usize| (255 * (step + 1) / (blur + 1)) as u8;
+        let row: Vec<u8> = (0..width)
+            .map(|x| {
+                let position = x % 40;
+                if position < 10 {
+                    255
+                } else if position < 10 + blur {
+                    255 - ramp(position - 10)
+                } else if position < 30 {
+                    0
+                } else if position < 30 + blur {
+                    ramp(position - 30)
+                } else {
+                    255
+                }
+            })
+            .collect();
+        LuminanceImage {
+            width: width as u32,
+            height,
+            pixels: row.repeat(height as usize),
+        }
+    }
+
+    /// Ensure that sharp pages score higher than blurry pages.
+    #[test]
+    fn sharp_vs_blurry() {
+        let sharp = assess_image(&bars(0));
+        let blurry = assess_image(&bars(8));
+        assert_eq!(sharp.contrast, 100.0);
+        assert_eq!(sharp.sharpness, 100.0);
+        assert!(blurry.sharpness < 20.0, "{blurry:?}");
+        assert!(sharp.score() > blurry.score());
+    }
+
+    /// Ensure that a blank page has a score of 0.
+    #[test]
+    fn blank_page() {
+        let quality = assess_image(&LuminanceImage {
+            width: 10,
+            height: 10,
+            pixels: vec![250; 100],
+        });
+        assert_eq!(quality.score(), 0.0);
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    events::{self, Event},
+    fs_utils,
+    lock::LockHolder,
+    manifest::{DocumentState, Manifest},
+    ui,
+};
+
+/// Name of the file that marks a scan directory as processed
+pub const FINAL_PDF: &str = "_final.pdf";
+
+/// Name of the optional, smaller copy of the final PDF for emailing
+pub const EMAIL_PDF: &str = "_email.pdf";
+
+/// Name prefix of the staging directories that are currently being scanned into
+pub const CURRENT_DIR: &str = "current";
+
+/// Name of the marker file in the scans directory that pauses processing
+const PAUSED_MARKER: &str = "paused";
+
+/// Name of the file in the scans directory that persists the processing queue
+const QUEUE_FILE: &str = "queue.toml";
+
+/// Name of the file in the scans directory that is locked while the
+/// processing queue is modified, so that multiple processes can share it
+const QUEUE_LOCK_FILE: &str = "queue.lock";
+
+/// Priority of a processing job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Priority {
+    /// Documents that were scanned in an earlier run, but not yet processed
+    Backlog,
+    /// Documents whose processing was interrupted (e.g. by a crash or reboot)
+    Resumed,
+    /// The document that was just scanned
+    Recent,
+}
+
+/// A scan directory waiting to be processed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Job {
+    priority: Priority,
+    directory: PathBuf,
+    /// Process that is processing the job, if it is in flight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<LockHolder>,
+}
+
+impl Job {
+    fn new(directory: PathBuf, priority: Priority) -> Self {
+        Self {
+            priority,
+            directory,
+            owner: None,
+        }
+    }
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Higher priority first. Within the same priority, older directories
+        // (which sort lower, since they are named by timestamp) come first.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.directory.cmp(&self.directory))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A scan directory whose processing failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Failure {
+    directory: PathBuf,
+    error: String,
+}
+
+/// Persisted state of the processing queue
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueFile {
+    #[serde(default)]
+    queued: Vec<Job>,
+    #[serde(default)]
+    in_flight: Vec<Job>,
+    #[serde(default)]
+    failed: Vec<Failure>,
+}
+
+/// Queue of scan directories waiting to be processed, ordered by priority
+///
+/// A queue that was loaded from a scans directory is persisted there after
+/// every change, so that processing resumes where it left off after a restart.
+#[derive(Debug, Default)]
+pub struct ProcessingQueue {
+    jobs: BinaryHeap<Job>,
+    /// Jobs that are being processed, but are not finished yet
+    in_flight: Vec<Job>,
+    /// Jobs whose processing failed, which are not queued again
+    failed: Vec<Failure>,
+    /// File the queue is persisted to
+    path: Option<PathBuf>,
+}
+
+impl ProcessingQueue {
+    /// Load the persisted queue of the scans directory
+    ///
+    /// Jobs that were in flight in a process that is no longer running are
+    /// queued again with priority. Unprocessed scan directories that are not
+    /// queued yet (and did not fail before) are added as backlog.
+    pub fn load(scans_dir: &Path) -> Result<Self> {
+        let mut queue = Self {
+            path: Some(scans_dir.join(QUEUE_FILE)),
+            ..Default::default()
+        };
+        let _lock = lock_queue(scans_dir)?;
+        queue.reload()?;
+
+        let (running, interrupted): (Vec<Job>, Vec<Job>) = queue
+            .in_flight
+            .drain(..)
+            .partition(|job| job.owner.as_ref().is_some_and(LockHolder::is_running));
+        queue.in_flight = running;
+        for job in interrupted {
+            debug!("Resuming interrupted processing of {:?}", job.directory);
+            queue.jobs.push(Job::new(job.directory, Priority::Resumed));
+        }
+        for directory in find_unprocessed(scans_dir)? {
+            let known = queue
+                .jobs
+                .iter()
+                .chain(&queue.in_flight)
+                .any(|job| job.directory == directory)
+                || queue.is_failed(&directory);
+            if !known {
+                queue.jobs.push(Job::new(directory, Priority::Backlog));
+            }
+        }
+        queue.save();
+        Ok(queue)
+    }
+
+    /// Replace the jobs with the persisted ones, dropping processed and
+    /// removed scan directories
+    fn reload(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = read_queue_file(path)?;
+        let is_unprocessed =
+            |job: &Job| job.directory.is_dir() && !job.directory.join(FINAL_PDF).exists();
+        self.jobs = file.queued.into_iter().filter(is_unprocessed).collect();
+        self.in_flight = file.in_flight.into_iter().filter(is_unprocessed).collect();
+        self.failed = file
+            .failed
+            .into_iter()
+            .filter(|failure| {
+                failure.directory.is_dir() && !failure.directory.join(FINAL_PDF).exists()
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Write the queue to its file, if it was loaded from a scans directory
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = QueueFile {
+            queued: self.jobs.clone().into_sorted_vec(),
+            in_flight: self.in_flight.clone(),
+            failed: self.failed.clone(),
+        };
+        let result = toml::to_string(&file)
+            .context("Failed to serialize processing queue")
+            .and_then(|content| fs_utils::write_synced(path, content));
+        if let Err(e) = result {
+            warn!("Failed to save processing queue {path:?}: {e:#}");
+        }
+    }
+
+    /// Apply a change to the queue
+    ///
+    /// A persisted queue is locked, reloaded, changed and saved again, so that
+    /// the changes of other processes are kept.
+    fn modify<T>(&mut self, change: impl FnOnce(&mut Self) -> T) -> T {
+        let Some(scans_dir) = self
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+        else {
+            return change(self);
+        };
+        let lock = lock_queue(&scans_dir).and_then(|lock| {
+            self.reload()?;
+            Ok(lock)
+        });
+        if let Err(e) = &lock {
+            warn!("Failed to reload processing queue: {e:#}");
+        }
+        let result = change(self);
+        self.save();
+        drop(lock);
+        result
+    }
+
+    /// Add a scan directory to the queue
+    ///
+    /// If the directory is already queued, only the higher priority is kept.
+    pub fn push(&mut self, directory: PathBuf, priority: Priority) {
+        self.modify(|queue| {
+            if let Some(existing) = queue.jobs.iter().find(|job| job.directory == directory) {
+                if existing.priority >= priority {
+                    return;
+                }
+                queue.jobs.retain(|job| job.directory != directory);
+            }
+            queue.jobs.push(Job::new(directory, priority));
+        });
+    }
+
+    /// Remove and return the scan directory that should be processed next
+    ///
+    /// The job stays in flight until it is finished (see [`Self::finish`]).
+    pub fn pop(&mut self) -> Option<PathBuf> {
+        self.modify(|queue| {
+            let job = queue.jobs.pop()?;
+            queue.in_flight.push(Job {
+                owner: Some(LockHolder::current()),
+                ..job.clone()
+            });
+            Some(job.directory)
+        })
+    }
+
+    /// Mark a scan directory as in flight, to process it right away instead of
+    /// in queue order
+    ///
+    /// If the processing is interrupted, the directory is resumed by the next
+    /// run.
+    pub fn start(&mut self, directory: PathBuf) {
+        self.modify(|queue| {
+            queue.jobs.retain(|job| job.directory != directory);
+            queue.in_flight.retain(|job| job.directory != directory);
+            queue
+                .failed
+                .retain(|failure| failure.directory != directory);
+            queue.in_flight.push(Job {
+                owner: Some(LockHolder::current()),
+                ..Job::new(directory, Priority::Recent)
+            });
+        });
+    }
+
+    /// Mark the processing of a scan directory as finished
+    pub fn finish(&mut self, directory: &Path) {
+        self.modify(|queue| queue.in_flight.retain(|job| job.directory != directory));
+    }
+
+    /// Mark the processing of a scan directory as failed
+    ///
+    /// The directory is not queued again until it is processed explicitly
+    /// (e.g. with `arkivisto process`).
+    pub fn fail(&mut self, directory: &Path, error: &anyhow::Error) {
+        self.modify(|queue| {
+            queue.in_flight.retain(|job| job.directory != directory);
+            queue
+                .failed
+                .retain(|failure| failure.directory != directory);
+            queue.failed.push(Failure {
+                directory: directory.to_path_buf(),
+                error: format!("{error:#}"),
+            });
+        });
+    }
+
+    /// Whether the processing of a scan directory failed
+    fn is_failed(&self, directory: &Path) -> bool {
+        self.failed
+            .iter()
+            .any(|failure| failure.directory == directory)
+    }
+
+    /// Number of queued scan directories
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+/// Lock the processing queue of the scans directory until the returned file
+/// is dropped
+fn lock_queue(scans_dir: &Path) -> Result<File> {
+    let path = scans_dir.join(QUEUE_LOCK_FILE);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open queue lock {path:?}"))?;
+    file.lock()
+        .with_context(|| format!("Failed to lock {path:?}"))?;
+    Ok(file)
+}
+
+/// Read a persisted queue, empty if there is none yet
+fn read_queue_file(path: &Path) -> Result<QueueFile> {
+    if !path.exists() {
+        return Ok(QueueFile::default());
+    }
+    let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+        .with_context(|| format!("Failed to read processing queue {path:?}"))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse processing queue {path:?}"))
+}
+
+/// State of the processing queue, see [`status`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct QueueStatus {
+    /// Number of unprocessed scan directories that are waiting
+    pub queued: usize,
+    /// Number of scan directories whose processing was interrupted or is still
+    /// running
+    pub in_flight: usize,
+    /// Scan directories whose processing failed, with the error
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// State of the processing queue, without modifying the persisted queue
+pub fn status(scans_dir: &Path) -> Result<QueueStatus> {
+    let file = read_queue_file(&scans_dir.join(QUEUE_FILE))?;
+    let skipped: Vec<&Path> = file
+        .in_flight
+        .iter()
+        .map(|job| job.directory.as_path())
+        .chain(
+            file.failed
+                .iter()
+                .map(|failure| failure.directory.as_path()),
+        )
+        .collect();
+    let queued = find_unprocessed(scans_dir)?
+        .into_iter()
+        .filter(|directory| !skipped.contains(&directory.as_path()))
+        .count();
+    Ok(QueueStatus {
+        queued,
+        in_flight: file.in_flight.len(),
+        failed: file
+            .failed
+            .into_iter()
+            .map(|failure| (failure.directory, failure.error))
+            .collect(),
+    })
+}
+
+/// Find all scan directories in `scans_dir` that have not been processed yet
+///
+/// Directories of archived documents (kept for reprocessing) are skipped. The
+/// returned directories are sorted by name (i.e. oldest first).
+pub fn find_unprocessed(scans_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut directories = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(scans_dir))
+        .with_context(|| format!("Failed to read scans directory {:?}", scans_dir))?
+    {
+        let entry = entry?;
+        let is_staging_dir = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(CURRENT_DIR));
+        if !entry.file_type()?.is_dir() || is_staging_dir {
+            continue;
+        }
+        let path = entry.path();
+        let archived =
+            Manifest::load(&path).is_ok_and(|manifest| manifest.state == DocumentState::Archived);
+        if !path.join(FINAL_PDF).exists() && !archived {
+            directories.push(path);
+        }
+    }
+    directories.sort();
+    Ok(directories)
+}
+
+/// Pause or resume processing of the queue
+///
+/// The state is stored as a marker file in the scans directory, so that it
+/// affects all running processes and survives restarts.
+pub fn set_paused(scans_dir: &Path, paused: bool) -> Result<()> {
+    let marker = scans_dir.join(PAUSED_MARKER);
+    if paused {
+        fs::write(&marker, b"").context("Failed to create pause marker")?;
+    } else if marker.exists() {
+        fs::remove_file(&marker).context("Failed to remove pause marker")?;
+    }
+    Ok(())
+}
+
+/// Whether processing of the queue is paused
+pub fn is_paused(scans_dir: &Path) -> bool {
+    scans_dir.join(PAUSED_MARKER).exists()
+}
+
+/// Block until processing of the queue is resumed
+pub fn wait_while_paused(scans_dir: &Path) {
+    if !is_paused(scans_dir) {
+        return;
+    }
+    debug!("Processing is paused, waiting");
+    events::emit(&Event::QueuePaused);
+    let spinner = ui::spinner("Processing is paused. Run `arkivisto queue resume` to continue…");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    while is_paused(scans_dir) {
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    spinner.finish_with_message(ui::success("Processing resumed"));
+    events::emit(&Event::QueueResumed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+
+    use tempfile::TempDir;
+
+    /// Ensure that the recent scan is processed first, followed by the backlog
+    /// in chronological order.
+    #[test]
+    fn recent_before_backlog() {
+        let mut queue = ProcessingQueue::default();
+        queue.push("20250102-120000".into(), Priority::Backlog);
+        queue.push("20250103-120000".into(), Priority::Recent);
+        queue.push("20250101-120000".into(), Priority::Backlog);
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.pop(), Some("20250103-120000".into()));
+        assert_eq!(queue.pop(), Some("20250101-120000".into()));
+        assert_eq!(queue.pop(), Some("20250102-120000".into()));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    /// Ensure that a directory is only queued once, with its highest priority.
+    #[test]
+    fn deduplicate() {
+        let mut queue = ProcessingQueue::default();
+        queue.push("20250101-120000".into(), Priority::Backlog);
+        queue.push("20250102-120000".into(), Priority::Backlog);
+        queue.push("20250102-120000".into(), Priority::Recent);
+        queue.push("20250102-120000".into(), Priority::Backlog);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some("20250102-120000".into()));
+    }
+
+    /// Ensure that processed directories, directories of archived documents,
+    /// staging directories and files are ignored.
+    #[test]
+    fn find_unprocessed_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        for name in ["20250102-120000", "20250101-120000", "20250103-120000"] {
+            fs::create_dir(scans_dir.join(name)).unwrap();
+        }
+        File::create(scans_dir.join("20250103-120000").join(FINAL_PDF)).unwrap();
+        fs::create_dir(scans_dir.join(CURRENT_DIR)).unwrap();
+        fs::create_dir(scans_dir.join(format!("{CURRENT_DIR}-scanner"))).unwrap();
+        File::create(scans_dir.join("20250104-120000")).unwrap();
+        let archived = scans_dir.join("20250105-120000");
+        fs::create_dir(&archived).unwrap();
+        let manifest = Manifest {
+            state: DocumentState::Archived,
+            ..Default::default()
+        };
+        manifest.save(&archived).unwrap();
+
+        assert_eq!(
+            find_unprocessed(scans_dir).unwrap(),
+            vec![
+                scans_dir.join("20250101-120000"),
+                scans_dir.join("20250102-120000"),
+            ]
+        );
+    }
+
+    /// Ensure that the queue is persisted, that jobs of processes that are
+    /// no longer running are resumed with priority, and that new scans are
+    /// discovered.
+    #[test]
+    fn persist_and_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        for name in ["20250101-120000", "20250102-120000", "20250103-120000"] {
+            fs::create_dir(scans_dir.join(name)).unwrap();
+        }
+
+        // Without a persisted queue, unprocessed scans are discovered
+        let mut queue = ProcessingQueue::load(scans_dir).unwrap();
+        assert_eq!(queue.len(), 3);
+        queue.push(scans_dir.join("20250103-120000"), Priority::Recent);
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250103-120000")));
+        queue.finish(&scans_dir.join("20250103-120000"));
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250101-120000")));
+        assert_eq!(queue.in_flight.len(), 1);
+        let state = status(scans_dir).unwrap();
+        assert_eq!((state.queued, state.in_flight), (2, 1));
+
+        // Jobs of running processes are not taken over, new scans are
+        // discovered
+        fs::create_dir(scans_dir.join("20250104-120000")).unwrap();
+        let mut other = ProcessingQueue::load(scans_dir).unwrap();
+        assert_eq!(other.len(), 3);
+        assert_eq!(other.in_flight.len(), 1);
+        assert_eq!(other.pop(), Some(scans_dir.join("20250102-120000")));
+
+        // Both processes share the persisted queue
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250103-120000")));
+        assert_eq!(queue.in_flight.len(), 3);
+        queue.finish(&scans_dir.join("20250103-120000"));
+        other.finish(&scans_dir.join("20250102-120000"));
+        for name in ["20250102-120000", "20250103-120000"] {
+            File::create(scans_dir.join(name).join(FINAL_PDF)).unwrap();
+        }
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250104-120000")));
+        assert_eq!(queue.pop(), None);
+        drop((queue, other));
+
+        // Jobs of processes that are no longer running are resumed first
+        let path = scans_dir.join(QUEUE_FILE);
+        let mut file = read_queue_file(&path).unwrap();
+        file.in_flight[0].owner.as_mut().unwrap().pid = u32::MAX;
+        fs::write(&path, toml::to_string(&file).unwrap()).unwrap();
+        fs::create_dir(scans_dir.join("20250105-120000")).unwrap();
+        let mut queue = ProcessingQueue::load(scans_dir).unwrap();
+        assert_eq!(queue.in_flight.len(), 1);
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250101-120000")));
+        assert_eq!(queue.pop(), Some(scans_dir.join("20250105-120000")));
+        assert_eq!(queue.pop(), None);
+
+        // A specific directory can be processed out of order
+        fs::create_dir(scans_dir.join("20250106-120000")).unwrap();
+        queue.push(scans_dir.join("20250106-120000"), Priority::Backlog);
+        queue.start(scans_dir.join("20250106-120000"));
+        assert!(queue.is_empty());
+        assert_eq!(queue.in_flight.len(), 4);
+        queue.finish(&scans_dir.join("20250106-120000"));
+        assert_eq!(queue.in_flight.len(), 3);
+
+        // Processed and removed directories are dropped
+        for name in ["20250101-120000", "20250104-120000", "20250106-120000"] {
+            File::create(scans_dir.join(name).join(FINAL_PDF)).unwrap();
+        }
+        fs::remove_dir(scans_dir.join("20250105-120000")).unwrap();
+        let queue = ProcessingQueue::load(scans_dir).unwrap();
+        assert!(queue.is_empty());
+        assert!(queue.in_flight.is_empty());
+    }
+
+    /// Ensure that failed jobs are not queued again, unless they are processed
+    /// explicitly.
+    #[test]
+    fn failed_jobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        for name in ["20250101-120000", "20250102-120000"] {
+            fs::create_dir(scans_dir.join(name)).unwrap();
+        }
+        let failed = scans_dir.join("20250101-120000");
+
+        let mut queue = ProcessingQueue::load(scans_dir).unwrap();
+        assert_eq!(queue.pop(), Some(failed.clone()));
+        queue.fail(&failed, &anyhow::anyhow!("OCR failed"));
+        assert!(queue.in_flight.is_empty());
+        assert_eq!(
+            status(scans_dir).unwrap(),
+            QueueStatus {
+                queued: 1,
+                in_flight: 0,
+                failed: vec![(failed.clone(), "OCR failed".into())],
+            }
+        );
+
+        let mut queue = ProcessingQueue::load(scans_dir).unwrap();
+        assert_eq!(queue.len(), 1);
+        queue.start(failed.clone());
+        queue.finish(&failed);
+        assert!(status(scans_dir).unwrap().failed.is_empty());
+    }
+
+    /// Ensure that pausing and resuming toggles the paused state, and that the
+    /// marker file is not treated as a scan directory.
+    #[test]
+    fn pause_and_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let scans_dir = temp_dir.path();
+        assert!(!is_paused(scans_dir));
+
+        set_paused(scans_dir, true).unwrap();
+        assert!(is_paused(scans_dir));
+        set_paused(scans_dir, true).unwrap();
+        assert!(is_paused(scans_dir));
+        assert!(find_unprocessed(scans_dir).unwrap().is_empty());
+
+        set_paused(scans_dir, false).unwrap();
+        assert!(!is_paused(scans_dir));
+        set_paused(scans_dir, false).unwrap();
+        assert!(!is_paused(scans_dir));
+    }
+}
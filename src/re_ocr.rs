@@ -0,0 +1,330 @@
+//! Running OCR again over archived PDFs
+//!
+//! After an upgrade of Tesseract (or with other OCR languages), the text
+//! layers of the archived PDFs can be recognized anew with `arkivisto re-ocr`.
+//! OCRmyPDF replaces the text layer while keeping the page images. The
+//! previous PDF is kept as an older version, and the old and new text are
+//! recorded next to each other in a hidden directory of the archive for
+//! comparison. The run is meant to be left running in the background: it
+//! waits while processing is paused or deferred (see [`power::defer_reason`]),
+//! can pause between documents, and continues where it stopped. A new run
+//! (e.g. after the next upgrade) starts over with `--restart`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, warn};
+
+use crate::{
+    archive, audit,
+    config::Config,
+    fs_utils,
+    naming::DocumentInfo,
+    ocr::{self, Ocrmypdf},
+    power, queue,
+    scheduler::{self, JobKind},
+    search::{self, Document},
+    tools, ui,
+};
+
+/// Name of the hidden directory in the archive with the old and new text of
+/// the recognized documents
+const COMPARISON_DIR: &str = ".arkivisto-re-ocr";
+
+/// Name of the copy of the archived PDF in the work directory
+const ARCHIVED_PDF: &str = "_archived.pdf";
+
+/// Interval in which a deferred run checks whether it may continue
+const DEFER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Paths of the old and the new text of an archived PDF of `outdir` in the
+/// [`COMPARISON_DIR`], which mirrors the structure of the archive
+fn comparison_paths(outdir: &Path, pdf: &Path) -> (PathBuf, PathBuf) {
+    let key = Path::new(&fs_utils::relative_key(outdir, pdf)).with_extension("");
+    let base = outdir.join(COMPARISON_DIR).join(key);
+    let stem = base.file_name().unwrap_or_default().to_string_lossy();
+    (
+        base.with_file_name(format!("{stem}.old.txt")),
+        base.with_file_name(format!("{stem}.new.txt")),
+    )
+}
+
+/// Drop the documents whose new text was already recorded by an earlier run
+fn pending(outdir: &Path, documents: Vec<Document>) -> Vec<Document> {
+    documents
+        .into_iter()
+        .filter(|document| !comparison_paths(outdir, &document.path).1.exists())
+        .collect()
+}
+
+/// Drop the old and new text recorded by the earlier runs in `outdir`, so
+/// that the next run recognizes all documents again
+pub fn restart(outdir: &Path) -> Result<()> {
+    let comparison_dir = outdir.join(COMPARISON_DIR);
+    if !comparison_dir.exists() {
+        return Ok(());
+    }
+    fs::remove_dir_all(&comparison_dir)
+        .with_context(|| format!("Failed to remove {comparison_dir:?}"))
+}
+
+/// Find the archived documents of the given PDFs in the search index
+fn find_documents(pdfs: &[PathBuf], config: &Config) -> Result<Vec<Document>> {
+    let outdir = fs::canonicalize(&config.outdir)
+        .with_context(|| format!("Archive directory {:?} does not exist", config.outdir))?;
+    let mut documents = search::documents(&config.outdir)?;
+    pdfs.iter()
+        .map(|pdf| {
+            let pdf = fs::canonicalize(pdf)
+                .with_context(|| format!("Document {pdf:?} does not exist"))?;
+            if !pdf.starts_with(&outdir) {
+                bail!("{pdf:?} is not in the archive directory {outdir:?}");
+            }
+            let key = fs_utils::relative_key(&outdir, &pdf);
+            let Some(position) = documents
+                .iter()
+                .position(|document| fs_utils::relative_key(&config.outdir, &document.path) == key)
+            else {
+                bail!("{pdf:?} is not in the search index (run `arkivisto index`)");
+            };
+            Ok(documents.swap_remove(position))
+        })
+        .collect()
+}
+
+/// Block while processing is paused (`arkivisto queue pause`) or should be
+/// deferred (e.g. on battery power)
+fn wait_until_allowed(scans_dir: &Path, config: &Config) {
+    queue::wait_while_paused(scans_dir);
+    let mut deferred = false;
+    while let Some(reason) = power::defer_reason(&config.processing) {
+        if !deferred {
+            println!("Waiting to continue: {reason}");
+            deferred = true;
+        }
+        thread::sleep(DEFER_CHECK_INTERVAL);
+    }
+}
+
+/// Number of whitespace-separated words of a text
+fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+/// Recognize the text of an archived document anew in `work_dir`, replace
+/// the PDF if the text changed, and record the old and new text
+///
+/// Returns whether the text changed.
+fn re_ocr_document(
+    document: &Document,
+    ocrmypdf: &Ocrmypdf,
+    languages: &[String],
+    work_dir: &Path,
+    config: &Config,
+) -> Result<bool> {
+    let pdf = &document.path;
+    fs_utils::ensure_empty_dir_exists(work_dir)?;
+    let archived = work_dir.join(ARCHIVED_PDF);
+    fs::copy(pdf, &archived).with_context(|| format!("Failed to copy {pdf:?}"))?;
+    scheduler::global().run(JobKind::Cpu, || {
+        ocr::run_ocrmypdf(
+            ocrmypdf,
+            work_dir,
+            &archived,
+            languages,
+            &["--redo-ocr".to_string()],
+            &config.processing.limits,
+        )
+    })?;
+    let new_pdf = work_dir.join(queue::FINAL_PDF);
+    let new_text = fs::read_to_string(work_dir.join(ocr::OCR_TEXT)).unwrap_or_default();
+
+    let old_text = if document.text.is_empty() && tools::is_installed("pdftotext") {
+        search::extract_text(pdf).unwrap_or_default()
+    } else {
+        document.text.clone()
+    };
+    let (old_path, new_path) = comparison_paths(&config.outdir, pdf);
+    if let Some(parent) = old_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {parent:?}"))?;
+    }
+    fs_utils::write_synced(&old_path, &old_text)
+        .with_context(|| format!("Failed to write {old_path:?}"))?;
+    let changed = new_text.trim() != old_text.trim();
+    if changed {
+        let email_pdf = pdf.with_file_name(format!(
+            "{}_email.pdf",
+            pdf.file_stem().unwrap_or_default().to_string_lossy()
+        ));
+        let new_email_pdf = if email_pdf.exists() {
+            ocr::optimize_for_email(ocrmypdf, work_dir, &config.processing.limits)?;
+            Some(work_dir.join(queue::EMAIL_PDF))
+        } else {
+            None
+        };
+        let tags = document.tags.join(", ");
+        let id = document.id.as_deref().unwrap_or_default();
+        if let Err(e) = archive::store_metadata(&new_pdf, &tags, id, config) {
+            warn!("Failed to store the tags and the ID in the PDF metadata: {e:#}");
+        }
+        archive::replace_pdf(pdf, &new_pdf, new_email_pdf.as_deref(), config)?;
+        let info = match (document.date, &document.title) {
+            (Some(date), Some(title)) => Some(DocumentInfo {
+                date,
+                title: title.clone(),
+                correspondent: document.correspondent.clone(),
+                tags: document.tags.clone(),
+                due: document.due,
+                location: document.location.clone(),
+            }),
+            _ => None,
+        };
+        search::add(
+            &config.outdir,
+            pdf,
+            &new_text,
+            document.id.as_deref(),
+            info.as_ref(),
+        )?;
+        let event = audit::Event::new(
+            audit::Action::ReOcr,
+            &config.outdir,
+            pdf,
+            config.scan.timezone,
+        )
+        .with_details(format!(
+            "{} instead of {} words",
+            word_count(&new_text),
+            word_count(&old_text)
+        ));
+        audit::record(&config.outdir, &event);
+    }
+    // The new text is recorded last, since it marks the document as done
+    fs_utils::write_synced(&new_path, &new_text)
+        .with_context(|| format!("Failed to write {new_path:?}"))?;
+    Ok(changed)
+}
+
+/// Run OCR again over the given archived PDFs (or all of them), return the
+/// number of documents whose text changed
+///
+/// Documents that were recognized by an earlier run are skipped, unless they
+/// are given explicitly. Documents that fail are skipped with a warning.
+pub fn re_ocr(
+    pdfs: &[PathBuf],
+    all: bool,
+    pause: Duration,
+    scans_dir: &Path,
+    config: &Config,
+) -> Result<usize> {
+    let Some(ocrmypdf) = ocr::find_ocrmypdf(&config.ocr) else {
+        tools::warn_missing(
+            ocr::ocrmypdf_program(&config.ocr),
+            "the archived PDFs can't be recognized again",
+        );
+        return Ok(0);
+    };
+    let documents = if all {
+        pending(&config.outdir, search::documents(&config.outdir)?)
+    } else {
+        find_documents(pdfs, config)?
+    };
+    let languages = config.ocr_languages();
+    println!(
+        "Running OCR again over {} document(s), the old and new text is recorded in {}",
+        documents.len(),
+        config.outdir.join(COMPARISON_DIR).display()
+    );
+
+    let work_dir = env::temp_dir().join(format!("arkivisto-re-ocr-{}", std::process::id()));
+    let mut changed = 0;
+    for (number, document) in documents.iter().enumerate() {
+        if number > 0 && !pause.is_zero() {
+            thread::sleep(pause);
+        }
+        wait_until_allowed(scans_dir, config);
+        let relative = fs_utils::relative_key(&config.outdir, &document.path);
+        match re_ocr_document(document, &ocrmypdf, &languages, &work_dir, config) {
+            Ok(true) => {
+                changed += 1;
+                println!(
+                    "{}",
+                    ui::success(format!(
+                        "[{}/{}] Replaced the text of {relative}",
+                        number + 1,
+                        documents.len()
+                    ))
+                );
+            }
+            Ok(false) => println!(
+                "[{}/{}] The text of {relative} is unchanged",
+                number + 1,
+                documents.len()
+            ),
+            Err(e) => warn!("Skipping {relative}: {e:#}"),
+        }
+    }
+    if work_dir.exists()
+        && let Err(e) = fs::remove_dir_all(&work_dir)
+    {
+        debug!("Failed to remove {work_dir:?}: {e}");
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that the old and new text of a document mirror its path in the
+    /// archive.
+    #[test]
+    fn comparison() {
+        let outdir = Path::new("/archive");
+        let (old, new) = comparison_paths(outdir, &outdir.join("2025/2025-05-30_invoice.pdf"));
+        assert_eq!(
+            old,
+            Path::new("/archive/.arkivisto-re-ocr/2025/2025-05-30_invoice.old.txt")
+        );
+        assert_eq!(
+            new,
+            Path::new("/archive/.arkivisto-re-ocr/2025/2025-05-30_invoice.new.txt")
+        );
+    }
+
+    /// Ensure that documents whose new text was recorded by an earlier run
+    /// are skipped, until a new run is started.
+    #[test]
+    fn skip_recognized() {
+        let outdir = TempDir::new().unwrap();
+        let document = |name: &str| Document {
+            path: outdir.path().join(name),
+            ..Default::default()
+        };
+        let (_, new) = comparison_paths(outdir.path(), &outdir.path().join("done.pdf"));
+        fs::create_dir_all(new.parent().unwrap()).unwrap();
+        fs::write(&new, "text").unwrap();
+
+        let documents = pending(
+            outdir.path(),
+            vec![document("done.pdf"), document("open.pdf")],
+        );
+        assert_eq!(documents, vec![document("open.pdf")]);
+
+        // A new run recognizes all documents again
+        restart(outdir.path()).unwrap();
+        let documents = pending(
+            outdir.path(),
+            vec![document("done.pdf"), document("open.pdf")],
+        );
+        assert_eq!(documents, vec![document("done.pdf"), document("open.pdf")]);
+        restart(outdir.path()).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! Expense report of archived receipts
+//!
+//! The totals of receipts (documents with a tag like `receipt`) are detected in
+//! their recognized text and summed per month of the document date. Receipts
+//! without a detected total are listed separately, so that they can be checked
+//! by hand.
+
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
+use chrono::NaiveDate;
+
+use crate::{search::Document, statements};
+
+/// Keywords (lowercase) of the lines with the total of a receipt
+const TOTAL_KEYWORDS: [&str; 8] = [
+    "total",
+    "summe",
+    "gesamt",
+    "zu zahlen",
+    "amount due",
+    "montant",
+    "betrag",
+    "totale",
+];
+
+/// Detect the total of a receipt in its recognized text, in cents
+///
+/// The total is the largest amount at the end of a line with a keyword like
+/// "Total" or "Summe", which skips subtotals and the VAT.
+pub fn find_total(text: &str) -> Option<i64> {
+    text.lines()
+        .filter(|line| {
+            let lowercase = line.to_lowercase();
+            TOTAL_KEYWORDS
+                .iter()
+                .any(|keyword| lowercase.contains(keyword))
+        })
+        .filter_map(|line| line.split_whitespace().next_back())
+        .filter_map(statements::parse_amount)
+        .filter_map(|amount| amount.replace('.', "").parse().ok())
+        .max()
+}
+
+/// Format an amount in cents with two decimal places (e.g. `-1234.50`)
+fn format_amount(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}{}.{:02}", cents.abs() / 100, cents.abs() % 100)
+}
+
+/// Receipts of a month
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Month {
+    pub receipts: usize,
+    /// Sum of the detected totals, in cents
+    pub total: i64,
+}
+
+/// Expenses per month, from the receipts of a period
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Receipts with a detected total, by month (e.g. `2025-03`)
+    pub months: BTreeMap<String, Month>,
+    /// Receipts whose total was not detected
+    pub without_total: Vec<PathBuf>,
+}
+
+impl Report {
+    /// Sum of all detected totals, in cents
+    pub fn total(&self) -> i64 {
+        self.months.values().map(|month| month.total).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (month, summary) in &self.months {
+            writeln!(
+                f,
+                "{month}  {:>4} receipt(s)  {:>12}",
+                summary.receipts,
+                format_amount(summary.total)
+            )?;
+        }
+        let receipts: usize = self.months.values().map(|month| month.receipts).sum();
+        write!(
+            f,
+            "Total    {receipts:>4} receipt(s)  {:>12}",
+            format_amount(self.total())
+        )
+    }
+}
+
+/// Sum the totals of the `documents` with the tag `tag` that are dated within
+/// `from` and `to` (inclusive) per month
+///
+/// Documents without a date are skipped.
+pub fn report(
+    documents: &[Document],
+    tag: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Report {
+    let mut report = Report::default();
+    for document in documents {
+        let Some(date) = document.date else {
+            continue;
+        };
+        let tagged = document
+            .tags
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(tag));
+        if !tagged || from.is_some_and(|from| date < from) || to.is_some_and(|to| date > to) {
+            continue;
+        }
+        match find_total(&document.text) {
+            Some(total) => {
+                let month = report
+                    .months
+                    .entry(date.format("%Y-%m").to_string())
+                    .or_default();
+                month.receipts += 1;
+                month.total += total;
+            }
+            None => report.without_total.push(document.path.clone()),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the total is detected, and not a subtotal, the VAT or an
+    /// amount of an article.
+    #[test]
+    fn totals() {
+        let receipt = "Migros Zürich\n\
+            Äpfel 3.20\n\
+            Brot 4.50\n\
+            Zwischensumme 7.70\n\
+            Total CHF 12.70\n\
+            MwSt 2.6% total 0.32\n\
+            Bar 20.00\n\
+            Rückgeld 7.30";
+        assert_eq!(find_total(receipt), Some(1270));
+        assert_eq!(find_total("TOTAL EUR 1.234,50"), Some(123450));
+        assert_eq!(find_total("Gesamt\n12.50"), None);
+        assert_eq!(find_total("Bread 4.50"), None);
+    }
+
+    /// Ensure that the receipts of the period are summed per month, and that
+    /// receipts without total are listed.
+    #[test]
+    fn monthly_report() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2025, month, day);
+        let receipt = |name: &str, date, text: &str| Document {
+            path: PathBuf::from(name),
+            date,
+            tags: vec!["Receipt".into()],
+            text: text.into(),
+            ..Default::default()
+        };
+        let documents = [
+            receipt("a.pdf", date(1, 5), "Total 10.50"),
+            receipt("b.pdf", date(1, 20), "Summe 4.25"),
+            receipt("c.pdf", date(2, 1), "Total 100.00"),
+            receipt("blurry.pdf", date(2, 3), "T0tal"),
+            receipt("undated.pdf", None, "Total 1.00"),
+            receipt("late.pdf", date(4, 1), "Total 1.00"),
+            Document {
+                tags: Vec::new(),
+                ..receipt("invoice.pdf", date(1, 7), "Total 99.00")
+            },
+        ];
+
+        let report = report(&documents, "receipt", date(1, 1), date(3, 31));
+        assert_eq!(
+            report.months.keys().collect::<Vec<_>>(),
+            vec!["2025-01", "2025-02"]
+        );
+        assert_eq!(
+            report.months["2025-01"],
+            Month {
+                receipts: 2,
+                total: 1475
+            }
+        );
+        assert_eq!(report.without_total, vec![PathBuf::from("blurry.pdf")]);
+        assert_eq!(report.total(), 11475);
+        assert_eq!(
+            report.to_string().lines().last(),
+            Some("Total       3 receipt(s)        114.75")
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! Export of scanned papers to reference managers
+//!
+//! Scanned journal articles and offprints belong into a reference manager
+//! rather than the household archive. They are pushed into a running Zotero
+//! through the local server of its connector, or exported as RIS file, which
+//! Zotero, Mendeley and others import with the PDFs attached. Both carry the
+//! DOI found in the recognized text.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::{Value, json};
+use tracing::debug;
+use ulid::Ulid;
+
+use crate::{fs_utils, search::Document};
+
+/// Prefix of all DOIs
+const DOI_PREFIX: &str = "10.";
+
+/// Address of the local server of the Zotero connector, which runs while
+/// Zotero is open
+pub const ZOTERO_ADDRESS: &str = "127.0.0.1:23119";
+
+/// Timeout of requests to Zotero (saving a large PDF may take a while)
+const ZOTERO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Find the first DOI in a text (e.g. `10.1000/xyz123` in
+/// `doi:10.1000/xyz123.`), without trailing punctuation
+fn find_doi(text: &str) -> Option<String> {
+    let mut rest = text;
+    while let Some(start) = rest.find(DOI_PREFIX) {
+        let candidate = &rest[start..];
+        // The registrant code has at least four digits (and possibly
+        // subdivisions), followed by a slash and the suffix
+        let registrant_len = candidate[DOI_PREFIX.len()..]
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .map_or(0, |len| len + DOI_PREFIX.len());
+        let inside_word = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric());
+        if !inside_word
+            && registrant_len >= DOI_PREFIX.len() + 4
+            && candidate[registrant_len..].starts_with('/')
+        {
+            let end = candidate
+                .find(|c: char| c.is_whitespace() || c == '"' || c == '<' || c == '>')
+                .unwrap_or(candidate.len());
+            let doi = candidate[..end].trim_end_matches(['.', ',', ';', ':', ')', ']']);
+            if doi.len() > registrant_len + 1 {
+                return Some(doi.to_string());
+            }
+        }
+        rest = &rest[start + DOI_PREFIX.len()..];
+    }
+    None
+}
+
+/// Title of a paper, or else the first line of its text (papers filed by
+/// hand)
+fn paper_title(document: &Document) -> String {
+    document.title.clone().unwrap_or_else(|| {
+        document
+            .text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string()
+    })
+}
+
+/// RIS record of a document, as journal article
+fn ris_record(document: &Document) -> String {
+    let mut record = String::from("TY  - JOUR\n");
+    let title = paper_title(document);
+    if !title.is_empty() {
+        let _ = writeln!(record, "TI  - {title}");
+    }
+    if let Some(doi) = find_doi(&document.text) {
+        let _ = writeln!(record, "DO  - {doi}");
+    }
+    if let Some(date) = document.date {
+        let _ = writeln!(record, "PY  - {}", date.format("%Y"));
+        let _ = writeln!(record, "DA  - {}", date.format("%Y/%m/%d"));
+    }
+    if let Some(correspondent) = &document.correspondent {
+        let _ = writeln!(record, "PB  - {correspondent}");
+    }
+    for tag in &document.tags {
+        let _ = writeln!(record, "KW  - {tag}");
+    }
+    let _ = writeln!(record, "L1  - {}", fs_utils::file_url(&document.path));
+    record.push_str("ER  - \n");
+    record
+}
+
+/// Export the `documents` as RIS file to `target`, return the number of
+/// exported documents
+pub fn export_ris(documents: &[Document], target: &Path) -> Result<usize> {
+    let content: Vec<String> = documents.iter().map(ris_record).collect();
+    fs_utils::write_synced(target, content.join("\n"))
+        .with_context(|| format!("Failed to write {target:?}"))?;
+    debug!("Exported {} document(s) to {target:?}", documents.len());
+    Ok(documents.len())
+}
+
+/// Zotero item of a document, as journal article with the given ID (which
+/// links the PDF to it)
+fn zotero_item(document: &Document, id: &str) -> Value {
+    let mut item = json!({
+        "id": id,
+        "itemType": "journalArticle",
+        "title": paper_title(document),
+        "tags": document
+            .tags
+            .iter()
+            .map(|tag| json!({ "tag": tag }))
+            .collect::<Vec<_>>(),
+        "attachments": [],
+    });
+    if let Some(doi) = find_doi(&document.text) {
+        item["DOI"] = json!(doi);
+    }
+    if let Some(date) = document.date {
+        item["date"] = json!(date.to_string());
+    }
+    if let Some(correspondent) = &document.correspondent {
+        item["publisher"] = json!(correspondent);
+    }
+    item
+}
+
+/// Send a `POST` request to the connector server at `address`, return the
+/// status code and the body of the response
+fn post(address: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<(u16, String)> {
+    let mut stream = TcpStream::connect(address).with_context(|| {
+        format!("Failed to connect to Zotero at {address} (is Zotero running?)")
+    })?;
+    stream.set_read_timeout(Some(ZOTERO_TIMEOUT))?;
+    stream.set_write_timeout(Some(ZOTERO_TIMEOUT))?;
+    let mut request = format!(
+        "POST {path} HTTP/1.0\r\nHost: {address}\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        let _ = write!(request, "{name}: {value}\r\n");
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .context("Failed to read the response of Zotero")?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid response of Zotero: {response:?}"))?;
+    let body = response
+        .split_once("\r\n\r\n")
+        .map_or("", |(_, body)| body)
+        .to_string();
+    debug!("Zotero answered {path} with status {status}");
+    Ok((status, body))
+}
+
+/// Push the `documents` with their PDFs into the Zotero that runs with its
+/// connector server at `address` (see [`ZOTERO_ADDRESS`]), return the number
+/// of pushed documents
+///
+/// The items are saved into the collection that is selected in Zotero.
+pub fn push_to_zotero(documents: &[Document], address: &str) -> Result<usize> {
+    if documents.is_empty() {
+        return Ok(0);
+    }
+    let session = Ulid::from_datetime(SystemTime::now()).to_string();
+    let items: Vec<Value> = documents
+        .iter()
+        .enumerate()
+        .map(|(number, document)| zotero_item(document, &format!("arkivisto-{number}")))
+        .collect();
+    let request = json!({ "sessionID": session, "uri": "", "items": items });
+    let (status, body) = post(
+        address,
+        "/connector/saveItems",
+        &[("Content-Type", "application/json")],
+        request.to_string().as_bytes(),
+    )?;
+    if status != 201 {
+        bail!("Zotero failed to save the items (status {status}): {body}");
+    }
+
+    for (number, document) in documents.iter().enumerate() {
+        let pdf = fs::read(&document.path)
+            .with_context(|| format!("Failed to read {:?}", document.path))?;
+        let metadata = json!({
+            "sessionID": session,
+            "parentItemID": format!("arkivisto-{number}"),
+            "title": "Full Text PDF",
+            "url": fs_utils::file_url(&document.path),
+            "contentType": "application/pdf",
+        });
+        let (status, body) = post(
+            address,
+            &format!("/connector/saveAttachment?sessionID={session}"),
+            &[
+                ("Content-Type", "application/pdf"),
+                ("X-Metadata", &metadata.to_string()),
+            ],
+            &pdf,
+        )?;
+        if status != 201 {
+            bail!(
+                "Zotero failed to save {:?} (status {status}): {body}",
+                document.path
+            );
+        }
+    }
+    debug!("Pushed {} document(s) to Zotero", documents.len());
+    Ok(documents.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        path::PathBuf,
+        thread,
+    };
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that DOIs are found in the text, without trailing punctuation,
+    /// and that other numbers starting with `10.` are ignored.
+    #[test]
+    fn dois() {
+        assert_eq!(
+            find_doi("Available at doi:10.1000/xyz123.\nReceived 2023"),
+            Some("10.1000/xyz123".into())
+        );
+        assert_eq!(
+            find_doi("(https://doi.org/10.1038/s41586-020-2649-2)"),
+            Some("10.1038/s41586-020-2649-2".into())
+        );
+        assert_eq!(
+            find_doi("Price 10.50/month, see 10.1000.10/abc"),
+            Some("10.1000.10/abc".into())
+        );
+        assert_eq!(find_doi("Page 10.12/ and 110.1000/x"), None);
+        assert_eq!(find_doi("10.1000/"), None);
+    }
+
+    /// Ensure that papers are exported as RIS records with their DOI and a
+    /// link to the PDF, and titled with the first line of their text if no
+    /// title is known.
+    #[test]
+    fn ris_records() {
+        let document = Document {
+            path: PathBuf::from("/archive/papers/Smith 2024.pdf"),
+            date: NaiveDate::from_ymd_opt(2024, 2, 1),
+            title: Some("Scanning in the wild".into()),
+            tags: vec!["paper".into()],
+            text: "Scanning in the wild\nDOI: 10.1234/sitw.2024".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ris_record(&document),
+            "TY  - JOUR\n\
+             TI  - Scanning in the wild\n\
+             DO  - 10.1234/sitw.2024\n\
+             PY  - 2024\n\
+             DA  - 2024/02/01\n\
+             KW  - paper\n\
+             L1  - file:///archive/papers/Smith%202024.pdf\n\
+             ER  - \n"
+        );
+
+        let filed = Document {
+            path: PathBuf::from("/archive/offprint.pdf"),
+            text: "\n  On archiving  \nby J. Doe".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ris_record(&filed),
+            "TY  - JOUR\n\
+             TI  - On archiving\n\
+             L1  - file:///archive/offprint.pdf\n\
+             ER  - \n"
+        );
+    }
+
+    /// Ensure that papers are sent to Zotero as journal articles with their
+    /// DOI and tags, followed by their PDFs.
+    #[test]
+    fn zotero() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("paper.pdf");
+        fs::write(&path, "%PDF paper").unwrap();
+        let document = Document {
+            path: path.clone(),
+            date: NaiveDate::from_ymd_opt(2024, 2, 1),
+            title: Some("Scanning in the wild".into()),
+            tags: vec!["paper".into()],
+            text: "DOI: 10.1234/sitw.2024".into(),
+            ..Default::default()
+        };
+
+        // A fake connector server, which records the requests
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push(line.trim_end().to_string());
+                }
+                let length: usize = head
+                    .iter()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.0 201 Created\r\n\r\n")
+                    .unwrap();
+                requests.push((head, body));
+            }
+            requests
+        });
+
+        assert_eq!(push_to_zotero(&[document], &address).unwrap(), 1);
+        let requests = server.join().unwrap();
+        assert!(requests[0].0[0].starts_with("POST /connector/saveItems "));
+        let items: Value = serde_json::from_slice(&requests[0].1).unwrap();
+        let item = &items["items"][0];
+        assert_eq!(item["itemType"], "journalArticle");
+        assert_eq!(item["title"], "Scanning in the wild");
+        assert_eq!(item["DOI"], "10.1234/sitw.2024");
+        assert_eq!(item["date"], "2024-02-01");
+        assert_eq!(item["tags"], json!([{ "tag": "paper" }]));
+
+        assert!(requests[1].0[0].starts_with("POST /connector/saveAttachment?sessionID="));
+        let metadata: Value = requests[1]
+            .0
+            .iter()
+            .find_map(|line| line.strip_prefix("X-Metadata: "))
+            .map(|metadata| serde_json::from_str(metadata).unwrap())
+            .unwrap();
+        assert_eq!(metadata["parentItemID"], item["id"]);
+        assert_eq!(metadata["sessionID"], items["sessionID"]);
+        assert_eq!(requests[1].1, b"%PDF paper");
+    }
+}
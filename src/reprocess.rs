@@ -0,0 +1,179 @@
+//! Processing archived documents again from their scans
+//!
+//! If the scans of archived documents are kept in the scans cache
+//! (`[archive] keep_scans`), a document can be processed again with other
+//! settings (e.g. after enabling deskewing, or with another OCR language). The
+//! result replaces the archived PDF, which is kept as an older version, and
+//! keeps the metadata of the document.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    archive,
+    config::Config,
+    manifest::{self, DocumentState},
+    naming::DocumentInfo,
+    process::{self, ProcessOptions},
+    prompt,
+    search::{self, Document},
+    timings::Timings,
+    ui,
+};
+
+/// Find the directory of an archived document in the scans cache, by its ID
+/// or else by its archived path
+fn find_scans(scans_dir: &Path, document: &Document) -> Result<Option<PathBuf>> {
+    let archived = manifest::find_documents(scans_dir, |manifest| {
+        manifest.state == DocumentState::Archived
+    })?;
+    let by_id = archived.iter().find(|(_, manifest)| {
+        document.id.is_some() && manifest.id.as_ref() == document.id.as_ref()
+    });
+    let by_path = || {
+        archived
+            .iter()
+            .find(|(_, manifest)| manifest.outputs.contains(&document.path))
+    };
+    Ok(by_id
+        .or_else(by_path)
+        .map(|(directory, _)| directory.clone()))
+}
+
+/// Process an archived PDF again from its scans and replace it, return the
+/// path of the older version, or `None` if the user kept the archived PDF
+pub fn reprocess(
+    pdf: &Path,
+    scans_dir: &Path,
+    config: &Config,
+    options: &ProcessOptions,
+    timings: &mut Timings,
+) -> Result<Option<PathBuf>> {
+    let pdf = fs::canonicalize(pdf).with_context(|| format!("Failed to find {pdf:?}"))?;
+    let outdir = fs::canonicalize(&config.outdir)
+        .with_context(|| format!("Failed to find the archive {:?}", config.outdir))?;
+    let pdf = config
+        .outdir
+        .join(pdf.strip_prefix(&outdir).with_context(|| {
+            format!(
+                "{} is not in the archive {}",
+                pdf.display(),
+                config.outdir.display()
+            )
+        })?);
+    let Some(document) = search::documents(&config.outdir)?
+        .into_iter()
+        .find(|document| document.path == pdf)
+    else {
+        bail!("{pdf:?} is not in the search index (run `arkivisto index`)");
+    };
+    let (Some(date), Some(title)) = (document.date, document.title.clone()) else {
+        bail!("{pdf:?} was not archived by arkivisto, so its scans are not known");
+    };
+    let Some(directory) = find_scans(scans_dir, &document)? else {
+        bail!(
+            "The scans of {pdf:?} are not in the scans cache (set `keep_scans` in the \
+             `[archive]` section to keep them when archiving)"
+        );
+    };
+
+    // Process the scans from scratch
+    let mut manifest = manifest::Manifest::load(&directory)?;
+    manifest.state = DocumentState::Scanned;
+    manifest.warnings.clear();
+    manifest.completed_steps.clear();
+    manifest.save(&directory)?;
+    process::process_document(&directory, config, options, timings)?;
+
+    let mut manifest = manifest::Manifest::load(&directory)?;
+    if !manifest.warnings.is_empty() {
+        for warning in &manifest.warnings {
+            println!("{}", ui::warning(warning));
+        }
+        let replace = prompt::Confirm::new("Replace the archived document anyway?")
+            .with_default(false)
+            .prompt()?;
+        if !replace {
+            manifest.state = DocumentState::Archived;
+            manifest.save(&directory)?;
+            return Ok(None);
+        }
+    }
+    let info = DocumentInfo {
+        date,
+        title,
+        correspondent: document.correspondent,
+        tags: document.tags,
+        due: document.due,
+        location: document.location,
+    };
+    archive::replace_archived(&directory, &pdf, &info, config).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::manifest::Manifest;
+
+    /// Ensure that the scans of archived documents are found by ID, or else
+    /// by the archived path, and that documents that are not archived yet
+    /// are ignored.
+    #[test]
+    fn find_archived_scans() {
+        let scans_dir = TempDir::new().unwrap();
+        let manifests = [
+            (
+                "20250101-120000",
+                DocumentState::Archived,
+                Some("01JA"),
+                "/archive/a.pdf",
+            ),
+            (
+                "20250102-120000",
+                DocumentState::Archived,
+                None,
+                "/archive/b.pdf",
+            ),
+            (
+                "20250103-120000",
+                DocumentState::Processed,
+                Some("01JC"),
+                "_final.pdf",
+            ),
+        ];
+        for (name, state, id, output) in manifests {
+            let directory = scans_dir.path().join(name);
+            fs::create_dir(&directory).unwrap();
+            let manifest = Manifest {
+                state,
+                id: id.map(str::to_string),
+                outputs: vec![output.into()],
+                ..Default::default()
+            };
+            manifest.save(&directory).unwrap();
+        }
+
+        let document = |id: Option<&str>, path: &str| Document {
+            id: id.map(str::to_string),
+            path: path.into(),
+            ..Default::default()
+        };
+        let find = |document| find_scans(scans_dir.path(), &document).unwrap();
+        assert_eq!(
+            find(document(Some("01JA"), "/archive/renamed.pdf")),
+            Some(scans_dir.path().join("20250101-120000"))
+        );
+        assert_eq!(
+            find(document(None, "/archive/b.pdf")),
+            Some(scans_dir.path().join("20250102-120000"))
+        );
+        assert_eq!(find(document(Some("01JC"), "/archive/c.pdf")), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::{
+    manifest::{self, DocumentState, Manifest},
+    prompt, queue, ui, verify,
+};
+
+/// Walk through all documents that need review, i.e. that are awaiting
+/// metadata or were flagged with warnings
+pub fn review(scans_dir: &Path) -> Result<()> {
+    let documents = manifest::find_documents(scans_dir, |manifest| {
+        manifest.state == DocumentState::NeedsReview
+    })?;
+    if documents.is_empty() {
+        println!("No documents need review");
+        return Ok(());
+    }
+
+    let total = documents.len();
+    for (i, (document_dir, manifest)) in documents.into_iter().enumerate() {
+        let label = format!("Document {}/{}", i + 1, total);
+        if !review_document(&document_dir, manifest, &label)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Show a document that needs review with its warnings, ask for missing
+/// metadata and whether the document was reviewed
+///
+/// Returns whether the user wants to continue reviewing further documents.
+pub fn review_document(document_dir: &Path, mut manifest: Manifest, label: &str) -> Result<bool> {
+    println!(
+        "{label}: {}",
+        manifest.title.as_deref().unwrap_or("(untitled)")
+    );
+    println!("  {}", document_dir.display());
+    for warning in &manifest.warnings {
+        println!("  {}", ui::warning(warning));
+    }
+
+    let final_pdf = document_dir.join(queue::FINAL_PDF);
+    match verify::open_file(&final_pdf) {
+        Ok(()) => manifest.shown = true,
+        Err(e) => warn!("Failed to open {:?}: {:#}", final_pdf, e),
+    }
+
+    // Ask for missing metadata
+    if manifest.title.is_none() || manifest.needs_naming {
+        let Some(title) = prompt::Text::new("Title?")
+            .with_help_message("Leave empty to keep it untitled, press Esc to skip this document")
+            .prompt_skippable()?
+        else {
+            return Ok(true);
+        };
+        let title = title.trim();
+        if !title.is_empty() {
+            manifest.title = Some(title.to_string());
+            manifest.needs_naming = false;
+        }
+    }
+
+    let option_reviewed = "Mark as reviewed";
+    let option_skip = "Skip";
+    let option_stop = "Stop reviewing";
+    let choice = prompt::Select::new(
+        "What to do with this document?",
+        vec![option_reviewed, option_skip, option_stop],
+    )
+    .prompt()?;
+    if choice == option_reviewed {
+        manifest.warnings.clear();
+        manifest.mark_processed();
+    }
+    manifest.save(document_dir)?;
+    Ok(choice != option_stop)
+}
@@ -0,0 +1,100 @@
+//! Detection of sandboxes that Arkivisto runs in (e.g. a Flatpak), in which
+//! Docker is not available and the OCR has to use local tools instead
+
+use std::{env, fmt::Display, path::Path};
+
+/// A sandbox that Arkivisto runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    Flatpak,
+    Snap,
+    /// A Nix build sandbox (e.g. running the tests of the Nix package), but
+    /// not a development shell (`nix-shell` or `nix develop`)
+    NixBuild,
+}
+
+impl Display for Sandbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Sandbox::Flatpak => "Flatpak",
+            Sandbox::Snap => "Snap",
+            Sandbox::NixBuild => "Nix build",
+        })
+    }
+}
+
+/// Detect the sandbox that Arkivisto runs in
+pub fn detect() -> Option<Sandbox> {
+    detect_with(Path::new("/"), |name| env::var(name).ok())
+}
+
+/// Home directory of Nix builds, which have no home directory
+const NIX_BUILD_HOME: &str = "/homeless-shelter";
+
+/// Detect the sandbox from the files in `root` and the environment variables
+/// resolved with `lookup`
+fn detect_with(root: &Path, lookup: impl Fn(&str) -> Option<String>) -> Option<Sandbox> {
+    // Development shells set `NIX_BUILD_TOP` as well, but keep the home
+    // directory of the user
+    let is_nix_build = lookup("NIX_BUILD_TOP").is_some()
+        && lookup("NIX_ENFORCE_PURITY").is_some()
+        && lookup("HOME").is_none_or(|home| home == NIX_BUILD_HOME);
+    if root.join(".flatpak-info").exists() || lookup("FLATPAK_ID").is_some() {
+        Some(Sandbox::Flatpak)
+    } else if lookup("SNAP").is_some() {
+        Some(Sandbox::Snap)
+    } else if is_nix_build {
+        Some(Sandbox::NixBuild)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that sandboxes are detected from their marker file and
+    /// environment variables.
+    #[test]
+    fn sandboxes() {
+        let root = TempDir::new().unwrap();
+        let no_variables = |_: &str| None;
+        assert_eq!(detect_with(root.path(), no_variables), None);
+
+        let variable = |set: &'static str| move |name: &str| (name == set).then(String::new);
+        assert_eq!(
+            detect_with(root.path(), variable("FLATPAK_ID")),
+            Some(Sandbox::Flatpak)
+        );
+        assert_eq!(
+            detect_with(root.path(), variable("SNAP")),
+            Some(Sandbox::Snap)
+        );
+
+        // Nix builds, but not development shells
+        let nix = |home: Option<&'static str>| {
+            move |name: &str| match name {
+                "NIX_BUILD_TOP" => Some("/build".to_string()),
+                "NIX_ENFORCE_PURITY" => Some("1".to_string()),
+                "HOME" => home.map(String::from),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            detect_with(root.path(), nix(Some(NIX_BUILD_HOME))),
+            Some(Sandbox::NixBuild)
+        );
+        assert_eq!(detect_with(root.path(), nix(None)), Some(Sandbox::NixBuild));
+        assert_eq!(detect_with(root.path(), nix(Some("/home/user"))), None);
+        assert_eq!(detect_with(root.path(), variable("NIX_BUILD_TOP")), None);
+
+        std::fs::write(root.path().join(".flatpak-info"), "[Application]\n").unwrap();
+        assert_eq!(
+            detect_with(root.path(), no_variables),
+            Some(Sandbox::Flatpak)
+        );
+    }
+}
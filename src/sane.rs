@@ -0,0 +1,174 @@
+use std::{
+    fmt::Display,
+    process::Command,
+    sync::{Once, OnceLock},
+};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::tools;
+
+/// A scanner device reported by SANE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Device name (e.g. "airscan:e1:HP ScanJet Flow N7000 snw1")
+    pub name: String,
+    /// Human readable description (e.g. "eSCL HP ScanJet Flow N7000 ip=…")
+    pub description: String,
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.description, self.name)
+    }
+}
+
+/// Warn if the installed `scanimage` is older than the oldest supported
+/// version (checked once per run)
+pub fn check_version() {
+    static CHECKED: Once = Once::new();
+    CHECKED.call_once(|| {
+        if let Some(version) = tools::version("scanimage", "--version") {
+            tools::check_version("scanimage", version);
+        }
+    });
+}
+
+/// Whether a device is addressed by its URL (e.g.
+/// "escl:https://192.168.1.5:443") instead of being discovered on the network
+///
+/// `scanimage -L` doesn't necessarily list such manually configured devices,
+/// even if they are reachable.
+pub fn is_manually_configured(device_name: &str) -> bool {
+    device_name.contains("://")
+}
+
+/// List the scanner devices that are currently reachable (`scanimage -L`)
+///
+/// Discovering the devices takes several seconds, so they are only listed
+/// once per run.
+pub fn list_devices() -> Result<Vec<Device>> {
+    static DEVICES: OnceLock<Vec<Device>> = OnceLock::new();
+    if let Some(devices) = DEVICES.get() {
+        return Ok(devices.clone());
+    }
+    let devices = discover_devices()?;
+    Ok(DEVICES.get_or_init(|| devices).clone())
+}
+
+/// Run `scanimage -L` to discover the reachable devices
+fn discover_devices() -> Result<Vec<Device>> {
+    let output = Command::new("scanimage")
+        .arg("-L")
+        .output()
+        .context("Failed to run `scanimage -L`")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`scanimage -L` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the output of `scanimage -L`
+///
+/// Every device is listed on its own line, e.g.
+/// "device `escl:https://192.168.1.5:443' is a HP ScanJet flatbed scanner".
+fn parse_devices(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("device `")?;
+            let (name, description) = rest.split_once("' is a ")?;
+            Some(Device {
+                name: name.to_string(),
+                description: description.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// List the scan sources supported by a device (e.g. "Flatbed" or "ADF")
+pub fn list_sources(device_name: &str) -> Result<Vec<String>> {
+    let output = Command::new("scanimage")
+        .arg(format!("--device-name={device_name}"))
+        .arg("--all-options")
+        .output()
+        .context("Failed to run `scanimage --all-options`")?;
+    Ok(parse_sources(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the sources from the device options in the output of
+/// `scanimage --all-options`, e.g. "--source Flatbed|ADF|ADF Duplex [Flatbed]"
+fn parse_sources(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("--source "))
+        .map(|options| {
+            let options = options.split(" [").next().unwrap_or(options);
+            options
+                .split('|')
+                .map(|source| source.trim().to_string())
+                .filter(|source| !source.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that devices are parsed from the `scanimage -L` output.
+    #[test]
+    fn devices() {
+        let output = "\
+device `airscan:e1:HP ScanJet Flow N7000 snw1' is a eSCL HP ScanJet Flow N7000 snw1 ip=192.168.1.5
+device `v4l:/dev/video0' is a Noname Integrated Camera virtual device
+
+No scanners were identified.";
+        assert_eq!(
+            parse_devices(output),
+            vec![
+                Device {
+                    name: "airscan:e1:HP ScanJet Flow N7000 snw1".into(),
+                    description: "eSCL HP ScanJet Flow N7000 snw1 ip=192.168.1.5".into(),
+                },
+                Device {
+                    name: "v4l:/dev/video0".into(),
+                    description: "Noname Integrated Camera virtual device".into(),
+                },
+            ]
+        );
+        assert!(parse_devices("").is_empty());
+    }
+
+    /// Ensure that devices addressed by their URL are recognized as manually
+    /// configured.
+    #[test]
+    fn manually_configured() {
+        assert!(is_manually_configured("escl:https://192.168.1.5:443"));
+        assert!(is_manually_configured(
+            "airscan:escl:Brother:http://10.0.0.2/eSCL"
+        ));
+        assert!(!is_manually_configured(
+            "airscan:e1:HP ScanJet Flow N7000 snw1"
+        ));
+        assert!(!is_manually_configured("fujitsu:fi-7160:12345"));
+    }
+
+    /// Ensure that the supported sources are parsed from the device options.
+    #[test]
+    fn sources() {
+        let output = "\
+Options specific to device `airscan:e1:HP ScanJet':
+  Standard:
+    --resolution 75|100|150|200|300|600dpi [300]
+    --mode Color|Gray [Color]
+    --source Flatbed|ADF|ADF Duplex [Flatbed]
+        Selects the scan source";
+        assert_eq!(parse_sources(output), vec!["Flatbed", "ADF", "ADF Duplex"]);
+        assert!(parse_sources("--mode Color|Gray [Color]").is_empty());
+    }
+}
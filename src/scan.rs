@@ -3,23 +3,86 @@ use std::{
     fs,
     path::{Path, PathBuf},
     process::Command,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, anyhow, ensure};
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use indicatif::MultiProgress;
 use tracing::{debug, trace, warn};
+use ulid::Ulid;
 
 use crate::{
-    config::{Scanner, ScannerSources},
-    fs_utils,
+    compression,
+    config::{
+        Geometry, PaperSize, ScanConfig, Scanner, ScannerBackend, ScannerSources, TiffCompression,
+        Timezone, VirtualPages, dir_slug, dir_timestamp,
+    },
+    estimate,
+    events::{self, Event},
+    fs_utils, history,
+    lock::ScannerLock,
+    manifest::{Manifest, ScanSource, ScanStats},
+    ocr, process, prompt, quality, queue, sane,
+    scheduler::{self, JobKind},
+    tiff_utils,
+    timings::Timings,
+    ui, verify, virtual_scanner,
 };
 
+/// Size of the scanned area in millimeters
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct ScanArea {
+    width: f32,
+    height: f32,
+}
+
+impl From<PaperSize> for ScanArea {
+    fn from(paper: PaperSize) -> Self {
+        let (width, height) = paper.dimensions_mm();
+        Self { width, height }
+    }
+}
+
+/// Size of an identity document
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum IdDocumentSize {
+    /// ID-1 format (ID cards, driving licences)
+    Card,
+    /// A6 format
+    A6,
+    /// A5 format
+    A5,
+}
+
+impl Display for IdDocumentSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdDocumentSize::Card => write!(f, "ID card (85.6 × 54 mm)"),
+            IdDocumentSize::A6 => write!(f, "A6 (105 × 148 mm)"),
+            IdDocumentSize::A5 => write!(f, "A5 (148 × 210 mm)"),
+        }
+    }
+}
+
+impl IdDocumentSize {
+    fn area(&self) -> ScanArea {
+        let (width, height) = match self {
+            IdDocumentSize::Card => (85.6, 54.0),
+            IdDocumentSize::A6 => (105.0, 148.0),
+            IdDocumentSize::A5 => (148.0, 210.0),
+        };
+        ScanArea { width, height }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum ScanMode {
     AdfSingleSided,
     AdfDuplex,
     AdfManualDuplex,
     Flatbed { page_count: usize },
+    IdDocument { size: IdDocumentSize },
 }
 
 impl Display for ScanMode {
@@ -29,11 +92,34 @@ impl Display for ScanMode {
             ScanMode::AdfDuplex => write!(f, "ADF duplex"),
             ScanMode::AdfManualDuplex => write!(f, "ADF manual duplex"),
             ScanMode::Flatbed { .. } => write!(f, "Flatbed"),
+            ScanMode::IdDocument { .. } => {
+                write!(f, "Identity document (front and back on one page)")
+            }
         }
     }
 }
 
 impl ScanMode {
+    /// Short name of the mode for directory names
+    fn slug(&self) -> &'static str {
+        match self {
+            ScanMode::AdfSingleSided => "adf",
+            ScanMode::AdfDuplex => "adf-duplex",
+            ScanMode::AdfManualDuplex => "adf-manual-duplex",
+            ScanMode::Flatbed { .. } => "flatbed",
+            ScanMode::IdDocument { .. } => "id-document",
+        }
+    }
+
+    fn source(&self) -> ScanSource {
+        match self {
+            ScanMode::AdfSingleSided | ScanMode::AdfDuplex | ScanMode::AdfManualDuplex => {
+                ScanSource::Adf
+            }
+            ScanMode::Flatbed { .. } | ScanMode::IdDocument { .. } => ScanSource::Flatbed,
+        }
+    }
+
     fn options(available_sources: &ScannerSources) -> Vec<Self> {
         let mut options = Vec::new();
         if available_sources.adf_single.is_some() {
@@ -47,6 +133,9 @@ impl ScanMode {
         }
         if available_sources.flatbed.is_some() {
             options.push(ScanMode::Flatbed { page_count: 0 });
+            options.push(ScanMode::IdDocument {
+                size: IdDocumentSize::Card,
+            });
         }
         options
     }
@@ -54,6 +143,8 @@ impl ScanMode {
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
 enum Resolution {
+    /// 75 DPI, for a quick preview of the framing
+    Preview,
     /// 300 DPI
     #[default]
     Normal,
@@ -64,6 +155,7 @@ enum Resolution {
 impl Resolution {
     fn as_dpi(&self) -> u32 {
         match self {
+            Resolution::Preview => 75,
             Resolution::Normal => 300,
             Resolution::High => 600,
         }
@@ -73,16 +165,17 @@ impl Resolution {
 /// Scan one or more pages using `scanimage`
 ///
 /// Scanned files will be stored as TIF files in the scans cache directory. The
-/// filename contains a number starting at 1000.
-fn run_scanimage(
-    scans_dir: &Path,
-    context: &ScanContext,
-    mode: &ScanMode,
-    resolution: &Resolution,
-) -> Result<()> {
+/// filename contains the page number, zero-padded to four digits and starting
+/// at 1 (e.g. `0001.tif`). Returns the time spent in `scanimage`, without the
+/// time the user needs between pages.
+fn run_scanimage(scans_dir: &Path, context: &ScanContext, job: &ScanJob) -> Result<Duration> {
     debug!("Scanning to {}", scans_dir.display());
-
-    // TODO: Manual duplex
+    let ScanJob {
+        mode,
+        resolution,
+        preview,
+        ..
+    } = job;
 
     // Macro to reduce repetition in source checking
     macro_rules! get_source {
@@ -98,24 +191,55 @@ fn run_scanimage(
         ScanMode::AdfSingleSided => get_source!(adf_single, "ADF single-sided"),
         ScanMode::AdfDuplex => get_source!(adf_duplex, "ADF duplex"),
         ScanMode::AdfManualDuplex => get_source!(adf_single, "ADF manual duplex"),
-        ScanMode::Flatbed { .. } => get_source!(flatbed, "Flatbed"),
+        ScanMode::Flatbed { .. } | ScanMode::IdDocument { .. } => {
+            get_source!(flatbed, "Flatbed")
+        }
     }?;
 
     // Call scanimage
-    match mode {
-        ScanMode::AdfSingleSided | ScanMode::AdfDuplex | ScanMode::AdfManualDuplex => {
+    let mut elapsed = Duration::ZERO;
+    match *mode {
+        ScanMode::AdfManualDuplex => {
+            elapsed = scan_manual_duplex(scans_dir, context, source, resolution)?;
+        }
+        ScanMode::AdfSingleSided | ScanMode::AdfDuplex => {
             // Scan all available pages from ADF
-            _scanimage(scans_dir, context, source, 0, None, resolution)?;
+            timed(&mut elapsed, || {
+                _scanimage(
+                    scans_dir,
+                    context,
+                    source,
+                    0,
+                    None,
+                    resolution,
+                    context.scan_area(),
+                )
+            })?;
         }
         ScanMode::Flatbed { page_count } => {
             assert!(
-                *page_count > 0,
+                page_count > 0,
                 "Page count is 0, this indicates an internal logic bug"
             );
             // Scan n pages from flatbed
-            for i in 0..*page_count {
+            for i in 0..page_count {
+                if *preview {
+                    preview_page(context, source, &format!("page {}/{}", i + 1, page_count))?;
+                    timed(&mut elapsed, || {
+                        _scanimage(
+                            scans_dir,
+                            context,
+                            source,
+                            i,
+                            Some(1),
+                            resolution,
+                            context.scan_area(),
+                        )
+                    })?;
+                    continue;
+                }
                 let scan_next_page =
-                    inquire::Confirm::new(&format!("Scan page {}/{}?", i + 1, page_count))
+                    prompt::Confirm::new(&format!("Scan page {}/{}?", i + 1, page_count))
                         .with_default(true)
                         .with_help_message(
                             "Press enter to scan, or type 'n' to abort the scan process.",
@@ -124,14 +248,204 @@ fn run_scanimage(
                 if !scan_next_page {
                     return Err(anyhow!("Scan aborted by user"));
                 }
-                _scanimage(scans_dir, context, source, i, Some(1), resolution)?;
+                timed(&mut elapsed, || {
+                    _scanimage(
+                        scans_dir,
+                        context,
+                        source,
+                        i,
+                        Some(1),
+                        resolution,
+                        context.scan_area(),
+                    )
+                })?;
+            }
+        }
+        ScanMode::IdDocument { size } => {
+            // Scan front and back, only the area covered by the document
+            for (i, side) in ["front", "back"].iter().enumerate() {
+                let scan_side = prompt::Confirm::new(&format!(
+                    "Place the {side} side in the top left corner of the flatbed. Scan?"
+                ))
+                .with_default(true)
+                .with_help_message("Press enter to scan, or type 'n' to abort the scan process.")
+                .prompt()?;
+                if !scan_side {
+                    return Err(anyhow!("Scan aborted by user"));
+                }
+                timed(&mut elapsed, || {
+                    _scanimage(
+                        scans_dir,
+                        context,
+                        source,
+                        i,
+                        Some(1),
+                        resolution,
+                        Some(size.area()),
+                    )
+                })?;
             }
         }
     }
 
+    Ok(elapsed)
+}
+
+/// Run a scan and add its duration to `elapsed`
+fn timed(elapsed: &mut Duration, scan: impl FnOnce() -> Result<()>) -> Result<()> {
+    let start = Instant::now();
+    let result = scan();
+    *elapsed += start.elapsed();
+    result
+}
+
+/// Scan the front sides of all sheets in the ADF, then the back sides after
+/// the user has flipped the stack, and interleave them
+///
+/// The back sides are scanned into a separate staging directory, which is
+/// removed afterwards. Returns the time spent in `scanimage`.
+fn scan_manual_duplex(
+    current_dir: &Path,
+    context: &ScanContext,
+    source: &str,
+    resolution: &Resolution,
+) -> Result<Duration> {
+    let area = context.scan_area();
+    let mut elapsed = Duration::ZERO;
+    timed(&mut elapsed, || {
+        _scanimage(current_dir, context, source, 0, None, resolution, area)
+    })?;
+    let fronts = process::collect_page_tifs(current_dir)?.len();
+    ensure!(fronts > 0, "No front sides were scanned");
+
+    let scan_backs = prompt::Confirm::new(&format!(
+        "Scanned {fronts} front side(s). Flip the stack and place it in the ADF again. Scan the back sides?"
+    ))
+    .with_default(true)
+    .with_help_message(
+        "Turn the stack over without changing the order of the sheets, so that the \
+         last sheet is scanned first. Press enter to scan, or type 'n' to abort.",
+    )
+    .prompt()?;
+    if !scan_backs {
+        return Err(anyhow!("Scan aborted by user"));
+    }
+
+    let backs_dir = create_staging_dir(&scans_dir()?, context.scanner, "-backs")?;
+    let result = timed(&mut elapsed, || {
+        _scanimage(&backs_dir, context, source, 0, None, resolution, area)
+    })
+    .and_then(|()| interleave_duplex(current_dir, &backs_dir));
+    if let Err(e) = fs::remove_dir_all(&backs_dir) {
+        debug!("Failed to remove {backs_dir:?}: {e}");
+    }
+    result.map(|()| elapsed)
+}
+
+/// Move the back sides in `backs_dir` in between the front sides in
+/// `fronts_dir`
+///
+/// The back sides of a flipped stack are scanned in reverse order, so the
+/// first back side belongs to the last front side. Both directories must
+/// contain the same number of pages.
+fn interleave_duplex(fronts_dir: &Path, backs_dir: &Path) -> Result<()> {
+    let fronts = process::collect_page_tifs(fronts_dir)?;
+    let backs = process::collect_page_tifs(backs_dir)?;
+    ensure!(
+        fronts.len() == backs.len(),
+        "Scanned {} front side(s), but {} back side(s)",
+        fronts.len(),
+        backs.len()
+    );
+    let page_path = |number: usize| fronts_dir.join(format!("{number:04}.tif"));
+
+    // Spread the front sides to the odd page numbers, starting with the last
+    // page so that no page is overwritten
+    for (index, front) in fronts.iter().enumerate().rev() {
+        let from = fronts_dir.join(front);
+        let to = page_path(2 * index + 1);
+        if from != to {
+            fs::rename(&from, &to)
+                .with_context(|| format!("Failed to rename {from:?} to {to:?}"))?;
+        }
+    }
+    for (index, back) in backs.iter().enumerate() {
+        let to = page_path(2 * (backs.len() - index));
+        fs_utils::move_path(&backs_dir.join(back), &to)?;
+    }
     Ok(())
 }
 
+/// Scan previews of a page at low resolution until the user confirms the
+/// framing (e.g. of a bound original on the flatbed)
+///
+/// The previews are scanned into a separate staging directory, which is
+/// removed afterwards, so that only the final scan is processed.
+fn preview_page(context: &ScanContext, source: &str, description: &str) -> Result<()> {
+    let preview_dir = create_staging_dir(&scans_dir()?, context.scanner, "-preview")?;
+    let result = (|| loop {
+        let scan_preview = prompt::Confirm::new(&format!("Scan a preview of {description}?"))
+            .with_default(true)
+            .with_help_message("Press enter to scan, or type 'n' to abort the scan process.")
+            .prompt()?;
+        if !scan_preview {
+            return Err(anyhow!("Scan aborted by user"));
+        }
+        _scanimage(
+            &preview_dir,
+            context,
+            source,
+            0,
+            Some(1),
+            &Resolution::Preview,
+            context.scan_area(),
+        )?;
+        let preview = preview_dir.join("0001.tif");
+        if let Err(e) = verify::open_file(&preview) {
+            warn!("Failed to open {:?}: {:#}", preview, e);
+            println!("Please open {} manually", preview.display());
+        }
+        let framing_ok = prompt::Confirm::new("Is the framing correct?")
+            .with_default(true)
+            .with_help_message(
+                "Answer yes to scan the page at full resolution, or no to reposition \
+                 the original and scan another preview",
+            )
+            .prompt()?;
+        if framing_ok {
+            return Ok(());
+        }
+    })();
+    if let Err(e) = fs::remove_dir_all(&preview_dir) {
+        debug!("Failed to remove {preview_dir:?}: {e}");
+    }
+    result
+}
+
+/// Scan area arguments for `scanimage`, corrected by the geometry of the
+/// scanner
+///
+/// The offsets are only passed if set, since not all backends support them.
+/// Without an area, the whole area of the scanner is scanned.
+fn geometry_args(geometry: &Geometry, area: Option<&ScanArea>) -> Vec<String> {
+    let mut args = Vec::new();
+    if geometry.left != 0.0 {
+        args.extend(["-l".into(), geometry.left.to_string()]);
+    }
+    if geometry.top != 0.0 {
+        args.extend(["-t".into(), geometry.top.to_string()]);
+    }
+    if let Some(area) = area {
+        args.extend([
+            "-x".into(),
+            (area.width + geometry.extra_width).to_string(),
+            "-y".into(),
+            (area.height + geometry.extra_height).to_string(),
+        ]);
+    }
+    args
+}
+
 /// Low-level function to call the `scanimage` binary.
 ///
 /// Parameters:
@@ -143,13 +457,16 @@ fn run_scanimage(
 ///     The scanner source.
 ///   start:
 ///     The batch offset. If this is set to 0, the filename of the first
-///     scanned page will be `1000.tif`. If it's set to 4, the filename
-///     of the first scanned page will be `1004.tif`.
+///     scanned page will be `0001.tif`. If it's set to 4, the filename
+///     of the first scanned page will be `0005.tif`.
 ///   count:
 ///     The number of pages to scan. If this is `None`, no count will be passed
 ///     to `scanimage` (i.e. all available pages will be scanned).
 ///   resolution:
 ///     The resolution of the scanned pages.
+///   area:
+///     The scanned area, starting at the top left corner. If this is `None`,
+///     the whole area of the scanner is scanned.
 fn _scanimage(
     scans_dir: &Path,
     context: &ScanContext,
@@ -157,23 +474,15 @@ fn _scanimage(
     start: usize,
     count: Option<usize>,
     resolution: &Resolution,
+    area: Option<ScanArea>,
 ) -> Result<()> {
-    let mut args = Vec::new();
-
     // Generic scanimage parameters
-    args.push("--format=tiff".into());
-    args.push(format!("--batch={}", scans_dir.join("%d.tif").display()));
-    args.push(format!("--batch-start={}", 1000 + start));
-    if let Some(batch_count) = count {
-        args.push(format!("--batch-count={}", batch_count));
-    }
+    let mut args = batch_args(scans_dir, start, count);
 
     // Common scanner-specific parameters for which we assume support by all scanners
+    args.push(format!("--device-name={}", context.scanner.device_name));
     args.push(format!("--resolution={}", resolution.as_dpi()));
-    args.push("-x".into());
-    args.push("210".into());
-    args.push("-y".into());
-    args.push("297".into());
+    args.extend(geometry_args(&context.scanner.geometry, area.as_ref()));
 
     // Scanner-specific arguments
     args.push(format!("--source={}", source));
@@ -183,34 +492,58 @@ fn _scanimage(
 
     debug!("Calling `scanimage` with arguments: {:?}", args);
 
+    // When scanning with multiple scanners, prefix messages with the scanner ID
+    let label = |message: String| match context.progress {
+        Some(_) => format!("[{}] {}", context.scanner.id, message),
+        None => message,
+    };
+
     // Show spinner
-    let spinner_message = if context.fake_scan {
-        "Faking `scanimage` to scan documents…"
+    let virtual_pages = virtual_pages(context);
+    let spinner_message = if virtual_pages.is_some() {
+        "Generating virtual pages…"
     } else {
         "Calling `scanimage` to scan documents…"
     };
-    let spinner = indicatif::ProgressBar::new_spinner().with_message(spinner_message);
+    let mut spinner = ui::spinner(label(spinner_message.into()));
+    if let Some(progress) = &context.progress {
+        spinner = progress.add(spinner);
+    }
     spinner.enable_steady_tick(Duration::from_millis(100));
 
-    // Run or fake command
-    if context.fake_scan {
-        fake_scanimage(scans_dir).context("Failed to fake `scanimage` command")?;
-        spinner.finish_with_message(format!(
+    // Run command or generate pages
+    if let Some(virtual_pages) = virtual_pages {
+        let duplex = context.scanner.sources.adf_duplex.as_deref() == Some(source);
+        virtual_scanner::scan(
+            scans_dir,
+            &virtual_pages,
+            start,
+            count,
+            duplex,
+            resolution.as_dpi(),
+            area.map_or_else(
+                || context.paper.dimensions_mm(),
+                |area| (area.width, area.height),
+            ),
+        )
+        .context("Failed to generate virtual pages")?;
+        spinner.finish_with_message(ui::success(label(format!(
             "Simulated document scan in {:.1}s",
             spinner.elapsed().as_secs_f32()
-        ));
+        ))));
     } else {
+        sane::check_version();
         let output = Command::new("scanimage").args(&args).output()?;
         if output.status.success() {
-            spinner.finish_with_message(format!(
+            spinner.finish_with_message(ui::success(label(format!(
                 "Scanned documents in {:.1}s",
                 spinner.elapsed().as_secs_f32()
-            ));
+            ))));
         } else {
-            spinner.abandon_with_message(format!(
+            spinner.abandon_with_message(ui::error(label(format!(
                 "Failed to scan documents after {:.1}s",
                 spinner.elapsed().as_secs_f32()
-            ));
+            ))));
             warn!(
                 "Scanimage failed with status {}. Stderr: {}",
                 output.status.code().unwrap_or(-1),
@@ -226,41 +559,182 @@ fn _scanimage(
     Ok(())
 }
 
-/// Fake scanimage function for testing purposes
+/// Generic `scanimage` parameters for scanning a batch of pages into
+/// `scans_dir` (see [`_scanimage`] for the meaning of the parameters).
 ///
-/// Note that this will only work, if a `testdata` folder exists in the current
-/// working directory.
-fn fake_scanimage(scans_dir: &Path) -> Result<()> {
-    debug!("Faking scan to {}", scans_dir.display());
-
-    let testdata_dir = Path::new("testdata");
-    ensure!(
-        testdata_dir.exists(),
-        "`testdata` folder not found in current working directory"
-    );
-    ensure!(testdata_dir.is_dir(), "`testdata` is not a directory");
+/// Page numbers are zero-padded to four digits. Batches with more than 9999
+/// pages result in longer filenames, so pages must always be sorted
+/// numerically, not lexically.
+fn batch_args(scans_dir: &Path, start: usize, count: Option<usize>) -> Vec<String> {
+    let mut args = vec![
+        "--format=tiff".into(),
+        format!("--batch={}", scans_dir.join("%04d.tif").display()),
+        format!("--batch-start={}", start + 1),
+    ];
+    if let Some(batch_count) = count {
+        args.push(format!("--batch-count={}", batch_count));
+    }
+    args
+}
 
-    std::thread::sleep(Duration::from_secs(1));
+/// Settings of the generated pages, if the scan is simulated
+///
+/// Pages are generated if the scanner uses the virtual backend, or if scanning
+/// is faked (`--fake-scan`).
+fn virtual_pages(context: &ScanContext) -> Option<VirtualPages> {
+    if context.scanner.backend == ScannerBackend::Virtual {
+        Some(context.scanner.virtual_pages.clone())
+    } else if context.fake_scan {
+        Some(VirtualPages::default())
+    } else {
+        None
+    }
+}
 
-    fs_utils::copy_dir_file_contents(testdata_dir, scans_dir)?;
+/// A device that can be selected for scanning
+enum DeviceChoice {
+    Configured(Scanner),
+    Unconfigured(sane::Device),
+}
 
-    Ok(())
+impl Display for DeviceChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceChoice::Configured(scanner) => write!(f, "{scanner}"),
+            DeviceChoice::Unconfigured(device) => write!(f, "{device} [not configured]"),
+        }
+    }
 }
 
 /// Select a device from the list of available scanners
-pub fn select_scanner(scanners: &[Scanner]) -> Result<Scanner> {
-    // If there is only one device, return it
-    if scanners.len() == 1 {
-        trace!("Only one scanner available, using it");
-        return Ok(scanners[0].clone());
-    }
+///
+/// If a configured scanner is unreachable, devices reported by SANE that are
+/// not configured are offered as well.
+pub fn select_scanner(scanners: &[Scanner], fake_scan: bool) -> Result<Scanner> {
+    let unconfigured = if fake_scan {
+        Vec::new()
+    } else {
+        unconfigured_devices(scanners)
+    };
 
-    // Otherwise, rompt the user to select a scan device
+    // Prompt the user to select a scan device, unless there is only one
     trace!(
-        "{} scanners available, asking user for selection",
-        scanners.len()
+        "{} scanners and {} unconfigured devices available, asking user for selection",
+        scanners.len(),
+        unconfigured.len()
     );
-    Ok(inquire::Select::new("Which device do you want to use?", scanners.to_vec()).prompt()?)
+    let choices = scanners
+        .iter()
+        .cloned()
+        .map(DeviceChoice::Configured)
+        .chain(unconfigured.into_iter().map(DeviceChoice::Unconfigured))
+        .collect();
+    match prompt::select("Which device do you want to use?", choices, 0)? {
+        DeviceChoice::Configured(scanner) => Ok(scanner),
+        DeviceChoice::Unconfigured(device) => configure_device(device),
+    }
+}
+
+/// Devices reported by SANE that are not configured
+///
+/// The list is only determined if no scanners are configured, or if at least
+/// one configured SANE scanner is unreachable. Scanners that are addressed by
+/// their URL are not checked, since SANE doesn't necessarily list them.
+fn unconfigured_devices(scanners: &[Scanner]) -> Vec<sane::Device> {
+    let sane_scanners: Vec<&Scanner> = scanners
+        .iter()
+        .filter(|scanner| {
+            scanner.backend == ScannerBackend::Sane
+                && !sane::is_manually_configured(&scanner.device_name)
+        })
+        .collect();
+    if !scanners.is_empty() && sane_scanners.is_empty() {
+        return Vec::new();
+    }
+    let devices = match sane::list_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            debug!("Failed to list SANE devices: {e:#}");
+            return Vec::new();
+        }
+    };
+    let is_configured =
+        |device: &sane::Device| scanners.iter().any(|s| s.device_name == device.name);
+    let unreachable: Vec<&Scanner> = sane_scanners
+        .into_iter()
+        .filter(|scanner| !devices.iter().any(|d| d.name == scanner.device_name))
+        .collect();
+    if !scanners.is_empty() && unreachable.is_empty() {
+        return Vec::new();
+    }
+    for scanner in unreachable {
+        warn!("Scanner {} is not reachable", scanner.id);
+    }
+    devices
+        .into_iter()
+        .filter(|device| !is_configured(device))
+        .collect()
+}
+
+/// Ask the user which of the sources of a device serves the described
+/// purpose, preselecting the first source that matches `guess` (which receives
+/// the lowercase source name)
+fn prompt_source(
+    sources: &[String],
+    description: &str,
+    guess: impl Fn(&str) -> bool,
+) -> Result<Option<String>> {
+    let message = format!("Which source is the {description}?");
+    if sources.is_empty() {
+        let source = prompt::Text::new(&message)
+            .with_help_message("Leave empty if the device doesn't have this source")
+            .prompt()?;
+        return Ok(Some(source.trim().to_string()).filter(|source| !source.is_empty()));
+    }
+
+    const NOT_AVAILABLE: &str = "Not available";
+    let options: Vec<&str> = sources
+        .iter()
+        .map(String::as_str)
+        .chain([NOT_AVAILABLE])
+        .collect();
+    let guess = sources
+        .iter()
+        .position(|source| guess(&source.to_lowercase()))
+        .unwrap_or(sources.len());
+    let source = prompt::Select::new(&message, options)
+        .with_starting_cursor(guess)
+        .prompt()?;
+    Ok((source != NOT_AVAILABLE).then(|| source.to_string()))
+}
+
+/// Create a scanner with generic defaults for a device that is not
+/// configured, prompting the user for its scan sources
+pub fn configure_device(device: sane::Device) -> Result<Scanner> {
+    let sources = sane::list_sources(&device.name).unwrap_or_else(|e| {
+        warn!("Failed to determine the sources of {}: {e:#}", device.name);
+        Vec::new()
+    });
+    let sources = ScannerSources {
+        adf_single: prompt_source(&sources, "single-sided document feeder (ADF)", |s| {
+            (s.contains("adf") || s.contains("feeder")) && !s.contains("duplex")
+        })?,
+        adf_duplex: prompt_source(&sources, "duplex document feeder (ADF)", |s| {
+            s.contains("duplex")
+        })?,
+        flatbed: prompt_source(&sources, "flatbed", |s| s.contains("flatbed"))?,
+    };
+    Ok(Scanner {
+        id: device.name.clone(),
+        device_name: device.name,
+        additional_args: Vec::new(),
+        backend: ScannerBackend::Sane,
+        virtual_pages: VirtualPages::default(),
+        sources,
+        max_paper: None,
+        geometry: Geometry::default(),
+        lock_file: None,
+    })
 }
 
 pub struct ScanContext<'a> {
@@ -269,46 +743,184 @@ pub struct ScanContext<'a> {
 
     /// Whether to fake scanning
     pub fake_scan: bool,
+
+    /// Shared progress view, if multiple scanners are used concurrently
+    pub progress: Option<MultiProgress>,
+
+    /// Offer to rescan pages with a quality score below this value
+    pub min_page_quality: Option<f32>,
+
+    /// Paper size of full-page scans
+    pub paper: PaperSize,
+
+    /// Format of the timestamp that document directories are named after
+    pub dir_format: &'a str,
+
+    /// Add the scan mode and the scanner to the names of document directories
+    pub dir_details: bool,
+
+    /// Timezone of the timestamps
+    pub timezone: Timezone,
+
+    /// Scan the largest area of the scanner and detect the paper size of
+    /// every page
+    pub detect_paper: bool,
+
+    /// Memory budget in bytes for processing the scanned pages (e.g. cropping
+    /// them to the detected paper size)
+    pub memory_budget: usize,
 }
 
-/// Scan a document, return output path
-pub fn scan_document(context: &ScanContext) -> Result<PathBuf> {
-    let scanner = context.scanner;
+impl ScanContext<'_> {
+    /// Area that is scanned for full-page scans
+    ///
+    /// If the paper size is detected, the largest area supported by the
+    /// scanner is scanned (its whole area, if the largest paper size is not
+    /// configured). Paper sizes that are too large for the scanner are
+    /// reduced to its largest size.
+    fn scan_area(&self) -> Option<ScanArea> {
+        match self.scanner.max_paper {
+            Some(max_paper) if self.detect_paper => Some(max_paper.into()),
+            None if self.detect_paper => None,
+            Some(max_paper) if !self.paper.fits_into(max_paper) => {
+                warn!(
+                    "Scanner {} cannot scan {:?} paper, scanning {:?} instead",
+                    self.scanner.id, self.paper, max_paper
+                );
+                Some(max_paper.into())
+            }
+            _ => Some(self.paper.into()),
+        }
+    }
+}
 
-    // Determine the XDG cache directory, creating it if it doesn't exist
-    let scans_dir = app_dirs::app_dir(app_dirs::AppDataType::UserCache, &crate::APP_INFO, "scans")
-        .context("Could not determine XDG app cache directory for scans")?;
+/// Return the XDG cache directory for scans, creating it if it doesn't exist
+pub fn scans_dir() -> Result<PathBuf> {
+    app_dirs::app_dir(app_dirs::AppDataType::UserCache, &crate::APP_INFO, "scans")
+        .context("Could not determine XDG app cache directory for scans")
+}
 
-    // Ensure that "current" scan directory exists and is empty
-    let current_dir = scans_dir.join("current");
-    fs_utils::ensure_empty_dir_exists(&current_dir)?;
+/// Parameters of a scan, as chosen by the user
+#[derive(Debug, Clone)]
+struct ScanJob {
+    mode: ScanMode,
+    resolution: Resolution,
+    /// Scan a low-resolution preview of every flatbed page, and only scan the
+    /// page at full resolution once the framing is confirmed
+    preview: bool,
+    /// Additional OCRmyPDF arguments for this document only
+    ocr_args: Vec<String>,
+}
 
-    // Determine scan mode
-    let mut mode =
-        inquire::Select::new("How to scan?", ScanMode::options(&scanner.sources)).prompt()?;
+/// Ask the user how to scan a document with the given scanner
+///
+/// If `parallel` is set, the scanner ID is shown in the prompts and only ADF
+/// modes are offered, since flatbed scans require interaction for every page.
+fn prompt_scan_job(
+    scanner: &Scanner,
+    parallel: bool,
+    history: &[history::Entry],
+) -> Result<ScanJob> {
+    // Determine scan mode, preselecting the mode used most often
+    let mut options = ScanMode::options(&scanner.sources);
+    let message = if parallel {
+        options.retain(|mode| mode.source() == ScanSource::Adf);
+        format!("How to scan with {}?", scanner.id)
+    } else {
+        "How to scan?".to_string()
+    };
+    // With a single source, only its basic mode is offered, so that the prompt
+    // is skipped. Its other modes (e.g. identity documents on the flatbed) are
+    // offered as scan options instead.
+    let sources = &scanner.sources;
+    let source_count = [&sources.adf_single, &sources.adf_duplex, &sources.flatbed]
+        .into_iter()
+        .filter(|source| source.is_some())
+        .count();
+    let variants = if source_count == 1 && options.len() > 1 {
+        options.split_off(1)
+    } else {
+        Vec::new()
+    };
+    let preferred = history::preferred_mode(history, &scanner.id)
+        .and_then(|slug| options.iter().position(|mode| mode.slug() == slug))
+        .unwrap_or_default();
+    let mut mode = prompt::select(&message, options, preferred)?;
+    if variants.is_empty() {
+        mode = prompt_mode_details(mode)?;
+    }
 
-    // Determine number of pages to scan
-    if matches!(mode, ScanMode::Flatbed { .. }) {
-        let page_count = inquire::CustomType::<usize>::new("Number of pages to scan?")
-            .with_default(1)
-            .with_validator(|input: &usize| {
-                Ok(if *input > 0 {
-                    inquire::validator::Validation::Valid
-                } else {
-                    inquire::validator::Validation::Invalid("Please enter a number ≥ 1".into())
-                })
+    // Show how long earlier scans with this mode took, for the whole document
+    // if the number of pages is known already, so that the user can take it
+    // into account when choosing the resolution
+    let stats: Vec<ScanStats> = history
+        .iter()
+        .filter_map(|entry| entry.stats.clone())
+        .collect();
+    let estimates: Vec<_> = [Resolution::Normal, Resolution::High]
+        .into_iter()
+        .filter_map(|resolution| {
+            estimate::estimate(&stats, mode.slug(), resolution.as_dpi())
+                .map(|estimate| (resolution, estimate))
+        })
+        .collect();
+    let page_count = known_page_count(mode).filter(|_| variants.is_empty());
+    if !estimates.is_empty() && (page_count.is_some() || !parallel) {
+        let pages = page_count.unwrap_or(1);
+        let durations: Vec<String> = estimates
+            .iter()
+            .map(|(resolution, estimate)| {
+                format!(
+                    "{} at {}dpi",
+                    estimate::format_duration(estimate.total_secs(pages)),
+                    resolution.as_dpi()
+                )
             })
-            .with_error_message("Please enter a valid number ≥ 1")
-            .prompt()?;
-        mode = ScanMode::Flatbed { page_count };
-    };
+            .collect();
+        match page_count {
+            Some(pages) => println!(
+                "Estimated duration for {pages} page(s), including processing: {}",
+                durations.join(", ")
+            ),
+            None => println!("Earlier scans took {} per page", durations.join(", ")),
+        }
+    }
 
     // Determine scan options
     let option_highdpi = "High resolution (600dpi instead of 300dpi)";
-    let options = inquire::MultiSelect::new(
-        "Choose options (if desired) and press enter to start scanning!",
-        vec![option_highdpi],
+    let option_preview = "Preview every page before scanning it (for bound or fragile originals)";
+    let option_ocr_args = "Advanced OCR options (additional ocrmypdf arguments)";
+    let variant_options: Vec<String> = variants
+        .iter()
+        .map(|variant| format!("{variant} instead of {mode}"))
+        .collect();
+    let mut scan_options = vec![option_highdpi];
+    if matches!(mode, ScanMode::Flatbed { .. }) {
+        scan_options.push(option_preview);
+    }
+    scan_options.extend(variant_options.iter().map(String::as_str));
+    scan_options.push(option_ocr_args);
+    // Preselect high resolution if it is usually chosen for this mode
+    let preselected: &[usize] = if history::prefers_higher_dpi(
+        history,
+        &scanner.id,
+        mode.slug(),
+        Resolution::Normal.as_dpi(),
+    ) {
+        &[0]
+    } else {
+        &[]
+    };
+    let options = prompt::MultiSelect::new(
+        // Further prompts follow in parallel mode, or for mode details
+        if parallel || !variants.is_empty() {
+            "Choose options (if desired) and press enter to continue"
+        } else {
+            "Choose options (if desired) and press enter to start scanning!"
+        },
+        scan_options,
     )
+    .with_default(preselected)
     .prompt()?;
     let resolution = if options.contains(&option_highdpi) {
         Resolution::High
@@ -320,15 +932,905 @@ pub fn scan_document(context: &ScanContext) -> Result<PathBuf> {
         resolution,
         resolution.as_dpi()
     );
+    if !variants.is_empty() {
+        let variant = variants
+            .iter()
+            .zip(&variant_options)
+            .find(|(_, option)| options.contains(&option.as_str()))
+            .map(|(variant, _)| *variant);
+        mode = prompt_mode_details(variant.unwrap_or(mode))?;
+    }
+    let ocr_args = if options.contains(&option_ocr_args) {
+        let input = prompt::Text::new("Additional ocrmypdf arguments?")
+            .with_help_message("Only used for this document, e.g. --force-ocr")
+            .with_validator(|input: &str| ocr::parse_ocrmypdf_args(input).map(|_| ()))
+            .prompt()?;
+        ocr::parse_ocrmypdf_args(&input).map_err(|e| anyhow!(e))?
+    } else {
+        Vec::new()
+    };
+
+    // Estimate the duration of scans whose number of pages was only asked
+    // for after the resolution
+    if page_count.is_none()
+        && let Some(page_count) = known_page_count(mode)
+        && let Some(estimate) = estimate::estimate(&stats, mode.slug(), resolution.as_dpi())
+    {
+        println!(
+            "{} for {} page(s) at {}dpi, including processing",
+            estimate::format_duration(estimate.total_secs(page_count)),
+            page_count,
+            resolution.as_dpi()
+        );
+    }
+
+    Ok(ScanJob {
+        mode,
+        resolution,
+        preview: options.contains(&option_preview),
+        ocr_args,
+    })
+}
+
+/// Number of pages that a scan mode scans, if it is known in advance
+fn known_page_count(mode: ScanMode) -> Option<usize> {
+    match mode {
+        ScanMode::Flatbed { page_count } => Some(page_count),
+        ScanMode::IdDocument { .. } => Some(2),
+        _ => None,
+    }
+}
+
+/// Ask for the details of a scan mode: the number of pages of flatbed scans,
+/// and the size of identity documents
+fn prompt_mode_details(mut mode: ScanMode) -> Result<ScanMode> {
+    // Determine number of pages to scan
+    if matches!(mode, ScanMode::Flatbed { .. }) {
+        let page_count = prompt::CustomType::<usize>::new("Number of pages to scan?")
+            .with_default(1)
+            .with_validator(|input: &usize| {
+                if *input > 0 {
+                    Ok(())
+                } else {
+                    Err("Please enter a number ≥ 1".into())
+                }
+            })
+            .with_error_message("Please enter a valid number ≥ 1")
+            .prompt()?;
+        mode = ScanMode::Flatbed { page_count };
+    };
+
+    // Determine size of identity document
+    if matches!(mode, ScanMode::IdDocument { .. }) {
+        let size = prompt::Select::new(
+            "Size of the document?",
+            vec![IdDocumentSize::Card, IdDocumentSize::A6, IdDocumentSize::A5],
+        )
+        .prompt()?;
+        mode = ScanMode::IdDocument { size };
+    }
+
+    Ok(mode)
+}
+
+/// Acquire the lock of the scanner, if configured
+fn lock_scanner(scanner: &Scanner) -> Result<Option<ScannerLock>> {
+    let Some(lock_file) = &scanner.lock_file else {
+        return Ok(None);
+    };
+    if let Some(parent) = lock_file.parent() {
+        fs_utils::ensure_dir_prompt(parent)?;
+    }
+    ScannerLock::acquire(lock_file).map(Some)
+}
+
+/// Create an empty staging directory for a scanner, return its path
+///
+/// The name is prefixed with [`queue::CURRENT_DIR`], so that the directory is
+/// not picked up for processing.
+fn create_staging_dir(scans_dir: &Path, scanner: &Scanner, purpose: &str) -> Result<PathBuf> {
+    let staging_name: String = scanner
+        .id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let current_dir = scans_dir.join(format!(
+        "{}{}-{}",
+        queue::CURRENT_DIR,
+        purpose,
+        staging_name
+    ));
+    fs_utils::ensure_empty_dir_exists(&current_dir)?;
+    Ok(current_dir)
+}
+
+/// Reserve a new document directory in the scans directory
+///
+/// Every document is assigned a stable ID (a ULID), which is appended to the
+/// timestamp in the directory name. If enabled, the scan mode (e.g.
+/// `adf-duplex`) and the scanner are inserted in between. The directory is
+/// created empty. Returns its path and a manifest with the ID, scanner and
+/// precise scan time, to be completed by the caller.
+pub fn create_document_dir(
+    scans_dir: &Path,
+    context: &ScanContext,
+    mode: &str,
+) -> Result<(PathBuf, Manifest)> {
+    let details = if context.dir_details {
+        vec![mode, context.scanner.id.as_str()]
+    } else {
+        Vec::new()
+    };
+    let (directory, manifest) = create_document_dir_at(
+        scans_dir,
+        context.timezone.now(),
+        context.dir_format,
+        &details,
+    )?;
+    let manifest = Manifest {
+        scanner: Some(context.scanner.id.clone()),
+        ..manifest
+    };
+    Ok((directory, manifest))
+}
+
+/// Reserve a new document directory for a document captured at `time` (e.g.
+/// an imported photo) with the given details in its name, see
+/// [`create_document_dir`]
+pub fn create_document_dir_at(
+    scans_dir: &Path,
+    time: DateTime<FixedOffset>,
+    dir_format: &str,
+    details: &[&str],
+) -> Result<(PathBuf, Manifest)> {
+    let id = Ulid::from_datetime(time.into());
+    let mut name = dir_timestamp(&time, dir_format);
+    for detail in details.iter().map(|detail| dir_slug(detail)) {
+        if !detail.is_empty() {
+            name.push('_');
+            name.push_str(&detail);
+        }
+    }
+    let name = format!("{name}-{id}");
+    let directory = fs_utils::create_unique_dir(scans_dir, &name)?;
+    let manifest = Manifest {
+        id: Some(id.to_string()),
+        scanned_at: Some(time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ..Default::default()
+    };
+    Ok((directory, manifest))
+}
+
+/// Run a scan job, return output path
+///
+/// Every scanner scans into its own staging directory, which is moved to a
+/// timestamped directory once the scan is complete.
+fn run_scan_job(context: &ScanContext, job: &ScanJob, timings: &mut Timings) -> Result<PathBuf> {
+    // Determine the XDG cache directory, creating it if it doesn't exist
+    let scans_dir = scans_dir()?;
+
+    // Ensure that the staging directory of the scanner exists and is empty
+    let current_dir = create_staging_dir(&scans_dir, context.scanner, "")?;
 
     // Run `scanimage` binary
-    run_scanimage(&current_dir, context, &mode, &resolution)
-        .context("Failed to run `scanimage` command")?;
+    events::emit(&Event::ScanStarted {
+        scanner: &context.scanner.id,
+    });
+    let scan_secs = timings
+        .measure("Scan", || run_scanimage(&current_dir, context, job))
+        .context("Failed to run `scanimage` command")?
+        .as_secs_f32();
+
+    // Move staging directory to a timestamped directory
+    let (new_dir, manifest) = create_document_dir(&scans_dir, context, job.mode.slug())?;
+    fs_utils::move_path(&current_dir, &new_dir)?;
+
+    // Detect the paper size of mixed-size feeds
+    let paper = if context.detect_paper {
+        detect_paper_sizes(
+            &new_dir,
+            job.resolution.as_dpi(),
+            job.mode.source(),
+            context.memory_budget,
+        )
+    } else {
+        None
+    };
 
-    // Rename current scan directory
-    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
-    let new_dir = scans_dir.join(timestamp);
-    fs::rename(&current_dir, &new_dir)?;
+    // Remember the scanner and source, so that scanner-specific corrections
+    // can be applied
+    let manifest = Manifest {
+        source: Some(job.mode.source()),
+        paper,
+        n_up: matches!(job.mode, ScanMode::IdDocument { .. }),
+        ocr_args: job.ocr_args.clone(),
+        scan_stats: Some(ScanStats {
+            mode: job.mode.slug().into(),
+            dpi: job.resolution.as_dpi(),
+            pages: process::collect_page_tifs(&new_dir)?.len(),
+            scan_secs,
+            process_secs: None,
+        }),
+        ..manifest
+    };
+    manifest.save(&new_dir)?;
+    events::emit(&Event::ScanFinished {
+        scanner: &context.scanner.id,
+        directory: &new_dir,
+        pages: process::collect_page_tifs(&new_dir)?.len(),
+    });
 
     Ok(new_dir)
 }
+
+/// Detect the paper size of every scanned page of a document from its
+/// dimensions, return it if all pages have the same known size
+///
+/// Scanners with automatic length detection only crop the length of a page to
+/// the paper, so pages that are wider than the paper of their length are
+/// cropped to its width. The ADF centers the paper, on the flatbed it is
+/// placed at the left edge.
+fn detect_paper_sizes(
+    document_dir: &Path,
+    dpi: u32,
+    source: ScanSource,
+    memory_budget: usize,
+) -> Option<PaperSize> {
+    let pages = process::collect_page_tifs(document_dir).ok()?;
+    let mut sizes = Vec::new();
+    for page in &pages {
+        let size = detect_paper_size(&document_dir.join(page), dpi, source, memory_budget)
+            .unwrap_or_else(|e| {
+                debug!("Failed to determine the size of {page}: {e:#}");
+                None
+            });
+        debug!("Detected paper size of {page}: {size:?}");
+        sizes.push(size);
+    }
+    let first = *sizes.first()?;
+    if sizes.iter().all(|size| *size == first) {
+        first
+    } else {
+        println!("The document contains pages of different sizes");
+        None
+    }
+}
+
+/// Detect the paper size of a scanned page and crop the page to the width of
+/// the paper if it is wider (see [`detect_paper_sizes`])
+fn detect_paper_size(
+    page: &Path,
+    dpi: u32,
+    source: ScanSource,
+    memory_budget: usize,
+) -> Result<Option<PaperSize>> {
+    let (width, height) = tiff_utils::page_dimensions(page)?;
+    let mm = |pixels: u32| pixels as f32 / dpi as f32 * 25.4;
+    if let Some(paper) = PaperSize::detect(mm(width), mm(height)) {
+        return Ok(Some(paper));
+    }
+    let Some((paper, paper_width)) = PaperSize::detect_by_length(mm(width), mm(height)) else {
+        return Ok(None);
+    };
+    let cropped_width = ((paper_width / 25.4 * dpi as f32).round() as u32).clamp(1, width);
+    let left = match source {
+        ScanSource::Adf => (width - cropped_width) / 2,
+        ScanSource::Flatbed | ScanSource::Camera => 0,
+    };
+    debug!("Cropping {page:?} to {cropped_width} columns at {left}");
+    let cropped = page.with_extension("cropped.tif");
+    tiff_utils::crop_columns(
+        page,
+        &cropped,
+        left,
+        cropped_width,
+        TiffCompression::None,
+        memory_budget,
+    )?;
+    fs::rename(&cropped, page).with_context(|| format!("Failed to replace {page:?}"))?;
+    Ok(Some(paper))
+}
+
+/// Scan a single reference page (e.g. for calibration) at normal resolution
+///
+/// The flatbed is preferred if available. Returns the staging directory,
+/// which must be removed by the caller, and the path of the scanned page.
+pub fn scan_reference_page(context: &ScanContext) -> Result<(PathBuf, PathBuf)> {
+    let mode = if context.scanner.sources.flatbed.is_some() {
+        ScanMode::Flatbed { page_count: 1 }
+    } else {
+        ScanMode::AdfSingleSided
+    };
+
+    let _lock = lock_scanner(context.scanner)?;
+    let current_dir = create_staging_dir(&scans_dir()?, context.scanner, "-reference")?;
+    let job = ScanJob {
+        mode,
+        resolution: Resolution::Normal,
+        preview: false,
+        ocr_args: Vec::new(),
+    };
+    run_scanimage(&current_dir, context, &job).context("Failed to run `scanimage` command")?;
+
+    let page = current_dir.join("0001.tif");
+    ensure!(page.exists(), "No page was scanned");
+    Ok((current_dir, page))
+}
+
+/// Scan a document, return output path
+///
+/// The duration of the scan is recorded in `timings`.
+pub fn scan_document(context: &ScanContext, timings: &mut Timings) -> Result<PathBuf> {
+    let job = prompt_scan_job(context.scanner, false, &history::load(&scans_dir()?))?;
+
+    // Lock the scanner for the duration of the scan, if configured
+    let _lock = lock_scanner(context.scanner)?;
+
+    let document_dir = run_scan_job(context, &job, timings)?;
+
+    // Offer to rescan pages of low quality
+    if let Some(min_quality) = context.min_page_quality {
+        rescan_low_quality_pages(context, &document_dir, min_quality, timings)?;
+    }
+
+    Ok(document_dir)
+}
+
+/// Scan batches from the ADF (single-sided) until the user stops, return the
+/// staging directory containing all pages
+///
+/// Pages are numbered continuously across batches. The caller is responsible
+/// for moving the pages out of the staging directory.
+pub fn scan_continuous(context: &ScanContext, timings: &mut Timings) -> Result<PathBuf> {
+    let source = context.scanner.sources.adf_single.as_ref().ok_or_else(|| {
+        anyhow!(
+            "ADF single-sided not available for scanner {}",
+            context.scanner.id
+        )
+    })?;
+
+    // Lock the scanner for the whole session, if configured
+    let _lock = lock_scanner(context.scanner)?;
+
+    let current_dir = create_staging_dir(&scans_dir()?, context.scanner, "-bulk")?;
+    loop {
+        let start = process::collect_page_tifs(&current_dir)?.len();
+        timings
+            .measure("Scan", || {
+                _scanimage(
+                    &current_dir,
+                    context,
+                    source,
+                    start,
+                    None,
+                    &Resolution::Normal,
+                    context.scan_area(),
+                )
+            })
+            .context("Failed to run `scanimage` command")?;
+        let page_count = process::collect_page_tifs(&current_dir)?.len();
+        let scan_more = prompt::Confirm::new(&format!(
+            "Scanned {page_count} page(s) so far. Scan another batch?"
+        ))
+        .with_default(true)
+        .with_help_message("Refill the document feeder before confirming")
+        .prompt()?;
+        if !scan_more {
+            break;
+        }
+    }
+
+    Ok(current_dir)
+}
+
+/// Assess the quality of all pages of a scanned document and offer to rescan
+/// the pages whose score is below `min_quality`
+///
+/// Pages are rescanned one by one, from the flatbed if available.
+fn rescan_low_quality_pages(
+    context: &ScanContext,
+    document_dir: &Path,
+    min_quality: f32,
+    timings: &mut Timings,
+) -> Result<()> {
+    // Find pages of low quality
+    let mut low_quality = Vec::new();
+    for (i, page) in process::collect_page_tifs(document_dir)?.iter().enumerate() {
+        match quality::assess(&document_dir.join(page)) {
+            Ok(page_quality) if page_quality.score() < min_quality => {
+                println!(
+                    "Page {} has a low quality score of {:.0} (contrast {:.0}, sharpness {:.0})",
+                    i + 1,
+                    page_quality.score(),
+                    page_quality.contrast,
+                    page_quality.sharpness,
+                );
+                low_quality.push(i);
+            }
+            Ok(page_quality) => trace!("Quality of page {}: {:?}", i + 1, page_quality),
+            Err(e) => debug!("Failed to assess quality of page {}: {:#}", i + 1, e),
+        }
+    }
+    if low_quality.is_empty() {
+        return Ok(());
+    }
+
+    // Determine rescan source
+    let Some(source) = context.scanner.sources.flatbed.as_ref().or(context
+        .scanner
+        .sources
+        .adf_single
+        .as_ref())
+    else {
+        warn!("Scanner has no source for rescanning single pages");
+        return flag_low_quality_pages(document_dir, &low_quality);
+    };
+
+    // Ask the user
+    let option_rescan = "Rescan";
+    let option_rescan_highdpi = "Rescan at high resolution (600dpi)";
+    let option_keep = "Keep pages";
+    let choice = prompt::Select::new(
+        &format!("Rescan {} page(s) of low quality?", low_quality.len()),
+        vec![option_rescan, option_rescan_highdpi, option_keep],
+    )
+    .prompt()?;
+    let resolution = if choice == option_rescan {
+        Some(Resolution::Normal)
+    } else if choice == option_rescan_highdpi {
+        Some(Resolution::High)
+    } else {
+        None
+    };
+
+    // Rescan pages, overwriting the original files
+    let mut kept = Vec::new();
+    timings.measure("Rescan", || -> Result<()> {
+        for i in low_quality {
+            let rescan = match resolution {
+                Some(_) => prompt::Confirm::new(&format!("Insert page {} and rescan?", i + 1))
+                    .with_default(true)
+                    .with_help_message("Type 'n' to keep the original page")
+                    .prompt()?,
+                None => false,
+            };
+            match resolution {
+                Some(resolution) if rescan => _scanimage(
+                    document_dir,
+                    context,
+                    source,
+                    i,
+                    Some(1),
+                    &resolution,
+                    context.scan_area(),
+                )?,
+                _ => kept.push(i),
+            }
+        }
+        Ok(())
+    })?;
+
+    flag_low_quality_pages(document_dir, &kept)
+}
+
+/// Rescan all pages of a document at high resolution, replacing the scanned
+/// pages (e.g. if OCR recognized almost no text)
+///
+/// The document has to be processed again afterwards.
+pub fn rescan_document(
+    context: &ScanContext,
+    document_dir: &Path,
+    timings: &mut Timings,
+) -> Result<()> {
+    let job = ScanJob {
+        resolution: Resolution::High,
+        ..prompt_scan_job(context.scanner, false, &history::load(&scans_dir()?))?
+    };
+    let _lock = lock_scanner(context.scanner)?;
+    let current_dir = create_staging_dir(&scans_dir()?, context.scanner, "-rescan")?;
+    let scan_secs = timings
+        .measure("Rescan", || run_scanimage(&current_dir, context, &job))
+        .context("Failed to run `scanimage` command")?
+        .as_secs_f32();
+    let pages = process::collect_page_tifs(&current_dir)?;
+    ensure!(!pages.is_empty(), "No pages were scanned");
+
+    // Replace the pages, including compressed ones
+    compression::decompress_pages(document_dir)?;
+    for page in process::collect_page_tifs(document_dir)? {
+        fs::remove_file(document_dir.join(&page))
+            .with_context(|| format!("Failed to remove {page}"))?;
+    }
+    for page in &pages {
+        fs_utils::move_path(&current_dir.join(page), &document_dir.join(page))?;
+    }
+    if let Err(e) = fs::remove_dir_all(&current_dir) {
+        debug!("Failed to remove {current_dir:?}: {e}");
+    }
+
+    let mut manifest = Manifest::load(document_dir)?;
+    manifest.source = Some(job.mode.source());
+    manifest.n_up = matches!(job.mode, ScanMode::IdDocument { .. });
+    manifest.ocr_args = job.ocr_args;
+    manifest.scan_stats = Some(ScanStats {
+        mode: job.mode.slug().into(),
+        dpi: job.resolution.as_dpi(),
+        pages: pages.len(),
+        scan_secs,
+        process_secs: None,
+    });
+    manifest.completed_steps.clear();
+    manifest.save(document_dir)
+}
+
+/// Flag the document for review if pages of low quality were kept
+fn flag_low_quality_pages(document_dir: &Path, kept: &[usize]) -> Result<()> {
+    if kept.is_empty() {
+        return Ok(());
+    }
+    let pages: Vec<String> = kept.iter().map(|i| (i + 1).to_string()).collect();
+    let mut manifest = Manifest::load(document_dir)?;
+    manifest.add_warning(format!("Pages of low quality kept: {}", pages.join(", ")));
+    manifest.save(document_dir)
+}
+
+/// Scan documents on multiple scanners concurrently, return output paths
+///
+/// The user selects the scanners and configures all scans up front, then the
+/// scans run in parallel with a merged progress view. Only ADF modes are
+/// available. If some scans fail, the successful ones are still returned.
+pub fn scan_parallel(
+    scanners: &[Scanner],
+    fake_scan: bool,
+    paper: PaperSize,
+    scan_config: &ScanConfig,
+    memory_budget: usize,
+    timings: &mut Timings,
+) -> Result<Vec<PathBuf>> {
+    let selected = prompt::MultiSelect::new("Which devices do you want to use?", scanners.to_vec())
+        .with_validator(|selected| {
+            if selected == 0 {
+                Err("Please select at least one device".into())
+            } else {
+                Ok(())
+            }
+        })
+        .prompt()?;
+
+    // Configure all scans and lock the scanners before starting
+    let history = history::load(&scans_dir()?);
+    let jobs = selected
+        .iter()
+        .map(|scanner| prompt_scan_job(scanner, true, &history))
+        .collect::<Result<Vec<_>>>()?;
+    let _locks = selected
+        .iter()
+        .map(lock_scanner)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Run scans concurrently
+    let progress = MultiProgress::new();
+    let scans: Vec<_> = selected.iter().zip(&jobs).collect();
+    let results = scheduler::global().map(JobKind::Io, &scans, |_, (scanner, job)| {
+        let context = ScanContext {
+            scanner,
+            fake_scan,
+            progress: Some(progress.clone()),
+            min_page_quality: None,
+            paper,
+            dir_format: &scan_config.dir_format,
+            dir_details: scan_config.dir_details,
+            timezone: scan_config.timezone,
+            detect_paper: scan_config.detect_paper_size,
+            memory_budget,
+        };
+        let mut scan_timings = Timings::default();
+        let result = run_scan_job(&context, job, &mut scan_timings);
+        (result, scan_timings)
+    });
+
+    // Collect results
+    let mut document_dirs = Vec::new();
+    for (scanner, (result, scan_timings)) in selected.iter().zip(results) {
+        timings.extend_prefixed(&scanner.id, scan_timings);
+        match result {
+            Ok(document_dir) => document_dirs.push(document_dir),
+            Err(e) => warn!("Scan with {} failed: {:#}", scanner.id, e),
+        }
+    }
+    ensure!(!document_dirs.is_empty(), "All scans failed");
+
+    Ok(document_dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::prompt::ScriptedPrompter;
+
+    /// Ensure that pages are numbered from 1 with zero-padded filenames.
+    #[test]
+    fn batch_args_first_page() {
+        let args = batch_args(Path::new("/scans"), 0, None);
+        assert_eq!(
+            args,
+            vec![
+                "--format=tiff",
+                "--batch=/scans/%04d.tif",
+                "--batch-start=1"
+            ]
+        );
+    }
+
+    /// Ensure that the batch offset and count are passed on, also for large
+    /// batches that exceed four digits.
+    #[test]
+    fn batch_args_large_batch() {
+        let args = batch_args(Path::new("/scans"), 12345, Some(1));
+        assert_eq!(args[2], "--batch-start=12346");
+        assert_eq!(args[3], "--batch-count=1");
+    }
+
+    /// Ensure that the scan area is only shifted if offsets are configured, and
+    /// that it is enlarged by the extra size.
+    #[test]
+    fn geometry() {
+        let area = ScanArea::from(PaperSize::A4);
+        assert_eq!(
+            geometry_args(&Geometry::default(), Some(&area)),
+            vec!["-x", "210", "-y", "297"]
+        );
+        let geometry = Geometry {
+            left: 1.5,
+            top: 0.0,
+            extra_width: 0.0,
+            extra_height: 4.0,
+        };
+        assert_eq!(
+            geometry_args(&geometry, Some(&area)),
+            vec!["-l", "1.5", "-x", "210", "-y", "301"]
+        );
+        assert_eq!(geometry_args(&geometry, None), vec!["-l", "1.5"]);
+    }
+
+    /// Ensure that pages that are only cropped in their length are cropped to
+    /// the width of the detected paper, centered for the ADF.
+    #[test]
+    fn crop_to_detected_paper() {
+        use std::io::BufWriter;
+
+        use tiff::encoder::{TiffEncoder, colortype};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let write_page = |name: &str, width: u32, height: u32| {
+            let pixels: Vec<u8> = (0..width * height).map(|i| (i % width) as u8).collect();
+            let file = fs::File::create(temp_dir.path().join(name)).unwrap();
+            TiffEncoder::new(BufWriter::new(file))
+                .unwrap()
+                .write_image::<colortype::Gray8>(width, height, &pixels)
+                .unwrap();
+        };
+
+        // At 10 DPI, A4 is 83x117 pixels, the full width of an A3 scanner 117
+        write_page("0001.tif", 117, 117);
+        write_page("0002.tif", 83, 117);
+        assert_eq!(
+            detect_paper_sizes(temp_dir.path(), 10, ScanSource::Adf, usize::MAX),
+            Some(PaperSize::A4)
+        );
+        let page = temp_dir.path().join("0001.tif");
+        assert_eq!(tiff_utils::page_dimensions(&page).unwrap(), (83, 117));
+        let (profile, _) = tiff_utils::column_profile(&page, usize::MAX).unwrap();
+        assert_eq!(profile[0], 17.0);
+
+        // On the flatbed, the paper is at the left edge
+        write_page("0003.tif", 117, 117);
+        let page = temp_dir.path().join("0003.tif");
+        assert_eq!(
+            detect_paper_size(&page, 10, ScanSource::Flatbed, usize::MAX).unwrap(),
+            Some(PaperSize::A4)
+        );
+        let (profile, _) = tiff_utils::column_profile(&page, usize::MAX).unwrap();
+        assert_eq!(profile[0], 0.0);
+
+        // Pages of unknown length are not cropped
+        write_page("0004.tif", 117, 60);
+        assert_eq!(
+            detect_paper_sizes(temp_dir.path(), 10, ScanSource::Adf, usize::MAX),
+            None
+        );
+        assert_eq!(
+            tiff_utils::page_dimensions(&temp_dir.path().join("0004.tif")).unwrap(),
+            (117, 60)
+        );
+    }
+
+    /// Ensure that the back sides of a manual duplex scan are interleaved in
+    /// reverse order with the front sides.
+    #[test]
+    fn interleave_manual_duplex() {
+        let fronts_dir = tempfile::TempDir::new().unwrap();
+        let backs_dir = tempfile::TempDir::new().unwrap();
+        for (number, page) in ["front 1", "front 2", "front 3"].iter().enumerate() {
+            fs::write(
+                fronts_dir.path().join(format!("{:04}.tif", number + 1)),
+                page,
+            )
+            .unwrap();
+        }
+        for (number, page) in ["back 3", "back 2", "back 1"].iter().enumerate() {
+            fs::write(
+                backs_dir.path().join(format!("{:04}.tif", number + 1)),
+                page,
+            )
+            .unwrap();
+        }
+        interleave_duplex(fronts_dir.path(), backs_dir.path()).unwrap();
+
+        let pages: Vec<String> = process::collect_page_tifs(fronts_dir.path())
+            .unwrap()
+            .iter()
+            .map(|page| fs::read_to_string(fronts_dir.path().join(page)).unwrap())
+            .collect();
+        assert_eq!(
+            pages,
+            [
+                "front 1", "back 1", "front 2", "back 2", "front 3", "back 3"
+            ]
+        );
+
+        // Missing back sides are an error
+        fs::write(backs_dir.path().join("0001.tif"), "back").unwrap();
+        assert!(interleave_duplex(fronts_dir.path(), backs_dir.path()).is_err());
+    }
+
+    fn scanner_with(sources: ScannerSources) -> Scanner {
+        Scanner {
+            id: "office".into(),
+            device_name: "test".into(),
+            additional_args: vec![],
+            backend: ScannerBackend::Virtual,
+            virtual_pages: VirtualPages::default(),
+            sources,
+            max_paper: None,
+            geometry: Geometry::default(),
+            lock_file: None,
+        }
+    }
+
+    /// Ensure that the mode prompt is skipped for flatbed-only scanners, and
+    /// that identity documents are offered as an option instead.
+    #[test]
+    fn flatbed_scan_job() {
+        let scanner = scanner_with(ScannerSources {
+            adf_single: None,
+            adf_duplex: None,
+            flatbed: Some("Flatbed".into()),
+        });
+        // Options, then the number of pages
+        let job = prompt::with_prompter(ScriptedPrompter::new(["", "3"]), || {
+            prompt_scan_job(&scanner, false, &[]).unwrap()
+        });
+        assert_eq!(job.mode, ScanMode::Flatbed { page_count: 3 });
+        assert_eq!(job.resolution, Resolution::Normal);
+        assert!(!job.preview);
+
+        // High resolution and identity document, then its size
+        let job = prompt::with_prompter(ScriptedPrompter::new(["1,3", "2"]), || {
+            prompt_scan_job(&scanner, false, &[]).unwrap()
+        });
+        assert_eq!(
+            job.mode,
+            ScanMode::IdDocument {
+                size: IdDocumentSize::A6
+            }
+        );
+        assert_eq!(job.resolution, Resolution::High);
+        assert!(job.ocr_args.is_empty());
+
+        // Advanced OCR options, which must start with an option
+        let job = prompt::with_prompter(
+            ScriptedPrompter::new(["4", "1", "--force-ocr --rotate-pages"]),
+            || prompt_scan_job(&scanner, false, &[]).unwrap(),
+        );
+        assert_eq!(job.ocr_args, vec!["--force-ocr", "--rotate-pages"]);
+        let result = prompt::with_prompter(ScriptedPrompter::new(["4", "1", "out.pdf"]), || {
+            prompt_scan_job(&scanner, false, &[])
+        });
+        assert!(result.is_err());
+    }
+
+    /// Ensure that the mode is selected for scanners with multiple sources,
+    /// and that flatbed scans can be previewed.
+    #[test]
+    fn selected_scan_job() {
+        let scanner = scanner_with(ScannerSources {
+            adf_single: Some("ADF".into()),
+            adf_duplex: Some("ADF Duplex".into()),
+            flatbed: Some("Flatbed".into()),
+        });
+        let job = prompt::with_prompter(ScriptedPrompter::new(["ADF duplex", "1"]), || {
+            prompt_scan_job(&scanner, false, &[]).unwrap()
+        });
+        assert_eq!(job.mode, ScanMode::AdfDuplex);
+        assert_eq!(job.resolution, Resolution::High);
+
+        let job = prompt::with_prompter(ScriptedPrompter::new(["Flatbed", "", "2"]), || {
+            prompt_scan_job(&scanner, false, &[]).unwrap()
+        });
+        assert_eq!(job.mode, ScanMode::Flatbed { page_count: 1 });
+        assert!(job.preview);
+
+        // In parallel mode, only ADF modes are offered
+        let result = prompt::with_prompter(ScriptedPrompter::new(["Flatbed"]), || {
+            prompt_scan_job(&scanner, true, &[])
+        });
+        assert!(result.is_err());
+    }
+
+    /// Ensure that document directories are named after the scan time and the
+    /// document ID, which are both recorded in the manifest.
+    #[test]
+    fn document_dir_with_id() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let scanner = Scanner {
+            id: "flatbed".into(),
+            device_name: "test".into(),
+            additional_args: vec![],
+            backend: ScannerBackend::Virtual,
+            virtual_pages: VirtualPages::default(),
+            sources: ScannerSources {
+                adf_single: None,
+                adf_duplex: None,
+                flatbed: Some("Flatbed".into()),
+            },
+            max_paper: None,
+            geometry: Geometry::default(),
+            lock_file: None,
+        };
+        let context = ScanContext {
+            scanner: &scanner,
+            fake_scan: true,
+            progress: None,
+            min_page_quality: None,
+            paper: PaperSize::A4,
+            dir_format: "%Y-%m-%d_%H%M%S",
+            dir_details: false,
+            timezone: Timezone::Utc,
+            detect_paper: false,
+            memory_budget: usize::MAX,
+        };
+
+        let (first, manifest) = create_document_dir(temp_dir.path(), &context, "flatbed").unwrap();
+        let (second, _) = create_document_dir(temp_dir.path(), &context, "flatbed").unwrap();
+        assert!(first.is_dir());
+        assert_ne!(first, second);
+
+        let id = manifest.id.clone().unwrap();
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), "2025-01-01_120000-".len() + 26);
+        assert!(name.ends_with(&id));
+        let scan_time = manifest.scan_time().unwrap();
+        assert!(name.starts_with(&scan_time.format("%Y-%m-%d_%H%M%S").to_string()));
+        assert_eq!(
+            Ulid::from_string(&id).unwrap().timestamp_ms(),
+            scan_time.timestamp_millis() as u64
+        );
+        assert_eq!(manifest.scanner.as_deref(), Some("flatbed"));
+
+        // With details, the mode and the scanner follow the timestamp
+        let context = ScanContext {
+            dir_details: true,
+            ..context
+        };
+        let (third, manifest) =
+            create_document_dir(temp_dir.path(), &context, "adf-duplex").unwrap();
+        let name = third.file_name().unwrap().to_str().unwrap();
+        let timestamp = manifest.scan_time().unwrap().format("%Y-%m-%d_%H%M%S");
+        assert_eq!(
+            name,
+            format!("{timestamp}_adf-duplex_flatbed-{}", manifest.id.unwrap())
+        );
+    }
+}
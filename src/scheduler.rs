@@ -0,0 +1,204 @@
+use std::{
+    sync::{
+        Condvar, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use tracing::debug;
+
+use crate::config::JobsConfig;
+
+/// Kind of a job, which determines the limit of concurrently running jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// CPU-bound work (e.g. image processing or OCR)
+    Cpu,
+    /// I/O-bound work (e.g. scanning or uploads)
+    Io,
+}
+
+/// A counting semaphore
+#[derive(Debug)]
+struct Slots {
+    free: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Slots {
+    fn new(count: usize) -> Self {
+        Self {
+            free: Mutex::new(count.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a slot is free, and occupy it until the guard is dropped
+    fn acquire(&self) -> SlotGuard<'_> {
+        let mut free = self.free.lock().expect("Poisoned scheduler lock");
+        while *free == 0 {
+            free = self.released.wait(free).expect("Poisoned scheduler lock");
+        }
+        *free -= 1;
+        SlotGuard(self)
+    }
+}
+
+struct SlotGuard<'a>(&'a Slots);
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().expect("Poisoned scheduler lock") += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Limits the number of concurrently running jobs of every kind
+///
+/// Jobs must not wait for other jobs of the same kind, since that might
+/// deadlock once all slots are occupied.
+#[derive(Debug)]
+pub struct Scheduler {
+    cpu: Slots,
+    io: Slots,
+    cpu_limit: usize,
+    io_limit: usize,
+}
+
+static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+
+/// Configure the global scheduler
+///
+/// Has no effect if the global scheduler is already in use.
+pub fn init(config: &JobsConfig) {
+    if SCHEDULER.set(Scheduler::new(config)).is_err() {
+        debug!("Scheduler is already initialized");
+    }
+}
+
+/// The global scheduler that governs all parallel work
+pub fn global() -> &'static Scheduler {
+    SCHEDULER.get_or_init(|| Scheduler::new(&JobsConfig::default()))
+}
+
+impl Scheduler {
+    pub fn new(config: &JobsConfig) -> Self {
+        let cpu_limit = config.cpu_limit();
+        let io_limit = config.max_io_jobs.max(1);
+        debug!("Job limits: {cpu_limit} CPU jobs, {io_limit} I/O jobs");
+        Self {
+            cpu: Slots::new(cpu_limit),
+            io: Slots::new(io_limit),
+            cpu_limit,
+            io_limit,
+        }
+    }
+
+    /// Maximal number of concurrently running jobs of the kind
+    pub fn limit(&self, kind: JobKind) -> usize {
+        self.slots(kind).1
+    }
+
+    fn slots(&self, kind: JobKind) -> (&Slots, usize) {
+        match kind {
+            JobKind::Cpu => (&self.cpu, self.cpu_limit),
+            JobKind::Io => (&self.io, self.io_limit),
+        }
+    }
+
+    /// Run a job as soon as a slot of its kind is free
+    pub fn run<T>(&self, kind: JobKind, job: impl FnOnce() -> T) -> T {
+        let _slot = self.slots(kind).0.acquire();
+        job()
+    }
+
+    /// Run a job for every item concurrently, within the limit of the kind
+    ///
+    /// The job receives the index and the item. The results are returned in the
+    /// order of the items.
+    pub fn map<I: Sync, T: Send>(
+        &self,
+        kind: JobKind,
+        items: &[I],
+        job: impl Fn(usize, &I) -> T + Sync,
+    ) -> Vec<T> {
+        let (_, limit) = self.slots(kind);
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+        thread::scope(|scope| {
+            for _ in 0..limit.min(items.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        let result = self.run(kind, || job(i, item));
+                        results.lock().expect("Poisoned result lock")[i] = Some(result);
+                    }
+                });
+            }
+        });
+        results
+            .into_inner()
+            .expect("Poisoned result lock")
+            .into_iter()
+            .map(|result| result.expect("Job did not run"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Ensure that results are returned in the order of the items.
+    #[test]
+    fn map_in_order() {
+        let scheduler = Scheduler::new(&JobsConfig {
+            max_cpu_jobs: Some(3),
+            ..Default::default()
+        });
+        let items: Vec<u64> = (0..20).collect();
+        let results = scheduler.map(JobKind::Cpu, &items, |i, item| {
+            thread::sleep(Duration::from_millis(20 - item));
+            (i, item * 2)
+        });
+        assert_eq!(
+            results,
+            (0..20).map(|i| (i as usize, i * 2)).collect::<Vec<_>>()
+        );
+        assert!(
+            scheduler
+                .map(JobKind::Io, &[] as &[u8], |_, _| ())
+                .is_empty()
+        );
+    }
+
+    /// Ensure that no more jobs than the limit run at the same time, also
+    /// if jobs are started from multiple threads.
+    #[test]
+    fn limit_concurrency() {
+        let scheduler = Scheduler::new(&JobsConfig {
+            max_io_jobs: 2,
+            ..Default::default()
+        });
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let job = |_, _: &u8| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| scheduler.map(JobKind::Io, &[0; 5], job));
+            }
+        });
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,810 @@
+//! Full-text search in the archived documents
+//!
+//! The recognized text of the archived PDFs is kept in an index in the archive
+//! directory, with one text file per PDF (mirroring the directory layout), so
+//! that archiving a document only writes its own entry. Documents are added
+//! when they are archived. `arkivisto index` adds the PDFs that were filed
+//! otherwise (extracting their text with `pdftotext`) and drops the deleted
+//! ones.
+//!
+//! The entries of archived documents also hold the metadata entered when
+//! archiving them (e.g. the correspondent), which is kept when the index is
+//! updated.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDate;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{fs_utils, labels, naming::DocumentInfo, tags, tools, ui};
+
+/// Name of the search index directory in the archive directory
+const INDEX_DIR: &str = ".arkivisto-index";
+
+/// Extension of the index entries, appended to the name of the PDF
+const ENTRY_EXTENSION: &str = ".txt";
+
+/// Suffix of the copies for emailing, which are not indexed (next to the
+/// archived PDF without the suffix)
+const EMAIL_SUFFIX: &str = "_email.pdf";
+
+/// Characters of context shown before and after a match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Indexed text of an archived PDF
+///
+/// Entries are stored as a header of `name: value` lines, followed by an
+/// empty line and the text.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Entry {
+    /// Modification time of the PDF (seconds since the Unix epoch) when its
+    /// text was indexed, to detect changed files. Zero if the text could not
+    /// be extracted yet.
+    modified: u64,
+    /// SHA-256 checksum of the PDF when it was indexed, to detect changes
+    /// that keep the modification time (e.g. corruption)
+    checksum: Option<String>,
+    /// Stable ID of the document, if it was archived by arkivisto
+    id: Option<String>,
+    /// Date of the document, if it was archived by arkivisto
+    date: Option<NaiveDate>,
+    correspondent: Option<String>,
+    title: Option<String>,
+    /// Expiry or follow-up date of the document
+    due: Option<NaiveDate>,
+    /// Where the paper original of the document is kept
+    location: Option<String>,
+    text: String,
+}
+
+impl Entry {
+    fn parse(content: &str) -> Result<Self> {
+        let (header, text) = content.split_once("\n\n").unwrap_or((content, ""));
+        let mut entry = Self {
+            text: text.to_string(),
+            ..Default::default()
+        };
+        for line in header.lines() {
+            match line.split_once(": ") {
+                Some(("modified", value)) => {
+                    entry.modified = value.parse().context("Invalid modification time")?;
+                }
+                Some(("sha256", value)) => entry.checksum = Some(value.to_string()),
+                Some(("id", value)) => entry.id = Some(value.to_string()),
+                Some(("date", value)) => {
+                    entry.date = Some(value.parse().context("Invalid date")?);
+                }
+                Some(("correspondent", value)) => entry.correspondent = Some(value.to_string()),
+                Some(("title", value)) => entry.title = Some(value.to_string()),
+                Some(("location", value)) => entry.location = Some(value.to_string()),
+                Some(("due", value)) => {
+                    entry.due = Some(value.parse().context("Invalid due date")?);
+                }
+                Some(_) => {}
+                None => return Err(anyhow!("Invalid header line {line:?}")),
+            }
+        }
+        Ok(entry)
+    }
+
+    fn to_content(&self) -> String {
+        let mut content = format!("modified: {}\n", self.modified);
+        if let Some(checksum) = &self.checksum {
+            content.push_str(&format!("sha256: {checksum}\n"));
+        }
+        if let Some(id) = &self.id {
+            content.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(date) = self.date {
+            content.push_str(&format!("date: {date}\n"));
+        }
+        // Line breaks would end the header
+        let single_line = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(correspondent) = &self.correspondent {
+            content.push_str(&format!("correspondent: {}\n", single_line(correspondent)));
+        }
+        if let Some(title) = &self.title {
+            content.push_str(&format!("title: {}\n", single_line(title)));
+        }
+        if let Some(due) = self.due {
+            content.push_str(&format!("due: {due}\n"));
+        }
+        if let Some(location) = &self.location {
+            content.push_str(&format!("location: {}\n", single_line(location)));
+        }
+        content.push('\n');
+        content.push_str(&self.text);
+        content
+    }
+}
+
+/// Path of the index entry of the PDF with the given key
+fn entry_path(outdir: &Path, key: &str) -> PathBuf {
+    outdir
+        .join(INDEX_DIR)
+        .join(format!("{key}{ENTRY_EXTENSION}"))
+}
+
+/// Write the index entry of the PDF with the given key
+fn save_entry(outdir: &Path, key: &str, entry: &Entry) -> Result<()> {
+    let path = entry_path(outdir, key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    fs_utils::write_synced(&path, entry.to_content())
+        .with_context(|| format!("Failed to write search index entry {path:?}"))
+}
+
+/// Load the index of `outdir`, by path of the PDFs relative to `outdir`
+fn load_index(outdir: &Path) -> Result<BTreeMap<String, Entry>> {
+    let index_dir = outdir.join(INDEX_DIR);
+    let mut entries = BTreeMap::new();
+    if !index_dir.is_dir() {
+        return Ok(entries);
+    }
+    for path in collect_files(&index_dir, ENTRY_EXTENSION)? {
+        let key = fs_utils::relative_key(&index_dir, &path);
+        let key = key
+            .strip_suffix(ENTRY_EXTENSION)
+            .unwrap_or(&key)
+            .to_string();
+        let entry = fs_utils::retry_stale(|| fs::read_to_string(&path))
+            .with_context(|| format!("Failed to read search index entry {path:?}"))
+            .and_then(|content| Entry::parse(&content));
+        match entry {
+            Ok(entry) => {
+                entries.insert(key, entry);
+            }
+            Err(e) => warn!("Skipping search index entry {path:?}: {e:#}"),
+        }
+    }
+    Ok(entries)
+}
+
+/// Modification time of a file, in seconds since the Unix epoch
+fn modified(path: &Path) -> Result<u64> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read modification time of {path:?}"))?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs()))
+}
+
+/// SHA-256 checksum of a file, as hex string
+pub fn checksum(path: &Path) -> Result<String> {
+    let data = fs_utils::retry_stale(|| fs::read(path))
+        .with_context(|| format!("Failed to read {path:?}"))?;
+    Ok(Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Add an archived PDF with its recognized text, its ID and its metadata to
+/// the index of `outdir`
+pub fn add(
+    outdir: &Path,
+    pdf: &Path,
+    text: &str,
+    id: Option<&str>,
+    info: Option<&DocumentInfo>,
+) -> Result<()> {
+    let entry = Entry {
+        modified: modified(pdf)?,
+        checksum: Some(checksum(pdf)?),
+        id: id.map(str::to_string),
+        date: info.map(|info| info.date),
+        correspondent: info.and_then(|info| info.correspondent.clone()),
+        title: info.map(|info| info.title.clone()),
+        due: info.and_then(|info| info.due),
+        location: info.and_then(|info| info.location.clone()),
+        text: text.to_string(),
+    };
+    save_entry(outdir, &fs_utils::relative_key(outdir, pdf), &entry)
+}
+
+/// Add an archived document with its recognized text and metadata (e.g.
+/// restored from the trash) to the index of `outdir`
+pub fn add_document(outdir: &Path, document: &Document) -> Result<()> {
+    let entry = Entry {
+        modified: modified(&document.path)?,
+        checksum: Some(checksum(&document.path)?),
+        id: document.id.clone(),
+        date: document.date,
+        correspondent: document.correspondent.clone(),
+        title: document.title.clone(),
+        due: document.due,
+        location: document.location.clone(),
+        text: document.text.clone(),
+    };
+    save_entry(
+        outdir,
+        &fs_utils::relative_key(outdir, &document.path),
+        &entry,
+    )
+}
+
+/// Find all files below `dir` whose name ends with `suffix` (ignoring case),
+/// recursively, except hidden files
+fn collect_files(dir: &Path, suffix: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(dir))
+        .with_context(|| format!("Failed to read directory {dir:?}"))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            files.extend(collect_files(&path, suffix)?);
+        } else if name.to_lowercase().ends_with(suffix) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Whether a PDF is the copy for emailing of an archived PDF next to it
+fn is_email_copy(pdf: &Path) -> bool {
+    let name = pdf.file_name().unwrap_or_default().to_string_lossy();
+    name.strip_suffix(EMAIL_SUFFIX)
+        .is_some_and(|stem| pdf.with_file_name(format!("{stem}.pdf")).exists())
+}
+
+/// Find all PDFs below `dir`, recursively, except copies for emailing and
+/// hidden files
+pub fn collect_pdfs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut pdfs = collect_files(dir, ".pdf")?;
+    pdfs.retain(|pdf| !is_email_copy(pdf));
+    Ok(pdfs)
+}
+
+/// Remove a PDF from the index of `outdir`, if it is indexed
+pub fn remove(outdir: &Path, pdf: &Path) -> Result<()> {
+    let path = entry_path(outdir, &fs_utils::relative_key(outdir, pdf));
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove search index entry {path:?}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Extract the text of a PDF with `pdftotext`
+pub fn extract_text(pdf: &Path) -> Result<String> {
+    let output = Command::new("pdftotext")
+        .arg(pdf)
+        .arg("-")
+        .output()
+        .context("Failed to run `pdftotext`")?;
+    if !output.status.success() {
+        warn!(
+            "pdftotext failed with status {}. Stderr: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        return Err(anyhow!("Failed to extract the text of {pdf:?}"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Changes made by [`update_index`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IndexUpdate {
+    /// Number of indexed PDFs
+    pub documents: usize,
+    /// Number of new or modified PDFs whose text was extracted
+    pub updated: usize,
+    /// Number of deleted PDFs that were dropped
+    pub removed: usize,
+}
+
+/// Bring the index of `outdir` up to date with the PDFs in it
+///
+/// The text of new and modified PDFs is extracted with `pdftotext`. Without
+/// it (or if the extraction fails), new PDFs are indexed without text, so
+/// that they are only found by name, and the extraction is tried again by the
+/// next update.
+pub fn update_index(outdir: &Path) -> Result<IndexUpdate> {
+    let mut index = load_index(outdir)?;
+    let pdfs = collect_pdfs(outdir)?;
+    let mut update = IndexUpdate {
+        documents: pdfs.len(),
+        ..Default::default()
+    };
+    let can_extract = tools::is_installed("pdftotext");
+
+    let progress = ui::progress_bar(pdfs.len() as u64).with_message("Indexing documents");
+    for pdf in pdfs {
+        let key = fs_utils::relative_key(outdir, &pdf);
+        let modified = modified(&pdf)?;
+        let known = index.remove(&key);
+        if known
+            .as_ref()
+            .is_some_and(|entry| entry.modified == modified)
+        {
+            progress.inc(1);
+            continue;
+        }
+        let text = if can_extract {
+            extract_text(&pdf).inspect_err(|e| warn!("{e:#}")).ok()
+        } else {
+            tools::warn_missing("pdftotext", "indexing new PDFs only by their name");
+            None
+        };
+        match text {
+            Some(text) => {
+                // Keep the ID and metadata of documents that were archived by
+                // arkivisto
+                let entry = Entry {
+                    modified,
+                    checksum: Some(checksum(&pdf)?),
+                    text,
+                    ..known.unwrap_or_default()
+                };
+                save_entry(outdir, &key, &entry)?;
+                update.updated += 1;
+            }
+            None if known.is_none() => {
+                let entry = Entry {
+                    checksum: Some(checksum(&pdf)?),
+                    ..Default::default()
+                };
+                save_entry(outdir, &key, &entry)?;
+            }
+            None => {}
+        }
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+
+    // Drop the entries of deleted PDFs
+    update.removed = index.len();
+    for key in index.keys() {
+        let path = entry_path(outdir, key);
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove search index entry {path:?}"))?;
+    }
+    Ok(update)
+}
+
+/// Index a PDF of `outdir` anew (e.g. after it was changed outside of
+/// arkivisto), keeping the ID and metadata of its entry
+///
+/// If the text cannot be extracted, the known text is kept and extracted
+/// again by the next update.
+pub fn reindex(outdir: &Path, pdf: &Path) -> Result<()> {
+    let key = fs_utils::relative_key(outdir, pdf);
+    let known = load_index(outdir)?.remove(&key).unwrap_or_default();
+    let text = if tools::is_installed("pdftotext") {
+        extract_text(pdf).inspect_err(|e| warn!("{e:#}")).ok()
+    } else {
+        tools::warn_missing("pdftotext", "keeping the indexed text");
+        None
+    };
+    let entry = match text {
+        Some(text) => Entry {
+            modified: modified(pdf)?,
+            checksum: Some(checksum(pdf)?),
+            text,
+            ..known
+        },
+        None => Entry {
+            modified: 0,
+            checksum: Some(checksum(pdf)?),
+            ..known
+        },
+    };
+    save_entry(outdir, &key, &entry)
+}
+
+/// Recorded checksums of the PDFs in the index of `outdir`, by path relative
+/// to `outdir` (`None` for entries indexed without checksum)
+pub fn checksums(outdir: &Path) -> Result<BTreeMap<String, Option<String>>> {
+    Ok(load_index(outdir)?
+        .into_iter()
+        .map(|(key, entry)| (key, entry.checksum))
+        .collect())
+}
+
+/// An archived document with its metadata, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub path: PathBuf,
+    pub id: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub correspondent: Option<String>,
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    /// Expiry or follow-up date
+    pub due: Option<NaiveDate>,
+    /// Where the paper original is kept
+    pub location: Option<String>,
+    /// Recognized text, empty if it is not known
+    pub text: String,
+}
+
+/// All documents in the index of `outdir` (see [`update_index`]), ordered by
+/// path
+pub fn documents(outdir: &Path) -> Result<Vec<Document>> {
+    let mut tags = tags::load_index(outdir)?;
+    Ok(load_index(outdir)?
+        .into_iter()
+        .map(|(key, entry)| Document {
+            path: outdir.join(&key),
+            id: entry.id,
+            date: entry.date,
+            correspondent: entry.correspondent,
+            title: entry.title,
+            tags: tags.remove(&key).unwrap_or_default(),
+            due: entry.due,
+            location: entry.location,
+            text: entry.text,
+        })
+        .collect())
+}
+
+/// An archived document found by a search
+#[derive(Debug, PartialEq)]
+pub struct Hit {
+    pub path: PathBuf,
+    /// Stable ID of the document, if it was archived by arkivisto
+    pub id: Option<String>,
+    /// Text around the first match in the text of the document
+    pub snippet: Option<String>,
+    /// Where the paper original is kept, if it was recorded
+    pub location: Option<String>,
+    score: usize,
+}
+
+/// Search the index of `outdir` for documents that contain all words of the
+/// query (in their text, path, tags, ID or location), best matches first
+///
+/// Words are matched case-insensitively, also as part of longer words.
+pub fn search(outdir: &Path, query: &str) -> Result<Vec<Hit>> {
+    let index = load_index(outdir)?;
+    let tags = tags::load_index(outdir).unwrap_or_else(|e| {
+        warn!("{e:#}");
+        BTreeMap::new()
+    });
+    // The IDs in the QR labels are found by their content as well
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(fold_case)
+        .map(|word| match word.strip_prefix(labels::ID_PREFIX) {
+            Some(id) => id.to_string(),
+            None => word,
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut hits = Vec::new();
+    for (key, entry) in &index {
+        let text = fold_case(&entry.text);
+        let mut metadata = fold_case(key);
+        for value in [
+            &entry.id,
+            &entry.correspondent,
+            &entry.title,
+            &entry.location,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            metadata.push(' ');
+            metadata.push_str(&fold_case(value));
+        }
+        for tag in tags.get(key).into_iter().flatten() {
+            metadata.push(' ');
+            metadata.push_str(&fold_case(tag));
+        }
+        let mut score = 0;
+        for word in &words {
+            let in_text = text.matches(word.as_str()).count();
+            let in_metadata = metadata.contains(word.as_str());
+            if in_text == 0 && !in_metadata {
+                score = 0;
+                break;
+            }
+            // Matches in the name or tags weigh more than in the text
+            score += in_text + if in_metadata { 10 } else { 0 };
+        }
+        if score > 0 {
+            hits.push(Hit {
+                path: outdir.join(key),
+                id: entry.id.clone(),
+                snippet: snippet(&entry.text, &words),
+                location: entry.location.clone(),
+                score,
+            });
+        }
+    }
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    debug!("Found {} document(s) for {query:?}", hits.len());
+    Ok(hits)
+}
+
+/// Convert a text to lowercase for matching, character by character (so that
+/// matches can be mapped back to the original text)
+fn fold_case(text: &str) -> String {
+    text.chars().flat_map(char::to_lowercase).collect()
+}
+
+/// Text around the first match of any of the `words` (see [`fold_case`]), on
+/// a single line
+fn snippet(text: &str, words: &[String]) -> Option<String> {
+    let chars: Vec<char> = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .collect();
+    // The lowercase characters, with the index of the original character
+    let (folded, origins): (Vec<char>, Vec<usize>) = chars
+        .iter()
+        .enumerate()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |folded| (folded, i)))
+        .unzip();
+    let (start, end) = words
+        .iter()
+        .filter_map(|word| {
+            let word: Vec<char> = word.chars().collect();
+            folded
+                .windows(word.len())
+                .position(|window| window == word.as_slice())
+                .map(|start| (origins[start], origins[start + word.len() - 1] + 1))
+        })
+        .min()?;
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = (end + SNIPPET_CONTEXT).min(chars.len());
+    Some(format!(
+        "{}{}{}",
+        if from > 0 { "…" } else { "" },
+        chars[from..to].iter().collect::<String>(),
+        if to < chars.len() { "…" } else { "" },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn paths_found(outdir: &Path, query: &str) -> Vec<PathBuf> {
+        search(outdir, query)
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.path)
+            .collect()
+    }
+
+    /// Ensure that snippets show the first match with some context, on a
+    /// single line.
+    #[test]
+    fn snippets() {
+        let words =
+            |query: &str| -> Vec<String> { query.split_whitespace().map(fold_case).collect() };
+        assert_eq!(
+            snippet("Your insurance\npolicy for 2023", &words("policy")),
+            Some("Your insurance policy for 2023".into())
+        );
+        let long = format!("{} Insurance policy {}", "a".repeat(50), "b".repeat(50));
+        assert_eq!(
+            snippet(&long, &words("insurance")),
+            Some(format!(
+                "…{} Insurance policy {}…",
+                "a".repeat(39),
+                "b".repeat(32)
+            ))
+        );
+        assert_eq!(snippet("Invoice", &words("policy")), None);
+
+        // Characters whose lowercase form is longer are matched as well
+        assert_eq!(
+            snippet("Adres: İstanbul", &words("İSTANBUL")),
+            Some("Adres: İstanbul".into())
+        );
+        assert_eq!(
+            snippet("Adres: İstanbul", &words("i̇stanbul")),
+            Some("Adres: İstanbul".into())
+        );
+    }
+
+    /// Ensure that entries are stored with their header, and that the text may
+    /// contain empty lines.
+    #[test]
+    fn entries() {
+        let mut entry = Entry {
+            modified: 1_700_000_000,
+            text: "Page 1\n\nPage 2".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            entry.to_content(),
+            "modified: 1700000000\n\nPage 1\n\nPage 2"
+        );
+        assert_eq!(Entry::parse(&entry.to_content()).unwrap(), entry);
+        entry.id = Some("01JABCDEFGHJKMNPQRSTVWXYZ0".into());
+        assert_eq!(
+            entry.to_content(),
+            "modified: 1700000000\nid: 01JABCDEFGHJKMNPQRSTVWXYZ0\n\nPage 1\n\nPage 2"
+        );
+        assert_eq!(Entry::parse(&entry.to_content()).unwrap(), entry);
+        entry.date = NaiveDate::from_ymd_opt(2025, 5, 30);
+        entry.correspondent = Some("Muster AG".into());
+        entry.title = Some("Invoice".into());
+        entry.due = NaiveDate::from_ymd_opt(2025, 6, 30);
+        entry.location = Some("Binder 3".into());
+        assert_eq!(Entry::parse(&entry.to_content()).unwrap(), entry);
+
+        // Line breaks in values would end the header
+        entry.title = Some("Invoice\nMay".into());
+        assert_eq!(
+            Entry::parse(&entry.to_content()).unwrap().title.as_deref(),
+            Some("Invoice May")
+        );
+        assert_eq!(Entry::parse("").unwrap(), Entry::default());
+        assert!(Entry::parse("garbage").is_err());
+    }
+
+    /// Ensure that documents are found by all words of the query, in their
+    /// text, name or tags, and that matches in the name rank first.
+    #[test]
+    fn search_documents() {
+        let outdir = TempDir::new().unwrap();
+        let policy = outdir.path().join("2023/insurance-policy.pdf");
+        let invoice = outdir.path().join("2023/invoice.pdf");
+        fs::create_dir(outdir.path().join("2023")).unwrap();
+        fs::write(&policy, "%PDF").unwrap();
+        fs::write(&invoice, "%PDF").unwrap();
+        add(outdir.path(), &policy, "Policy number 42", None, None).unwrap();
+        add(
+            outdir.path(),
+            &invoice,
+            "Invoice for the insurance premium 2023",
+            Some("01JABCDEFGHJKMNPQRSTVWXYZ0"),
+            Some(&DocumentInfo {
+                date: NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(),
+                title: "Premium".into(),
+                correspondent: Some("Muster AG".into()),
+                tags: Vec::new(),
+                due: None,
+                location: Some("Binder 3".into()),
+            }),
+        )
+        .unwrap();
+        tags::record(outdir.path(), &invoice, &["Bills".into()], None).unwrap();
+
+        let paths = |query: &str| paths_found(outdir.path(), query);
+        assert_eq!(paths("insurance"), vec![policy.clone(), invoice.clone()]);
+        assert_eq!(paths("Insurance 2023 premium"), vec![invoice.clone()]);
+        assert_eq!(paths("bills"), vec![invoice.clone()]);
+        assert_eq!(paths("01jabcdefghjkmnp"), vec![invoice.clone()]);
+        let label =
+            labels::label_content(outdir.path(), &invoice, Some("01JABCDEFGHJKMNPQRSTVWXYZ0"));
+        assert_eq!(paths(&label), vec![invoice.clone()]);
+        assert!(paths(labels::ID_PREFIX).is_empty());
+        assert_eq!(paths("muster"), vec![invoice.clone()]);
+        assert_eq!(paths("binder"), vec![invoice.clone()]);
+        assert!(paths("insurance car").is_empty());
+        assert!(paths(" ").is_empty());
+
+        let hits = search(outdir.path(), "premium").unwrap();
+        assert_eq!(
+            hits[0].snippet.as_deref(),
+            Some("Invoice for the insurance premium 2023")
+        );
+        assert_eq!(hits[0].id.as_deref(), Some("01JABCDEFGHJKMNPQRSTVWXYZ0"));
+    }
+
+    /// Ensure that updating the index keeps the text of unchanged PDFs, adds
+    /// new PDFs and drops deleted ones, and skips copies for emailing. PDFs
+    /// whose text could not be extracted are only found by name, until the
+    /// extraction succeeds.
+    #[test]
+    fn update() {
+        let outdir = TempDir::new().unwrap();
+        let archived = outdir.path().join("archived.pdf");
+        let deleted = outdir.path().join("deleted.pdf");
+        let filed = outdir.path().join("filed.pdf");
+        for pdf in [&archived, &deleted, &filed] {
+            fs::write(pdf, "%PDF").unwrap();
+        }
+        fs::write(outdir.path().join("archived_email.pdf"), "%PDF").unwrap();
+        let info = DocumentInfo {
+            date: NaiveDate::from_ymd_opt(2025, 5, 30).unwrap(),
+            title: "Invoice".into(),
+            correspondent: None,
+            tags: vec!["bills".into()],
+            due: None,
+            location: None,
+        };
+        add(
+            outdir.path(),
+            &archived,
+            "Recognized text",
+            None,
+            Some(&info),
+        )
+        .unwrap();
+        add(outdir.path(), &deleted, "Old text", None, None).unwrap();
+        tags::record(outdir.path(), &archived, &info.tags, None).unwrap();
+        fs::remove_file(&deleted).unwrap();
+
+        let update = update_index(outdir.path()).unwrap();
+        assert_eq!(update.documents, 2);
+        assert_eq!(update.removed, 1);
+        let index = load_index(outdir.path()).unwrap();
+        assert_eq!(
+            index.keys().collect::<Vec<_>>(),
+            vec!["archived.pdf", "filed.pdf"]
+        );
+        assert_eq!(index["archived.pdf"].text, "Recognized text");
+        assert_eq!(paths_found(outdir.path(), "filed"), vec![filed.clone()]);
+
+        // The fake PDF has no text to extract
+        assert_eq!(
+            index["filed.pdf"],
+            Entry {
+                checksum: Some(checksum(&filed).unwrap()),
+                ..Default::default()
+            }
+        );
+        assert!(!entry_path(outdir.path(), "deleted.pdf").exists());
+
+        // Documents are listed with the metadata entered when archiving them
+        let documents = documents(outdir.path()).unwrap();
+        assert_eq!(
+            documents[0],
+            Document {
+                path: archived,
+                date: Some(info.date),
+                title: Some("Invoice".into()),
+                tags: vec!["bills".into()],
+                text: "Recognized text".into(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(documents[1].title, None);
+
+        // Removed PDFs are dropped from the index
+        remove(outdir.path(), &deleted).unwrap();
+        remove(outdir.path(), &filed).unwrap();
+        assert!(!entry_path(outdir.path(), "filed.pdf").exists());
+    }
+
+    /// Ensure that only copies for emailing next to their archived PDF are
+    /// skipped, and not PDFs whose name merely ends like them.
+    #[test]
+    fn email_copies() {
+        let outdir = TempDir::new().unwrap();
+        for name in ["invoice.pdf", "invoice_email.pdf", "newsletter_email.pdf"] {
+            fs::write(outdir.path().join(name), "%PDF").unwrap();
+        }
+        let mut pdfs = collect_pdfs(outdir.path()).unwrap();
+        pdfs.sort();
+        assert_eq!(
+            pdfs,
+            vec![
+                outdir.path().join("invoice.pdf"),
+                outdir.path().join("newsletter_email.pdf"),
+            ]
+        );
+    }
+}
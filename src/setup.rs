@@ -0,0 +1,370 @@
+//! Interactive creation of the config file, with the scanners detected by
+//! SANE
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use toml_edit::{Array, DocumentMut, Item, Value};
+use tracing::warn;
+
+use crate::{
+    config::{self, Config, OcrConfig, Scanner, dir_slug},
+    fs_utils, prompt, sane, scan, tools, ui,
+};
+
+/// Suggested archive directory
+const DEFAULT_OUTDIR: &str = "~/Documents/Archive";
+
+/// Quote a string for TOML
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Render a scanner as a `[[scanners]]` table
+fn scanner_toml(scanner: &Scanner) -> String {
+    let mut toml = String::new();
+    let _ = writeln!(toml, "[[scanners]]");
+    let _ = writeln!(toml, "id = {}", toml_string(&scanner.id));
+    let _ = writeln!(toml, "device_name = {}", toml_string(&scanner.device_name));
+    let _ = writeln!(toml, "[scanners.sources]");
+    let sources = [
+        ("adf_single", &scanner.sources.adf_single),
+        ("adf_duplex", &scanner.sources.adf_duplex),
+        ("flatbed", &scanner.sources.flatbed),
+    ];
+    for (key, source) in sources {
+        if let Some(source) = source {
+            let _ = writeln!(toml, "{key} = {}", toml_string(source));
+        }
+    }
+    toml
+}
+
+/// Suggested ID of a scanner, based on the description of the device (e.g.
+/// "hp-scanjet-flow-n7000" for "eSCL HP ScanJet Flow N7000 ip=192.168.1.5")
+fn suggested_id(device: &sane::Device) -> String {
+    let description = device.description.split(" ip=").next().unwrap_or_default();
+    let description = description
+        .strip_prefix("eSCL ")
+        .or_else(|| description.strip_prefix("WSD "))
+        .unwrap_or(description);
+    let id = dir_slug(description);
+    if id.is_empty() {
+        dir_slug(&device.name)
+    } else {
+        id
+    }
+}
+
+/// Ask which of the detected devices to configure, and detect their sources
+///
+/// Devices of the `configured` scanners are skipped, and their IDs cannot be
+/// reused.
+fn detect_scanners(configured: &[Scanner]) -> Result<Vec<Scanner>> {
+    let devices = sane::list_devices()?;
+    let mut scanners: Vec<Scanner> = Vec::new();
+    for device in devices {
+        if configured
+            .iter()
+            .any(|scanner| scanner.device_name == device.name)
+        {
+            continue;
+        }
+        let add = prompt::Confirm::new(&format!("Add {device}?"))
+            .with_default(true)
+            .prompt()?;
+        if !add {
+            continue;
+        }
+        let taken: Vec<String> = configured
+            .iter()
+            .chain(&scanners)
+            .map(|scanner| scanner.id.clone())
+            .collect();
+        let id = prompt::Text::new("ID of the scanner?")
+            .with_initial_value(&suggested_id(&device))
+            .with_help_message("Used to select the scanner and in directory names")
+            .with_validator(move |input: &str| {
+                let input = input.trim();
+                if input.is_empty() {
+                    Err("Please enter an ID".into())
+                } else if taken.iter().any(|id| id == input) {
+                    Err(format!(
+                        "A scanner with ID \"{input}\" is already configured"
+                    ))
+                } else {
+                    Ok(())
+                }
+            })
+            .prompt()?;
+        let mut scanner = scan::configure_device(device)?;
+        scanner.id = id.trim().to_string();
+        scanners.push(scanner);
+    }
+    Ok(scanners)
+}
+
+/// List the external tools that are not installed, with the features that are
+/// not available without them
+fn report_missing_tools(ocr_config: &OcrConfig) {
+    let missing = tools::missing(ocr_config);
+    if missing.is_empty() {
+        return;
+    }
+    println!("Some external tools are not installed:");
+    for (program, features) in missing {
+        println!(
+            "  {}",
+            ui::warning(format!("{program} (needed for {features})"))
+        );
+    }
+}
+
+/// Create the config file interactively, with the detected scanners
+///
+/// An existing config file is only replaced after confirmation. Returns the
+/// path of the config file.
+pub fn init_config() -> Result<PathBuf> {
+    let path = config::config_path()?;
+    if path.exists() {
+        let overwrite =
+            prompt::Confirm::new(&format!("{} already exists. Replace it?", path.display()))
+                .with_default(false)
+                .with_help_message("Use `manage-scanners` to only add or remove scanners")
+                .prompt()?;
+        ensure!(overwrite, "Config file {:?} was not replaced", path);
+    }
+
+    let outdir = prompt::Text::new("Archive directory?")
+        .with_initial_value(DEFAULT_OUTDIR)
+        .with_help_message("Processed documents are filed into this directory")
+        .prompt()?;
+    let scanners = detect_scanners(&[]).unwrap_or_else(|e| {
+        warn!("Failed to detect scanners: {e:#}");
+        Vec::new()
+    });
+    if scanners.is_empty() {
+        println!("No scanners configured. Add them later with `arkivisto manage-scanners`.");
+    }
+    let config = Config::new(PathBuf::from(outdir.trim()), scanners);
+    report_missing_tools(&config.ocr);
+    config.save()
+}
+
+/// Remove the scanners with the given IDs from a config file, and append the
+/// `added` scanners
+///
+/// The rest of the file, including comments and formatting, is kept.
+fn update_scanners(content: &str, removed: &[&str], added: &[Scanner]) -> Result<String> {
+    let mut document: DocumentMut = content.parse().context("Failed to parse config file")?;
+    match document.get_mut("scanners") {
+        Some(Item::ArrayOfTables(scanners)) => scanners.retain(|scanner| {
+            !scanner
+                .get("id")
+                .and_then(Item::as_str)
+                .is_some_and(|id| removed.contains(&id))
+        }),
+        Some(Item::Value(Value::Array(scanners))) => ensure!(
+            scanners.is_empty(),
+            "Scanners in inline tables are not supported, please edit the config file manually"
+        ),
+        _ => {}
+    }
+
+    // New scanners are appended as `[[scanners]]` tables, which requires that
+    // no (empty) inline array remains
+    let has_tables = document
+        .get("scanners")
+        .and_then(Item::as_array_of_tables)
+        .is_some_and(|scanners| !scanners.is_empty());
+    if !has_tables {
+        document.remove("scanners");
+        if added.is_empty() {
+            document["scanners"] = toml_edit::value(Array::new());
+        }
+    }
+    let mut content = document.to_string();
+    for scanner in added {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push('\n');
+        content.push_str(&scanner_toml(scanner));
+    }
+    Ok(content)
+}
+
+/// Add newly detected scanners to the existing config file, or remove
+/// configured ones, without changing the rest of the configuration
+pub fn manage_scanners() -> Result<()> {
+    let path = config::config_path()?;
+    ensure!(
+        path.exists(),
+        "Config file {:?} does not exist, create it with `arkivisto init-config`",
+        path
+    );
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {:?}", path))?;
+    let config: Config = toml::from_str(&content).context("Failed to parse config file")?;
+
+    let removed = if config.scanners.is_empty() {
+        Vec::new()
+    } else {
+        prompt::MultiSelect::new("Remove scanners?", config.scanners.clone())
+            .with_help_message("Select retired scanners, or none to keep all")
+            .prompt()?
+    };
+    let removed: Vec<&str> = removed.iter().map(|scanner| scanner.id.as_str()).collect();
+    let remaining: Vec<Scanner> = config
+        .scanners
+        .iter()
+        .filter(|scanner| !removed.contains(&scanner.id.as_str()))
+        .cloned()
+        .collect();
+    let added = detect_scanners(&remaining)?;
+    if removed.is_empty() && added.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+
+    write_config(&path, &update_scanners(&content, &removed, &added)?)?;
+    println!(
+        "Removed {} and added {} scanner(s) in {}",
+        removed.len(),
+        added.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Validate a config and write it to `path`
+fn write_config(path: &Path, content: &str) -> Result<()> {
+    toml::from_str::<Config>(content).context("The generated config is invalid")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    fs_utils::write_synced(path, content)
+        .with_context(|| format!("Failed to write config file {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Geometry, ScannerBackend, ScannerSources, VirtualPages};
+
+    fn scanner(id: &str, flatbed: Option<&str>) -> Scanner {
+        Scanner {
+            id: id.into(),
+            device_name: format!("airscan:e1:{id}"),
+            backend: ScannerBackend::Sane,
+            virtual_pages: VirtualPages::default(),
+            max_paper: None,
+            geometry: Geometry::default(),
+            lock_file: None,
+            additional_args: Vec::new(),
+            sources: ScannerSources {
+                adf_single: Some("ADF".into()),
+                adf_duplex: Some("ADF \"Duplex\"".into()),
+                flatbed: flatbed.map(String::from),
+            },
+        }
+    }
+
+    /// Ensure that the generated scanner tables can be loaded, with their
+    /// sources.
+    #[test]
+    fn scanner_tables() {
+        let content = format!(
+            "outdir = \"/archive\"\n{}{}",
+            scanner_toml(&scanner("office", None)),
+            scanner_toml(&scanner("home", Some("Flatbed")))
+        );
+        let config: Config = toml::from_str(&content).unwrap();
+        assert_eq!(config.scanners.len(), 2);
+        assert_eq!(config.scanners[0].id, "office");
+        assert_eq!(config.scanners[0].device_name, "airscan:e1:office");
+        assert_eq!(
+            config.scanners[0].sources.adf_duplex.as_deref(),
+            Some("ADF \"Duplex\"")
+        );
+        assert_eq!(config.scanners[0].sources.flatbed, None);
+        assert_eq!(
+            config.scanners[1].sources.flatbed.as_deref(),
+            Some("Flatbed")
+        );
+    }
+
+    /// Ensure that scanners are added and removed without changing the rest
+    /// of the config file.
+    #[test]
+    fn updated_scanners() {
+        let content = r#"# My archive
+outdir = "~/Archive"
+
+[[scanners]]
+id = "office" # Second floor
+device_name = "airscan:e1:office"
+[scanners.sources]
+adf_single = "ADF"
+
+[[scanners]]
+id = "old"
+device_name = "airscan:e1:old"
+[scanners.sources]
+flatbed = "Flatbed"
+
+[processing]
+deskew = true
+"#;
+        let updated =
+            update_scanners(content, &["old"], &[scanner("home", Some("Flatbed"))]).unwrap();
+        assert!(updated.starts_with("# My archive\n"));
+        assert!(updated.contains("id = \"office\" # Second floor\n"));
+        let config: Config = toml::from_str(&updated).unwrap();
+        let ids: Vec<&str> = config.scanners.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["office", "home"]);
+        assert_eq!(
+            config.scanners[1].sources.flatbed.as_deref(),
+            Some("Flatbed")
+        );
+        assert!(config.processing.deskew);
+
+        // Removing all scanners leaves an empty list
+        let updated = update_scanners(&updated, &["office", "home"], &[]).unwrap();
+        let config: Config = toml::from_str(&updated).unwrap();
+        assert!(config.scanners.is_empty());
+        assert!(config.processing.deskew);
+
+        // Scanners are added to an empty list
+        let updated = update_scanners(&updated, &[], &[scanner("office", None)]).unwrap();
+        let config: Config = toml::from_str(&updated).unwrap();
+        assert_eq!(config.scanners.len(), 1);
+    }
+
+    /// Ensure that scanner IDs are derived from the model in the description.
+    #[test]
+    fn scanner_ids() {
+        let device = |name: &str, description: &str| sane::Device {
+            name: name.into(),
+            description: description.into(),
+        };
+        assert_eq!(
+            suggested_id(&device(
+                "airscan:e1:HP ScanJet Flow N7000 snw1",
+                "eSCL HP ScanJet Flow N7000 snw1 ip=192.168.1.5"
+            )),
+            "hp-scanjet-flow-n7000-snw1"
+        );
+        assert_eq!(
+            suggested_id(&device("fujitsu:fi-7160:1234", "FUJITSU fi-7160 scanner")),
+            "fujitsu-fi-7160-scanner"
+        );
+        assert_eq!(suggested_id(&device("test:0", "")), "test-0");
+    }
+}
@@ -0,0 +1,154 @@
+//! Copies of documents stamped with a watermark (e.g. "Copy for Insurance AG,
+//! 2025-06-01"), to share a document without handing out the archived copy
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Result;
+use tracing::debug;
+
+use crate::{config::Config, extract, fs_utils, magick};
+
+/// Size of the rendered watermark page (A4 at 150 dpi), which qpdf scales to
+/// the size of every page
+const STAMP_SIZE: (u32, u32) = (1240, 1754);
+
+/// Render the watermark template for a recipient and date
+fn render_watermark(template: &str, recipient: &str, date: &str) -> String {
+    template
+        .replace("{recipient}", recipient)
+        .replace("{date}", date)
+}
+
+/// Escape text for `-annotate`, which would otherwise expand `%` escapes,
+/// read the text from a file if it starts with `@`, and interpret backslashes
+fn escape_annotation(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '%' => escaped.push_str("%%"),
+            '@' if i == 0 => escaped.push_str("\\@"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// ImageMagick arguments that render a transparent page with the watermark
+/// diagonally across it
+fn stamp_args(text: &str) -> Vec<String> {
+    let (width, height) = STAMP_SIZE;
+    vec![
+        "-size".into(),
+        format!("{width}x{height}"),
+        "xc:none".into(),
+        "-fill".into(),
+        "rgba(200,0,0,0.3)".into(),
+        "-gravity".into(),
+        "center".into(),
+        "-pointsize".into(),
+        "64".into(),
+        "-annotate".into(),
+        "-55x-55+0+0".into(),
+        escape_annotation(text),
+    ]
+}
+
+/// File name of a copy, based on the document name and the recipient
+fn default_output(document: &Path, recipient: &str) -> Result<PathBuf> {
+    let name = extract::document_name(document)?;
+    let recipient: String = recipient
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    Ok(env::current_dir()?.join(format!("{name}-{recipient}.pdf")))
+}
+
+/// Create a copy of a document with the configured watermark across every
+/// page, return the path of the copy
+pub fn share(
+    document: &Path,
+    recipient: &str,
+    output: Option<&Path>,
+    config: &Config,
+) -> Result<PathBuf> {
+    let document = extract::document_pdf(document)?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => default_output(&document, recipient)?,
+    };
+    let date = config.scan.timezone.now().format("%Y-%m-%d").to_string();
+    let text = render_watermark(&config.share.watermark, recipient, &date);
+    debug!("Stamping {document:?} with {text:?}");
+
+    let work_dir = env::temp_dir().join(format!("arkivisto-share-{}", std::process::id()));
+    fs_utils::ensure_empty_dir_exists(&work_dir)?;
+    let result = (|| {
+        let stamp = work_dir.join("stamp.pdf");
+        extract::run(magick::command().args(stamp_args(&text)).arg(&stamp))?;
+        extract::run(
+            Command::new("qpdf")
+                .arg(&document)
+                .arg("--overlay")
+                .arg(&stamp)
+                .arg("--repeat=1")
+                .arg("--")
+                .arg(&output),
+        )
+    })();
+    if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+        debug!("Failed to remove {work_dir:?}: {e}");
+    }
+    result?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the recipient and date are filled into the template.
+    #[test]
+    fn watermark_template() {
+        assert_eq!(
+            render_watermark("Copy for {recipient}, {date}", "Insurance AG", "2025-06-01"),
+            "Copy for Insurance AG, 2025-06-01"
+        );
+        assert_eq!(render_watermark("COPY", "X", "2025-06-01"), "COPY");
+    }
+
+    /// Ensure that the watermark text is passed as a single argument.
+    #[test]
+    fn stamp_arguments() {
+        let args = stamp_args("Copy for X, 2025-06-01");
+        assert_eq!(args.last().unwrap(), "Copy for X, 2025-06-01");
+        assert_eq!(args[1], "1240x1754");
+    }
+
+    /// Ensure that ImageMagick escapes in the watermark text are rendered
+    /// literally.
+    #[test]
+    fn stamp_escapes() {
+        let args = stamp_args("@/etc/passwd 100% C:\\x");
+        assert_eq!(args.last().unwrap(), "\\@/etc/passwd 100%% C:\\\\x");
+        assert_eq!(escape_annotation("a@b"), "a@b");
+    }
+
+    /// Ensure that the copy is named after the document and the recipient.
+    #[test]
+    fn output_name() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            default_output(Path::new("/scans/20250601-ABC/_final.pdf"), "Insurance AG").unwrap(),
+            cwd.join("20250601-ABC-Insurance_AG.pdf")
+        );
+        assert_eq!(
+            default_output(Path::new("contract.pdf"), "Bank").unwrap(),
+            cwd.join("contract-Bank.pdf")
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Splitting of an archived PDF that contains several documents (e.g. from a
+//! bulk scan with a missing separator sheet) into separate documents
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use tracing::{debug, warn};
+use ulid::Ulid;
+
+use crate::{
+    archive, audit,
+    config::{Config, Timezone},
+    date_detect, extract, fs_utils, history, links,
+    manifest::Manifest,
+    naming::DocumentInfo,
+    notes, prompt, scan, search, tags, tools, verify,
+};
+
+/// Suffix of the copies for emailing, which are removed with the original
+const EMAIL_SUFFIX: &str = "_email.pdf";
+
+/// Parse the page ranges of the parts of a document with `page_count` pages,
+/// return the page numbers of every part
+///
+/// Every page must be part of exactly one part, so that no page is lost.
+fn parse_parts(ranges: &[String], page_count: u32) -> Result<Vec<Vec<u32>>> {
+    let parts = ranges
+        .iter()
+        .map(|range| extract::parse_pages(range, page_count))
+        .collect::<Result<Vec<_>>>()?;
+    for page in 1..=page_count {
+        let count = parts.iter().flatten().filter(|p| **p == page).count();
+        ensure!(count > 0, "Page {page} is not part of any range");
+        ensure!(count == 1, "Page {page} is part of several ranges");
+    }
+    Ok(parts)
+}
+
+/// A part of a document, with its metadata
+struct Part {
+    pdf: PathBuf,
+    text: String,
+    info: DocumentInfo,
+}
+
+/// Extract the parts of `document` into `work_dir` and ask for their
+/// metadata, return `None` if the user skips one of them
+fn prepare_parts(
+    document: &Path,
+    original: &search::Document,
+    ranges: &[String],
+    work_dir: &Path,
+    config: &Config,
+) -> Result<Option<Vec<Part>>> {
+    let parts = parse_parts(ranges, extract::page_count(document)?)?;
+    let can_extract = tools::is_installed("pdftotext");
+    if !can_extract {
+        tools::warn_missing("pdftotext", "indexing the parts only by their name");
+    }
+    // Preselect the metadata of the original document
+    let preset = Manifest {
+        title: original.title.clone(),
+        correspondent: original.correspondent.clone(),
+        tags: original.tags.clone(),
+        ..Default::default()
+    };
+    let today = config.scan.timezone.now().date_naive();
+    let history = history::load(&scan::scans_dir()?);
+
+    let mut prepared = Vec::new();
+    for (index, (range, pages)) in ranges.iter().zip(&parts).enumerate() {
+        let spec = pages
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let pdf = work_dir.join(format!("part{}.pdf", index + 1));
+        extract::extract(document, &spec, Some(&pdf), &[])?;
+        let text = if can_extract {
+            search::extract_text(&pdf)
+                .inspect_err(|e| warn!("{e:#}"))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        println!("Part {} of {} (pages {range}):", index + 1, parts.len());
+        let detected = date_detect::candidates(&text, config.date_order(), today);
+        let Some(info) = archive::ask_document_info(
+            &preset,
+            &text,
+            &detected,
+            original.date.unwrap_or(today),
+            &history,
+        )?
+        else {
+            return Ok(None);
+        };
+        prepared.push(Part { pdf, text, info });
+    }
+    Ok(Some(prepared))
+}
+
+/// Split an archived PDF into parts with the given page ranges (e.g. `1-2`
+/// and `3-5`), and archive every part as a document of its own, return the
+/// paths of the archived parts
+///
+/// The metadata of every part is asked first, preselecting the metadata of
+/// the original document. Only then the parts are archived and the original
+/// (with its copy for emailing) is removed from the archive and its indexes.
+/// Returns no paths if the user skips a part, leaving the archive unchanged.
+///
+/// Requires `qpdf`.
+pub fn split(document: &Path, ranges: &[String], config: &Config) -> Result<Vec<PathBuf>> {
+    ensure!(ranges.len() >= 2, "At least two page ranges are needed");
+    let document = fs::canonicalize(document)
+        .with_context(|| format!("Document {document:?} does not exist"))?;
+    let outdir = fs::canonicalize(&config.outdir)
+        .with_context(|| format!("Archive directory {:?} does not exist", config.outdir))?;
+    if !document.starts_with(&outdir) {
+        bail!("{document:?} is not in the archive directory {outdir:?}");
+    }
+    let key = fs_utils::relative_key(&outdir, &document);
+    let original = search::documents(&config.outdir)?
+        .into_iter()
+        .find(|known| fs_utils::relative_key(&config.outdir, &known.path) == key)
+        .unwrap_or_default();
+    if prompt::is_interactive()
+        && let Err(e) = verify::open_file(&document)
+    {
+        warn!("Failed to open {document:?}: {e:#}");
+    }
+
+    let work_dir = env::temp_dir().join(format!("arkivisto-split-{}", std::process::id()));
+    fs_utils::ensure_empty_dir_exists(&work_dir)?;
+    let result = (|| {
+        let Some(parts) = prepare_parts(&document, &original, ranges, &work_dir, config)? else {
+            return Ok(Vec::new());
+        };
+        let mut archived = Vec::new();
+        for part in parts {
+            let id = Ulid::from_datetime(SystemTime::now()).to_string();
+            archived.push(archive::file_pdf(
+                &part.pdf,
+                &part.text,
+                &part.info,
+                &id,
+                audit::Action::Split,
+                config,
+            )?);
+        }
+        remove_original(
+            &document,
+            &config.outdir,
+            config.archive.notes_dir.as_deref(),
+            &archived,
+            config.scan.timezone,
+        )?;
+        Ok(archived)
+    })();
+    if let Err(e) = fs::remove_dir_all(&work_dir) {
+        debug!("Failed to remove {work_dir:?}: {e}");
+    }
+    result
+}
+
+/// Remove a document that was split into `parts` (with its copy for emailing)
+/// from the archive in `outdir`, its indexes and its note in `notes_dir`, and
+/// record the removal in the audit log
+fn remove_original(
+    document: &Path,
+    outdir: &Path,
+    notes_dir: Option<&Path>,
+    parts: &[PathBuf],
+    timezone: Timezone,
+) -> Result<()> {
+    let stem = document.file_stem().unwrap_or_default().to_string_lossy();
+    let email_copy = document.with_file_name(format!("{stem}{EMAIL_SUFFIX}"));
+    for path in [document, &email_copy] {
+        if path.exists() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {path:?}"))?;
+        }
+    }
+    let outdir = fs::canonicalize(outdir).unwrap_or_else(|_| outdir.to_path_buf());
+    if let Err(e) = search::remove(&outdir, document) {
+        warn!("Failed to remove {document:?} from the search index: {e:#}");
+    }
+    if let Err(e) = tags::remove(&outdir, document) {
+        warn!("Failed to remove {document:?} from the tag index: {e:#}");
+    }
+    if let Err(e) = links::remove(&outdir, document) {
+        warn!("Failed to remove the links of {document:?}: {e:#}");
+    }
+    if let Some(notes_dir) = notes_dir
+        && let Err(e) = notes::remove(notes_dir, &outdir, document)
+    {
+        warn!("Failed to remove the note of {document:?}: {e:#}");
+    }
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|part| fs_utils::relative_key(&outdir, part))
+        .collect();
+    let event = audit::Event::new(audit::Action::Split, &outdir, document, timezone)
+        .with_details(format!("into {}", parts.join(", ")));
+    audit::record(&outdir, &event);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that the parts must cover every page exactly once.
+    #[test]
+    fn parts() {
+        let ranges = |ranges: &[&str]| -> Vec<String> {
+            ranges.iter().map(|range| range.to_string()).collect()
+        };
+        assert_eq!(
+            parse_parts(&ranges(&["1-2", "3,5", "4"]), 5).unwrap(),
+            vec![vec![1, 2], vec![3, 5], vec![4]]
+        );
+        assert!(parse_parts(&ranges(&["1-2", "4-5"]), 5).is_err());
+        assert!(parse_parts(&ranges(&["1-3", "3-5"]), 5).is_err());
+        assert!(parse_parts(&ranges(&["1-3", "4-6"]), 5).is_err());
+    }
+
+    /// Ensure that the original is removed with its copy for emailing, its
+    /// index entries and its note.
+    #[test]
+    fn remove_split_document() {
+        let outdir = TempDir::new().unwrap();
+        let document = outdir.path().join("scans.pdf");
+        let email_copy = outdir.path().join("scans_email.pdf");
+        fs::write(&document, "%PDF").unwrap();
+        fs::write(&email_copy, "%PDF").unwrap();
+        search::add(outdir.path(), &document, "Two letters", None, None).unwrap();
+        tags::record(outdir.path(), &document, &["letters".into()], Some("01JAB")).unwrap();
+        let notes_dir = outdir.path().join("notes");
+        let info = DocumentInfo {
+            date: NaiveDate::from_ymd_opt(2025, 5, 30).unwrap(),
+            title: "Letters".into(),
+            correspondent: None,
+            tags: vec!["letters".into()],
+            due: None,
+            location: None,
+        };
+        let note = notes::write(&notes_dir, outdir.path(), &document, "", &info, None).unwrap();
+
+        let parts = [outdir.path().join("a.pdf"), outdir.path().join("b.pdf")];
+        remove_original(
+            &document,
+            outdir.path(),
+            Some(&notes_dir),
+            &parts,
+            Timezone::Utc,
+        )
+        .unwrap();
+        assert!(!document.exists());
+        assert!(!note.exists());
+        assert!(!email_copy.exists());
+        assert!(search::documents(outdir.path()).unwrap().is_empty());
+        assert!(tags::load_index(outdir.path()).unwrap().is_empty());
+        let events = audit::load(outdir.path(), &[]).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, audit::Action::Split);
+        assert_eq!(events[0].path, "scans.pdf");
+        assert_eq!(events[0].details.as_deref(), Some("into a.pdf, b.pdf"));
+    }
+}
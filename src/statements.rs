@@ -0,0 +1,371 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+
+use crate::config::DateOrder;
+
+/// Name of the CSV file with the extracted transactions
+pub const TRANSACTIONS_CSV: &str = "_transactions.csv";
+
+/// Keywords (lowercase) that indicate a bank or credit card statement
+const KEYWORDS: [&str; 7] = [
+    "kontoauszug",
+    "account statement",
+    "bank statement",
+    "statement of account",
+    "credit card statement",
+    "kreditkartenabrechnung",
+    "relevé de compte",
+];
+
+/// Minimal number of transactions in a statement
+const MIN_TRANSACTIONS: usize = 3;
+
+/// A single row of a statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// Booking date in ISO 8601 notation (e.g. `2024-12-31`), or as printed
+    /// on the statement if it doesn't contain a year
+    pub date: String,
+    /// Booking text
+    pub description: String,
+    /// Amount, normalized to a `.` as decimal separator (e.g. `-1234.50`)
+    pub amount: String,
+    /// Balance after the transaction, if printed
+    pub balance: Option<String>,
+}
+
+/// Extract the transactions of a bank or credit card statement from the
+/// recognized text
+///
+/// Returns `None` if the text doesn't look like a statement. Dates are read in
+/// the given order of day, month and year.
+pub fn extract_statement(text: &str, date_order: DateOrder) -> Option<Vec<Transaction>> {
+    let lowercase = text.to_lowercase();
+    if !KEYWORDS.iter().any(|keyword| lowercase.contains(keyword)) {
+        return None;
+    }
+    let transactions: Vec<Transaction> = text
+        .lines()
+        .filter_map(|line| parse_transaction(line, date_order))
+        .collect();
+    (transactions.len() >= MIN_TRANSACTIONS).then_some(transactions)
+}
+
+/// Parse a table row that starts with a date and ends with one or two
+/// amounts (the transaction amount and the balance)
+fn parse_transaction(line: &str, date_order: DateOrder) -> Option<Transaction> {
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.is_empty() || !is_date(tokens[0]) {
+        return None;
+    }
+    let date = normalize_date(tokens.remove(0), date_order);
+
+    // Skip the value date
+    if tokens.first().is_some_and(|token| is_date(token)) {
+        tokens.remove(0);
+    }
+
+    // Collect up to two amounts from the end of the line
+    let mut amounts = Vec::new();
+    while amounts.len() < 2 {
+        match tokens.last().and_then(|token| parse_amount(token)) {
+            Some(amount) => {
+                amounts.insert(0, amount);
+                tokens.pop();
+            }
+            None => break,
+        }
+    }
+    if amounts.is_empty() || tokens.is_empty() {
+        return None;
+    }
+    let mut amounts = amounts.into_iter();
+    Some(Transaction {
+        date,
+        description: tokens.join(" "),
+        amount: amounts.next()?,
+        balance: amounts.next(),
+    })
+}
+
+/// Whether a token is a date (e.g. `31.12.2024`, `31.12.24`, `31.12.`,
+/// `2024-12-31` or `12/31/2024`)
+fn is_date(token: &str) -> bool {
+    let parts: Vec<&str> = token.trim_end_matches('.').split(['.', '-', '/']).collect();
+    let all_digits = parts
+        .iter()
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    if !all_digits {
+        return false;
+    }
+    let lengths: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+    matches!(
+        lengths.as_slice(),
+        [1..=2, 1..=2] | [1..=2, 1..=2, 2] | [1..=2, 1..=2, 4] | [4, 2, 2]
+    ) && (lengths.len() == 3 || token.ends_with('.'))
+}
+
+/// Convert a date to ISO 8601 notation
+///
+/// Dates with a four digit year in front are always read as year, month, day.
+/// Dates without a year or with an invalid day or month are returned as is.
+fn normalize_date(token: &str, date_order: DateOrder) -> String {
+    let parts: Vec<&str> = token.trim_end_matches('.').split(['.', '-', '/']).collect();
+    let numbers: Vec<u32> = parts.iter().filter_map(|part| part.parse().ok()).collect();
+    let date = match (parts.as_slice(), numbers.as_slice()) {
+        ([year, ..], &[y, m, d]) if year.len() == 4 && numbers.len() == parts.len() => {
+            NaiveDate::from_ymd_opt(y as i32, m, d)
+        }
+        ([.., year], &[a, b, y]) if numbers.len() == parts.len() => {
+            let y = if year.len() == 2 { 2000 + y } else { y };
+            let (d, m) = match date_order {
+                DateOrder::Mdy => (b, a),
+                DateOrder::Dmy | DateOrder::Ymd => (a, b),
+            };
+            NaiveDate::from_ymd_opt(y as i32, m, d)
+        }
+        _ => None,
+    };
+    date.map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| token.to_string())
+}
+
+/// Parse an amount with two decimal places, e.g. `1'234.50`, `1.234,50-` or
+/// `-12.00`, return it normalized (e.g. `-1234.50`)
+pub fn parse_amount(token: &str) -> Option<String> {
+    let (negative, unsigned) = if let Some(rest) = token.strip_prefix('-') {
+        (true, rest)
+    } else if let Some(rest) = token.strip_suffix('-') {
+        (true, rest)
+    } else {
+        (false, token.strip_prefix('+').unwrap_or(token))
+    };
+
+    // The decimal separator is followed by exactly two digits
+    let separator_index = unsigned.len().checked_sub(3)?;
+    if !unsigned.is_char_boundary(separator_index) {
+        return None;
+    }
+    let (integer, fraction) = unsigned.split_at(separator_index);
+    let fraction = fraction.strip_prefix(['.', ','])?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) || integer.is_empty() {
+        return None;
+    }
+    let digits: String = integer
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '’' | '.' | ','))
+        .collect();
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let sign = if negative { "-" } else { "" };
+    Some(format!("{sign}{digits}.{fraction}"))
+}
+
+/// Quote a CSV field if needed
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format transactions as CSV
+pub fn to_csv(transactions: &[Transaction]) -> String {
+    let mut csv = String::from("date,description,amount,balance\n");
+    for transaction in transactions {
+        let fields = [
+            csv_field(&transaction.date),
+            csv_field(&transaction.description),
+            csv_field(&transaction.amount),
+            csv_field(transaction.balance.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Write transactions as CSV
+pub fn write_csv(transactions: &[Transaction], path: &Path) -> Result<()> {
+    fs::write(path, to_csv(transactions)).with_context(|| format!("Failed to write {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    const STATEMENT: &str = "\
+Example Bank AG
+Kontoauszug Nr. 12 / 2024
+IBAN CH93 0076 2011 6238 5295 7
+
+Datum Valuta Buchungstext Betrag Saldo
+Saldovortrag 1'520.35
+02.12.2024 02.12.2024 Migros Zürich -45.60 1'474.75
+05.12.2024 05.12.2024 Lohn Dezember 5'200.00 6'674.75
+10.12.2024 10.12.2024 Miete, \"Wohnung\" -1'850.00 4'824.75
+Seite 1/1";
+
+    /// Ensure that the rows of a statement are extracted.
+    #[test]
+    fn extract() {
+        let transactions = extract_statement(STATEMENT, DateOrder::Dmy).unwrap();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(
+            transactions[0],
+            Transaction {
+                date: "2024-12-02".into(),
+                description: "Migros Zürich".into(),
+                amount: "-45.60".into(),
+                balance: Some("1474.75".into()),
+            }
+        );
+        assert_eq!(transactions[2].amount, "-1850.00");
+    }
+
+    /// Ensure that other documents are not treated as statements.
+    #[test]
+    fn not_a_statement() {
+        let invoice = STATEMENT.replace("Kontoauszug", "Rechnung");
+        assert_eq!(extract_statement(&invoice, DateOrder::Dmy), None);
+        assert_eq!(extract_statement("Kontoauszug\nleer", DateOrder::Dmy), None);
+    }
+
+    /// Ensure that common date formats are recognized.
+    #[test]
+    fn dates() {
+        for date in ["31.12.2024", "31.12.24", "1.2.", "2024-12-31", "12/31/2024"] {
+            assert!(is_date(date), "{date}");
+        }
+        for token in ["31.12", "2024", "12:30", "Saldo", "1.234,50"] {
+            assert!(!is_date(token), "{token}");
+        }
+    }
+
+    /// Ensure that dates are read in the configured order and converted to
+    /// ISO 8601 notation.
+    #[test]
+    fn date_order() {
+        assert_eq!(normalize_date("02.12.2024", DateOrder::Dmy), "2024-12-02");
+        assert_eq!(normalize_date("12/02/2024", DateOrder::Mdy), "2024-12-02");
+        assert_eq!(normalize_date("02.12.24", DateOrder::Dmy), "2024-12-02");
+        assert_eq!(normalize_date("2024-12-02", DateOrder::Mdy), "2024-12-02");
+        assert_eq!(normalize_date("12/31/2024", DateOrder::Dmy), "12/31/2024");
+        assert_eq!(normalize_date("1.2.", DateOrder::Dmy), "1.2.");
+
+        let statement = "Account Statement\n\
+            12/02/2024 Grocery Store -45.60 1,474.75\n\
+            12/05/2024 Payroll 5,200.00 6,674.75\n\
+            12/10/2024 Rent -1,850.00 4,824.75";
+        let transactions = extract_statement(statement, DateOrder::Mdy).unwrap();
+        assert_eq!(transactions[2].date, "2024-12-10");
+    }
+
+    /// Ensure that amounts in different notations are normalized.
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_amount("1'234.50"), Some("1234.50".into()));
+        assert_eq!(parse_amount("1.234,50-"), Some("-1234.50".into()));
+        assert_eq!(parse_amount("-12.00"), Some("-12.00".into()));
+        assert_eq!(parse_amount("+0,05"), Some("0.05".into()));
+        assert_eq!(parse_amount("12.5"), None);
+        assert_eq!(parse_amount(".50"), None);
+        assert_eq!(parse_amount("Zürich"), None);
+        assert_eq!(parse_amount("Gebühr"), None);
+    }
+
+    /// Ensure that rows ending in a word with multibyte characters are
+    /// skipped instead of panicking.
+    #[test]
+    fn multibyte_last_token() {
+        assert_eq!(parse_transaction("01.01.2024 Gebühr", DateOrder::Dmy), None);
+        assert_eq!(
+            parse_transaction("01.01.2024 Gebühr 5.00", DateOrder::Dmy).map(|t| t.amount),
+            Some("5.00".into())
+        );
+    }
+
+    /// Ensure that fields with separators are quoted in the CSV output.
+    #[test]
+    fn csv() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join(TRANSACTIONS_CSV);
+        let transactions = extract_statement(STATEMENT, DateOrder::Dmy).unwrap();
+        write_csv(&transactions, &path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            csv.lines().nth(3),
+            Some("2024-12-10,\"Miete, \"\"Wohnung\"\"\",-1850.00,4824.75")
+        );
+    }
+
+    proptest! {
+        /// Ensure that parsing arbitrary lines never panics.
+        #[test]
+        fn parse_arbitrary_lines(line in "\\PC*") {
+            for date_order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+                parse_transaction(&line, date_order);
+            }
+        }
+
+        /// Ensure that parsing arbitrary rows that start with a date never
+        /// panics.
+        #[test]
+        fn parse_arbitrary_rows(row in "\\PC*") {
+            for date_order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+                parse_transaction(&format!("01.01.2024 {row}"), date_order);
+            }
+        }
+
+        /// Ensure that parsing arbitrary date-like tokens never panics, and
+        /// that the result is either ISO 8601 or the unchanged token.
+        #[test]
+        fn normalize_arbitrary_dates(token in "[0-9]{0,12}([./-][0-9]{0,12}){0,3}\\.?") {
+            for date_order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+                let date = normalize_date(&token, date_order);
+                prop_assert!(
+                    date == token || NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_ok(),
+                    "{token} -> {date}"
+                );
+            }
+        }
+
+        /// Ensure that valid dates are read in the configured order.
+        #[test]
+        fn normalize_valid_dates(date in (1970i32..2100, 1u32..=12, 1u32..=28)
+            .prop_map(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d).unwrap()))
+        {
+            let iso = date.format("%Y-%m-%d").to_string();
+            let dmy = date.format("%d.%m.%Y").to_string();
+            let mdy = date.format("%-m/%-d/%Y").to_string();
+            prop_assert_eq!(normalize_date(&dmy, DateOrder::Dmy), iso.clone());
+            prop_assert_eq!(normalize_date(&mdy, DateOrder::Mdy), iso.clone());
+            prop_assert_eq!(normalize_date(&iso, DateOrder::Mdy), iso);
+        }
+
+        /// Ensure that amounts with thousands separators are normalized.
+        #[test]
+        fn amounts_roundtrip(cents in -1_000_000_000i64..1_000_000_000, separator in "['’.]?") {
+            let units = (cents.abs() / 100).to_string();
+            let mut grouped = String::new();
+            for (i, digit) in units.chars().enumerate() {
+                if i > 0 && (units.len() - i) % 3 == 0 {
+                    grouped.push_str(&separator);
+                }
+                grouped.push(digit);
+            }
+            let decimal = if separator == "." { ',' } else { '.' };
+            let sign = if cents < 0 { "-" } else { "" };
+            let token = format!("{sign}{grouped}{decimal}{:02}", cents.abs() % 100);
+            let expected = format!("{sign}{units}.{:02}", cents.abs() % 100);
+            prop_assert_eq!(parse_amount(&token), Some(expected));
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::tiff_utils;
+
+/// Number of columns on each side of a column that are used to determine the
+/// local background brightness
+const BASELINE_RADIUS: usize = 15;
+
+/// Minimal deviation (in luminance levels) of a column from its surroundings
+/// to be considered part of a streak
+const MIN_DEVIATION: f32 = 8.0;
+
+/// Maximal width (in pixels) of a streak. Wider vertical structures are most
+/// likely part of the document.
+const MAX_WIDTH: u32 = 8;
+
+/// A vertical streak, e.g. caused by dust on the ADF glass
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streak {
+    /// Leftmost column of the streak
+    pub x: u32,
+    /// Width of the streak in pixels
+    pub width: u32,
+}
+
+/// Detect vertical streaks that are present at the same position on all pages
+/// of a side
+///
+/// Dirt on the ADF glass results in thin lines at the same position on every
+/// page, so at least two pages of a side are required. The pages of a duplex
+/// scan alternate between `sides` (2), which are scanned by different sensors
+/// and compared separately. Pages of different widths are not compared.
+/// Returns the streaks of every side.
+pub fn detect<P: AsRef<Path>>(
+    pages: &[P],
+    sides: usize,
+    memory_budget: usize,
+) -> Result<Vec<Vec<Streak>>> {
+    (0..sides)
+        .map(|side| {
+            let pages: Vec<&Path> = pages
+                .iter()
+                .skip(side)
+                .step_by(sides)
+                .map(AsRef::as_ref)
+                .collect();
+            if pages.len() < 2 {
+                return Ok(Vec::new());
+            }
+            let mut profiles = Vec::with_capacity(pages.len());
+            for page in pages {
+                let (profile, _height) = tiff_utils::column_profile(page, memory_budget)?;
+                profiles.push(profile);
+            }
+            Ok(find_streaks(&profiles))
+        })
+        .collect()
+}
+
+/// Find streaks in the column profiles of multiple pages
+fn find_streaks(profiles: &[Vec<f32>]) -> Vec<Streak> {
+    let Some(width) = profiles.first().map(Vec::len) else {
+        return Vec::new();
+    };
+    if profiles.iter().any(|profile| profile.len() != width) {
+        return Vec::new();
+    }
+
+    // A column is part of a streak if it deviates on all pages
+    let mut flagged = vec![true; width];
+    for profile in profiles {
+        for (flag, deviates) in flagged.iter_mut().zip(deviating_columns(profile)) {
+            *flag &= deviates;
+        }
+    }
+
+    // Group adjacent columns, ignore wide structures
+    let mut streaks = Vec::new();
+    let mut start = None;
+    for (x, flag) in flagged.iter().chain(std::iter::once(&false)).enumerate() {
+        match (start, flag) {
+            (None, true) => start = Some(x),
+            (Some(first), false) => {
+                let streak_width = (x - first) as u32;
+                if streak_width <= MAX_WIDTH {
+                    streaks.push(Streak {
+                        x: first as u32,
+                        width: streak_width,
+                    });
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    streaks
+}
+
+/// Determine which columns of a profile deviate from their surroundings
+fn deviating_columns(profile: &[f32]) -> Vec<bool> {
+    (0..profile.len())
+        .map(|x| {
+            let from = x.saturating_sub(BASELINE_RADIUS);
+            let to = (x + BASELINE_RADIUS + 1).min(profile.len());
+            let mut window = profile[from..to].to_vec();
+            window.sort_by(f32::total_cmp);
+            let baseline = window[window.len() / 2];
+            (profile[x] - baseline).abs() >= MIN_DEVIATION
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A white page profile with some darker columns
+    fn profile(width: usize, dark: &[usize]) -> Vec<f32> {
+        let mut profile = vec![240.0; width];
+        for x in dark {
+            profile[*x] = 200.0;
+        }
+        profile
+    }
+
+    /// Ensure that thin lines present on all pages are detected.
+    #[test]
+    fn detect_streak() {
+        let profiles = vec![
+            profile(100, &[10, 40, 41, 70]),
+            profile(100, &[40, 41, 70, 90]),
+            profile(100, &[40, 41, 50, 70]),
+        ];
+        assert_eq!(
+            find_streaks(&profiles),
+            vec![Streak { x: 40, width: 2 }, Streak { x: 70, width: 1 }]
+        );
+    }
+
+    /// Ensure that the fronts and backs of duplex scans are compared
+    /// separately.
+    #[test]
+    fn duplex_sides() {
+        use std::{fs::File, io::BufWriter};
+
+        use tiff::encoder::{TiffEncoder, colortype};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let pages: Vec<_> = [10, 50, 10, 50]
+            .into_iter()
+            .enumerate()
+            .map(|(i, dark)| {
+                let path = temp_dir.path().join(format!("page{i}.tif"));
+                let row: Vec<u8> = (0..100)
+                    .map(|x| if x == dark { 200 } else { 240 })
+                    .collect();
+                let file = File::create(&path).unwrap();
+                TiffEncoder::new(BufWriter::new(file))
+                    .unwrap()
+                    .write_image::<colortype::Gray8>(100, 20, &row.repeat(20))
+                    .unwrap();
+                path
+            })
+            .collect();
+        assert_eq!(
+            detect(&pages, 2, usize::MAX).unwrap(),
+            vec![
+                vec![Streak { x: 10, width: 1 }],
+                vec![Streak { x: 50, width: 1 }]
+            ]
+        );
+        assert_eq!(detect(&pages, 1, usize::MAX).unwrap(), vec![vec![]]);
+        assert_eq!(
+            detect(&pages[..3], 2, usize::MAX).unwrap(),
+            vec![vec![Streak { x: 10, width: 1 }], vec![]]
+        );
+    }
+
+    /// Ensure that wide structures (e.g. a dark margin) are not considered
+    /// streaks.
+    #[test]
+    fn ignore_wide_structures() {
+        let dark: Vec<usize> = (20..40).collect();
+        let profiles = vec![profile(100, &dark), profile(100, &dark)];
+        assert!(find_streaks(&profiles).is_empty());
+    }
+
+    /// Ensure that pages of different sizes are not compared.
+    #[test]
+    fn different_widths() {
+        let profiles = vec![profile(100, &[40]), profile(120, &[40])];
+        assert!(find_streaks(&profiles).is_empty());
+        assert!(find_streaks(&[]).is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+//! Tags of archived documents
+//!
+//! Tags that were used before form a vocabulary, which is kept in a file next
+//! to the config file and offered when archiving further documents. The tags
+//! of the archived documents are recorded in an index in the archive
+//! directory, next to the PDFs (and in the keywords of the PDFs), together
+//! with the stable IDs of the documents.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{config, fs_utils};
+
+/// Name of the tag vocabulary file in the config directory (one tag per line)
+const VOCABULARY_FILE: &str = "tags.txt";
+
+/// Name of the tag index in the archive directory
+const INDEX_FILE: &str = ".arkivisto-tags.toml";
+
+/// Split a comma-separated list of tags, dropping empty and duplicate tags
+pub fn parse(input: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    merge(&mut tags, input.split(',').map(str::trim));
+    tags
+}
+
+/// Add the `new` tags to `tags`, except empty tags and tags that only differ
+/// in case from a known one
+pub fn merge<'a>(tags: &mut Vec<String>, new: impl IntoIterator<Item = &'a str>) {
+    for tag in new {
+        if !tag.is_empty() && !tags.iter().any(|known| known.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+}
+
+/// Return the path of the tag vocabulary file, next to the config file
+pub fn vocabulary_path() -> Result<PathBuf> {
+    Ok(config::config_path()?.with_file_name(VOCABULARY_FILE))
+}
+
+/// Load the tag vocabulary, empty if there is none yet
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub fn load_vocabulary(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+        .with_context(|| format!("Failed to read tag vocabulary {path:?}"))?;
+    let mut tags = Vec::new();
+    merge(
+        &mut tags,
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#')),
+    );
+    Ok(tags)
+}
+
+/// Add tags to the vocabulary, which is kept sorted
+pub fn add_to_vocabulary(path: &Path, tags: &[String]) -> Result<()> {
+    let mut vocabulary = load_vocabulary(path)?;
+    let known = vocabulary.len();
+    merge(&mut vocabulary, tags.iter().map(String::as_str));
+    if vocabulary.len() == known {
+        return Ok(());
+    }
+    vocabulary.sort_by_key(|tag| tag.to_lowercase());
+    let mut content = vocabulary.join("\n");
+    content.push('\n');
+    fs_utils::write_synced(path, content)
+        .with_context(|| format!("Failed to write tag vocabulary {path:?}"))
+}
+
+/// Replace tags in the vocabulary (e.g. misspelled variants of a tag by the
+/// correct one), given by their old names
+pub fn rename_in_vocabulary(path: &Path, renames: &BTreeMap<String, String>) -> Result<()> {
+    let vocabulary = load_vocabulary(path)?;
+    let mut renamed = Vec::new();
+    merge(
+        &mut renamed,
+        vocabulary
+            .iter()
+            .map(|tag| renames.get(tag).unwrap_or(tag).as_str()),
+    );
+    if renamed == vocabulary {
+        return Ok(());
+    }
+    renamed.sort_by_key(|tag| tag.to_lowercase());
+    let mut content = renamed.join("\n");
+    content.push('\n');
+    fs_utils::write_synced(path, content)
+        .with_context(|| format!("Failed to write tag vocabulary {path:?}"))
+}
+
+/// Tags and IDs of the archived documents, by path relative to the archive
+/// directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    documents: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ids: BTreeMap<String, String>,
+}
+
+impl Index {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs_utils::retry_stale(|| fs::read_to_string(path))
+            .with_context(|| format!("Failed to read tag index {path:?}"))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse tag index {path:?}"))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize tag index")?;
+        fs_utils::write_synced(path, content)
+            .with_context(|| format!("Failed to write tag index {path:?}"))
+    }
+}
+
+/// Record the tags and the ID of an archived PDF in the index of `outdir`
+///
+/// PDFs without tags are removed from the tags of the index.
+pub fn record(outdir: &Path, pdf: &Path, tags: &[String], id: Option<&str>) -> Result<()> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let mut changed = if tags.is_empty() {
+        index.documents.remove(&key).is_some()
+    } else {
+        index
+            .documents
+            .insert(key.clone(), tags.to_vec())
+            .as_deref()
+            != Some(tags)
+    };
+    if let Some(id) = id {
+        changed |= index.ids.insert(key, id.to_string()).as_deref() != Some(id);
+    }
+    if !changed {
+        return Ok(());
+    }
+    index.save(&path)
+}
+
+/// Remove the tags and the ID of a PDF from the index of `outdir`
+pub fn remove(outdir: &Path, pdf: &Path) -> Result<()> {
+    let path = outdir.join(INDEX_FILE);
+    let mut index = Index::load(&path)?;
+    let key = fs_utils::relative_key(outdir, pdf);
+    let removed_tags = index.documents.remove(&key).is_some();
+    let removed_id = index.ids.remove(&key).is_some();
+    if !removed_tags && !removed_id {
+        return Ok(());
+    }
+    index.save(&path)
+}
+
+/// Paths of the PDFs with tags or an ID in the index of `outdir`, relative
+/// to `outdir`
+pub fn keys(outdir: &Path) -> Result<BTreeSet<String>> {
+    let index = Index::load(&outdir.join(INDEX_FILE))?;
+    Ok(index
+        .documents
+        .into_keys()
+        .chain(index.ids.into_keys())
+        .collect())
+}
+
+/// Tags of the PDFs in the index of `outdir`, by path relative to `outdir`
+pub fn load_index(outdir: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+    Ok(Index::load(&outdir.join(INDEX_FILE))?.documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Ensure that empty tags and tags that only differ in case are dropped.
+    #[test]
+    fn parse_tags() {
+        assert_eq!(parse("taxes, , 2024,Taxes"), vec!["taxes", "2024"]);
+        assert!(parse(" ").is_empty());
+    }
+
+    /// Ensure that new tags are added to the vocabulary, which is kept sorted
+    /// without duplicates.
+    #[test]
+    fn vocabulary() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(VOCABULARY_FILE);
+        assert!(load_vocabulary(&path).unwrap().is_empty());
+
+        add_to_vocabulary(&path, &["taxes".into(), "Bills".into()]).unwrap();
+        add_to_vocabulary(&path, &["Taxes".into(), "car".into()]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Bills\ncar\ntaxes\n");
+
+        fs::write(&path, "# Tags\ninsurance\n\n bills \n").unwrap();
+        assert_eq!(load_vocabulary(&path).unwrap(), vec!["insurance", "bills"]);
+
+        // Renamed tags are merged with the existing ones
+        let renames = BTreeMap::from([
+            ("bills".to_string(), "Bills".to_string()),
+            ("insurance".to_string(), "Bills".to_string()),
+        ]);
+        rename_in_vocabulary(&path, &renames).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Bills\n");
+    }
+
+    /// Ensure that the tags and IDs of archived PDFs are recorded by relative
+    /// path, that PDFs without tags are removed from the tags of the index,
+    /// and that removed PDFs are dropped entirely.
+    #[test]
+    fn index() {
+        let outdir = TempDir::new().unwrap();
+        let invoice = outdir.path().join("2025").join("invoice.pdf");
+        let letter = outdir.path().join("letter.pdf");
+        record(outdir.path(), &invoice, &["bills".into()], None).unwrap();
+        record(
+            outdir.path(),
+            &letter,
+            &["car".into(), "insurance".into()],
+            Some("01JAB"),
+        )
+        .unwrap();
+        record(outdir.path(), &letter, &[], Some("01JAB")).unwrap();
+        record(outdir.path(), &outdir.path().join("other.pdf"), &[], None).unwrap();
+
+        assert_eq!(
+            load_index(outdir.path()).unwrap(),
+            BTreeMap::from([("2025/invoice.pdf".to_string(), vec!["bills".to_string()])])
+        );
+        assert_eq!(
+            Index::load(&outdir.path().join(INDEX_FILE)).unwrap().ids,
+            BTreeMap::from([("letter.pdf".to_string(), "01JAB".to_string())])
+        );
+
+        remove(outdir.path(), &invoice).unwrap();
+        remove(outdir.path(), &letter).unwrap();
+        let index = Index::load(&outdir.path().join(INDEX_FILE)).unwrap();
+        assert!(index.documents.is_empty() && index.ids.is_empty());
+    }
+}
@@ -0,0 +1,1640 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Seek, Write},
+    ops::Range,
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use tiff::{
+    ColorType,
+    decoder::{ChunkType, Decoder, DecodingResult, ifd::Value},
+    encoder::{
+        DirectoryEncoder, Rational, TiffEncoder, TiffKindStandard,
+        compression::{CompressionAlgorithm, Compressor, Deflate, DeflateLevel, Lzw, Uncompressed},
+    },
+    tags::{
+        CompressionMethod, PhotometricInterpretation, PlanarConfiguration, ResolutionUnit, Tag,
+    },
+};
+use tracing::{trace, warn};
+
+use crate::{calibration::Calibration, config::TiffCompression};
+
+/// Target size of the (uncompressed) strips written to the combined TIFF
+const STRIP_SIZE: usize = 64 * 1024;
+
+/// Fraction of the sample range that is clipped to black and to white when
+/// stretching the contrast
+const CONTRAST_CLIP: f64 = 0.1;
+
+impl TiffCompression {
+    fn compressor(&self) -> Compressor {
+        match self {
+            TiffCompression::Lzw => Compressor::Lzw(Lzw),
+            TiffCompression::Deflate => {
+                Compressor::Deflate(Deflate::with_level(DeflateLevel::Balanced))
+            }
+            TiffCompression::None => Compressor::Uncompressed(Uncompressed),
+        }
+    }
+
+    fn method(&self) -> CompressionMethod {
+        match self {
+            TiffCompression::Lzw => CompressionMethod::LZW,
+            TiffCompression::Deflate => CompressionMethod::Deflate,
+            TiffCompression::None => CompressionMethod::None,
+        }
+    }
+}
+
+/// Resolution of a TIFF page, as stored in the TIFF tags
+#[derive(Debug, Clone, PartialEq)]
+struct PageResolution {
+    unit: ResolutionUnit,
+    /// Horizontal resolution as (numerator, denominator)
+    x: (u32, u32),
+    /// Vertical resolution as (numerator, denominator)
+    y: (u32, u32),
+}
+
+impl PageResolution {
+    /// Horizontal and vertical resolution in dots per inch, if known
+    fn dpi(&self) -> Option<(f32, f32)> {
+        let factor = match self.unit {
+            ResolutionUnit::Inch => 1.0,
+            ResolutionUnit::Centimeter => 2.54,
+            _ => return None,
+        };
+        let dpi = |(n, d): (u32, u32)| (n != 0 && d != 0).then(|| n as f32 / d as f32 * factor);
+        Some((dpi(self.x)?, dpi(self.y)?))
+    }
+}
+
+/// Pixel layout of a TIFF page
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PixelLayout {
+    photometric: PhotometricInterpretation,
+    samples: u16,
+    bits_per_sample: u16,
+}
+
+impl PixelLayout {
+    fn from_color_type(color_type: ColorType) -> Result<Self> {
+        let (photometric, samples, bits_per_sample) = match color_type {
+            ColorType::Gray(bits @ (1 | 8 | 16)) => {
+                (PhotometricInterpretation::BlackIsZero, 1, bits)
+            }
+            ColorType::RGB(bits @ (8 | 16)) => (PhotometricInterpretation::RGB, 3, bits),
+            ColorType::RGBA(bits @ (8 | 16)) => (PhotometricInterpretation::RGB, 4, bits),
+            color_type => return Err(anyhow!("Unsupported TIFF color type: {:?}", color_type)),
+        };
+        Ok(Self {
+            photometric,
+            samples,
+            bits_per_sample: u16::from(bits_per_sample),
+        })
+    }
+
+    /// Number of bytes per row of pixels (rows are padded to full bytes)
+    fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.samples as usize * self.bits_per_sample as usize).div_ceil(8)
+    }
+}
+
+/// Combine multiple TIFF files into a single multi-page TIFF file.
+///
+/// The pages are written in the order in which they are passed in, so
+/// reordering or skipping pages is up to the caller. If an input file contains
+/// multiple pages itself, all of them are copied. The resolution of every page
+/// is preserved.
+///
+/// Pages are streamed strip by strip, so only a few rows of a page are held in
+/// memory at any time (as long as the input files are split into strips or
+/// tiles). If a single input strip exceeds the `memory_budget` (in bytes), a
+/// warning is logged.
+pub fn combine_tiffs<P: AsRef<Path>>(
+    pages: &[P],
+    output: &Path,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<()> {
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create output TIFF {:?}", output))?;
+    let mut encoder =
+        TiffEncoder::new(BufWriter::new(file)).context("Failed to create TIFF encoder")?;
+
+    for page in pages {
+        let page = page.as_ref();
+        trace!("Adding {:?} to {:?}", page, output);
+        copy_pages(page, &mut encoder, compression, STRIP_SIZE, memory_budget)
+            .with_context(|| format!("Failed to copy {:?}", page))?;
+    }
+
+    Ok(())
+}
+
+/// Split a (multi-page) TIFF file into single-page files in `output_dir`,
+/// named after their page number starting at `first_page` (e.g. `0001.tif`).
+/// Returns the number of pages.
+pub fn split_pages(
+    input: &Path,
+    output_dir: &Path,
+    first_page: usize,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<usize> {
+    let mut decoder = open_decoder(input)?;
+    let mut count = 0;
+    loop {
+        let output = output_dir.join(format!("{:04}.tif", first_page + count));
+        let file = File::create(&output)
+            .with_context(|| format!("Failed to create output TIFF {:?}", output))?;
+        let mut encoder =
+            TiffEncoder::new(BufWriter::new(file)).context("Failed to create TIFF encoder")?;
+        copy_page(
+            &mut decoder,
+            &mut encoder,
+            compression,
+            STRIP_SIZE,
+            memory_budget,
+            &Transform::default(),
+        )
+        .with_context(|| format!("Failed to copy page {} of {:?}", count + 1, input))?;
+        count += 1;
+        if !decoder.more_images() {
+            return Ok(count);
+        }
+        decoder.next_image()?;
+    }
+}
+
+/// Corrections of the pixels of a page, see [`correct_page`]
+#[derive(Debug, Clone, Default)]
+pub struct Corrections {
+    /// Columns (the first column and the number of columns) that are
+    /// replaced by the column to their left, or to their right at the left
+    /// border (e.g. streaks)
+    pub fill_columns: Vec<(u32, u32)>,
+    /// Level and gamma correction of the scanner
+    pub calibration: Option<Calibration>,
+    /// Stretch the contrast, like ImageMagick's `-auto-level -level 10%,90%`
+    pub stretch_contrast: bool,
+}
+
+/// Correct the first page of a TIFF file
+///
+/// The columns are filled first, then the calibration is applied. When
+/// stretching the contrast, the darkest and lightest samples of the corrected
+/// page are stretched to black and white (with the same mapping for all color
+/// channels), then the darkest and lightest 10% of the range are clipped.
+/// The levels of bilevel pages are not changed. Like `combine_tiffs`, the page
+/// is streamed strip by strip.
+pub fn correct_page(
+    input: &Path,
+    output: &Path,
+    corrections: &Corrections,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<()> {
+    let mut decoder = open_decoder(input)?;
+    let layout = PixelLayout::from_color_type(decoder.colortype()?)?;
+    let (width, _) = decoder.dimensions()?;
+    let mut transform = Transform {
+        fill: ColumnFill::new(&corrections.fill_columns, width),
+        ..Transform::default()
+    };
+    if layout.bits_per_sample != 1 {
+        let calibration = corrections
+            .calibration
+            .map(|calibration| Levels::calibrate(&calibration, layout.bits_per_sample));
+        transform.levels = if corrections.stretch_contrast {
+            let (min, max) = sample_range(&mut decoder, layout, &transform.fill)
+                .with_context(|| format!("Failed to read {:?}", input))?;
+            let (min, max) = match &calibration {
+                Some(calibration) => (calibration.map(min), calibration.map(max)),
+                None => (min, max),
+            };
+            trace!("Stretching samples {min}..={max} of {:?}", input);
+            let stretch = Levels::stretch(min, max, layout.bits_per_sample, CONTRAST_CLIP);
+            Some(match calibration {
+                Some(calibration) => calibration.then(&stretch),
+                None => stretch,
+            })
+        } else {
+            calibration
+        };
+    }
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create output TIFF {:?}", output))?;
+    let mut encoder =
+        TiffEncoder::new(BufWriter::new(file)).context("Failed to create TIFF encoder")?;
+    copy_page(
+        &mut decoder,
+        &mut encoder,
+        compression,
+        STRIP_SIZE,
+        memory_budget,
+        &transform,
+    )
+    .with_context(|| format!("Failed to copy {:?}", input))
+}
+
+/// Crop the first page of a TIFF file to `width` columns starting at column
+/// `left`, keeping all rows
+///
+/// Like `correct_page`, the page is streamed strip by strip.
+pub fn crop_columns(
+    input: &Path,
+    output: &Path,
+    left: u32,
+    width: u32,
+    compression: TiffCompression,
+    memory_budget: usize,
+) -> Result<()> {
+    let mut decoder = open_decoder(input)?;
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create output TIFF {:?}", output))?;
+    let mut encoder =
+        TiffEncoder::new(BufWriter::new(file)).context("Failed to create TIFF encoder")?;
+    copy_page(
+        &mut decoder,
+        &mut encoder,
+        compression,
+        STRIP_SIZE,
+        memory_budget,
+        &Transform {
+            crop: Some(Crop { left, width }),
+            ..Transform::default()
+        },
+    )
+    .with_context(|| format!("Failed to crop {:?}", input))
+}
+
+/// Changes of the pixels of a page while it is copied, in the order in which
+/// they are applied
+#[derive(Default)]
+struct Transform {
+    fill: ColumnFill,
+    levels: Option<Levels>,
+    crop: Option<Crop>,
+}
+
+/// Columns of a page that are replaced by another column
+#[derive(Debug, Default)]
+struct ColumnFill {
+    /// Target and source column
+    columns: Vec<(usize, usize)>,
+}
+
+impl ColumnFill {
+    /// Fill the `(first column, number of columns)` ranges of a page of
+    /// `page_width` pixels with the column to their left, or to their right
+    /// at the left border
+    fn new(ranges: &[(u32, u32)], page_width: u32) -> Self {
+        let mut columns = Vec::new();
+        for &(x, width) in ranges {
+            let source = if x > 0 { x - 1 } else { width };
+            if source >= page_width {
+                continue;
+            }
+            for target in x..x.saturating_add(width).min(page_width) {
+                columns.push((target as usize, source as usize));
+            }
+        }
+        Self { columns }
+    }
+
+    /// Fill the columns of every row of a chunk of a page of `page_width`
+    /// pixels
+    fn apply(
+        &self,
+        chunk: &mut DecodingResult,
+        layout: PixelLayout,
+        page_width: u32,
+    ) -> Result<()> {
+        if self.columns.is_empty() {
+            return Ok(());
+        }
+        let samples = layout.samples as usize;
+        match chunk {
+            DecodingResult::U8(data) if layout.bits_per_sample == 1 => {
+                for row in data.chunks_mut(layout.row_bytes(page_width)) {
+                    for &(target, source) in &self.columns {
+                        let bit = (row[source / 8] >> (7 - source % 8)) & 1;
+                        let mask = 1 << (7 - target % 8);
+                        row[target / 8] = (row[target / 8] & !mask) | (bit * mask);
+                    }
+                }
+            }
+            DecodingResult::U8(data) => self.fill_samples(data, samples, page_width),
+            DecodingResult::U16(data) => self.fill_samples(data, samples, page_width),
+            _ => return Err(anyhow!("Unsupported TIFF sample format")),
+        }
+        Ok(())
+    }
+
+    fn fill_samples<T: Copy>(&self, data: &mut [T], samples: usize, page_width: u32) {
+        for row in data.chunks_mut(page_width as usize * samples) {
+            for &(target, source) in &self.columns {
+                row.copy_within(source * samples..(source + 1) * samples, target * samples);
+            }
+        }
+    }
+}
+
+/// Columns of a page that are kept when cropping
+#[derive(Debug, Clone, Copy)]
+struct Crop {
+    left: u32,
+    width: u32,
+}
+
+impl Crop {
+    /// Crop the rows of a chunk of a page of `page_width` pixels
+    fn apply(&self, chunk: &[u8], layout: PixelLayout, page_width: u32) -> Vec<u8> {
+        let bits_per_pixel = layout.samples as usize * layout.bits_per_sample as usize;
+        let start = self.left as usize * bits_per_pixel;
+        let bits = self.width as usize * bits_per_pixel;
+        let mut cropped = Vec::with_capacity(chunk.len());
+        for row in chunk.chunks(layout.row_bytes(page_width)) {
+            if start.is_multiple_of(8) {
+                cropped.extend(&row[start / 8..(start + bits).div_ceil(8)]);
+            } else {
+                // Bilevel rows are shifted bit by bit, the padding is zero
+                let bit = |index: usize| (row[index / 8] >> (7 - index % 8)) & 1;
+                for byte_start in (0..bits).step_by(8) {
+                    let byte = (byte_start..(byte_start + 8).min(bits)).fold(0u8, |byte, i| {
+                        byte | bit(start + i) << (7 - (i - byte_start))
+                    });
+                    cropped.push(byte);
+                }
+            }
+        }
+        cropped
+    }
+}
+
+/// Mapping of sample values, applied to the color channels of a page
+struct Levels {
+    /// New value of every sample value
+    table: Vec<u16>,
+}
+
+impl Levels {
+    /// Map the black and white point of the calibration to black and white,
+    /// and apply its gamma, like ImageMagick's `-level`
+    fn calibrate(calibration: &Calibration, bits: u16) -> Self {
+        let full = (1u32 << bits) - 1;
+        let black = f64::from(calibration.black) / 100.0;
+        let white = f64::from(calibration.white) / 100.0;
+        let gamma = f64::from(calibration.gamma);
+        let table = (0..=full)
+            .map(|value| {
+                let level = if white > black {
+                    ((f64::from(value) / f64::from(full) - black) / (white - black)).clamp(0.0, 1.0)
+                } else {
+                    f64::from(value) / f64::from(full)
+                };
+                (level.powf(1.0 / gamma) * f64::from(full)).round() as u16
+            })
+            .collect();
+        Self { table }
+    }
+
+    /// Map a sample value
+    fn map(&self, value: u16) -> u16 {
+        self.table[usize::from(value)]
+    }
+
+    /// Apply `next` after these levels
+    fn then(&self, next: &Levels) -> Self {
+        Self {
+            table: self.table.iter().map(|&value| next.map(value)).collect(),
+        }
+    }
+
+    /// Stretch the samples `min..=max` to the full range of `bits`, then clip
+    /// the `clip` (0-0.5) darkest and lightest fraction of the range
+    fn stretch(min: u16, max: u16, bits: u16, clip: f64) -> Self {
+        let full = (1u32 << bits) - 1;
+        let (min, max) = if max > min {
+            (f64::from(min), f64::from(max))
+        } else {
+            (0.0, f64::from(full))
+        };
+        let table = (0..=full)
+            .map(|value| {
+                let auto_level = (f64::from(value) - min) / (max - min);
+                let level = ((auto_level - clip) / (1.0 - 2.0 * clip)).clamp(0.0, 1.0);
+                (level * f64::from(full)).round() as u16
+            })
+            .collect();
+        Self { table }
+    }
+
+    /// Map the color samples of a chunk with `samples` samples per pixel
+    fn apply(&self, chunk: DecodingResult, samples: u16) -> Result<DecodingResult> {
+        Ok(match chunk {
+            DecodingResult::U8(mut data) => {
+                for (index, value) in data.iter_mut().enumerate() {
+                    if !is_alpha(index, samples) {
+                        *value = self.table[usize::from(*value)] as u8;
+                    }
+                }
+                DecodingResult::U8(data)
+            }
+            DecodingResult::U16(mut data) => {
+                for (index, value) in data.iter_mut().enumerate() {
+                    if !is_alpha(index, samples) {
+                        *value = self.table[usize::from(*value)];
+                    }
+                }
+                DecodingResult::U16(data)
+            }
+            _ => return Err(anyhow!("Unsupported TIFF sample format")),
+        })
+    }
+}
+
+/// Whether the sample at `index` of a chunk is an alpha sample
+fn is_alpha(index: usize, samples: u16) -> bool {
+    samples == 4 && index % 4 == 3
+}
+
+/// Smallest and largest color sample of the current page, after filling the
+/// columns
+fn sample_range(
+    decoder: &mut Decoder<BufReader<File>>,
+    layout: PixelLayout,
+    fill: &ColumnFill,
+) -> Result<(u16, u16)> {
+    let (width, _) = decoder.dimensions()?;
+    let chunk_count = match decoder.get_chunk_type() {
+        ChunkType::Strip => decoder.strip_count()?,
+        ChunkType::Tile => 1,
+    };
+    let mut range = (u16::MAX, u16::MIN);
+    let mut update = |index: usize, value: u16| {
+        if !is_alpha(index, layout.samples) {
+            range = (range.0.min(value), range.1.max(value));
+        }
+    };
+    for chunk_index in 0..chunk_count {
+        let mut chunk = match decoder.get_chunk_type() {
+            ChunkType::Strip => decoder.read_chunk(chunk_index)?,
+            ChunkType::Tile => decoder.read_image()?,
+        };
+        fill.apply(&mut chunk, layout, width)?;
+        match chunk {
+            DecodingResult::U8(data) => {
+                for (index, value) in data.into_iter().enumerate() {
+                    update(index, u16::from(value));
+                }
+            }
+            DecodingResult::U16(data) => {
+                for (index, value) in data.into_iter().enumerate() {
+                    update(index, value);
+                }
+            }
+            _ => return Err(anyhow!("Unsupported TIFF sample format")),
+        }
+    }
+    Ok(range)
+}
+
+/// Pixel format of a decoded page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One bit per pixel (0 is black), rows are padded to full bytes
+    Bilevel,
+    /// 8 bit luminance
+    Gray,
+    /// 8 bit red, green and blue samples
+    Rgb,
+}
+
+/// A page of a TIFF file that is decoded strip by strip
+pub struct Page<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// Horizontal and vertical resolution in DPI, if known
+    pub dpi: Option<(f32, f32)>,
+    pub format: PixelFormat,
+    decoder: &'a mut Decoder<BufReader<File>>,
+    layout: PixelLayout,
+    /// Indices of the chunks that are not decoded yet
+    chunks: Range<u32>,
+}
+
+impl<'a> Page<'a> {
+    /// The current page of the decoder
+    fn new(decoder: &'a mut Decoder<BufReader<File>>, memory_budget: usize) -> Result<Self> {
+        let (width, height) = decoder.dimensions()?;
+        let layout = PixelLayout::from_color_type(decoder.colortype()?)?;
+        let dpi = read_resolution(decoder)?.and_then(|resolution| resolution.dpi());
+        let format = match (layout.bits_per_sample, layout.samples) {
+            (1, _) => PixelFormat::Bilevel,
+            (_, 1) => PixelFormat::Gray,
+            _ => PixelFormat::Rgb,
+        };
+        let chunk_count = chunk_count(decoder, layout, memory_budget)?;
+        Ok(Self {
+            width,
+            height,
+            dpi,
+            format,
+            decoder,
+            layout,
+            chunks: 0..chunk_count,
+        })
+    }
+
+    /// Decode the next rows of the page (in the pixel format of the page), or
+    /// return `None` after the last row
+    pub fn read_rows(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(chunk_index) = self.chunks.next() else {
+            return Ok(None);
+        };
+        let chunk = match self.decoder.get_chunk_type() {
+            ChunkType::Strip => self.decoder.read_chunk(chunk_index),
+            ChunkType::Tile => self.decoder.read_image(),
+        }
+        .context("Failed to decode TIFF")?;
+        let samples = match chunk {
+            DecodingResult::U8(data) => data,
+            DecodingResult::U16(data) => data.into_iter().map(|v| (v >> 8) as u8).collect(),
+            _ => return Err(anyhow!("Unsupported TIFF sample format")),
+        };
+        if self.format == PixelFormat::Rgb && self.layout.samples > 3 {
+            return Ok(Some(
+                samples
+                    .chunks_exact(self.layout.samples as usize)
+                    .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect(),
+            ));
+        }
+        Ok(Some(samples))
+    }
+}
+
+/// Decode the pages of a (multi-page) TIFF file one after the other
+///
+/// 16 bit samples are reduced to 8 bits, the alpha channel is dropped. The
+/// pages are decoded strip by strip while they are read, so only one strip is
+/// held in memory at any time. If a strip exceeds the `memory_budget` (in
+/// bytes), a warning is logged.
+pub fn for_each_page(
+    path: &Path,
+    memory_budget: usize,
+    mut f: impl FnMut(Page<'_>) -> Result<()>,
+) -> Result<()> {
+    let mut decoder = open_decoder(path)?;
+    loop {
+        f(Page::new(&mut decoder, memory_budget)?)?;
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+    }
+    Ok(())
+}
+
+/// Width and height of the first page of a TIFF file
+pub fn page_dimensions(path: &Path) -> Result<(u32, u32)> {
+    Ok(open_decoder(path)?.dimensions()?)
+}
+
+/// A page that is decoded completely, with one byte per sample
+///
+/// The samples of bilevel pages are 0 (black) or 255 (white).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    /// Horizontal and vertical resolution in DPI, if known
+    pub dpi: Option<(f32, f32)>,
+    pub format: PixelFormat,
+    /// Samples, row by row
+    pub samples: Vec<u8>,
+}
+
+impl Raster {
+    /// A white page
+    pub fn white(width: u32, height: u32, dpi: Option<(f32, f32)>, format: PixelFormat) -> Self {
+        Self {
+            width,
+            height,
+            dpi,
+            format,
+            samples: vec![255; width as usize * height as usize * format.channels()],
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Number of samples per pixel of a [`Raster`]
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Bilevel | PixelFormat::Gray => 1,
+            PixelFormat::Rgb => 3,
+        }
+    }
+}
+
+/// Decode the first page of a TIFF file completely
+///
+/// Unlike [`for_each_page`], the whole page is held in memory, for changes
+/// that need access to all pixels (e.g. rotation). Like there, 16 bit samples
+/// are reduced to 8 bits and the alpha channel is dropped.
+pub fn read_raster(path: &Path, memory_budget: usize) -> Result<Raster> {
+    let mut decoder = open_decoder(path)?;
+    let mut page = Page::new(&mut decoder, memory_budget)?;
+    let mut data = Vec::new();
+    while let Some(rows) = page.read_rows()? {
+        data.extend(rows);
+    }
+    let width = page.width as usize;
+    let samples: Vec<u8> = if page.format == PixelFormat::Bilevel {
+        data.chunks_exact(width.div_ceil(8).max(1))
+            .flat_map(|row| (0..width).map(move |x| (row[x / 8] << (x % 8)) & 0x80))
+            .map(|bit| if bit == 0 { 0 } else { 255 })
+            .collect()
+    } else {
+        data
+    };
+    ensure!(
+        samples.len() == width * page.height as usize * page.format.channels(),
+        "Page of {:?} is truncated",
+        path
+    );
+    Ok(Raster {
+        width: page.width,
+        height: page.height,
+        dpi: page.dpi,
+        format: page.format,
+        samples,
+    })
+}
+
+/// Write a page as a single-page TIFF file
+///
+/// The samples of bilevel pages are reduced to black and white at half the
+/// range.
+pub fn write_raster(output: &Path, raster: &Raster, compression: TiffCompression) -> Result<()> {
+    ensure!(raster.width > 0 && raster.height > 0, "Page is empty");
+    let layout = PixelLayout::from_color_type(match raster.format {
+        PixelFormat::Bilevel => ColorType::Gray(1),
+        PixelFormat::Gray => ColorType::Gray(8),
+        PixelFormat::Rgb => ColorType::RGB(8),
+    })?;
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create output TIFF {:?}", output))?;
+    let mut encoder =
+        TiffEncoder::new(BufWriter::new(file)).context("Failed to create TIFF encoder")?;
+    let mut directory = encoder.image_directory()?;
+    let mut compressor = compression.compressor();
+
+    let rows_per_strip =
+        (STRIP_SIZE / layout.row_bytes(raster.width)).clamp(1, raster.height as usize);
+    let mut strips = Strips {
+        rows_per_strip,
+        offsets: Vec::new(),
+        byte_counts: Vec::new(),
+    };
+    let row_samples = raster.width as usize * raster.format.channels();
+    for rows in raster.samples.chunks(rows_per_strip * row_samples) {
+        let mut buffer = match raster.format {
+            PixelFormat::Bilevel => rows
+                .chunks(row_samples)
+                .flat_map(|row| row.chunks(8))
+                .map(|pixels| {
+                    (pixels.iter().enumerate())
+                        .filter(|(_, sample)| **sample >= 128)
+                        .fold(0u8, |byte, (i, _)| byte | 0x80 >> i)
+                })
+                .collect(),
+            PixelFormat::Gray | PixelFormat::Rgb => rows.to_vec(),
+        };
+        write_strip(
+            &mut directory,
+            &mut compressor,
+            &mut buffer,
+            &mut strips.offsets,
+            &mut strips.byte_counts,
+        )?;
+    }
+
+    write_tags(
+        &mut directory,
+        raster.width,
+        raster.height,
+        layout,
+        compression,
+        &strips,
+    )?;
+    let rational = |dpi: f32| {
+        if dpi.fract() == 0.0 {
+            (dpi as u32, 1)
+        } else {
+            ((dpi * 100.0).round() as u32, 100)
+        }
+    };
+    let resolution = raster.dpi.map(|(x, y)| PageResolution {
+        unit: ResolutionUnit::Inch,
+        x: rational(x),
+        y: rational(y),
+    });
+    write_resolution(&mut directory, resolution.as_ref())?;
+    directory.finish()?;
+    Ok(())
+}
+
+/// Luminance (0-255) of the pixels of the first page of a TIFF file
+pub struct LuminanceImage {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels, row by row
+    pub pixels: Vec<u8>,
+}
+
+/// Read the luminance of the first page of a TIFF file
+///
+/// 16 bit samples are reduced to 8 bits, the alpha channel is ignored.
+pub fn read_luminance(path: &Path) -> Result<LuminanceImage> {
+    let mut decoder = open_decoder(path)?;
+    let (width, height) = decoder.dimensions()?;
+    let layout = luminance_layout(&mut decoder)?;
+    let chunk = decoder.read_image().context("Failed to decode TIFF")?;
+    Ok(LuminanceImage {
+        width,
+        height,
+        pixels: luminance(chunk, layout)?,
+    })
+}
+
+/// Layout of the current page, which must contain luminance information
+fn luminance_layout(decoder: &mut Decoder<BufReader<File>>) -> Result<PixelLayout> {
+    let layout = PixelLayout::from_color_type(decoder.colortype()?)?;
+    ensure!(
+        layout.bits_per_sample != 1,
+        "Bilevel images do not contain luminance information"
+    );
+    Ok(layout)
+}
+
+/// Luminance (0-255) of the pixels of a decoded chunk
+fn luminance(chunk: DecodingResult, layout: PixelLayout) -> Result<Vec<u8>> {
+    let samples: Vec<u8> = match chunk {
+        DecodingResult::U8(data) => data,
+        DecodingResult::U16(data) => data.into_iter().map(|v| (v >> 8) as u8).collect(),
+        _ => return Err(anyhow!("Unsupported TIFF sample format")),
+    };
+    Ok(samples
+        .chunks_exact(layout.samples as usize)
+        .map(|pixel| {
+            if layout.samples >= 3 {
+                // ITU-R BT.601 luma
+                (0.299 * f32::from(pixel[0])
+                    + 0.587 * f32::from(pixel[1])
+                    + 0.114 * f32::from(pixel[2]))
+                .round()
+                .min(255.0) as u8
+            } else {
+                pixel[0]
+            }
+        })
+        .collect())
+}
+
+/// Compute the histogram of the luminance (0-255) of the first page of a TIFF
+/// file
+pub fn luminance_histogram(path: &Path) -> Result<[u64; 256]> {
+    let image = read_luminance(path)?;
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels {
+        histogram[pixel as usize] += 1;
+    }
+    Ok(histogram)
+}
+
+/// Smallest value in a histogram below which at least `fraction` (0-1) of all
+/// samples lie
+pub fn histogram_percentile(histogram: &[u64], fraction: f64) -> usize {
+    let total: u64 = histogram.iter().sum();
+    let target = (total as f64 * fraction).ceil().max(1.0) as u64;
+    let mut sum = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        sum += count;
+        if sum >= target {
+            return value;
+        }
+    }
+    histogram.len().saturating_sub(1)
+}
+
+/// Mean luminance of every pixel column of the first page of a TIFF file
+///
+/// Like `correct_page`, the page is streamed strip by strip, so that only
+/// one strip is held in memory at any time. Returns the column means (from
+/// left to right) and the height of the page.
+pub fn column_profile(path: &Path, memory_budget: usize) -> Result<(Vec<f32>, u32)> {
+    let mut decoder = open_decoder(path)?;
+    let (width, height) = decoder.dimensions()?;
+    let layout = luminance_layout(&mut decoder)?;
+    ensure!(width > 0 && height > 0, "Image is empty");
+    let chunk_count = chunk_count(&mut decoder, layout, memory_budget)?;
+
+    // Strips consist of full rows, so the column follows from the position
+    // of a pixel in the page
+    let mut sums = vec![0u64; width as usize];
+    let mut pixel_index = 0;
+    for chunk_index in 0..chunk_count {
+        let chunk = match decoder.get_chunk_type() {
+            ChunkType::Strip => decoder.read_chunk(chunk_index)?,
+            ChunkType::Tile => decoder.read_image()?,
+        };
+        for pixel in luminance(chunk, layout)? {
+            sums[pixel_index % width as usize] += u64::from(pixel);
+            pixel_index += 1;
+        }
+    }
+    let profile = sums
+        .into_iter()
+        .map(|sum| sum as f32 / height as f32)
+        .collect();
+    Ok((profile, height))
+}
+
+/// Number of chunks of the current page that are read one at a time, warn if
+/// a chunk exceeds the `memory_budget` (in bytes)
+///
+/// Strips are read one at a time. Tiled images are read as a whole, because
+/// tiles don't consist of full rows.
+fn chunk_count(
+    decoder: &mut Decoder<BufReader<File>>,
+    layout: PixelLayout,
+    memory_budget: usize,
+) -> Result<u32> {
+    let (width, height) = decoder.dimensions()?;
+    let (chunk_height, chunk_count) = match decoder.get_chunk_type() {
+        ChunkType::Strip => (decoder.chunk_dimensions().1, decoder.strip_count()?),
+        ChunkType::Tile => (height, 1),
+    };
+    let chunk_size = chunk_height as usize * layout.row_bytes(width);
+    if chunk_size > memory_budget {
+        warn!(
+            "TIFF page is stored in chunks of {} MiB, exceeding the memory budget",
+            chunk_size / 1024 / 1024
+        );
+    }
+    Ok(chunk_count)
+}
+
+fn open_decoder(path: &Path) -> Result<Decoder<BufReader<File>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open TIFF {:?}", path))?;
+    Ok(Decoder::new(BufReader::new(file))
+        .context("Failed to read TIFF header")?
+        .with_limits(tiff::decoder::Limits::unlimited()))
+}
+
+/// Copy all pages of the TIFF file at `path` into the encoder
+fn copy_pages<W: Write + Seek>(
+    path: &Path,
+    encoder: &mut TiffEncoder<W>,
+    compression: TiffCompression,
+    strip_size: usize,
+    memory_budget: usize,
+) -> Result<()> {
+    let mut decoder = open_decoder(path)?;
+    loop {
+        copy_page(
+            &mut decoder,
+            encoder,
+            compression,
+            strip_size,
+            memory_budget,
+            &Transform::default(),
+        )?;
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+    }
+    Ok(())
+}
+
+/// Copy the current page of the decoder into the encoder, changing the pixels
+/// with the transform
+fn copy_page<W: Write + Seek>(
+    decoder: &mut Decoder<BufReader<File>>,
+    encoder: &mut TiffEncoder<W>,
+    compression: TiffCompression,
+    strip_size: usize,
+    memory_budget: usize,
+    transform: &Transform,
+) -> Result<()> {
+    let (input_width, height) = decoder.dimensions()?;
+    let layout = PixelLayout::from_color_type(decoder.colortype()?)?;
+    let width = match transform.crop {
+        Some(crop) => {
+            ensure!(
+                crop.left.saturating_add(crop.width) <= input_width && crop.width > 0,
+                "Cannot crop {} columns at {} of a page of width {input_width}",
+                crop.width,
+                crop.left
+            );
+            crop.width
+        }
+        None => input_width,
+    };
+    let resolution = read_resolution(decoder)?;
+    let planar = decoder
+        .find_tag_unsigned::<u16>(Tag::PlanarConfiguration)?
+        .and_then(PlanarConfiguration::from_u16)
+        .unwrap_or(PlanarConfiguration::Chunky);
+    ensure!(
+        planar == PlanarConfiguration::Chunky || layout.samples == 1,
+        "Planar TIFF images are not supported"
+    );
+
+    let row_bytes = layout.row_bytes(width);
+    let rows_per_strip = (strip_size / row_bytes).clamp(1, height as usize);
+    let strip_bytes = rows_per_strip * row_bytes;
+
+    let chunk_count = chunk_count(decoder, layout, memory_budget)?;
+
+    let mut directory = encoder.image_directory()?;
+    let mut compressor = compression.compressor();
+    let mut strip_offsets = Vec::new();
+    let mut strip_byte_counts = Vec::new();
+    let mut buffer = Vec::with_capacity(strip_bytes);
+    for chunk_index in 0..chunk_count {
+        let mut chunk = match decoder.get_chunk_type() {
+            ChunkType::Strip => decoder.read_chunk(chunk_index)?,
+            ChunkType::Tile => decoder.read_image()?,
+        };
+        transform.fill.apply(&mut chunk, layout, input_width)?;
+        let chunk = match &transform.levels {
+            Some(levels) => levels.apply(chunk, layout.samples)?,
+            None => chunk,
+        };
+        let chunk = chunk_to_bytes(chunk)?;
+        let mut chunk = match transform.crop {
+            Some(crop) => crop.apply(&chunk, layout, input_width),
+            None => chunk,
+        }
+        .into_iter();
+        loop {
+            buffer.extend(chunk.by_ref().take(strip_bytes - buffer.len()));
+            if buffer.len() < strip_bytes {
+                break;
+            }
+            write_strip(
+                &mut directory,
+                &mut compressor,
+                &mut buffer,
+                &mut strip_offsets,
+                &mut strip_byte_counts,
+            )?;
+        }
+    }
+    if !buffer.is_empty() {
+        write_strip(
+            &mut directory,
+            &mut compressor,
+            &mut buffer,
+            &mut strip_offsets,
+            &mut strip_byte_counts,
+        )?;
+    }
+
+    let strips = Strips {
+        rows_per_strip,
+        offsets: strip_offsets,
+        byte_counts: strip_byte_counts,
+    };
+    write_tags(&mut directory, width, height, layout, compression, &strips)?;
+    write_resolution(&mut directory, resolution.as_ref())?;
+    directory.finish()?;
+
+    Ok(())
+}
+
+/// Strips of a page that were written with [`write_strip`]
+struct Strips {
+    rows_per_strip: usize,
+    offsets: Vec<u32>,
+    byte_counts: Vec<u32>,
+}
+
+/// Write the tags that describe the pixels and strips of a page
+fn write_tags<W: Write + Seek>(
+    directory: &mut DirectoryEncoder<'_, W, TiffKindStandard>,
+    width: u32,
+    height: u32,
+    layout: PixelLayout,
+    compression: TiffCompression,
+    strips: &Strips,
+) -> Result<()> {
+    directory.write_tag(Tag::ImageWidth, width)?;
+    directory.write_tag(Tag::ImageLength, height)?;
+    directory.write_tag(
+        Tag::BitsPerSample,
+        &vec![layout.bits_per_sample; layout.samples as usize][..],
+    )?;
+    directory.write_tag(Tag::Compression, compression.method().to_u16())?;
+    directory.write_tag(Tag::PhotometricInterpretation, layout.photometric.to_u16())?;
+    directory.write_tag(Tag::SamplesPerPixel, layout.samples)?;
+    if layout.samples == 4 {
+        // Unassociated alpha
+        directory.write_tag(Tag::ExtraSamples, 2u16)?;
+    }
+    directory.write_tag(Tag::RowsPerStrip, strips.rows_per_strip as u32)?;
+    directory.write_tag(Tag::StripOffsets, &strips.offsets[..])?;
+    directory.write_tag(Tag::StripByteCounts, &strips.byte_counts[..])?;
+    directory.write_tag(
+        Tag::PlanarConfiguration,
+        PlanarConfiguration::Chunky.to_u16(),
+    )?;
+    Ok(())
+}
+
+/// Write the resolution tags of a page, if the resolution is known
+fn write_resolution<W: Write + Seek>(
+    directory: &mut DirectoryEncoder<'_, W, TiffKindStandard>,
+    resolution: Option<&PageResolution>,
+) -> Result<()> {
+    if let Some(resolution) = resolution {
+        directory.write_tag(Tag::ResolutionUnit, resolution.unit.to_u16())?;
+        let (n, d) = resolution.x;
+        directory.write_tag(Tag::XResolution, Rational { n, d })?;
+        let (n, d) = resolution.y;
+        directory.write_tag(Tag::YResolution, Rational { n, d })?;
+    }
+    Ok(())
+}
+
+/// Compress the buffer and write it as a strip, then clear the buffer
+fn write_strip<W: Write + Seek>(
+    directory: &mut DirectoryEncoder<'_, W, TiffKindStandard>,
+    compressor: &mut Compressor,
+    buffer: &mut Vec<u8>,
+    strip_offsets: &mut Vec<u32>,
+    strip_byte_counts: &mut Vec<u32>,
+) -> Result<()> {
+    let mut compressed = Vec::new();
+    compressor.write_to(&mut compressed, buffer)?;
+    let offset = directory.write_data(&compressed[..])?;
+    strip_offsets.push(u32::try_from(offset).context("Combined TIFF exceeds 4 GiB")?);
+    strip_byte_counts.push(u32::try_from(compressed.len())?);
+    buffer.clear();
+    Ok(())
+}
+
+/// Convert decoded samples to bytes in native byte order (as used by the
+/// encoder)
+fn chunk_to_bytes(chunk: DecodingResult) -> Result<Vec<u8>> {
+    match chunk {
+        DecodingResult::U8(data) => Ok(data),
+        DecodingResult::U16(data) => Ok(data.iter().flat_map(|v| v.to_ne_bytes()).collect()),
+        _ => Err(anyhow!("Unsupported TIFF sample format")),
+    }
+}
+
+/// Read the resolution tags of the current page, if present
+fn read_resolution(decoder: &mut Decoder<BufReader<File>>) -> Result<Option<PageResolution>> {
+    let unit = decoder
+        .find_tag_unsigned::<u16>(Tag::ResolutionUnit)?
+        .and_then(ResolutionUnit::from_u16)
+        .unwrap_or(ResolutionUnit::Inch);
+    let x = decoder.find_tag(Tag::XResolution)?;
+    let y = decoder.find_tag(Tag::YResolution)?;
+    Ok(match (x, y) {
+        (Some(Value::Rational(xn, xd)), Some(Value::Rational(yn, yd))) => Some(PageResolution {
+            unit,
+            x: (xn, xd),
+            y: (yn, yd),
+        }),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+    use tiff::encoder::{Compression, colortype};
+
+    const COMPRESSIONS: [TiffCompression; 3] = [
+        TiffCompression::Lzw,
+        TiffCompression::Deflate,
+        TiffCompression::None,
+    ];
+
+    /// Write a grayscale test page with the given resolution (in DPI)
+    fn write_test_page(path: &Path, shade: u8, dpi: u32) {
+        let file = File::create(path).unwrap();
+        let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray8>(20, 10).unwrap();
+        image.resolution(ResolutionUnit::Inch, Rational { n: dpi, d: 1 });
+        image.write_data(&[shade; 200]).unwrap();
+    }
+
+    /// Count the pages (image directories) in a TIFF file
+    fn page_count(path: &Path) -> Result<usize> {
+        let mut decoder = open_decoder(path)?;
+        let mut count = 1;
+        while decoder.more_images() {
+            decoder.next_image()?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Read the first pixel of every page in a TIFF file
+    fn read_first_pixels(path: &Path) -> Vec<u8> {
+        let mut decoder = open_decoder(path).unwrap();
+        let mut pixels = Vec::new();
+        loop {
+            match decoder.read_image().unwrap() {
+                DecodingResult::U8(data) => pixels.push(data[0]),
+                _ => panic!("Unexpected pixel format"),
+            }
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image().unwrap();
+        }
+        pixels
+    }
+
+    /// Ensure that the luminance histogram counts every pixel of the first page.
+    #[test]
+    fn histogram() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("page.tif");
+        write_test_page(&path, 128, 300);
+        let histogram = luminance_histogram(&path).unwrap();
+        assert_eq!(histogram[128], 200);
+        assert_eq!(histogram.iter().sum::<u64>(), 200);
+
+        let file = File::create(&path).unwrap();
+        TiffEncoder::new(BufWriter::new(file))
+            .unwrap()
+            .write_image::<colortype::RGB8>(2, 1, &[255, 255, 255, 255, 0, 0])
+            .unwrap();
+        let histogram = luminance_histogram(&path).unwrap();
+        assert_eq!(histogram[255], 1);
+        assert_eq!(histogram[76], 1);
+    }
+
+    /// Ensure that percentiles are determined from the cumulative counts.
+    #[test]
+    fn percentile() {
+        let histogram = [10, 0, 80, 10];
+        assert_eq!(histogram_percentile(&histogram, 0.0), 0);
+        assert_eq!(histogram_percentile(&histogram, 0.1), 0);
+        assert_eq!(histogram_percentile(&histogram, 0.5), 2);
+        assert_eq!(histogram_percentile(&histogram, 0.95), 3);
+        assert_eq!(histogram_percentile(&[0; 4], 0.5), 3);
+    }
+
+    /// Ensure that the column profile contains the mean of every column.
+    #[test]
+    fn profile() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("page.tif");
+        let file = File::create(&path).unwrap();
+        TiffEncoder::new(BufWriter::new(file))
+            .unwrap()
+            .write_image::<colortype::Gray8>(3, 2, &[0, 100, 255, 50, 100, 255])
+            .unwrap();
+        let (profile, height) = column_profile(&path, usize::MAX).unwrap();
+        assert_eq!(profile, vec![25.0, 100.0, 255.0]);
+        assert_eq!(height, 2);
+        assert_eq!(page_dimensions(&path).unwrap(), (3, 2));
+
+        // Pages of multiple strips are summed up strip by strip
+        let (width, height) = (100, 500);
+        let pixels: Vec<u8> = (0..width * height).map(|i| (i % width) as u8).collect();
+        {
+            let file = File::create(&path).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder
+                .new_image::<colortype::Gray8>(width, height)
+                .unwrap();
+            image.rows_per_strip(64).unwrap();
+            image.write_data(&pixels).unwrap();
+        }
+        assert_eq!(open_decoder(&path).unwrap().strip_count().unwrap(), 8);
+        let (profile, _) = column_profile(&path, 1024).unwrap();
+        assert_eq!(profile, (0..width).map(|x| x as f32).collect::<Vec<_>>());
+    }
+
+    /// Ensure that pages are decoded strip by strip, with the alpha channel
+    /// dropped, and that every page of the file is read.
+    #[test]
+    fn pages_strip_by_strip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("pages.tif");
+        let pixels: Vec<u8> = (0..10 * 7 * 4).map(|i| i as u8).collect();
+        {
+            let file = File::create(&path).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder.new_image::<colortype::RGBA8>(10, 7).unwrap();
+            image.rows_per_strip(3).unwrap();
+            image.write_data(&pixels).unwrap();
+            encoder
+                .write_image::<colortype::Gray8>(4, 2, &[9; 8])
+                .unwrap();
+        }
+        let mut pages = Vec::new();
+        for_each_page(&path, 10 * 3 * 4, |mut page| {
+            let mut strips = Vec::new();
+            while let Some(rows) = page.read_rows()? {
+                strips.push(rows);
+            }
+            pages.push((page.width, page.height, page.format, strips));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(pages.len(), 2);
+        let (width, height, format, strips) = &pages[0];
+        assert_eq!((*width, *height, *format), (10, 7, PixelFormat::Rgb));
+        assert_eq!(
+            strips.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![90, 90, 30]
+        );
+        let rgb: Vec<u8> = pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        assert_eq!(strips.concat(), rgb);
+        assert_eq!(pages[1], (4, 2, PixelFormat::Gray, vec![vec![9; 8]]));
+    }
+
+    /// Ensure that pages are cropped to the columns, keeping all rows, and
+    /// that bilevel rows are shifted to the first column.
+    #[test]
+    fn crop() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let output = temp_dir.path().join("output.tif");
+        let pixels: Vec<u8> = (0..4)
+            .flat_map(|row| (0..10).map(move |x| row * 10 + x))
+            .collect();
+        {
+            let file = File::create(&input).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(10, 4).unwrap();
+            image.rows_per_strip(3).unwrap();
+            image.write_data(&pixels).unwrap();
+        }
+        crop_columns(&input, &output, 3, 4, TiffCompression::Lzw, usize::MAX).unwrap();
+        let mut decoder = open_decoder(&output).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        let DecodingResult::U8(data) = decoder.read_image().unwrap() else {
+            panic!("Unexpected pixel format");
+        };
+        assert_eq!(
+            data,
+            [3, 4, 5, 6, 13, 14, 15, 16, 23, 24, 25, 26, 33, 34, 35, 36]
+        );
+        assert!(crop_columns(&input, &output, 8, 4, TiffCompression::Lzw, usize::MAX).is_err());
+
+        // Two bilevel rows of 12 pixels, cropped to 9 pixels at column 2
+        let bilevel = PixelLayout {
+            photometric: PhotometricInterpretation::BlackIsZero,
+            samples: 1,
+            bits_per_sample: 1,
+        };
+        let crop = Crop { left: 2, width: 9 };
+        assert_eq!(
+            crop.apply(
+                &[0b0011_0101, 0b1110_0000, 0b1000_0000, 0b0001_0000],
+                bilevel,
+                12
+            ),
+            [0b1101_0111, 0b1000_0000, 0b0000_0000, 0b0000_0000]
+        );
+    }
+
+    /// Ensure that the contrast is stretched like `-auto-level -level
+    /// 10%,90%`, without touching the alpha channel, and that pages of a
+    /// single shade are only leveled.
+    #[test]
+    fn contrast() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let output = temp_dir.path().join("output.tif");
+        let read = |path: &Path| open_decoder(path).unwrap().read_image().unwrap();
+        let stretch_contrast = |compression| {
+            let corrections = Corrections {
+                stretch_contrast: true,
+                ..Corrections::default()
+            };
+            correct_page(&input, &output, &corrections, compression, usize::MAX).unwrap();
+        };
+
+        // 50..=150 is stretched to 0..=255, then 10% are clipped on both ends
+        let file = File::create(&input).unwrap();
+        TiffEncoder::new(BufWriter::new(file))
+            .unwrap()
+            .write_image::<colortype::Gray8>(5, 1, &[50, 55, 60, 100, 150])
+            .unwrap();
+        stretch_contrast(TiffCompression::Lzw);
+        match read(&output) {
+            DecodingResult::U8(data) => assert_eq!(data, [0, 0, 0, 128, 255]),
+            _ => panic!("Unexpected pixel format"),
+        }
+
+        let file = File::create(&input).unwrap();
+        TiffEncoder::new(BufWriter::new(file))
+            .unwrap()
+            .write_image::<colortype::RGBA8>(2, 1, &[100, 150, 200, 7, 120, 150, 200, 9])
+            .unwrap();
+        stretch_contrast(TiffCompression::None);
+        match read(&output) {
+            DecodingResult::U8(data) => assert_eq!(data, [0, 128, 255, 7, 32, 128, 255, 9]),
+            _ => panic!("Unexpected pixel format"),
+        }
+
+        write_test_page(&input, 128, 300);
+        stretch_contrast(TiffCompression::Deflate);
+        assert_eq!(read_first_pixels(&output), [128]);
+    }
+
+    /// Ensure that columns are filled with their neighbour before the
+    /// contrast is stretched, and that the calibration is applied like
+    /// ImageMagick's `-level`.
+    #[test]
+    fn corrections() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let output = temp_dir.path().join("output.tif");
+        let read = |path: &Path| match open_decoder(path).unwrap().read_image().unwrap() {
+            DecodingResult::U8(data) => data,
+            _ => panic!("Unexpected pixel format"),
+        };
+        {
+            let file = File::create(&input).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(6, 2).unwrap();
+            image.rows_per_strip(1).unwrap();
+            image
+                .write_data(&[10, 20, 30, 250, 0, 60, 10, 20, 30, 250, 0, 60])
+                .unwrap();
+        }
+        let mut corrections = Corrections {
+            fill_columns: vec![(0, 1), (3, 2), (6, 1)],
+            ..Corrections::default()
+        };
+        correct_page(
+            &input,
+            &output,
+            &corrections,
+            TiffCompression::Lzw,
+            usize::MAX,
+        )
+        .unwrap();
+        assert_eq!(read(&output)[..6], [20, 20, 30, 30, 30, 60]);
+
+        // The filled columns don't count for the contrast
+        corrections.stretch_contrast = true;
+        correct_page(
+            &input,
+            &output,
+            &corrections,
+            TiffCompression::Lzw,
+            usize::MAX,
+        )
+        .unwrap();
+        assert_eq!(read(&output)[6..], [0, 0, 48, 48, 48, 255]);
+
+        let file = File::create(&input).unwrap();
+        TiffEncoder::new(BufWriter::new(file))
+            .unwrap()
+            .write_image::<colortype::Gray8>(4, 1, &[51, 153, 204, 0])
+            .unwrap();
+        let mut calibration = Calibration {
+            black: 20.0,
+            white: 80.0,
+            gamma: 1.0,
+        };
+        let corrections = Corrections {
+            calibration: Some(calibration),
+            ..Corrections::default()
+        };
+        correct_page(
+            &input,
+            &output,
+            &corrections,
+            TiffCompression::Lzw,
+            usize::MAX,
+        )
+        .unwrap();
+        assert_eq!(read(&output), [0, 170, 255, 0]);
+        calibration.gamma = 2.0;
+        let corrections = Corrections {
+            calibration: Some(calibration),
+            stretch_contrast: true,
+            ..Corrections::default()
+        };
+        correct_page(
+            &input,
+            &output,
+            &corrections,
+            TiffCompression::Lzw,
+            usize::MAX,
+        )
+        .unwrap();
+        assert_eq!(read(&output), [0, 228, 255, 0]);
+
+        // Bilevel rows of 12 pixels
+        let bilevel = PixelLayout {
+            photometric: PhotometricInterpretation::BlackIsZero,
+            samples: 1,
+            bits_per_sample: 1,
+        };
+        let mut chunk =
+            DecodingResult::U8(vec![0b0011_0101, 0b1110_0000, 0b1000_0000, 0b0001_0000]);
+        ColumnFill::new(&[(0, 1), (4, 2)], 12)
+            .apply(&mut chunk, bilevel, 12)
+            .unwrap();
+        let DecodingResult::U8(data) = chunk else {
+            panic!("Unexpected pixel format");
+        };
+        assert_eq!(data, [0b0011_1101, 0b1110_0000, 0b0000_0000, 0b0001_0000]);
+    }
+
+    /// Ensure that pages are combined in the order in which they are passed
+    /// in, for all supported compression algorithms.
+    #[test]
+    fn combine_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let pages: Vec<_> = (0..3u8)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{i}.tif"));
+                write_test_page(&path, i * 10, 300);
+                path
+            })
+            .collect();
+
+        for compression in COMPRESSIONS {
+            let output = temp_dir.path().join("combined.tif");
+            let reordered = [&pages[2], &pages[0]];
+            combine_tiffs(&reordered, &output, compression, usize::MAX).unwrap();
+            assert_eq!(page_count(&output).unwrap(), 2);
+            assert_eq!(read_first_pixels(&output), vec![20, 0]);
+        }
+    }
+
+    /// Ensure that multi-page files are split into numbered pages in their
+    /// original order.
+    #[test]
+    fn split_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let pages: Vec<_> = (0..3u8)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{i}.tif"));
+                write_test_page(&path, i * 10, 300);
+                path
+            })
+            .collect();
+        let combined = temp_dir.path().join("combined.tif");
+        combine_tiffs(&pages, &combined, TiffCompression::Lzw, usize::MAX).unwrap();
+
+        let output_dir = temp_dir.path().join("document");
+        std::fs::create_dir(&output_dir).unwrap();
+        let count =
+            split_pages(&combined, &output_dir, 2, TiffCompression::Lzw, usize::MAX).unwrap();
+        assert_eq!(count, 3);
+        for (i, shade) in [(2, 0), (3, 10), (4, 20)] {
+            let page = output_dir.join(format!("{i:04}.tif"));
+            assert_eq!(page_count(&page).unwrap(), 1);
+            assert_eq!(read_first_pixels(&page), vec![shade]);
+        }
+    }
+
+    /// Ensure that the resolution of the input pages is preserved.
+    #[test]
+    fn preserve_resolution() {
+        let temp_dir = TempDir::new().unwrap();
+        let page = temp_dir.path().join("page.tif");
+        write_test_page(&page, 0, 600);
+
+        let output = temp_dir.path().join("combined.tif");
+        combine_tiffs(&[&page], &output, TiffCompression::Lzw, usize::MAX).unwrap();
+
+        let mut decoder = open_decoder(&output).unwrap();
+        let resolution = read_resolution(&mut decoder).unwrap().unwrap();
+        assert_eq!(resolution.unit, ResolutionUnit::Inch);
+        assert_eq!(resolution.x, (600, 1));
+    }
+
+    /// Ensure that pages are written and read back completely, with their
+    /// resolution, and that bilevel pages are reduced to black and white.
+    #[test]
+    fn raster_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("page.tif");
+        for (format, dpi) in [
+            (PixelFormat::Bilevel, (300.0, 300.0)),
+            (PixelFormat::Gray, (200.0, 100.0)),
+            (PixelFormat::Rgb, (72.5, 72.5)),
+        ] {
+            let mut raster = Raster::white(11, 3, Some(dpi), format);
+            for (i, sample) in raster.samples.iter_mut().enumerate() {
+                *sample = (i * 37 % 256) as u8;
+            }
+            write_raster(&path, &raster, TiffCompression::Lzw).unwrap();
+            let read = read_raster(&path, usize::MAX).unwrap();
+            if format == PixelFormat::Bilevel {
+                for sample in &mut raster.samples {
+                    *sample = if *sample >= 128 { 255 } else { 0 };
+                }
+            }
+            assert_eq!(read, raster);
+        }
+    }
+
+    /// Ensure that a missing input file results in an error.
+    #[test]
+    fn missing_input() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("combined.tif");
+        let result = combine_tiffs(
+            &[temp_dir.path().join("missing.tif")],
+            &output,
+            TiffCompression::Lzw,
+            usize::MAX,
+        );
+        assert!(result.is_err());
+    }
+
+    /// Combine a single page with a custom output strip size and return the
+    /// decoded pixel data of the result.
+    fn roundtrip(input: &Path, compression: TiffCompression, strip_size: usize) -> DecodingResult {
+        let output = input.with_extension("out.tif");
+        {
+            let file = File::create(&output).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            copy_pages(input, &mut encoder, compression, strip_size, usize::MAX).unwrap();
+        }
+        open_decoder(&output).unwrap().read_image().unwrap()
+    }
+
+    /// Ensure that pages are streamed correctly when input and output strips
+    /// don't line up.
+    #[test]
+    fn stream_unaligned_strips() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let (width, height) = (37, 53);
+        let data: Vec<u8> = (0..width * height).map(|i| (i % 251) as u8).collect();
+        {
+            let file = File::create(&input).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file))
+                .unwrap()
+                .with_compression(Compression::Lzw);
+            let mut image = encoder
+                .new_image::<colortype::Gray8>(width, height)
+                .unwrap();
+            image.rows_per_strip(5).unwrap();
+            image.write_data(&data).unwrap();
+        }
+
+        for compression in COMPRESSIONS {
+            // 100 bytes result in 2 rows per output strip
+            match roundtrip(&input, compression, 100) {
+                DecodingResult::U8(output) => assert_eq!(output, data),
+                _ => panic!("Unexpected pixel format"),
+            }
+        }
+    }
+
+    /// Ensure that 16 bit RGB pages are copied without changes.
+    #[test]
+    fn stream_rgb16() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("input.tif");
+        let data: Vec<u16> = (0..3 * 8 * 6).map(|i| i * 433).collect();
+        {
+            let file = File::create(&input).unwrap();
+            let mut encoder = TiffEncoder::new(BufWriter::new(file)).unwrap();
+            encoder
+                .write_image::<colortype::RGB16>(8, 6, &data)
+                .unwrap();
+        }
+
+        for compression in COMPRESSIONS {
+            match roundtrip(&input, compression, 64) {
+                DecodingResult::U16(output) => assert_eq!(output, data),
+                _ => panic!("Unexpected pixel format"),
+            }
+        }
+    }
+}
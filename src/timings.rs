@@ -0,0 +1,120 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// Record the durations of the individual steps of a document run
+#[derive(Debug, Default)]
+pub struct Timings {
+    steps: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    /// Run `f` and record its duration under the given step name
+    pub fn measure<T>(&mut self, step: impl Into<String>, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(step, start.elapsed());
+        result
+    }
+
+    /// Record the duration of a step
+    pub fn record(&mut self, step: impl Into<String>, duration: Duration) {
+        self.steps.push((step.into(), duration));
+    }
+
+    /// Add all steps of `other`, with their names prefixed by `prefix`
+    pub fn extend_prefixed(&mut self, prefix: &str, other: Timings) {
+        self.steps.extend(
+            other
+                .steps
+                .into_iter()
+                .map(|(step, duration)| (format!("{prefix}: {step}"), duration)),
+        );
+    }
+
+    /// The sum of all recorded step durations
+    pub fn total(&self) -> Duration {
+        self.steps.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        let width = self
+            .steps
+            .iter()
+            .map(|(step, _)| step.chars().count())
+            .chain(std::iter::once("Total".len()))
+            .max()
+            .unwrap_or_default();
+        writeln!(f, "Timings:")?;
+        for (step, duration) in &self.steps {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                duration.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            writeln!(
+                f,
+                "  {step:<width$}  {:>8.2}s  {share:>5.1}%",
+                duration.as_secs_f64()
+            )?;
+        }
+        write!(f, "  {:<width$}  {:>8.2}s", "Total", total.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the total is the sum of all steps.
+    #[test]
+    fn total() {
+        let mut timings = Timings::default();
+        assert_eq!(timings.total(), Duration::ZERO);
+        timings.record("Scan", Duration::from_millis(1500));
+        timings.record("OCR", Duration::from_millis(500));
+        assert_eq!(timings.total(), Duration::from_secs(2));
+    }
+
+    /// Ensure that merged steps are prefixed.
+    #[test]
+    fn extend_prefixed() {
+        let mut other = Timings::default();
+        other.record("Scan", Duration::from_secs(2));
+        let mut timings = Timings::default();
+        timings.record("Process", Duration::from_secs(1));
+        timings.extend_prefixed("hp", other);
+        assert_eq!(timings.steps[1].0, "hp: Scan");
+        assert_eq!(timings.total(), Duration::from_secs(3));
+    }
+
+    /// Ensure that `measure` records a step and passes through the result.
+    #[test]
+    fn measure() {
+        let mut timings = Timings::default();
+        let result = timings.measure("Step", || 42);
+        assert_eq!(result, 42);
+        assert_eq!(timings.steps.len(), 1);
+        assert_eq!(timings.steps[0].0, "Step");
+    }
+
+    /// Ensure that the breakdown contains every step with its share of the
+    /// total duration.
+    #[test]
+    fn display() {
+        let mut timings = Timings::default();
+        timings.record("Scan", Duration::from_secs(3));
+        timings.record("Run OCR", Duration::from_secs(1));
+        assert_eq!(
+            timings.to_string(),
+            "Timings:\n  \
+               Scan         3.00s   75.0%\n  \
+               Run OCR      1.00s   25.0%\n  \
+               Total        4.00s"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Detection of the external tools that are installed
+//!
+//! Most external tools are optional: If one is missing, the steps that need it
+//! are skipped with a warning, or a native implementation is used instead.
+//! Installed tools that are older than the oldest supported version are
+//! reported as well.
+
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsStr,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use tracing::{debug, warn};
+
+use crate::{config::OcrConfig, magick, ocr};
+
+/// External tools, with the features that need them
+pub const TOOLS: &[(&str, &str)] = &[
+    ("scanimage", "scanning"),
+    (
+        "magick",
+        "photo import, redacting and stamping pages, and virtual scanners",
+    ),
+    ("ocrmypdf", "OCR with OCRmyPDF (PDF/A)"),
+    ("tesseract", "OCR with Tesseract"),
+    ("qpdf", "extracting and sharing pages"),
+    ("qrencode", "QR labels of archived documents"),
+    (
+        "pdftotext",
+        "indexing PDFs that were not archived by arkivisto",
+    ),
+];
+
+/// Oldest supported versions of the tools, with the problems of older ones
+const MINIMUM_VERSIONS: &[(&str, Version, &str)] = &[
+    (
+        "ImageMagick",
+        Version::new(6, 9, 0),
+        "importing photos and redacting pages may fail",
+    ),
+    (
+        "scanimage",
+        Version::new(1, 0, 27),
+        "batch scans may fail or miss pages",
+    ),
+];
+
+/// Missing tools that were already warned about, with the consequence
+static WARNED: Mutex<BTreeSet<(String, String)>> = Mutex::new(BTreeSet::new());
+
+/// Version of an external tool (e.g. 6.9.11)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse the first version number in the output of a tool, e.g.
+    /// "Version: ImageMagick 7.1.1-29 Q16-HDRI" or
+    /// "scanimage (sane-backends) 1.2.1; backend version 1.2.1"
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|word| {
+            let end = word
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(word.len());
+            let mut numbers = word[..end].split('.').map(|n| n.parse::<u32>().ok());
+            let major = numbers.next()??;
+            let minor = numbers.next()??;
+            let patch = numbers.next().unwrap_or(Some(0))?;
+            Some(Self::new(major, minor, patch))
+        })
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Version of `program`, parsed from the output of `program <arg>` (e.g.
+/// `--version`)
+pub fn version(program: &str, arg: &str) -> Option<Version> {
+    let output = Command::new(program).arg(arg).output().ok()?;
+    let version = Version::parse(&String::from_utf8_lossy(&output.stdout))
+        .or_else(|| Version::parse(&String::from_utf8_lossy(&output.stderr)));
+    debug!("Version of `{program}`: {version:?}");
+    version
+}
+
+/// Warn if `version` of `tool` (e.g. "ImageMagick") is older than the oldest
+/// supported version, return whether it is supported
+pub fn check_version(tool: &str, version: Version) -> bool {
+    let Some((_, minimum, problem)) = MINIMUM_VERSIONS.iter().find(|(name, ..)| *name == tool)
+    else {
+        return true;
+    };
+    if version >= *minimum {
+        return true;
+    }
+    warn!(
+        "{tool} {version} is older than the oldest supported version {minimum}, {problem}. \
+         Please upgrade it."
+    );
+    false
+}
+
+/// Whether `program` is found in one of the directories of `PATH`
+pub fn is_installed(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| find_in(program, &path).is_some())
+}
+
+/// Path of `program` in one of the directories of `path` (in the format of
+/// `PATH`)
+pub fn find_in(program: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Whether `path` is an executable file
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether `path` is an executable file
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Warn that `program` is not installed and what is skipped because of it
+/// (e.g. "not straightening the pages"), once per run
+pub fn warn_missing(program: &str, consequence: &str) {
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if warned.insert((program.to_string(), consequence.to_string())) {
+        warn!("`{program}` is not installed, {consequence}");
+    }
+}
+
+/// The tools of [`TOOLS`] that are not installed
+///
+/// OCRmyPDF is reported as the program that its configured runner needs
+/// (`docker` or `ocrmypdf`).
+pub fn missing(ocr_config: &OcrConfig) -> Vec<(&'static str, &'static str)> {
+    TOOLS
+        .iter()
+        .copied()
+        .filter_map(|(program, features)| match program {
+            "magick" => (!magick::is_installed()).then_some((program, features)),
+            "ocrmypdf" => ocr::find_ocrmypdf(ocr_config)
+                .is_none()
+                .then(|| (ocr::ocrmypdf_program(ocr_config), features)),
+            program => (!is_installed(program)).then_some((program, features)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that programs are searched in `PATH`, and that directories are
+    /// not programs.
+    #[test]
+    fn installed() {
+        assert!(is_installed("sh"));
+        assert!(!is_installed("arkivisto-missing-tool"));
+        assert!(!is_executable(Path::new("/")));
+    }
+
+    /// Ensure that versions are parsed from the output of ImageMagick 6 and 7
+    /// and scanimage, and that they are compared numerically.
+    #[test]
+    fn versions() {
+        assert_eq!(
+            Version::parse("Version: ImageMagick 7.1.1-29 Q16-HDRI x86_64 22086"),
+            Some(Version::new(7, 1, 1))
+        );
+        assert_eq!(
+            Version::parse("Version: ImageMagick 6.9.11-60 Q16 x86_64 2021-01-25"),
+            Some(Version::new(6, 9, 11))
+        );
+        assert_eq!(
+            Version::parse("scanimage (sane-backends) 1.2.1; backend version 1.2.1"),
+            Some(Version::new(1, 2, 1))
+        );
+        assert_eq!(Version::parse("tool 2.4"), Some(Version::new(2, 4, 0)));
+        assert_eq!(Version::parse("no version 42"), None);
+        assert!(Version::new(6, 10, 0) > Version::new(6, 9, 11));
+        assert_eq!(Version::new(6, 9, 11).to_string(), "6.9.11");
+
+        assert!(check_version("ImageMagick", Version::new(6, 9, 11)));
+        assert!(!check_version("ImageMagick", Version::new(6, 8, 9)));
+        assert!(check_version("qpdf", Version::new(1, 0, 0)));
+    }
+
+    /// Ensure that files without execute permission are not considered
+    /// programs.
+    #[cfg(unix)]
+    #[test]
+    fn executable() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let program = temp_dir.path().join("tool");
+        fs::write(&program, "#!/bin/sh\n").unwrap();
+        assert!(!is_executable(&program));
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(is_executable(&program));
+    }
+}
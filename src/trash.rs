@@ -0,0 +1,423 @@
+//! Trash of the archive
+//!
+//! Deleted documents are moved into a hidden directory in the archive
+//! directory (`.trash`, one subdirectory per document), together with their
+//! metadata and recognized text, and dropped from the indexes. Until they are
+//! purged, they can be restored to their previous path with their metadata.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    audit,
+    config::Timezone,
+    fs_utils, links, notes,
+    search::{self, Document},
+    tags,
+};
+
+/// Name of the trash directory in the archive directory
+const TRASH_DIR: &str = ".trash";
+
+/// Name of the record of a deleted document in its trash entry
+const RECORD_FILE: &str = "record.json";
+
+/// Suffix of the copies for emailing, which are deleted with the document
+const EMAIL_SUFFIX: &str = "_email.pdf";
+
+/// A deleted document with its metadata, as far as it is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Previous path of the document, relative to the archive directory
+    pub path: String,
+    /// Time of the deletion (RFC 3339, in the configured timezone)
+    pub deleted: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Date of the document (`YYYY-MM-DD`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correspondent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Expiry or follow-up date of the document (`YYYY-MM-DD`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<String>,
+    /// Where the paper original is kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+}
+
+/// A document in the trash
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trashed {
+    /// Name of the trash entry, to restore the document
+    pub name: String,
+    pub record: Record,
+}
+
+impl fmt::Display for Trashed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (deleted {})",
+            self.name, self.record.path, self.record.deleted
+        )
+    }
+}
+
+/// Name of the copy for emailing of a PDF named `name`
+fn email_name(name: &str) -> String {
+    let stem = name.strip_suffix(".pdf").unwrap_or(name);
+    format!("{stem}{EMAIL_SUFFIX}")
+}
+
+/// Move an archived PDF (with its copy for emailing) into the trash of the
+/// archive in `outdir` and drop it from the indexes and its note from
+/// `notes_dir`, return the name of its trash entry
+pub fn delete(
+    document: &Path,
+    outdir: &Path,
+    notes_dir: Option<&Path>,
+    timezone: Timezone,
+) -> Result<String> {
+    let document = fs::canonicalize(document)
+        .with_context(|| format!("Document {document:?} does not exist"))?;
+    let outdir = fs::canonicalize(outdir)
+        .with_context(|| format!("Archive directory {outdir:?} does not exist"))?;
+    let trash_dir = outdir.join(TRASH_DIR);
+    if !document.starts_with(&outdir) || document.starts_with(&trash_dir) {
+        bail!("{document:?} is not an archived document in {outdir:?}");
+    }
+    let key = fs_utils::relative_key(&outdir, &document);
+    let known = search::documents(&outdir)?
+        .into_iter()
+        .find(|known| fs_utils::relative_key(&outdir, &known.path) == key)
+        .unwrap_or_default();
+    let now = timezone.now();
+    let record = Record {
+        path: key,
+        deleted: now.to_rfc3339_opts(SecondsFormat::Secs, false),
+        id: known.id,
+        date: known.date.map(|date| date.to_string()),
+        correspondent: known.correspondent,
+        title: known.title,
+        tags: known.tags,
+        due: known.due.map(|due| due.to_string()),
+        location: known.location,
+        text: known.text,
+    };
+
+    let name = document
+        .file_name()
+        .context("Document has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    fs::create_dir_all(&trash_dir)
+        .with_context(|| format!("Failed to create trash directory {trash_dir:?}"))?;
+    let stem = name.strip_suffix(".pdf").unwrap_or(&name);
+    let entry = fs_utils::create_unique_dir(
+        &trash_dir,
+        &format!("{}_{stem}", now.format("%Y%m%dT%H%M%S")),
+    )?;
+    let moved = (|| {
+        let content =
+            serde_json::to_string_pretty(&record).context("Failed to serialize record")?;
+        fs_utils::write_synced(&entry.join(RECORD_FILE), content)?;
+        fs_utils::move_path(&document, &entry.join(&name))
+    })();
+    if let Err(e) = moved {
+        if let Err(e) = fs::remove_dir_all(&entry) {
+            debug!("Failed to remove {entry:?}: {e}");
+        }
+        return Err(e);
+    }
+    let email_copy = document.with_file_name(email_name(&name));
+    if email_copy.exists() {
+        fs_utils::move_path(&email_copy, &entry.join(email_name(&name)))?;
+    }
+
+    if let Err(e) = search::remove(&outdir, &document) {
+        warn!("Failed to remove {document:?} from the search index: {e:#}");
+    }
+    if let Err(e) = tags::remove(&outdir, &document) {
+        warn!("Failed to remove {document:?} from the tag index: {e:#}");
+    }
+    if let Err(e) = links::remove(&outdir, &document) {
+        warn!("Failed to remove the links of {document:?}: {e:#}");
+    }
+    if let Some(notes_dir) = notes_dir
+        && let Err(e) = notes::remove(notes_dir, &outdir, &document)
+    {
+        warn!("Failed to remove the note of {document:?}: {e:#}");
+    }
+    let entry_name = entry
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let event = audit::Event::new(audit::Action::Delete, &outdir, &document, timezone)
+        .with_id(record.id.as_deref())
+        .with_details(format!("to the trash as {entry_name}"));
+    audit::record(&outdir, &event);
+    Ok(entry_name)
+}
+
+/// The documents in the trash of the archive in `outdir`, oldest first
+///
+/// Entries without a valid record are skipped with a warning.
+pub fn list(outdir: &Path) -> Result<Vec<Trashed>> {
+    let trash_dir = outdir.join(TRASH_DIR);
+    if !trash_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut trashed = Vec::new();
+    for entry in fs_utils::retry_stale(|| fs::read_dir(&trash_dir))
+        .with_context(|| format!("Failed to read trash directory {trash_dir:?}"))?
+    {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let record_path = path.join(RECORD_FILE);
+        let record = fs::read_to_string(&record_path)
+            .context("Failed to read record")
+            .and_then(|content| serde_json::from_str(&content).context("Invalid record"));
+        match record {
+            Ok(record) => trashed.push(Trashed {
+                name: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                record,
+            }),
+            Err(e) => warn!("Skipping trash entry {path:?}: {e:#}"),
+        }
+    }
+    // The names start with the time of the deletion
+    trashed.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(trashed)
+}
+
+/// Restore the document of the trash entry `name` to its previous path in the
+/// archive in `outdir`, with its metadata and its note in `notes_dir`, return
+/// its path
+pub fn restore(
+    outdir: &Path,
+    name: &str,
+    notes_dir: Option<&Path>,
+    timezone: Timezone,
+) -> Result<PathBuf> {
+    let Some(trashed) = list(outdir)?
+        .into_iter()
+        .find(|trashed| trashed.name == name)
+    else {
+        bail!("There is no document named {name:?} in the trash");
+    };
+    let record = trashed.record;
+    let entry = outdir.join(TRASH_DIR).join(name);
+    let target = outdir.join(&record.path);
+    ensure!(
+        !target.exists(),
+        "{target:?} already exists, move it away to restore the document"
+    );
+    let file_name = target
+        .file_name()
+        .context("Record has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {parent:?}"))?;
+    }
+    fs_utils::move_path(&entry.join(&file_name), &target)?;
+    let email_copy = entry.join(email_name(&file_name));
+    if email_copy.exists() {
+        fs_utils::move_path(&email_copy, &target.with_file_name(email_name(&file_name)))?;
+    }
+
+    let document = Document {
+        path: target.clone(),
+        id: record.id.clone(),
+        date: record
+            .date
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()),
+        correspondent: record.correspondent,
+        title: record.title,
+        tags: record.tags,
+        due: record
+            .due
+            .as_deref()
+            .and_then(|due| NaiveDate::parse_from_str(due, "%Y-%m-%d").ok()),
+        location: record.location,
+        text: record.text,
+    };
+    if let Err(e) = search::add_document(outdir, &document) {
+        warn!("Failed to add {target:?} to the search index: {e:#}");
+    }
+    if let Err(e) = tags::record(outdir, &target, &document.tags, document.id.as_deref()) {
+        warn!("Failed to add {target:?} to the tag index: {e:#}");
+    }
+    if let Some(notes_dir) = notes_dir
+        && let Err(e) = notes::write_document(notes_dir, outdir, &document)
+    {
+        warn!("Failed to write the note of {target:?}: {e:#}");
+    }
+    fs::remove_dir_all(&entry).with_context(|| format!("Failed to remove {entry:?}"))?;
+    let event = audit::Event::new(audit::Action::Restore, outdir, &target, timezone)
+        .with_id(document.id.as_deref());
+    audit::record(outdir, &event);
+    Ok(target)
+}
+
+/// Remove the documents from the trash of the archive in `outdir` that were
+/// deleted at least `days` days ago (or all documents if `days` is `None`),
+/// return them
+///
+/// Documents with an invalid time of deletion are kept.
+pub fn purge(outdir: &Path, days: Option<u32>, timezone: Timezone) -> Result<Vec<Trashed>> {
+    let now = timezone.now();
+    let mut purged = Vec::new();
+    for trashed in list(outdir)? {
+        if let Some(days) = days {
+            match DateTime::parse_from_rfc3339(&trashed.record.deleted) {
+                Ok(deleted) if now - deleted >= Duration::days(days.into()) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Keeping trash entry {}: {e}", trashed.name);
+                    continue;
+                }
+            }
+        }
+        let entry = outdir.join(TRASH_DIR).join(&trashed.name);
+        fs::remove_dir_all(&entry).with_context(|| format!("Failed to remove {entry:?}"))?;
+        let event = audit::Event::new(
+            audit::Action::Purge,
+            outdir,
+            &outdir.join(&trashed.record.path),
+            timezone,
+        )
+        .with_id(trashed.record.id.as_deref());
+        audit::record(outdir, &event);
+        purged.push(trashed);
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::naming::DocumentInfo;
+
+    /// Ensure that deleted documents are dropped from the indexes, and
+    /// restored to their previous path with their metadata.
+    #[test]
+    fn delete_and_restore() {
+        let outdir = TempDir::new().unwrap();
+        let outdir = fs::canonicalize(outdir.path()).unwrap();
+        let document = outdir.join("2025/invoice.pdf");
+        let email_copy = outdir.join("2025/invoice_email.pdf");
+        fs::create_dir(outdir.join("2025")).unwrap();
+        fs::write(&document, "%PDF").unwrap();
+        fs::write(&email_copy, "%PDF small").unwrap();
+        let info = DocumentInfo {
+            date: NaiveDate::from_ymd_opt(2025, 5, 30).unwrap(),
+            title: "Invoice".into(),
+            correspondent: Some("Muster AG".into()),
+            tags: vec!["bills".into()],
+            due: None,
+            location: Some("Binder 3".into()),
+        };
+        search::add(&outdir, &document, "Total 42", Some("01JAB"), Some(&info)).unwrap();
+        tags::record(&outdir, &document, &info.tags, Some("01JAB")).unwrap();
+        let indexed = search::documents(&outdir).unwrap();
+        let notes_dir = outdir.join("notes");
+        let note = notes::write(&notes_dir, &outdir, &document, "Total 42", &info, None).unwrap();
+
+        let name = delete(&document, &outdir, Some(&notes_dir), Timezone::Utc).unwrap();
+        assert!(name.ends_with("_invoice"));
+        assert!(!document.exists());
+        assert!(!email_copy.exists());
+        assert!(search::documents(&outdir).unwrap().is_empty());
+        assert!(tags::load_index(&outdir).unwrap().is_empty());
+        assert!(!note.exists());
+        let trashed = list(&outdir).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].name, name);
+        assert_eq!(trashed[0].record.path, "2025/invoice.pdf");
+        assert_eq!(trashed[0].record.date.as_deref(), Some("2025-05-30"));
+
+        // Documents are not restored over other documents
+        fs::write(&document, "%PDF other").unwrap();
+        assert!(restore(&outdir, &name, Some(&notes_dir), Timezone::Utc).is_err());
+        fs::remove_file(&document).unwrap();
+
+        assert!(restore(&outdir, "unknown", Some(&notes_dir), Timezone::Utc).is_err());
+        let restored = restore(&outdir, &name, Some(&notes_dir), Timezone::Utc).unwrap();
+        assert_eq!(restored, document);
+        assert_eq!(fs::read_to_string(&email_copy).unwrap(), "%PDF small");
+        assert_eq!(search::documents(&outdir).unwrap(), indexed);
+        assert!(list(&outdir).unwrap().is_empty());
+        assert!(note.exists());
+
+        let actions: Vec<_> = audit::load(&outdir, &[])
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(actions, vec![audit::Action::Delete, audit::Action::Restore]);
+    }
+
+    /// Ensure that only documents deleted longer ago than the retention are
+    /// purged, unless all documents are purged.
+    #[test]
+    fn purge_expired() {
+        let outdir = TempDir::new().unwrap();
+        for name in ["old.pdf", "new.pdf"] {
+            let document = outdir.path().join(name);
+            fs::write(&document, "%PDF").unwrap();
+            delete(&document, outdir.path(), None, Timezone::Utc).unwrap();
+        }
+        let old = list(outdir.path())
+            .unwrap()
+            .into_iter()
+            .find(|trashed| trashed.record.path == "old.pdf")
+            .unwrap();
+        let record_path = outdir
+            .path()
+            .join(TRASH_DIR)
+            .join(&old.name)
+            .join(RECORD_FILE);
+        let record = Record {
+            deleted: "2025-01-01T10:00:00+01:00".into(),
+            ..old.record
+        };
+        fs::write(&record_path, serde_json::to_string(&record).unwrap()).unwrap();
+
+        let purged = purge(outdir.path(), Some(30), Timezone::Utc).unwrap();
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].record.path, "old.pdf");
+        let remaining = list(outdir.path()).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].record.path, "new.pdf");
+
+        assert_eq!(purge(outdir.path(), None, Timezone::Utc).unwrap().len(), 1);
+        assert!(list(outdir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+//! Guided troubleshooting of documents for which OCR recognized almost no
+//! text, so that they are not archived as searchable-in-name-only PDFs
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    manifest::Manifest,
+    process::{self, ProcessOptions},
+    prompt,
+    scan::{self, ScanContext},
+    timings::Timings,
+    ui,
+};
+
+/// Whether a warning reports that OCR recognized (almost) no text
+fn is_text_warning(warning: &str) -> bool {
+    warning == process::NO_TEXT_WARNING || warning == process::SPARSE_TEXT_WARNING
+}
+
+/// Parse OCR languages entered by the user (e.g. `deu+eng` or `deu, eng`)
+fn parse_languages(input: &str) -> Vec<String> {
+    input
+        .split(['+', ',', ' '])
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// If OCR recognized almost no text in a processed document, offer to run OCR
+/// again with other languages, to rescan the document at high resolution, or
+/// to skip OCR
+///
+/// Rescanning requires a scan context. The document is processed again until
+/// the problem is resolved or the user keeps the document for review.
+pub fn troubleshoot_ocr(
+    directory: &Path,
+    config: &Config,
+    options: &ProcessOptions,
+    scan_context: Option<&ScanContext>,
+    timings: &mut Timings,
+) -> Result<()> {
+    loop {
+        let mut manifest = Manifest::load(directory)?;
+        let Some(warning) = manifest
+            .warnings
+            .iter()
+            .find(|warning| is_text_warning(warning))
+        else {
+            return Ok(());
+        };
+        println!(
+            "{}",
+            ui::warning(format!(
+                "{warning}. The PDF would be searchable in name only."
+            ))
+        );
+
+        let option_languages = "Run OCR again with other languages";
+        let option_rescan = "Rescan at high resolution (600dpi)";
+        let option_skip = "Skip OCR (image-only PDF)";
+        let option_keep = "Keep the document for review";
+        let mut choices = vec![option_languages];
+        if scan_context.is_some() {
+            choices.push(option_rescan);
+        }
+        choices.extend([option_skip, option_keep]);
+        let choice = prompt::Select::new("How to proceed?", choices).prompt()?;
+
+        let mut options = options.clone();
+        if choice == option_languages {
+            let languages = prompt::Text::new("OCR languages?")
+                .with_initial_value(&config.ocr_languages().join("+"))
+                .with_help_message("Tesseract language codes, separated by '+' (e.g. deu+eng)")
+                .prompt()?;
+            options.ocr_languages = Some(parse_languages(&languages));
+        } else if choice == option_rescan
+            && let Some(context) = scan_context
+        {
+            scan::rescan_document(context, directory, timings)?;
+        } else if choice == option_skip {
+            options.skip_ocr = true;
+        } else {
+            return Ok(());
+        }
+
+        // Process again, without the warnings of the previous run
+        manifest
+            .warnings
+            .retain(|warning| !is_text_warning(warning));
+        manifest.save(directory)?;
+        process::process_document(directory, config, &options, timings)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that languages can be separated by plus signs, commas or
+    /// spaces.
+    #[test]
+    fn languages() {
+        assert_eq!(parse_languages("deu+eng"), vec!["deu", "eng"]);
+        assert_eq!(parse_languages(" deu, fra ita "), vec!["deu", "fra", "ita"]);
+        assert!(parse_languages(" + ").is_empty());
+    }
+}
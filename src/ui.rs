@@ -0,0 +1,173 @@
+//! Styling of the terminal output
+//!
+//! Messages, spinners and progress bars are styled according to the `[ui]`
+//! config. Colors are disabled if the `NO_COLOR` environment variable is set
+//! (see <https://no-color.org/>) or the theme is `none`.
+
+use std::{borrow::Cow, env, fmt::Display, sync::OnceLock};
+
+use console::Style;
+use indicatif::ProgressBar;
+use tracing::debug;
+
+use crate::config::{ProgressStyle, Symbols, Theme, UiConfig};
+
+static UI: OnceLock<UiConfig> = OnceLock::new();
+
+/// Configure the appearance of the output
+///
+/// Has no effect if output was already styled.
+pub fn init(config: &UiConfig) {
+    if UI.set(*config).is_err() {
+        debug!("UI is already initialized");
+    }
+    if config.theme == Theme::None || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+}
+
+fn config() -> &'static UiConfig {
+    UI.get_or_init(UiConfig::default)
+}
+
+/// Kind of a message, which determines its symbol and color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Success,
+    Warning,
+    Error,
+}
+
+fn symbol(symbols: Symbols, kind: Kind) -> &'static str {
+    match (symbols, kind) {
+        (Symbols::Unicode, Kind::Success) => "✔",
+        (Symbols::Unicode, Kind::Warning) => "⚠",
+        (Symbols::Unicode, Kind::Error) => "✘",
+        (Symbols::Emoji, Kind::Success) => "✅",
+        (Symbols::Emoji, Kind::Warning) => "⚠️",
+        (Symbols::Emoji, Kind::Error) => "❌",
+        (Symbols::Ascii, Kind::Success) => "[ok]",
+        (Symbols::Ascii, Kind::Warning) => "[!]",
+        (Symbols::Ascii, Kind::Error) => "[x]",
+    }
+}
+
+/// Color of a kind of message, or of spinners and progress bars (`None`), as
+/// named in indicatif templates
+fn color(theme: Theme, kind: Option<Kind>) -> Option<&'static str> {
+    match (theme, kind) {
+        (Theme::None, _) => None,
+        (_, Some(Kind::Success)) => Some("green"),
+        (_, Some(Kind::Error)) => Some("red"),
+        (Theme::Dark, Some(Kind::Warning)) => Some("yellow"),
+        (Theme::Light, Some(Kind::Warning)) => Some("magenta"),
+        (Theme::Dark, None) => Some("cyan"),
+        (Theme::Light, None) => Some("blue"),
+    }
+}
+
+fn message(kind: Kind, text: impl Display) -> String {
+    let config = config();
+    let symbol = symbol(config.symbols, kind);
+    match color(config.theme, Some(kind)) {
+        Some(color) => format!("{} {text}", Style::from_dotted_str(color).apply_to(symbol)),
+        None => format!("{symbol} {text}"),
+    }
+}
+
+/// A message about a completed step
+pub fn success(text: impl Display) -> String {
+    message(Kind::Success, text)
+}
+
+/// A message about a problem that doesn't stop the current step
+pub fn warning(text: impl Display) -> String {
+    message(Kind::Warning, text)
+}
+
+/// A message about a failed step
+pub fn error(text: impl Display) -> String {
+    message(Kind::Error, text)
+}
+
+fn spinner_style(config: &UiConfig) -> indicatif::ProgressStyle {
+    let tick_chars = match config.progress_style {
+        ProgressStyle::Smooth => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ",
+        ProgressStyle::Blocks => "▖▘▝▗ ",
+        ProgressStyle::Ascii => "-\\|/ ",
+    };
+    let template = match color(config.theme, None) {
+        Some(color) => format!("{{spinner:.{color}}} {{msg}}"),
+        None => "{spinner} {msg}".into(),
+    };
+    indicatif::ProgressStyle::with_template(&template)
+        .expect("Invalid spinner template")
+        .tick_chars(tick_chars)
+}
+
+fn bar_style(config: &UiConfig) -> indicatif::ProgressStyle {
+    let progress_chars = match config.progress_style {
+        ProgressStyle::Smooth => "█▉▊▋▌▍▎▏ ",
+        ProgressStyle::Blocks => "█▓▒░",
+        ProgressStyle::Ascii => "=> ",
+    };
+    let template = match color(config.theme, None) {
+        Some(color) => format!("{{bar:40.{color}}} {{msg}}"),
+        None => "{bar:40} {msg}".into(),
+    };
+    indicatif::ProgressStyle::with_template(&template)
+        .expect("Invalid progress bar template")
+        .progress_chars(progress_chars)
+}
+
+/// A spinner with a message, which must be ticked by the caller (e.g. with
+/// `enable_steady_tick`)
+pub fn spinner(message: impl Into<Cow<'static, str>>) -> ProgressBar {
+    ProgressBar::new_spinner()
+        .with_style(spinner_style(config()))
+        .with_message(message)
+}
+
+/// A progress bar with `len` steps
+pub fn progress_bar(len: u64) -> ProgressBar {
+    ProgressBar::new(len).with_style(bar_style(config()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that every combination of settings yields valid styles, and
+    /// that no colors are used with the `none` theme.
+    #[test]
+    fn styles() {
+        for theme in [Theme::Dark, Theme::Light, Theme::None] {
+            for progress_style in [
+                ProgressStyle::Smooth,
+                ProgressStyle::Blocks,
+                ProgressStyle::Ascii,
+            ] {
+                let config = UiConfig {
+                    theme,
+                    symbols: Symbols::default(),
+                    progress_style,
+                };
+                spinner_style(&config);
+                bar_style(&config);
+            }
+        }
+        for kind in [Kind::Success, Kind::Warning, Kind::Error] {
+            assert_eq!(color(Theme::None, Some(kind)), None);
+        }
+    }
+
+    /// Ensure that ASCII symbols don't contain any other characters.
+    #[test]
+    fn ascii_symbols() {
+        for kind in [Kind::Success, Kind::Warning, Kind::Error] {
+            assert!(symbol(Symbols::Ascii, kind).is_ascii());
+        }
+        assert_eq!(symbol(Symbols::Ascii, Kind::Warning), "[!]");
+    }
+}
@@ -0,0 +1,65 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{Context, Result};
+use tracing::{debug, warn};
+
+use crate::{
+    config::Config,
+    manifest::{Manifest, Verification},
+    prompt, queue,
+};
+
+/// Open the final PDF of a document and ask the user to confirm that the
+/// digital copy is complete and legible.
+///
+/// The answer is recorded in the manifest of the document. Returns whether
+/// the user confirmed the copy.
+pub fn confirm_final_pdf(directory: &Path, config: &Config) -> Result<bool> {
+    let final_pdf = directory.join(queue::FINAL_PDF);
+    let shown = match open_file(&final_pdf) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to open {:?}: {:#}", final_pdf, e);
+            println!("Please open {} manually", final_pdf.display());
+            false
+        }
+    };
+
+    let confirmed = prompt::Confirm::new("Is the digital copy complete and legible?")
+        .with_help_message("Only destroy the paper original if you answer yes")
+        .with_default(false)
+        .prompt()?;
+
+    let mut manifest = Manifest::load(directory)?;
+    manifest.shown |= shown;
+    manifest.verification = Some(Verification {
+        confirmed,
+        at: config
+            .scan
+            .timezone
+            .now()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+    });
+    manifest.save(directory)?;
+
+    if !confirmed {
+        println!("Keep the paper original and rescan the document.");
+    }
+    Ok(confirmed)
+}
+
+/// Open a file with the default application of the desktop environment
+pub fn open_file(path: &Path) -> Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    debug!("Opening {:?} with {}", path, opener);
+    Command::new(opener)
+        .arg(path)
+        .spawn()
+        .with_context(|| format!("Failed to run `{opener}`"))?;
+    Ok(())
+}
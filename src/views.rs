@@ -0,0 +1,251 @@
+//! Secondary views of the archive
+//!
+//! The archive directory has a single layout (see `outdir_layout`). To browse
+//! it along other dimensions in a file manager, the views directory contains
+//! links to the archived PDFs by tag, by correspondent and by year, e.g.
+//! `by-tag/taxes/2024-03-01_tax-office_assessment.pdf`. The views are
+//! generated from the search and tag indexes, and replaced entirely when they
+//! are rebuilt.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use tracing::debug;
+
+use crate::{config::LinkKind, search::Document};
+
+/// Subdirectory of the views directory with the documents by tag
+const BY_TAG: &str = "by-tag";
+
+/// Subdirectory of the views directory with the documents by correspondent
+const BY_CORRESPONDENT: &str = "by-correspondent";
+
+/// Subdirectory of the views directory with the documents by year
+const BY_YEAR: &str = "by-year";
+
+/// Links created by [`rebuild`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ViewsUpdate {
+    /// Number of documents that are linked in at least one view
+    pub documents: usize,
+    /// Number of links
+    pub links: usize,
+}
+
+/// Name of a folder in a view, without path separators and leading dots
+fn folder_name(name: &str) -> String {
+    name.replace(['/', '\\'], "-")
+        .trim()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+/// Path of a new link to `target` in `dir`, named like the target, with a
+/// numeric suffix if the name is taken by a document of another folder
+fn link_path(dir: &Path, target: &Path) -> PathBuf {
+    let file_name = target.file_name().unwrap_or_default();
+    let mut path = dir.join(file_name);
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut counter = 2;
+    while path.symlink_metadata().is_ok() {
+        path = dir.join(format!("{stem}-{counter:02}{extension}"));
+        counter += 1;
+    }
+    path
+}
+
+/// Create a link to `target` at `link`
+fn create_link(target: &Path, link: &Path, kind: LinkKind) -> Result<()> {
+    match kind {
+        #[cfg(unix)]
+        LinkKind::Symlink => std::os::unix::fs::symlink(target, link),
+        #[cfg(not(unix))]
+        LinkKind::Symlink => std::os::windows::fs::symlink_file(target, link),
+        LinkKind::Hardlink => fs::hard_link(target, link),
+    }
+    .with_context(|| format!("Failed to link {link:?} to {target:?}"))
+}
+
+/// Folders of each view that a document is linked in
+fn folders(document: &Document) -> Vec<(&'static str, String)> {
+    let mut folders: Vec<_> = document
+        .tags
+        .iter()
+        .map(|tag| (BY_TAG, folder_name(tag)))
+        .collect();
+    if let Some(correspondent) = &document.correspondent {
+        folders.push((BY_CORRESPONDENT, folder_name(correspondent)));
+    }
+    if let Some(date) = document.date {
+        folders.push((BY_YEAR, date.format("%Y").to_string()));
+    }
+    folders.retain(|(_, folder)| !folder.is_empty());
+    folders
+}
+
+/// Replace the views in `views_dir` with links to the `documents` of the
+/// archive in `outdir`
+///
+/// Documents without metadata (e.g. filed by hand) only appear in the views
+/// of the tags they were given. Other files in `views_dir` are left alone.
+pub fn rebuild(
+    outdir: &Path,
+    views_dir: &Path,
+    documents: &[Document],
+    kind: LinkKind,
+) -> Result<ViewsUpdate> {
+    // Links inside the archive would be indexed as documents of their own
+    ensure!(
+        !views_dir.starts_with(outdir),
+        "The views directory {views_dir:?} must not be inside the archive directory {outdir:?}"
+    );
+    let mut update = ViewsUpdate::default();
+    let mut views: BTreeMap<&str, BTreeMap<String, Vec<&Path>>> = BTreeMap::new();
+    for document in documents {
+        let folders = folders(document);
+        if !folders.is_empty() {
+            update.documents += 1;
+        }
+        for (view, folder) in folders {
+            views
+                .entry(view)
+                .or_default()
+                .entry(folder)
+                .or_default()
+                .push(&document.path);
+        }
+    }
+
+    for view in [BY_TAG, BY_CORRESPONDENT, BY_YEAR] {
+        // Build the view next to the old one, then replace it
+        let new_dir = views_dir.join(format!(".{view}.new"));
+        if new_dir.exists() {
+            fs::remove_dir_all(&new_dir)
+                .with_context(|| format!("Failed to remove {new_dir:?}"))?;
+        }
+        fs::create_dir_all(&new_dir)
+            .with_context(|| format!("Failed to create directory {new_dir:?}"))?;
+        for (folder, targets) in views.get(view).into_iter().flatten() {
+            let dir = new_dir.join(folder);
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create directory {dir:?}"))?;
+            for target in targets {
+                create_link(target, &link_path(&dir, target), kind)?;
+                update.links += 1;
+            }
+        }
+        let dir = views_dir.join(view);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {dir:?}"))?;
+        }
+        fs::rename(&new_dir, &dir)
+            .with_context(|| format!("Failed to rename {new_dir:?} to {dir:?}"))?;
+        debug!("Rebuilt view {dir:?}");
+    }
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    /// Ensure that the views link the documents by tag, correspondent and
+    /// year, that rebuilding them drops outdated links, and that the views
+    /// directory must be outside of the archive.
+    #[test]
+    fn rebuild_views() {
+        let outdir = TempDir::new().unwrap();
+        let views_dir = TempDir::new().unwrap();
+        let invoice = outdir.path().join("2025/invoice.pdf");
+        let other_invoice = outdir.path().join("2024/invoice.pdf");
+        let filed = outdir.path().join("filed.pdf");
+        fs::create_dir(outdir.path().join("2025")).unwrap();
+        fs::create_dir(outdir.path().join("2024")).unwrap();
+        for pdf in [&invoice, &other_invoice, &filed] {
+            fs::write(pdf, "%PDF").unwrap();
+        }
+        let mut documents = vec![
+            Document {
+                path: invoice.clone(),
+                date: NaiveDate::from_ymd_opt(2025, 5, 30),
+                correspondent: Some("Muster AG/Zürich".into()),
+                tags: vec!["bills".into(), "car".into()],
+                ..Default::default()
+            },
+            Document {
+                path: other_invoice.clone(),
+                date: NaiveDate::from_ymd_opt(2024, 1, 10),
+                tags: vec!["bills".into()],
+                ..Default::default()
+            },
+            Document {
+                path: filed,
+                ..Default::default()
+            },
+        ];
+
+        let update = rebuild(
+            outdir.path(),
+            views_dir.path(),
+            &documents,
+            LinkKind::Symlink,
+        )
+        .unwrap();
+        assert_eq!(
+            update,
+            ViewsUpdate {
+                documents: 2,
+                links: 6
+            }
+        );
+        let view = |path: &str| views_dir.path().join(path);
+        assert_eq!(
+            fs::read_link(view("by-tag/bills/invoice.pdf")).unwrap(),
+            invoice
+        );
+        assert_eq!(
+            fs::read_link(view("by-tag/bills/invoice-02.pdf")).unwrap(),
+            other_invoice
+        );
+        assert!(view("by-tag/car/invoice.pdf").exists());
+        assert!(view("by-correspondent/Muster AG-Zürich/invoice.pdf").exists());
+        assert!(view("by-year/2024/invoice.pdf").exists());
+
+        // Rebuilding replaces the views, but keeps other files
+        fs::write(view("notes.txt"), "").unwrap();
+        documents[0].tags = vec!["bills".into()];
+        rebuild(
+            outdir.path(),
+            views_dir.path(),
+            &documents,
+            LinkKind::Hardlink,
+        )
+        .unwrap();
+        assert!(!view("by-tag/car").exists());
+        assert!(view("notes.txt").exists());
+        let link = view("by-year/2025/invoice.pdf");
+        assert!(!link.is_symlink());
+        assert_eq!(fs::read_to_string(link).unwrap(), "%PDF");
+
+        assert!(
+            rebuild(
+                outdir.path(),
+                &outdir.path().join("views"),
+                &documents,
+                LinkKind::Symlink,
+            )
+            .is_err()
+        );
+    }
+}
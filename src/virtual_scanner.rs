@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use tracing::{debug, warn};
+
+use crate::{config::VirtualPages, magick};
+
+/// Margin around the text, in millimeters
+const MARGIN_MM: f32 = 25.0;
+
+/// A page to generate
+#[derive(Debug, Clone, PartialEq)]
+struct Page {
+    /// Page number within the scan, starting at 1
+    number: usize,
+    /// Whether the page is the blank back side of a sheet
+    blank: bool,
+}
+
+/// Generate synthetic pages instead of scanning them
+///
+/// The pages are numbered like the pages of `scanimage` (see `batch_args`). If
+/// `count` is `None`, all sheets in the virtual document feeder are scanned.
+/// Duplex scans produce two pages per sheet.
+pub fn scan(
+    scans_dir: &Path,
+    config: &VirtualPages,
+    start: usize,
+    count: Option<usize>,
+    duplex: bool,
+    dpi: u32,
+    size_mm: (f32, f32),
+) -> Result<()> {
+    for page in pages(config, count, duplex) {
+        let output = scans_dir.join(format!("{:04}.tif", start + page.number));
+        let args = page_args(config, &page, dpi, size_mm, &output);
+        debug!("Generating virtual page with arguments: {:?}", args);
+        let result = magick::command().args(&args).output()?;
+        if !result.status.success() {
+            warn!(
+                "{} failed with status {}. Stderr: {}",
+                magick::program(),
+                result.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&result.stderr),
+            );
+            return Err(anyhow!("Failed to generate virtual page"));
+        }
+    }
+    Ok(())
+}
+
+/// The pages of a virtual scan
+fn pages(config: &VirtualPages, count: Option<usize>, duplex: bool) -> Vec<Page> {
+    let sides = if duplex { 2 } else { 1 };
+    let count = count.unwrap_or(config.sheets * sides);
+    (1..=count)
+        .map(|number| Page {
+            number,
+            blank: duplex && config.blank_backs && number % 2 == 0,
+        })
+        .collect()
+}
+
+/// ImageMagick arguments to render a page with text, noise and skew
+fn page_args(
+    config: &VirtualPages,
+    page: &Page,
+    dpi: u32,
+    (width_mm, height_mm): (f32, f32),
+    output: &Path,
+) -> Vec<String> {
+    let to_pixels = |mm: f32| (mm / 25.4 * dpi as f32).round() as u32;
+    let (width, height) = (to_pixels(width_mm), to_pixels(height_mm));
+    let mut args: Vec<String> = vec![
+        "-size".into(),
+        format!("{width}x{height}"),
+        "xc:white".into(),
+        "-units".into(),
+        "PixelsPerInch".into(),
+        "-density".into(),
+        dpi.to_string(),
+    ];
+    if !page.blank {
+        let margin = to_pixels(MARGIN_MM);
+        args.extend([
+            "-fill".into(),
+            "black".into(),
+            "-pointsize".into(),
+            "12".into(),
+            "-annotate".into(),
+            format!("+{margin}+{margin}"),
+            format!("Page {}\n\n{}", page.number, config.text),
+        ]);
+    }
+    if config.noise > 0.0 {
+        args.extend([
+            "-seed".into(),
+            (config.seed + page.number as u64).to_string(),
+            "-attenuate".into(),
+            config.noise.to_string(),
+            "+noise".into(),
+            "Gaussian".into(),
+        ]);
+    }
+    if config.skew != 0.0 {
+        args.extend([
+            "-background".into(),
+            "white".into(),
+            "-rotate".into(),
+            config.skew.to_string(),
+            "-gravity".into(),
+            "center".into(),
+            "-extent".into(),
+            format!("{width}x{height}"),
+        ]);
+    }
+    args.extend([
+        "-colorspace".into(),
+        "Gray".into(),
+        "-compress".into(),
+        "LZW".into(),
+        output.to_string_lossy().into_owned(),
+    ]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure that the whole feeder is scanned by default, and that duplex
+    /// scans have blank backs if configured.
+    #[test]
+    fn page_list() {
+        let config = VirtualPages {
+            sheets: 3,
+            ..Default::default()
+        };
+        assert_eq!(pages(&config, None, false).len(), 3);
+        assert_eq!(pages(&config, Some(1), false).len(), 1);
+        assert!(pages(&config, None, true).iter().all(|page| !page.blank));
+
+        let config = VirtualPages {
+            blank_backs: true,
+            ..config
+        };
+        let blank: Vec<bool> = pages(&config, None, true)
+            .iter()
+            .map(|page| page.blank)
+            .collect();
+        assert_eq!(blank, [false, true, false, true, false, true]);
+        assert!(pages(&config, None, false).iter().all(|page| !page.blank));
+    }
+
+    /// Ensure that pages are rendered in the scanned size, with text, seeded
+    /// noise and skew.
+    #[test]
+    fn render_args() {
+        let config = VirtualPages {
+            skew: 1.5,
+            seed: 42,
+            ..Default::default()
+        };
+        let page = Page {
+            number: 2,
+            blank: false,
+        };
+        let args = page_args(&config, &page, 300, (210.0, 297.0), Path::new("0002.tif"));
+        assert_eq!(&args[..3], ["-size", "2480x3508", "xc:white"]);
+        assert!(args.contains(&"+295+295".to_string()));
+        assert!(args.iter().any(|arg| arg.starts_with("Page 2\n")));
+        let seed = args.iter().position(|arg| arg == "-seed").unwrap();
+        assert_eq!(args[seed + 1], "44");
+        let rotate = args.iter().position(|arg| arg == "-rotate").unwrap();
+        assert_eq!(args[rotate + 1], "1.5");
+        assert_eq!(args.last().unwrap(), "0002.tif");
+
+        let config = VirtualPages {
+            noise: 0.0,
+            ..Default::default()
+        };
+        let blank = Page {
+            number: 1,
+            blank: true,
+        };
+        let args = page_args(&config, &blank, 300, (210.0, 297.0), Path::new("0001.tif"));
+        assert!(!args.iter().any(|arg| arg == "-annotate" || arg == "+noise"));
+    }
+}